@@ -1,14 +1,47 @@
+mod arith;
 mod bridge;
+mod cam;
+mod cdc;
+mod channel;
+mod cosim;
+mod crossbar;
+pub mod csr;
+mod dag_enum;
+mod decoder;
 pub mod epoch;
 mod eval_awi;
+mod external;
+mod fifo;
 mod inout;
 mod lazy_awi;
+mod mem;
+mod ports;
+mod select;
+mod shrink;
+mod sim;
 mod temporal;
 
+pub use arith::OverflowArith;
 pub use bridge::Drive;
-pub use epoch::{Assertions, Epoch, SuspendedEpoch};
+pub use cam::Cam;
+pub use cdc::{clock_crossing, pulse_synchronizer, synchronizer};
+pub use channel::Channel;
+pub use cosim::CoSim;
+pub use crossbar::crossbar;
+pub use csr::{Csr, CsrAccess, CsrMap};
+pub use dag_enum::{Enum, EnumVariants};
+pub use decoder::{Decoder, DecoderPattern};
+pub use epoch::{Assertions, Epoch, EpochInfo, SuspendedEpoch};
 pub use eval_awi::EvalAwi;
-pub use inout::{In, Out};
+pub(crate) use external::{schedule_event, Scheduled};
+pub use external::{Scope, ValueChange};
+pub use fifo::Fifo;
+pub use inout::{In, InOut, Out};
 pub use lazy_awi::LazyAwi;
+pub use mem::{Mem, Rom};
+pub use ports::Ports;
+pub use select::{match_mux, priority_mux};
+pub use shrink::ShrinkReport;
+pub use sim::{Phase, Sim};
 pub use temporal::{delay, Loop, Net};
 pub(crate) use temporal::{DELAY, DELAYED_LOOP_SOURCE, LOOP_SOURCE, UNDRIVEN_LOOP_SOURCE};
@@ -0,0 +1,152 @@
+use crate::{dag, lower::meta::cin_sum, Error};
+
+/// Saturating and overflow-checked arithmetic for mimicking `Bits`. The
+/// `signed` argument selects between unsigned and two's complement signed
+/// interpretations, which changes the resulting hardware, so it is not
+/// dynamic.
+///
+/// The additive functions are built directly on the same adder that the
+/// `cin_sum_` operation lowers to, so the sum and overflow flag share one
+/// carry chain instead of the adder being duplicated for the overflow
+/// detection.
+///
+/// # Errors
+///
+/// All functions return `Error::BitwidthMismatch` if `self.bw() != rhs.bw()`
+pub trait OverflowArith {
+    /// Wrapping addition of `rhs` to `self`, returning if overflow occured
+    fn checked_add_(&mut self, rhs: &Self, signed: bool) -> Result<dag::bool, Error>;
+
+    /// Wrapping subtraction of `rhs` from `self`, returning if overflow
+    /// occured
+    fn checked_sub_(&mut self, rhs: &Self, signed: bool) -> Result<dag::bool, Error>;
+
+    /// Wrapping multiplication of `self` by `rhs`, returning if overflow
+    /// occured
+    fn checked_mul_(&mut self, rhs: &Self, signed: bool) -> Result<dag::bool, Error>;
+
+    /// Addition of `rhs` to `self` that saturates at the numerical bounds
+    fn add_sat_(&mut self, rhs: &Self, signed: bool) -> Result<(), Error>;
+
+    /// Subtraction of `rhs` from `self` that saturates at the numerical bounds
+    fn sub_sat_(&mut self, rhs: &Self, signed: bool) -> Result<(), Error>;
+
+    /// Multiplication of `self` by `rhs` that saturates at the numerical
+    /// bounds
+    fn mul_sat_(&mut self, rhs: &Self, signed: bool) -> Result<(), Error>;
+}
+
+/// Returns the bound that an operation saturates to if it overflows. For
+/// unsigned operations, this is the unsigned maximum if `high` else zero. For
+/// signed operations, this is the signed minimum if `high` else the signed
+/// maximum.
+fn saturation_bound(x: &dag::Bits, signed: bool, high: dag::bool) -> dag::Awi {
+    let mut res = dag::Awi::zero(x.nzbw());
+    let mut tmp = dag::Awi::zero(x.nzbw());
+    if signed {
+        res.imax_();
+        tmp.imin_();
+        res.mux_(&tmp, high).unwrap();
+    } else {
+        tmp.umax_();
+        res.mux_(&tmp, high).unwrap();
+    }
+    res
+}
+
+/// Sets `x` to `x + rhs` or `x - rhs`, returning the overflow flag
+fn sum(x: &mut dag::Bits, rhs: &dag::Bits, sub: bool, signed: bool) -> Result<dag::bool, Error> {
+    if x.bw() != rhs.bw() {
+        return Err(Error::BitwidthMismatch(x.bw(), rhs.bw()))
+    }
+    // subtraction is `x + !rhs + 1`, with an unsigned borrow being the
+    // inverse of the carry
+    let (sum, unsigned_overflow, signed_overflow) = if sub {
+        let mut not_rhs = dag::Awi::from(rhs);
+        not_rhs.not_();
+        cin_sum(&dag::InlAwi::from(true), x, &not_rhs)?
+    } else {
+        cin_sum(&dag::InlAwi::from(false), x, rhs)?
+    };
+    x.copy_(&sum).unwrap();
+    Ok(if signed {
+        signed_overflow.to_bool()
+    } else if sub {
+        !unsigned_overflow.to_bool()
+    } else {
+        unsigned_overflow.to_bool()
+    })
+}
+
+/// Sets `x` to the wrapping product `x * rhs`, returning the overflow flag
+fn product(x: &mut dag::Bits, rhs: &dag::Bits, signed: bool) -> Result<dag::bool, Error> {
+    if x.bw() != rhs.bw() {
+        return Err(Error::BitwidthMismatch(x.bw(), rhs.bw()))
+    }
+    let w = x.bw();
+    let mut full = dag::Awi::zero(x.nzbw().checked_mul(awint::bw(2)).unwrap());
+    if signed {
+        let mut lhs = dag::Awi::from(&*x);
+        let mut rhs = dag::Awi::from(rhs);
+        full.arb_imul_add_(&mut lhs, &mut rhs);
+    } else {
+        full.arb_umul_add_(x, rhs);
+    }
+    x.field_width(&full, w).unwrap();
+    let overflow = if signed {
+        // the product fits if the upper `w + 1` bits are all copies of the sign
+        let mut upper = dag::Awi::zero(x.nzbw().checked_add(1).unwrap());
+        upper.field_from(&full, w - 1, w + 1).unwrap();
+        !(upper.is_zero() | upper.is_umax())
+    } else {
+        let mut upper = dag::Awi::zero(x.nzbw());
+        upper.field_from(&full, w, w).unwrap();
+        !upper.is_zero()
+    };
+    Ok(overflow)
+}
+
+impl OverflowArith for dag::Bits {
+    fn checked_add_(&mut self, rhs: &Self, signed: bool) -> Result<dag::bool, Error> {
+        sum(self, rhs, false, signed)
+    }
+
+    fn checked_sub_(&mut self, rhs: &Self, signed: bool) -> Result<dag::bool, Error> {
+        sum(self, rhs, true, signed)
+    }
+
+    fn checked_mul_(&mut self, rhs: &Self, signed: bool) -> Result<dag::bool, Error> {
+        product(self, rhs, signed)
+    }
+
+    fn add_sat_(&mut self, rhs: &Self, signed: bool) -> Result<(), Error> {
+        // signed addition can only overflow when the operands have the same sign
+        let high = if signed { self.msb() } else { true.into() };
+        let overflow = sum(self, rhs, false, signed)?;
+        let bound = saturation_bound(self, signed, high);
+        self.mux_(&bound, overflow).unwrap();
+        Ok(())
+    }
+
+    fn sub_sat_(&mut self, rhs: &Self, signed: bool) -> Result<(), Error> {
+        // signed subtraction can only overflow when the operands have different
+        // signs, and unsigned subtraction can only overflow towards zero
+        let high = if signed { self.msb() } else { false.into() };
+        let overflow = sum(self, rhs, true, signed)?;
+        let bound = saturation_bound(self, signed, high);
+        self.mux_(&bound, overflow).unwrap();
+        Ok(())
+    }
+
+    fn mul_sat_(&mut self, rhs: &Self, signed: bool) -> Result<(), Error> {
+        let high = if signed {
+            self.msb() ^ rhs.msb()
+        } else {
+            true.into()
+        };
+        let overflow = product(self, rhs, signed)?;
+        let bound = saturation_bound(self, signed, high);
+        self.mux_(&bound, overflow).unwrap();
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::num::NonZeroUsize;
+
+use awint::bw;
+
+use crate::{
+    dag,
+    lower::meta::{equal, lowest_set, onehot_encode, selector_awi},
+    Error, Loop,
+};
+
+/// A content-addressable memory of `depth` entries of `width` bits, each
+/// with a valid bit. The storage is `Loop`s driven with a delay of 1, so a
+/// cycle corresponds to `epoch.run(1)`. All entries start out invalid.
+///
+/// Lookups compare the key against every valid entry in parallel with
+/// balanced compare trees. [Cam::matches] returns the one-hot (or multi-hot
+/// if valid entries are duplicated) match vector, and [Cam::lookup] adds a
+/// priority encoder that selects the lowest matching index, built with a
+/// logarithmic depth smear instead of a linear priority chain.
+///
+/// ```
+/// use starlight::{awi, dag::*, Cam, Epoch, EvalAwi, LazyAwi};
+/// let epoch = Epoch::new();
+/// let write = LazyAwi::opaque(bw(1));
+/// let index = LazyAwi::opaque(bw(2));
+/// let data = LazyAwi::opaque(bw(8));
+/// let key = LazyAwi::opaque(bw(8));
+/// let cam = Cam::new(bw(8), 4).unwrap();
+/// let (found, hit) = cam.lookup(&key).unwrap();
+/// let found = EvalAwi::from(&found);
+/// let hit = EvalAwi::from_bool(hit);
+/// cam.drive(&write, &index, &data, &awi!(1)).unwrap();
+/// {
+///     use awi::*;
+///     key.retro_u8_(0x42).unwrap();
+///     write.retro_bool_(true).unwrap();
+///     index.retro_(&awi!(2_u2)).unwrap();
+///     data.retro_u8_(0x42).unwrap();
+///     assert!(!hit.eval_bool().unwrap());
+///     epoch.run(1).unwrap();
+///     write.retro_bool_(false).unwrap();
+///     assert!(hit.eval_bool().unwrap());
+///     assert_eq!(found.eval().unwrap(), awi!(2_u2));
+/// }
+/// drop(epoch);
+/// ```
+#[derive(Debug)]
+pub struct Cam {
+    storage: Vec<Loop>,
+    valid: Loop,
+}
+
+impl Cam {
+    /// Creates a new `Cam` with `depth` invalid entries of `width` bits
+    ///
+    /// # Errors
+    ///
+    /// If `depth == 0`
+    pub fn new(width: NonZeroUsize, depth: usize) -> Result<Self, Error> {
+        let Some(depth_w) = NonZeroUsize::new(depth) else {
+            return Err(Error::OtherStr("`Cam` created with a zero `depth`"))
+        };
+        let mut storage = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            storage.push(Loop::zero(width));
+        }
+        Ok(Self {
+            storage,
+            valid: Loop::zero(depth_w),
+        })
+    }
+
+    /// Returns the bitwidth of the entries as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.storage[0].nzbw()
+    }
+
+    /// Returns the bitwidth of the entries as a `usize`
+    #[must_use]
+    pub fn bw(&self) -> usize {
+        self.nzbw().get()
+    }
+
+    /// Returns the number of entries
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Returns the bitwidth that indexes must have, which is the number of
+    /// bits needed to represent `depth - 1` (or 1 if `depth == 1`)
+    #[must_use]
+    pub fn index_nzbw(&self) -> NonZeroUsize {
+        dag::Bits::nontrivial_bits(self.depth() - 1).unwrap_or(bw(1))
+    }
+
+    /// Returns the valid bits of the entries, with bit `i` for entry `i`
+    #[must_use]
+    pub fn valid(&self) -> dag::Awi {
+        dag::Awi::from(&*self.valid)
+    }
+
+    /// Returns the match vector for `key`, where bit `i` is set if entry `i`
+    /// is valid and equal to `key`
+    ///
+    /// # Errors
+    ///
+    /// If `key.bw() != self.bw()`
+    pub fn matches(&self, key: &dag::Bits) -> Result<dag::Awi, Error> {
+        if key.bw() != self.bw() {
+            return Err(Error::BitwidthMismatch(self.bw(), key.bw()))
+        }
+        let mut res = self.valid();
+        for (i, entry) in self.storage.iter().enumerate() {
+            let eq = equal(entry, key)?;
+            let bit = res.get(i).unwrap() & eq.to_bool();
+            res.set(i, bit).unwrap();
+        }
+        Ok(res)
+    }
+
+    /// Returns the index of the lowest matching entry for `key` (or zero if
+    /// there is none) with a bitwidth of [Cam::index_nzbw], and if there was
+    /// a match
+    ///
+    /// # Errors
+    ///
+    /// If `key.bw() != self.bw()`
+    pub fn lookup(&self, key: &dag::Bits) -> Result<(dag::Awi, dag::bool), Error> {
+        let matches = self.matches(key)?;
+        let hit = !matches.is_zero();
+        let index = onehot_encode(&lowest_set(&matches)?, self.index_nzbw())?;
+        Ok((index, hit))
+    }
+
+    /// Consumes `self`, driving the storage for each cycle. If the single bit
+    /// `write_enable` is set, `data` is written to the entry at `index` and
+    /// its valid bit is set to the single bit `valid` at the end of the cycle,
+    /// so entries are invalidated by writing with `valid` unset. Writes to
+    /// out of range indexes are ignored.
+    ///
+    /// # Errors
+    ///
+    /// If `write_enable.bw() != 1`, `index` does not have a bitwidth of
+    /// [Cam::index_nzbw], `data.bw() != self.bw()`, or `valid.bw() != 1`
+    pub fn drive(
+        self,
+        write_enable: &dag::Bits,
+        index: &dag::Bits,
+        data: &dag::Bits,
+        valid: &dag::Bits,
+    ) -> Result<(), Error> {
+        if write_enable.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, write_enable.bw()))
+        }
+        if index.bw() != self.index_nzbw().get() {
+            return Err(Error::BitwidthMismatch(self.index_nzbw().get(), index.bw()))
+        }
+        if data.bw() != self.bw() {
+            return Err(Error::BitwidthMismatch(self.bw(), data.bw()))
+        }
+        if valid.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, valid.bw()))
+        }
+        let sel = if self.depth() == 1 {
+            dag::Awi::from_bool(index.is_zero())
+        } else {
+            selector_awi(index, Some(self.depth()))?
+        };
+        let mut next_valid = self.valid();
+        for (i, entry) in self.storage.into_iter().enumerate() {
+            let we = write_enable.to_bool() & sel.get(i).unwrap();
+            let mut next = dag::Awi::from(&*entry);
+            next.mux_(data, we).unwrap();
+            entry.drive_with_delay(&next, 1)?;
+            let v = next_valid.get(i).unwrap();
+            let mut tmp = dag::Awi::from_bool(v);
+            tmp.mux_(valid, we).unwrap();
+            next_valid.set(i, tmp.to_bool()).unwrap();
+        }
+        self.valid.drive_with_delay(&next_valid, 1)?;
+        Ok(())
+    }
+}
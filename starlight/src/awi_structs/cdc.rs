@@ -0,0 +1,105 @@
+use awint::bw;
+
+use crate::{dag, Error, Loop};
+
+/// Models `x` being sampled by an asynchronous clock domain. The sampling
+/// register can go metastable when its input changes close to the clock
+/// edge, so every bit of `x` that changed since the previous cycle resolves
+/// pseudorandomly to either its new or its old value, independently of the
+/// other bits. Bits that did not change pass through. The pseudorandom
+/// choices come from a xorshift generator seeded with `seed` and stepped
+/// once per cycle, so simulations are reproducible.
+///
+/// There are no clock domains in the `Ensemble` itself, so crossings are
+/// not found automatically. Wrapping the signal at every crossing with this
+/// surfaces crossing bugs in simulation, e.g. a multi-bit binary counter can
+/// be observed with values that it never had, while a gray-coded counter or
+/// a [synchronizer] only ever observes old or new values.
+pub fn clock_crossing(x: &dag::Bits, seed: u64) -> dag::Awi {
+    let w = x.bw();
+    let prev = Loop::zero(x.nzbw());
+    let old = dag::Awi::from(&*prev);
+    prev.drive_with_delay(x, 1).unwrap();
+    // xorshift64, which cannot have a zero state
+    let seed = if seed == 0 {
+        0x9e37_79b9_7f4a_7c15
+    } else {
+        seed
+    };
+    let state = Loop::from_u64(dag::u64::from(seed));
+    let mut next_state = dag::Awi::from(&*state);
+    let mut tmp = next_state.clone();
+    tmp.shl_(13).unwrap();
+    next_state.xor_(&tmp).unwrap();
+    tmp.copy_(&next_state).unwrap();
+    tmp.lshr_(7).unwrap();
+    next_state.xor_(&tmp).unwrap();
+    tmp.copy_(&next_state).unwrap();
+    tmp.shl_(17).unwrap();
+    next_state.xor_(&tmp).unwrap();
+    let mut choice = dag::Awi::zero(x.nzbw());
+    for i in (0..w).step_by(64) {
+        choice.field(i, &state, 0, (w - i).min(64)).unwrap();
+    }
+    state.drive_with_delay(&next_state, 1).unwrap();
+    // `(x & choice) | (old & !choice)`
+    let mut res = dag::Awi::from(x);
+    res.and_(&choice).unwrap();
+    choice.not_();
+    choice.and_(&old).unwrap();
+    res.or_(&choice).unwrap();
+    res
+}
+
+/// Passes `x` through a chain of `stages` registers, which is the standard
+/// multi-flop synchronizer for a signal crossing into another clock domain.
+/// Two stages is typical. The registers are `Loop`s driven with a delay of 1
+/// and initially zero. Use [clock_crossing] on `x` to model the
+/// metastability of the first stage.
+///
+/// Only single bit signals or gray-coded values (such as
+/// [Fifo::write_pointer_gray](crate::Fifo::write_pointer_gray)) should be
+/// synchronized this way, because the bits of a multi-bit signal can be
+/// captured in different cycles.
+///
+/// # Errors
+///
+/// If `stages == 0`
+pub fn synchronizer(x: &dag::Bits, stages: usize) -> Result<dag::Awi, Error> {
+    if stages == 0 {
+        return Err(Error::OtherStr("`synchronizer` called with zero `stages`"))
+    }
+    let mut res = dag::Awi::from(x);
+    for _ in 0..stages {
+        let stage = Loop::zero(x.nzbw());
+        let next = dag::Awi::from(&*stage);
+        stage.drive_with_delay(&res, 1)?;
+        res = next;
+    }
+    Ok(res)
+}
+
+/// Synchronizes the single bit `pulse` into another clock domain, returning
+/// a single cycle pulse for every cycle that `pulse` was set. This works by
+/// toggling a register on every pulse and synchronizing the toggle with a
+/// two stage [synchronizer], so pulses must be separated by at least as many
+/// cycles as the destination domain needs to observe each toggle.
+///
+/// # Errors
+///
+/// If `pulse.bw() != 1`
+pub fn pulse_synchronizer(pulse: &dag::Bits) -> Result<dag::Awi, Error> {
+    if pulse.bw() != 1 {
+        return Err(Error::BitwidthMismatch(1, pulse.bw()))
+    }
+    let toggle = Loop::zero(bw(1));
+    let mut next_toggle = dag::Awi::from(&*toggle);
+    next_toggle.xor_(pulse).unwrap();
+    let synced = synchronizer(&toggle, 2)?;
+    toggle.drive_with_delay(&next_toggle, 1)?;
+    let prev = Loop::zero(bw(1));
+    let mut res = dag::Awi::from(&*prev);
+    res.xor_(&synced).unwrap();
+    prev.drive_with_delay(&synced, 1)?;
+    Ok(res)
+}
@@ -0,0 +1,258 @@
+use std::num::NonZeroUsize;
+
+use awint::bw;
+
+use crate::{dag, synchronizer, Error, Loop};
+
+/// A streaming valid/ready handshake channel, similar to an AXI stream. The
+/// producer drives `valid` and `data`, and the consumer drives `ready` with
+/// [Channel::drive_ready]. A transfer happens when both `valid` and `ready`
+/// are set. The temporal value of `ready` can be read with
+/// [Channel::ready] before the consumer drives it, because it is internally a
+/// [Loop].
+///
+/// Registers in the combinators are `Loop`s driven with a delay of 1, so a
+/// cycle corresponds to `epoch.run(1)`.
+#[derive(Debug)]
+pub struct Channel {
+    valid: dag::Awi,
+    data: dag::Awi,
+    ready: Loop,
+}
+
+/// Returns `!x`
+fn not(x: &dag::Bits) -> dag::Awi {
+    let mut res = dag::Awi::from(x);
+    res.not_();
+    res
+}
+
+/// Returns `lhs & rhs`
+fn and(lhs: &dag::Bits, rhs: &dag::Bits) -> dag::Awi {
+    let mut res = dag::Awi::from(lhs);
+    res.and_(rhs).unwrap();
+    res
+}
+
+/// Returns `lhs | rhs`
+fn or(lhs: &dag::Bits, rhs: &dag::Bits) -> dag::Awi {
+    let mut res = dag::Awi::from(lhs);
+    res.or_(rhs).unwrap();
+    res
+}
+
+impl Channel {
+    /// Creates a `Channel` from the producer side `valid` and `data` signals.
+    /// Assertions are automatically registered with the current `Epoch` that
+    /// check the protocol rule that once `valid` is set, it must stay set with
+    /// the same `data` until a transfer happens.
+    ///
+    /// # Errors
+    ///
+    /// If `valid.bw() != 1`
+    #[track_caller]
+    pub fn new(valid: &dag::Bits, data: &dag::Bits) -> Result<Self, Error> {
+        if valid.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, valid.bw()))
+        }
+        let ready = Loop::zero(bw(1));
+        // if the last cycle was a stall
+        let prev_stall = Loop::zero(bw(1));
+        let prev_data = Loop::zero(data.nzbw());
+        let stall = and(valid, &not(&ready));
+        let mut kept = dag::Awi::from(valid);
+        kept.and_(&dag::Awi::from_bool(data.const_eq(&prev_data).unwrap()))
+            .unwrap();
+        let ok = or(&not(&prev_stall), &kept);
+        dag::mimick::assert!(ok.lsb());
+        prev_stall.drive_with_delay(&stall, 1)?;
+        prev_data.drive_with_delay(data, 1)?;
+        Ok(Self {
+            valid: dag::Awi::from(valid),
+            data: dag::Awi::from(data),
+            ready,
+        })
+    }
+
+    /// Returns the single bit `valid` signal
+    #[must_use]
+    pub fn valid(&self) -> &dag::Bits {
+        &self.valid
+    }
+
+    /// Returns the data signal
+    #[must_use]
+    pub fn data(&self) -> &dag::Bits {
+        &self.data
+    }
+
+    /// Returns the temporal value of the single bit `ready` signal
+    #[must_use]
+    pub fn ready(&self) -> &dag::Bits {
+        &self.ready
+    }
+
+    /// Returns if a transfer is happening, which is `valid & ready`
+    #[must_use]
+    pub fn fire(&self) -> dag::Awi {
+        and(&self.valid, &self.ready)
+    }
+
+    /// Returns the bitwidth of the data as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.data.nzbw()
+    }
+
+    /// Returns the bitwidth of the data as a `usize`
+    #[must_use]
+    pub fn bw(&self) -> usize {
+        self.data.bw()
+    }
+
+    /// Consumes `self`, driving the consumer side `ready` signal. The `valid`
+    /// and `data` signals should be retrieved beforehand.
+    ///
+    /// # Errors
+    ///
+    /// If `ready.bw() != 1`
+    pub fn drive_ready(self, ready: &dag::Bits) -> Result<(), Error> {
+        self.ready.drive(ready)
+    }
+
+    /// Applies `f` to the data of `self`, the handshake passes through
+    /// unchanged
+    #[must_use]
+    pub fn map<F: FnOnce(&dag::Bits) -> dag::Awi>(self, f: F) -> Self {
+        Self {
+            data: f(&self.data),
+            valid: self.valid,
+            ready: self.ready,
+        }
+    }
+
+    /// Inserts a single entry register stage. The `ready` of `self` is set if
+    /// the stage is empty or if it is being emptied in the same cycle.
+    fn register_stage(self) -> Result<Self, Error> {
+        let full = Loop::zero(bw(1));
+        let data = Loop::zero(self.nzbw());
+        let out_ready = Loop::zero(bw(1));
+        let in_ready = or(&not(&full), &out_ready);
+        let take = and(&self.valid, &in_ready);
+        let mut next_full = dag::Awi::from(&*full);
+        next_full.mux_(&self.valid, in_ready.lsb()).unwrap();
+        let mut next_data = dag::Awi::from(&*data);
+        next_data.mux_(&self.data, take.lsb()).unwrap();
+        let res = Self {
+            valid: dag::Awi::from(&*full),
+            data: dag::Awi::from(&*data),
+            ready: out_ready,
+        };
+        full.drive_with_delay(&next_full, 1)?;
+        data.drive_with_delay(&next_data, 1)?;
+        self.ready.drive(&in_ready)?;
+        Ok(res)
+    }
+
+    /// Buffers `self` with a FIFO that can hold `depth` entries, implemented
+    /// as a chain of `depth` register stages. This has a latency of `depth`
+    /// cycles. If `depth == 0`, `self` is returned unchanged.
+    pub fn fifo(self, depth: usize) -> Result<Self, Error> {
+        let mut res = self;
+        for _ in 0..depth {
+            res = res.register_stage()?;
+        }
+        Ok(res)
+    }
+
+    /// Broadcasts `self` to two consumers, which may accept the data in
+    /// different cycles. `self` transfers once both consumers have accepted
+    /// the data. The outputs keep track of which consumers have already
+    /// accepted, so their `valid`s do not depend on the `ready`s.
+    pub fn fork(self) -> Result<(Self, Self), Error> {
+        let done = [Loop::zero(bw(1)), Loop::zero(bw(1))];
+        let ready = [Loop::zero(bw(1)), Loop::zero(bw(1))];
+        let valid = [
+            and(&self.valid, &not(&done[0])),
+            and(&self.valid, &not(&done[1])),
+        ];
+        // if an output has accepted the current data in this or a previous cycle
+        let finished = [
+            or(&done[0], &and(&valid[0], &ready[0])),
+            or(&done[1], &and(&valid[1], &ready[1])),
+        ];
+        let in_ready = and(&finished[0], &finished[1]);
+        let [done0, done1] = done;
+        let [ready0, ready1] = ready;
+        let [valid0, valid1] = valid;
+        done0.drive_with_delay(&and(&finished[0], &not(&in_ready)), 1)?;
+        done1.drive_with_delay(&and(&finished[1], &not(&in_ready)), 1)?;
+        self.ready.drive(&in_ready)?;
+        Ok((
+            Self {
+                valid: valid0,
+                data: self.data.clone(),
+                ready: ready0,
+            },
+            Self {
+                valid: valid1,
+                data: self.data,
+                ready: ready1,
+            },
+        ))
+    }
+
+    /// Passes `self` into another clock domain with a two-phase handshake.
+    /// The data is held in a register while a request toggle is synchronized
+    /// to the destination, and an acknowledge toggle is synchronized back once
+    /// the destination has accepted it. The data itself is not synchronized,
+    /// because it is stable while it is being read. Only one transfer can be
+    /// in flight at a time.
+    pub fn synchronize(self) -> Result<Self, Error> {
+        let req = Loop::zero(bw(1));
+        let ack = Loop::zero(bw(1));
+        let data = Loop::zero(self.nzbw());
+        // source side
+        let ack_synced = synchronizer(&ack, 2)?;
+        let mut busy = dag::Awi::from(&*req);
+        busy.xor_(&ack_synced).unwrap();
+        let in_ready = not(&busy);
+        let take = and(&self.valid, &in_ready);
+        let mut next_req = dag::Awi::from(&*req);
+        next_req.xor_(&take).unwrap();
+        let mut next_data = dag::Awi::from(&*data);
+        next_data.mux_(&self.data, take.lsb()).unwrap();
+        // destination side
+        let req_synced = synchronizer(&req, 2)?;
+        let mut valid = dag::Awi::from(&*ack);
+        valid.xor_(&req_synced).unwrap();
+        let out_ready = Loop::zero(bw(1));
+        let mut next_ack = and(&valid, &out_ready);
+        next_ack.xor_(&ack).unwrap();
+        let res = Self {
+            valid,
+            data: dag::Awi::from(&*data),
+            ready: out_ready,
+        };
+        req.drive_with_delay(&next_req, 1)?;
+        ack.drive_with_delay(&next_ack, 1)?;
+        data.drive_with_delay(&next_data, 1)?;
+        self.ready.drive(&in_ready)?;
+        Ok(res)
+    }
+
+    /// Waits for both `self` and `rhs` to be valid and transfers them
+    /// together. The data of `self` is in the least significant bits of the
+    /// output data, followed by the data of `rhs`.
+    pub fn join(self, rhs: Self) -> Result<Self, Error> {
+        let ready = Loop::zero(bw(1));
+        let valid = and(&self.valid, &rhs.valid);
+        let mut data =
+            dag::Awi::zero(NonZeroUsize::new(self.bw().checked_add(rhs.bw()).unwrap()).unwrap());
+        data.field_to(0, &self.data, self.bw()).unwrap();
+        data.field_to(self.bw(), &rhs.data, rhs.bw()).unwrap();
+        self.ready.drive(&and(&ready, &rhs.valid))?;
+        rhs.ready.drive(&and(&ready, &self.valid))?;
+        Ok(Self { valid, data, ready })
+    }
+}
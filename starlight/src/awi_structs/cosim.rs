@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+use crate::{awi, Epoch, Error, EvalAwi, LazyAwi};
+
+#[derive(Debug)]
+struct Connection<'a> {
+    src_epoch: usize,
+    src: &'a EvalAwi,
+    dst_epoch: usize,
+    dst: &'a LazyAwi,
+    delay: u128,
+    /// Values that have been sampled but not yet delivered, with the times at
+    /// which they are delivered
+    in_flight: VecDeque<(u128, awi::Awi)>,
+    /// The last value delivered to `dst`
+    last: Option<awi::Awi>,
+}
+
+/// A co-simulation harness that connects `EvalAwi` outputs of some `Epoch`s to
+/// `LazyAwi` inputs of other `Epoch`s, and advances all of them in lockstep
+/// one unit of time per [CoSim::step]. This can be used to simulate a routed
+/// target against the original program or a testbench without manually
+/// shuttling values. The `Epoch`s are entered with [Epoch::scope], so they
+/// can be created in any order or with [Epoch::new_detached].
+///
+/// In each step, values sampled by delayed connections that are due are
+/// delivered, then zero delay connections are propagated until nothing
+/// changes, then the delayed connections are sampled, and finally every
+/// `Epoch` is run for one unit of time.
+///
+/// ```
+/// use starlight::{awi, dag, CoSim, Epoch, EvalAwi, LazyAwi, Loop};
+///
+/// // a counter design
+/// let design = Epoch::new_detached();
+/// let (enable, count) = design.scope(|_| {
+///     use dag::*;
+///     let enable = LazyAwi::opaque(bw(1));
+///     let counter = Loop::zero(bw(8));
+///     let mut next = awi!(counter);
+///     next.inc_(enable.to_bool());
+///     let count = EvalAwi::from(&counter);
+///     counter.drive_with_delay(&next, 1).unwrap();
+///     (enable, count)
+/// });
+/// // a testbench that toggles the enable every cycle
+/// let testbench = Epoch::new_detached();
+/// let (toggle, count_in, seen) = testbench.scope(|_| {
+///     use dag::*;
+///     let toggle = Loop::zero(bw(1));
+///     let mut next = awi!(toggle);
+///     next.not_();
+///     let out = EvalAwi::from(&toggle);
+///     toggle.drive_with_delay(&next, 1).unwrap();
+///     let count_in = LazyAwi::opaque(bw(8));
+///     let seen = EvalAwi::from(&count_in);
+///     (out, count_in, seen)
+/// });
+///
+/// let mut cosim = CoSim::new();
+/// let d = cosim.add_epoch(&design);
+/// let t = cosim.add_epoch(&testbench);
+/// cosim.connect(t, &toggle, d, &enable, 0).unwrap();
+/// cosim.connect(d, &count, t, &count_in, 0).unwrap();
+/// cosim.run(6).unwrap();
+/// assert_eq!(cosim.time(), 6);
+/// // the enable was set in 3 of the 6 cycles
+/// assert_eq!(design.scope(|_| count.eval_u8()).unwrap(), 3);
+/// cosim.step().unwrap();
+/// assert_eq!(testbench.scope(|_| seen.eval_u8()).unwrap(), 3);
+/// drop(cosim);
+/// design.scope(|_| {
+///     drop(enable);
+///     drop(count);
+/// });
+/// testbench.scope(|_| {
+///     drop(toggle);
+///     drop(count_in);
+///     drop(seen);
+/// });
+/// ```
+#[derive(Debug, Default)]
+pub struct CoSim<'a> {
+    epochs: Vec<&'a Epoch>,
+    connections: Vec<Connection<'a>>,
+    time: u128,
+}
+
+impl<'a> CoSim<'a> {
+    /// Creates an empty `CoSim` at time zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `epoch` to be advanced by `self`, returning its index for use in
+    /// [CoSim::connect]
+    pub fn add_epoch(&mut self, epoch: &'a Epoch) -> usize {
+        self.epochs.push(epoch);
+        self.epochs.len() - 1
+    }
+
+    /// Returns the number of units of time that have been stepped
+    pub fn time(&self) -> u128 {
+        self.time
+    }
+
+    /// Connects `src` of the `Epoch` at index `src_epoch` to `dst` of the
+    /// `Epoch` at index `dst_epoch`, so that `dst` is retroactively assigned
+    /// the value of `src` after `delay` units of time
+    ///
+    /// # Errors
+    ///
+    /// If an index is out of bounds or the bitwidths do not match
+    pub fn connect(
+        &mut self,
+        src_epoch: usize,
+        src: &'a EvalAwi,
+        dst_epoch: usize,
+        dst: &'a LazyAwi,
+        delay: u128,
+    ) -> Result<(), Error> {
+        for i in [src_epoch, dst_epoch] {
+            if i >= self.epochs.len() {
+                return Err(Error::OtherString(format!(
+                    "`CoSim::connect` was given epoch index {i}, but there are {} epochs",
+                    self.epochs.len()
+                )))
+            }
+        }
+        if src.nzbw() != dst.nzbw() {
+            return Err(Error::BitwidthMismatch(src.bw(), dst.bw()))
+        }
+        self.connections.push(Connection {
+            src_epoch,
+            src,
+            dst_epoch,
+            dst,
+            delay,
+            in_flight: VecDeque::new(),
+            last: None,
+        });
+        Ok(())
+    }
+
+    /// Evaluates the source of connection `i`
+    fn sample(&self, i: usize) -> Result<awi::Awi, Error> {
+        let connection = &self.connections[i];
+        self.epochs[connection.src_epoch].scope(|_| connection.src.eval())
+    }
+
+    /// Assigns `val` to the destination of connection `i`, returning if it
+    /// changed from the last delivered value
+    fn deliver(&mut self, i: usize, val: awi::Awi) -> Result<bool, Error> {
+        let connection = &self.connections[i];
+        if connection.last.as_ref() == Some(&val) {
+            return Ok(false)
+        }
+        self.epochs[connection.dst_epoch].scope(|_| connection.dst.retro_(&val))?;
+        self.connections[i].last = Some(val);
+        Ok(true)
+    }
+
+    /// Advances all the `Epoch`s by one unit of time
+    ///
+    /// # Errors
+    ///
+    /// If evaluation fails in any `Epoch`, or if zero delay connections form
+    /// a combinational loop that does not settle
+    pub fn step(&mut self) -> Result<(), Error> {
+        for i in 0..self.connections.len() {
+            while let Some((time, _)) = self.connections[i].in_flight.front() {
+                if *time > self.time {
+                    break
+                }
+                let (_, val) = self.connections[i].in_flight.pop_front().unwrap();
+                self.deliver(i, val)?;
+            }
+        }
+        // any chain of changes through zero delay connections that does not loop
+        // settles in this many rounds
+        let mut settled = false;
+        for _ in 0..=self.connections.len() {
+            let mut changed = false;
+            for i in 0..self.connections.len() {
+                if self.connections[i].delay == 0 {
+                    let val = self.sample(i)?;
+                    changed |= self.deliver(i, val)?;
+                }
+            }
+            if !changed {
+                settled = true;
+                break
+            }
+        }
+        if !settled {
+            return Err(Error::OtherStr(
+                "`CoSim` zero delay connections form a combinational loop that does not settle",
+            ))
+        }
+        for i in 0..self.connections.len() {
+            let delay = self.connections[i].delay;
+            if delay != 0 {
+                let val = self.sample(i)?;
+                let time = self.time.checked_add(delay).unwrap();
+                self.connections[i].in_flight.push_back((time, val));
+            }
+        }
+        for epoch in &self.epochs {
+            epoch.scope(|epoch| epoch.run(1))?;
+        }
+        self.time = self.time.checked_add(1).unwrap();
+        Ok(())
+    }
+
+    /// Calls [CoSim::step] `steps` times
+    pub fn run(&mut self, steps: u128) -> Result<(), Error> {
+        for _ in 0..steps {
+            self.step()?;
+        }
+        Ok(())
+    }
+}
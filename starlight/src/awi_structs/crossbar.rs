@@ -0,0 +1,159 @@
+use std::{num::NonZeroUsize, ops::Range};
+
+use awint::bw;
+
+use crate::{
+    dag,
+    lower::meta::{lowest_set, onehot_mux, tsmear},
+    Channel, Error, Loop,
+};
+
+/// Returns a constant of bitwidth `w` with the value `x`, `x` is truncated if
+/// it does not fit
+fn constant(w: NonZeroUsize, x: usize) -> dag::Awi {
+    let mut res = dag::Awi::zero(w);
+    res.usize_(x);
+    res
+}
+
+/// Returns a single bit that is set if `x` is in `range`
+fn in_range(x: &dag::Bits, range: &Range<usize>) -> dag::bool {
+    let start = x.uge(&constant(x.nzbw(), range.start)).unwrap();
+    if dag::Bits::nontrivial_bits(range.end).map_or(0, |w| w.get()) > x.bw() {
+        // the end is past everything that `x` can represent
+        start
+    } else {
+        start & x.ult(&constant(x.nzbw(), range.end)).unwrap()
+    }
+}
+
+/// Connects the `masters` to slaves in an N by M crossbar, returning the
+/// channels to the slaves. Every master is a request [Channel] that has its
+/// target address in the `address` bit range of its data, and slave `j`
+/// receives the requests with addresses in `regions[j]`. Requests that do not
+/// fall into any region go to an extra default slave, which is the last
+/// returned channel, so that it can be answered with an error instead of
+/// stalling the master. All masters must have the same data bitwidth.
+///
+/// The data of each slave channel is the data of the master followed by the
+/// index of the master in the most significant bits, with the bitwidth needed
+/// to represent `masters.len() - 1` (or 1 bit). Responses can be routed back
+/// with a second `crossbar` that uses the index as the address and has a
+/// region of one address per master.
+///
+/// Each slave has a round robin arbiter, where the master that was granted
+/// last has the lowest priority. A grant is held while the slave is not
+/// ready, so the slave channels keep to the protocol of [Channel::new].
+/// Different slaves can transfer from different masters in the same cycle.
+/// If `pipeline` is nonzero, that many register stages are inserted at each
+/// slave output, see [Channel::fifo].
+///
+/// # Errors
+///
+/// If there are no masters, the masters have different data bitwidths,
+/// `address` is empty or not within the data, or if `regions` are empty or
+/// overlap
+pub fn crossbar(
+    masters: Vec<Channel>,
+    address: Range<usize>,
+    regions: &[Range<usize>],
+    pipeline: usize,
+) -> Result<Vec<Channel>, Error> {
+    let Some(num_masters) = NonZeroUsize::new(masters.len()) else {
+        return Err(Error::OtherStr("`crossbar` called with no masters"))
+    };
+    let data_w = masters[0].nzbw();
+    for master in &masters {
+        if master.nzbw() != data_w {
+            return Err(Error::BitwidthMismatch(data_w.get(), master.bw()))
+        }
+    }
+    let Some(addr_w) = NonZeroUsize::new(address.end.saturating_sub(address.start)) else {
+        return Err(Error::OtherStr(
+            "`crossbar` called with an empty `address` range",
+        ))
+    };
+    if address.end > data_w.get() {
+        return Err(Error::OtherString(format!(
+            "`crossbar` `address` range {address:?} is not within the data bitwidth {data_w}"
+        )))
+    }
+    for (j, region) in regions.iter().enumerate() {
+        if region.is_empty() {
+            return Err(Error::OtherString(format!(
+                "`crossbar` region {j} ({region:?}) is empty"
+            )))
+        }
+        for (k, other) in regions[..j].iter().enumerate() {
+            if (region.start < other.end) && (other.start < region.end) {
+                return Err(Error::OtherString(format!(
+                    "`crossbar` region {j} ({region:?}) overlaps with region {k} ({other:?})"
+                )))
+            }
+        }
+    }
+    let num_slaves = regions.len() + 1;
+    let id_w = dag::Bits::nontrivial_bits(num_masters.get() - 1).unwrap_or(bw(1));
+    let out_w = data_w.checked_add(id_w.get()).unwrap();
+
+    // `requests[j]` has bit `i` set if master `i` requests slave `j`
+    let mut requests = vec![dag::Awi::zero(num_masters); num_slaves];
+    let mut datas = vec![];
+    for (i, master) in masters.iter().enumerate() {
+        let mut addr = dag::Awi::zero(addr_w);
+        addr.field_from(master.data(), address.start, addr_w.get())
+            .unwrap();
+        let mut any = dag::bool::from(false);
+        for (j, region) in regions.iter().enumerate() {
+            let hit = in_range(&addr, region);
+            any |= hit;
+            requests[j].set(i, master.valid().to_bool() & hit).unwrap();
+        }
+        requests[num_slaves - 1]
+            .set(i, master.valid().to_bool() & !any)
+            .unwrap();
+        let mut data = dag::Awi::zero(out_w);
+        data.field_to(0, master.data(), data_w.get()).unwrap();
+        data.field_to(data_w.get(), &constant(id_w, i), id_w.get())
+            .unwrap();
+        datas.push(data);
+    }
+
+    let mut master_ready = dag::Awi::zero(num_masters);
+    let mut res = vec![];
+    for request in requests {
+        // the masters with a higher priority than the last granted one
+        let priority = Loop::umax(num_masters);
+        // the grant of the last cycle if it stalled
+        let held = Loop::zero(num_masters);
+        let stalled = Loop::zero(bw(1));
+        let mut preferred = request.clone();
+        preferred.and_(&priority).unwrap();
+        let mut grant = lowest_set(&request)?;
+        grant
+            .mux_(&lowest_set(&preferred)?, !preferred.is_zero())
+            .unwrap();
+        grant.mux_(&held, stalled.to_bool()).unwrap();
+        let valid = dag::Awi::from_bool(!grant.is_zero());
+        let data = onehot_mux(&datas, &grant)?;
+        let slave = Channel::new(&valid, &data)?;
+        let ready = dag::Awi::from(slave.ready());
+        let fire = valid.to_bool() & ready.to_bool();
+        let mut granted_ready = dag::Awi::zero(num_masters);
+        granted_ready.mux_(&grant, ready.to_bool()).unwrap();
+        master_ready.or_(&granted_ready).unwrap();
+
+        // after a transfer, the masters above the granted one go first
+        let mut next_priority = tsmear(&grant)?;
+        next_priority.not_();
+        next_priority.mux_(&priority, !fire).unwrap();
+        priority.drive_with_delay(&next_priority, 1)?;
+        held.drive_with_delay(&grant, 1)?;
+        stalled.drive_with_delay(&dag::Awi::from_bool(valid.to_bool() & !ready.to_bool()), 1)?;
+        res.push(slave.fifo(pipeline)?);
+    }
+    for (i, master) in masters.into_iter().enumerate() {
+        master.drive_ready(&dag::Awi::from_bool(master_ready.get(i).unwrap()))?;
+    }
+    Ok(res)
+}
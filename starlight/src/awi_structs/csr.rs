@@ -0,0 +1,593 @@
+//! Control and status register generation.
+//!
+//! A [CsrMap] describes named registers at addresses of a simple bus, each
+//! made of named fields with an access type. [Csr::new] generates the
+//! address decoding, the `Loop` based storage, and the read data mux of a
+//! map, and [CsrMap::to_json] writes a machine readable register map for
+//! software.
+//!
+//! ```
+//! use starlight::{awi, csr::*, dag::*, Epoch, EvalAwi, LazyAwi};
+//! let epoch = Epoch::new();
+//! let map = {
+//!     use awi::*;
+//!     let mut map = CsrMap::new(bw(8), bw(4));
+//!     map.register("ctrl", 0).unwrap();
+//!     map.field("enable", bw(1), CsrAccess::ReadWrite, &awi!(0))
+//!         .unwrap();
+//!     map.field("mode", bw(3), CsrAccess::ReadWrite, &awi!(5_u3))
+//!         .unwrap();
+//!     map.register("status", 1).unwrap();
+//!     map.field("busy", bw(1), CsrAccess::ReadOnly, &awi!(0))
+//!         .unwrap();
+//!     map
+//! };
+//! println!("{}", map.to_json());
+//!
+//! let write = LazyAwi::opaque(bw(1));
+//! let address = LazyAwi::opaque(bw(4));
+//! let write_data = LazyAwi::opaque(bw(8));
+//! let mut csr = Csr::new(&map, &write, &address, &write_data).unwrap();
+//! let enable = csr.field("ctrl", "enable").unwrap();
+//! // the status reflects the control register
+//! csr.set("status", "busy", &enable).unwrap();
+//! let read_data = EvalAwi::from(&csr.drive().unwrap());
+//!
+//! {
+//!     use awi::*;
+//!     address.retro_(&awi!(1_u4)).unwrap();
+//!     write.retro_bool_(false).unwrap();
+//!     write_data.retro_u8_(0).unwrap();
+//!     assert_eq!(read_data.eval_u8().unwrap(), 0);
+//!     // write to `ctrl`
+//!     address.retro_(&awi!(0_u4)).unwrap();
+//!     write.retro_bool_(true).unwrap();
+//!     write_data.retro_u8_(0b0111).unwrap();
+//!     epoch.run(1).unwrap();
+//!     write.retro_bool_(false).unwrap();
+//!     assert_eq!(read_data.eval_u8().unwrap(), 0b0111);
+//!     address.retro_(&awi!(1_u4)).unwrap();
+//!     assert_eq!(read_data.eval_u8().unwrap(), 1);
+//! }
+//! drop(epoch);
+//! ```
+
+use std::{fmt::Write, num::NonZeroUsize};
+
+use crate::{awi, dag, lower::meta::onehot_mux, Error, Loop};
+
+/// The software access type of a [CsrField]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CsrAccess {
+    /// Stored, software can read and write it
+    ReadWrite,
+    /// Driven by hardware with [Csr::set], software writes are ignored
+    ReadOnly,
+    /// Not stored, software writes appear as a single cycle pulse from
+    /// [Csr::field] and reads return zero
+    WriteOnly,
+    /// Stored, hardware sets bits with [Csr::set] and software clears them by
+    /// writing ones. If both happen in the same cycle, the set wins.
+    WriteOneToClear,
+}
+
+impl CsrAccess {
+    /// Returns the short name used in register maps
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CsrAccess::ReadWrite => "rw",
+            CsrAccess::ReadOnly => "ro",
+            CsrAccess::WriteOnly => "wo",
+            CsrAccess::WriteOneToClear => "w1c",
+        }
+    }
+
+    /// Returns if fields of this access type have storage
+    pub fn is_stored(self) -> bool {
+        matches!(self, CsrAccess::ReadWrite | CsrAccess::WriteOneToClear)
+    }
+}
+
+/// A field of a [CsrRegister]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrField {
+    pub name: String,
+    /// The bit offset of the field within the register
+    pub offset: usize,
+    pub width: NonZeroUsize,
+    pub access: CsrAccess,
+    /// The value stored fields start with, and the value that
+    /// [CsrAccess::ReadOnly] fields are documented to have after reset
+    pub reset: awi::Awi,
+}
+
+/// A register of a [CsrMap]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrRegister {
+    pub name: String,
+    /// The word address of the register on the bus
+    pub address: usize,
+    /// The fields of the register in the order they were added
+    pub fields: Vec<CsrField>,
+}
+
+/// The description of a set of control and status registers, see the
+/// [module level documentation](crate::csr)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrMap {
+    data_width: NonZeroUsize,
+    address_width: NonZeroUsize,
+    registers: Vec<CsrRegister>,
+}
+
+/// Names are restricted so that they can be used as identifiers by software
+/// and written to register maps without escaping
+fn check_name(name: &str) -> Result<(), Error> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || (c == '_'))
+        .unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || (c == '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::OtherString(format!(
+            "CSR name \"{name}\" is not a valid identifier"
+        )))
+    }
+}
+
+/// Formats `bits` as hexadecimal without a prefix
+fn hex(bits: &awi::Bits) -> String {
+    awi::Awi::bits_to_string_radix(bits, false, 16, false, 1).unwrap()
+}
+
+impl CsrMap {
+    /// Creates an empty map for a bus with `data_width` bit words and
+    /// `address_width` bit word addresses
+    pub fn new(data_width: NonZeroUsize, address_width: NonZeroUsize) -> Self {
+        Self {
+            data_width,
+            address_width,
+            registers: vec![],
+        }
+    }
+
+    /// Returns the bitwidth of the bus data
+    pub fn data_width(&self) -> NonZeroUsize {
+        self.data_width
+    }
+
+    /// Returns the bitwidth of the bus addresses
+    pub fn address_width(&self) -> NonZeroUsize {
+        self.address_width
+    }
+
+    /// Returns the registers in the order they were added
+    pub fn registers(&self) -> &[CsrRegister] {
+        &self.registers
+    }
+
+    /// Returns the register named `name`
+    pub fn get_register(&self, name: &str) -> Option<&CsrRegister> {
+        self.registers.iter().find(|reg| reg.name == name)
+    }
+
+    /// Adds a register named `name` at `address`. Fields added afterwards go
+    /// into this register.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not an identifier or is already used, or if `address` does
+    /// not fit in the address width or is already used
+    pub fn register(&mut self, name: &str, address: usize) -> Result<(), Error> {
+        check_name(name)?;
+        if self.get_register(name).is_some() {
+            return Err(Error::OtherString(format!(
+                "CSR register \"{name}\" already exists"
+            )))
+        }
+        if dag::Bits::nontrivial_bits(address).map_or(0, |w| w.get()) > self.address_width.get() {
+            return Err(Error::OtherString(format!(
+                "address {address} of CSR register \"{name}\" does not fit in {} bits",
+                self.address_width
+            )))
+        }
+        if let Some(reg) = self.registers.iter().find(|reg| reg.address == address) {
+            return Err(Error::OtherString(format!(
+                "address {address} of CSR register \"{name}\" is already used by \"{}\"",
+                reg.name
+            )))
+        }
+        self.registers.push(CsrRegister {
+            name: name.to_owned(),
+            address,
+            fields: vec![],
+        });
+        Ok(())
+    }
+
+    /// Adds a field to the last added register, placed directly above the
+    /// highest field so far. `reset` is the initial value.
+    ///
+    /// # Errors
+    ///
+    /// See [CsrMap::field_at]
+    pub fn field(
+        &mut self,
+        name: &str,
+        width: NonZeroUsize,
+        access: CsrAccess,
+        reset: &awi::Bits,
+    ) -> Result<(), Error> {
+        let offset = self
+            .registers
+            .last()
+            .and_then(|reg| reg.fields.iter().map(|f| f.offset + f.width.get()).max())
+            .unwrap_or(0);
+        self.field_at(name, offset, width, access, reset)
+    }
+
+    /// Adds a field to the last added register at bit `offset`. `reset` is
+    /// the initial value.
+    ///
+    /// # Errors
+    ///
+    /// If no register has been added, if `name` is not an identifier or is
+    /// already used in the register, if the field overlaps another field or
+    /// does not fit in the data width, or if `reset.bw() != width.get()`
+    pub fn field_at(
+        &mut self,
+        name: &str,
+        offset: usize,
+        width: NonZeroUsize,
+        access: CsrAccess,
+        reset: &awi::Bits,
+    ) -> Result<(), Error> {
+        check_name(name)?;
+        if reset.nzbw() != width {
+            return Err(Error::BitwidthMismatch(width.get(), reset.bw()))
+        }
+        let data_width = self.data_width.get();
+        let Some(reg) = self.registers.last_mut() else {
+            return Err(Error::OtherStr(
+                "`CsrMap::field` was called before any register was added",
+            ))
+        };
+        let Some(end) = offset
+            .checked_add(width.get())
+            .filter(|end| *end <= data_width)
+        else {
+            return Err(Error::OtherString(format!(
+                "CSR field \"{}.{name}\" does not fit in {data_width} bits",
+                reg.name
+            )))
+        };
+        for field in &reg.fields {
+            if field.name == name {
+                return Err(Error::OtherString(format!(
+                    "CSR field \"{}.{name}\" already exists",
+                    reg.name
+                )))
+            }
+            if (offset < (field.offset + field.width.get())) && (field.offset < end) {
+                return Err(Error::OtherString(format!(
+                    "CSR field \"{}.{name}\" overlaps with \"{}\"",
+                    reg.name, field.name
+                )))
+            }
+        }
+        reg.fields.push(CsrField {
+            name: name.to_owned(),
+            offset,
+            width,
+            access,
+            reset: awi::Awi::from(reset),
+        });
+        Ok(())
+    }
+
+    /// Returns the reset value of the whole register `name`, with
+    /// [CsrAccess::WriteOnly] fields reading as zero
+    pub fn reset_value(&self, name: &str) -> Option<awi::Awi> {
+        let reg = self.get_register(name)?;
+        let mut res = awi::Awi::zero(self.data_width);
+        for field in &reg.fields {
+            if field.access != CsrAccess::WriteOnly {
+                res.field_to(field.offset, &field.reset, field.width.get())
+                    .unwrap();
+            }
+        }
+        Some(res)
+    }
+
+    /// Returns a JSON register map for software. The object has the
+    /// `"data_width"` and `"address_width"` of the bus and a `"registers"`
+    /// array, where each register has a `"name"`, `"address"`, `"reset"`, and
+    /// a `"fields"` array. Each field has a `"name"`, `"offset"`, `"width"`,
+    /// `"access"` (one of `"rw"`, `"ro"`, `"wo"`, or `"w1c"`), and `"reset"`.
+    /// Reset values are hexadecimal strings.
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        write!(
+            s,
+            "{{\"data_width\":{},\"address_width\":{},\"registers\":[",
+            self.data_width, self.address_width
+        )
+        .unwrap();
+        for (i, reg) in self.registers.iter().enumerate() {
+            if i != 0 {
+                s.push(',');
+            }
+            write!(
+                s,
+                "{{\"name\":\"{}\",\"address\":{},\"reset\":\"0x{}\",\"fields\":[",
+                reg.name,
+                reg.address,
+                hex(&self.reset_value(&reg.name).unwrap())
+            )
+            .unwrap();
+            for (j, field) in reg.fields.iter().enumerate() {
+                if j != 0 {
+                    s.push(',');
+                }
+                write!(
+                    s,
+                    "{{\"name\":\"{}\",\"offset\":{},\"width\":{},\"access\":\"{}\",\"reset\":\"\
+                     0x{}\"}}",
+                    field.name,
+                    field.offset,
+                    field.width,
+                    field.access.as_str(),
+                    hex(&field.reset)
+                )
+                .unwrap();
+            }
+            s.push_str("]}");
+        }
+        s.push_str("]}");
+        s
+    }
+}
+
+/// The generated logic of a [CsrMap]. The stored fields are `Loop`s driven
+/// with a delay of 1, so a bus cycle corresponds to `epoch.run(1)`. Hardware
+/// reads fields with [Csr::field] and provides the values of read only fields
+/// and the set bits of write one to clear fields with [Csr::set], and then
+/// [Csr::drive] finishes the logic and returns the read data.
+#[derive(Debug)]
+pub struct Csr {
+    map: CsrMap,
+    write: dag::Awi,
+    write_data: dag::Awi,
+    /// If the address selects each register
+    hits: Vec<dag::Awi>,
+    /// The storage of each field of each register, if it is stored
+    storage: Vec<Vec<Option<Loop>>>,
+    /// The values set by hardware of each field of each register
+    hw: Vec<Vec<Option<dag::Awi>>>,
+}
+
+impl Csr {
+    /// Generates the address decoding and storage of `map` for a bus where
+    /// the single bit `write` writes `write_data` to the register at
+    /// `address` at the end of the cycle.
+    ///
+    /// # Errors
+    ///
+    /// If `write.bw() != 1` or the bitwidths of `address` or `write_data` do
+    /// not match the map
+    pub fn new(
+        map: &CsrMap,
+        write: &dag::Bits,
+        address: &dag::Bits,
+        write_data: &dag::Bits,
+    ) -> Result<Self, Error> {
+        if write.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, write.bw()))
+        }
+        if address.nzbw() != map.address_width {
+            return Err(Error::BitwidthMismatch(
+                map.address_width.get(),
+                address.bw(),
+            ))
+        }
+        if write_data.nzbw() != map.data_width {
+            return Err(Error::BitwidthMismatch(
+                map.data_width.get(),
+                write_data.bw(),
+            ))
+        }
+        let mut hits = vec![];
+        let mut storage = vec![];
+        let mut hw = vec![];
+        for reg in &map.registers {
+            let mut reg_address = dag::Awi::zero(map.address_width);
+            reg_address.usize_(reg.address);
+            hits.push(dag::Awi::from_bool(address.const_eq(&reg_address).unwrap()));
+            storage.push(
+                reg.fields
+                    .iter()
+                    .map(|field| {
+                        if field.access.is_stored() {
+                            Some(Loop::from_bits(&dag::Awi::from(&field.reset)))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect(),
+            );
+            hw.push(vec![None; reg.fields.len()]);
+        }
+        Ok(Self {
+            map: map.clone(),
+            write: dag::Awi::from(write),
+            write_data: dag::Awi::from(write_data),
+            hits,
+            storage,
+            hw,
+        })
+    }
+
+    /// Returns the map `self` was generated from
+    pub fn map(&self) -> &CsrMap {
+        &self.map
+    }
+
+    /// Returns the indexes of the register and field
+    fn find(&self, register: &str, field: &str) -> Result<(usize, usize), Error> {
+        let reg_i = self
+            .map
+            .registers
+            .iter()
+            .position(|reg| reg.name == register)
+            .ok_or_else(|| Error::OtherString(format!("no CSR register \"{register}\"")))?;
+        let field_i = self.map.registers[reg_i]
+            .fields
+            .iter()
+            .position(|f| f.name == field)
+            .ok_or_else(|| Error::OtherString(format!("no CSR field \"{register}.{field}\"")))?;
+        Ok((reg_i, field_i))
+    }
+
+    /// Returns if the register with index `reg_i` is written this cycle
+    fn write_enable(&self, reg_i: usize) -> dag::Awi {
+        let mut res = self.hits[reg_i].clone();
+        res.and_(&self.write).unwrap();
+        res
+    }
+
+    /// Returns the bits of the write data for a field
+    fn write_field(&self, field: &CsrField) -> dag::Awi {
+        let mut res = dag::Awi::zero(field.width);
+        res.field_from(&self.write_data, field.offset, field.width.get())
+            .unwrap();
+        res
+    }
+
+    /// Returns the value of the field for use by hardware. Stored fields
+    /// return their current value, [CsrAccess::WriteOnly] fields return the
+    /// written bits during the cycle they are written and zero otherwise, and
+    /// [CsrAccess::ReadOnly] fields return what was given to [Csr::set].
+    ///
+    /// # Errors
+    ///
+    /// If the field does not exist, or if it is read only and has not been
+    /// set yet
+    pub fn field(&self, register: &str, field: &str) -> Result<dag::Awi, Error> {
+        let (reg_i, field_i) = self.find(register, field)?;
+        let csr_field = &self.map.registers[reg_i].fields[field_i];
+        if let Some(ref stored) = self.storage[reg_i][field_i] {
+            return Ok(dag::Awi::from(&**stored))
+        }
+        match csr_field.access {
+            CsrAccess::WriteOnly => {
+                let mut res = dag::Awi::zero(csr_field.width);
+                res.mux_(
+                    &self.write_field(csr_field),
+                    self.write_enable(reg_i).to_bool(),
+                )
+                .unwrap();
+                Ok(res)
+            }
+            _ => self.hw[reg_i][field_i].clone().ok_or_else(|| {
+                Error::OtherString(format!("CSR field \"{register}.{field}\" has not been set"))
+            }),
+        }
+    }
+
+    /// Sets the hardware side of the field. For [CsrAccess::ReadOnly] fields
+    /// this is the value software reads, and for
+    /// [CsrAccess::WriteOneToClear] fields the bits of `value` that are set
+    /// get set in the storage at the end of the cycle.
+    ///
+    /// # Errors
+    ///
+    /// If the field does not exist, is not read only or write one to clear, or
+    /// if `value` has the wrong bitwidth
+    pub fn set(&mut self, register: &str, field: &str, value: &dag::Bits) -> Result<(), Error> {
+        let (reg_i, field_i) = self.find(register, field)?;
+        let csr_field = &self.map.registers[reg_i].fields[field_i];
+        if !matches!(
+            csr_field.access,
+            CsrAccess::ReadOnly | CsrAccess::WriteOneToClear
+        ) {
+            return Err(Error::OtherString(format!(
+                "CSR field \"{register}.{field}\" is not set by hardware"
+            )))
+        }
+        if value.nzbw() != csr_field.width {
+            return Err(Error::BitwidthMismatch(csr_field.width.get(), value.bw()))
+        }
+        self.hw[reg_i][field_i] = Some(dag::Awi::from(value));
+        Ok(())
+    }
+
+    /// Consumes `self`, driving the storage and returning the read data of
+    /// the register at the address. The read data is combinational from the
+    /// address and is zero if no register is at the address. Write one to
+    /// clear fields that were not given to [Csr::set] are never set by
+    /// hardware.
+    ///
+    /// # Errors
+    ///
+    /// If a read only field was not given to [Csr::set]
+    pub fn drive(self) -> Result<dag::Awi, Error> {
+        let mut words = vec![];
+        let mut nexts = vec![];
+        for (reg_i, reg) in self.map.registers.iter().enumerate() {
+            let we = self.write_enable(reg_i);
+            let mut word = dag::Awi::zero(self.map.data_width);
+            for (field_i, field) in reg.fields.iter().enumerate() {
+                let width = field.width.get();
+                match field.access {
+                    CsrAccess::ReadWrite => {
+                        let stored = self.storage[reg_i][field_i].as_ref().unwrap();
+                        word.field_to(field.offset, stored, width).unwrap();
+                        let mut next = dag::Awi::from(&**stored);
+                        next.mux_(&self.write_field(field), we.to_bool()).unwrap();
+                        nexts.push(next);
+                    }
+                    CsrAccess::WriteOneToClear => {
+                        let stored = self.storage[reg_i][field_i].as_ref().unwrap();
+                        word.field_to(field.offset, stored, width).unwrap();
+                        let mut keep = dag::Awi::zero(field.width);
+                        keep.mux_(&self.write_field(field), we.to_bool()).unwrap();
+                        keep.not_();
+                        let mut next = dag::Awi::from(&**stored);
+                        next.and_(&keep).unwrap();
+                        if let Some(ref set) = self.hw[reg_i][field_i] {
+                            next.or_(set).unwrap();
+                        }
+                        nexts.push(next);
+                    }
+                    CsrAccess::ReadOnly => {
+                        let value = self.hw[reg_i][field_i].as_ref().ok_or_else(|| {
+                            Error::OtherString(format!(
+                                "read only CSR field \"{}.{}\" was never set",
+                                reg.name, field.name
+                            ))
+                        })?;
+                        word.field_to(field.offset, value, width).unwrap();
+                    }
+                    CsrAccess::WriteOnly => (),
+                }
+            }
+            words.push(word);
+        }
+        // the storage is in the same order as the next values
+        let mut nexts = nexts.into_iter();
+        for stored in self.storage.into_iter().flatten().flatten() {
+            stored.drive_with_delay(&nexts.next().unwrap(), 1)?;
+        }
+        if words.is_empty() {
+            return Ok(dag::Awi::zero(self.map.data_width))
+        }
+        let mut sel = dag::Awi::zero(NonZeroUsize::new(self.hits.len()).unwrap());
+        for (i, hit) in self.hits.iter().enumerate() {
+            sel.set(i, hit.to_bool()).unwrap();
+        }
+        onehot_mux(&words, &sel)
+    }
+}
@@ -0,0 +1,215 @@
+use std::{borrow::Borrow, marker::PhantomData, num::NonZeroUsize, ops::Deref};
+
+use crate::{dag, lower::meta::general_mux, Error};
+
+/// Implemented for fieldless enums that can be encoded into bits with
+/// [Enum]. [crate::dag_enum] can be used to define an enum along with this
+/// implementation.
+pub trait EnumVariants: Copy + PartialEq + 'static {
+    /// All the variants of the enum, the index of a variant in this list is
+    /// its encoding
+    const VARIANTS: &'static [Self];
+}
+
+/// Defines a fieldless enum along with an implementation of
+/// [EnumVariants](crate::EnumVariants), so that it can be used with
+/// [Enum](crate::Enum). The enum automatically derives `Debug`, `Clone`,
+/// `Copy`, `PartialEq`, `Eq`, and `Hash`. The variants are encoded in the
+/// order they are listed.
+///
+/// ```
+/// use starlight::{dag_enum, Enum};
+///
+/// dag_enum! {
+///     pub enum Opcode {
+///         Add,
+///         Sub,
+///         Xor,
+///     }
+/// }
+///
+/// let epoch = starlight::Epoch::new();
+/// assert_eq!(Enum::<Opcode>::bw(), 2);
+/// let opcode = Enum::new(Opcode::Sub);
+/// let is_sub = opcode.is(Opcode::Sub);
+/// drop(epoch);
+/// ```
+#[macro_export]
+macro_rules! dag_enum {
+    (
+        $(#[$attr:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$v_attr:meta])* $variant:ident),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $($(#[$v_attr])* $variant),*
+        }
+
+        impl $crate::EnumVariants for $name {
+            const VARIANTS: &'static [Self] = &[$(Self::$variant),*];
+        }
+    };
+}
+
+/// A mimicking value of the enum `E`, represented as bits with a binary
+/// encoding of the index of the variant in `E::VARIANTS`. This avoids error
+/// prone manual encoding of things like FSM states and opcodes into `Awi`s.
+#[derive(Debug, Clone)]
+pub struct Enum<E: EnumVariants> {
+    bits: dag::Awi,
+    _phantom: PhantomData<E>,
+}
+
+/// Returns the index of `variant` in `E::VARIANTS`
+fn variant_index<E: EnumVariants>(variant: E) -> usize {
+    E::VARIANTS
+        .iter()
+        .position(|v| *v == variant)
+        .expect("`EnumVariants::VARIANTS` is missing a variant")
+}
+
+impl<E: EnumVariants> Enum<E> {
+    /// Returns the bitwidth of the encoding as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw() -> NonZeroUsize {
+        let max = E::VARIANTS.len().saturating_sub(1);
+        NonZeroUsize::new(max.checked_ilog2().map_or(1, |lb| (lb as usize) + 1)).unwrap()
+    }
+
+    /// Returns the bitwidth of the encoding as a `usize`
+    #[must_use]
+    pub fn bw() -> usize {
+        Self::nzbw().get()
+    }
+
+    /// Returns the encoding of `variant`
+    fn encoding(variant: E) -> dag::Awi {
+        let mut bits = dag::Awi::zero(Self::nzbw());
+        bits.usize_(variant_index(variant));
+        bits
+    }
+
+    /// Creates an `Enum` with the constant value `variant`
+    #[must_use]
+    pub fn new(variant: E) -> Self {
+        Self {
+            bits: Self::encoding(variant),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates an `Enum` with an opaque value, which may be an invalid
+    /// encoding
+    #[must_use]
+    pub fn opaque() -> Self {
+        Self {
+            bits: dag::Awi::opaque(Self::nzbw()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates an `Enum` from the encoded `bits`, which may be an invalid
+    /// encoding
+    ///
+    /// # Errors
+    ///
+    /// If `bits.bw() != Self::bw()`
+    pub fn from_bits(bits: &dag::Bits) -> Result<Self, Error> {
+        if bits.bw() != Self::bw() {
+            return Err(Error::BitwidthMismatch(Self::bw(), bits.bw()))
+        }
+        Ok(Self {
+            bits: dag::Awi::from(bits),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Sets `self` to the constant value `variant`
+    pub fn set(&mut self, variant: E) {
+        self.bits = Self::encoding(variant);
+    }
+
+    /// Sets `self` to `variant` if `cond` is true, otherwise `self` is
+    /// unchanged
+    pub fn mux_(&mut self, variant: E, cond: impl Into<dag::bool>) {
+        self.bits
+            .mux_(&Self::encoding(variant), cond.into())
+            .unwrap();
+    }
+
+    /// Returns if `self` is `variant`
+    #[must_use]
+    pub fn is(&self, variant: E) -> dag::bool {
+        self.bits.const_eq(&Self::encoding(variant)).unwrap()
+    }
+
+    /// Returns if `self` is a valid encoding of any variant
+    #[must_use]
+    pub fn is_valid(&self) -> dag::bool {
+        let len = E::VARIANTS.len();
+        if len == 0 {
+            dag::bool::from(false)
+        } else if len == (1 << Self::bw()) {
+            dag::bool::from(true)
+        } else {
+            let mut max = dag::Awi::zero(Self::nzbw());
+            max.usize_(len - 1);
+            self.bits.ule(&max).unwrap()
+        }
+    }
+
+    /// An exhaustive `match` over the variants of `E`. `f` is called once for
+    /// every variant to get the value of the corresponding arm, and the value
+    /// of the arm for the current variant of `self` is returned. This lowers
+    /// to lookup tables selected by the encoding. An assertion that `self` is
+    /// a valid encoding is automatically registered with the current `Epoch`.
+    ///
+    /// # Errors
+    ///
+    /// If `E` has no variants or if the arms have different bitwidths
+    #[track_caller]
+    pub fn match_<F: FnMut(E) -> dag::Awi>(&self, mut f: F) -> Result<dag::Awi, Error> {
+        let mut arms: Vec<dag::Awi> = E::VARIANTS.iter().map(|variant| f(*variant)).collect();
+        let w = if let Some(arm) = arms.first() {
+            arm.bw()
+        } else {
+            return Err(Error::OtherStr(
+                "`Enum::match_` on an enum with no variants",
+            ))
+        };
+        for arm in &arms {
+            if arm.bw() != w {
+                return Err(Error::BitwidthMismatch(w, arm.bw()))
+            }
+        }
+        dag::mimick::assert!(self.is_valid());
+        if arms.len() == 1 {
+            Ok(arms.pop().unwrap())
+        } else {
+            general_mux(&arms, &self.bits)
+        }
+    }
+}
+
+impl<E: EnumVariants> Deref for Enum<E> {
+    type Target = dag::Bits;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bits
+    }
+}
+
+impl<E: EnumVariants> Borrow<dag::Bits> for Enum<E> {
+    fn borrow(&self) -> &dag::Bits {
+        &self.bits
+    }
+}
+
+impl<E: EnumVariants> AsRef<dag::Bits> for Enum<E> {
+    fn as_ref(&self) -> &dag::Bits {
+        &self.bits
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::HashMap, num::NonZeroUsize};
+
+use crate::{awi, dag, Error};
+
+/// The number of literals that are combined before a result is shared, this
+/// fits in a single 4 input lookup table
+const CHUNK: usize = 4;
+
+/// A named pattern of a [Decoder]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderPattern {
+    pub name: String,
+    /// The bits that are compared
+    pub mask: awi::Awi,
+    /// The values that the bits in `mask` must have, all other bits are zero
+    pub value: awi::Awi,
+}
+
+/// A generator of instruction decoders from a table of opcode patterns. Each
+/// pattern has a name, a mask of the instruction bits it cares about, and the
+/// values those bits must have. No two patterns may match the same
+/// instruction, which is checked when patterns are added.
+///
+/// [Decoder::decode] generates logic with a one-hot output. The literals of
+/// all patterns are ordered so that the literals common to the most patterns
+/// come first, and the comparisons are built as balanced trees of ANDs over
+/// chunks of literals, where identical subtrees are shared between patterns.
+/// This makes the opcode bits shared by groups of instructions be compared
+/// once rather than once per instruction, and keeps the depth logarithmic.
+///
+/// ```
+/// use starlight::{awi, dag::*, Decoder, Epoch, EvalAwi, LazyAwi};
+/// let epoch = Epoch::new();
+/// let mut decoder = Decoder::new(bw(8));
+/// {
+///     use awi::*;
+///     decoder
+///         .pattern("load", &awi!(0xf0_u8), &awi!(0x10_u8))
+///         .unwrap();
+///     decoder
+///         .pattern("store", &awi!(0xf0_u8), &awi!(0x20_u8))
+///         .unwrap();
+///     decoder
+///         .pattern("halt", &awi!(0xff_u8), &awi!(0xff_u8))
+///         .unwrap();
+///     // overlaps with "load"
+///     assert!(decoder
+///         .pattern("bad", &awi!(0x1f_u8), &awi!(0x11_u8))
+///         .is_err());
+/// }
+/// let inst = LazyAwi::opaque(bw(8));
+/// let onehot = EvalAwi::from(&decoder.decode(&inst).unwrap());
+/// {
+///     use awi::*;
+///     inst.retro_u8_(0x2a).unwrap();
+///     assert_eq!(onehot.eval().unwrap(), awi!(0b010_u3));
+///     inst.retro_u8_(0x00).unwrap();
+///     assert_eq!(onehot.eval().unwrap(), awi!(0b000_u3));
+/// }
+/// drop(epoch);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoder {
+    nzbw: NonZeroUsize,
+    patterns: Vec<DecoderPattern>,
+}
+
+impl Decoder {
+    /// Creates a `Decoder` with no patterns for instructions of `width` bits
+    pub fn new(width: NonZeroUsize) -> Self {
+        Self {
+            nzbw: width,
+            patterns: vec![],
+        }
+    }
+
+    /// Returns the bitwidth of the instructions as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.nzbw
+    }
+
+    /// Returns the bitwidth of the instructions as a `usize`
+    #[must_use]
+    pub fn bw(&self) -> usize {
+        self.nzbw.get()
+    }
+
+    /// Returns the patterns in the order they were added, which is the order
+    /// of the bits of the output of [Decoder::decode]
+    pub fn patterns(&self) -> &[DecoderPattern] {
+        &self.patterns
+    }
+
+    /// Returns the index of the pattern named `name`
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.patterns.iter().position(|p| p.name == name)
+    }
+
+    /// Adds a pattern that matches instructions `inst` where `inst & mask`
+    /// equals `value`
+    ///
+    /// # Errors
+    ///
+    /// If the bitwidths do not match the instruction width, if `value` has
+    /// set bits outside of `mask`, if `name` is already used, or if the
+    /// pattern can match an instruction that another pattern matches
+    pub fn pattern(
+        &mut self,
+        name: &str,
+        mask: &awi::Bits,
+        value: &awi::Bits,
+    ) -> Result<(), Error> {
+        if mask.nzbw() != self.nzbw {
+            return Err(Error::BitwidthMismatch(self.bw(), mask.bw()))
+        }
+        if value.nzbw() != self.nzbw {
+            return Err(Error::BitwidthMismatch(self.bw(), value.bw()))
+        }
+        let mut outside = awi::Awi::from(mask);
+        outside.not_();
+        outside.and_(value).unwrap();
+        if !outside.is_zero() {
+            return Err(Error::OtherString(format!(
+                "`Decoder` pattern \"{name}\" has value bits outside of its mask"
+            )))
+        }
+        if self.index_of(name).is_some() {
+            return Err(Error::OtherString(format!(
+                "`Decoder` pattern \"{name}\" already exists"
+            )))
+        }
+        for other in &self.patterns {
+            // the patterns overlap if they agree on all bits that both compare
+            let mut differ = awi::Awi::from(value);
+            differ.xor_(&other.value).unwrap();
+            differ.and_(mask).unwrap();
+            differ.and_(&other.mask).unwrap();
+            if differ.is_zero() {
+                return Err(Error::OtherString(format!(
+                    "`Decoder` pattern \"{name}\" overlaps with pattern \"{}\"",
+                    other.name
+                )))
+            }
+        }
+        self.patterns.push(DecoderPattern {
+            name: name.to_owned(),
+            mask: awi::Awi::from(mask),
+            value: awi::Awi::from(value),
+        });
+        Ok(())
+    }
+
+    /// Generates the decoding logic for `inst`, returning a one-hot value
+    /// with a bit for each pattern in the order they were added. The result
+    /// is zero if no pattern matches.
+    ///
+    /// # Errors
+    ///
+    /// If there are no patterns or `inst.bw() != self.bw()`
+    pub fn decode(&self, inst: &dag::Bits) -> Result<dag::Awi, Error> {
+        if inst.nzbw() != self.nzbw {
+            return Err(Error::BitwidthMismatch(self.bw(), inst.bw()))
+        }
+        let Some(num) = NonZeroUsize::new(self.patterns.len()) else {
+            return Err(Error::OtherStr("`Decoder::decode` called with no patterns"))
+        };
+        // literals are (bit index, value) pairs
+        let mut lits: Vec<Vec<(usize, bool)>> = vec![];
+        let mut counts: HashMap<(usize, bool), usize> = HashMap::new();
+        for pattern in &self.patterns {
+            let mut pattern_lits = vec![];
+            for i in 0..self.bw() {
+                if pattern.mask.get(i).unwrap() {
+                    let lit = (i, pattern.value.get(i).unwrap());
+                    *counts.entry(lit).or_default() += 1;
+                    pattern_lits.push(lit);
+                }
+            }
+            lits.push(pattern_lits);
+        }
+        let mut memo = HashMap::new();
+        let mut res = dag::Awi::zero(num);
+        for (i, mut pattern_lits) in lits.into_iter().enumerate() {
+            pattern_lits.sort_by(|a, b| counts[b].cmp(&counts[a]).then(a.cmp(b)));
+            res.set(i, and_tree(&mut memo, inst, &pattern_lits))
+                .unwrap();
+        }
+        Ok(res)
+    }
+}
+
+/// Returns the AND of the literals `lits` of `inst`, sharing subtrees
+/// through `memo`
+fn and_tree(
+    memo: &mut HashMap<Vec<(usize, bool)>, dag::bool>,
+    inst: &dag::Bits,
+    lits: &[(usize, bool)],
+) -> dag::bool {
+    if let Some(res) = memo.get(lits) {
+        return *res
+    }
+    let res = if lits.len() <= CHUNK {
+        let mut res = dag::bool::from(true);
+        for (i, b) in lits.iter().copied() {
+            let bit = inst.get(i).unwrap();
+            res &= if b { bit } else { !bit };
+        }
+        res
+    } else {
+        // split on a chunk boundary so that chunks line up between patterns
+        let chunks = lits.len().div_ceil(CHUNK);
+        let mid = chunks.div_ceil(2) * CHUNK;
+        let (lhs, rhs) = lits.split_at(mid);
+        and_tree(memo, inst, lhs) & and_tree(memo, inst, rhs)
+    };
+    memo.insert(lits.to_vec(), res);
+    res
+}
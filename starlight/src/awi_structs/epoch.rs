@@ -5,10 +5,13 @@
 
 use std::{
     cell::RefCell,
-    fmt::Debug,
+    collections::{hash_map::RandomState, HashMap},
+    fmt::{self, Debug},
+    hash::{BuildHasher, Hasher},
     mem::{self},
     num::NonZeroUsize,
     rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
     thread::panicking,
 };
 
@@ -21,9 +24,21 @@ use awint::{
     bw, dag,
 };
 
+use super::external::External;
 use crate::{
-    ensemble::{Delay, Ensemble, Value},
-    Error, EvalAwi,
+    awi,
+    ensemble::{
+        ApproxConfig, ApproxReport, BatchSim, BlackBox, CellLibrary, CombinationalLoop,
+        CommonValue, DeadReport, Delay, Ensemble, ErrorSpec, EvalCache, EvalCacheStats, FlowReport,
+        Induction, Journal, Limits, LoweringReport, LutTableStats, MacroOp, MappedNetlist, Match,
+        OptimizePass, OptimizeReport, OptimizeSettings, OutputReport, PExternal, PassReport,
+        PowerModel, PowerReport, Profile, Provenance, RandomizeReport, ReachabilityReport,
+        RegState, ReplayLog, Replayed, ResetKind, ResetReport, ScanReport, TieBreak, Value,
+    },
+    lower::{meta::FunnelStrategy, LutDecomposition},
+    sweep::SweepStats,
+    utils::StarRng,
+    Error, EvalAwi, LazyAwi,
 };
 
 /// A list of single bit `EvalAwi`s for assertions
@@ -46,6 +61,55 @@ impl Default for Assertions {
 
 ptr_struct!(PEpochShared);
 
+static NEXT_EPOCH_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies an `Epoch` for diagnostics. `EvalAwi`s and `LazyAwi`s record
+/// the `EpochInfo` of the `Epoch` they were created in, and return
+/// [Error::HandleFromDifferentEpoch] if they are used while an `Epoch` with a
+/// different `lineage` is current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EpochInfo {
+    /// Unique among all `Epoch`s created by the process
+    pub id: u64,
+    /// The `id` of the `Epoch` whose `PExternal`s this `Epoch` uses. This is
+    /// the same as `id` unless the `Ensemble` was copied from another `Epoch`
+    /// such as with [Epoch::specialize] or [Epoch::coi_reduce].
+    pub lineage: u64,
+    /// The source file where the `Epoch` was created
+    pub file: &'static str,
+    pub line: u32,
+    pub col: u32,
+}
+
+impl EpochInfo {
+    #[track_caller]
+    fn new() -> Self {
+        let id = NEXT_EPOCH_ID.fetch_add(1, Ordering::Relaxed);
+        let location = std::panic::Location::caller();
+        Self {
+            id,
+            lineage: id,
+            file: location.file(),
+            line: location.line(),
+            col: location.column(),
+        }
+    }
+}
+
+impl fmt::Display for EpochInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`Epoch` {} created at {}:{}:{}",
+            self.id, self.file, self.line, self.col
+        )?;
+        if self.lineage != self.id {
+            write!(f, " (derived from `Epoch` {})", self.lineage)?;
+        }
+        Ok(())
+    }
+}
+
 /// Data stored  in `EpochData` per each live `EpochShared`
 #[derive(Debug)]
 pub struct PerEpochShared {
@@ -77,8 +141,10 @@ impl PerEpochShared {
 /// nothing with the `EpochKey` and `mem::forget`s the `EvalAwi` assertions.
 pub struct EpochData {
     pub epoch_key: Option<EpochKey>,
+    pub info: EpochInfo,
     pub ensemble: Ensemble,
     pub responsible_for: Arena<PEpochShared, PerEpochShared>,
+    pub external: External,
 }
 
 impl Drop for EpochData {
@@ -97,6 +163,7 @@ impl Debug for EpochData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("EpochData")
             .field("epoch_key", &self.epoch_key)
+            .field("info", &self.info)
             .field("responsible_for.len()", &self.responsible_for.len())
             .finish()
     }
@@ -138,11 +205,14 @@ impl Debug for EpochShared {
 
 impl EpochShared {
     /// Creates a new `EpochData` that is not registered anywhere yet.
+    #[track_caller]
     pub fn new() -> Self {
         let mut epoch_data = EpochData {
             epoch_key: None,
+            info: EpochInfo::new(),
             ensemble: Ensemble::new(),
             responsible_for: Arena::new(),
+            external: External::default(),
         };
         let p_self = epoch_data.responsible_for.insert(PerEpochShared::new());
         Self {
@@ -236,6 +306,24 @@ impl EpochShared {
         }
     }
 
+    /// Like [EpochShared::remove_as_current], but for detached `EpochShared`s
+    /// this also pops the `EpochKey` so that nothing of `self` is left on
+    /// either stack
+    pub fn remove_as_current_detached(&self) -> Result<(), Error> {
+        self.remove_as_current()?;
+        let mut lock = self.epoch_data.borrow_mut();
+        if let Some(epoch_key) = lock.epoch_key.take() {
+            if let Err((self_gen, top_gen)) = epoch_key.pop_off_epoch_stack() {
+                return Err(Error::OtherString(format!(
+                    "a detached `starlight::Epoch` was left out of stacklike order, such that an \
+                     `awint_dag::epoch::EpochKey` with generation {self_gen} was attempted to be \
+                     dropped before the current key with generation {top_gen}"
+                )))
+            }
+        }
+        Ok(())
+    }
+
     /// Removes states and drops assertions from the `Ensemble` that were
     /// associated with this particular `EpochShared`.
     ///
@@ -431,6 +519,21 @@ pub fn debug_epoch_stack() {
     });
 }
 
+/// Returns the current `EpochShared` if handles created in `created_in` can be
+/// used with it, otherwise returns `Error::HandleFromDifferentEpoch`
+pub(crate) fn get_current_epoch_for_handle(created_in: EpochInfo) -> Result<EpochShared, Error> {
+    let epoch_shared = get_current_epoch()?;
+    let current = epoch_shared.epoch_data.borrow().info;
+    if current.lineage == created_in.lineage {
+        Ok(epoch_shared)
+    } else {
+        Err(Error::HandleFromDifferentEpoch {
+            created_in,
+            current,
+        })
+    }
+}
+
 /// Allows access to the current epoch. Do no call recursively.
 pub fn no_recursive_current_epoch<T, F: FnMut(&EpochShared) -> T>(mut f: F) -> T {
     CURRENT_EPOCH.with(|top| {
@@ -460,13 +563,18 @@ pub fn _callback() -> EpochCallback {
     fn new_pstate(nzbw: NonZeroUsize, op: Op<PState>, location: Option<Location>) -> PState {
         no_recursive_current_epoch_mut(|current| {
             let mut epoch_data = current.epoch_data.borrow_mut();
+            let len = epoch_data.ensemble.stator.states.len();
             let p_state = epoch_data.ensemble.make_state(nzbw, op.clone(), location);
-            epoch_data
-                .responsible_for
-                .get_mut(current.p_self)
-                .unwrap()
-                .states_inserted
-                .push(p_state);
+            // large literals can return an existing shared state, which whoever
+            // inserted it is responsible for
+            if epoch_data.ensemble.stator.states.len() > len {
+                epoch_data
+                    .responsible_for
+                    .get_mut(current.p_self)
+                    .unwrap()
+                    .states_inserted
+                    .push(p_state);
+            }
             p_state
         })
     }
@@ -543,12 +651,43 @@ pub fn _callback() -> EpochCallback {
     }
 }
 
+/// Removes an `EpochShared` that a `scope` function made current when
+/// dropped, so that the stacks are restored even if the closure panics
+struct ScopeGuard {
+    epoch_shared: EpochShared,
+    is_detached: bool,
+    /// If the `Epoch` is dropped by an unwind, in which case its states need
+    /// to be removed here because `EpochInnerDrop` does nothing while panicking
+    is_owned: bool,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if self.is_owned {
+            let _ = self.epoch_shared.drop_associated();
+        }
+        let res = if self.is_detached {
+            self.epoch_shared.remove_as_current_detached()
+        } else {
+            self.epoch_shared.remove_as_current()
+        };
+        // do not cause a double panic if the closure is unwinding
+        if let Err(e) = res {
+            if !panicking() {
+                panic!("{e}");
+            }
+        }
+    }
+}
+
 /// Has the actual drop code attached, preventing the need for unsafe or a
 /// nonzero cost abstraction somewhere
 #[derive(Debug)]
 struct EpochInnerDrop {
     epoch_shared: EpochShared,
     is_suspended: bool,
+    /// If the `Epoch` is not on the stacks except during `Epoch::scope`
+    is_detached: bool,
 }
 
 impl Drop for EpochInnerDrop {
@@ -556,6 +695,18 @@ impl Drop for EpochInnerDrop {
     fn drop(&mut self) {
         // prevent invoking recursive panics and a buffer overrun
         if !panicking() {
+            if self.is_detached {
+                // temporarily enter so that the cleanup happens in our own `Ensemble`, this
+                // is stacklike regardless of what else is on the stacks
+                self.epoch_shared.set_as_current();
+                if let Err(e) = self.epoch_shared.drop_associated() {
+                    panic!("{e}");
+                }
+                if let Err(e) = self.epoch_shared.remove_as_current_detached() {
+                    panic!("panicked upon dropping a detached `Epoch`: {e}");
+                }
+                return
+            }
             if let Err(e) = self.epoch_shared.drop_associated() {
                 panic!("{e}");
             }
@@ -692,11 +843,36 @@ impl SuspendedEpoch {
     pub fn ensemble<O, F: FnMut(&Ensemble) -> O>(&self, f: F) -> O {
         self.shared().ensemble(f)
     }
+
+    /// Resumes `self`, runs `f` with the resumed `Epoch`, and then suspends it
+    /// again. This allows builder code to pass around `SuspendedEpoch`s and
+    /// explicitly enter them instead of depending on which `Epoch` happens to
+    /// be current. If `f` panics, the `Epoch` is removed from the stack before
+    /// it is dropped. Like all `Epoch`s, `SuspendedEpoch`s are thread local.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` leaves the `Epoch` stack in a different state than it
+    /// started with
+    #[track_caller]
+    pub fn scope<O, F: FnOnce(&Epoch) -> O>(self, f: F) -> (Self, O) {
+        let epoch = self.resume();
+        let guard = ScopeGuard {
+            epoch_shared: epoch.shared().clone(),
+            is_detached: false,
+            is_owned: true,
+        };
+        let res = f(&epoch);
+        // `suspend` does the removal instead
+        mem::forget(guard);
+        (epoch.suspend(), res)
+    }
 }
 
 impl Epoch {
     /// Creates a new `Epoch` with an independent `Ensemble`
     #[allow(clippy::new_without_default)]
+    #[track_caller]
     pub fn new() -> Self {
         let new = EpochShared::new();
         new.set_as_current();
@@ -704,10 +880,56 @@ impl Epoch {
             inner: EpochInnerDrop {
                 epoch_shared: new,
                 is_suspended: false,
+                is_detached: false,
             },
         }
     }
 
+    /// Creates a new `Epoch` with an independent `Ensemble` that is detached,
+    /// meaning that it is never registered as the current `Epoch` except
+    /// within [Epoch::scope]. All uses of it need to go through `scope`, but
+    /// in return it can be created and dropped in any order relative to other
+    /// `Epoch`s, such as by test frameworks that run tests in arbitrary order
+    /// on the same thread. Upon being dropped, it temporarily enters itself to
+    /// clean up its own states.
+    ///
+    /// ```
+    /// use starlight::{dag, Epoch, EvalAwi};
+    ///
+    /// let epoch0 = Epoch::new_detached();
+    /// let epoch1 = Epoch::new_detached();
+    /// let x = epoch0.scope(|_| EvalAwi::from(&dag::Awi::from_u8(7)));
+    /// epoch1.scope(|_| ());
+    /// assert_eq!(epoch0.scope(|_| x.eval_u8()).unwrap(), 7);
+    /// epoch0.scope(|_| drop(x));
+    /// // any order is allowed
+    /// drop(epoch0);
+    /// drop(epoch1);
+    /// ```
+    ///
+    /// Note that `Epoch::shared_with` and `Epoch::suspend` should not be used
+    /// with detached `Epoch`s.
+    #[track_caller]
+    pub fn new_detached() -> Self {
+        Self {
+            inner: EpochInnerDrop {
+                epoch_shared: EpochShared::new(),
+                is_suspended: false,
+                is_detached: true,
+            },
+        }
+    }
+
+    /// Returns if `self` was created by [Epoch::new_detached]
+    pub fn is_detached(&self) -> bool {
+        self.inner.is_detached
+    }
+
+    /// Returns the diagnostic information of `self`, see [EpochInfo]
+    pub fn info(&self) -> EpochInfo {
+        self.shared().epoch_data.borrow().info
+    }
+
     /// Creates an `Epoch` that shares the `Ensemble` of `other`
     ///
     /// The epoch from this can be dropped out of order from `other`,
@@ -720,6 +942,7 @@ impl Epoch {
             inner: EpochInnerDrop {
                 epoch_shared: shared,
                 is_suspended: false,
+                is_detached: false,
             },
         }
     }
@@ -732,7 +955,7 @@ impl Epoch {
     /// Checks if `self.shared()` is the same as the current epoch, and returns
     /// the `EpochShared` if so. Returns `NoCurrentlyActiveEpoch` or
     /// `WrongCurrentlyActiveEpoch` depending on error conditions.
-    fn check_current(&self) -> Result<EpochShared, Error> {
+    pub(crate) fn check_current(&self) -> Result<EpochShared, Error> {
         let epoch_shared = get_current_epoch()?;
         if Rc::ptr_eq(&epoch_shared.epoch_data, &self.shared().epoch_data) {
             Ok(self.shared().clone())
@@ -741,6 +964,33 @@ impl Epoch {
         }
     }
 
+    /// Runs `f` with `self` temporarily being the current `Epoch`, restoring
+    /// the previously current `Epoch` afterwards. Mimicking operations in `f`
+    /// are performed in `self` regardless of the order in which `Epoch`s were
+    /// created, which allows builder code to work against an explicit `&Epoch`
+    /// handle. The previously current `Epoch` is restored even if `f`
+    /// panics. Note that `Epoch`s are still thread local and cannot be sent
+    /// to other threads, so builder code running on a thread pool needs to
+    /// create its `Epoch`s on the thread that uses them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` leaves the `Epoch` stack in a different state than it
+    /// started with
+    #[track_caller]
+    pub fn scope<O, F: FnOnce(&Epoch) -> O>(&self, f: F) -> O {
+        if self.check_current().is_ok() {
+            return f(self)
+        }
+        self.shared().set_as_current();
+        let _guard = ScopeGuard {
+            epoch_shared: self.shared().clone(),
+            is_detached: self.is_detached(),
+            is_owned: false,
+        };
+        f(self)
+    }
+
     /// Suspends the `Epoch` from being the current epoch temporarily.
     ///
     /// # Panics
@@ -764,6 +1014,98 @@ impl Epoch {
         self.ensemble(|ensemble| ensemble.clone())
     }
 
+    /// Creates a new `Epoch` that uses `ensemble` instead of starting empty,
+    /// and sets it as the current `Epoch`. This is used for loading an
+    /// `Ensemble` from [Ensemble::parse_ir] or some other source so that
+    /// [Epoch::find_external_by_name], [Epoch::peek], and [Epoch::poke] can
+    /// be used to interact with it.
+    #[track_caller]
+    pub fn from_ensemble(ensemble: Ensemble) -> Self {
+        let epoch_shared = EpochShared::new();
+        epoch_shared.epoch_data.borrow_mut().ensemble = ensemble;
+        epoch_shared.set_as_current();
+        Self {
+            inner: EpochInnerDrop {
+                epoch_shared,
+                is_suspended: false,
+                is_detached: false,
+            },
+        }
+    }
+
+    /// Creates a copy of `self` that is specialized on the `LazyAwi`s of
+    /// `assignments` being the constants they are paired with. The copy is
+    /// lowered and optimized, which propagates the constants and prunes
+    /// everything that becomes unused, and is returned as a `SuspendedEpoch`
+    /// so that `self` stays current. `self` is unchanged, so it can be
+    /// specialized many times on different values without rebuilding the
+    /// design. Assertions are carried over to the copy.
+    ///
+    /// The `PExternal`s of the copy are the same as those of `self`, so the
+    /// `LazyAwi`s and `EvalAwi`s of `self` can be used with the specialized
+    /// `Epoch` while it is current. The remaining unassigned `LazyAwi`s stay
+    /// as inputs, and the assigned ones are constant. The handles should only
+    /// be dropped while `self` is current. Requires that `self` be the current
+    /// `Epoch`.
+    ///
+    /// # Errors
+    ///
+    /// If any of the `LazyAwi`s are not from `self`, have mismatching
+    /// bitwidths, or were already constant assigned, or if lowering fails
+    pub fn specialize(
+        &self,
+        assignments: &[(&LazyAwi, awi::Awi)],
+    ) -> Result<SuspendedEpoch, Error> {
+        let epoch_shared = self.check_current()?;
+        let p_assertions: Vec<PExternal> = self
+            .assertions()
+            .bits
+            .iter()
+            .map(|bit| bit.p_external())
+            .collect();
+        let specialized = EpochShared::new();
+        {
+            let source = epoch_shared.epoch_data.borrow();
+            let mut lock = specialized.epoch_data.borrow_mut();
+            lock.ensemble = source.ensemble.clone();
+            // the handles of `self` can be used with the copy
+            lock.info.lineage = source.info.lineage;
+        }
+        specialized.set_as_current();
+        // from here on, the drop code of `epoch` cleans up in case of errors
+        let epoch = Epoch {
+            inner: EpochInnerDrop {
+                epoch_shared: specialized,
+                is_suspended: false,
+                is_detached: false,
+            },
+        };
+        let mut bits = vec![];
+        for p_external in p_assertions {
+            bits.push(EvalAwi::try_clone_from(p_external)?);
+        }
+        let p_self = epoch.shared().p_self;
+        epoch
+            .shared()
+            .epoch_data
+            .borrow_mut()
+            .responsible_for
+            .get_mut(p_self)
+            .unwrap()
+            .assertions
+            .bits
+            .extend(bits);
+        for (lazy, value) in assignments {
+            Ensemble::change_thread_local_rnode_value(
+                lazy.p_external(),
+                CommonValue::Bits(value),
+                true,
+            )?;
+        }
+        epoch.optimize()?;
+        Ok(epoch.suspend())
+    }
+
     pub fn verify_integrity(&self) -> Result<(), Error> {
         self.ensemble(|ensemble| ensemble.verify_integrity())
     }
@@ -795,23 +1137,282 @@ impl Epoch {
         lock.ensemble.prune_unused_states()
     }
 
+    /// Starts collecting a [DeadReport] of states pruned by
+    /// [Epoch::prune_unused_states] and `LNode`s removed as unused by
+    /// optimization, grouped by their [crate::Attribution] (usually the
+    /// nearest `LazyAwi` feeding them). Any previously collected report is
+    /// discarded.
+    pub fn start_dead_report(&self) {
+        self.shared().epoch_data.borrow_mut().ensemble.dead_report = Some(DeadReport::new());
+    }
+
+    /// Stops collecting and returns the collected report, or `None` if
+    /// collection was not started
+    pub fn stop_dead_report(&self) -> Option<DeadReport> {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .dead_report
+            .take()
+    }
+
     /// Lowers states internally into `LNode`s and `TNode`s, for trees of
     /// `RNode`s that need it. This is not needed in most circumstances,
     /// `EvalAwi` and optimization functions do this on demand. Requires
     /// that `self` be the current `Epoch`.
     pub fn lower(&self) -> Result<(), Error> {
         let epoch_shared = self.check_current()?;
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .journal_lowering();
         Ensemble::handle_states_to_lower(&epoch_shared)?;
         Ensemble::lower_for_rnodes(&epoch_shared)?;
         let _ = epoch_shared.assert_assertions(false);
         Ok(())
     }
 
+    /// Lowers and then checks that no bit of the `observables` can depend on
+    /// a bit of the `secrets`, except through the bits of the
+    /// `declassifiers`. The declassifiers are `EvalAwi`s created from values
+    /// that are allowed to be derived from secrets. See
+    /// [Ensemble::information_flow] for details. Requires that `self` be the
+    /// current `Epoch`.
+    pub fn check_information_flow(
+        &self,
+        secrets: &[&LazyAwi],
+        observables: &[&EvalAwi],
+        declassifiers: &[&EvalAwi],
+    ) -> Result<FlowReport, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        let secrets: Vec<PExternal> = secrets.iter().map(|x| x.p_external()).collect();
+        let observables: Vec<PExternal> = observables.iter().map(|x| x.p_external()).collect();
+        let declassifiers: Vec<PExternal> = declassifiers.iter().map(|x| x.p_external()).collect();
+        lock.ensemble
+            .information_flow(&secrets, &observables, &declassifiers)
+    }
+
+    /// Lowers and then applies triple modular redundancy to the combinational
+    /// fan-in cones of `roots`, returning the number of triplicated `LNode`s.
+    /// The cones stop at registers and `LazyAwi`s. The selected logic is
+    /// triplicated and majority voters are inserted at every point where the
+    /// rest of the design reads from it, so the external interfaces are
+    /// preserved. This is done after lowering so that it does not need to be
+    /// done at the mimicking level, and the optimizer does not merge the
+    /// replicas. Requires that `self` be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let a = LazyAwi::opaque(bw(8));
+    /// let mut b = Awi::from(&a);
+    /// b.rotl_(1).unwrap();
+    /// b.xor_(&a).unwrap();
+    /// let b = EvalAwi::from(&b);
+    /// epoch.apply_tmr(&[&b]).unwrap();
+    /// epoch.optimize().unwrap();
+    /// drop(epoch);
+    /// ```
+    pub fn apply_tmr(&self, roots: &[&EvalAwi]) -> Result<usize, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let roots: Vec<PExternal> = roots.iter().map(|x| x.p_external()).collect();
+        lock.ensemble.apply_tmr(&roots)
+    }
+
+    /// Optimizes and then pipelines the logic of this `Epoch` by inserting
+    /// registers (`TNode`s with a delay of 1) so that no combinational path
+    /// goes through more than `max_depth` lookup tables. Every `LNode` is
+    /// assigned to the level of the longest path to it, and the pipeline
+    /// stages are only cut between whole levels. Among those cuts, the ones
+    /// needing the fewest registers are chosen, but this is not a general
+    /// min-cut or retiming register placement, and moving individual `LNode`s
+    /// off of their levels could need fewer registers. Returns the latency in
+    /// cycles of each `EvalAwi` by its `PExternal`. Every path from the
+    /// inputs to an output goes through that many registers, so the output
+    /// reflects the inputs from that many `epoch.run(1)` calls ago. Designs
+    /// that already have `Loop`s or other temporal elements are not
+    /// supported, because their timing would change. Requires that `self`
+    /// be the current `Epoch`.
+    pub fn auto_pipeline(&self, max_depth: usize) -> Result<HashMap<PExternal, usize>, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.auto_pipeline(max_depth)
+    }
+
+    /// Lowers and then locks the logic of this `Epoch` by inserting key
+    /// controlled XOR and XNOR gates after a random `density` fraction of the
+    /// `LNode`s, such that the circuit only functions correctly when
+    /// `key_bits` is set to the returned key. The key gates use the bits of
+    /// `key_bits` in order, wrapping around if there are more gates than key
+    /// bits. `key_bits` should usually be an opaque `LazyAwi` that is not used
+    /// by anything else. The key and the locked `LNode`s are chosen with a
+    /// fresh random seed every time, use [Ensemble::lock_logic] for
+    /// reproducible locking. Requires that `self` be the current `Epoch`.
+    ///
+    /// # Errors
+    ///
+    /// If `density` is not in `0.0..=1.0`, or if `key_bits` is not from this
+    /// `Epoch` or had bits removed
+    pub fn lock_logic(&self, key_bits: &LazyAwi, density: f64) -> Result<awi::Awi, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let ensemble = &mut lock.ensemble;
+        let (_, rnode) = ensemble.notary.get_rnode(key_bits.p_external())?;
+        let mut p_keys = vec![];
+        if let Some(bits) = rnode.bits() {
+            for bit in bits {
+                if let Some(bit) = bit {
+                    p_keys.push(*bit);
+                } else {
+                    return Err(Error::OtherStr(
+                        "`Epoch::lock_logic`: a bit of `key_bits` has been removed",
+                    ))
+                }
+            }
+        } else {
+            return Err(Error::OtherStr(
+                "`Epoch::lock_logic`: `key_bits` has not been initialized",
+            ))
+        }
+        // the key must not be predictable
+        let seed = RandomState::new().build_hasher().finish();
+        ensemble.lock_logic(&p_keys, density, &mut StarRng::new(seed))
+    }
+
+    /// Directly instantiates lookup tables, bypassing the usual lowering of
+    /// mimicking operations. This is intended for custom technology mapping
+    /// or importing netlists. The bits of `inputs` are concatenated to form
+    /// the index, with the first input in the least significant position.
+    /// `table` has `2^n` entries for `n` total input bits, and the bitwidth of
+    /// the returned output is the bitwidth of an entry. The inputs are lowered
+    /// if they have not been already. Requires that `self` be the current
+    /// `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{awi, dag::*, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let a = LazyAwi::opaque(bw(1));
+    /// let b = LazyAwi::opaque(bw(1));
+    /// // a single output XOR gate
+    /// let table = {
+    ///     use awi::*;
+    ///     awi!(0110)
+    /// };
+    /// let x = epoch.lut(&[&a, &b], &table).unwrap();
+    /// let x = EvalAwi::from(&x);
+    /// a.retro_bool_(true).unwrap();
+    /// b.retro_bool_(false).unwrap();
+    /// assert_eq!(x.eval_bool().unwrap(), true);
+    /// drop(epoch);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the bitwidth of `table` is not a nonzero multiple of the number of
+    /// entries, or if any of `inputs` are not from this `Epoch`
+    pub fn lut(&self, inputs: &[&dag::Bits], table: &awi::Bits) -> Result<dag::Awi, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut num_inputs = 0usize;
+        for input in inputs {
+            num_inputs = num_inputs.saturating_add(input.bw());
+        }
+        let out_w = u32::try_from(num_inputs)
+            .ok()
+            .and_then(|w| 1usize.checked_shl(w))
+            .and_then(|num_entries| {
+                if table.bw().is_multiple_of(num_entries) {
+                    NonZeroUsize::new(table.bw() / num_entries)
+                } else {
+                    None
+                }
+            })
+            .ok_or(Error::OtherString(format!(
+                "`Epoch::lut`: table bitwidth {} is not a nonzero multiple of the number of \
+                 entries for {} input bits",
+                table.bw(),
+                num_inputs
+            )))?;
+        let mut p_inputs = vec![];
+        for input in inputs {
+            let p_state = input.state();
+            Ensemble::dfs_lower(&epoch_shared, p_state)?;
+            let mut lock = epoch_shared.epoch_data.borrow_mut();
+            lock.ensemble.initialize_state_bits_if_needed(p_state)?;
+            p_inputs.push(p_state);
+        }
+        let out = dag::Awi::opaque(out_w);
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble
+            .instantiate_lut(&p_inputs, table, out.state())?;
+        Ok(out)
+    }
+
+    /// Like [Epoch::lower], except that this also returns an error if any
+    /// state has a stored error from evaluation or lowering, so that problems
+    /// like invalid bitwidths in generator code can be handled gracefully.
+    /// Errors from lowering are `Error::LoweringFailed` with context about the
+    /// operation, bitwidths, and location of the offending state. Requires
+    /// that `self` be the current `Epoch`.
+    pub fn try_lower(&self) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .journal_lowering();
+        // errors that are attributed to a state get their context below
+        let res = Ensemble::handle_states_to_lower(&epoch_shared)
+            .and_then(|()| Ensemble::lower_for_rnodes(&epoch_shared));
+        let lock = epoch_shared.epoch_data.borrow();
+        for p_state in lock.ensemble.stator.states.ptrs() {
+            if let Some(ref err) = lock.ensemble.stator.states[p_state].err {
+                let err = match err {
+                    // resource limits are not specific to the state
+                    Error::LoweringFailed(..)
+                    | Error::ResourceLimitExceeded(..)
+                    | Error::PtrCapacityExceeded(..) => err.clone(),
+                    _ => lock.ensemble.lowering_error_context(p_state, err.clone()),
+                };
+                return Err(err)
+            }
+        }
+        drop(lock);
+        res?;
+        let _ = epoch_shared.assert_assertions(false);
+        Ok(())
+    }
+
+    /// Like [Epoch::lower], except that when the lowering of a tree of states
+    /// fails, the error is recorded in the returned [LoweringReport] and
+    /// lowering continues with the other trees. This allows all the problems
+    /// in a large design to be found in one pass. Trees that depend on a
+    /// failed state are left partially lowered, so the `Epoch` should not be
+    /// used for evaluation or optimization if the report has errors. Failures
+    /// of assertions are recorded in [LoweringReport::other_errors]. Requires
+    /// that `self` be the current `Epoch`.
+    pub fn lower_best_effort(&self) -> Result<LoweringReport, Error> {
+        let epoch_shared = self.check_current()?;
+        Ok(Ensemble::lower_best_effort(&epoch_shared))
+    }
+
     /// Aggressively prunes all states, lowering `RNode`s for `EvalAwi`s and
     /// `LazyAwi`s if necessary and evaluating assertions. Requires that `self`
     /// be the current `Epoch`.
     pub fn lower_and_prune(&self) -> Result<(), Error> {
         let epoch_shared = self.check_current()?;
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .journal_lowering();
         Ensemble::handle_states_to_lower(&epoch_shared)?;
         Ensemble::lower_for_rnodes(&epoch_shared)?;
         // get rid of constant assertions
@@ -824,18 +1425,917 @@ impl Epoch {
     /// that `self` be the current `Epoch`.
     pub fn optimize(&self) -> Result<(), Error> {
         let epoch_shared = self.check_current()?;
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .journal_lowering();
+        Ensemble::handle_states_to_lower(&epoch_shared)?;
+        Ensemble::lower_for_rnodes(&epoch_shared)?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.optimize_all()?;
+        drop(lock);
+        let _ = epoch_shared.assert_assertions(false);
+        Ok(())
+    }
+
+    /// Like [Epoch::optimize], but uses
+    /// [Ensemble::optimize_all_deterministic] so that the resulting netlist
+    /// and its order do not depend on the order in which the logic was
+    /// constructed. This is useful for snapshot based tests of exports.
+    /// Requires that `self` be the current `Epoch`.
+    pub fn optimize_deterministic(&self) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .journal_lowering();
         Ensemble::handle_states_to_lower(&epoch_shared)?;
-        Ensemble::lower_for_rnodes(&epoch_shared).unwrap();
+        Ensemble::lower_for_rnodes(&epoch_shared)?;
         let mut lock = epoch_shared.epoch_data.borrow_mut();
-        lock.ensemble.optimize_all().unwrap();
+        lock.ensemble.optimize_all_deterministic()?;
         drop(lock);
         let _ = epoch_shared.assert_assertions(false);
         Ok(())
     }
 
+    /// Runs the same passes as [Epoch::optimize] while recording the
+    /// statistics before and after each pass and how long it took, see
+    /// [Epoch::optimize_passes_with_report]. Requires that `self` be the
+    /// current `Epoch`.
+    pub fn optimize_with_report(&self) -> Result<OptimizeReport, Error> {
+        self.optimize_passes_with_report(&[OptimizePass::Lower, OptimizePass::OptimizeAll])
+    }
+
+    /// Runs `passes` in order while recording a [PassReport] for each of
+    /// them, which is useful for tuning the order of passes and cost models.
+    /// Assertions are evaluated after the last pass like in
+    /// [Epoch::optimize]. Requires that `self` be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, lower::LutDecomposition, Epoch, EvalAwi, LazyAwi, OptimizePass};
+    /// let epoch = Epoch::new();
+    /// let a = LazyAwi::opaque(bw(8));
+    /// let mut b = Awi::from(&a);
+    /// b.inc_(true);
+    /// let _out = EvalAwi::from(&b);
+    /// let report = epoch
+    ///     .optimize_passes_with_report(&[
+    ///         OptimizePass::Peephole,
+    ///         OptimizePass::Lower,
+    ///         OptimizePass::OptimizeAll,
+    ///         OptimizePass::DecomposeLuts(3, LutDecomposition::Shannon),
+    ///     ])
+    ///     .unwrap();
+    /// assert_eq!(report.passes().len(), 4);
+    /// println!("{report}");
+    /// drop(epoch);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If a pass fails or if a combinational loop prevents calculating the
+    /// statistics
+    pub fn optimize_passes_with_report(
+        &self,
+        passes: &[OptimizePass],
+    ) -> Result<OptimizeReport, Error> {
+        let epoch_shared = self.check_current()?;
+        let stats = |ensemble: &Ensemble| {
+            SweepStats::from_ensemble(ensemble, Delay::from_amount(1), Delay::zero())
+        };
+        let mut report = OptimizeReport::default();
+        for pass in passes {
+            let before = stats(&epoch_shared.epoch_data.borrow().ensemble)?;
+            #[cfg(not(target_arch = "wasm32"))]
+            let start = std::time::Instant::now();
+            match *pass {
+                OptimizePass::Peephole => {
+                    epoch_shared
+                        .epoch_data
+                        .borrow_mut()
+                        .ensemble
+                        .peephole_states()?;
+                }
+                OptimizePass::Lower => {
+                    epoch_shared
+                        .epoch_data
+                        .borrow_mut()
+                        .ensemble
+                        .journal_lowering();
+                    Ensemble::handle_states_to_lower(&epoch_shared)?;
+                    Ensemble::lower_for_rnodes(&epoch_shared)?;
+                }
+                OptimizePass::OptimizeAll => {
+                    epoch_shared
+                        .epoch_data
+                        .borrow_mut()
+                        .ensemble
+                        .optimize_all()?;
+                }
+                OptimizePass::MergeEquivalentCones(max_nodes) => {
+                    let mut lock = epoch_shared.epoch_data.borrow_mut();
+                    lock.ensemble.merge_equivalent_cones(max_nodes)?;
+                    lock.ensemble.optimize_all()?;
+                }
+                OptimizePass::DecomposeLuts(max_inputs, decomposition) => {
+                    epoch_shared
+                        .epoch_data
+                        .borrow_mut()
+                        .ensemble
+                        .decompose_luts(max_inputs, decomposition)?;
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            let runtime = start.elapsed();
+            #[cfg(target_arch = "wasm32")]
+            let runtime = std::time::Duration::ZERO;
+            let after = stats(&epoch_shared.epoch_data.borrow().ensemble)?;
+            report.push(PassReport {
+                pass: *pass,
+                before,
+                after,
+                runtime,
+            });
+        }
+        let _ = epoch_shared.assert_assertions(false);
+        Ok(report)
+    }
+
+    /// Starts collecting a [Profile] of how often each internal equivalence
+    /// changes value during evaluation. Any previously collected profile is
+    /// discarded.
+    pub fn start_profiling(&self) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .evaluator
+            .toggles = Some(HashMap::new());
+    }
+
+    /// Stops profiling and returns the collected profile, which is keyed by
+    /// the [Ensemble::stable_ids] of the equivalences so that it stays valid
+    /// when the internal pointers change
+    ///
+    /// # Errors
+    ///
+    /// If profiling was not started, or if there is a combinational loop of
+    /// `LNode`s
+    pub fn stop_profiling(&self) -> Result<Profile, Error> {
+        let lock = &mut *self.shared().epoch_data.borrow_mut();
+        let toggles = lock
+            .ensemble
+            .evaluator
+            .toggles
+            .take()
+            .ok_or(Error::OtherStr("profiling was not started"))?;
+        lock.ensemble.toggles_to_profile(toggles)
+    }
+
+    /// Starts recording the dynamic support of every evaluated `EvalAwi`,
+    /// which can be retrieved with [EvalAwi::last_eval_support]. Any
+    /// previously recorded supports are discarded.
+    pub fn start_provenance(&self) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .evaluator
+            .provenance = Some(Provenance::new());
+    }
+
+    /// Stops recording supports and returns the recorded [Provenance], or
+    /// `None` if provenance tracking was not started
+    pub fn stop_provenance(&self) -> Option<Provenance> {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .evaluator
+            .provenance
+            .take()
+    }
+
+    /// Enables memoizing requested values with an [EvalCache] of at most
+    /// `capacity` entries, which is useful when the same outputs are evaluated
+    /// repeatedly in between small input changes. Any previous cache and its
+    /// statistics are discarded.
+    pub fn enable_eval_cache(&self, capacity: NonZeroUsize) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .evaluator
+            .cache = Some(EvalCache::new(capacity));
+    }
+
+    /// Disables the evaluation cache and returns its final statistics, or
+    /// `None` if it was not enabled
+    pub fn disable_eval_cache(&self) -> Option<EvalCacheStats> {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .evaluator
+            .cache
+            .take()
+            .map(|cache| cache.stats())
+    }
+
+    /// Returns the current statistics of the evaluation cache, or `None` if it
+    /// is not enabled
+    pub fn eval_cache_stats(&self) -> Option<EvalCacheStats> {
+        self.shared()
+            .epoch_data
+            .borrow()
+            .ensemble
+            .evaluator
+            .cache
+            .as_ref()
+            .map(|cache| cache.stats())
+    }
+
+    /// Returns statistics about how many `LNode` lookup tables share the same
+    /// interned allocation, see [Ensemble::lut_table_stats]
+    pub fn lut_table_stats(&self) -> LutTableStats {
+        self.ensemble(|ensemble| ensemble.lut_table_stats())
+    }
+
+    /// Estimates the dynamic power of this `Epoch` from the toggle counts in
+    /// `activity` (collected with [Epoch::start_profiling]) using the
+    /// capacitance `model`. Equivalences with cones that were changed by
+    /// optimization after the activity was collected have no toggles. The
+    /// power is broken down by [crate::Attribution]. Requires that `self` be
+    /// the current `Epoch`.
+    pub fn estimate_power(
+        &self,
+        activity: &Profile,
+        model: &PowerModel,
+    ) -> Result<PowerReport, Error> {
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble.estimate_power(activity, model)
+    }
+
+    /// Lowers and then returns an [OutputReport] with the number of `LNode`s,
+    /// the maximum depth, the number of `TNode`s, and the contributing inputs
+    /// of the fanin cone of every live `EvalAwi`. This is most useful after
+    /// [Epoch::optimize]. The report implements `Display` for quick printing.
+    /// Requires that `self` be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let a = LazyAwi::opaque(bw(8));
+    /// let b = LazyAwi::opaque(bw(8));
+    /// let mut sum = Awi::from(&a);
+    /// sum.add_(&b).unwrap();
+    /// let sum = EvalAwi::from(&sum);
+    /// sum.set_debug_name("sum").unwrap();
+    /// epoch.optimize().unwrap();
+    /// let report = epoch.report_outputs().unwrap();
+    /// let cone = report.get(sum.p_external()).unwrap();
+    /// assert_eq!(cone.inputs, vec![a.p_external(), b.p_external()]);
+    /// println!("{report}");
+    /// drop(epoch);
+    /// ```
+    pub fn report_outputs(&self) -> Result<OutputReport, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble.report_outputs()
+    }
+
+    /// Lowers and then searches for values of the `LazyAwi`s that make `output`
+    /// evaluate to `desired`, see [Ensemble::find_input_for]. Returns `None`
+    /// if there are no such values. The returned values can be applied with
+    /// `LazyAwi::retro_` on the `LazyAwi`s with the same `PExternal`s.
+    /// Requires that `self` be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{awi, dag::*, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let a = LazyAwi::opaque(bw(8));
+    /// let b = LazyAwi::opaque(bw(8));
+    /// let mut sum = Awi::from(&a);
+    /// sum.add_(&b).unwrap();
+    /// sum.xor_(&a).unwrap();
+    /// let out = EvalAwi::from(&sum);
+    /// epoch.optimize().unwrap();
+    /// let desired = awi::Awi::from_u8(0x5a);
+    /// let inputs = epoch.find_input_for(&out, &desired).unwrap().unwrap();
+    /// for (p_external, value) in inputs {
+    ///     if p_external == a.p_external() {
+    ///         a.retro_(&value).unwrap();
+    ///     } else {
+    ///         b.retro_(&value).unwrap();
+    ///     }
+    /// }
+    /// assert_eq!(out.eval().unwrap(), desired);
+    /// drop(epoch);
+    /// ```
+    pub fn find_input_for(
+        &self,
+        output: &EvalAwi,
+        desired: &awi::Bits,
+    ) -> Result<Option<Vec<(PExternal, awi::Awi)>>, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.find_input_for(output.p_external(), desired)
+    }
+
+    /// Like [Epoch::optimize], but additionally uses a `profile` collected from
+    /// this `Epoch` to choose between area and depth. Ripple carry adders with
+    /// frequently toggling (hot) carries are turned into carry select adders,
+    /// while cold adders keep the smaller ripple carry structure. Lookup
+    /// tables that only feed into one other lookup table are always fused,
+    /// which reduces both area and depth. Hot lookup tables also get lookup
+    /// tables with other uses duplicated into them, which reduces depth at the
+    /// expense of area. Requires that `self` be the current `Epoch`.
+    pub fn optimize_with_profile(&self, profile: &Profile) -> Result<(), Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.fuse_luts_with_profile(profile)
+    }
+
+    /// Splits every lookup table with more than `max_inputs` inputs according
+    /// to `decomposition` (see [Ensemble::decompose_luts]), returning the
+    /// number of lookup tables that were split. Lowering already splits lookup
+    /// tables according to [Limits], this is for when an already optimized
+    /// `Epoch` needs to be mapped to smaller lookup tables. Requires that
+    /// `self` be the current `Epoch`.
+    pub fn decompose_luts(
+        &self,
+        max_inputs: usize,
+        decomposition: LutDecomposition,
+    ) -> Result<usize, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.decompose_luts(max_inputs, decomposition)
+    }
+
+    /// Applies the shrinking rewrite rules to the `State`s that have not been
+    /// lowered yet, see [Ensemble::peephole_states]. This should be called
+    /// before [Epoch::optimize] or anything else that lowers. Returns the
+    /// number of rewrites. Requires that `self` be the current `Epoch`.
+    pub fn peephole_states(&self) -> Result<usize, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.peephole_states()
+    }
+
+    /// Experimental equality saturation pass over the `State`s that have not
+    /// been lowered yet, see [Ensemble::egraph_optimize_states]. This should be
+    /// called before [Epoch::optimize] or anything else that lowers. Requires
+    /// that `self` be the current `Epoch`.
+    #[cfg(feature = "egraph")]
+    pub fn egraph_optimize_states(
+        &self,
+        config: &crate::ensemble::EgraphConfig,
+    ) -> Result<crate::ensemble::EgraphReport, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.egraph_optimize_states(config)
+    }
+
+    /// Optimizes `self`, merges equivalences that compute the same function
+    /// according to [Ensemble::merge_equivalent_cones], and optimizes again to
+    /// remove what became unused. Returns the number of merged equivalences.
+    /// Cones are analyzed up to `max_nodes` BDD nodes. Requires that `self`
+    /// be the current `Epoch`.
+    pub fn merge_equivalent_cones(&self, max_nodes: usize) -> Result<usize, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let num_merged = lock.ensemble.merge_equivalent_cones(max_nodes)?;
+        lock.ensemble.optimize_all()?;
+        Ok(num_merged)
+    }
+
+    /// Optimizes `self` and then simplifies lookup tables within the error
+    /// tolerances of `specs` according to [Ensemble::approximate]. Requires
+    /// that `self` be the current `Epoch`.
+    pub fn approximate(
+        &self,
+        specs: &[ErrorSpec],
+        config: &ApproxConfig,
+    ) -> Result<ApproxReport, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.approximate(specs, config)
+    }
+
+    /// Optimizes `self` and then maps it to the standard cells of `library`
+    /// according to [Ensemble::map_to_cells]. Requires that `self` be the
+    /// current `Epoch`.
+    pub fn map_to_cells(&self, library: &CellLibrary) -> Result<MappedNetlist, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble.map_to_cells(library)
+    }
+
+    /// Optimizes `self` and then compiles a bit-parallel simulation of it
+    /// according to [Ensemble::batch_sim]. Requires that `self` be the current
+    /// `Epoch`.
+    pub fn batch_sim(&self) -> Result<BatchSim, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble.batch_sim()
+    }
+
+    /// Optimizes `self` and then finds all the instances of the pattern
+    /// program of `pattern` according to [Ensemble::find_subcircuits]. The
+    /// pattern should be optimized beforehand. Requires that `self` be the
+    /// current `Epoch`.
+    pub fn find_subcircuits(&self, pattern: &SuspendedEpoch) -> Result<Vec<Match>, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        Ok(pattern.ensemble(|pattern| lock.ensemble.find_subcircuits(pattern)))
+    }
+
+    /// Optimizes `self` and then replaces the instances of the pattern program
+    /// of `pattern` with the program of `replacement` according to
+    /// [Ensemble::replace_subcircuits]. The pattern and replacement should be
+    /// optimized beforehand. Requires that `self` be the current `Epoch`.
+    pub fn replace_subcircuits(
+        &self,
+        pattern: &SuspendedEpoch,
+        replacement: &SuspendedEpoch,
+    ) -> Result<usize, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        pattern.ensemble(|pattern| {
+            replacement
+                .ensemble(|replacement| lock.ensemble.replace_subcircuits(pattern, replacement))
+        })
+    }
+
+    /// Optimizes `self` and then returns the reachable register states
+    /// according to [Ensemble::analyze_reachability]. Requires that `self` be
+    /// the current `Epoch`.
+    pub fn analyze_reachability(&self, max_nodes: usize) -> Result<ReachabilityReport, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.analyze_reachability(max_nodes)
+    }
+
+    /// Optimizes `self`, simplifies logic according to the reachable register
+    /// states with [Ensemble::prune_unreachable_states], and optimizes again
+    /// to remove what became unused. Note that this assumes synchronous
+    /// behavior, see [Ensemble::analyze_reachability]. Requires that `self` be
+    /// the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, Epoch, EvalAwi, LazyAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let advance = LazyAwi::opaque(bw(1));
+    /// // a one-hot state machine that rotates while `advance` is set
+    /// let state = Loop::from_bits(&awi!(0b0001_u4));
+    /// let mut next = awi!(state);
+    /// next.rotl_(1).unwrap();
+    /// next.mux_(&state, !advance.to_bool()).unwrap();
+    /// // this is always false
+    /// let mut two_hot = awi!(state);
+    /// two_hot.and_(&awi!(0b11_u4)).unwrap();
+    /// let two_hot = EvalAwi::from_bool(two_hot.const_eq(&awi!(0b11_u4)).unwrap());
+    /// state.drive_with_delay(&next, 1).unwrap();
+    /// let report = epoch.prune_unreachable_states(1 << 12).unwrap();
+    /// assert_eq!(report.groups[0].reachable, 4.0);
+    /// assert_eq!(two_hot.eval_bool().unwrap(), false);
+    /// drop(epoch);
+    /// ```
+    pub fn prune_unreachable_states(&self, max_nodes: usize) -> Result<ReachabilityReport, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let report = lock.ensemble.prune_unreachable_states(max_nodes)?;
+        lock.ensemble.optimize_all()?;
+        Ok(report)
+    }
+
+    /// Optimizes `self` and then attempts to prove that all the bits of
+    /// `property` are set in every reachable state by 1-induction, see
+    /// [Ensemble::prove_invariant]. Returns a counterexample if the property
+    /// fails in the initial state or is not inductive. Requires that `self`
+    /// be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, ensemble::Induction, Epoch, EvalAwi, LazyAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let advance = LazyAwi::opaque(bw(1));
+    /// // a one-hot state machine that rotates while `advance` is set
+    /// let state = Loop::from_bits(&awi!(0b0001_u4));
+    /// let mut next = awi!(state);
+    /// next.rotl_(1).unwrap();
+    /// next.mux_(&state, !advance.to_bool()).unwrap();
+    /// let mut lower = awi!(state);
+    /// lower.dec_(false);
+    /// lower.and_(&state).unwrap();
+    /// let one_hot = EvalAwi::from_bool(lower.is_zero() & !state.is_zero());
+    /// // this is also invariant, but not inductive because an unreachable
+    /// // state like `0b1001` satisfies it and can rotate into `0b0011`
+    /// let not_both = EvalAwi::from_bool(!(state.get(0).unwrap() & state.get(1).unwrap()));
+    /// state.drive_with_delay(&next, 1).unwrap();
+    /// assert!(epoch.prove_invariant(&one_hot, 10000).unwrap().is_proven());
+    /// assert!(matches!(
+    ///     epoch.prove_invariant(&not_both, 10000).unwrap(),
+    ///     Induction::NotInductive(_)
+    /// ));
+    /// drop(epoch);
+    /// ```
+    pub fn prove_invariant(
+        &self,
+        property: &EvalAwi,
+        max_nodes: usize,
+    ) -> Result<Induction, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble
+            .prove_invariant(property.p_external(), max_nodes)
+    }
+
+    /// Optimizes `self` and encodes the cones of `roots` for external SMT
+    /// solvers, see [Ensemble::smt_cone]. Requires that `self` be the current
+    /// `Epoch`.
+    #[cfg(feature = "smt")]
+    pub fn smt_cone(&self, roots: &[&EvalAwi]) -> Result<crate::ensemble::SmtCone, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        let roots: Vec<PExternal> = roots.iter().map(|e| e.p_external()).collect();
+        lock.ensemble.smt_cone(&roots)
+    }
+
+    /// Optimizes `self` and uses `solver` to check if `a` and `b` are
+    /// equivalent for all values of the `LazyAwi`s, see
+    /// [Ensemble::check_equivalence_smt]. The values of a counterexample can
+    /// be retroactively assigned to the `LazyAwi`s to reproduce it. Requires
+    /// that `self` be the current `Epoch`.
+    ///
+    /// ```no_run
+    /// use starlight::{dag::*, ensemble::SmtCommand, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let x = LazyAwi::opaque(bw(8));
+    /// let y = LazyAwi::opaque(bw(8));
+    /// let mut a = awi!(x);
+    /// a.add_(&y).unwrap();
+    /// let mut b = awi!(y);
+    /// b.add_(&x).unwrap();
+    /// let a = EvalAwi::from(&a);
+    /// let b = EvalAwi::from(&b);
+    /// let res = epoch
+    ///     .check_equivalence_smt(&a, &b, &mut SmtCommand::z3())
+    ///     .unwrap();
+    /// assert!(res.is_unsat());
+    /// drop(epoch);
+    /// ```
+    #[cfg(feature = "smt")]
+    pub fn check_equivalence_smt<S: crate::ensemble::SmtSolver>(
+        &self,
+        a: &EvalAwi,
+        b: &EvalAwi,
+        solver: &mut S,
+    ) -> Result<crate::ensemble::SmtResult, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble
+            .check_equivalence_smt(a.p_external(), b.p_external(), solver)
+    }
+
+    /// Optimizes `self` and uses `solver` to check if all the bits of
+    /// `property` are set for all values of the `LazyAwi`s, see
+    /// [Ensemble::check_property_smt]. Requires that `self` be the current
+    /// `Epoch`.
+    #[cfg(feature = "smt")]
+    pub fn check_property_smt<S: crate::ensemble::SmtSolver>(
+        &self,
+        property: &EvalAwi,
+        solver: &mut S,
+    ) -> Result<crate::ensemble::SmtResult, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble
+            .check_property_smt(property.p_external(), solver)
+    }
+
+    /// Lowers and then threads the single bit `reset` into all the registers
+    /// lowered from `Loop`s with known initial values, so that they are set to
+    /// those values according to `kind`, see [Ensemble::add_reset]. Returns
+    /// the number of registers that got a reset. Requires that `self` be the
+    /// current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, ensemble::ResetKind, Epoch, EvalAwi, LazyAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let reset = LazyAwi::opaque(bw(1));
+    /// let counter = Loop::from_bits(&awi!(3_u8));
+    /// let mut next = awi!(counter);
+    /// next.inc_(true);
+    /// let out = EvalAwi::from(&counter);
+    /// counter.drive_with_delay(&next, 1).unwrap();
+    /// assert_eq!(
+    ///     epoch.add_reset(&reset, ResetKind::SyncActiveHigh).unwrap(),
+    ///     8
+    /// );
+    /// assert!(epoch
+    ///     .check_reset(&reset, ResetKind::SyncActiveHigh, 10000)
+    ///     .unwrap()
+    ///     .is_complete());
+    /// reset.retro_bool_(false).unwrap();
+    /// epoch.run(5).unwrap();
+    /// assert_eq!(out.eval().unwrap().to_u8(), 8);
+    /// reset.retro_bool_(true).unwrap();
+    /// epoch.run(1).unwrap();
+    /// assert_eq!(out.eval().unwrap().to_u8(), 3);
+    /// drop(epoch);
+    /// ```
+    pub fn add_reset(&self, reset: &LazyAwi, kind: ResetKind) -> Result<usize, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.add_reset(reset.p_external(), kind)
+    }
+
+    /// Lowers and then checks which registers are brought into a known state
+    /// by holding the single bit `reset` active, see [Ensemble::check_reset].
+    /// Requires that `self` be the current `Epoch`.
+    pub fn check_reset(
+        &self,
+        reset: &LazyAwi,
+        kind: ResetKind,
+        max_nodes: usize,
+    ) -> Result<ResetReport, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble
+            .check_reset(reset.p_external(), kind, max_nodes)
+    }
+
+    /// Lowers and then stitches all the registers into scan chains with
+    /// `scan_enable` and one chain per bit of `scan_in`, see
+    /// [Ensemble::insert_scan_chains]. Returns the scan-out bits and a report
+    /// with the chain order. Requires that `self` be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{awi, dag::*, Epoch, EvalAwi, LazyAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let scan_enable = LazyAwi::opaque(bw(1));
+    /// let scan_in = LazyAwi::opaque(bw(1));
+    /// let counter = Loop::zero(bw(4));
+    /// let mut next = awi!(counter);
+    /// next.inc_(true);
+    /// let out = EvalAwi::from(&counter);
+    /// counter.drive_with_delay(&next, 1).unwrap();
+    /// let (scan_out, report) = epoch.insert_scan_chains(&scan_enable, &scan_in).unwrap();
+    /// assert_eq!(report.max_len(), 4);
+    /// {
+    ///     use awi::*;
+    ///     scan_enable.retro_bool_(false).unwrap();
+    ///     epoch.run(5).unwrap();
+    ///     assert_eq!(out.eval().unwrap(), awi!(5_u4));
+    ///     // load 0b1100 by shifting in from the last register to the first
+    ///     scan_enable.retro_bool_(true).unwrap();
+    ///     for b in [true, true, false, false] {
+    ///         scan_in.retro_bool_(b).unwrap();
+    ///         epoch.run(1).unwrap();
+    ///     }
+    ///     assert_eq!(out.eval().unwrap(), awi!(0b1100_u4));
+    ///     assert!(scan_out.eval_bool().unwrap());
+    /// }
+    /// drop(epoch);
+    /// ```
+    pub fn insert_scan_chains(
+        &self,
+        scan_enable: &LazyAwi,
+        scan_in: &LazyAwi,
+    ) -> Result<(EvalAwi, ScanReport), Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let report = lock
+            .ensemble
+            .insert_scan_chains(scan_enable.p_external(), scan_in.p_external())?;
+        drop(lock);
+        Ok((EvalAwi::try_clone_from(report.scan_out)?, report))
+    }
+
+    /// Lowers and then captures the values held by all the registers and the
+    /// pending temporal events, see [Ensemble::dump_register_state]. The
+    /// result can be given to [Epoch::load_register_state] to resume the
+    /// simulation from this point. Requires that `self` be the current
+    /// `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{awi, dag::*, Epoch, EvalAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let counter = Loop::zero(bw(4));
+    /// let mut next = awi!(counter);
+    /// next.inc_(true);
+    /// let out = EvalAwi::from(&counter);
+    /// counter.drive_with_delay(&next, 1).unwrap();
+    /// epoch.run(3).unwrap();
+    /// let state = epoch.dump_register_state().unwrap();
+    /// epoch.run(5).unwrap();
+    /// assert_eq!(out.eval().unwrap().to_u8(), 8);
+    /// epoch.load_register_state(&state).unwrap();
+    /// assert_eq!(out.eval().unwrap().to_u8(), 3);
+    /// epoch.run(2).unwrap();
+    /// assert_eq!(out.eval().unwrap().to_u8(), 5);
+    /// drop(epoch);
+    /// ```
+    pub fn dump_register_state(&self) -> Result<RegState, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.dump_register_state()
+    }
+
+    /// Lowers and then restores the registers and pending temporal events of
+    /// `state`, see [Ensemble::load_register_state]. Requires that `self` be
+    /// the current `Epoch`.
+    pub fn load_register_state(&self, state: &RegState) -> Result<(), Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.load_register_state(state)
+    }
+
+    /// Lowers and then finds and classifies all combinational loops, see
+    /// [Ensemble::combinational_loops]. Requires that `self` be the current
+    /// `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, ensemble::LoopKind, Epoch, LazyAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let enable = LazyAwi::opaque(bw(1));
+    /// let d = LazyAwi::opaque(bw(1));
+    /// // a level sensitive latch from a `Loop` driven without a delay
+    /// let q = Loop::zero(bw(1));
+    /// let mut next = Awi::from_bits(&q);
+    /// next.mux_(&d, enable.to_bool()).unwrap();
+    /// q.drive(&next).unwrap();
+    /// let loops = epoch.combinational_loops(1000).unwrap();
+    /// assert_eq!(loops.len(), 1);
+    /// assert_eq!(loops[0].kind, LoopKind::Latch);
+    /// assert!(epoch.deny_latches(1000).is_err());
+    /// drop(epoch);
+    /// ```
+    pub fn combinational_loops(&self, max_nodes: usize) -> Result<Vec<CombinationalLoop>, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        Ok(lock.ensemble.combinational_loops(max_nodes))
+    }
+
+    /// Lowers and then returns an error if there are any combinational loops
+    /// that are not false loops, see [Ensemble::deny_latches]. Requires that
+    /// `self` be the current `Epoch`.
+    pub fn deny_latches(&self, max_nodes: usize) -> Result<(), Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        lock.ensemble.deny_latches(max_nodes)
+    }
+
+    /// Lowers and then creates a copy of `self` reduced to the cone of
+    /// influence of `properties`, see [Ensemble::coi_reduce]. Registers and
+    /// inputs that cannot affect the properties are removed, which can greatly
+    /// shrink problems for formal checks on the properties. The copy is
+    /// returned as a `SuspendedEpoch` so that `self` stays current, and the
+    /// `PExternal`s of the copy are the same as those of `self`. Assertions
+    /// are not carried over. Requires that `self` be the current `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{awi, dag, Epoch, EvalAwi, LazyAwi, Loop};
+    /// let epoch = Epoch::new();
+    /// let (a, b, out, other) = {
+    ///     use dag::*;
+    ///     let a = LazyAwi::opaque(bw(8));
+    ///     let b = LazyAwi::opaque(bw(8));
+    ///     let counter = Loop::zero(bw(8));
+    ///     let mut next = awi!(counter);
+    ///     next.add_(&a).unwrap();
+    ///     let out = EvalAwi::from(&counter);
+    ///     counter.drive_with_delay(&next, 1).unwrap();
+    ///     let other = EvalAwi::from(&b);
+    ///     (a, b, out, other)
+    /// };
+    /// let reduced = epoch.coi_reduce(&[&out]).unwrap();
+    /// let (reduced, _) = reduced.scope(|reduced| {
+    ///     // `b` is not in the cone of influence of `out`
+    ///     assert!(b.retro_u8_(1).is_err());
+    ///     a.retro_u8_(3).unwrap();
+    ///     reduced.run(2).unwrap();
+    ///     assert_eq!(out.eval_u8().unwrap(), 6);
+    /// });
+    /// drop(reduced);
+    /// drop(a);
+    /// drop(b);
+    /// drop(out);
+    /// drop(other);
+    /// drop(epoch);
+    /// ```
+    pub fn coi_reduce(&self, properties: &[&EvalAwi]) -> Result<SuspendedEpoch, Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut ensemble = epoch_shared.epoch_data.borrow().ensemble.clone();
+        let p_externals: Vec<PExternal> = properties.iter().map(|x| x.p_external()).collect();
+        ensemble.coi_reduce(&p_externals)?;
+        let reduced = Epoch::from_ensemble(ensemble);
+        reduced.shared().epoch_data.borrow_mut().info.lineage =
+            epoch_shared.epoch_data.borrow().info.lineage;
+        Ok(reduced.suspend())
+    }
+
+    /// Lowers `self` and then changes the function of the lookup table that
+    /// drives bit `bit_i` of the `EvalAwi` or `LazyAwi` corresponding to
+    /// `p_external` to `new_table`, see [Ensemble::replace_lut]. This is for
+    /// emulating runtime reconfigurable lookup tables and for quick what-if
+    /// analysis, only values that depend on the lookup table are reevaluated.
+    /// Note that optimization can change or remove lookup tables. Requires
+    /// that `self` be the current `Epoch`.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not from this `Epoch`, if `bit_i` is out of range or
+    /// its bit was removed, if no lookup table drives the bit, or if
+    /// [Ensemble::replace_lut] fails
+    pub fn replace_lut(
+        &self,
+        p_external: PExternal,
+        bit_i: usize,
+        new_table: &awi::Bits,
+    ) -> Result<(), Error> {
+        self.lower()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let ensemble = &mut lock.ensemble;
+        let (_, rnode) = ensemble.notary.get_rnode(p_external)?;
+        let p_back = rnode
+            .bits()
+            .ok_or(Error::OtherStr(
+                "`Epoch::replace_lut`: the `RNode` has not been initialized",
+            ))?
+            .get(bit_i)
+            .copied()
+            .flatten()
+            .ok_or(Error::OtherString(format!(
+                "`Epoch::replace_lut`: bit {bit_i} is out of range or was removed"
+            )))?;
+        let p_lnode = ensemble
+            .lnode_driving(p_back)
+            .ok_or(Error::OtherString(format!(
+                "`Epoch::replace_lut`: bit {bit_i} is not driven by a lookup table"
+            )))?;
+        ensemble.replace_lut(p_lnode, new_table)
+    }
+
+    /// Optimizes `self` and then applies up to `num_transformations` random
+    /// equivalence-preserving transformations seeded by `seed`, see
+    /// [Ensemble::randomize]. This is useful for generating families of
+    /// benchmarks that are structurally different but compute the same
+    /// functions. Requires that `self` be the current `Epoch`.
+    pub fn randomize(
+        &self,
+        seed: u64,
+        num_transformations: usize,
+    ) -> Result<RandomizeReport, Error> {
+        self.optimize()?;
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.randomize(seed, num_transformations)
+    }
+
     /// Evaluates temporal nodes according to their delays until `time` has
-    /// passed. Requires that `self` be the current `Epoch`.
+    /// passed. If there are [Epoch::on_value_change] callbacks or
+    /// [Epoch::schedule_external_event] events, the run stops at every event
+    /// time to apply and report them. Requires that `self` be the current
+    /// `Epoch`.
     pub fn run<D: Into<Delay>>(&self, time: D) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        if epoch_shared.epoch_data.borrow().external.is_empty() {
+            self.run_without_external(time.into())
+        } else {
+            self.run_with_external(time.into())
+        }
+    }
+
+    /// Like [Epoch::run] but ignores external callbacks and scheduled events
+    pub(crate) fn run_without_external(&self, time: Delay) -> Result<(), Error> {
         let epoch_shared = self.check_current()?;
         if epoch_shared
             .epoch_data
@@ -845,9 +2345,9 @@ impl Epoch {
             .states
             .is_empty()
         {
-            epoch_shared.internal_run(time.into())
+            epoch_shared.internal_run(time)
         } else {
-            epoch_shared.internal_run_with_lower_capability(time.into())
+            epoch_shared.internal_run_with_lower_capability(time)
         }
     }
 
@@ -872,4 +2372,279 @@ impl Epoch {
             Ok(ensemble.delayer.delayed_events.is_empty() && ensemble.evaluator.are_events_empty())
         })
     }
+
+    /// Sets the resource limits of the `Ensemble`. Note that already existing
+    /// structures are not removed if they exceed the new limits, instead
+    /// `Error::ResourceLimitExceeded` will be returned from `Epoch` functions
+    /// that would create more. Mimicking operations themselves cannot return
+    /// errors, so states created after `max_states` is exceeded are replaced
+    /// by opaques with the error stored, which is reported by functions like
+    /// [Epoch::try_lower].
+    pub fn set_limits(&self, limits: Limits) {
+        self.shared().epoch_data.borrow_mut().ensemble.limits = limits;
+    }
+
+    /// Returns the current resource limits
+    pub fn limits(&self) -> Limits {
+        self.ensemble(|ensemble| ensemble.limits)
+    }
+
+    /// Sets the [OptimizeSettings] used by [Epoch::optimize] and other
+    /// optimization functions, which enable optimizations that change the
+    /// observable behavior of the design
+    pub fn set_optimize_settings(&self, settings: OptimizeSettings) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .optimize_settings = settings;
+    }
+
+    /// Returns the current [OptimizeSettings]
+    pub fn optimize_settings(&self) -> OptimizeSettings {
+        self.ensemble(|ensemble| ensemble.optimize_settings)
+    }
+
+    /// Sets which higher level operations should be preserved as their own
+    /// nodes (e.g. `ensemble::MulNode`s for `MacroOp::Mul` and
+    /// `ensemble::RomNode`s for `MacroOp::Rom`) when states are lowered, so
+    /// that they can be mapped to dedicated hardware. This only affects states
+    /// lowered after this is called.
+    pub fn preserve_macro_ops(&self, macro_ops: &[MacroOp]) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .preserved_macro_ops = macro_ops.to_vec();
+    }
+
+    /// Sets how funnel shifts and the dynamic shift, rotate, and field
+    /// operations are lowered, see [FunnelStrategy]. On targets with small
+    /// LUTs, `FunnelStrategy::Crossbar` or `FunnelStrategy::Auto` with the
+    /// target LUT size produce better mapped results than the default dynamic
+    /// LUTs. This only affects states lowered after this is called.
+    pub fn set_funnel_strategy(&self, strategy: FunnelStrategy) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .funnel_strategy = strategy;
+    }
+
+    /// Returns the current [FunnelStrategy]
+    pub fn funnel_strategy(&self) -> FunnelStrategy {
+        self.ensemble(|ensemble| ensemble.funnel_strategy)
+    }
+
+    /// Sets how ties between events that happen at the same time with the
+    /// same priority are broken, see [TieBreak]. Use this to make the outcome
+    /// of simultaneous drives of the same value reproducible and controllable.
+    pub fn set_tie_break(&self, tie_break: TieBreak) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .evaluator
+            .tie_break = tie_break;
+    }
+
+    /// Returns the current [TieBreak]
+    pub fn tie_break(&self) -> TieBreak {
+        self.ensemble(|ensemble| ensemble.evaluator.tie_break)
+    }
+
+    /// Removes all preserved macro operation nodes, leaving only their
+    /// decompositions. This is used when the target does not have the
+    /// corresponding dedicated hardware.
+    pub fn remove_macro_op_nodes(&self) -> Result<(), Error> {
+        let mut lock = self.shared().epoch_data.borrow_mut();
+        lock.ensemble.remove_all_mul_nodes()?;
+        lock.ensemble.remove_all_rom_nodes()
+    }
+
+    /// Instantiates a black-box module named `name` with `inputs` and output
+    /// ports of the bitwidths in `outputs`, returning the outputs. The
+    /// function of the module is unknown, the outputs are opaque to
+    /// optimization and are unknown during evaluation unless assigned with
+    /// `LazyAwi::retro_` (which can be used to model the module in
+    /// simulation). `delays` are the pin-to-pin delays as `(input port index,
+    /// output port index, delay)`, which are used in timing analysis, see
+    /// [crate::ensemble::BlackBox]. The instance lives as long as `self` and
+    /// is exported with the "slir" format. Requires that `self` be the current
+    /// `Epoch`.
+    ///
+    /// ```
+    /// use starlight::{dag::*, sweep::SweepStats, Delay, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let addr = LazyAwi::opaque(bw(4));
+    /// let mut next = Awi::from(&addr);
+    /// next.inc_(true);
+    /// // a memory with an output delay of 5 from the address
+    /// let outputs = epoch
+    ///     .blackbox("vendor_rom", &[&next], &[bw(8)], &[(0, 0, Delay::from(5))])
+    ///     .unwrap();
+    /// let mut data = Awi::from(&outputs[0]);
+    /// data.not_();
+    /// let _out = EvalAwi::from(&data);
+    /// epoch.optimize().unwrap();
+    /// let stats = epoch
+    ///     .ensemble(|ensemble| SweepStats::from_ensemble(ensemble, Delay::from(1), Delay::zero()))
+    ///     .unwrap();
+    /// assert!(stats.estimated_delay > Delay::from(5));
+    /// drop(epoch);
+    /// ```
+    pub fn blackbox(
+        &self,
+        name: &str,
+        inputs: &[&dag::Bits],
+        outputs: &[NonZeroUsize],
+        delays: &[(usize, usize, Delay)],
+    ) -> Result<Vec<LazyAwi>, Error> {
+        let epoch_shared = self.check_current()?;
+        let inputs: Vec<EvalAwi> = inputs.iter().map(|x| EvalAwi::from_bits(x)).collect();
+        let outputs: Vec<LazyAwi> = outputs.iter().map(|w| LazyAwi::opaque(*w)).collect();
+        let blackbox = BlackBox {
+            name: name.to_owned(),
+            inputs: inputs.iter().map(|x| x.p_external()).collect(),
+            outputs: outputs.iter().map(|x| x.p_external()).collect(),
+            delays: delays.to_vec(),
+        };
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .insert_blackbox(blackbox)?;
+        Ok(outputs)
+    }
+
+    /// Starts recording every state created in this `Epoch` into a
+    /// [ReplayLog]. Any previously recorded log is discarded.
+    pub fn start_replay_recording(&self) {
+        self.shared().epoch_data.borrow_mut().ensemble.replay_log = Some(ReplayLog::new());
+    }
+
+    /// Stops recording and returns the recorded log, or `None` if recording
+    /// was not started
+    pub fn stop_replay_recording(&self) -> Option<ReplayLog> {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .replay_log
+            .take()
+    }
+
+    /// Starts journaling the mutations of this `Epoch` so that they can be
+    /// undone with [Epoch::undo], see [Journal]. Any previous journal is
+    /// discarded.
+    pub fn start_journal(&self) {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .start_journal();
+    }
+
+    /// Stops journaling and returns the journal, or `None` if journaling was
+    /// not started
+    pub fn stop_journal(&self) -> Option<Journal> {
+        self.shared()
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .stop_journal()
+    }
+
+    /// Undoes up to `n` of the most recent journaled mutations, see
+    /// [Ensemble::undo]. Mimicking types, `LazyAwi`s, and `EvalAwi`s created
+    /// after the undone point should not be used afterwards. Requires that
+    /// `self` be the current `Epoch`.
+    pub fn undo(&self, n: usize) -> Result<usize, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.undo(n)
+    }
+
+    /// Redoes up to `n` of the most recently undone mutations, see
+    /// [Ensemble::redo]. Requires that `self` be the current `Epoch`.
+    pub fn redo(&self, n: usize) -> Result<usize, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.redo(n)
+    }
+
+    /// Recreates all the states recorded in `log` in this `Epoch`, see
+    /// [Ensemble::rebuild_from_replay]. The `LazyAwi`s and `EvalAwi`s of the
+    /// original program do not exist in `self`, instead the inputs and outputs
+    /// of the replayed design can be accessed with [Epoch::poke] and
+    /// [Epoch::peek] through the `PExternal`s in the returned [Replayed].
+    ///
+    /// # Errors
+    ///
+    /// If `self` is not the current `Epoch` or if `log` is malformed
+    pub fn replay(&self, log: &ReplayLog) -> Result<Replayed, Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.rebuild_from_replay(log)
+    }
+
+    /// Finds the `PExternal` of a `LazyAwi`, `EvalAwi`, or other `RNode` based
+    /// struct that had `set_debug_name` called with `debug_name`. If there are
+    /// multiple with the same name, an arbitrary one is returned. This and
+    /// [Epoch::peek] and [Epoch::poke] allow external tools to interact with
+    /// an `Epoch` without holding the structs themselves.
+    pub fn find_external_by_name(&self, debug_name: &str) -> Option<PExternal> {
+        self.ensemble(|ensemble| ensemble.notary.find_by_debug_name(debug_name))
+    }
+
+    /// Evaluates the value of the `RNode` corresponding to `p_external`, like
+    /// `EvalAwi::eval` would. Returns `None` if any bit could not be evaluated
+    /// to a known value. Requires that `self` be the current `Epoch`.
+    pub fn peek(&self, p_external: PExternal) -> Result<Option<awi::Awi>, Error> {
+        let epoch_shared = self.check_current()?;
+        let nzbw = epoch_shared
+            .epoch_data
+            .borrow()
+            .ensemble
+            .notary
+            .get_rnode(p_external)?
+            .1
+            .nzbw();
+        let mut res = awi::Awi::zero(nzbw);
+        for bit_i in 0..res.bw() {
+            let val = Ensemble::request_thread_local_rnode_value(p_external, bit_i)?;
+            if let Some(val) = val.known_value() {
+                res.set(bit_i, val).unwrap();
+            } else {
+                return Ok(None)
+            }
+        }
+        Ok(Some(res))
+    }
+
+    /// Retroactively assigns `rhs` to the `RNode` corresponding to
+    /// `p_external`, like `LazyAwi::retro_` would. Requires that `self` be the
+    /// current `Epoch`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `RNode` is read-only (e.x. it is from an
+    /// `EvalAwi`) or if the bitwidths mismatch
+    pub fn poke(&self, p_external: PExternal, rhs: &awi::Bits) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        let read_only = epoch_shared
+            .epoch_data
+            .borrow()
+            .ensemble
+            .notary
+            .get_rnode(p_external)?
+            .1
+            .read_only();
+        if read_only {
+            return Err(Error::OtherString(format!(
+                "`Epoch::poke`: {p_external} is read-only"
+            )))
+        }
+        Ensemble::change_thread_local_rnode_value(p_external, CommonValue::Bits(rhs), false)
+    }
 }
@@ -8,8 +8,8 @@ use awint::{
 use crate::{
     awi,
     ensemble::{Ensemble, PExternal},
-    epoch::get_current_epoch,
-    Error,
+    epoch::{get_current_epoch, get_current_epoch_for_handle},
+    EpochInfo, Error,
 };
 
 // Note: `mem::forget` can be used on `EvalAwi`s, but in this crate it should
@@ -28,6 +28,8 @@ use crate::{
 /// current `Epoch`.
 pub struct EvalAwi {
     p_external: PExternal,
+    // the `Epoch` this was created in, for diagnosing usage in other `Epoch`s
+    epoch: EpochInfo,
     // needed for things like `Configurator`s that need the bitwidth outside of an `Epoch`
     nzbw: NonZeroUsize,
 }
@@ -119,11 +121,12 @@ impl EvalAwi {
         };
         let res = if let Ok(epoch) = get_current_epoch() {
             let mut lock = epoch.epoch_data.borrow_mut();
+            let info = lock.info;
             match lock
                 .ensemble
                 .make_rnode_for_pstate(p_state, Some(location), true, true)
             {
-                Ok(tmp) => Ok(tmp),
+                Ok((p_external, nzbw)) => Ok((p_external, info, nzbw)),
                 Err(e) => Err(Error::OtherString(format!(
                     "could not create or `future_*` an `EvalAwi` from the given mimicking state: \
                      {e}"
@@ -136,7 +139,11 @@ impl EvalAwi {
             ))
         };
         match res {
-            Ok((p_external, nzbw)) => Self { p_external, nzbw },
+            Ok((p_external, epoch, nzbw)) => Self {
+                p_external,
+                epoch,
+                nzbw,
+            },
             Err(e) => {
                 panic!("{e:?}")
             }
@@ -147,8 +154,21 @@ impl EvalAwi {
         self.p_external
     }
 
+    /// Returns the [EpochInfo] of the `Epoch` that `self` was created in
+    pub fn created_in(&self) -> EpochInfo {
+        self.epoch
+    }
+
+    /// Returns `Error::HandleFromDifferentEpoch` if `self` cannot be used with
+    /// the current `Epoch`
+    pub(crate) fn check_epoch(&self) -> Result<(), Error> {
+        get_current_epoch_for_handle(self.epoch).map(|_| ())
+    }
+
     fn drop_internal(&self) {
-        if let Ok(epoch) = get_current_epoch() {
+        // if the current `Epoch` is a different one, decrementing would affect an
+        // unrelated `RNode`
+        if let Ok(epoch) = get_current_epoch_for_handle(self.epoch) {
             let mut lock = epoch.epoch_data.borrow_mut();
             let _ = lock.ensemble.rnode_dec_rc(self.p_external());
         }
@@ -163,8 +183,9 @@ impl EvalAwi {
     }
 
     pub(crate) fn try_clone_from(p_external: PExternal) -> Result<Self, Error> {
-        let epoch = get_current_epoch()?;
-        let mut lock = epoch.epoch_data.borrow_mut();
+        let epoch_shared = get_current_epoch()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let epoch = lock.info;
         let p_rnode = lock.ensemble.rnode_inc_rc(p_external)?;
         let w = lock
             .ensemble
@@ -175,6 +196,7 @@ impl EvalAwi {
             .nzbw();
         Ok(Self {
             p_external,
+            epoch,
             nzbw: w,
         })
     }
@@ -183,6 +205,7 @@ impl EvalAwi {
     /// the same `eval` effects. Returns an error if the active `Epoch` is not
     /// correct.
     pub fn try_clone(&self) -> Result<Self, Error> {
+        self.check_epoch()?;
         EvalAwi::try_clone_from(self.p_external())
     }
 
@@ -198,6 +221,7 @@ impl EvalAwi {
     /// `opaque`, but in general this will return an error that a bit could not
     /// be evaluated to a known value, if any upstream inputs are `opaque`.
     pub fn eval(&self) -> Result<awi::Awi, Error> {
+        self.check_epoch()?;
         let nzbw = self.nzbw();
         let mut res = awi::Awi::zero(nzbw);
         for bit_i in 0..res.bw() {
@@ -211,11 +235,44 @@ impl EvalAwi {
                 )))
             }
         }
+        Ensemble::record_thread_local_rnode_support(self.p_external)?;
         Ok(res)
     }
 
+    /// Like `EvalAwi::eval`, except it does not return an error for bits that
+    /// could not be evaluated to a known value. Returns a mask of the bits
+    /// that are known and the values of those bits, with unknown bits being
+    /// zero.
+    pub fn eval_partial(&self) -> Result<(awi::Awi, awi::Awi), Error> {
+        self.check_epoch()?;
+        let nzbw = self.nzbw();
+        let mut mask = awi::Awi::zero(nzbw);
+        let mut res = awi::Awi::zero(nzbw);
+        for bit_i in 0..res.bw() {
+            let val = Ensemble::request_thread_local_rnode_value(self.p_external, bit_i)?;
+            if let Some(val) = val.known_value() {
+                mask.set(bit_i, true).unwrap();
+                res.set(bit_i, val).unwrap();
+            }
+        }
+        Ok((mask, res))
+    }
+
+    /// Returns the input bits that the last evaluation of `self` depended on,
+    /// as `PExternal`s of `LazyAwi`s and bit indexes. Only the inputs whose
+    /// values actually determined the result are included, e.x. if `a & b`
+    /// evaluated to zero because `a` was zero, then only `a` is included. This
+    /// requires provenance tracking to have been enabled with
+    /// [Epoch::start_provenance](crate::Epoch::start_provenance) before the
+    /// evaluation, and returns `None` if `self` was not evaluated since then.
+    pub fn last_eval_support(&self) -> Result<Option<Vec<(PExternal, usize)>>, Error> {
+        self.check_epoch()?;
+        Ensemble::thread_local_rnode_support(self.p_external)
+    }
+
     /// Like `EvalAwi::eval`, except it returns if the values are all unknowns
     pub fn eval_is_all_unknown(&self) -> Result<bool, Error> {
+        self.check_epoch()?;
         let nzbw = self.nzbw();
         let mut all_unknown = true;
         for bit_i in 0..nzbw.get() {
@@ -230,9 +287,22 @@ impl EvalAwi {
     /// Sets a debug name for `self` that is used in debug reporting and
     /// rendering
     pub fn set_debug_name<S: AsRef<str>>(&self, debug_name: S) -> Result<(), Error> {
+        self.check_epoch()?;
         Ensemble::thread_local_rnode_set_debug_name(self.p_external, Some(debug_name.as_ref()))
     }
 
+    /// Marks `self` as an observability point that the optimizer should keep
+    /// intact. The optimizer will not forward the bits into their sources or
+    /// propagate constants through them into the downstream logic, so the
+    /// result of [Epoch::optimize](crate::Epoch::optimize) does not depend on
+    /// what other probes exist. The mark persists for the lifetime of the
+    /// `Epoch` even after `self` is dropped, so that dropping a probe does not
+    /// change later optimizations.
+    pub fn mark_keep(&self) -> Result<(), Error> {
+        self.check_epoch()?;
+        Ensemble::thread_local_rnode_mark_keep(self.p_external)
+    }
+
     pub fn opaque(w: NonZeroUsize) -> Self {
         Self::from_bits(&dag::Awi::opaque(w))
     }
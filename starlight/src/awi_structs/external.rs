@@ -0,0 +1,373 @@
+use std::{collections::BTreeMap, fmt::Debug};
+
+use crate::{
+    awi,
+    ensemble::{Ensemble, PExternal},
+    epoch::EpochShared,
+    Delay, Epoch, Error,
+};
+
+/// The argument given to callbacks registered with [Epoch::on_value_change]
+#[derive(Debug)]
+pub struct ValueChange<'a> {
+    epoch: &'a Epoch,
+    time: Delay,
+    p_external: PExternal,
+    value: &'a awi::Bits,
+}
+
+impl<'a> ValueChange<'a> {
+    /// The `Epoch` that is being run, which is current during the callback.
+    /// This can be used to [Epoch::peek] other values or to
+    /// [Epoch::schedule_external_event]s in response to the change.
+    pub fn epoch(&self) -> &'a Epoch {
+        self.epoch
+    }
+
+    /// The simulation time at which the change was observed
+    pub fn time(&self) -> Delay {
+        self.time
+    }
+
+    /// The `PExternal` that the callback was registered for
+    pub fn p_external(&self) -> PExternal {
+        self.p_external
+    }
+
+    /// The new value
+    pub fn value(&self) -> &'a awi::Bits {
+        self.value
+    }
+}
+
+type Callback = Box<dyn FnMut(&ValueChange) -> Result<(), Error>>;
+
+struct Watch {
+    p_external: PExternal,
+    /// The last value that was reported
+    last: Option<awi::Awi>,
+    /// This is `None` while the callback is being called
+    callback: Option<Callback>,
+}
+
+/// An assignment scheduled for an `RNode`
+#[derive(Debug)]
+pub(crate) enum Scheduled {
+    /// Assigns the whole value
+    Value(awi::Awi),
+    /// Assigns a single bit, leaving the other bits unchanged. `None` assigns
+    /// an unknown.
+    Bit(usize, Option<bool>),
+}
+
+/// The state of the external co-simulation interface of an `Epoch`
+#[derive(Default)]
+pub struct External {
+    watches: Vec<Watch>,
+    /// Keyed by time and then by the order of scheduling
+    scheduled: BTreeMap<(Delay, u64), (PExternal, Scheduled)>,
+    num_scheduled: u64,
+}
+
+impl Debug for External {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("External")
+            .field("watches.len()", &self.watches.len())
+            .field("scheduled", &self.scheduled)
+            .finish()
+    }
+}
+
+impl External {
+    /// Returns if nothing is watched or scheduled, in which case runs do not
+    /// need to stop at every event
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty() && self.scheduled.is_empty()
+    }
+
+    pub fn next_scheduled_time(&self) -> Option<Delay> {
+        self.scheduled.keys().next().map(|(time, _)| *time)
+    }
+
+    /// Removes the next scheduled event if it is due at or before `time`
+    pub(crate) fn pop_due(&mut self, time: Delay) -> Option<(PExternal, Scheduled)> {
+        if self.next_scheduled_time()? <= time {
+            self.scheduled.pop_first().map(|(_, event)| event)
+        } else {
+            None
+        }
+    }
+}
+
+/// Schedules `event` for the writable `RNode` corresponding to `p_external`
+/// at the absolute `time`, `what` is the name of the function for errors
+pub(crate) fn schedule_event(
+    epoch_shared: &EpochShared,
+    what: &str,
+    time: Delay,
+    p_external: PExternal,
+    event: Scheduled,
+) -> Result<(), Error> {
+    let mut lock = epoch_shared.epoch_data.borrow_mut();
+    let (_, rnode) = lock.ensemble.notary.get_rnode(p_external)?;
+    if rnode.read_only() {
+        return Err(Error::OtherString(format!(
+            "`{what}`: {p_external} is read-only"
+        )))
+    }
+    match event {
+        Scheduled::Value(ref value) => {
+            if rnode.nzbw() != value.nzbw() {
+                return Err(Error::BitwidthMismatch(rnode.nzbw().get(), value.bw()))
+            }
+        }
+        Scheduled::Bit(bit_i, _) => {
+            if bit_i >= rnode.nzbw().get() {
+                return Err(Error::OtherString(format!(
+                    "`{what}`: bit {bit_i} is out of range of {p_external} which has bitwidth {}",
+                    rnode.nzbw()
+                )))
+            }
+        }
+    }
+    let current_time = lock.ensemble.delayer.current_time;
+    if time < current_time {
+        return Err(Error::OtherString(format!(
+            "`{what}`: time {} is before the current time {}",
+            time.amount(),
+            current_time.amount()
+        )))
+    }
+    let external = &mut lock.external;
+    let order = external.num_scheduled;
+    external.num_scheduled = external.num_scheduled.checked_add(1).unwrap();
+    external
+        .scheduled
+        .insert((time, order), (p_external, event));
+    Ok(())
+}
+
+/// A region of pending activity reported by [Epoch::pending_activity]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Pending drives of `TNode`s with this delay. The `Loop`s and `Net`s of
+    /// a clock domain usually share the same delay.
+    Temporal(Delay),
+    /// Events scheduled with [Epoch::schedule_external_event] for the `RNode`
+    /// corresponding to the `PExternal`
+    External(PExternal),
+}
+
+/// Records `time` for `scope` if it is the earliest so far
+fn note_activity(earliest: &mut BTreeMap<Scope, Delay>, scope: Scope, time: Delay) {
+    let entry = earliest.entry(scope).or_insert(time);
+    if time < *entry {
+        *entry = time;
+    }
+}
+
+impl Epoch {
+    /// Returns the current simulation time, which is the total delay that has
+    /// been run
+    pub fn current_time(&self) -> Delay {
+        self.ensemble(|ensemble| ensemble.delayer.current_time)
+    }
+
+    /// A more detailed version of [Epoch::quiesced] for co-simulation time
+    /// stepping. Returns the scopes that still have pending activity together
+    /// with the earliest time (in the same absolute units as
+    /// [Epoch::current_time]) of a pending event in each scope, sorted by
+    /// time. Unlike `quiesced`, this includes events scheduled with
+    /// [Epoch::schedule_external_event]. Like `quiesced`, zero delay events
+    /// are processed first. Requires that `self` be the current `Epoch`.
+    pub fn pending_activity(&self) -> Result<Vec<(Scope, Delay)>, Error> {
+        self.run(Delay::zero())?;
+        let epoch_shared = self.check_current()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        let mut earliest = BTreeMap::new();
+        for (_, time, events) in &lock.ensemble.delayer.delayed_events {
+            for p_tnode in events.tnode_drives.iter().copied() {
+                if let Some(tnode) = lock.ensemble.tnodes.get(p_tnode) {
+                    note_activity(&mut earliest, Scope::Temporal(tnode.delay()), *time);
+                }
+            }
+        }
+        for ((time, _), (p_external, _)) in &lock.external.scheduled {
+            note_activity(&mut earliest, Scope::External(*p_external), *time);
+        }
+        let mut res: Vec<(Scope, Delay)> = earliest.into_iter().collect();
+        res.sort_by_key(|(scope, time)| (*time, *scope));
+        Ok(res)
+    }
+
+    /// Registers `callback` to be called during [Epoch::run] whenever the
+    /// value of the `RNode` corresponding to `p_external` changes, which
+    /// together with [Epoch::schedule_external_event] allows an external
+    /// simulator (e.x. a software model of a CPU) to interact with the
+    /// simulation in a DPI-like fashion. The callback is first called at the
+    /// start of the next run with the initial value, and values that are not
+    /// entirely known are not reported. Errors returned by the callback are
+    /// returned by `run`.
+    ///
+    /// The callback is stored in the `Epoch`, so it should not own any
+    /// `EvalAwi`s or `LazyAwi`s of the `Epoch`, use [ValueChange::epoch] to
+    /// peek and poke by `PExternal` instead.
+    ///
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    ///
+    /// use starlight::{awi, dag, Epoch, EvalAwi, Loop};
+    ///
+    /// let epoch = Epoch::new();
+    /// let count = {
+    ///     use dag::*;
+    ///     let counter = Loop::zero(bw(8));
+    ///     let mut next = awi!(counter);
+    ///     next.inc_(true);
+    ///     let count = EvalAwi::from(&counter);
+    ///     counter.drive_with_delay(&next, 10).unwrap();
+    ///     count
+    /// };
+    /// let seen = Rc::new(RefCell::new(vec![]));
+    /// let seen_clone = seen.clone();
+    /// epoch
+    ///     .on_value_change(count.p_external(), move |change| {
+    ///         seen_clone
+    ///             .borrow_mut()
+    ///             .push((change.time().amount(), change.value().to_u8()));
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// epoch.run(25).unwrap();
+    /// assert_eq!(*seen.borrow(), vec![(0, 0), (10, 1), (20, 2)]);
+    /// drop(count);
+    /// drop(epoch);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not from this `Epoch`
+    pub fn on_value_change<F: FnMut(&ValueChange) -> Result<(), Error> + 'static>(
+        &self,
+        p_external: PExternal,
+        callback: F,
+    ) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.notary.get_rnode(p_external)?;
+        lock.external.watches.push(Watch {
+            p_external,
+            last: None,
+            callback: Some(Box::new(callback)),
+        });
+        Ok(())
+    }
+
+    /// Schedules the `LazyAwi` corresponding to `p_external` to be
+    /// retroactively assigned `value` when [Epoch::run] reaches `time`, which
+    /// is absolute simulation time (see [Epoch::current_time]). Events
+    /// scheduled for the same time are applied in the order that they were
+    /// scheduled, after the `TNode` events at that time. This can be called
+    /// from within [Epoch::on_value_change] callbacks, including for the
+    /// current time.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not from this `Epoch` or is read-only, if the
+    /// bitwidths mismatch, or if `time` is in the past
+    pub fn schedule_external_event<D: Into<Delay>>(
+        &self,
+        time: D,
+        p_external: PExternal,
+        value: &awi::Bits,
+    ) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        schedule_event(
+            &epoch_shared,
+            "Epoch::schedule_external_event",
+            time.into(),
+            p_external,
+            Scheduled::Value(awi::Awi::from(value)),
+        )
+    }
+
+    /// Calls the callbacks of watched values that have changed
+    fn notify_value_changes(&self) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        let time = self.current_time();
+        // callbacks can register more callbacks, those are handled in the next round
+        let num_watches = epoch_shared.epoch_data.borrow().external.watches.len();
+        for i in 0..num_watches {
+            let p_external = epoch_shared.epoch_data.borrow().external.watches[i].p_external;
+            let Some(value) = self.peek(p_external)? else {
+                continue
+            };
+            let mut lock = epoch_shared.epoch_data.borrow_mut();
+            let watch = &mut lock.external.watches[i];
+            if watch.last.as_ref() == Some(&value) {
+                continue
+            }
+            watch.last = Some(value.clone());
+            let mut callback = watch.callback.take().unwrap();
+            // the callback needs to be able to use the `Epoch`
+            drop(lock);
+            let res = callback(&ValueChange {
+                epoch: self,
+                time,
+                p_external,
+                value: &value,
+            });
+            epoch_shared.epoch_data.borrow_mut().external.watches[i].callback = Some(callback);
+            res?;
+        }
+        Ok(())
+    }
+
+    /// The version of [Epoch::run] used when there are external callbacks or
+    /// scheduled events, which stops at every event time
+    pub(crate) fn run_with_external(&self, time: Delay) -> Result<(), Error> {
+        let epoch_shared = self.check_current()?;
+        let final_time = self.current_time().checked_add(time).unwrap();
+        loop {
+            let current_time = self.current_time();
+            loop {
+                let event = epoch_shared
+                    .epoch_data
+                    .borrow_mut()
+                    .external
+                    .pop_due(current_time);
+                match event {
+                    Some((p_external, Scheduled::Value(value))) => self.poke(p_external, &value)?,
+                    Some((p_external, Scheduled::Bit(bit_i, value))) => {
+                        Ensemble::change_thread_local_rnode_bit(p_external, bit_i, value)?
+                    }
+                    None => break,
+                }
+            }
+            self.run_without_external(Delay::zero())?;
+            self.notify_value_changes()?;
+            let (next_tnode_time, next_scheduled_time) = {
+                let lock = epoch_shared.epoch_data.borrow();
+                (
+                    lock.ensemble.delayer.peek_next_event_time(),
+                    lock.external.next_scheduled_time(),
+                )
+            };
+            if next_scheduled_time == Some(current_time) {
+                // the callbacks scheduled events for the current time
+                continue
+            }
+            if current_time == final_time {
+                break
+            }
+            let mut next_time = final_time;
+            for time in [next_tnode_time, next_scheduled_time].into_iter().flatten() {
+                next_time = next_time.min(time);
+            }
+            self.run_without_external(Delay::from_amount(
+                next_time.amount() - current_time.amount(),
+            ))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use std::num::NonZeroUsize;
+
+use crate::{
+    dag,
+    lower::meta::{onehot_mux, selector_awi},
+    Error, Loop,
+};
+
+/// A synchronous first-in first-out buffer of `depth` entries of `width`
+/// bits. The storage and pointers are `Loop`s driven with a delay of 1, so a
+/// cycle corresponds to `epoch.run(1)`. The pointers count modulo `2 * depth`
+/// so that a full FIFO can be distinguished from an empty one.
+///
+/// The status flags and [Fifo::front] can be read before the FIFO is driven
+/// with [Fifo::drive], and they reflect the state at the beginning of the
+/// cycle.
+#[derive(Debug)]
+pub struct Fifo {
+    storage: Vec<Loop>,
+    wr_ptr: Loop,
+    rd_ptr: Loop,
+    depth: usize,
+}
+
+impl Fifo {
+    /// Creates a new empty `Fifo` with entries of `width` bits that can hold
+    /// `depth` entries
+    ///
+    /// # Errors
+    ///
+    /// If `depth == 0`
+    pub fn new(width: NonZeroUsize, depth: usize) -> Result<Self, Error> {
+        if depth == 0 {
+            return Err(Error::OtherStr("`Fifo::new` called with a zero `depth`"))
+        }
+        let ptr_w = depth
+            .checked_mul(2)
+            .and_then(|x| dag::Bits::nontrivial_bits(x - 1))
+            .ok_or(Error::OtherStr(
+                "`Fifo::new` called with a `depth` that is too large",
+            ))?;
+        let mut storage = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            storage.push(Loop::zero(width));
+        }
+        Ok(Self {
+            storage,
+            wr_ptr: Loop::zero(ptr_w),
+            rd_ptr: Loop::zero(ptr_w),
+            depth,
+        })
+    }
+
+    /// Returns the bitwidth of the entries as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.storage[0].nzbw()
+    }
+
+    /// Returns the bitwidth of the entries as a `usize`
+    #[must_use]
+    pub fn bw(&self) -> usize {
+        self.nzbw().get()
+    }
+
+    /// Returns the number of entries the FIFO can hold
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the bitwidth of the pointers and of [Fifo::len]
+    fn ptr_nzbw(&self) -> NonZeroUsize {
+        self.wr_ptr.nzbw()
+    }
+
+    /// Returns `x` as a constant with the bitwidth of the pointers, `x` is
+    /// truncated if it does not fit
+    fn ptr_const(&self, x: usize) -> dag::Awi {
+        let mut res = dag::Awi::zero(self.ptr_nzbw());
+        res.usize_(x);
+        res
+    }
+
+    /// Returns the pointer incremented modulo `2 * depth`
+    fn ptr_inc(&self, ptr: &dag::Bits) -> dag::Awi {
+        let mut res = dag::Awi::from(ptr);
+        if self.depth.is_power_of_two() {
+            // wraps around naturally
+            res.inc_(true);
+        } else {
+            let wrap = ptr.const_eq(&self.ptr_const(2 * self.depth - 1)).unwrap();
+            res.inc_(true);
+            res.mux_(&self.ptr_const(0), wrap).unwrap();
+        }
+        res
+    }
+
+    /// Returns the storage index that `ptr` points to
+    fn ptr_index(&self, ptr: &dag::Bits) -> dag::Awi {
+        let mut res = dag::Awi::from(ptr);
+        if self.depth.is_power_of_two() {
+            // the most significant bit is the wrap bit
+            res.range_and_(0..(ptr.bw() - 1)).unwrap();
+        } else {
+            let wrapped = ptr.uge(&self.ptr_const(self.depth)).unwrap();
+            let mut tmp = dag::Awi::from(ptr);
+            tmp.sub_(&self.ptr_const(self.depth)).unwrap();
+            res.mux_(&tmp, wrapped).unwrap();
+        }
+        res
+    }
+
+    /// Returns the number of entries currently in the FIFO, with the bitwidth
+    /// needed to represent `2 * depth - 1`
+    #[must_use]
+    pub fn len(&self) -> dag::Awi {
+        let mut res = dag::Awi::from(&*self.wr_ptr);
+        res.sub_(&self.rd_ptr).unwrap();
+        // if the write pointer has wrapped around and the read pointer has not
+        let wrapped = self.wr_ptr.ult(&self.rd_ptr).unwrap();
+        let mut tmp = res.clone();
+        tmp.add_(&self.ptr_const(2 * self.depth)).unwrap();
+        res.mux_(&tmp, wrapped).unwrap();
+        res
+    }
+
+    /// Returns if the FIFO has no entries
+    #[must_use]
+    pub fn is_empty(&self) -> dag::bool {
+        self.wr_ptr.const_eq(&self.rd_ptr).unwrap()
+    }
+
+    /// Returns if the FIFO has `depth` entries
+    #[must_use]
+    pub fn is_full(&self) -> dag::bool {
+        self.len().const_eq(&self.ptr_const(self.depth)).unwrap()
+    }
+
+    /// Returns if the FIFO has at least `threshold` entries
+    #[must_use]
+    pub fn is_almost_full(&self, threshold: usize) -> dag::bool {
+        if threshold > self.depth {
+            dag::bool::from(false)
+        } else {
+            self.len().uge(&self.ptr_const(threshold)).unwrap()
+        }
+    }
+
+    /// Returns if the FIFO has at most `threshold` entries
+    #[must_use]
+    pub fn is_almost_empty(&self, threshold: usize) -> dag::bool {
+        if threshold >= self.depth {
+            dag::bool::from(true)
+        } else {
+            self.len().ule(&self.ptr_const(threshold)).unwrap()
+        }
+    }
+
+    /// Returns the oldest entry, which is only meaningful if the FIFO is not
+    /// empty
+    #[must_use]
+    pub fn front(&self) -> dag::Awi {
+        if self.depth == 1 {
+            return dag::Awi::from(&*self.storage[0])
+        }
+        let entries: Vec<dag::Awi> = self
+            .storage
+            .iter()
+            .map(|entry| dag::Awi::from(&**entry))
+            .collect();
+        // the widths are consistent by construction
+        let sel = selector_awi(&self.ptr_index(&self.rd_ptr), Some(self.depth)).unwrap();
+        onehot_mux(&entries, &sel).unwrap()
+    }
+
+    /// Returns the gray-coded write pointer, for passing to logic that is
+    /// asynchronous to the FIFO
+    ///
+    /// # Errors
+    ///
+    /// If the `depth` is not a power of two, because then the gray code does
+    /// not have a single bit change when the pointer wraps around
+    pub fn write_pointer_gray(&self) -> Result<dag::Awi, Error> {
+        self.gray(&self.wr_ptr)
+    }
+
+    /// Returns the gray-coded read pointer, for passing to logic that is
+    /// asynchronous to the FIFO
+    ///
+    /// # Errors
+    ///
+    /// If the `depth` is not a power of two
+    pub fn read_pointer_gray(&self) -> Result<dag::Awi, Error> {
+        self.gray(&self.rd_ptr)
+    }
+
+    fn gray(&self, ptr: &dag::Bits) -> Result<dag::Awi, Error> {
+        if !self.depth.is_power_of_two() {
+            return Err(Error::OtherStr(
+                "gray-coded `Fifo` pointers require a power of two `depth`",
+            ))
+        }
+        let mut res = dag::Awi::from(ptr);
+        if ptr.bw() > 1 {
+            res.lshr_(1).unwrap();
+            res.xor_(ptr).unwrap();
+        }
+        Ok(res)
+    }
+
+    /// Consumes `self`, driving the FIFO for each cycle. If the single bit
+    /// `push` is set and the FIFO is not full, `data` is written to the back
+    /// of the FIFO. If the single bit `pop` is set and the FIFO is not empty,
+    /// the front entry is removed. A push to a full FIFO or a pop from an
+    /// empty FIFO is ignored.
+    ///
+    /// # Errors
+    ///
+    /// If `push.bw() != 1`, `pop.bw() != 1`, or `data.bw() != self.bw()`
+    pub fn drive(self, push: &dag::Bits, data: &dag::Bits, pop: &dag::Bits) -> Result<(), Error> {
+        if push.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, push.bw()))
+        }
+        if pop.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, pop.bw()))
+        }
+        if data.bw() != self.bw() {
+            return Err(Error::BitwidthMismatch(self.bw(), data.bw()))
+        }
+        let mut do_push = dag::Awi::from_bool(self.is_full());
+        do_push.not_();
+        do_push.and_(push).unwrap();
+        let mut do_pop = dag::Awi::from_bool(self.is_empty());
+        do_pop.not_();
+        do_pop.and_(pop).unwrap();
+
+        let sel = selector_awi(&self.ptr_index(&self.wr_ptr), Some(self.depth))?;
+        let mut next_wr_ptr = dag::Awi::from(&*self.wr_ptr);
+        next_wr_ptr
+            .mux_(&self.ptr_inc(&self.wr_ptr), do_push.lsb())
+            .unwrap();
+        let mut next_rd_ptr = dag::Awi::from(&*self.rd_ptr);
+        next_rd_ptr
+            .mux_(&self.ptr_inc(&self.rd_ptr), do_pop.lsb())
+            .unwrap();
+
+        for (i, entry) in self.storage.into_iter().enumerate() {
+            let mut we = do_push.clone();
+            we.and_(&dag::Awi::from_bool(sel.get(i).unwrap())).unwrap();
+            let mut next = dag::Awi::from(&*entry);
+            next.mux_(data, we.lsb()).unwrap();
+            entry.drive_with_delay(&next, 1)?;
+        }
+        self.wr_ptr.drive_with_delay(&next_wr_ptr, 1)?;
+        self.rd_ptr.drive_with_delay(&next_rd_ptr, 1)?;
+        Ok(())
+    }
+}
@@ -143,6 +143,12 @@ impl<const W: usize> In<W> {
         self.0.retro_unknown_()
     }
 
+    /// Retroactively-partially-unknown-assigns, see
+    /// [LazyAwi::retro_partial_unknown_]
+    pub fn retro_partial_unknown_(&self, mask: &awi::Bits, value: &awi::Bits) -> Result<(), Error> {
+        self.0.retro_partial_unknown_(mask, value)
+    }
+
     /// Retroactively-constant-assigns by `rhs`, the same as `retro_` except it
     /// adds the guarantee that the value will never be changed again (or else
     /// it will result in errors if you try another `retro_*` function on
@@ -364,6 +370,12 @@ impl<const W: usize> Out<W> {
         self.0.eval()
     }
 
+    /// Like `EvalAwi::eval`, except it returns a mask of the known bits and
+    /// their values, see [EvalAwi::eval_partial]
+    pub fn eval_partial(&self) -> Result<(awi::Awi, awi::Awi), Error> {
+        self.0.eval_partial()
+    }
+
     /// Like `EvalAwi::eval`, except it returns if the values are all unknowns
     pub fn eval_is_all_unknown(&self) -> Result<bool, Error> {
         self.0.eval_is_all_unknown()
@@ -395,3 +407,185 @@ impl<const W: usize, B: AsRef<dag::Bits>> TryFrom<B> for Out<W> {
     }
 }
 */
+
+/// A bidirectional port of constant width, for modeling things like tri-state
+/// buses and SRAM data pins. Internally, this is a pair of `RNode`s: a
+/// [crate::LazyAwi] for the value the outside world drives onto the pin along
+/// with whether it is driving at all, and a [crate::EvalAwi] for the value the
+/// program drives out along with its output enable.
+///
+/// `InOut<W>` dereferences to the value driven by the outside world. The
+/// program side is attached with [InOut::drive], after which [InOut::eval]
+/// returns the resolved value of the pin. If both sides are driving at the
+/// same time, there is contention, which is detected by an assertion
+/// automatically registered with the current `Epoch` and can also be checked
+/// directly with [InOut::check_contention].
+pub struct InOut<const W: usize> {
+    input: LazyAwi,
+    input_en: LazyAwi,
+    eval_input_en: EvalAwi,
+    output_en: Option<EvalAwi>,
+    resolved: EvalAwi,
+}
+
+impl<const W: usize> InOut<W> {
+    /// Initializes an `InOut<W>` that is not being driven by the outside world
+    /// and has an unknown dynamic input value
+    #[track_caller]
+    pub fn opaque() -> Self {
+        let input = LazyAwi::opaque(bw(W));
+        let input_en = LazyAwi::zero(bw(1));
+        let eval_input_en = EvalAwi::from_bits(&input_en);
+        let resolved = EvalAwi::from_bits(&input);
+        Self {
+            input,
+            input_en,
+            eval_input_en,
+            output_en: None,
+            resolved,
+        }
+    }
+
+    /// Returns the `PExternal` of the value driven by the outside world
+    pub fn p_external(&self) -> PExternal {
+        self.input.p_external()
+    }
+
+    /// Returns the bitwidth of the port as a `NonZeroUsize`
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.input.nzbw()
+    }
+
+    /// Returns the bitwidth of the port as a `usize`
+    pub fn bw(&self) -> usize {
+        self.input.bw()
+    }
+
+    /// Returns if [InOut::drive] has been called on `self`
+    pub fn is_driven(&self) -> bool {
+        self.output_en.is_some()
+    }
+
+    /// Attaches the program side of `self`, where `output` is driven onto the
+    /// pin whenever `output_en` is true. This also registers an assertion that
+    /// the outside world and the program are not driving at the same time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `output.bw() != W` or if `self` has already been
+    /// driven
+    #[track_caller]
+    pub fn drive(&mut self, output: &dag::Bits, output_en: dag::bool) -> Result<(), Error> {
+        if output.bw() != W {
+            return Err(Error::ConstBitwidthMismatch(output.bw(), W))
+        }
+        if self.is_driven() {
+            return Err(Error::OtherStr("this `InOut` has already been driven"))
+        }
+        let mut resolved = dag::Awi::from(&*self.input);
+        resolved.mux_(output, output_en).unwrap();
+        let contention = self.input_en.lsb() & output_en;
+        dag::mimick::assert!(!contention);
+        self.output_en = Some(EvalAwi::from_bool(output_en));
+        self.resolved = EvalAwi::from_bits(&resolved);
+        Ok(())
+    }
+
+    /// Retroactively has the outside world drive the pin with `rhs`
+    pub fn retro_(&self, rhs: &awi::Bits) -> Result<(), Error> {
+        self.input.retro_(rhs)?;
+        self.input_en.retro_bool_(true)
+    }
+
+    /// Retroactively has the outside world stop driving the pin, which is
+    /// modeled as the outside value becoming unknown
+    pub fn retro_release_(&self) -> Result<(), Error> {
+        self.input_en.retro_bool_(false)?;
+        self.input.retro_unknown_()
+    }
+
+    /// Evaluates the resolved value of the pin. If the program side has not
+    /// been attached with [InOut::drive], this is the value driven by the
+    /// outside world.
+    pub fn eval(&self) -> Result<awi::Awi, Error> {
+        self.resolved.eval()
+    }
+
+    /// Evaluates if the program is currently driving the pin
+    pub fn eval_output_en(&self) -> Result<bool, Error> {
+        if let Some(ref output_en) = self.output_en {
+            output_en.eval_bool()
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Evaluates if the outside world is currently driving the pin
+    pub fn eval_input_en(&self) -> Result<bool, Error> {
+        self.eval_input_en.eval_bool()
+    }
+
+    /// Returns an error if both the outside world and the program are
+    /// currently driving the pin
+    pub fn check_contention(&self) -> Result<(), Error> {
+        if self.eval_input_en()? && self.eval_output_en()? {
+            Err(Error::OtherString(format!(
+                "contention on `InOut` {}, both sides are driving",
+                self.p_external()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sets a debug name for `self` that is used in debug reporting and
+    /// rendering
+    pub fn set_debug_name<S: AsRef<str>>(&self, debug_name: S) -> Result<(), Error> {
+        let debug_name = debug_name.as_ref();
+        self.input.set_debug_name(debug_name)?;
+        self.input_en
+            .set_debug_name(format!("{debug_name}_input_en"))?;
+        self.resolved
+            .set_debug_name(format!("{debug_name}_resolved"))
+    }
+}
+
+impl<const W: usize> Deref for InOut<W> {
+    type Target = dag::Bits;
+
+    #[track_caller]
+    fn deref(&self) -> &Self::Target {
+        &self.input
+    }
+}
+
+impl<const W: usize> Index<RangeFull> for InOut<W> {
+    type Output = dag::Bits;
+
+    #[track_caller]
+    fn index(&self, _i: RangeFull) -> &dag::Bits {
+        self
+    }
+}
+
+impl<const W: usize> std::borrow::Borrow<dag::Bits> for InOut<W> {
+    #[track_caller]
+    fn borrow(&self) -> &dag::Bits {
+        self
+    }
+}
+
+impl<const W: usize> AsRef<dag::Bits> for InOut<W> {
+    #[track_caller]
+    fn as_ref(&self) -> &dag::Bits {
+        self
+    }
+}
+
+impl<const W: usize> fmt::Debug for InOut<W> {
+    /// Can only display some fields if the `Epoch` `self` was created in is
+    /// active
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_auto_awi(&format!("InOut<{W}>"), self.p_external(), self.nzbw(), f)
+    }
+}
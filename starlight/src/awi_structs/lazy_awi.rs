@@ -14,9 +14,9 @@ use awint::{
 use crate::{
     awi,
     ensemble::{BasicValue, BasicValueKind, CommonValue, Ensemble, PExternal},
-    epoch::get_current_epoch,
+    epoch::{get_current_epoch, get_current_epoch_for_handle},
     utils::DisplayStr,
-    Delay, Error, EvalAwi,
+    Delay, EpochInfo, Error, EvalAwi,
 };
 
 // Note: `mem::forget` can be used on `LazyAwi`s, but in this crate it should
@@ -33,6 +33,8 @@ use crate::{
 /// current `Epoch`
 pub struct LazyAwi {
     p_external: PExternal,
+    // the `Epoch` this was created in, for diagnosing usage in other `Epoch`s
+    epoch: EpochInfo,
     // needed for things like `Configurator`s that need the bitwidth outside of an `Epoch`
     nzbw: NonZeroUsize,
     // this is only used for `internal_as_ref` to work
@@ -79,9 +81,7 @@ macro_rules! retro {
             /// Retroactively-assigns by `rhs`. Returns an error if this
             /// is being called after the corresponding Epoch is dropped.
             pub fn $f(&self) -> Result<(), Error> {
-                Ensemble::change_thread_local_rnode_value(
-                    self.p_external,
-                    CommonValue::Basic(BasicValue {
+                self.change_value(CommonValue::Basic(BasicValue {
                         kind: BasicValueKind::$kind,
                         nzbw: self.nzbw(),
                     }),
@@ -135,16 +135,19 @@ impl LazyAwi {
             col: tmp.column(),
         };
         let opaque = dag::Awi::opaque_with(w, "LazyOpaque", &[]);
-        let p_external = get_current_epoch()
-            .expect("attempted to create a `LazyAwi` when no active `starlight::Epoch` exists")
-            .epoch_data
-            .borrow_mut()
+        let epoch_shared = get_current_epoch()
+            .expect("attempted to create a `LazyAwi` when no active `starlight::Epoch` exists");
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let epoch = lock.info;
+        let p_external = lock
             .ensemble
             .make_rnode_for_pstate(opaque.state(), Some(location), false, false)
             .unwrap()
             .0;
+        drop(lock);
         Self {
             p_external,
+            epoch,
             nzbw: w,
             tmp_dag: Some(opaque),
         }
@@ -154,10 +157,26 @@ impl LazyAwi {
         self.p_external
     }
 
+    /// Returns the [EpochInfo] of the `Epoch` that `self` was created in
+    pub fn created_in(&self) -> EpochInfo {
+        self.epoch
+    }
+
+    /// Returns `Error::HandleFromDifferentEpoch` if `self` cannot be used with
+    /// the current `Epoch`
+    fn check_epoch(&self) -> Result<(), Error> {
+        get_current_epoch_for_handle(self.epoch).map(|_| ())
+    }
+
+    fn change_value(&self, common_value: CommonValue, make_const: bool) -> Result<(), Error> {
+        self.check_epoch()?;
+        Ensemble::change_thread_local_rnode_value(self.p_external, common_value, make_const)
+    }
+
     /// Gets the associated `PState`, returns an error if the active `Epoch` is
     /// not correct or the `Epoch` was pruned.
     pub fn try_get_p_state(&self) -> Result<PState, Error> {
-        let epoch = get_current_epoch()?;
+        let epoch = get_current_epoch_for_handle(self.epoch)?;
         let lock = epoch.epoch_data.borrow();
         let (_, rnode) = lock.ensemble.notary.get_rnode(self.p_external())?;
         if let Some(p_state) = rnode.associated_state {
@@ -171,8 +190,9 @@ impl LazyAwi {
         p_external: PExternal,
         p_state: Option<PState>,
     ) -> Result<Self, Error> {
-        let epoch = get_current_epoch()?;
-        let mut lock = epoch.epoch_data.borrow_mut();
+        let epoch_shared = get_current_epoch()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let epoch = lock.info;
         let p_rnode = lock.ensemble.rnode_inc_rc(p_external)?;
         let w = lock
             .ensemble
@@ -183,6 +203,7 @@ impl LazyAwi {
             .nzbw();
         Ok(Self {
             p_external,
+            epoch,
             nzbw: w,
             tmp_dag: p_state.map(Awi::from_state),
         })
@@ -195,6 +216,7 @@ impl LazyAwi {
     /// `retro_const_*` or `drive` called on it and then more operations are
     /// used. Returns an error if the active `Epoch` is not correct.
     pub fn try_clone(&self) -> Result<Self, Error> {
+        self.check_epoch()?;
         if let Some(ref x) = self.tmp_dag {
             LazyAwi::try_clone_from(self.p_external(), Some(x.state()))
         } else {
@@ -203,7 +225,9 @@ impl LazyAwi {
     }
 
     fn drop_internal(&self) {
-        if let Ok(epoch) = get_current_epoch() {
+        // if the current `Epoch` is a different one, decrementing would affect an
+        // unrelated `RNode`
+        if let Ok(epoch) = get_current_epoch_for_handle(self.epoch) {
             let mut lock = epoch.epoch_data.borrow_mut();
             let _ = lock.ensemble.rnode_dec_rc(self.p_external());
         }
@@ -211,9 +235,10 @@ impl LazyAwi {
 
     #[track_caller]
     fn internal_as_ref(&self) -> &dag::Bits {
-        // is not perfect without gen counters, but helps guard against inter-epoch
-        // usage
-        let p_state = self.try_get_p_state().unwrap();
+        let p_state = match self.try_get_p_state() {
+            Ok(p_state) => p_state,
+            Err(e) => panic!("{e}"),
+        };
         if let Some(ref tmp) = self.tmp_dag {
             assert_eq!(p_state, tmp.state());
             tmp
@@ -236,14 +261,13 @@ impl LazyAwi {
     /// Retroactively-assigns by `rhs`. Returns an error if bitwidths mismatch
     /// or if this is being called after the corresponding Epoch is dropped.
     pub fn retro_(&self, rhs: &awi::Bits) -> Result<(), Error> {
-        Ensemble::change_thread_local_rnode_value(self.p_external, CommonValue::Bits(rhs), false)
+        self.change_value(CommonValue::Bits(rhs), false)
     }
 
     /// Retroactively-unknown-assigns, the same as `retro_` except it sets the
     /// bits to a dynamically unknown value
     pub fn retro_unknown_(&self) -> Result<(), Error> {
-        Ensemble::change_thread_local_rnode_value(
-            self.p_external,
+        self.change_value(
             CommonValue::Basic(BasicValue {
                 kind: BasicValueKind::Opaque,
                 nzbw: self.nzbw(),
@@ -252,12 +276,52 @@ impl LazyAwi {
         )
     }
 
+    /// Retroactively-partially-unknown-assigns, the bits where `mask` is set
+    /// are assigned the corresponding bits of `value`, and the other bits are
+    /// set to a dynamically unknown value. The evaluator propagates whatever
+    /// is known as far as it can (ternary simulation), so this can be used to
+    /// check which outputs are robust to don't-care inputs, see
+    /// [EvalAwi::eval_partial](crate::EvalAwi::eval_partial).
+    ///
+    /// ```
+    /// use starlight::{awi, dag, Epoch, EvalAwi, LazyAwi};
+    /// let epoch = Epoch::new();
+    /// let x = LazyAwi::opaque(dag::bw(4));
+    /// let y = {
+    ///     use dag::*;
+    ///     let mut y = Awi::from(&x);
+    ///     y.and_(&awi!(0b0011_u4)).unwrap();
+    ///     EvalAwi::from(&y)
+    /// };
+    /// {
+    ///     use awi::*;
+    ///     // only the lower two bits are known
+    ///     x.retro_partial_unknown_(&awi!(0b0011_u4), &awi!(0b0001_u4))
+    ///         .unwrap();
+    ///     assert_eq!(y.eval().unwrap(), awi!(0b0001_u4));
+    ///     // only the upper two bits are known
+    ///     x.retro_partial_unknown_(&awi!(0b1100_u4), &awi!(0b1111_u4))
+    ///         .unwrap();
+    ///     assert_eq!(
+    ///         y.eval_partial().unwrap(),
+    ///         (awi!(0b1100_u4), awi!(0b0000_u4))
+    ///     );
+    /// }
+    /// drop(epoch);
+    /// ```
+    pub fn retro_partial_unknown_(&self, mask: &awi::Bits, value: &awi::Bits) -> Result<(), Error> {
+        if mask.bw() != value.bw() {
+            return Err(Error::BitwidthMismatch(value.bw(), mask.bw()))
+        }
+        self.change_value(CommonValue::Partial(mask, value), false)
+    }
+
     /// Retroactively-constant-assigns by `rhs`, the same as `retro_` except it
     /// adds the guarantee that the value will never be changed again (or else
     /// it will result in errors if you try another `retro_*` function on
     /// `self`)
     pub fn retro_const_(&self, rhs: &awi::Bits) -> Result<(), Error> {
-        Ensemble::change_thread_local_rnode_value(self.p_external, CommonValue::Bits(rhs), true)
+        self.change_value(CommonValue::Bits(rhs), true)
     }
 
     /// Retroactively-constant-unknown-assigns by `rhs`, the same as
@@ -265,8 +329,7 @@ impl LazyAwi {
     /// never be changed again (or else it will result in errors if you try
     /// another `retro_*` function on `self`)
     pub fn retro_const_unknown_(&self) -> Result<(), Error> {
-        Ensemble::change_thread_local_rnode_value(
-            self.p_external,
+        self.change_value(
             CommonValue::Basic(BasicValue {
                 kind: BasicValueKind::Opaque,
                 nzbw: self.nzbw(),
@@ -294,6 +357,8 @@ impl LazyAwi {
         delay: D,
     ) -> Result<(), Error> {
         let rhs = rhs.borrow();
+        self.check_epoch()?;
+        rhs.check_epoch()?;
         let lhs_w = self.bw();
         let rhs_w = rhs.bw();
         if lhs_w != rhs_w {
@@ -315,6 +380,7 @@ impl LazyAwi {
     /// Sets a debug name for `self` that is used in debug reporting and
     /// rendering
     pub fn set_debug_name<S: AsRef<str>>(&self, debug_name: S) -> Result<(), Error> {
+        self.check_epoch()?;
         Ensemble::thread_local_rnode_set_debug_name(self.p_external(), Some(debug_name.as_ref()))
     }
 }
@@ -363,7 +429,7 @@ pub(crate) fn format_auto_awi(
     if let Ok(epoch) = get_current_epoch() {
         if let Ok(lock) = epoch.epoch_data.try_borrow() {
             if let Ok((_, rnode)) = lock.ensemble.notary.get_rnode(p_external) {
-                if let Some(ref debug_name) = rnode.debug_name {
+                if let Some(debug_name) = rnode.debug_name() {
                     tmp.field("debug_name", &DisplayStr(debug_name));
                 }
                 /*if let Some(s) = lock.ensemble.get_state_debug(self.state()) {
@@ -0,0 +1,398 @@
+use std::{num::NonZeroUsize, path::Path};
+
+use awint::bw;
+
+use crate::{
+    awi, dag,
+    lower::meta::{onehot_mux, selector_awi},
+    Error, Loop,
+};
+
+/// Splits `slice` into words of `width` bits, with the bytes in order and
+/// the least significant bit of each byte first. The last word is zero
+/// padded if needed.
+fn words_from_bin(width: NonZeroUsize, slice: &[u8]) -> Vec<awi::Awi> {
+    let mut res = vec![];
+    let total = slice.len() * 8;
+    if let Some(total_w) = NonZeroUsize::new(total) {
+        let mut bits = awi::Awi::zero(total_w);
+        bits.u8_slice_(slice);
+        let mut from = 0;
+        while from < total {
+            let mut word = awi::Awi::zero(width);
+            let field_w = width.get().min(total - from);
+            word.field_from(&bits, from, field_w).unwrap();
+            res.push(word);
+            from += field_w;
+        }
+    }
+    res
+}
+
+/// Parses the `$readmemh` style hexadecimal format. Words are separated by
+/// whitespace and may contain `_` separators, `//` starts a comment that
+/// continues to the end of the line, and `@` followed by a hexadecimal
+/// address moves to that word address. Skipped words are zero. If `depth` is
+/// `Some`, words at addresses past the depth return an error instead of
+/// growing the result.
+fn words_from_hex(
+    width: NonZeroUsize,
+    s: &str,
+    depth: Option<usize>,
+) -> Result<Vec<awi::Awi>, Error> {
+    let mut res = vec![];
+    let mut addr = 0usize;
+    for (line_i, line) in s.lines().enumerate() {
+        let line = line.split("//").next().unwrap();
+        for token in line.split_whitespace() {
+            if let Some(new_addr) = token.strip_prefix('@') {
+                addr = usize::from_str_radix(&new_addr.replace('_', ""), 16).map_err(|e| {
+                    Error::OtherString(format!(
+                        "invalid address \"{token}\" on line {}: {e}",
+                        line_i + 1
+                    ))
+                })?;
+                continue
+            }
+            let word = awi::Awi::from_str_radix(None, token, 16, width).map_err(|e| {
+                Error::OtherString(format!(
+                    "invalid word \"{token}\" for bitwidth {width} on line {}: {e:?}",
+                    line_i + 1
+                ))
+            })?;
+            let len = addr
+                .checked_add(1)
+                .filter(|len| depth.is_none_or(|depth| *len <= depth))
+                .ok_or_else(|| {
+                    Error::OtherString(format!(
+                        "word \"{token}\" on line {} is at the out of range address {addr:#x}",
+                        line_i + 1
+                    ))
+                })?;
+            if len > res.len() {
+                res.resize(len, awi::Awi::zero(width));
+            }
+            res[addr] = word;
+            addr += 1;
+        }
+    }
+    Ok(res)
+}
+
+/// Reads the file at `path` and parses it with `words_from_hex`
+fn words_from_hex_file<P: AsRef<Path>>(
+    width: NonZeroUsize,
+    path: P,
+    depth: Option<usize>,
+) -> Result<Vec<awi::Awi>, Error> {
+    let path = path.as_ref();
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| Error::OtherString(format!("could not read file {path:?}: {e}")))?;
+    words_from_hex(width, &s, depth)
+}
+
+/// A read-only memory of constant words of the same bitwidth, which can be
+/// built up from files or byte slices such as firmware images.
+///
+/// [Rom::read] creates a single mimicking lookup table operation with the
+/// whole table as a literal, so the ROM stays a dedicated node until it is
+/// lowered. Large tables are lowered into balanced trees of lookup tables
+/// that each have at most the number of inputs given by the [crate::Limits]
+/// of the `Epoch`. If [crate::MacroOp::Rom] is preserved with
+/// [crate::Epoch::preserve_macro_ops], an [crate::ensemble::RomNode] with the
+/// table is kept alongside the lowered lookup tables for exporting.
+#[derive(Debug, Clone)]
+pub struct Rom {
+    words: Vec<awi::Awi>,
+    nzbw: NonZeroUsize,
+}
+
+impl Rom {
+    /// Creates an empty `Rom` with words of `width` bits
+    pub fn new(width: NonZeroUsize) -> Self {
+        Self {
+            words: vec![],
+            nzbw: width,
+        }
+    }
+
+    /// Creates a `Rom` from the bytes of `slice` split into words of `width`
+    /// bits, the least significant bits of the first byte going into the
+    /// least significant bits of the first word. The last word is zero padded
+    /// if needed.
+    pub fn from_bin(width: NonZeroUsize, slice: &[u8]) -> Self {
+        Self {
+            words: words_from_bin(width, slice),
+            nzbw: width,
+        }
+    }
+
+    /// Creates a `Rom` from the string `s` in the `$readmemh` style format,
+    /// see [Rom::from_hex]
+    ///
+    /// # Errors
+    ///
+    /// If a word or address is invalid or a word does not fit in `width` bits
+    pub fn from_hex_str(width: NonZeroUsize, s: &str) -> Result<Self, Error> {
+        Ok(Self {
+            words: words_from_hex(width, s, None)?,
+            nzbw: width,
+        })
+    }
+
+    /// Creates a `Rom` from the file at `path` in the `$readmemh` style
+    /// format. Words are hexadecimal and separated by whitespace, and may
+    /// contain `_` separators. `//` starts a comment that continues to the end
+    /// of the line, and `@` followed by a hexadecimal word address moves to
+    /// that address. Skipped words are zero.
+    ///
+    /// # Errors
+    ///
+    /// If the file cannot be read, a word or address is invalid, or a word
+    /// does not fit in `width` bits
+    pub fn from_hex<P: AsRef<Path>>(width: NonZeroUsize, path: P) -> Result<Self, Error> {
+        Ok(Self {
+            words: words_from_hex_file(width, path, None)?,
+            nzbw: width,
+        })
+    }
+
+    /// Returns the bitwidth of the words as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.nzbw
+    }
+
+    /// Returns the bitwidth of the words as a `usize`
+    #[must_use]
+    pub fn bw(&self) -> usize {
+        self.nzbw.get()
+    }
+
+    /// Returns the number of words
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Returns if there are no words
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Returns the words
+    pub fn words(&self) -> &[awi::Awi] {
+        &self.words
+    }
+
+    /// Appends `word` to the end
+    ///
+    /// # Errors
+    ///
+    /// If `word.bw() != self.bw()`
+    pub fn push(&mut self, word: &awi::Bits) -> Result<(), Error> {
+        if word.bw() != self.bw() {
+            return Err(Error::BitwidthMismatch(self.bw(), word.bw()))
+        }
+        self.words.push(awi::Awi::from(word));
+        Ok(())
+    }
+
+    /// Returns the word at `addr`. Addresses past the end of the `Rom` read
+    /// zero.
+    ///
+    /// # Errors
+    ///
+    /// If `addr` is too narrow to address every word or so wide that the
+    /// table cannot be allocated
+    pub fn read(&self, addr: &dag::Bits) -> Result<dag::Awi, Error> {
+        let num_entries = u32::try_from(addr.bw())
+            .ok()
+            .and_then(|w| 1usize.checked_shl(w))
+            .ok_or(Error::OtherStr("`Rom::read` address is too wide"))?;
+        if num_entries < self.len() {
+            return Err(Error::OtherString(format!(
+                "`Rom::read` address of bitwidth {} cannot address all {} words",
+                addr.bw(),
+                self.len()
+            )))
+        }
+        let table_w = num_entries
+            .checked_mul(self.bw())
+            .and_then(NonZeroUsize::new)
+            .ok_or(Error::OtherStr("`Rom::read` address is too wide"))?;
+        let mut table = awi::Awi::zero(table_w);
+        for (i, word) in self.words.iter().enumerate() {
+            table.field_to(i * self.bw(), word, self.bw()).unwrap();
+        }
+        let mut res = dag::Awi::zero(self.nzbw);
+        res.lut_(&dag::Awi::from(&table), addr).unwrap();
+        Ok(res)
+    }
+}
+
+/// A memory of `depth` words of `width` bits with an asynchronous read port
+/// and a synchronous write port. The words are `Loop`s driven with a delay of
+/// 1, so a cycle corresponds to `epoch.run(1)`. The initial contents can be
+/// loaded from files or byte slices with [Mem::init_from_hex] and
+/// [Mem::init_from_bin].
+///
+/// [Mem::read] can be used before the memory is driven with [Mem::drive], and
+/// reflects the contents at the beginning of the cycle.
+#[derive(Debug)]
+pub struct Mem {
+    storage: Vec<Loop>,
+}
+
+impl Mem {
+    /// Creates a new `Mem` with `depth` words of `width` bits that are
+    /// initially zero
+    ///
+    /// # Errors
+    ///
+    /// If `depth == 0`
+    pub fn new(width: NonZeroUsize, depth: usize) -> Result<Self, Error> {
+        Self::from_rom(&Rom::new(width), depth)
+    }
+
+    /// Creates a new `Mem` with `depth` words initialized to the words of
+    /// `rom`, the rest of the words are initially zero
+    ///
+    /// # Errors
+    ///
+    /// If `depth == 0` or `rom` has more than `depth` words
+    pub fn from_rom(rom: &Rom, depth: usize) -> Result<Self, Error> {
+        if depth == 0 {
+            return Err(Error::OtherStr("`Mem` created with a zero `depth`"))
+        }
+        if rom.len() > depth {
+            return Err(Error::OtherString(format!(
+                "`Mem` initialized with {} words, which is more than its `depth` of {depth}",
+                rom.len()
+            )))
+        }
+        let mut storage = Vec::with_capacity(depth);
+        for i in 0..depth {
+            if let Some(word) = rom.words().get(i) {
+                storage.push(Loop::from_bits(&dag::Awi::from(word)));
+            } else {
+                storage.push(Loop::zero(rom.nzbw()));
+            }
+        }
+        Ok(Self { storage })
+    }
+
+    /// Creates a new `Mem` with `depth` words initialized from the bytes of
+    /// `slice`, see [Rom::from_bin]
+    ///
+    /// # Errors
+    ///
+    /// If `depth == 0` or `slice` has more than `depth` words
+    pub fn init_from_bin(width: NonZeroUsize, depth: usize, slice: &[u8]) -> Result<Self, Error> {
+        Self::from_rom(&Rom::from_bin(width, slice), depth)
+    }
+
+    /// Creates a new `Mem` with `depth` words initialized from the
+    /// `$readmemh` style file at `path`, see [Rom::from_hex]
+    ///
+    /// # Errors
+    ///
+    /// If `depth == 0`, the file has a word at an address that is not less
+    /// than `depth`, or the file cannot be read or parsed
+    pub fn init_from_hex<P: AsRef<Path>>(
+        width: NonZeroUsize,
+        depth: usize,
+        path: P,
+    ) -> Result<Self, Error> {
+        let rom = Rom {
+            words: words_from_hex_file(width, path, Some(depth))?,
+            nzbw: width,
+        };
+        Self::from_rom(&rom, depth)
+    }
+
+    /// Returns the bitwidth of the words as a `NonZeroUsize`
+    #[must_use]
+    pub fn nzbw(&self) -> NonZeroUsize {
+        self.storage[0].nzbw()
+    }
+
+    /// Returns the bitwidth of the words as a `usize`
+    #[must_use]
+    pub fn bw(&self) -> usize {
+        self.nzbw().get()
+    }
+
+    /// Returns the number of words
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.storage.len()
+    }
+
+    /// Returns the bitwidth that addresses must have, which is the number of
+    /// bits needed to represent `depth - 1` (or 1 if `depth == 1`)
+    #[must_use]
+    pub fn addr_nzbw(&self) -> NonZeroUsize {
+        dag::Bits::nontrivial_bits(self.depth() - 1).unwrap_or(bw(1))
+    }
+
+    /// Returns the one-hot selection of the words for `addr`, which is zero
+    /// if `addr` is out of range
+    fn select(&self, addr: &dag::Bits) -> Result<dag::Awi, Error> {
+        if addr.bw() != self.addr_nzbw().get() {
+            return Err(Error::BitwidthMismatch(self.addr_nzbw().get(), addr.bw()))
+        }
+        if self.depth() == 1 {
+            Ok(dag::Awi::from_bool(addr.is_zero()))
+        } else {
+            selector_awi(addr, Some(self.depth()))
+        }
+    }
+
+    /// Returns the word at `addr`, or zero if `addr` is out of range
+    ///
+    /// # Errors
+    ///
+    /// If `addr` does not have a bitwidth of [Mem::addr_nzbw]
+    pub fn read(&self, addr: &dag::Bits) -> Result<dag::Awi, Error> {
+        let sel = self.select(addr)?;
+        let words: Vec<dag::Awi> = self
+            .storage
+            .iter()
+            .map(|word| dag::Awi::from(&**word))
+            .collect();
+        onehot_mux(&words, &sel)
+    }
+
+    /// Consumes `self`, driving the memory for each cycle. If the single bit
+    /// `write_enable` is set, `data` is written to the word at `addr` at the
+    /// end of the cycle. Writes to out of range addresses are ignored.
+    ///
+    /// # Errors
+    ///
+    /// If `write_enable.bw() != 1`, `addr` does not have a bitwidth of
+    /// [Mem::addr_nzbw], or `data.bw() != self.bw()`
+    pub fn drive(
+        self,
+        write_enable: &dag::Bits,
+        addr: &dag::Bits,
+        data: &dag::Bits,
+    ) -> Result<(), Error> {
+        if write_enable.bw() != 1 {
+            return Err(Error::BitwidthMismatch(1, write_enable.bw()))
+        }
+        if data.bw() != self.bw() {
+            return Err(Error::BitwidthMismatch(self.bw(), data.bw()))
+        }
+        let sel = self.select(addr)?;
+        for (i, word) in self.storage.into_iter().enumerate() {
+            let mut we = dag::Awi::from(write_enable);
+            we.and_(&dag::Awi::from_bool(sel.get(i).unwrap())).unwrap();
+            let mut next = dag::Awi::from(&*word);
+            next.mux_(data, we.lsb()).unwrap();
+            word.drive_with_delay(&next, 1)?;
+        }
+        Ok(())
+    }
+}
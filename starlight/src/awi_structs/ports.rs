@@ -0,0 +1,302 @@
+use std::num::NonZeroUsize;
+
+use crate::{awi, dag, Error};
+
+/// Implemented for structs of fields that are bundled together as ports, such
+/// as all the inputs or all the outputs of a design. [crate::ports] can be used
+/// to define a struct along with this implementation and helper functions for
+/// creating `LazyAwi` and `EvalAwi` bundles of the same fields.
+///
+/// When flattened, the first field is in the least significant bits.
+pub trait Ports: Sized {
+    /// The names and bitwidths of all the fields in order
+    const FIELDS: &'static [(&'static str, usize)];
+
+    /// Returns all the fields in order
+    fn fields(&self) -> Vec<&dag::Bits>;
+
+    /// Creates `Self` from all the fields in order
+    ///
+    /// # Panics
+    ///
+    /// If `fields.len() != Self::FIELDS.len()`
+    fn from_fields(fields: Vec<dag::Awi>) -> Self;
+
+    /// Returns the total bitwidth of all the fields as a `NonZeroUsize`
+    #[must_use]
+    fn nzbw() -> NonZeroUsize {
+        NonZeroUsize::new(Self::bw()).expect("`Ports` with no fields or only zero width fields")
+    }
+
+    /// Returns the total bitwidth of all the fields as a `usize`
+    #[must_use]
+    fn bw() -> usize {
+        Self::FIELDS.iter().map(|(_, w)| *w).sum()
+    }
+
+    /// Concatenates all the fields together
+    ///
+    /// # Errors
+    ///
+    /// If a field does not have the bitwidth listed in `Self::FIELDS`
+    fn flatten(&self) -> Result<dag::Awi, Error> {
+        let mut res = dag::Awi::zero(Self::nzbw());
+        let mut to = 0;
+        for (field, (_, w)) in self.fields().into_iter().zip(Self::FIELDS) {
+            if field.bw() != *w {
+                return Err(Error::BitwidthMismatch(*w, field.bw()))
+            }
+            res.field_to(to, field, *w).unwrap();
+            to += *w;
+        }
+        Ok(res)
+    }
+
+    /// The inverse of [Ports::flatten]
+    ///
+    /// # Errors
+    ///
+    /// If `bits.bw() != Self::bw()`
+    fn unflatten(bits: &dag::Bits) -> Result<Self, Error> {
+        if bits.bw() != Self::bw() {
+            return Err(Error::BitwidthMismatch(Self::bw(), bits.bw()))
+        }
+        let mut fields = vec![];
+        let mut from = 0;
+        for (_, w) in Self::FIELDS {
+            let mut field = dag::Awi::zero(NonZeroUsize::new(*w).unwrap());
+            field.field_from(bits, from, *w).unwrap();
+            fields.push(field);
+            from += *w;
+        }
+        Ok(Self::from_fields(fields))
+    }
+
+    /// The same as [Ports::flatten] but for the evaluated values of the fields
+    ///
+    /// # Errors
+    ///
+    /// If the number of fields is wrong or if a field does not have the
+    /// bitwidth listed in `Self::FIELDS`
+    fn flatten_awi(fields: &[&awi::Bits]) -> Result<awi::Awi, Error> {
+        if fields.len() != Self::FIELDS.len() {
+            return Err(Error::OtherString(format!(
+                "`Ports::flatten_awi` expected {} fields but got {}",
+                Self::FIELDS.len(),
+                fields.len()
+            )))
+        }
+        let mut res = awi::Awi::zero(Self::nzbw());
+        let mut to = 0;
+        for (field, (_, w)) in fields.iter().zip(Self::FIELDS) {
+            if field.bw() != *w {
+                return Err(Error::BitwidthMismatch(*w, field.bw()))
+            }
+            res.field_to(to, field, *w).unwrap();
+            to += *w;
+        }
+        Ok(res)
+    }
+
+    /// The same as [Ports::unflatten] but for evaluated values, returning the
+    /// fields in order
+    ///
+    /// # Errors
+    ///
+    /// If `bits.bw() != Self::bw()`
+    fn unflatten_awi(bits: &awi::Bits) -> Result<Vec<awi::Awi>, Error> {
+        if bits.bw() != Self::bw() {
+            return Err(Error::BitwidthMismatch(Self::bw(), bits.bw()))
+        }
+        let mut fields = vec![];
+        let mut from = 0;
+        for (_, w) in Self::FIELDS {
+            let mut field = awi::Awi::zero(NonZeroUsize::new(*w).unwrap());
+            field.field_from(bits, from, *w).unwrap();
+            fields.push(field);
+            from += *w;
+        }
+        Ok(fields)
+    }
+}
+
+/// Defines a struct of fields with the given bitwidths along with an
+/// implementation of [Ports](crate::Ports). The struct is generic over the
+/// type of its fields, defaulting to `dag::Awi`, so that the same fields can
+/// be bundled as `LazyAwi` inputs, `EvalAwi` outputs, or `awi::Awi` values
+/// without wiring each field by hand. The struct automatically derives `Debug`
+/// and `Clone`, and gets these functions:
+///
+/// - `map`, `try_map`, and `each_ref` for any field type
+/// - `Struct::<LazyAwi>::opaque` for creating opaque inputs, `to_dag` for
+///   getting their mimicking values, and `retro_` and `retro_flat_` for
+///   assigning them from values
+/// - `to_eval` for creating `EvalAwi` outputs from the mimicking values, and
+///   `eval` and `eval_flat` for evaluating them
+///
+/// The created `LazyAwi`s and `EvalAwi`s get debug names of the form
+/// `Struct.field`.
+///
+/// ```
+/// use starlight::{awi, ports, Epoch, EvalAwi, LazyAwi, Ports};
+///
+/// ports! {
+///     pub struct Adder {
+///         pub lhs: 8,
+///         pub rhs: 8,
+///     }
+/// }
+///
+/// let epoch = Epoch::new();
+/// let inputs = Adder::<LazyAwi>::opaque();
+/// let dut = inputs.to_dag();
+/// let mut sum = dut.lhs.clone();
+/// sum.add_(&dut.rhs).unwrap();
+/// let sum = EvalAwi::from(&sum);
+/// // `lhs` is in the least significant bits
+/// inputs.retro_flat_(&awi::Awi::from_u16(0x0302)).unwrap();
+/// assert_eq!(sum.eval_u8().unwrap(), 5);
+/// assert_eq!(Adder::bw(), 16);
+/// drop(epoch);
+/// ```
+#[macro_export]
+macro_rules! ports {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$f_attr:meta])* $f_vis:vis $field:ident: $w:expr),+ $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        $vis struct $name<T = $crate::dag::Awi> {
+            $($(#[$f_attr])* $f_vis $field: T),+
+        }
+
+        impl<T> $name<T> {
+            /// Applies `f` to every field along with the name of the field
+            pub fn map<U, F>(self, mut f: F) -> $name<U>
+            where
+                F: FnMut(&'static ::core::primitive::str, T) -> U,
+            {
+                $name {
+                    $($field: f(::core::stringify!($field), self.$field)),+
+                }
+            }
+
+            /// Applies `f` to every field along with the name of the field,
+            /// returning the first error
+            pub fn try_map<U, E, F>(self, mut f: F) -> ::core::result::Result<$name<U>, E>
+            where
+                F: FnMut(&'static ::core::primitive::str, T) -> ::core::result::Result<U, E>,
+            {
+                ::core::result::Result::Ok($name {
+                    $($field: f(::core::stringify!($field), self.$field)?),+
+                })
+            }
+
+            /// Returns a struct of references to the fields
+            pub fn each_ref(&self) -> $name<&T> {
+                $name {
+                    $($field: &self.$field),+
+                }
+            }
+        }
+
+        impl $crate::Ports for $name {
+            const FIELDS: &'static [(&'static ::core::primitive::str, ::core::primitive::usize)] =
+                &[$((::core::stringify!($field), $w)),+];
+
+            fn fields(&self) -> ::std::vec::Vec<&$crate::dag::Bits> {
+                ::std::vec![$(&*self.$field),+]
+            }
+
+            fn from_fields(fields: ::std::vec::Vec<$crate::dag::Awi>) -> Self {
+                ::core::assert_eq!(fields.len(), <Self as $crate::Ports>::FIELDS.len());
+                let mut fields = fields.into_iter();
+                $name {
+                    $($field: fields.next().unwrap()),+
+                }
+            }
+        }
+
+        impl $name {
+            /// Creates `EvalAwi`s for all the fields
+            #[track_caller]
+            pub fn to_eval(&self) -> $name<$crate::EvalAwi> {
+                $name {
+                    $($field: {
+                        let eval = $crate::EvalAwi::from_bits(&self.$field);
+                        eval.set_debug_name(::core::concat!(
+                            ::core::stringify!($name), ".", ::core::stringify!($field)
+                        )).unwrap();
+                        eval
+                    }),+
+                }
+            }
+        }
+
+        impl $name<$crate::LazyAwi> {
+            /// Creates opaque `LazyAwi`s for all the fields
+            #[track_caller]
+            pub fn opaque() -> Self {
+                $name {
+                    $($field: {
+                        let lazy = $crate::LazyAwi::opaque($crate::awi::bw($w));
+                        lazy.set_debug_name(::core::concat!(
+                            ::core::stringify!($name), ".", ::core::stringify!($field)
+                        )).unwrap();
+                        lazy
+                    }),+
+                }
+            }
+
+            /// Returns the mimicking values of all the fields
+            pub fn to_dag(&self) -> $name {
+                $name {
+                    $($field: $crate::dag::Awi::from_bits(&self.$field)),+
+                }
+            }
+
+            /// Retroactively-assigns all the fields from the fields of `rhs`
+            pub fn retro_(
+                &self,
+                rhs: &$name<$crate::awi::Awi>,
+            ) -> ::core::result::Result<(), $crate::Error> {
+                $(self.$field.retro_(&rhs.$field)?;)+
+                ::core::result::Result::Ok(())
+            }
+
+            /// Retroactively-assigns all the fields from flattened bits
+            pub fn retro_flat_(
+                &self,
+                rhs: &$crate::awi::Bits,
+            ) -> ::core::result::Result<(), $crate::Error> {
+                let values = <$name as $crate::Ports>::unflatten_awi(rhs)?;
+                for (lazy, value) in [$(&self.$field),+].into_iter().zip(values.iter()) {
+                    lazy.retro_(value)?;
+                }
+                ::core::result::Result::Ok(())
+            }
+        }
+
+        impl $name<$crate::EvalAwi> {
+            /// Evaluates all the fields
+            pub fn eval(
+                &self,
+            ) -> ::core::result::Result<$name<$crate::awi::Awi>, $crate::Error> {
+                ::core::result::Result::Ok($name {
+                    $($field: self.$field.eval()?),+
+                })
+            }
+
+            /// Evaluates all the fields and flattens them
+            pub fn eval_flat(
+                &self,
+            ) -> ::core::result::Result<$crate::awi::Awi, $crate::Error> {
+                let values = self.eval()?;
+                <$name as $crate::Ports>::flatten_awi(&[$(&*values.$field),+])
+            }
+        }
+    };
+}
@@ -0,0 +1,150 @@
+use crate::{dag, Error};
+
+/// Selects the first value in `arms` whose condition is true, or `default` if
+/// no condition is true, with the same semantics as an `if`/`else if`/`else`
+/// chain. Unlike a chain of `mux_`s, which has a depth linear in the number
+/// of arms, this builds a balanced tree of muxes with a logarithmic depth.
+/// [crate::sel_] is sugar for this function.
+///
+/// # Errors
+///
+/// If any of the values have a different bitwidth from `default`
+pub fn priority_mux(
+    arms: &[(dag::bool, &dag::Bits)],
+    default: &dag::Bits,
+) -> Result<dag::Awi, Error> {
+    for (_, val) in arms {
+        if val.bw() != default.bw() {
+            return Err(Error::BitwidthMismatch(default.bw(), val.bw()))
+        }
+    }
+    if arms.is_empty() {
+        return Ok(dag::Awi::from(default))
+    }
+    let (val, any) = priority_tree(arms);
+    let mut res = dag::Awi::from(default);
+    res.mux_(&val, any).unwrap();
+    Ok(res)
+}
+
+/// Returns the first value in `arms` with a true condition (or an arbitrary
+/// value if there is none), and if any condition is true. `arms` must be
+/// nonempty.
+fn priority_tree(arms: &[(dag::bool, &dag::Bits)]) -> (dag::Awi, dag::bool) {
+    if let [(cond, val)] = arms {
+        return (dag::Awi::from(*val), *cond)
+    }
+    let (lhs, rhs) = arms.split_at(arms.len() / 2);
+    let (lhs_val, lhs_any) = priority_tree(lhs);
+    let (mut val, rhs_any) = priority_tree(rhs);
+    val.mux_(&lhs_val, lhs_any).unwrap();
+    (val, lhs_any | rhs_any)
+}
+
+/// Selects the value of the first arm in `arms` that has a pattern equal to
+/// `sel`, or `default` if there is no such arm, with the same semantics as a
+/// `match` on integer patterns. This builds a balanced tree of muxes like
+/// [priority_mux]. [crate::mux_] is sugar for this function.
+///
+/// # Errors
+///
+/// If any of the values have a different bitwidth from `default`, or if a
+/// pattern cannot be represented in `sel.bw()` bits
+pub fn match_mux(
+    sel: &dag::Bits,
+    arms: &[(&[usize], &dag::Bits)],
+    default: &dag::Bits,
+) -> Result<dag::Awi, Error> {
+    let mut conds = vec![];
+    for (patterns, val) in arms {
+        let mut cond = dag::bool::from(false);
+        for pattern in patterns.iter().copied() {
+            if (sel.bw() < (usize::BITS as usize)) && ((pattern >> sel.bw()) != 0) {
+                return Err(Error::OtherString(format!(
+                    "`match_mux` pattern {pattern} cannot be represented in the {} bits of `sel`",
+                    sel.bw()
+                )))
+            }
+            let mut lit = dag::Awi::zero(sel.nzbw());
+            lit.usize_(pattern);
+            cond |= sel.const_eq(&lit).unwrap();
+        }
+        conds.push((cond, *val));
+    }
+    priority_mux(&conds, default)
+}
+
+/// Sugar for [priority_mux](crate::priority_mux) with the semantics of an
+/// `if`/`else if`/`else` chain on mimicking `bool`s. Each arm is a condition
+/// and a value separated by `=>`, and the last arm must be a `_ =>` default.
+/// Because this is hardware, all of the values are computed regardless of
+/// the conditions. The values can be anything that derefs to `Bits`, and the
+/// result is a `Result<dag::Awi, Error>`.
+///
+/// ```
+/// use starlight::{dag, sel_, Epoch, EvalAwi};
+/// use dag::*;
+///
+/// let epoch = Epoch::new();
+/// let a = inlawi!(0x12u8);
+/// let b = Awi::from_u8(0x34);
+/// let c = inlawi!(0x56u8);
+/// // the same as `if a.is_zero() { a } else if true { b } else { c }`
+/// let x = sel_!(a.is_zero() => a, true => &b, _ => c).unwrap();
+/// assert_eq!(EvalAwi::from(&x).eval_u8().unwrap(), 0x34);
+/// drop(epoch);
+/// ```
+#[macro_export]
+macro_rules! sel_ {
+    (@arms [$($arms:tt)*] _ => $default:expr $(,)?) => {{
+        let arms: &[($crate::dag::bool, &$crate::dag::Bits)] = &[$($arms)*];
+        let default: &$crate::dag::Bits = &$default;
+        $crate::priority_mux(arms, default)
+    }};
+    (@arms [$($arms:tt)*] $cond:expr => $val:expr, $($rest:tt)*) => {
+        $crate::sel_!(@arms [$($arms)* ($crate::dag::bool::from($cond), &$val),] $($rest)*)
+    };
+    ($($rest:tt)*) => {
+        $crate::sel_!(@arms [] $($rest)*)
+    };
+}
+
+/// Sugar for [match_mux](crate::match_mux) with the semantics of a `match` on
+/// the value of a mimicking `Bits`. The selector comes first followed by a
+/// `;`, then each arm is one or more `usize` patterns separated by `|` and a
+/// value separated by `=>`, and the last arm must be a `_ =>` default. Because
+/// this is hardware, all of the values are computed regardless of the
+/// selector. The values can be anything that derefs to `Bits`, and the result
+/// is a `Result<dag::Awi, Error>`.
+///
+/// ```
+/// use starlight::{dag, mux_, Epoch, EvalAwi};
+/// use dag::*;
+///
+/// let epoch = Epoch::new();
+/// let opcode = inlawi!(10);
+/// let a = inlawi!(0x12u8);
+/// let b = inlawi!(0x34u8);
+/// let x = mux_!(opcode;
+///     0 => a,
+///     1 | 2 => b,
+///     _ => Awi::zero(a.nzbw()),
+/// )
+/// .unwrap();
+/// assert_eq!(EvalAwi::from(&x).eval_u8().unwrap(), 0x34);
+/// drop(epoch);
+/// ```
+#[macro_export]
+macro_rules! mux_ {
+    (@arms $sel:expr; [$($arms:tt)*] _ => $default:expr $(,)?) => {{
+        let arms: &[(&[::core::primitive::usize], &$crate::dag::Bits)] = &[$($arms)*];
+        let default: &$crate::dag::Bits = &$default;
+        $crate::match_mux(&$sel, arms, default)
+    }};
+    (@arms $sel:expr; [$($arms:tt)*] $($pattern:literal)|+ => $val:expr, $($rest:tt)*) => {
+        $crate::mux_!(@arms $sel; [$($arms)* (&[$($pattern),+], &$val),] $($rest)*)
+    };
+    ($sel:expr; $($rest:tt)*) => {
+        $crate::mux_!(@arms $sel; [] $($rest)*)
+    };
+}
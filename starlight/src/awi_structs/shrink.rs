@@ -0,0 +1,108 @@
+use crate::{awi, ensemble::PExternal, Epoch, Error, LazyAwi};
+
+/// The result of [Epoch::shrink_failure]
+#[derive(Debug, Clone)]
+pub struct ShrinkReport {
+    /// The minimized values of the inputs, in the same order as they were
+    /// given. The inputs are left assigned to these values.
+    pub values: Vec<awi::Awi>,
+    /// The assertion that fails with the minimized values
+    pub failing: PExternal,
+    /// The number of candidate values that were tried
+    pub num_attempts: usize,
+}
+
+impl Epoch {
+    /// Returns the first assertion that evaluates to false, if any. Assertions
+    /// that cannot be evaluated to a known value do not count as failing.
+    fn first_failing_assertion(&self) -> Option<PExternal> {
+        for bit in &self.assertions().bits {
+            if let Ok(val) = bit.eval() {
+                if val.is_zero() {
+                    return Some(bit.p_external())
+                }
+            }
+        }
+        None
+    }
+
+    /// Retro-assigns `value` to `input` and returns if an assertion still
+    /// fails
+    fn shrink_attempt(&self, input: &LazyAwi, value: &awi::Bits) -> Result<bool, Error> {
+        input.retro_(value)?;
+        Ok(self.first_failing_assertion().is_some())
+    }
+
+    /// When an assertion of `self` is failing with the currently assigned
+    /// values of `inputs`, this searches for a smaller failing case by first
+    /// trying to zero whole inputs and then trying to clear individual set
+    /// bits, until no single change keeps the failure. This is similar to
+    /// shrinking in property testing frameworks. Because the evaluator only
+    /// propagates changes, each attempt only reevaluates the cones of the
+    /// changed bits. The inputs are left assigned to the minimized values,
+    /// which are also returned in the report. Requires that `self` be the
+    /// current `Epoch`.
+    ///
+    /// # Errors
+    ///
+    /// If no assertion is failing, or if any of the inputs do not have a known
+    /// value
+    pub fn shrink_failure(&self, inputs: &[&LazyAwi]) -> Result<ShrinkReport, Error> {
+        self.check_current()?;
+        let mut values = vec![];
+        for input in inputs {
+            if let Some(value) = self.peek(input.p_external())? {
+                values.push(value);
+            } else {
+                return Err(Error::OtherString(format!(
+                    "`Epoch::shrink_failure`: input {} does not have a known value",
+                    input.p_external()
+                )))
+            }
+        }
+        if self.first_failing_assertion().is_none() {
+            return Err(Error::OtherStr(
+                "`Epoch::shrink_failure`: no assertion is failing",
+            ))
+        }
+        let mut num_attempts = 0;
+        loop {
+            let mut progress = false;
+            for (input, value) in inputs.iter().zip(values.iter_mut()) {
+                if value.is_zero() {
+                    continue
+                }
+                let mut candidate = awi::Awi::zero(value.nzbw());
+                num_attempts += 1;
+                if self.shrink_attempt(input, &candidate)? {
+                    value.copy_(&candidate).unwrap();
+                    progress = true;
+                    continue
+                }
+                for bit_i in 0..value.bw() {
+                    if !value.get(bit_i).unwrap() {
+                        continue
+                    }
+                    candidate.copy_(value).unwrap();
+                    candidate.set(bit_i, false).unwrap();
+                    num_attempts += 1;
+                    if self.shrink_attempt(input, &candidate)? {
+                        value.copy_(&candidate).unwrap();
+                        progress = true;
+                    }
+                }
+                // restore the best value after any unsuccessful attempt
+                input.retro_(value)?;
+            }
+            if !progress {
+                break
+            }
+        }
+        let failing = self.first_failing_assertion().unwrap();
+        Ok(ShrinkReport {
+            values,
+            failing,
+            num_attempts,
+        })
+    }
+}
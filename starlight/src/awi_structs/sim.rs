@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::{Epoch, Error};
+
+/// When a callback added with [Sim::add_phase] is called
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Called once before any time passes, on the first [Sim::run] or in
+    /// [Sim::finish] if `run` was never called
+    Init,
+    /// Called at every time that is a multiple of the period, before the
+    /// `Epoch` is run past that time. This includes time zero after the `Init`
+    /// callbacks.
+    EveryCycle(u128),
+    /// Called once by [Sim::finish]
+    Final,
+}
+
+type Callback<'a> = Box<dyn FnMut(u128) -> Result<(), Error> + 'a>;
+
+/// A testbench harness on top of [Epoch::run] that calls callbacks at defined
+/// points in time. The callbacks receive the current time and typically
+/// capture `LazyAwi`s to retroactively assign and `EvalAwi`s to evaluate,
+/// which formalizes the usual pattern of alternating `retro_*`, `run`, and
+/// `eval`. The callbacks are called within [Epoch::scope], so the `Epoch` can
+/// be detached. Callbacks for the same time are called in the order they were
+/// added, and the first error stops the simulation.
+///
+/// ```
+/// use std::cell::RefCell;
+///
+/// use starlight::{dag, Epoch, EvalAwi, LazyAwi, Loop, Phase, Sim};
+///
+/// let epoch = Epoch::new();
+/// let enable = LazyAwi::opaque(dag::bw(1));
+/// let count = {
+///     use dag::*;
+///     let counter = Loop::zero(bw(8));
+///     let mut next = awi!(counter);
+///     next.inc_(enable.to_bool());
+///     let count = EvalAwi::from(&counter);
+///     counter.drive_with_delay(&next, 1).unwrap();
+///     count
+/// };
+///
+/// let samples = RefCell::new(vec![]);
+/// let mut sim = Sim::new(&epoch);
+/// sim.add_phase(Phase::Init, |_| enable.retro_bool_(false))
+///     .unwrap();
+/// // enable the counter every other cycle
+/// sim.add_phase(Phase::EveryCycle(1), |time| {
+///     enable.retro_bool_(time % 2 == 1)
+/// })
+/// .unwrap();
+/// sim.add_phase(Phase::EveryCycle(4), |_| {
+///     samples.borrow_mut().push(count.eval_u8()?);
+///     Ok(())
+/// })
+/// .unwrap();
+/// sim.run(12).unwrap();
+/// assert_eq!(sim.time(), 12);
+/// sim.finish().unwrap();
+/// assert_eq!(*samples.borrow(), vec![0, 2, 4]);
+/// drop(enable);
+/// drop(count);
+/// drop(epoch);
+/// ```
+pub struct Sim<'a> {
+    epoch: &'a Epoch,
+    phases: Vec<(Phase, Callback<'a>)>,
+    time: u128,
+    initialized: bool,
+}
+
+impl<'a> fmt::Debug for Sim<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sim")
+            .field("epoch", &self.epoch)
+            .field(
+                "phases",
+                &self
+                    .phases
+                    .iter()
+                    .map(|(phase, _)| *phase)
+                    .collect::<Vec<_>>(),
+            )
+            .field("time", &self.time)
+            .field("initialized", &self.initialized)
+            .finish()
+    }
+}
+
+impl<'a> Sim<'a> {
+    /// Creates a `Sim` with no callbacks for `epoch` at time zero
+    pub fn new(epoch: &'a Epoch) -> Self {
+        Self {
+            epoch,
+            phases: vec![],
+            time: 0,
+            initialized: false,
+        }
+    }
+
+    /// Returns the number of units of time that have been run
+    pub fn time(&self) -> u128 {
+        self.time
+    }
+
+    /// Adds `callback` to be called at the points defined by `phase`, with the
+    /// current time as its argument
+    ///
+    /// # Errors
+    ///
+    /// If `phase` is `Phase::EveryCycle(0)`
+    pub fn add_phase<F: FnMut(u128) -> Result<(), Error> + 'a>(
+        &mut self,
+        phase: Phase,
+        callback: F,
+    ) -> Result<(), Error> {
+        if phase == Phase::EveryCycle(0) {
+            return Err(Error::OtherStr(
+                "`Sim::add_phase` was given `Phase::EveryCycle` with a period of zero",
+            ))
+        }
+        self.phases.push((phase, Box::new(callback)));
+        Ok(())
+    }
+
+    /// Calls the callbacks of the phases for which `f` is true
+    fn call(&mut self, f: impl Fn(Phase) -> bool) -> Result<(), Error> {
+        let time = self.time;
+        for (phase, callback) in &mut self.phases {
+            if f(*phase) {
+                self.epoch.scope(|_| callback(time))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the `Init` callbacks if they have not been called yet
+    fn init(&mut self) -> Result<(), Error> {
+        if !self.initialized {
+            self.initialized = true;
+            self.call(|phase| phase == Phase::Init)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the `Epoch` for `time` units of time, calling the `EveryCycle`
+    /// callbacks at every multiple of their periods along the way. Callbacks
+    /// for the time at the end of the run are called at the start of the next
+    /// `run`.
+    ///
+    /// # Errors
+    ///
+    /// If a callback or [Epoch::run] returns an error
+    pub fn run(&mut self, time: u128) -> Result<(), Error> {
+        self.init()?;
+        let end = self.time.checked_add(time).unwrap();
+        while self.time < end {
+            let now = self.time;
+            self.call(
+                |phase| matches!(phase, Phase::EveryCycle(period) if now.is_multiple_of(period)),
+            )?;
+            // run until the next time that a callback is due
+            let mut next = end;
+            for (phase, _) in &self.phases {
+                if let Phase::EveryCycle(period) = *phase {
+                    next = next.min(now.saturating_add(period - (now % period)));
+                }
+            }
+            self.epoch.scope(|epoch| epoch.run(next - now))?;
+            self.time = next;
+        }
+        Ok(())
+    }
+
+    /// Calls the `Final` callbacks, after calling the `Init` callbacks if
+    /// [Sim::run] was never called
+    ///
+    /// # Errors
+    ///
+    /// If a callback returns an error
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.init()?;
+        self.call(|phase| phase == Phase::Final)
+    }
+}
@@ -2,7 +2,12 @@ use std::{borrow::Borrow, num::NonZeroUsize, ops::Deref};
 
 use awint::awint_dag::{Lineage, Op, PState};
 
-use crate::{awi, dag, epoch::get_current_epoch, lower::meta::general_mux, Delay, Error};
+use crate::{
+    awi, dag,
+    epoch::get_current_epoch,
+    lower::meta::{general_mux, is_onehot, onehot_mux},
+    Delay, Error,
+};
 
 pub(crate) const DELAY: &str = "starlight::delay";
 pub(crate) const UNDRIVEN_LOOP_SOURCE: &str = "starlight::undriven_loop_source";
@@ -567,16 +572,39 @@ impl Net {
         } else {
             Awi::from(inx)
         };
-        let tmp = general_mux(&self.ports, &small_inx);
+        let tmp = general_mux(&self.ports, &small_inx).unwrap();
         self.source.drive(&tmp).unwrap();
 
         dag::Option::some_at_dagtime((), in_range)
     }
 
+    /// Drives with the value of the port corresponding to the single set bit
+    /// of `onehot`, where the `i`th bit of `onehot` selects the `i`th port.
+    /// This is more natural than [Net::drive] for bus-style interconnects
+    /// where each port already has its own enable signal.
+    ///
+    /// An assertion that `onehot` is actually one-hot is automatically
+    /// registered with the current `Epoch`. If the assertion fails, the source
+    /// is driven with the bitwise OR of all the selected ports, which is zero
+    /// if no bit of `onehot` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `onehot.bw() != self.len()`, which includes the
+    /// case where `self.is_empty()`
+    #[track_caller]
+    pub fn drive_onehot(self, onehot: &dag::Bits) -> Result<(), Error> {
+        if onehot.bw() != self.len() {
+            return Err(Error::BitwidthMismatch(self.len(), onehot.bw()))
+        }
+        let tmp = onehot_mux(&self.ports, onehot)?;
+        self.source.drive(&tmp).unwrap();
+        dag::mimick::assert!(is_onehot(onehot)?.lsb());
+        Ok(())
+    }
+
     // TODO we can do this
-    // Drives with a one-hot vector of selectors.
     //pub fn drive_priority(mut self, inx: impl Into<dag::usize>) {
-    //pub fn drive_onehot(mut self, onehot)
 }
 
 impl Deref for Net {
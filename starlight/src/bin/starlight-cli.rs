@@ -0,0 +1,16 @@
+//! See the `starlight::cli` module
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match starlight::cli::run(std::env::args().skip(1)) {
+        Ok(output) => {
+            print!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
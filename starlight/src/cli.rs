@@ -0,0 +1,373 @@
+//! The flows behind the `starlight-cli` binary, which is built when the "cli"
+//! feature is enabled. Designs are passed around in the "slir" text format of
+//! [Ensemble::write_ir], so that the common flows can be run by people who
+//! do not write Rust. The `RNode` names set by `set_debug_name` before the
+//! design was written are how values are referred to.
+//!
+//! Each flow is available both as a function working on strings, and through
+//! [run] which parses command line arguments and handles the files.
+//!
+//! ```
+//! use starlight::{cli, dag, ensemble::Ensemble, Epoch, EvalAwi, LazyAwi};
+//!
+//! let epoch = Epoch::new();
+//! let a = LazyAwi::opaque(dag::bw(8));
+//! a.set_debug_name("a").unwrap();
+//! let mut b = dag::Awi::from(&a);
+//! b.not_();
+//! let b = EvalAwi::from(&b);
+//! b.set_debug_name("b").unwrap();
+//! epoch.optimize().unwrap();
+//! let ir = epoch.ensemble(|ensemble| ensemble.write_ir());
+//! drop(epoch);
+//!
+//! let out = cli::simulate_ir(&ir, &[
+//!     cli::SimulateStep::Set("a".to_owned(), "0x0f".to_owned()),
+//!     cli::SimulateStep::Print("b".to_owned()),
+//! ])
+//! .unwrap();
+//! assert_eq!(out, "b = 0xf0_u8\n");
+//! ```
+
+use std::{collections::HashMap, fmt::Write, num::NonZeroUsize};
+
+use crate::{
+    awi,
+    ensemble::{Ensemble, PExternal},
+    route::{Configurator, Router},
+    sweep::SweepStats,
+    Corresponder, Delay, Epoch, Error, EvalAwi, LazyAwi,
+};
+
+/// The usage text printed by `starlight-cli help`
+pub const USAGE: &str = "\
+usage: starlight-cli <subcommand> [arguments]
+
+subcommands:
+    optimize <in.slir> [-o <out.slir>]
+        lowers and optimizes the design
+    simulate <in.slir> [--set <name>=<value> | --run <delay> | --print <name>]...
+        runs the steps in order, values can have `0x`, `0o`, or `0b` prefixes
+    route <program.slir> <target.slir> [--config-prefix <prefix>] [-o <out>]
+        routes the program onto the target and prints the configuration. Named
+        values with the same name in both are corresponded, and the writable
+        values of the target with names starting with the prefix (`config` by
+        default) are its configuration bits
+    export <in.slir> [--format slir|stats|blif|svg] [-o <out>]
+        writes the design in another format, `blif` is for VPR with latches on
+        a `clk` input, `svg` needs the \"debug\" feature and `-o` to be a
+        directory
+    help
+        prints this message
+";
+
+/// A step of [simulate_ir]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulateStep {
+    /// Assigns the value parsed from the second string to the writable value
+    /// named by the first string
+    Set(String, String),
+    /// Runs the simulation for a delay
+    Run(Delay),
+    /// Adds a `name = value` line to the output
+    Print(String),
+}
+
+/// Parses `s` as a value of bitwidth `w`, with an optional `0x`, `0o`, or
+/// `0b` radix prefix and otherwise decimal
+pub(crate) fn parse_value(s: &str, w: NonZeroUsize) -> Result<awi::Awi, Error> {
+    let (s, radix) = if let Some(s) = s.strip_prefix("0x") {
+        (s, 16)
+    } else if let Some(s) = s.strip_prefix("0o") {
+        (s, 8)
+    } else if let Some(s) = s.strip_prefix("0b") {
+        (s, 2)
+    } else {
+        (s, 10)
+    };
+    awi::Awi::from_str_radix(None, s, radix, w)
+        .map_err(|e| Error::OtherString(format!("could not parse value \"{s}\": {e:?}")))
+}
+
+fn find_external(epoch: &Epoch, name: &str) -> Result<PExternal, Error> {
+    epoch
+        .find_external_by_name(name)
+        .ok_or_else(|| Error::OtherString(format!("could not find a value named \"{name}\"")))
+}
+
+/// Returns the name, `PExternal`, and read-only-ness of every named `RNode`
+fn named_rnodes(ensemble: &Ensemble) -> Vec<(String, PExternal, bool)> {
+    let mut res = vec![];
+    for p_rnode in ensemble.notary.rnodes().ptrs() {
+        let (p_external, rnode) = ensemble.notary.rnodes().get(p_rnode).unwrap();
+        if let Some(name) = rnode.debug_name() {
+            res.push((name.to_owned(), *p_external, rnode.read_only()));
+        }
+    }
+    res
+}
+
+/// Parses `ir`, lowers and optimizes it, and returns the result in the same
+/// format
+pub fn optimize_ir(ir: &str) -> Result<String, Error> {
+    let epoch = Epoch::from_ensemble(Ensemble::parse_ir(ir)?);
+    epoch.optimize()?;
+    Ok(epoch.ensemble(|ensemble| ensemble.write_ir()))
+}
+
+/// Parses `ir` and runs `steps` on it in order, returning the lines from
+/// [SimulateStep::Print]. Values that could not be evaluated are printed as
+/// `unknown`.
+pub fn simulate_ir(ir: &str, steps: &[SimulateStep]) -> Result<String, Error> {
+    let epoch = Epoch::from_ensemble(Ensemble::parse_ir(ir)?);
+    let mut res = String::new();
+    for step in steps {
+        match step {
+            SimulateStep::Set(name, value) => {
+                let p_external = find_external(&epoch, name)?;
+                let w = epoch.ensemble(|ensemble| {
+                    ensemble
+                        .notary
+                        .get_rnode(p_external)
+                        .map(|(_, rnode)| rnode.nzbw())
+                })?;
+                epoch.poke(p_external, &parse_value(value, w)?)?;
+            }
+            SimulateStep::Run(delay) => epoch.run(*delay)?,
+            SimulateStep::Print(name) => {
+                let p_external = find_external(&epoch, name)?;
+                if let Some(value) = epoch.peek(p_external)? {
+                    writeln!(res, "{name} = {value:?}").unwrap();
+                } else {
+                    writeln!(res, "{name} = unknown").unwrap();
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Routes `program_ir` onto `target_ir` and returns a `name = value` line for
+/// each configuration value of the target, see [route_configs]
+pub fn route_ir(program_ir: &str, target_ir: &str, config_prefix: &str) -> Result<String, Error> {
+    let mut res = String::new();
+    for (name, value) in route_configs(program_ir, target_ir, config_prefix)? {
+        writeln!(res, "{name} = {value:?}").unwrap();
+    }
+    Ok(res)
+}
+
+/// Routes `program_ir` onto `target_ir` and returns the name and value of each
+/// configuration value of the target. Named values that appear in both are
+/// corresponded with each other, and must both be writable or both be
+/// read-only. The other writable values of the target with names starting
+/// with `config_prefix` are used as the configuration, and values like unused
+/// pads are ignored.
+pub fn route_configs(
+    program_ir: &str,
+    target_ir: &str,
+    config_prefix: &str,
+) -> Result<Vec<(String, awi::Awi)>, Error> {
+    let program = Ensemble::parse_ir(program_ir)?;
+    let target = Ensemble::parse_ir(target_ir)?;
+    let program_rnodes = named_rnodes(&program);
+    let program_names: HashMap<&str, bool> = program_rnodes
+        .iter()
+        .map(|(name, _, read_only)| (name.as_str(), *read_only))
+        .collect();
+
+    // the handles are dropped after all the epochs are, so that they do not
+    // need to be dropped while their own `Epoch` is current
+    let mut configurator = Configurator::new();
+    let mut configs = vec![];
+    let mut target_lazy = HashMap::new();
+    let mut target_eval = HashMap::new();
+    let target_epoch = Epoch::from_ensemble(target);
+    for (name, p_external, read_only) in target_epoch.ensemble(named_rnodes) {
+        match program_names.get(name.as_str()) {
+            Some(program_read_only) => {
+                if *program_read_only != read_only {
+                    return Err(Error::OtherString(format!(
+                        "\"{name}\" is read-only in only one of the program and target"
+                    )))
+                }
+                if read_only {
+                    target_eval.insert(name, EvalAwi::try_clone_from(p_external)?);
+                } else {
+                    target_lazy.insert(name, LazyAwi::try_clone_from(p_external, None)?);
+                }
+            }
+            None => {
+                if !read_only && name.starts_with(config_prefix) {
+                    let config = LazyAwi::try_clone_from(p_external, None)?;
+                    configurator.configurable(&config)?;
+                    configs.push((name, config));
+                }
+            }
+        }
+    }
+    let target_epoch = target_epoch.suspend();
+
+    let mut corresponder = Corresponder::new();
+    let mut program_lazy = vec![];
+    let mut program_eval = vec![];
+    let program_epoch = Epoch::from_ensemble(program);
+    for (name, p_external, read_only) in program_rnodes {
+        if read_only {
+            if let Some(target) = target_eval.get(&name) {
+                let program = EvalAwi::try_clone_from(p_external)?;
+                corresponder.correspond_eval(&program, target)?;
+                program_eval.push(program);
+            }
+        } else if let Some(target) = target_lazy.get(&name) {
+            let program = LazyAwi::try_clone_from(p_external, None)?;
+            corresponder.correspond_lazy(&program, target)?;
+            program_lazy.push(program);
+        }
+    }
+    let program_epoch = program_epoch.suspend();
+
+    let mut router = Router::new(&target_epoch, &configurator, &program_epoch, &corresponder)?;
+    router.route()?;
+    // `get_config` needs the target to be current
+    let (_target_epoch, res) = target_epoch.scope(|_| {
+        configs
+            .iter()
+            .map(|(name, config)| Ok((name.clone(), router.get_config(config)?)))
+            .collect()
+    });
+    res
+}
+
+/// Parses `ir` and writes it in `format`, which can be `slir` for the
+/// canonical form of the input, `stats` for a summary of the design, or
+/// `blif` for [Ensemble::write_blif] with a `top` model and `clk` clock
+pub fn export_ir(ir: &str, format: &str) -> Result<String, Error> {
+    let ensemble = Ensemble::parse_ir(ir)?;
+    match format {
+        "slir" => Ok(ensemble.write_ir()),
+        "stats" => {
+            let stats = SweepStats::from_ensemble(&ensemble, Delay::from(1), Delay::zero())?;
+            let mut res = String::new();
+            writeln!(res, "rnodes: {}", ensemble.notary.rnodes().len()).unwrap();
+            writeln!(res, "lnodes: {}", stats.num_lnodes).unwrap();
+            writeln!(res, "tnodes: {}", stats.num_tnodes).unwrap();
+            writeln!(res, "equivalences: {}", stats.num_equivs).unwrap();
+            writeln!(res, "depth: {}", stats.depth).unwrap();
+            Ok(res)
+        }
+        "blif" => ensemble.write_blif("top", "clk"),
+        _ => Err(Error::OtherString(format!(
+            "unknown export format \"{format}\", expected `slir`, `stats`, or `blif`"
+        ))),
+    }
+}
+
+fn read_file(path: &str) -> Result<String, Error> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::OtherString(format!("could not read \"{path}\": {e}")))
+}
+
+fn next_arg<I: Iterator<Item = String>>(args: &mut I, what: &str) -> Result<String, Error> {
+    args.next()
+        .ok_or_else(|| Error::OtherString(format!("expected {what}\n\n{USAGE}")))
+}
+
+/// Runs `starlight-cli` with `args`, which should not include the name of
+/// the binary. Returns the text that should be printed, which is empty if an
+/// `-o` output was written instead. See [USAGE] for the subcommands.
+pub fn run<I: IntoIterator<Item = String>>(args: I) -> Result<String, Error> {
+    let mut args = args.into_iter();
+    let subcommand = args.next().unwrap_or_else(|| "help".to_owned());
+    let mut inputs = vec![];
+    let mut output = None;
+    let mut format = "slir".to_owned();
+    let mut config_prefix = "config".to_owned();
+    let mut steps = vec![];
+    match subcommand.as_str() {
+        "help" | "-h" | "--help" => return Ok(USAGE.to_owned()),
+        "optimize" | "simulate" | "route" | "export" => (),
+        _ => {
+            return Err(Error::OtherString(format!(
+                "unknown subcommand \"{subcommand}\"\n\n{USAGE}"
+            )))
+        }
+    }
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(next_arg(&mut args, "a path after `-o`")?),
+            "--format" if subcommand == "export" => {
+                format = next_arg(&mut args, "a format after `--format`")?
+            }
+            "--config-prefix" if subcommand == "route" => {
+                config_prefix = next_arg(&mut args, "a prefix after `--config-prefix`")?
+            }
+            "--set" if subcommand == "simulate" => {
+                let assignment = next_arg(&mut args, "`<name>=<value>` after `--set`")?;
+                let (name, value) = assignment.split_once('=').ok_or_else(|| {
+                    Error::OtherString(format!("expected `<name>=<value>`, got \"{assignment}\""))
+                })?;
+                steps.push(SimulateStep::Set(name.to_owned(), value.to_owned()));
+            }
+            "--run" if subcommand == "simulate" => {
+                let delay = next_arg(&mut args, "a delay after `--run`")?;
+                let delay = delay.parse::<u128>().map_err(|_| {
+                    Error::OtherString(format!("could not parse delay \"{delay}\""))
+                })?;
+                steps.push(SimulateStep::Run(Delay::from(delay)));
+            }
+            "--print" if subcommand == "simulate" => {
+                steps.push(SimulateStep::Print(next_arg(
+                    &mut args,
+                    "a name after `--print`",
+                )?));
+            }
+            _ if arg.starts_with('-') => {
+                return Err(Error::OtherString(format!(
+                    "unexpected option \"{arg}\" for `{subcommand}`\n\n{USAGE}"
+                )))
+            }
+            _ => inputs.push(arg),
+        }
+    }
+    let num_inputs = if subcommand == "route" { 2 } else { 1 };
+    if inputs.len() != num_inputs {
+        return Err(Error::OtherString(format!(
+            "`{subcommand}` expected {num_inputs} input file(s)\n\n{USAGE}"
+        )))
+    }
+    let res = match subcommand.as_str() {
+        "optimize" => optimize_ir(&read_file(&inputs[0])?)?,
+        "simulate" => simulate_ir(&read_file(&inputs[0])?, &steps)?,
+        "route" => route_ir(
+            &read_file(&inputs[0])?,
+            &read_file(&inputs[1])?,
+            &config_prefix,
+        )?,
+        "export" if format == "svg" => {
+            #[cfg(feature = "debug")]
+            {
+                let out_dir = output.ok_or(Error::OtherStr(
+                    "`svg` export needs an output directory from `-o`",
+                ))?;
+                let ensemble = Ensemble::parse_ir(&read_file(&inputs[0])?)?;
+                ensemble.render_to_svgs_in_dir(std::path::PathBuf::from(out_dir))?;
+                return Ok(String::new())
+            }
+            #[cfg(not(feature = "debug"))]
+            {
+                return Err(Error::OtherStr(
+                    "`svg` export needs the \"debug\" feature to be enabled",
+                ))
+            }
+        }
+        _ => export_ir(&read_file(&inputs[0])?, &format)?,
+    };
+    if let Some(output) = output {
+        std::fs::write(&output, res)
+            .map_err(|e| Error::OtherString(format!("could not write \"{output}\": {e}")))?;
+        Ok(String::new())
+    } else {
+        Ok(res)
+    }
+}
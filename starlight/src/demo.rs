@@ -0,0 +1,381 @@
+//! A small facade for building and simulating circuits from other languages,
+//! meant for interactive demos. With the "wasm" feature enabled, [Circuit] is
+//! exported with `wasm_bindgen` so that it can be used from JavaScript after
+//! compiling to `wasm32-unknown-unknown`. Signals are referred to by `u32`
+//! handles, and values are strings in the format of `starlight-cli` (decimal,
+//! or with a `0x`, `0o`, or `0b` prefix). Errors become exceptions with the
+//! error message in JavaScript.
+//!
+//! ```
+//! use starlight::demo::Circuit;
+//!
+//! let mut circuit = Circuit::new();
+//! let a = circuit.input("a", 8).unwrap();
+//! let b = circuit.input("b", 8).unwrap();
+//! let sum = circuit.add(a, b).unwrap();
+//! circuit.output("sum", sum).unwrap();
+//! circuit.set("a", "0x10").unwrap();
+//! circuit.set("b", "7").unwrap();
+//! assert_eq!(circuit.get("sum").unwrap().as_deref(), Some("0x17"));
+//! ```
+//!
+//! The same in JavaScript:
+//!
+//! ```text
+//! const circuit = new Circuit();
+//! const sum = circuit.add(circuit.input("a", 8), circuit.input("b", 8));
+//! circuit.output("sum", sum);
+//! circuit.set("a", "0x10");
+//! circuit.set("b", "7");
+//! console.log(circuit.get("sum")); // "0x17"
+//! ```
+
+use std::num::NonZeroUsize;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::{awi, cli::parse_value, dag, Epoch, Error, EvalAwi, LazyAwi};
+
+#[cfg(feature = "wasm")]
+impl From<Error> for JsValue {
+    fn from(e: Error) -> Self {
+        JsValue::from_str(&e.to_string())
+    }
+}
+
+fn nzbw(w: u32) -> Result<NonZeroUsize, Error> {
+    usize::try_from(w)
+        .ok()
+        .and_then(NonZeroUsize::new)
+        .ok_or(Error::OtherStr("bitwidth cannot be zero"))
+}
+
+/// A circuit with its own detached `Epoch`, see the module level
+/// documentation. Because the `Epoch` is detached, any number of `Circuit`s
+/// can exist and be dropped in any order.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug)]
+pub struct Circuit {
+    epoch: Epoch,
+    /// Mimicking types do not keep their states from being pruned during
+    /// evaluation, so every wire has an `EvalAwi` keeping it alive. Wires are
+    /// set to `None` when they are invalidated by optimization.
+    wires: Vec<Option<(dag::Awi, EvalAwi)>>,
+    inputs: Vec<(String, LazyAwi)>,
+    outputs: Vec<(String, EvalAwi)>,
+}
+
+impl Drop for Circuit {
+    fn drop(&mut self) {
+        // the handles need to be dropped in their `Epoch`
+        self.epoch.scope(|_| {
+            self.wires.clear();
+            self.inputs.clear();
+            self.outputs.clear();
+        });
+    }
+}
+
+impl Default for Circuit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type Wires = [Option<(dag::Awi, EvalAwi)>];
+
+fn wire_(wires: &Wires, wire: u32) -> Result<&dag::Awi, Error> {
+    match usize::try_from(wire).ok().and_then(|i| wires.get(i)) {
+        Some(Some((awi, _))) => Ok(awi),
+        Some(None) => Err(Error::OtherString(format!(
+            "wire {wire} was invalidated by `Circuit::optimize`"
+        ))),
+        None => Err(Error::OtherString(format!("wire {wire} does not exist"))),
+    }
+}
+
+fn check_bw(a: &dag::Awi, b: &dag::Awi) -> Result<(), Error> {
+    if a.bw() != b.bw() {
+        return Err(Error::BitwidthMismatch(a.bw(), b.bw()))
+    }
+    Ok(())
+}
+
+impl Circuit {
+    /// Creates a new wire from `f`, which is called in the `Epoch` of the
+    /// circuit. Even getting the bitwidth of a `dag::Awi` needs the `Epoch`
+    /// to be current, so all wire accesses go through here.
+    fn build<F: FnOnce(&Wires) -> Result<dag::Awi, Error>>(&mut self, f: F) -> Result<u32, Error> {
+        let wire = self.epoch.scope(|_| {
+            f(&self.wires).map(|awi| {
+                let keepalive = EvalAwi::from(&awi);
+                (awi, keepalive)
+            })
+        })?;
+        self.wires.push(Some(wire));
+        Ok(u32::try_from(self.wires.len() - 1).unwrap())
+    }
+
+    /// Creates a new wire from `f` applied to a copy of wire `a`
+    fn unary<F: FnOnce(&mut dag::Awi) -> Result<(), Error>>(
+        &mut self,
+        a: u32,
+        f: F,
+    ) -> Result<u32, Error> {
+        self.build(|wires| {
+            let mut a = wire_(wires, a)?.clone();
+            f(&mut a).map(|_| a)
+        })
+    }
+
+    /// Creates a new wire from `f` applied to a copy of wire `a` and wire `b`,
+    /// which need to have the same bitwidth
+    fn binary<F: FnOnce(&mut dag::Awi, &dag::Awi)>(
+        &mut self,
+        a: u32,
+        b: u32,
+        f: F,
+    ) -> Result<u32, Error> {
+        self.build(|wires| {
+            let (mut a, b) = (wire_(wires, a)?.clone(), wire_(wires, b)?);
+            check_bw(&a, b)?;
+            f(&mut a, b);
+            Ok(a)
+        })
+    }
+
+    /// Creates a new single bit wire from the comparison `f` of wire `a` and
+    /// wire `b`
+    fn compare<F: FnOnce(&dag::Awi, &dag::Awi) -> dag::bool>(
+        &mut self,
+        a: u32,
+        b: u32,
+        f: F,
+    ) -> Result<u32, Error> {
+        self.build(|wires| {
+            let (a, b) = (wire_(wires, a)?, wire_(wires, b)?);
+            check_bw(a, b)?;
+            let mut res = dag::Awi::zero(awi::bw(1));
+            res.bool_(f(a, b));
+            Ok(res)
+        })
+    }
+
+    fn shift<F: FnOnce(&mut dag::Awi, usize)>(
+        &mut self,
+        a: u32,
+        s: u32,
+        f: F,
+    ) -> Result<u32, Error> {
+        self.unary(a, |a| match usize::try_from(s) {
+            Ok(s) if s < a.bw() => {
+                f(a, s);
+                Ok(())
+            }
+            _ => Err(Error::OtherString(format!(
+                "shift of {s} is not less than the bitwidth {}",
+                a.bw()
+            ))),
+        })
+    }
+
+    fn find_input(&self, name: &str) -> Result<&LazyAwi, Error> {
+        self.inputs
+            .iter()
+            .find(|(s, _)| s == name)
+            .map(|(_, lazy)| lazy)
+            .ok_or_else(|| Error::OtherString(format!("there is no input named \"{name}\"")))
+    }
+
+    fn find_output(&self, name: &str) -> Result<&EvalAwi, Error> {
+        self.outputs
+            .iter()
+            .find(|(s, _)| s == name)
+            .map(|(_, eval)| eval)
+            .ok_or_else(|| Error::OtherString(format!("there is no output named \"{name}\"")))
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Circuit {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> Self {
+        Self {
+            epoch: Epoch::new_detached(),
+            wires: vec![],
+            inputs: vec![],
+            outputs: vec![],
+        }
+    }
+
+    /// Creates a new input named `name` and returns its wire
+    pub fn input(&mut self, name: &str, width: u32) -> Result<u32, Error> {
+        if self.find_input(name).is_ok() {
+            return Err(Error::OtherString(format!(
+                "there is already an input named \"{name}\""
+            )))
+        }
+        let w = nzbw(width)?;
+        let mut lazy = None;
+        let res = self.build(|_| {
+            let tmp = LazyAwi::opaque(w);
+            tmp.set_debug_name(name)?;
+            Ok(dag::Awi::from(&*lazy.insert(tmp)))
+        });
+        if let Some(lazy) = lazy {
+            self.inputs.push((name.to_owned(), lazy));
+        }
+        res
+    }
+
+    /// Returns a wire with the constant `value`
+    pub fn constant(&mut self, value: &str, width: u32) -> Result<u32, Error> {
+        let value = parse_value(value, nzbw(width)?)?;
+        self.build(|_| Ok(dag::Awi::from(&value)))
+    }
+
+    /// Returns the bitwidth of `wire`
+    pub fn width(&self, wire: u32) -> Result<u32, Error> {
+        self.epoch
+            .scope(|_| wire_(&self.wires, wire).map(|w| u32::try_from(w.bw()).unwrap()))
+    }
+
+    pub fn not(&mut self, a: u32) -> Result<u32, Error> {
+        self.unary(a, |a| {
+            a.not_();
+            Ok(())
+        })
+    }
+
+    pub fn and(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.binary(a, b, |a, b| a.and_(b).unwrap())
+    }
+
+    pub fn or(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.binary(a, b, |a, b| a.or_(b).unwrap())
+    }
+
+    pub fn xor(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.binary(a, b, |a, b| a.xor_(b).unwrap())
+    }
+
+    pub fn add(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.binary(a, b, |a, b| a.add_(b).unwrap())
+    }
+
+    pub fn sub(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.binary(a, b, |a, b| a.sub_(b).unwrap())
+    }
+
+    /// Returns a single bit wire that is set if `a == b`
+    pub fn eq(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.compare(a, b, |a, b| a.const_eq(b).unwrap())
+    }
+
+    /// Returns a single bit wire that is set if `a < b` when unsigned
+    pub fn ult(&mut self, a: u32, b: u32) -> Result<u32, Error> {
+        self.compare(a, b, |a, b| a.ult(b).unwrap())
+    }
+
+    /// Returns a wire that is `b` if the single bit `cond` is set, otherwise
+    /// `a`
+    pub fn mux(&mut self, a: u32, b: u32, cond: u32) -> Result<u32, Error> {
+        self.build(|wires| {
+            let (mut a, b, cond) = (
+                wire_(wires, a)?.clone(),
+                wire_(wires, b)?,
+                wire_(wires, cond)?,
+            );
+            check_bw(&a, b)?;
+            if cond.bw() != 1 {
+                return Err(Error::BitwidthMismatch(1, cond.bw()))
+            }
+            a.mux_(b, cond.get(0).unwrap()).unwrap();
+            Ok(a)
+        })
+    }
+
+    pub fn shl(&mut self, a: u32, s: u32) -> Result<u32, Error> {
+        self.shift(a, s, |a, s| a.shl_(s).unwrap())
+    }
+
+    /// Logical right shift
+    pub fn lshr(&mut self, a: u32, s: u32) -> Result<u32, Error> {
+        self.shift(a, s, |a, s| a.lshr_(s).unwrap())
+    }
+
+    /// Returns `a` delayed by `delay`, see [crate::delay]
+    pub fn delay(&mut self, a: u32, delay: u32) -> Result<u32, Error> {
+        self.unary(a, |a| {
+            crate::delay(a, u128::from(delay));
+            Ok(())
+        })
+    }
+
+    /// Makes `wire` an output named `name`
+    pub fn output(&mut self, name: &str, wire: u32) -> Result<(), Error> {
+        if self.find_output(name).is_ok() {
+            return Err(Error::OtherString(format!(
+                "there is already an output named \"{name}\""
+            )))
+        }
+        let eval = self.epoch.scope(|_| {
+            let eval = EvalAwi::from(wire_(&self.wires, wire)?);
+            eval.set_debug_name(name).map(|_| eval)
+        })?;
+        self.outputs.push((name.to_owned(), eval));
+        Ok(())
+    }
+
+    /// Sets the input `name` to `value`
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let lazy = self.find_input(name)?;
+        self.epoch
+            .scope(|_| lazy.retro_(&parse_value(value, lazy.nzbw())?))
+    }
+
+    /// Returns the value of the output `name` in hexadecimal, or `None` if
+    /// it is not fully known
+    pub fn get(&self, name: &str) -> Result<Option<String>, Error> {
+        let eval = self.find_output(name)?;
+        match self.epoch.scope(|_| eval.eval()) {
+            Ok(value) => {
+                let hex = awi::Awi::bits_to_string_radix(&value, false, 16, false, 1).unwrap();
+                Ok(Some(format!("0x{hex}")))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Runs the simulation for `delay`
+    pub fn run(&mut self, delay: u32) -> Result<(), Error> {
+        self.epoch.scope(|epoch| epoch.run(u128::from(delay)))
+    }
+
+    /// Lowers and optimizes the circuit. This invalidates all existing wires,
+    /// but inputs and outputs still work and new wires can be created from
+    /// new inputs and constants.
+    pub fn optimize(&mut self) -> Result<(), Error> {
+        self.epoch.scope(|epoch| {
+            // drop the keepalives first so that intermediates can be optimized away
+            for wire in &mut self.wires {
+                *wire = None;
+            }
+            epoch.optimize()
+        })
+    }
+
+    /// Returns the number of lookup tables and other logic nodes, which is
+    /// most meaningful after [Circuit::optimize]
+    pub fn num_lnodes(&self) -> u32 {
+        self.epoch
+            .ensemble(|ensemble| u32::try_from(ensemble.lnodes.len()).unwrap())
+    }
+
+    /// Returns the circuit in the "slir" text format, which can be used with
+    /// `starlight-cli`
+    pub fn write_ir(&self) -> String {
+        self.epoch.ensemble(|ensemble| ensemble.write_ir())
+    }
+}
@@ -1,12 +1,55 @@
+mod approx;
+mod atpg;
+mod batch;
+mod bdd;
+mod blackbox;
+mod blif;
+mod cache;
+mod canonical;
+mod cell_map;
+mod coi;
 mod correspond;
+mod dead;
 #[cfg(feature = "debug")]
 mod debug;
+#[cfg(feature = "egraph")]
+mod egraph;
+mod flow;
+mod induction;
+mod ir;
+mod journal;
+mod latch;
+mod liberty;
+mod limits;
 mod lnode;
+mod lock;
+mod lut_table;
+mod macro_op;
 mod optimize;
+mod passes;
+mod pipeline;
+mod power;
+mod profile;
+mod provenance;
+mod query;
+mod randomize;
+mod reach;
+mod reg_state;
 #[cfg(feature = "debug")]
 pub mod render;
+mod replay;
+mod report;
+mod reset;
+mod rewrite;
 mod rnode;
+mod salvage;
+mod scan;
+#[cfg(feature = "smt")]
+mod smt;
+mod stable_id;
 mod state;
+mod subcircuit;
+mod tmr;
 mod tnode;
 mod together;
 mod value;
@@ -14,38 +57,77 @@ mod value;
 #[allow(unused)]
 use std::num::NonZeroU32;
 
+pub use approx::{ApproxConfig, ApproxReport, ErrorSpec, MAX_EXHAUSTIVE_APPROX_INPUTS};
 use awint::awint_dag::triple_arena::ptr_struct;
+pub use batch::{BatchSim, BATCH_LANES};
+pub use bdd::{Bdd, BddRef};
+pub use blackbox::BlackBox;
+pub use cache::{EvalCache, EvalCacheStats};
+pub use cell_map::{CellInstance, CellReport, MappedNetlist};
 pub use correspond::Corresponder;
-pub use lnode::{LNode, LNodeKind};
-pub use optimize::Optimizer;
+pub use dead::{Attribution, DeadReport};
+#[cfg(feature = "egraph")]
+pub use egraph::{EgraphConfig, EgraphReport};
+pub use flow::{FlowReport, FlowViolation};
+pub use induction::{Induction, InductionTrace};
+pub use journal::{Journal, JournalEntry, Snapshot};
+pub use latch::{CombinationalLoop, LoopKind};
+pub use liberty::{CellDef, CellFunction, CellLibrary};
+pub use limits::{Limits, PtrRequirements, PTR_CAPACITY, U32_PTR_CAPACITY};
+pub use lnode::{LNode, LNodeInputs, LNodeKind, LNODE_INLINE_INPUTS, MAX_UNCHUNKED_LUT_INPUTS};
+pub use lut_table::{LutInterner, LutTable, LutTableStats};
+pub use macro_op::{MacroOp, MulNode, RomNode};
+pub use optimize::{OptimizeSettings, Optimizer};
+pub use passes::{OptimizePass, OptimizeReport, PassReport};
+pub use power::{PowerModel, PowerReport};
+pub use profile::{Profile, MAX_FUSED_LUT_INPUTS};
+pub use provenance::Provenance;
+pub use query::NodeRef;
+pub use randomize::RandomizeReport;
+pub use reach::{ReachabilityReport, ReachableStates};
+pub use reg_state::RegState;
+pub use replay::{ReplayEntry, ReplayLog, Replayed};
+pub use report::{OutputCone, OutputReport};
+pub use reset::{ResetKind, ResetReport};
+pub use rewrite::{rewrite_rules, Pattern, RewriteOp, RewriteRule, REWRITE_RULES};
 pub use rnode::{Notary, PExternal, RNode};
-pub use state::{State, Stator};
+pub use salvage::LoweringReport;
+pub use scan::ScanReport;
+#[cfg(feature = "smt")]
+pub use smt::{SmtCommand, SmtCone, SmtResult, SmtSolver};
+pub use stable_id::StableIds;
+pub(crate) use state::{FusionKey, FusionOperand};
+pub use state::{State, Stator, MIN_SHARED_LITERAL_BW};
+pub use subcircuit::Match;
 pub use tnode::{Delay, Delayer, TNode};
 pub use together::{Ensemble, Equiv, Referent};
 pub use value::{
     BasicValue, BasicValueKind, ChangeKind, CommonValue, DynamicValue, EvalPhase, Evaluator, Event,
-    Value,
+    TieBreak, Value,
 };
 
 #[cfg(any(
     debug_assertions,
     all(feature = "gen_counters", not(feature = "u32_ptrs")),
 ))]
-ptr_struct!(PBack; PLNode; PTNode; PRNode);
+ptr_struct!(PBack; PLNode; PTNode; PRNode; PMulNode; PRomNode; PBlackBox);
 
 #[cfg(all(
     not(debug_assertions),
     not(feature = "gen_counters"),
     not(feature = "u32_ptrs"),
 ))]
-ptr_struct!(PBack(); PLNode(); PTNode(); PRNode());
+ptr_struct!(PBack(); PLNode(); PTNode(); PRNode(); PMulNode(); PRomNode(); PBlackBox());
 
 #[cfg(all(not(debug_assertions), feature = "gen_counters", feature = "u32_ptrs",))]
 ptr_struct!(
     PBack[NonZeroU32](NonZeroU32);
     PLNode[NonZeroU32](NonZeroU32);
     PTNode[NonZeroU32](NonZeroU32);
-    PRNode[NonZeroU32](NonZeroU32)
+    PRNode[NonZeroU32](NonZeroU32);
+    PMulNode[NonZeroU32](NonZeroU32);
+    PRomNode[NonZeroU32](NonZeroU32);
+    PBlackBox[NonZeroU32](NonZeroU32)
 );
 
 #[cfg(all(
@@ -53,7 +135,15 @@ ptr_struct!(
     not(feature = "gen_counters"),
     feature = "u32_ptrs",
 ))]
-ptr_struct!(PBack[NonZeroU32](); PLNode[NonZeroU32](); PTNode[NonZeroU32](); PRNode[NonZeroU32]());
+ptr_struct!(
+    PBack[NonZeroU32]();
+    PLNode[NonZeroU32]();
+    PTNode[NonZeroU32]();
+    PRNode[NonZeroU32]();
+    PMulNode[NonZeroU32]();
+    PRomNode[NonZeroU32]();
+    PBlackBox[NonZeroU32]()
+);
 
 // these are completely internal and so can always go without gen counters
 #[cfg(any(debug_assertions, not(feature = "u32_ptrs")))]
@@ -0,0 +1,414 @@
+use std::{collections::HashMap, fmt};
+
+use awint::{awint_dag::triple_arena::Advancer, Awi};
+
+use crate::{
+    ensemble::{
+        batch::eval_table_word, DynamicValue, Ensemble, LNode, LNodeKind, PBack, PExternal, PLNode,
+        Referent, Value,
+    },
+    utils::StarRng,
+    Error,
+};
+
+/// If there are at most this many free input bits, [Ensemble::approximate]
+/// simulates every combination of them and the reported errors are exact
+pub const MAX_EXHAUSTIVE_APPROX_INPUTS: usize = 16;
+
+/// The largest error that an output of [Ensemble::approximate] is allowed to
+/// have, as the absolute difference between the unsigned values of the exact
+/// and approximated outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorSpec {
+    /// The read only `RNode` of the output, such as that of an `EvalAwi`,
+    /// which can have at most 128 bits
+    pub p_external: PExternal,
+    pub max_error: u128,
+}
+
+/// Configuration for [Ensemble::approximate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApproxConfig {
+    /// The number of random input samples used if there are more than
+    /// [MAX_EXHAUSTIVE_APPROX_INPUTS] free input bits, rounded up to a
+    /// multiple of 64
+    pub num_samples: usize,
+    pub seed: u64,
+    /// The maximum number of minterm flips that are checked by simulation
+    pub max_trials: usize,
+}
+
+impl Default for ApproxConfig {
+    fn default() -> Self {
+        Self {
+            num_samples: 4096,
+            seed: 0,
+            max_trials: 10_000,
+        }
+    }
+}
+
+/// The result of [Ensemble::approximate]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApproxReport {
+    /// The number of lookup table entries that were flipped
+    pub flipped_minterms: usize,
+    /// The number of simulated input samples
+    pub num_samples: usize,
+    /// If every combination of the free input bits was simulated, in which
+    /// case `max_errors` are exact bounds instead of observed errors
+    pub exhaustive: bool,
+    /// The maximum error of each output in the order of the specs
+    pub max_errors: Vec<u128>,
+    pub lnodes_before: usize,
+    pub lnodes_after: usize,
+}
+
+impl fmt::Display for ApproxReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "flipped {} minterms, {} -> {} lnodes, {} {} samples",
+            self.flipped_minterms,
+            self.lnodes_before,
+            self.lnodes_after,
+            self.num_samples,
+            if self.exhaustive {
+                "exhaustive"
+            } else {
+                "random"
+            }
+        )?;
+        for (i, error) in self.max_errors.iter().enumerate() {
+            writeln!(f, "output {i}: max error {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Bit-parallel simulation of the combinational `LNode`s
+struct ApproxSim {
+    words: usize,
+    /// mask of the valid samples in the last word
+    last_mask: u64,
+    /// equivalence to index of its words in `vals`
+    inx: HashMap<PBack, usize>,
+    vals: Vec<u64>,
+    order: Vec<PLNode>,
+    /// the working tables of static LUTs
+    tables: HashMap<PLNode, Awi>,
+}
+
+impl ApproxSim {
+    fn word(&self, ensemble: &Ensemble, p_back: PBack, w: usize) -> u64 {
+        let p_equiv = ensemble.backrefs.get_val(p_back).unwrap().p_self_equiv;
+        self.vals[self.inx[&p_equiv] * self.words + w]
+    }
+
+    /// Simulates the `LNode`s starting from position `start` in the order
+    fn simulate(&mut self, ensemble: &Ensemble, start: usize) {
+        for pos in start..self.order.len() {
+            let p_lnode = self.order[pos];
+            let lnode = &ensemble.lnodes[p_lnode];
+            let p_equiv = ensemble
+                .backrefs
+                .get_val(lnode.p_self)
+                .unwrap()
+                .p_self_equiv;
+            if ensemble.backrefs.get_val(p_equiv).unwrap().val.is_const() {
+                continue
+            }
+            let out = self.inx[&p_equiv] * self.words;
+            for w in 0..self.words {
+                let res = match &lnode.kind {
+                    LNodeKind::Copy(inp) => self.word(ensemble, *inp, w),
+                    LNodeKind::Lut(inp, _) => {
+                        let inputs: Vec<u64> =
+                            inp.iter().map(|p| self.word(ensemble, *p, w)).collect();
+                        let table = &self.tables[&p_lnode];
+                        eval_table_word(
+                            |i| 0u64.wrapping_sub(table.get(i).unwrap() as u64),
+                            &inputs,
+                        )
+                    }
+                    LNodeKind::Carry(inp) => {
+                        let inputs: Vec<u64> =
+                            inp.iter().map(|p| self.word(ensemble, *p, w)).collect();
+                        let table = LNode::carry_lut();
+                        eval_table_word(
+                            |i| 0u64.wrapping_sub(table.get(i).unwrap() as u64),
+                            &inputs,
+                        )
+                    }
+                    LNodeKind::DynamicLut(inp, lut) => {
+                        let inputs: Vec<u64> =
+                            inp.iter().map(|p| self.word(ensemble, *p, w)).collect();
+                        eval_table_word(
+                            |i| match lut[i] {
+                                DynamicValue::ConstUnknown | DynamicValue::Const(false) => 0,
+                                DynamicValue::Const(true) => u64::MAX,
+                                DynamicValue::Dynam(p) => self.word(ensemble, p, w),
+                            },
+                            &inputs,
+                        )
+                    }
+                };
+                self.vals[out + w] = res;
+            }
+        }
+    }
+
+    /// Returns the number of samples that select entry `i` of the table of
+    /// `p_lnode`
+    fn minterm_count(&self, ensemble: &Ensemble, p_lnode: PLNode, i: usize) -> u64 {
+        let LNodeKind::Lut(ref inp, _) = ensemble.lnodes[p_lnode].kind else {
+            unreachable!()
+        };
+        let mut count = 0;
+        for w in 0..self.words {
+            let mut mask = if w + 1 == self.words {
+                self.last_mask
+            } else {
+                u64::MAX
+            };
+            for (j, p) in inp.iter().enumerate() {
+                let x = self.word(ensemble, *p, w);
+                mask &= if (i >> j) & 1 == 1 { x } else { !x };
+            }
+            count += u64::from(mask.count_ones());
+        }
+        count
+    }
+
+    /// Returns the unsigned values of `bits` for every sample
+    fn output_values(&self, bits: &[Option<usize>]) -> Vec<u128> {
+        let num = (self.words - 1) * 64 + (64 - self.last_mask.leading_zeros() as usize);
+        let mut res = vec![0u128; num];
+        for (j, bit) in bits.iter().enumerate() {
+            if let Some(inx) = bit {
+                for (s, val) in res.iter_mut().enumerate() {
+                    let word = self.vals[inx * self.words + (s / 64)];
+                    if (word >> (s % 64)) & 1 == 1 {
+                        *val |= 1 << j;
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Ensemble {
+    /// Approximate logic synthesis for error tolerant outputs. This flips
+    /// entries of the static lookup tables that are rarely or never selected
+    /// according to a bit-parallel simulation, as long as every output stays
+    /// within the error of its [ErrorSpec] on all the simulated samples, and
+    /// then optimizes to remove the simplified logic. Entries are flipped
+    /// toward the more common value of their table, in order of how rarely
+    /// they are selected, so that tables become constant or independent of
+    /// some inputs.
+    ///
+    /// The free inputs are all the non-constant equivalences not driven by an
+    /// `LNode`, such as the bits of `LazyAwi`s and the outputs of `TNode`s. If
+    /// there are at most [MAX_EXHAUSTIVE_APPROX_INPUTS] of them every
+    /// combination is simulated and the errors in the returned report are
+    /// exact bounds, otherwise `config.num_samples` random samples are used
+    /// and the errors are only those that were observed. This should be
+    /// called after [crate::Epoch::optimize].
+    ///
+    /// # Errors
+    ///
+    /// If a spec is not a lowered `RNode` or has more than 128 bits, or if
+    /// there is a combinational loop not broken by a `TNode`
+    pub fn approximate(
+        &mut self,
+        specs: &[ErrorSpec],
+        config: &ApproxConfig,
+    ) -> Result<ApproxReport, Error> {
+        self.journal_lnode_step("approximate");
+        // pending events may refer to `LNode`s that get removed
+        self.restart_request_phase()?;
+        let mut report = ApproxReport {
+            lnodes_before: self.lnodes.len(),
+            ..Default::default()
+        };
+        let order = self.topo_order()?;
+        let mut inx: HashMap<PBack, usize> = HashMap::new();
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                inx.insert(p_back, inx.len());
+            }
+        }
+        let mut driven = vec![false; inx.len()];
+        let mut tables = HashMap::new();
+        for p_lnode in &order {
+            let lnode = &self.lnodes[*p_lnode];
+            let p_equiv = self.backrefs.get_val(lnode.p_self).unwrap().p_self_equiv;
+            driven[inx[&p_equiv]] = true;
+            if let LNodeKind::Lut(_, ref table) = lnode.kind {
+                tables.insert(*p_lnode, (**table).clone());
+            }
+        }
+        let mut consts = vec![];
+        let mut free = vec![];
+        for (p_equiv, i) in &inx {
+            match self.backrefs.get_val(*p_equiv).unwrap().val {
+                Value::Const(b) => consts.push((*i, b)),
+                Value::ConstUnknown => consts.push((*i, false)),
+                Value::Unknown | Value::Dynam(_) => {
+                    if !driven[*i] {
+                        free.push(*i);
+                    }
+                }
+            }
+        }
+        // for determinism
+        free.sort_unstable();
+        report.exhaustive = free.len() <= MAX_EXHAUSTIVE_APPROX_INPUTS;
+        report.num_samples = if report.exhaustive {
+            1 << free.len()
+        } else {
+            config.num_samples.max(1).next_multiple_of(64)
+        };
+        let words = report.num_samples.div_ceil(64);
+        let last_mask = if report.num_samples.is_multiple_of(64) {
+            u64::MAX
+        } else {
+            (1u64 << (report.num_samples % 64)) - 1
+        };
+        let mut sim = ApproxSim {
+            words,
+            last_mask,
+            inx,
+            vals: vec![0; driven.len() * words],
+            order,
+            tables,
+        };
+        for (i, b) in consts {
+            for w in 0..words {
+                sim.vals[i * words + w] = if b { u64::MAX } else { 0 };
+            }
+        }
+        let mut rng = StarRng::new(config.seed);
+        for (k, i) in free.iter().enumerate() {
+            for w in 0..words {
+                sim.vals[i * words + w] = if report.exhaustive {
+                    // sample `s` has input `k` set to bit `k` of `s`
+                    let mut word = 0u64;
+                    for b in 0..64 {
+                        if (((w * 64 + b) >> k) & 1) == 1 {
+                            word |= 1 << b;
+                        }
+                    }
+                    word
+                } else {
+                    rng.next_u64()
+                };
+            }
+        }
+        sim.simulate(self, 0);
+
+        // the output bits and their exact values
+        let mut outputs = vec![];
+        for spec in specs {
+            let (_, rnode) = self.notary.get_rnode(spec.p_external)?;
+            if rnode.nzbw().get() > 128 {
+                return Err(Error::OtherString(format!(
+                    "`Ensemble::approximate`: output {} has more than 128 bits",
+                    spec.p_external
+                )))
+            }
+            let bits = rnode.bits().ok_or_else(|| {
+                Error::OtherString(format!(
+                    "`Ensemble::approximate`: output {} has not been lowered",
+                    spec.p_external
+                ))
+            })?;
+            let bits: Vec<Option<usize>> = bits
+                .iter()
+                .map(|bit| bit.map(|p| sim.inx[&self.backrefs.get_val(p).unwrap().p_self_equiv]))
+                .collect();
+            let exact = sim.output_values(&bits);
+            outputs.push((bits, exact, spec.max_error));
+        }
+        let errors = |sim: &ApproxSim| -> Option<Vec<u128>> {
+            let mut res = vec![];
+            for (bits, exact, max_error) in &outputs {
+                let mut max = 0;
+                for (x, y) in sim.output_values(bits).iter().zip(exact.iter()) {
+                    max = max.max(x.abs_diff(*y));
+                }
+                if max > *max_error {
+                    return None
+                }
+                res.push(max);
+            }
+            Some(res)
+        };
+
+        // the candidate flips in order of significance
+        let mut candidates = vec![];
+        let position: HashMap<PLNode, usize> = sim
+            .order
+            .iter()
+            .enumerate()
+            .map(|(pos, p)| (*p, pos))
+            .collect();
+        for (p_lnode, table) in &sim.tables {
+            let majority = table.count_ones() * 2 > table.bw();
+            for i in 0..table.bw() {
+                if table.get(i).unwrap() != majority {
+                    let count = sim.minterm_count(self, *p_lnode, i);
+                    candidates.push((count, position[p_lnode], i));
+                }
+            }
+        }
+        candidates.sort_unstable();
+        let mut trials = 0;
+        report.max_errors = vec![0; specs.len()];
+        for (_, pos, i) in candidates {
+            let p_lnode = sim.order[pos];
+            let table = sim.tables.get_mut(&p_lnode).unwrap();
+            let b = table.get(i).unwrap();
+            table.set(i, !b).unwrap();
+            if sim.minterm_count(self, p_lnode, i) == 0 {
+                // not observable on any sample
+                report.flipped_minterms += 1;
+                continue
+            }
+            if trials >= config.max_trials {
+                sim.tables.get_mut(&p_lnode).unwrap().set(i, b).unwrap();
+                continue
+            }
+            trials += 1;
+            let vals = sim.vals.clone();
+            sim.simulate(self, pos);
+            if let Some(errors) = errors(&sim) {
+                report.flipped_minterms += 1;
+                report.max_errors = errors;
+            } else {
+                sim.tables.get_mut(&p_lnode).unwrap().set(i, b).unwrap();
+                sim.vals = vals;
+            }
+        }
+
+        for (p_lnode, table) in sim.tables {
+            if let LNodeKind::Lut(ref inp, ref current) = self.lnodes[p_lnode].kind {
+                if **current != table {
+                    let kind = LNodeKind::Lut(inp.clone(), self.lut_tables.intern(table));
+                    let p_self = self.lnodes[p_lnode].p_self;
+                    let partial_ord_num = self
+                        .backrefs
+                        .get_val(p_self)
+                        .unwrap()
+                        .evaluator_partial_order;
+                    self.set_lnode_kind(p_lnode, kind, partial_ord_num);
+                }
+            }
+        }
+        self.optimize_all()?;
+        report.lnodes_after = self.lnodes.len();
+        Ok(report)
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{HashMap, HashSet};
+
+use awint::{awint_dag::triple_arena::Advancer, Awi, Bits};
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNode, LNodeKind, PBack, PExternal, PLNode, Referent},
+    Error,
+};
+
+/// Where the value of an equivalence in the searched cone comes from
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    /// A bit of a writable `RNode` that the search assigns
+    Var,
+    /// A value that the search cannot change
+    Fixed(Option<bool>),
+    /// Computed from the single static `LNode` driving it
+    LNode,
+}
+
+/// The result of checking the outputs against the desired value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Check {
+    Conflict,
+    Undecided,
+    Satisfied,
+}
+
+/// Evaluates a table with possibly unknown inputs, returning a known value
+/// only if all the entries that the known inputs can select agree
+fn eval_table<F: Fn(usize) -> Option<bool>>(entry: F, inputs: &[Option<bool>]) -> Option<bool> {
+    let mut known_mask = 0usize;
+    let mut known_bits = 0usize;
+    for (i, inp) in inputs.iter().enumerate() {
+        if let Some(b) = inp {
+            known_mask |= 1 << i;
+            if *b {
+                known_bits |= 1 << i;
+            }
+        }
+    }
+    let mut res = None;
+    for i in 0..(1usize << inputs.len()) {
+        if (i & known_mask) != known_bits {
+            continue
+        }
+        let b = entry(i)?;
+        match res {
+            None => res = Some(b),
+            Some(prev) if prev != b => return None,
+            Some(_) => (),
+        }
+    }
+    res
+}
+
+impl Ensemble {
+    /// Returns `true` if the equivalence of `p_equiv` has a writable `RNode`
+    fn has_writable_rnode(&self, p_equiv: PBack) -> bool {
+        let mut adv = self.backrefs.advancer_surject(p_equiv);
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisRNode(p_rnode) = *self.backrefs.get_key(p_back).unwrap() {
+                if !self.notary.rnodes().get(p_rnode).unwrap().1.read_only() {
+                    return true
+                }
+            }
+        }
+        false
+    }
+
+    /// Evaluates the `LNode` of `p_lnode` with three valued logic according
+    /// to the values of the equivalences in `values`
+    fn eval_lnode_three_valued(
+        &self,
+        p_lnode: PLNode,
+        values: &HashMap<PBack, Option<bool>>,
+    ) -> Option<bool> {
+        let value = |p_inp: PBack| values[&self.backrefs.get_val(p_inp).unwrap().p_self_equiv];
+        match self.lnodes[p_lnode].kind {
+            LNodeKind::Copy(p_inp) => value(p_inp),
+            LNodeKind::Lut(ref inp, ref lut) => {
+                let inputs: Vec<Option<bool>> = inp.iter().map(|p| value(*p)).collect();
+                eval_table(|i| Some(lut.get(i).unwrap()), &inputs)
+            }
+            LNodeKind::Carry(ref inp) => {
+                let lut = LNode::carry_lut();
+                let inputs: Vec<Option<bool>> = inp.iter().map(|p| value(*p)).collect();
+                eval_table(|i| Some(lut.get(i).unwrap()), &inputs)
+            }
+            LNodeKind::DynamicLut(ref inp, ref lut) => {
+                let inputs: Vec<Option<bool>> = inp.iter().map(|p| value(*p)).collect();
+                eval_table(
+                    |i| match lut[i] {
+                        DynamicValue::ConstUnknown => None,
+                        DynamicValue::Const(b) => Some(b),
+                        DynamicValue::Dynam(p) => value(p),
+                    },
+                    &inputs,
+                )
+            }
+        }
+    }
+
+    /// Searches for values of the writable `RNode`s (such as those of
+    /// `LazyAwi`s) that make the read only `RNode` of `p_external` (such as
+    /// that of an `EvalAwi`) evaluate to `desired`. This is a simple form of
+    /// test pattern generation that backtracks over the input bits in the
+    /// fan-in cone of the output, using three valued propagation through the
+    /// `LNode`s to detect conflicts and satisfied outputs early. The search is
+    /// combinational, everything else in the cone that cannot be assigned
+    /// (such as the outputs of `TNode`s) is held at its current value. The
+    /// search is complete but can take exponential time in the number of
+    /// input bits.
+    ///
+    /// Returns `None` if no assignment exists, otherwise the values of the
+    /// writable `RNode`s that have bits in the cone, in arena order. Bits that
+    /// do not matter are set to zero.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a lowered `RNode`, if the bitwidths do not
+    /// match, or if there is a combinational loop not broken by a `TNode`
+    pub fn find_input_for(
+        &mut self,
+        p_external: PExternal,
+        desired: &Bits,
+    ) -> Result<Option<Vec<(PExternal, Awi)>>, Error> {
+        let (_, rnode) = self.notary.get_rnode(p_external)?;
+        if rnode.nzbw() != desired.nzbw() {
+            return Err(Error::BitwidthMismatch(rnode.nzbw().get(), desired.bw()))
+        }
+        let Some(bits) = rnode.bits() else {
+            return Err(Error::OtherStr(
+                "`find_input_for` was called on an `RNode` that has not been lowered",
+            ))
+        };
+        let outputs: Vec<Option<PBack>> = bits
+            .iter()
+            .map(|p| p.map(|p| self.backrefs.get_val(p).unwrap().p_self_equiv))
+            .collect();
+
+        // classify the cone, the variables are decided in the order they are
+        // found from the output bits
+        let mut sources: HashMap<PBack, Source> = HashMap::new();
+        let mut vars: Vec<PBack> = vec![];
+        let mut cone_lnodes: HashSet<PLNode> = HashSet::new();
+        let mut front: Vec<PBack> = outputs.iter().rev().copied().flatten().collect();
+        while let Some(p_equiv) = front.pop() {
+            if sources.contains_key(&p_equiv) {
+                continue
+            }
+            let val = self.backrefs.get_val(p_equiv).unwrap().val;
+            let source = if val.is_const() {
+                Source::Fixed(val.known_value())
+            } else if self.has_writable_rnode(p_equiv) {
+                vars.push(p_equiv);
+                Source::Var
+            } else if let Some(p_lnode) = self.sole_lnode_driver(p_equiv) {
+                cone_lnodes.insert(p_lnode);
+                for p_inp in self.lnode_input_equivs(p_lnode).into_iter().rev() {
+                    if !sources.contains_key(&p_inp) {
+                        front.push(p_inp);
+                    }
+                }
+                Source::LNode
+            } else {
+                Source::Fixed(self.request_value(p_equiv)?.known_value())
+            };
+            sources.insert(p_equiv, source);
+        }
+        let order: Vec<PLNode> = self
+            .topo_order()?
+            .into_iter()
+            .filter(|p_lnode| cone_lnodes.contains(p_lnode))
+            .collect();
+
+        let mut values: HashMap<PBack, Option<bool>> = HashMap::with_capacity(sources.len());
+        for (p_equiv, source) in &sources {
+            if let Source::Fixed(b) = source {
+                values.insert(*p_equiv, *b);
+            }
+        }
+        let mut assignment: Vec<Option<bool>> = vec![None; vars.len()];
+        let mut check = |assignment: &[Option<bool>]| {
+            for (p_var, b) in vars.iter().zip(assignment.iter()) {
+                values.insert(*p_var, *b);
+            }
+            for p_lnode in order.iter().copied() {
+                let b = self.eval_lnode_three_valued(p_lnode, &values);
+                let p_equiv = self
+                    .backrefs
+                    .get_val(self.lnodes[p_lnode].p_self)
+                    .unwrap()
+                    .p_self_equiv;
+                values.insert(p_equiv, b);
+            }
+            let mut res = Check::Satisfied;
+            for (i, p_equiv) in outputs.iter().enumerate() {
+                match p_equiv.and_then(|p| values[&p]) {
+                    Some(b) if b != desired.get(i).unwrap() => return Check::Conflict,
+                    Some(_) => (),
+                    None => res = Check::Undecided,
+                }
+            }
+            res
+        };
+
+        // depth first search where the variables `0..decided` are assigned
+        let mut decided = 0;
+        'outer: loop {
+            match check(&assignment) {
+                Check::Satisfied => break,
+                Check::Undecided if decided < vars.len() => {
+                    assignment[decided] = Some(false);
+                    decided += 1;
+                    continue
+                }
+                Check::Undecided | Check::Conflict => (),
+            }
+            // backtrack to the last decision that has not tried `true`
+            while decided > 0 {
+                decided -= 1;
+                if assignment[decided] == Some(false) {
+                    assignment[decided] = Some(true);
+                    decided += 1;
+                    continue 'outer
+                }
+                assignment[decided] = None;
+            }
+            return Ok(None)
+        }
+
+        let var_values: HashMap<PBack, bool> = vars
+            .iter()
+            .zip(assignment.iter())
+            .map(|(p_var, b)| (*p_var, b.unwrap_or(false)))
+            .collect();
+        let mut res = vec![];
+        for (_, p_input_external, input) in self.notary.rnodes() {
+            if input.read_only() {
+                continue
+            }
+            let Some(input_bits) = input.bits() else {
+                continue
+            };
+            let mut awi = Awi::zero(input.nzbw());
+            let mut in_cone = false;
+            for (i, p_bit) in input_bits.iter().enumerate() {
+                if let Some(p_bit) = p_bit {
+                    let p_equiv = self.backrefs.get_val(*p_bit).unwrap().p_self_equiv;
+                    if let Some(b) = var_values.get(&p_equiv) {
+                        awi.set(i, *b).unwrap();
+                        in_cone = true;
+                    }
+                }
+            }
+            if in_cone {
+                res.push((*p_input_external, awi));
+            }
+        }
+        Ok(Some(res))
+    }
+}
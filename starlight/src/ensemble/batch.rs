@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+
+use awint::{awint_dag::triple_arena::Advancer, Awi, Bits};
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNode, LNodeKind, PBack, PExternal, Profile, Referent},
+    utils::StarRng,
+    Error,
+};
+
+/// The number of independent lanes that a [BatchSim] evaluates at once
+pub const BATCH_LANES: usize = 64;
+
+/// Evaluates a table on bit-parallel words, where `entry` returns the word of
+/// each entry, by successively multiplexing on the most significant input
+pub(crate) fn eval_table_word<F: Fn(usize) -> u64>(entry: F, inputs: &[u64]) -> u64 {
+    let mut t: Vec<u64> = (0..(1usize << inputs.len())).map(entry).collect();
+    for j in (0..inputs.len()).rev() {
+        let half = 1usize << j;
+        for i in 0..half {
+            t[i] = (!inputs[j] & t[i]) | (inputs[j] & t[i + half]);
+        }
+    }
+    t[0]
+}
+
+/// An entry of a dynamic table in a [BatchOp]
+#[derive(Debug, Clone, Copy)]
+enum BatchEntry {
+    Const(u64),
+    Equiv(usize),
+}
+
+/// A compiled `LNode`, with equivalences as indexes into the words
+#[derive(Debug, Clone)]
+enum BatchOp {
+    Copy(usize, usize),
+    /// The table entries are stored as words of all zeros or all ones
+    Lut(usize, Vec<usize>, Vec<u64>),
+    DynamicLut(usize, Vec<usize>, Vec<BatchEntry>),
+}
+
+/// Bit-parallel batch simulation of an `Ensemble`, from
+/// [Ensemble::batch_sim]. Every equivalence is stored as a word with one bit
+/// for each of [BATCH_LANES] independent lanes, and lookup tables are
+/// evaluated with bitwise operations on all the lanes at once, which is much
+/// faster than running the event driven evaluator once per input vector for
+/// fuzzing, coverage, and switching activity collection.
+///
+/// The simulation is compiled from a snapshot of the `Ensemble` and is
+/// independent of it afterwards. A `TNode` with a delay of 1 is a register
+/// which [BatchSim::step] updates like [Epoch::run](crate::Epoch::run) with a
+/// delay of 1. `TNode`s with larger delays sample their drivers at the time
+/// they fire in the event driven evaluator, which depends on when the drivers
+/// changed and cannot be modeled per lane, so they are not supported. All
+/// lanes start with the current values of the `Ensemble`, with unknown values
+/// being zero.
+#[derive(Debug, Clone)]
+pub struct BatchSim {
+    vals: Vec<u64>,
+    /// the values after the last evaluation, for counting toggles
+    prev: Vec<u64>,
+    toggles: Vec<u64>,
+    /// the [Ensemble::stable_ids] of the equivalences, for profiles
+    ids: Vec<u64>,
+    ops: Vec<BatchOp>,
+    /// zero delay `TNode`s, as pairs of the driver and output
+    wires: Vec<(usize, usize)>,
+    /// `TNode`s with a delay of 1, as pairs of the driver and output
+    registers: Vec<(usize, usize)>,
+    rnodes: HashMap<PExternal, (bool, Vec<Option<usize>>)>,
+}
+
+impl Ensemble {
+    /// Compiles a [BatchSim] of `self`. This should be used after
+    /// [Epoch::optimize](crate::Epoch::optimize) so that the `LNode`s are
+    /// few and every `RNode` is lowered.
+    ///
+    /// # Errors
+    ///
+    /// If there is a combinational loop not broken by a `TNode`, or if there
+    /// is a `TNode` with a delay larger than 1
+    pub fn batch_sim(&self) -> Result<BatchSim, Error> {
+        let stable_ids = self.stable_ids()?;
+        let mut inx: HashMap<PBack, usize> = HashMap::new();
+        let mut ids = vec![];
+        let mut vals = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                inx.insert(p_back, ids.len());
+                ids.push(stable_ids.equiv(p_back).unwrap());
+                let val = self.backrefs.get_val(p_back).unwrap().val;
+                vals.push(if val.known_value() == Some(true) {
+                    u64::MAX
+                } else {
+                    0
+                });
+            }
+        }
+        let e = |p_back: PBack| inx[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        let table_words = |table: &Bits| -> Vec<u64> {
+            (0..table.bw())
+                .map(|i| 0u64.wrapping_sub(u64::from(table.get(i).unwrap())))
+                .collect()
+        };
+        let mut ops = vec![];
+        for p_lnode in self.topo_order()? {
+            let lnode = &self.lnodes[p_lnode];
+            let out = e(lnode.p_self);
+            // constants are already set
+            if self.backrefs.get_val(lnode.p_self).unwrap().val.is_const() {
+                continue
+            }
+            ops.push(match lnode.kind {
+                LNodeKind::Copy(inp) => BatchOp::Copy(out, e(inp)),
+                LNodeKind::Lut(ref inp, ref table) => {
+                    BatchOp::Lut(out, inp.iter().map(|p| e(*p)).collect(), table_words(table))
+                }
+                LNodeKind::Carry(ref inp) => BatchOp::Lut(
+                    out,
+                    inp.iter().map(|p| e(*p)).collect(),
+                    table_words(&LNode::carry_lut()),
+                ),
+                LNodeKind::DynamicLut(ref inp, ref lut) => BatchOp::DynamicLut(
+                    out,
+                    inp.iter().map(|p| e(*p)).collect(),
+                    lut.iter()
+                        .map(|entry| match entry {
+                            DynamicValue::ConstUnknown | DynamicValue::Const(false) => {
+                                BatchEntry::Const(0)
+                            }
+                            DynamicValue::Const(true) => BatchEntry::Const(u64::MAX),
+                            DynamicValue::Dynam(p) => BatchEntry::Equiv(e(*p)),
+                        })
+                        .collect(),
+                ),
+            });
+        }
+        let mut wires = vec![];
+        let mut registers = vec![];
+        for tnode in self.tnodes.vals() {
+            let (driver, out) = (e(tnode.p_driver), e(tnode.p_self));
+            if self.backrefs.get_val(tnode.p_self).unwrap().val.is_const() {
+                continue
+            }
+            match tnode.delay().amount() {
+                0 => wires.push((driver, out)),
+                1 => registers.push((driver, out)),
+                delay => {
+                    return Err(Error::OtherString(format!(
+                        "`Ensemble::batch_sim` found a `TNode` with delay {delay}, only delays of \
+                         0 and 1 are supported"
+                    )))
+                }
+            }
+        }
+        let mut rnodes = HashMap::new();
+        for (_, p_external, rnode) in self.notary.rnodes() {
+            let bits = rnode
+                .bits()
+                .map(|bits| bits.iter().map(|bit| bit.map(e)).collect())
+                .unwrap_or_else(|| vec![None; rnode.nzbw().get()]);
+            rnodes.insert(*p_external, (rnode.read_only(), bits));
+        }
+        let mut res = BatchSim {
+            prev: vec![],
+            toggles: vec![0; vals.len()],
+            vals,
+            ids,
+            ops,
+            wires,
+            registers,
+            rnodes,
+        };
+        res.evaluate();
+        res.toggles.fill(0);
+        Ok(res)
+    }
+}
+
+impl BatchSim {
+    fn bits(&self, p_external: PExternal) -> Result<&[Option<usize>], Error> {
+        self.rnodes
+            .get(&p_external)
+            .map(|(_, bits)| bits.as_slice())
+            .ok_or(Error::InvalidPExternal(p_external))
+    }
+
+    fn writable_bits(&self, p_external: PExternal, bw: usize) -> Result<Vec<Option<usize>>, Error> {
+        let (read_only, bits) = self
+            .rnodes
+            .get(&p_external)
+            .ok_or(Error::InvalidPExternal(p_external))?;
+        if *read_only {
+            return Err(Error::OtherStr(
+                "`BatchSim` can only set writable `RNode`s, such as those of `LazyAwi`s",
+            ))
+        }
+        if bits.len() != bw {
+            return Err(Error::BitwidthMismatch(bits.len(), bw))
+        }
+        Ok(bits.clone())
+    }
+
+    /// Sets the value of the writable `RNode` of `p_external` (such as that
+    /// of a `LazyAwi`) in `lane`. This takes effect at the next
+    /// [BatchSim::evaluate] or [BatchSim::step].
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a writable `RNode` of the simulated
+    /// `Ensemble`, if `lane` is not less than [BATCH_LANES], or if the
+    /// bitwidths do not match
+    pub fn set(&mut self, p_external: PExternal, lane: usize, bits: &Bits) -> Result<(), Error> {
+        if lane >= BATCH_LANES {
+            return Err(Error::OtherString(format!(
+                "`BatchSim::set`: lane {lane} is not less than {BATCH_LANES}"
+            )))
+        }
+        for (j, bit) in self
+            .writable_bits(p_external, bits.bw())?
+            .iter()
+            .enumerate()
+        {
+            if let Some(inx) = bit {
+                let mask = 1u64 << lane;
+                if bits.get(j).unwrap() {
+                    self.vals[*inx] |= mask;
+                } else {
+                    self.vals[*inx] &= !mask;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the writable `RNode` of `p_external` in all lanes at once, where
+    /// `words[j]` has bit `lane` set if bit `j` is set in `lane`
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a writable `RNode` of the simulated `Ensemble`
+    /// or if `words` does not have a word for every bit
+    pub fn set_words(&mut self, p_external: PExternal, words: &[u64]) -> Result<(), Error> {
+        for (bit, word) in self
+            .writable_bits(p_external, words.len())?
+            .iter()
+            .zip(words)
+        {
+            if let Some(inx) = bit {
+                self.vals[*inx] = *word;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the writable `RNode` of `p_external` to random values in all
+    /// lanes
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a writable `RNode` of the simulated `Ensemble`
+    pub fn randomize(&mut self, p_external: PExternal, rng: &mut StarRng) -> Result<(), Error> {
+        let len = self.bits(p_external)?.len();
+        let words: Vec<u64> = (0..len).map(|_| rng.next_u64()).collect();
+        self.set_words(p_external, &words)
+    }
+
+    /// Returns the value of the `RNode` of `p_external` (such as that of an
+    /// `EvalAwi`) in `lane`. Bits that were not lowered are zero.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not an `RNode` of the simulated `Ensemble` or if
+    /// `lane` is not less than [BATCH_LANES]
+    pub fn get(&self, p_external: PExternal, lane: usize) -> Result<Awi, Error> {
+        if lane >= BATCH_LANES {
+            return Err(Error::OtherString(format!(
+                "`BatchSim::get`: lane {lane} is not less than {BATCH_LANES}"
+            )))
+        }
+        let words = self.get_words(p_external)?;
+        let mut res = Awi::zero(std::num::NonZeroUsize::new(words.len()).unwrap());
+        for (j, word) in words.iter().enumerate() {
+            res.set(j, ((word >> lane) & 1) != 0).unwrap();
+        }
+        Ok(res)
+    }
+
+    /// Returns the value of the `RNode` of `p_external` in all lanes, with a
+    /// word for every bit like in [BatchSim::set_words]
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not an `RNode` of the simulated `Ensemble`
+    pub fn get_words(&self, p_external: PExternal) -> Result<Vec<u64>, Error> {
+        Ok(self
+            .bits(p_external)?
+            .iter()
+            .map(|bit| bit.map(|inx| self.vals[inx]).unwrap_or(0))
+            .collect())
+    }
+
+    /// Evaluates all the `LNode`s and zero delay `TNode`s, and records the
+    /// toggles since the last evaluation
+    pub fn evaluate(&mut self) {
+        loop {
+            for op in &self.ops {
+                let vals = &self.vals;
+                let (out, res) = match op {
+                    BatchOp::Copy(out, inp) => (*out, vals[*inp]),
+                    BatchOp::Lut(out, inp, table) => {
+                        let inputs: Vec<u64> = inp.iter().map(|i| vals[*i]).collect();
+                        (*out, eval_table_word(|i| table[i], &inputs))
+                    }
+                    BatchOp::DynamicLut(out, inp, lut) => {
+                        let inputs: Vec<u64> = inp.iter().map(|i| vals[*i]).collect();
+                        let res = eval_table_word(
+                            |i| match lut[i] {
+                                BatchEntry::Const(word) => word,
+                                BatchEntry::Equiv(inx) => vals[inx],
+                            },
+                            &inputs,
+                        );
+                        (*out, res)
+                    }
+                };
+                self.vals[out] = res;
+            }
+            // the `LNode` order does not include zero delay `TNode`s, so they are
+            // settled by repeating
+            let mut changed = false;
+            for (driver, out) in &self.wires {
+                if self.vals[*out] != self.vals[*driver] {
+                    self.vals[*out] = self.vals[*driver];
+                    changed = true;
+                }
+            }
+            if !changed {
+                break
+            }
+        }
+        if self.prev.len() == self.vals.len() {
+            for (toggles, (prev, val)) in self
+                .toggles
+                .iter_mut()
+                .zip(self.prev.iter().zip(self.vals.iter()))
+            {
+                *toggles = toggles.saturating_add(u64::from((prev ^ val).count_ones()));
+            }
+            self.prev.copy_from_slice(&self.vals);
+        } else {
+            self.prev.clone_from(&self.vals);
+        }
+    }
+
+    /// Advances time by one unit of delay, updating every register, and then
+    /// evaluates. The inputs to the registers are sampled from the last
+    /// evaluation before any are changed.
+    pub fn step(&mut self) {
+        let sampled: Vec<u64> = self
+            .registers
+            .iter()
+            .map(|(driver, _)| self.vals[*driver])
+            .collect();
+        for ((_, out), val) in self.registers.iter().zip(sampled) {
+            self.vals[*out] = val;
+        }
+        self.evaluate();
+    }
+
+    /// Returns the number of toggles of every equivalence summed over all
+    /// lanes as a [Profile], which can be used with
+    /// [Epoch::estimate_power](crate::Epoch::estimate_power) and profile
+    /// guided optimizations of the `Ensemble` that `self` was compiled from.
+    pub fn profile(&self) -> Profile {
+        Profile::from_id_toggles(
+            self.ids
+                .iter()
+                .copied()
+                .zip(self.toggles.iter().copied())
+                .filter(|(_, toggles)| *toggles != 0),
+        )
+    }
+
+    /// Clears the toggles recorded for [BatchSim::profile]
+    pub fn clear_toggles(&mut self) {
+        self.toggles.fill(0);
+    }
+}
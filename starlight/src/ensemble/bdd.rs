@@ -0,0 +1,652 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+};
+
+use awint::{awint_dag::triple_arena::Advancer, Awi, Bits};
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNode, LNodeKind, PBack, PLNode, Referent, Value},
+    Error,
+};
+
+/// A reference to a node in a [Bdd]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BddRef(u32);
+
+impl BddRef {
+    pub const FALSE: Self = Self(0);
+    pub const TRUE: Self = Self(1);
+
+    pub fn is_const(self) -> bool {
+        self.0 < 2
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+struct BddNode {
+    var: u32,
+    lo: BddRef,
+    hi: BddRef,
+}
+
+/// A small reduced ordered binary decision diagram manager. Nodes are hash
+/// consed, so two `BddRef`s from the same `Bdd` are equal if and only if they
+/// represent the same function. Variables with smaller indexes are closer to
+/// the root. This is used for functional analysis of lookup tables and of
+/// moderately sized cones of `LNode`s, where truth tables would be too large.
+#[derive(Debug, Clone)]
+pub struct Bdd {
+    nodes: Vec<BddNode>,
+    unique: HashMap<BddNode, BddRef>,
+    ite_cache: HashMap<(BddRef, BddRef, BddRef), BddRef>,
+}
+
+impl Bdd {
+    pub fn new() -> Self {
+        let terminal = BddNode {
+            var: u32::MAX,
+            lo: BddRef::FALSE,
+            hi: BddRef::FALSE,
+        };
+        Self {
+            nodes: vec![terminal, terminal],
+            unique: HashMap::new(),
+            ite_cache: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of nodes, including the two terminals
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() <= 2
+    }
+
+    pub fn constant(&self, b: bool) -> BddRef {
+        if b {
+            BddRef::TRUE
+        } else {
+            BddRef::FALSE
+        }
+    }
+
+    /// Returns the function that is the value of variable `var`
+    pub fn var(&mut self, var: usize) -> BddRef {
+        self.mk(u32::try_from(var).unwrap(), BddRef::FALSE, BddRef::TRUE)
+    }
+
+    fn mk(&mut self, var: u32, lo: BddRef, hi: BddRef) -> BddRef {
+        if lo == hi {
+            return lo
+        }
+        let node = BddNode { var, lo, hi };
+        if let Some(f) = self.unique.get(&node) {
+            return *f
+        }
+        let f = BddRef(u32::try_from(self.nodes.len()).unwrap());
+        self.nodes.push(node);
+        self.unique.insert(node, f);
+        f
+    }
+
+    /// Returns the top variable of `f`, or `None` if `f` is constant
+    pub fn top_var(&self, f: BddRef) -> Option<usize> {
+        if f.is_const() {
+            None
+        } else {
+            Some(self.nodes[f.0 as usize].var as usize)
+        }
+    }
+
+    /// Returns the negative and positive cofactors of `f` with respect to
+    /// `var`, which must not be below the top variable of `f`
+    fn top_cofactors(&self, f: BddRef, var: u32) -> (BddRef, BddRef) {
+        let node = self.nodes[f.0 as usize];
+        if !f.is_const() && (node.var == var) {
+            (node.lo, node.hi)
+        } else {
+            (f, f)
+        }
+    }
+
+    /// If-then-else, returns `(f & g) | (!f & h)`
+    pub fn ite(&mut self, f: BddRef, g: BddRef, h: BddRef) -> BddRef {
+        if f == BddRef::TRUE {
+            return g
+        }
+        if f == BddRef::FALSE {
+            return h
+        }
+        if g == h {
+            return g
+        }
+        if (g == BddRef::TRUE) && (h == BddRef::FALSE) {
+            return f
+        }
+        if let Some(res) = self.ite_cache.get(&(f, g, h)) {
+            return *res
+        }
+        let var = [f, g, h]
+            .iter()
+            .filter(|x| !x.is_const())
+            .map(|x| self.nodes[x.0 as usize].var)
+            .min()
+            .unwrap();
+        let (f0, f1) = self.top_cofactors(f, var);
+        let (g0, g1) = self.top_cofactors(g, var);
+        let (h0, h1) = self.top_cofactors(h, var);
+        let lo = self.ite(f0, g0, h0);
+        let hi = self.ite(f1, g1, h1);
+        let res = self.mk(var, lo, hi);
+        self.ite_cache.insert((f, g, h), res);
+        res
+    }
+
+    pub fn not(&mut self, f: BddRef) -> BddRef {
+        self.ite(f, BddRef::FALSE, BddRef::TRUE)
+    }
+
+    pub fn and(&mut self, f: BddRef, g: BddRef) -> BddRef {
+        self.ite(f, g, BddRef::FALSE)
+    }
+
+    pub fn or(&mut self, f: BddRef, g: BddRef) -> BddRef {
+        self.ite(f, BddRef::TRUE, g)
+    }
+
+    pub fn xor(&mut self, f: BddRef, g: BddRef) -> BddRef {
+        let not_g = self.not(g);
+        self.ite(f, not_g, g)
+    }
+
+    /// Returns `f` with `var` fixed to `b`
+    pub fn restrict(&mut self, f: BddRef, var: usize, b: bool) -> BddRef {
+        let var = u32::try_from(var).unwrap();
+        let mut memo = HashMap::new();
+        self.restrict_impl(f, var, b, &mut memo)
+    }
+
+    fn restrict_impl(
+        &mut self,
+        f: BddRef,
+        var: u32,
+        b: bool,
+        memo: &mut HashMap<BddRef, BddRef>,
+    ) -> BddRef {
+        let node = self.nodes[f.0 as usize];
+        if f.is_const() || (node.var > var) {
+            return f
+        }
+        if node.var == var {
+            return if b { node.hi } else { node.lo }
+        }
+        if let Some(res) = memo.get(&f) {
+            return *res
+        }
+        let lo = self.restrict_impl(node.lo, var, b, memo);
+        let hi = self.restrict_impl(node.hi, var, b, memo);
+        let res = self.mk(node.var, lo, hi);
+        memo.insert(f, res);
+        res
+    }
+
+    /// Returns `f` with the variables `vars` existentially quantified, which
+    /// is the function that is true wherever `f` is true for some assignment
+    /// of `vars`
+    pub fn exists(&mut self, f: BddRef, vars: &[usize]) -> BddRef {
+        let vars: HashSet<u32> = vars.iter().map(|v| u32::try_from(*v).unwrap()).collect();
+        let mut memo = HashMap::new();
+        self.exists_impl(f, &vars, &mut memo)
+    }
+
+    fn exists_impl(
+        &mut self,
+        f: BddRef,
+        vars: &HashSet<u32>,
+        memo: &mut HashMap<BddRef, BddRef>,
+    ) -> BddRef {
+        if f.is_const() {
+            return f
+        }
+        if let Some(res) = memo.get(&f) {
+            return *res
+        }
+        let node = self.nodes[f.0 as usize];
+        let lo = self.exists_impl(node.lo, vars, memo);
+        let hi = self.exists_impl(node.hi, vars, memo);
+        let res = if vars.contains(&node.var) {
+            self.or(lo, hi)
+        } else {
+            let var = self.var(node.var as usize);
+            self.ite(var, hi, lo)
+        };
+        memo.insert(f, res);
+        res
+    }
+
+    /// Returns `f` with every variable `v` in `map` replaced by the variable
+    /// `map[v]`. The new variables may be in any order relative to the old
+    /// ones.
+    pub fn rename(&mut self, f: BddRef, map: &HashMap<usize, usize>) -> BddRef {
+        let mut memo = HashMap::new();
+        self.rename_impl(f, map, &mut memo)
+    }
+
+    fn rename_impl(
+        &mut self,
+        f: BddRef,
+        map: &HashMap<usize, usize>,
+        memo: &mut HashMap<BddRef, BddRef>,
+    ) -> BddRef {
+        if f.is_const() {
+            return f
+        }
+        if let Some(res) = memo.get(&f) {
+            return *res
+        }
+        let node = self.nodes[f.0 as usize];
+        let lo = self.rename_impl(node.lo, map, memo);
+        let hi = self.rename_impl(node.hi, map, memo);
+        let var = node.var as usize;
+        let var = self.var(map.get(&var).copied().unwrap_or(var));
+        let res = self.ite(var, hi, lo);
+        memo.insert(f, res);
+        res
+    }
+
+    /// Returns the number of assignments of `vars` for which `f` is true.
+    /// Panics if `f` depends on variables not in `vars`.
+    pub fn sat_count(&self, f: BddRef, vars: &[usize]) -> f64 {
+        let mut vars: Vec<u32> = vars.iter().map(|v| u32::try_from(*v).unwrap()).collect();
+        vars.sort_unstable();
+        vars.dedup();
+        let pos = |f: BddRef| -> usize {
+            if f.is_const() {
+                vars.len()
+            } else {
+                vars.binary_search(&self.nodes[f.0 as usize].var)
+                    .expect("`f` depends on a variable not in `vars`")
+            }
+        };
+        // the number of satisfying assignments of the variables at and below
+        // the top variable of each node
+        let mut memo: HashMap<BddRef, f64> = HashMap::new();
+        let mut stack = vec![(f, false)];
+        while let Some((g, expanded)) = stack.pop() {
+            if g.is_const() || memo.contains_key(&g) {
+                continue
+            }
+            let node = self.nodes[g.0 as usize];
+            if !expanded {
+                stack.push((g, true));
+                stack.push((node.lo, false));
+                stack.push((node.hi, false));
+                continue
+            }
+            let count = |child: BddRef| -> f64 {
+                let c = match child {
+                    BddRef::FALSE => 0.0,
+                    BddRef::TRUE => 1.0,
+                    _ => memo[&child],
+                };
+                c * 2f64.powi(i32::try_from(pos(child) - pos(g) - 1).unwrap())
+            };
+            let res = count(node.lo) + count(node.hi);
+            memo.insert(g, res);
+        }
+        let c = match f {
+            BddRef::FALSE => 0.0,
+            BddRef::TRUE => 1.0,
+            _ => memo[&f],
+        };
+        c * 2f64.powi(i32::try_from(pos(f)).unwrap())
+    }
+
+    /// Returns the function of the static `lut` with the functions `inputs`
+    /// as its inputs. Panics if the number of inputs does not match the table.
+    pub fn apply_lut(&mut self, lut: &Bits, inputs: &[BddRef]) -> BddRef {
+        assert_eq!(lut.bw(), 1 << inputs.len());
+        self.apply_lut_impl(lut, 0, inputs)
+    }
+
+    fn apply_lut_impl(&mut self, lut: &Bits, offset: usize, inputs: &[BddRef]) -> BddRef {
+        if let Some((last, rest)) = inputs.split_last() {
+            let half = 1 << rest.len();
+            let lo = self.apply_lut_impl(lut, offset, rest);
+            let hi = self.apply_lut_impl(lut, offset + half, rest);
+            self.ite(*last, hi, lo)
+        } else {
+            self.constant(lut.get(offset).unwrap())
+        }
+    }
+
+    /// Returns the function of a dynamic lookup table with the functions
+    /// `entries` as its entries and `inputs` as its index inputs. Panics if
+    /// the number of inputs does not match the number of entries.
+    pub fn apply_dynamic_lut(&mut self, entries: &[BddRef], inputs: &[BddRef]) -> BddRef {
+        assert_eq!(entries.len(), 1 << inputs.len());
+        if let Some((last, rest)) = inputs.split_last() {
+            let (lo, hi) = entries.split_at(entries.len() / 2);
+            let lo = self.apply_dynamic_lut(lo, rest);
+            let hi = self.apply_dynamic_lut(hi, rest);
+            self.ite(*last, hi, lo)
+        } else {
+            entries[0]
+        }
+    }
+
+    /// Returns the function of the static `lut` with input `i` as variable
+    /// `i`
+    pub fn apply_lut_vars(&mut self, lut: &Bits) -> BddRef {
+        let n = lut.bw().trailing_zeros() as usize;
+        let inputs: Vec<BddRef> = (0..n).map(|i| self.var(i)).collect();
+        self.apply_lut(lut, &inputs)
+    }
+
+    /// Evaluates `f` with variable `i` set to `assignment(i)`
+    pub fn eval<F: FnMut(usize) -> bool>(&self, f: BddRef, mut assignment: F) -> bool {
+        let mut f = f;
+        while !f.is_const() {
+            let node = self.nodes[f.0 as usize];
+            f = if assignment(node.var as usize) {
+                node.hi
+            } else {
+                node.lo
+            };
+        }
+        f == BddRef::TRUE
+    }
+
+    /// Returns an assignment that makes `f` true as the values of the
+    /// variables on one path to the `TRUE` terminal, variables not in the
+    /// assignment can have any value. Returns `None` if `f` is `FALSE`.
+    pub fn satisfying_assignment(&self, f: BddRef) -> Option<HashMap<usize, bool>> {
+        if f == BddRef::FALSE {
+            return None
+        }
+        let mut res = HashMap::new();
+        let mut f = f;
+        while !f.is_const() {
+            let node = self.nodes[f.0 as usize];
+            // any nonterminal node has a path to `TRUE` from both of its
+            // branches unless that branch is `FALSE`
+            let b = node.lo == BddRef::FALSE;
+            res.insert(node.var as usize, b);
+            f = if b { node.hi } else { node.lo };
+        }
+        Some(res)
+    }
+
+    /// Returns the truth table of `f` over the variables `0..num_vars`, or
+    /// `None` if `f` depends on other variables
+    pub fn to_lut(&self, f: BddRef, num_vars: usize) -> Option<Awi> {
+        if self.support(f).iter().any(|var| *var >= num_vars) {
+            return None
+        }
+        let mut lut = Awi::zero(NonZeroUsize::new(1 << num_vars).unwrap());
+        for i in 0..lut.bw() {
+            lut.set(i, self.eval(f, |var| ((i >> var) & 1) != 0))
+                .unwrap();
+        }
+        Some(lut)
+    }
+
+    /// Returns the variables that `f` depends on, in increasing order
+    pub fn support(&self, f: BddRef) -> Vec<usize> {
+        let mut vars = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![f];
+        while let Some(f) = stack.pop() {
+            if f.is_const() || !visited.insert(f) {
+                continue
+            }
+            let node = self.nodes[f.0 as usize];
+            vars.insert(node.var as usize);
+            stack.push(node.lo);
+            stack.push(node.hi);
+        }
+        let mut res: Vec<usize> = vars.into_iter().collect();
+        res.sort_unstable();
+        res
+    }
+
+    /// Returns the number of nonterminal nodes reachable from `f`
+    pub fn node_count(&self, f: BddRef) -> usize {
+        let mut visited = HashSet::new();
+        let mut stack = vec![f];
+        while let Some(f) = stack.pop() {
+            if f.is_const() || !visited.insert(f) {
+                continue
+            }
+            let node = self.nodes[f.0 as usize];
+            stack.push(node.lo);
+            stack.push(node.hi);
+        }
+        visited.len()
+    }
+
+    /// Returns if swapping variables `i` and `j` leaves `f` unchanged
+    pub fn is_symmetric(&mut self, f: BddRef, i: usize, j: usize) -> bool {
+        if i == j {
+            return true
+        }
+        let f01 = self.restrict(f, i, false);
+        let f01 = self.restrict(f01, j, true);
+        let f10 = self.restrict(f, i, true);
+        let f10 = self.restrict(f10, j, false);
+        f01 == f10
+    }
+
+    /// Returns the column multiplicity of `f` with respect to the `bound`
+    /// variables, which is the number of distinct functions that `f` reduces
+    /// to over all assignments of the `bound` variables. A disjoint
+    /// Ashenhurst-Curtis decomposition with `bound` as the bound set needs
+    /// `ceil(log2(multiplicity))` encoding functions.
+    pub fn column_multiplicity(&mut self, f: BddRef, bound: &[usize]) -> usize {
+        let mut columns = HashSet::new();
+        for b in 0..(1usize << bound.len()) {
+            let mut column = f;
+            for (j, var) in bound.iter().copied().enumerate() {
+                column = self.restrict(column, var, ((b >> j) & 1) != 0);
+            }
+            columns.insert(column);
+        }
+        columns.len()
+    }
+}
+
+impl Default for Bdd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ensemble {
+    /// Returns the single `LNode` driving the equivalence of `p_equiv`, or
+    /// `None` if it has no drivers or any other kind of driver
+    pub(crate) fn sole_lnode_driver(&self, p_equiv: PBack) -> Option<PLNode> {
+        let mut res = None;
+        let mut adv = self.backrefs.advancer_surject(p_equiv);
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            match *self.backrefs.get_key(p_back).unwrap() {
+                Referent::ThisLNode(p_lnode) => {
+                    if res.is_some() {
+                        return None
+                    }
+                    res = Some(p_lnode);
+                }
+                Referent::ThisTNode(_) => return None,
+                Referent::ThisRNode(p_rnode)
+                    if !self.notary.rnodes().get(p_rnode).unwrap().1.read_only() =>
+                {
+                    return None
+                }
+                _ => (),
+            }
+        }
+        res
+    }
+
+    /// Returns the function of the `LNode` at `p_lnode` given the functions of
+    /// its inputs in the order of [Ensemble::lnode_input_equivs]. Dynamic
+    /// tables must not have `ConstUnknown` entries.
+    pub(crate) fn lnode_bdd(
+        &self,
+        bdd: &mut Bdd,
+        p_lnode: PLNode,
+        input_funcs: &[BddRef],
+    ) -> BddRef {
+        match self.lnodes[p_lnode].kind {
+            LNodeKind::Copy(_) => input_funcs[0],
+            LNodeKind::Lut(_, ref lut) => bdd.apply_lut(lut, input_funcs),
+            LNodeKind::Carry(_) => bdd.apply_lut(&LNode::carry_lut(), input_funcs),
+            LNodeKind::DynamicLut(ref inp, ref lut) => {
+                // the dynamic entries come after the index inputs
+                let (index, mut dynam) = input_funcs.split_at(inp.len());
+                let entries: Vec<BddRef> = lut
+                    .iter()
+                    .map(|x| match x {
+                        DynamicValue::Const(b) => bdd.constant(*b),
+                        DynamicValue::Dynam(_) => {
+                            let (f, rest) = dynam.split_first().unwrap();
+                            dynam = rest;
+                            *f
+                        }
+                        DynamicValue::ConstUnknown => unreachable!(),
+                    })
+                    .collect();
+                bdd.apply_dynamic_lut(&entries, index)
+            }
+        }
+    }
+
+    /// Builds the functions of the equivalences of `roots` and their fan-in in
+    /// `bdd`. Constant equivalences become constants, and equivalences that
+    /// are not driven by a single static `LNode` become leaf variables. Once
+    /// `bdd` has more than `max_nodes` nodes, the remaining equivalences also
+    /// become leaf variables. Returns the function of every visited
+    /// equivalence, in topological order, and if it is a leaf.
+    pub fn cone_bdds(
+        &self,
+        bdd: &mut Bdd,
+        roots: &[PBack],
+        max_nodes: usize,
+    ) -> Vec<(PBack, BddRef, bool)> {
+        let mut order = vec![];
+        let mut funcs: HashMap<PBack, BddRef> = HashMap::new();
+        let mut num_leaves = 0;
+        let mut on_stack = HashSet::new();
+        let mut stack: Vec<(PBack, bool)> = roots
+            .iter()
+            .map(|p| (self.backrefs.get_val(*p).unwrap().p_self_equiv, false))
+            .collect();
+        while let Some((p_equiv, expanded)) = stack.pop() {
+            if funcs.contains_key(&p_equiv) {
+                continue
+            }
+            let equiv = self.backrefs.get_val(p_equiv).unwrap();
+            if let Value::Const(b) = equiv.val {
+                let f = bdd.constant(b);
+                funcs.insert(p_equiv, f);
+                order.push((p_equiv, f, false));
+                continue
+            }
+            // dynamic tables with unknown entries are left as leaves
+            let p_lnode = self.sole_lnode_driver(p_equiv).filter(|p_lnode| {
+                if let LNodeKind::DynamicLut(_, ref lut) = self.lnodes[*p_lnode].kind {
+                    !lut.iter().any(|x| matches!(x, DynamicValue::ConstUnknown))
+                } else {
+                    true
+                }
+            });
+            let inputs = p_lnode.map(|p_lnode| self.lnode_input_equivs(p_lnode));
+            let mut func = None;
+            if let (Some(p_lnode), Some(inputs)) = (p_lnode, inputs) {
+                if bdd.len() <= max_nodes {
+                    if !expanded {
+                        on_stack.insert(p_equiv);
+                        stack.push((p_equiv, true));
+                        for p_inp in inputs {
+                            if !funcs.contains_key(&p_inp) && !on_stack.contains(&p_inp) {
+                                stack.push((p_inp, false));
+                            }
+                        }
+                        continue
+                    }
+                    on_stack.remove(&p_equiv);
+                    // inputs that are not built yet are from combinational loops
+                    let input_funcs: Option<Vec<BddRef>> = inputs
+                        .iter()
+                        .map(|p_inp| funcs.get(p_inp).copied())
+                        .collect();
+                    if let Some(input_funcs) = input_funcs {
+                        func = Some(self.lnode_bdd(bdd, p_lnode, &input_funcs));
+                    }
+                }
+            }
+            let (f, is_leaf) = if let Some(f) = func {
+                (f, false)
+            } else {
+                num_leaves += 1;
+                (bdd.var(num_leaves - 1), true)
+            };
+            funcs.insert(p_equiv, f);
+            order.push((p_equiv, f, is_leaf));
+        }
+        order
+    }
+
+    /// Uses [Bdd]s to find equivalences that compute the same function of
+    /// their fan-in as another equivalence, and merges them by removing their
+    /// `LNode` drivers. This catches functional duplicates that structural
+    /// deduplication cannot, e.x. `(a ^ b) ^ c` and `a ^ (b ^ c)`. Cones are
+    /// only analyzed up to `max_nodes` BDD nodes, beyond which equivalences
+    /// are treated as opaque. Returns the number of merged equivalences.
+    pub fn merge_equivalent_cones(&mut self, max_nodes: usize) -> Result<usize, Error> {
+        self.journal_lnode_step("merge_equivalent_cones");
+        // pending events may refer to `LNode`s that get removed
+        self.restart_request_phase()?;
+        let mut roots = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                roots.push(p_back);
+            }
+        }
+        let mut bdd = Bdd::new();
+        let order = self.cone_bdds(&mut bdd, &roots, max_nodes);
+        // because of the topological order, a representative can never be in
+        // the fan-in of one of its duplicates
+        let mut representatives: HashMap<BddRef, PBack> = HashMap::new();
+        let mut merges = vec![];
+        for (p_equiv, f, is_leaf) in order {
+            match representatives.get(&f) {
+                Some(p_rep) if !is_leaf => merges.push((*p_rep, p_equiv)),
+                Some(_) => (),
+                None => {
+                    representatives.insert(f, p_equiv);
+                }
+            }
+        }
+        // `union_equiv` may remove the `ThisEquiv` of either side
+        let mut current: HashMap<PBack, PBack> = HashMap::new();
+        for (p_rep, p_dup) in merges.iter().copied() {
+            let p_cur = current.get(&p_rep).copied().unwrap_or(p_rep);
+            let p_lnode = self.sole_lnode_driver(p_dup).unwrap();
+            let lnode = self.lnodes.remove(p_lnode).unwrap();
+            lnode.inputs(|p_inp| {
+                self.backrefs.remove_key(p_inp).unwrap();
+            });
+            self.backrefs.remove_key(lnode.p_self).unwrap();
+            self.union_equiv(p_cur, p_dup)?;
+            if !self.backrefs.contains(p_cur) {
+                current.insert(p_rep, p_dup);
+            }
+        }
+        self.reinit_partial_orders()?;
+        Ok(merges.len())
+    }
+}
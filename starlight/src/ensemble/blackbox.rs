@@ -0,0 +1,93 @@
+use crate::{
+    ensemble::{Delay, Ensemble, PBlackBox, PExternal},
+    Error,
+};
+
+/// An instance of external IP whose function is unknown, such as a vendor
+/// memory or PLL. The fields are `RNode`s to the bits of the ports. The inputs
+/// are read only `RNode`s which keep the logic driving them from being
+/// optimized away, and the outputs are writable `RNode`s of opaque values, so
+/// that nothing is assumed about them during optimization. Evaluation sees
+/// the outputs as unknown unless they are assigned like any `LazyAwi`.
+///
+/// The timing of the instance is given by pin-to-pin delays, which are used
+/// by [crate::sweep::SweepStats::from_ensemble]. Port pairs without a delay
+/// have no combinational path between them, which is the case for
+/// registered outputs.
+#[derive(Debug, Clone)]
+pub struct BlackBox {
+    /// The name of the module that this is an instance of
+    pub name: String,
+    pub inputs: Vec<PExternal>,
+    pub outputs: Vec<PExternal>,
+    /// Combinational paths as `(input port index, output port index, delay)`
+    pub delays: Vec<(usize, usize, Delay)>,
+}
+
+impl BlackBox {
+    /// Returns the `RNode`s of the inputs followed by the outputs
+    pub fn p_externals(&self) -> impl Iterator<Item = PExternal> + '_ {
+        self.inputs.iter().chain(self.outputs.iter()).copied()
+    }
+}
+
+impl Ensemble {
+    /// Inserts `blackbox` and increments the reference counts of its `RNode`s
+    /// so that they live as long as the `BlackBox`
+    ///
+    /// # Errors
+    ///
+    /// If `name` is empty, there are no outputs, a delay refers to a port that
+    /// does not exist, or a port pair has more than one delay
+    pub fn insert_blackbox(&mut self, blackbox: BlackBox) -> Result<PBlackBox, Error> {
+        if blackbox.name.is_empty() {
+            return Err(Error::OtherStr("`BlackBox` has an empty name"))
+        }
+        if blackbox.outputs.is_empty() {
+            return Err(Error::OtherString(format!(
+                "`BlackBox` \"{}\" has no outputs",
+                blackbox.name
+            )))
+        }
+        for (j, (i, o, _)) in blackbox.delays.iter().enumerate() {
+            if (*i >= blackbox.inputs.len()) || (*o >= blackbox.outputs.len()) {
+                return Err(Error::OtherString(format!(
+                    "`BlackBox` \"{}\" has a delay from input {i} to output {o}, but there are {} \
+                     inputs and {} outputs",
+                    blackbox.name,
+                    blackbox.inputs.len(),
+                    blackbox.outputs.len()
+                )))
+            }
+            if blackbox.delays[..j]
+                .iter()
+                .any(|(i1, o1, _)| (i1 == i) && (o1 == o))
+            {
+                return Err(Error::OtherString(format!(
+                    "`BlackBox` \"{}\" has more than one delay from input {i} to output {o}",
+                    blackbox.name
+                )))
+            }
+        }
+        for p_external in blackbox.p_externals() {
+            self.notary.get_rnode(p_external)?;
+        }
+        for p_external in blackbox.p_externals() {
+            self.rnode_inc_rc(p_external).unwrap();
+        }
+        Ok(self.blackboxes.insert(blackbox))
+    }
+
+    /// Removes the `BlackBox` at `p_blackbox`, its `RNode`s are removed if
+    /// nothing else references them
+    pub fn remove_blackbox(&mut self, p_blackbox: PBlackBox) -> Result<(), Error> {
+        if let Some(blackbox) = self.blackboxes.remove(p_blackbox) {
+            for p_external in blackbox.p_externals() {
+                self.rnode_dec_rc(p_external)?;
+            }
+            Ok(())
+        } else {
+            Err(Error::InvalidPtr)
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNode, LNodeKind, PBack, PExternal, Referent, Value},
+    Error,
+};
+
+/// Replaces characters that BLIF readers treat specially
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_whitespace() || (c == '#') || (c == '\\') || (c == '=') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Writes the rows of a `.names` cover that is true for the set entries of
+/// `lut`, with the first input being the least significant index bit
+fn write_lut_cover(res: &mut String, lut: &awint::Bits, num_inputs: usize) {
+    for i in 0..lut.bw() {
+        if lut.get(i).unwrap() {
+            for j in 0..num_inputs {
+                res.push(if (i >> j) & 1 == 1 { '1' } else { '0' });
+            }
+            res.push_str(" 1\n");
+        }
+    }
+}
+
+impl Ensemble {
+    /// Writes the `Ensemble` as a BLIF netlist in the dialect that VPR reads,
+    /// so that a design can be placed and routed by VTR for comparison with
+    /// the router of this crate. This should be used after
+    /// [Epoch::optimize](crate::Epoch::optimize) so that the `LNode`s are
+    /// mapped to LUTs.
+    ///
+    /// Every equivalence is a net. Writable `RNode`s become the `.inputs` and
+    /// read only `RNode`s become the `.outputs`, named by their debug names
+    /// with `[i]` bit indexes or by their order if they have none. `LNode`s
+    /// become `.names` covers, with `DynamicLut`s expanded into covers over
+    /// their table inputs. Zero delay `TNode`s become buffers, and every unit
+    /// of a nonzero delay becomes a `.latch` triggered on the rising edge of
+    /// the `clock` input, with the initial value of the `TNode` or the
+    /// unknown initial value `3`. The `clock` input is only added if there
+    /// are latches. `BlackBox`es become `.subckt`s of `.blackbox` models with
+    /// `in{i}[j]` and `out{i}[j]` ports. `MulNode`s and `RomNode`s are written
+    /// as their `LNode` decompositions.
+    ///
+    /// # Errors
+    ///
+    /// If an `RNode` has not been initialized, or if a net that is not
+    /// constant does not have exactly one driver. Nets that are constant
+    /// unknown are written as zero.
+    pub fn write_blif(&self, model: &str, clock: &str) -> Result<String, Error> {
+        let clock = sanitize(clock);
+        let mut ids: HashMap<PBack, usize> = HashMap::new();
+        let mut nets: Vec<String> = vec![];
+        let mut vals: Vec<Value> = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                ids.insert(p_back, nets.len());
+                nets.push(format!("n{}", nets.len()));
+                vals.push(self.backrefs.get_val(p_back).unwrap().val);
+            }
+        }
+        let e = |p_back: PBack| ids[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        let mut drivers = vec![0usize; nets.len()];
+
+        // the ports of `BlackBox`es are internal to the model
+        let mut blackbox_ports: HashSet<PExternal> = HashSet::new();
+        for blackbox in self.blackboxes.vals() {
+            blackbox_ports.extend(blackbox.p_externals());
+        }
+        // `MulNode`s and `RomNode`s are exported as their decompositions
+        let macro_op_ports = self.macro_op_p_externals();
+        let mut used_names: HashSet<String> = HashSet::new();
+        let mut inputs: Vec<String> = vec![];
+        // pairs of nets and output names
+        let mut outputs: Vec<(usize, String)> = vec![];
+        for (i, (_, p_external, rnode)) in self.notary.rnodes().into_iter().enumerate() {
+            let bits = rnode.bits().ok_or_else(|| {
+                Error::OtherString(format!(
+                    "could not write BLIF, `RNode` {p_external} has not been initialized"
+                ))
+            })?;
+            if rnode.read_only() {
+                if blackbox_ports.contains(p_external) || macro_op_ports.contains(p_external) {
+                    continue
+                }
+            } else {
+                // writable `RNode`s drive their nets, including `BlackBox` outputs
+                for bit in bits.iter().flatten() {
+                    drivers[e(*bit)] += 1;
+                }
+                if blackbox_ports.contains(p_external) {
+                    continue
+                }
+            }
+            let prefix = if rnode.read_only() { "out" } else { "in" };
+            let mut name = rnode
+                .debug_name()
+                .map(sanitize)
+                .unwrap_or_else(|| format!("{prefix}{i}"));
+            if !used_names.insert(name.clone()) {
+                name = format!("{prefix}{i}");
+                used_names.insert(name.clone());
+            }
+            for (j, bit) in bits.iter().enumerate() {
+                if let Some(bit) = bit {
+                    let bit_name = format!("{name}[{j}]");
+                    if rnode.read_only() {
+                        outputs.push((e(*bit), bit_name));
+                    } else {
+                        nets[e(*bit)].clone_from(&bit_name);
+                        inputs.push(bit_name);
+                    }
+                }
+            }
+        }
+        for lnode in self.lnodes.vals() {
+            drivers[e(lnode.p_self)] += 1;
+        }
+        for tnode in self.tnodes.vals() {
+            drivers[e(tnode.p_self)] += 1;
+        }
+        for (id, num) in drivers.iter().enumerate() {
+            if !vals[id].is_const() && (*num != 1) {
+                return Err(Error::OtherString(format!(
+                    "could not write BLIF, net {} has {num} drivers",
+                    nets[id]
+                )))
+            }
+        }
+
+        let mut body = String::new();
+        // constant nets are driven by constant covers and nothing else
+        for (id, val) in vals.iter().enumerate() {
+            match val {
+                Value::Const(true) => writeln!(body, ".names {}\n1", nets[id]).unwrap(),
+                Value::Const(false) | Value::ConstUnknown => {
+                    writeln!(body, ".names {}", nets[id]).unwrap()
+                }
+                Value::Unknown | Value::Dynam(_) => (),
+            }
+        }
+        let is_const = |p_back: PBack| vals[e(p_back)].is_const();
+        for lnode in self.lnodes.vals() {
+            if is_const(lnode.p_self) {
+                continue
+            }
+            let out = &nets[e(lnode.p_self)];
+            match &lnode.kind {
+                LNodeKind::Copy(inp) => {
+                    writeln!(body, ".names {} {out}\n1 1", nets[e(*inp)]).unwrap();
+                }
+                LNodeKind::Lut(inp, lut) => {
+                    body.push_str(".names");
+                    for inp in inp {
+                        write!(body, " {}", nets[e(*inp)]).unwrap();
+                    }
+                    writeln!(body, " {out}").unwrap();
+                    write_lut_cover(&mut body, lut, inp.len());
+                }
+                LNodeKind::Carry(inp) => {
+                    body.push_str(".names");
+                    for inp in inp {
+                        write!(body, " {}", nets[e(*inp)]).unwrap();
+                    }
+                    writeln!(body, " {out}").unwrap();
+                    write_lut_cover(&mut body, &LNode::carry_lut(), inp.len());
+                }
+                LNodeKind::DynamicLut(inp, lut) => {
+                    // the table entries that are nets become extra inputs after the index
+                    // inputs, and each row selects the entry that its index points to
+                    let table_inputs: Vec<PBack> = lut
+                        .iter()
+                        .filter_map(|entry| {
+                            if let DynamicValue::Dynam(p) = entry {
+                                Some(*p)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect();
+                    body.push_str(".names");
+                    for inp in inp.iter().chain(table_inputs.iter()) {
+                        write!(body, " {}", nets[e(*inp)]).unwrap();
+                    }
+                    writeln!(body, " {out}").unwrap();
+                    let mut table_i = 0;
+                    for (i, entry) in lut.iter().enumerate() {
+                        let selected = match entry {
+                            DynamicValue::ConstUnknown | DynamicValue::Const(false) => continue,
+                            DynamicValue::Const(true) => None,
+                            DynamicValue::Dynam(_) => {
+                                table_i += 1;
+                                Some(table_i - 1)
+                            }
+                        };
+                        for j in 0..inp.len() {
+                            body.push(if (i >> j) & 1 == 1 { '1' } else { '0' });
+                        }
+                        for j in 0..table_inputs.len() {
+                            body.push(if selected == Some(j) { '1' } else { '-' });
+                        }
+                        body.push_str(" 1\n");
+                    }
+                }
+            }
+        }
+        let mut has_latches = false;
+        for (i, tnode) in self.tnodes.vals().enumerate() {
+            if is_const(tnode.p_self) {
+                continue
+            }
+            let out = &nets[e(tnode.p_self)];
+            let driver = &nets[e(tnode.p_driver)];
+            let delay = tnode.delay().amount();
+            if delay == 0 {
+                writeln!(body, ".names {driver} {out}\n1 1").unwrap();
+                continue
+            }
+            has_latches = true;
+            let init = match tnode.initial {
+                Some(false) => 0,
+                Some(true) => 1,
+                None => 3,
+            };
+            // a chain of latches for longer delays
+            let mut prev = driver.clone();
+            for j in 1..delay {
+                let next = format!("t{i}_{j}");
+                writeln!(body, ".latch {prev} {next} re {clock} {init}").unwrap();
+                prev = next;
+            }
+            writeln!(body, ".latch {prev} {out} re {clock} {init}").unwrap();
+        }
+        for blackbox in self.blackboxes.vals() {
+            write!(body, ".subckt {}", sanitize(&blackbox.name)).unwrap();
+            for (port_i, p_external) in blackbox.p_externals().enumerate() {
+                let (port, port_i) = if port_i < blackbox.inputs.len() {
+                    ("in", port_i)
+                } else {
+                    ("out", port_i - blackbox.inputs.len())
+                };
+                let (_, rnode) = self.notary.get_rnode(p_external)?;
+                for (j, bit) in rnode.bits().unwrap().iter().enumerate() {
+                    if let Some(bit) = bit {
+                        write!(body, " {port}{port_i}[{j}]={}", nets[e(*bit)]).unwrap();
+                    }
+                }
+            }
+            body.push('\n');
+        }
+        for (id, name) in &outputs {
+            writeln!(body, ".names {} {name}\n1 1", nets[*id]).unwrap();
+        }
+
+        let mut res = String::new();
+        writeln!(res, ".model {}", sanitize(model)).unwrap();
+        res.push_str(".inputs");
+        for input in &inputs {
+            write!(res, " {input}").unwrap();
+        }
+        if has_latches {
+            write!(res, " {clock}").unwrap();
+        }
+        res.push_str("\n.outputs");
+        for (_, name) in &outputs {
+            write!(res, " {name}").unwrap();
+        }
+        res.push('\n');
+        res.push_str(&body);
+        res.push_str(".end\n");
+        // one `.blackbox` model per name, with the ports of the first instance
+        let mut models: HashSet<&str> = HashSet::new();
+        for blackbox in self.blackboxes.vals() {
+            if !models.insert(&blackbox.name) {
+                continue
+            }
+            writeln!(res, "\n.model {}", sanitize(&blackbox.name)).unwrap();
+            for (ports, port) in [(&blackbox.inputs, "inputs"), (&blackbox.outputs, "outputs")] {
+                write!(res, ".{port}").unwrap();
+                let prefix = if port == "inputs" { "in" } else { "out" };
+                for (port_i, p_external) in ports.iter().enumerate() {
+                    let (_, rnode) = self.notary.get_rnode(*p_external)?;
+                    for j in 0..rnode.nzbw().get() {
+                        write!(res, " {prefix}{port_i}[{j}]").unwrap();
+                    }
+                }
+                res.push('\n');
+            }
+            res.push_str(".blackbox\n.end\n");
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::HashMap, num::NonZeroUsize};
+
+use crate::ensemble::{PBack, Value};
+
+/// Statistics of an [EvalCache]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvalCacheStats {
+    /// The number of requests answered from the cache
+    pub hits: u64,
+    /// The number of requests that had to go through the evaluator
+    pub misses: u64,
+    /// The number of times that cached values were invalidated, either by a
+    /// value change or by a structural change to the `Ensemble`
+    pub invalidations: u64,
+    /// The number of times the cache was full and had to drop entries
+    pub evictions: u64,
+}
+
+/// A memoized cache of requested values, enabled with
+/// [crate::Epoch::enable_eval_cache]. Entries are keyed by equivalence and
+/// are only valid for the change generation they were recorded in. Any value
+/// change in the `Ensemble` starts a new generation, so repeatedly requesting
+/// the same values in between input changes only has to go through the
+/// evaluator once per generation.
+#[derive(Debug, Clone)]
+pub struct EvalCache {
+    generation: u64,
+    /// If anything was inserted during the current generation
+    is_current_used: bool,
+    values: HashMap<PBack, (u64, Value)>,
+    capacity: NonZeroUsize,
+    stats: EvalCacheStats,
+}
+
+impl EvalCache {
+    /// Creates a cache that holds at most `capacity` entries, larger
+    /// capacities trade memory for fewer evaluator requests
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            generation: 0,
+            is_current_used: false,
+            values: HashMap::new(),
+            capacity,
+            stats: EvalCacheStats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> NonZeroUsize {
+        self.capacity
+    }
+
+    pub fn stats(&self) -> EvalCacheStats {
+        self.stats
+    }
+
+    /// Returns the number of entries, including stale ones
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Starts a new change generation, invalidating all current entries
+    pub fn next_generation(&mut self) {
+        // avoid counting invalidations when nothing was cached in the generation
+        if self.is_current_used {
+            self.stats.invalidations = self.stats.invalidations.saturating_add(1);
+            self.is_current_used = false;
+        }
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Removes all entries, this needs to be called on any structural change
+    /// that could invalidate or reuse the equivalence keys
+    pub fn clear(&mut self) {
+        if self.is_current_used {
+            self.stats.invalidations = self.stats.invalidations.saturating_add(1);
+            self.is_current_used = false;
+        }
+        self.values.clear();
+    }
+
+    /// Returns the cached value of `p_equiv` if it is valid in the current
+    /// generation, and records a hit or miss
+    pub fn get(&mut self, p_equiv: PBack) -> Option<Value> {
+        match self.values.get(&p_equiv) {
+            Some((gen, val)) if *gen == self.generation => {
+                self.stats.hits = self.stats.hits.saturating_add(1);
+                Some(*val)
+            }
+            _ => {
+                self.record_miss();
+                None
+            }
+        }
+    }
+
+    /// Records a miss for a request that could not use the cache
+    pub fn record_miss(&mut self) {
+        self.stats.misses = self.stats.misses.saturating_add(1);
+    }
+
+    /// Records the value of `p_equiv` in the current generation
+    pub fn insert(&mut self, p_equiv: PBack, val: Value) {
+        if (self.values.len() >= self.capacity.get()) && !self.values.contains_key(&p_equiv) {
+            // first try dropping stale entries before dropping everything
+            let generation = self.generation;
+            self.values.retain(|_, (gen, _)| *gen == generation);
+            if self.values.len() >= self.capacity.get() {
+                self.values.clear();
+            }
+            self.stats.evictions = self.stats.evictions.saturating_add(1);
+        }
+        self.values.insert(p_equiv, (self.generation, val));
+        self.is_current_used = true;
+    }
+}
@@ -0,0 +1,479 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use awint::{
+    awint_dag::triple_arena::{Advancer, Arena, Ptr, Recast, SurjectArena},
+    Awi,
+};
+
+use crate::{
+    ensemble::{
+        value::stable_hash, DynamicValue, Ensemble, LNodeKind, PBack, PExternal, PLNode, PTNode,
+        Referent, StableIds, Value,
+    },
+    Error,
+};
+
+/// Reorders `arena` so that its elements are in the order of `order`, which
+/// must contain every `Ptr` of `arena` once, and returns the recaster
+fn reorder_arena<P: Ptr, T>(arena: &mut Arena<P, T>, order: &[P]) -> Arena<P, P> {
+    let mut recaster = Arena::<P, P>::new();
+    recaster.clone_from_with(arena, |_, _| P::invalid());
+    let mut res = Arena::new();
+    for p in order.iter().copied() {
+        let q = res.insert(arena.remove(p).unwrap());
+        *recaster.get_mut(p).unwrap() = q;
+    }
+    debug_assert!(arena.is_empty());
+    *arena = res;
+    recaster
+}
+
+/// The kind, static table, constant dynamic table entries, and output and
+/// input equivalences of an `LNode` in an [ArenaStructure]
+type LNodeStructure = (u8, Option<Awi>, Vec<Option<Option<bool>>>, Vec<usize>);
+
+/// The fanout IDs of equivalences and `LNode`s, see `Ensemble::fanout_ids`
+type FanoutIds = (HashMap<PBack, u64>, HashMap<PLNode, u64>);
+
+/// The structure of an `Ensemble` in arena order with pointers replaced by
+/// arena positions, used for checking that two `Ensemble`s are identical
+/// including their arena order
+#[derive(Debug, PartialEq, Eq)]
+struct ArenaStructure {
+    /// The value and sorted referent keys of each equivalence
+    equivs: Vec<(Value, Vec<(u8, usize)>)>,
+    lnodes: Vec<LNodeStructure>,
+    /// The output equivalence, driver equivalence, and delay of each `TNode`
+    tnodes: Vec<(usize, usize, u128)>,
+}
+
+/// Returns the key that the referents of an equivalence are sorted by
+fn referent_key(referent: Referent) -> (u8, usize) {
+    match referent {
+        Referent::ThisEquiv => (0, 0),
+        Referent::ThisLNode(p_lnode) => (1, p_lnode.inx().get()),
+        Referent::ThisTNode(p_tnode) => (2, p_tnode.inx().get()),
+        Referent::Input(p_lnode) => (3, p_lnode.inx().get()),
+        Referent::Driver(p_tnode) => (4, p_tnode.inx().get()),
+        Referent::ThisRNode(p_rnode) => (5, p_rnode.inx().get()),
+        Referent::ThisStateBit(_, i) => (6, i),
+    }
+}
+
+impl Ensemble {
+    /// Returns if the arenas of `self` are already in the given orders, with
+    /// the referents of each equivalence sorted by `referent_key`
+    fn in_order(
+        &self,
+        equiv_order: &[PBack],
+        lnode_order: &[PLNode],
+        tnode_order: &[PTNode],
+    ) -> bool {
+        if !(self.lnodes.ptrs().eq(lnode_order.iter().copied())
+            && self.tnodes.ptrs().eq(tnode_order.iter().copied()))
+        {
+            return false
+        }
+        // the referents of each equivalence must also be contiguous
+        let mut expected = equiv_order.iter().copied();
+        let mut prev = None;
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            let key = referent_key(*self.backrefs.get_key(p_back).unwrap());
+            if key.0 == 0 {
+                if expected.next() != Some(p_back) {
+                    return false
+                }
+                prev = Some((p_back, key));
+            } else {
+                let p_equiv = self.backrefs.get_val(p_back).unwrap().p_self_equiv;
+                if prev.map(|(p_prev, prev_key)| (p_prev != p_equiv) || (prev_key > key))
+                    != Some(false)
+                {
+                    return false
+                }
+                prev = Some((p_equiv, key));
+            }
+        }
+        true
+    }
+
+    /// Rebuilds the arenas of `self` so that the equivalences, `LNode`s, and
+    /// `TNode`s are in the order given by the closures, which map each
+    /// element to a sort key. Elements with equal keys keep their relative
+    /// order. There must be no `State`s, pending evaluation events, or
+    /// optimizations.
+    fn reorder_by<KE: Ord, KL: Ord, KT: Ord>(
+        &mut self,
+        mut equiv_key: impl FnMut(&Self, PBack) -> KE,
+        mut lnode_key: impl FnMut(&Self, PLNode) -> KL,
+        mut tnode_key: impl FnMut(&Self, PTNode) -> KT,
+    ) -> Result<(), Error> {
+        self.optimizer.check_clear()?;
+        self.evaluator.check_clear()?;
+        self.stator.check_clear()?;
+
+        let mut lnode_order: Vec<PLNode> = self.lnodes.ptrs().collect();
+        lnode_order.sort_by_cached_key(|p| lnode_key(self, *p));
+        let mut tnode_order: Vec<PTNode> = self.tnodes.ptrs().collect();
+        tnode_order.sort_by_cached_key(|p| tnode_key(self, *p));
+        let mut equiv_order = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                equiv_order.push(p_back);
+            }
+        }
+        equiv_order.sort_by_cached_key(|p| equiv_key(self, *p));
+        if self.in_order(&equiv_order, &lnode_order, &tnode_order) {
+            // avoid invalidating pointers
+            return Ok(())
+        }
+
+        let p_lnode_recaster = reorder_arena(&mut self.lnodes, &lnode_order);
+        self.delayer.compress();
+        let p_tnode_recaster = reorder_arena(&mut self.tnodes, &tnode_order);
+        if let Err(e) = self.delayer.recast(&p_tnode_recaster) {
+            return Err(Error::OtherString(format!(
+                "recast error with {e} in the `Delayer`"
+            )));
+        }
+
+        // rebuild the backrefs with the surjects in order, the `Equiv`s still have
+        // their old `p_self_equiv`s which are recast at the end
+        let mut p_back_recaster = Arena::<PBack, PBack>::new();
+        self.backrefs
+            .clone_keys_to_arena(&mut p_back_recaster, |_, _| PBack::invalid());
+        let mut backrefs = SurjectArena::new();
+        for p_equiv in equiv_order {
+            let equiv = self.backrefs.get_val(p_equiv).unwrap().clone();
+            let p_new_equiv = backrefs.insert(Referent::ThisEquiv, equiv);
+            *p_back_recaster.get_mut(p_equiv).unwrap() = p_new_equiv;
+            // the referents are also sorted so that the surject order is canonical
+            let mut referents = vec![];
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_back) = adv.advance(&self.backrefs) {
+                if p_back == p_equiv {
+                    continue
+                }
+                let mut referent = *self.backrefs.get_key(p_back).unwrap();
+                match referent {
+                    Referent::ThisLNode(ref mut p_lnode) | Referent::Input(ref mut p_lnode) => {
+                        p_lnode.recast(&p_lnode_recaster).unwrap();
+                    }
+                    Referent::ThisTNode(ref mut p_tnode) | Referent::Driver(ref mut p_tnode) => {
+                        p_tnode.recast(&p_tnode_recaster).unwrap();
+                    }
+                    Referent::ThisEquiv | Referent::ThisRNode(_) => (),
+                    Referent::ThisStateBit(..) => unreachable!(),
+                }
+                referents.push((referent_key(referent), p_back, referent));
+            }
+            referents.sort_by_key(|(key, ..)| *key);
+            for (_, p_back, referent) in referents {
+                let p_new = backrefs.insert_key(p_new_equiv, referent).unwrap();
+                *p_back_recaster.get_mut(p_back).unwrap() = p_new;
+            }
+        }
+        self.backrefs = backrefs;
+        if let Err(e) = self.backrefs.recast(&p_back_recaster) {
+            return Err(Error::OtherString(format!(
+                "recast error with {e} in the backrefs"
+            )));
+        }
+        if let Err(e) = self.notary.recast(&p_back_recaster) {
+            return Err(Error::OtherString(format!(
+                "recast error with {e} in the notary"
+            )));
+        }
+        if let Err(e) = self.lnodes.recast(&p_back_recaster) {
+            return Err(Error::OtherString(format!(
+                "recast error with {e} in the lnodes"
+            )));
+        }
+        if let Err(e) = self.tnodes.recast(&p_back_recaster) {
+            return Err(Error::OtherString(format!(
+                "recast error with {e} in the tnodes"
+            )));
+        }
+        if let Some(ref mut toggles) = self.evaluator.toggles {
+            *toggles = toggles
+                .drain()
+                .filter_map(|(mut p_equiv, count)| {
+                    p_equiv
+                        .recast(&p_back_recaster)
+                        .ok()
+                        .map(|_| (p_equiv, count))
+                })
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Returns the sort key of a `TNode` used by [Ensemble::canonicalize_order]
+    fn canonical_tnode_key(&self, ids: &StableIds, p_tnode: PTNode) -> (u64, u64, u128) {
+        let tnode = &self.tnodes[p_tnode];
+        let id = |p_back: PBack| {
+            ids.equiv(self.backrefs.get_val(p_back).unwrap().p_self_equiv)
+                .unwrap_or(0)
+        };
+        (id(tnode.p_self), id(tnode.p_driver), tnode.delay().amount())
+    }
+
+    /// Returns IDs derived from what each equivalence and `LNode` fans out to,
+    /// which break ties between elements with equal `ids`. The fanout ID of an
+    /// equivalence is a hash of the fanout IDs of the `LNode`s reading it
+    /// along with the input positions, the `TNode`s it drives, and the `RNode`
+    /// bits referencing it. The fanout ID of an `LNode` combines its own ID
+    /// with the fanout ID of its output.
+    fn fanout_ids(&self, ids: &StableIds) -> Result<FanoutIds, Error> {
+        let mut rnode_bits: HashMap<PBack, Vec<(PExternal, usize)>> = HashMap::new();
+        for (_, p_external, rnode) in self.notary.rnodes() {
+            if let Some(bits) = rnode.bits() {
+                for (i, bit) in bits.iter().enumerate() {
+                    if let Some(bit) = bit {
+                        let p_equiv = self.backrefs.get_val(*bit).unwrap().p_self_equiv;
+                        rnode_bits
+                            .entry(p_equiv)
+                            .or_default()
+                            .push((*p_external, i));
+                    }
+                }
+            }
+        }
+        let mut equiv_fanouts: HashMap<PBack, u64> = HashMap::new();
+        let mut lnode_fanouts: HashMap<PLNode, u64> = HashMap::new();
+        // the readers of an equivalence come after its driver in topological order
+        let equiv_fanout = |p_equiv: PBack, lnode_fanouts: &HashMap<PLNode, u64>| {
+            let mut readers = vec![];
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_ref).unwrap() {
+                    Referent::Input(p_lnode) => {
+                        let mut position = 0;
+                        let mut i = 0;
+                        self.lnodes[p_lnode].inputs(|p_inp| {
+                            if p_inp == p_ref {
+                                position = i;
+                            }
+                            i += 1;
+                        });
+                        readers.push((0u8, lnode_fanouts[&p_lnode], position as u128));
+                    }
+                    // `TNode`s can be in loops, so only their IDs are used
+                    Referent::Driver(p_tnode) => {
+                        let (id, _, delay) = self.canonical_tnode_key(ids, p_tnode);
+                        readers.push((1, id, delay));
+                    }
+                    _ => (),
+                }
+            }
+            readers.sort_unstable();
+            let mut bits = rnode_bits.get(&p_equiv).cloned().unwrap_or_default();
+            bits.sort_unstable();
+            stable_hash(&(readers, bits))
+        };
+        for p_lnode in self.topo_order()?.into_iter().rev() {
+            let p_equiv = self
+                .backrefs
+                .get_val(self.lnodes[p_lnode].p_self)
+                .unwrap()
+                .p_self_equiv;
+            let fanout = if let Some(fanout) = equiv_fanouts.get(&p_equiv) {
+                *fanout
+            } else {
+                let fanout = equiv_fanout(p_equiv, &lnode_fanouts);
+                equiv_fanouts.insert(p_equiv, fanout);
+                fanout
+            };
+            lnode_fanouts.insert(p_lnode, stable_hash(&(ids.lnode(p_lnode), fanout)));
+        }
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if matches!(self.backrefs.get_key(p_back).unwrap(), Referent::ThisEquiv)
+                && !equiv_fanouts.contains_key(&p_back)
+            {
+                let fanout = equiv_fanout(p_back, &lnode_fanouts);
+                equiv_fanouts.insert(p_back, fanout);
+            }
+        }
+        Ok((equiv_fanouts, lnode_fanouts))
+    }
+
+    /// Like [Ensemble::canonicalize_order] but using already computed `ids`
+    pub(crate) fn canonicalize_order_with(&mut self, ids: &StableIds) -> Result<(), Error> {
+        let (equiv_fanouts, lnode_fanouts) = self.fanout_ids(ids)?;
+        self.reorder_by(
+            |_, p_equiv| (ids.equiv(p_equiv), equiv_fanouts.get(&p_equiv).copied()),
+            |_, p_lnode| (ids.lnode(p_lnode), lnode_fanouts.get(&p_lnode).copied()),
+            |this, p_tnode| {
+                let p_self = this.tnodes[p_tnode].p_self;
+                let p_equiv = this.backrefs.get_val(p_self).unwrap().p_self_equiv;
+                (
+                    this.canonical_tnode_key(ids, p_tnode),
+                    equiv_fanouts.get(&p_equiv).copied(),
+                )
+            },
+        )
+    }
+
+    /// Reorders the arenas of `self` by the [Ensemble::stable_ids] of the
+    /// equivalences and `LNode`s, so that the arena order (which determines
+    /// the order of optimization and of iteration by exporters) does not
+    /// depend on the order in which things were constructed. Ties between
+    /// equal IDs are broken by what the elements fan out to, so only
+    /// elements that are interchangeable keep their relative order. There
+    /// must be no `State`s, pending evaluation events, or optimizations. See
+    /// [Ensemble::optimize_all_deterministic].
+    ///
+    /// # Errors
+    ///
+    /// If there is a combinational loop of `LNode`s
+    pub fn canonicalize_order(&mut self) -> Result<(), Error> {
+        let ids = self.stable_ids()?;
+        self.canonicalize_order_with(&ids)
+    }
+
+    /// Reverses the order of the arenas of `self`, used for checking that
+    /// results do not depend on arena order
+    fn reverse_order(&mut self) -> Result<(), Error> {
+        // the closures are called in arena order
+        let mut equiv_i = 0usize;
+        let mut lnode_i = 0usize;
+        let mut tnode_i = 0usize;
+        self.reorder_by(
+            |_, _| {
+                equiv_i += 1;
+                Reverse(equiv_i)
+            },
+            |_, _| {
+                lnode_i += 1;
+                Reverse(lnode_i)
+            },
+            |_, _| {
+                tnode_i += 1;
+                Reverse(tnode_i)
+            },
+        )
+    }
+
+    /// Returns the structure of `self` in arena order
+    fn arena_structure(&self) -> ArenaStructure {
+        let mut equiv_positions: HashMap<PBack, usize> = HashMap::new();
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                equiv_positions.insert(p_back, equiv_positions.len());
+            }
+        }
+        let equiv_position =
+            |p_back: PBack| equiv_positions[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        let lnode_positions: HashMap<PLNode, usize> = self
+            .lnodes
+            .ptrs()
+            .enumerate()
+            .map(|(i, p)| (p, i))
+            .collect();
+        let tnode_positions: HashMap<PTNode, usize> = self
+            .tnodes
+            .ptrs()
+            .enumerate()
+            .map(|(i, p)| (p, i))
+            .collect();
+        let mut res = ArenaStructure {
+            equivs: vec![],
+            lnodes: vec![],
+            tnodes: vec![],
+        };
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            let referent = *self.backrefs.get_key(p_back).unwrap();
+            let key = match referent {
+                Referent::ThisEquiv => {
+                    let val = self.backrefs.get_val(p_back).unwrap().val;
+                    res.equivs.push((val, vec![]));
+                    continue
+                }
+                Referent::ThisLNode(p_lnode) => (1, lnode_positions[&p_lnode]),
+                Referent::ThisTNode(p_tnode) => (2, tnode_positions[&p_tnode]),
+                Referent::Input(p_lnode) => (3, lnode_positions[&p_lnode]),
+                Referent::Driver(p_tnode) => (4, tnode_positions[&p_tnode]),
+                referent => referent_key(referent),
+            };
+            res.equivs[equiv_position(p_back)].1.push(key);
+        }
+        for equiv in &mut res.equivs {
+            equiv.1.sort_unstable();
+        }
+        for lnode in self.lnodes.vals() {
+            let mut inputs = vec![];
+            lnode.inputs(|p_inp| inputs.push(equiv_position(p_inp)));
+            let mut output = vec![equiv_position(lnode.p_self)];
+            output.extend(inputs);
+            let (kind, table, dynamic_table) = match lnode.kind {
+                LNodeKind::Copy(_) => (0, None, vec![]),
+                LNodeKind::Lut(_, ref table) => (1, Some((**table).clone()), vec![]),
+                LNodeKind::DynamicLut(_, ref table) => (
+                    2,
+                    None,
+                    table
+                        .iter()
+                        .map(|entry| match entry {
+                            DynamicValue::ConstUnknown => Some(None),
+                            DynamicValue::Const(b) => Some(Some(*b)),
+                            DynamicValue::Dynam(_) => None,
+                        })
+                        .collect(),
+                ),
+                LNodeKind::Carry(_) => (3, None, vec![]),
+            };
+            res.lnodes.push((kind, table, dynamic_table, output));
+        }
+        for tnode in self.tnodes.vals() {
+            res.tnodes.push((
+                equiv_position(tnode.p_self),
+                equiv_position(tnode.p_driver),
+                tnode.delay().amount(),
+            ));
+        }
+        res
+    }
+
+    /// Like [Ensemble::optimize_all], but additionally canonicalizes the arena
+    /// order with [Ensemble::canonicalize_order] before optimizing (since the
+    /// order of optimization is determined by arena order) and again at the
+    /// end, so that the result does not depend on the order in which things
+    /// were constructed. This is opt in because it is more expensive and
+    /// invalidates all `PBack`s, `PLNode`s, and `PTNode`s. Combinational loops
+    /// prevent canonicalization but are otherwise allowed.
+    pub fn optimize_all_deterministic(&mut self) -> Result<(), Error> {
+        self.optimize_all_with(true)
+    }
+
+    /// Optimizes a clone of `self` and a clone with reversed arena order with
+    /// [Ensemble::optimize_all_deterministic], and checks that the results
+    /// are identical including their arena order. This is intended for tests
+    /// asserting that optimization is deterministic.
+    ///
+    /// # Errors
+    ///
+    /// If the results differ or if optimization fails
+    pub fn verify_deterministic_optimization(&self) -> Result<(), Error> {
+        let mut a = self.clone();
+        let mut b = self.clone();
+        a.restart_request_phase()?;
+        b.restart_request_phase()?;
+        b.force_remove_all_states()?;
+        b.reverse_order()?;
+        a.optimize_all_deterministic()?;
+        b.optimize_all_deterministic()?;
+        if a.arena_structure() == b.arena_structure() {
+            Ok(())
+        } else {
+            Err(Error::OtherStr(
+                "`Ensemble::verify_deterministic_optimization`: optimization results depend on \
+                 arena order",
+            ))
+        }
+    }
+}
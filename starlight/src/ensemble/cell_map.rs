@@ -0,0 +1,690 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{
+        CellFunction, CellLibrary, DynamicValue, Ensemble, LNode, LNodeKind, PBack, PLNode,
+        Referent, Value,
+    },
+    Error,
+};
+
+/// An instance of a cell in a [MappedNetlist]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CellInstance {
+    /// The index of the cell in the library
+    pub cell: usize,
+    /// The nets of the inputs in the order of [CellDef::inputs]
+    ///
+    /// [CellDef::inputs]: crate::ensemble::CellDef::inputs
+    pub inputs: Vec<usize>,
+    /// The net of the output
+    pub output: usize,
+}
+
+/// A netlist of standard cells from [Ensemble::map_to_cells]
+///
+/// Net 0 is constant zero and net 1 is constant one, followed by the nets of
+/// the input port bits and then the internal nets. Flip-flops are all
+/// clocked by a single implicit clock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedNetlist {
+    library: CellLibrary,
+    nets: Vec<String>,
+    num_port_nets: usize,
+    inputs: Vec<(String, usize)>,
+    outputs: Vec<(String, Vec<usize>)>,
+    instances: Vec<CellInstance>,
+}
+
+/// The area and timing of a [MappedNetlist]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CellReport {
+    /// The total area in the units of the library
+    pub area: f64,
+    /// The longest delay from an input port or flip-flop output to an output
+    /// port or flip-flop input, in the units of the library
+    pub critical_delay: f64,
+    /// The number of instances of each used cell, sorted by name
+    pub cell_counts: Vec<(String, usize)>,
+}
+
+/// Writes one line per cell with its count and total area, and then the
+/// total area and critical delay
+impl fmt::Display for CellReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, count) in &self.cell_counts {
+            writeln!(f, "{name:<16} {count:>8}")?;
+        }
+        writeln!(f, "area {}", self.area)?;
+        writeln!(f, "critical delay {}", self.critical_delay)
+    }
+}
+
+impl MappedNetlist {
+    pub fn library(&self) -> &CellLibrary {
+        &self.library
+    }
+
+    /// Returns the names of the nets, input port bits are named like `x[0]`
+    pub fn nets(&self) -> &[String] {
+        &self.nets
+    }
+
+    /// Returns the names and bitwidths of the input ports
+    pub fn inputs(&self) -> &[(String, usize)] {
+        &self.inputs
+    }
+
+    /// Returns the names of the output ports and the nets of their bits
+    pub fn outputs(&self) -> &[(String, Vec<usize>)] {
+        &self.outputs
+    }
+
+    /// Returns the cell instances in topological order of the combinational
+    /// cells
+    pub fn instances(&self) -> &[CellInstance] {
+        &self.instances
+    }
+
+    /// Calculates the [CellReport]
+    pub fn report(&self) -> CellReport {
+        let mut area = 0.0;
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        // the instances are in topological order except for flip-flops, which
+        // start paths with their clock to output delay
+        let mut arrival = vec![0.0f64; self.nets.len()];
+        let mut critical_delay = 0.0f64;
+        for instance in &self.instances {
+            let cell = &self.library.cells[instance.cell];
+            area += cell.area;
+            *counts.entry(&cell.name).or_default() += 1;
+            if cell.function == CellFunction::Dff {
+                arrival[instance.output] = cell.delay;
+            } else {
+                let max = instance
+                    .inputs
+                    .iter()
+                    .map(|net| arrival[*net])
+                    .fold(0.0, f64::max);
+                arrival[instance.output] = max + cell.delay;
+            }
+        }
+        for instance in &self.instances {
+            if self.library.cells[instance.cell].function == CellFunction::Dff {
+                critical_delay = critical_delay.max(arrival[instance.inputs[0]]);
+            }
+        }
+        for (_, nets) in &self.outputs {
+            for net in nets {
+                critical_delay = critical_delay.max(arrival[*net]);
+            }
+        }
+        let mut cell_counts: Vec<(String, usize)> = counts
+            .into_iter()
+            .map(|(name, count)| (name.to_owned(), count))
+            .collect();
+        cell_counts.sort_unstable();
+        CellReport {
+            area,
+            critical_delay,
+            cell_counts,
+        }
+    }
+
+    /// Writes the netlist as a structural Verilog module named `module`, with
+    /// a `clock` input port if there are flip-flops
+    pub fn write_verilog(&self, module: &str, clock: &str) -> String {
+        let module = sanitize(module);
+        let clock = sanitize(clock);
+        let has_clock = self
+            .instances
+            .iter()
+            .any(|instance| self.library.cells[instance.cell].function == CellFunction::Dff);
+        let mut ports: Vec<&str> = vec![];
+        if has_clock {
+            ports.push(&clock);
+        }
+        ports.extend(self.inputs.iter().map(|(name, _)| name.as_str()));
+        ports.extend(self.outputs.iter().map(|(name, _)| name.as_str()));
+        let mut res = String::new();
+        writeln!(res, "module {module}({});", ports.join(", ")).unwrap();
+        if has_clock {
+            writeln!(res, "  input {clock};").unwrap();
+        }
+        for (name, w) in &self.inputs {
+            writeln!(res, "  input [{}:0] {name};", w - 1).unwrap();
+        }
+        for (name, nets) in &self.outputs {
+            writeln!(res, "  output [{}:0] {name};", nets.len() - 1).unwrap();
+        }
+        for net in &self.nets[self.num_port_nets..] {
+            writeln!(res, "  wire {net};").unwrap();
+        }
+        for (i, instance) in self.instances.iter().enumerate() {
+            let cell = &self.library.cells[instance.cell];
+            write!(res, "  {} g{i} (", cell.name).unwrap();
+            for (pin, net) in cell.inputs.iter().zip(instance.inputs.iter()) {
+                write!(res, ".{pin}({}), ", self.nets[*net]).unwrap();
+            }
+            if let Some(ref pin) = cell.clock {
+                write!(res, ".{pin}({clock}), ").unwrap();
+            }
+            writeln!(res, ".{}({}));", cell.output, self.nets[instance.output]).unwrap();
+        }
+        for (name, nets) in &self.outputs {
+            for (j, net) in nets.iter().enumerate() {
+                writeln!(res, "  assign {name}[{j}] = {};", self.nets[*net]).unwrap();
+            }
+        }
+        res.push_str("endmodule\n");
+        res
+    }
+}
+
+/// Replaces characters that are not allowed in simple Verilog identifiers
+fn sanitize(name: &str) -> String {
+    let mut res: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !res.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        res.insert(0, '_');
+    }
+    res
+}
+
+/// A literal of an [Aig] node, the least significant bit is set if it is
+/// complemented
+type Lit = usize;
+
+const FALSE: Lit = 0;
+const TRUE: Lit = 1;
+
+#[derive(Debug, Clone, Copy)]
+enum AigNode {
+    /// Node 0 is constant zero
+    Const,
+    /// A net that is an input port bit or a flip-flop output
+    Leaf(usize),
+    And(Lit, Lit),
+}
+
+/// A structurally hashed And-Inverter graph
+#[derive(Debug)]
+struct Aig {
+    nodes: Vec<AigNode>,
+    hash: HashMap<(Lit, Lit), Lit>,
+}
+
+impl Aig {
+    fn new() -> Self {
+        Self {
+            nodes: vec![AigNode::Const],
+            hash: HashMap::new(),
+        }
+    }
+
+    fn leaf(&mut self, net: usize) -> Lit {
+        self.nodes.push(AigNode::Leaf(net));
+        (self.nodes.len() - 1) << 1
+    }
+
+    fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        if (a == FALSE) || (a == (b ^ 1)) {
+            return FALSE
+        }
+        if (a == TRUE) || (a == b) {
+            return b
+        }
+        if let Some(lit) = self.hash.get(&(a, b)) {
+            return *lit
+        }
+        self.nodes.push(AigNode::And(a, b));
+        let lit = (self.nodes.len() - 1) << 1;
+        self.hash.insert((a, b), lit);
+        lit
+    }
+
+    fn or(&mut self, a: Lit, b: Lit) -> Lit {
+        self.and(a ^ 1, b ^ 1) ^ 1
+    }
+
+    fn mux(&mut self, s: Lit, hi: Lit, lo: Lit) -> Lit {
+        if hi == lo {
+            return hi
+        }
+        let hi = self.and(s, hi);
+        let lo = self.and(s ^ 1, lo);
+        self.or(hi, lo)
+    }
+
+    /// Returns the table entry of `entries` selected by `index`, with the
+    /// first index literal being the least significant bit
+    fn select(&mut self, entries: &[Lit], index: &[Lit]) -> Lit {
+        let Some((s, rest)) = index.split_last() else {
+            return entries[0]
+        };
+        let (lo, hi) = entries.split_at(entries.len() / 2);
+        let lo = self.select(lo, rest);
+        let hi = self.select(hi, rest);
+        self.mux(*s, hi, lo)
+    }
+}
+
+/// What drives a net of an equivalence
+#[derive(Debug, Clone, Copy)]
+enum Driver {
+    Const(bool),
+    /// An input port bit or the output of a chain of flip-flops
+    Leaf(usize),
+    LNode(PLNode),
+    /// A zero delay `TNode` from another net
+    Wire(usize),
+}
+
+/// Assigns cells to the nodes of an [Aig] that are needed for the demanded
+/// literals
+struct CellMapper<'a> {
+    aig: &'a Aig,
+    library: &'a CellLibrary,
+    inv: usize,
+    nand2: usize,
+    nor2: Option<usize>,
+    pos: Vec<Option<usize>>,
+    neg: Vec<Option<usize>>,
+    nets: Vec<String>,
+    instances: Vec<CellInstance>,
+}
+
+impl CellMapper<'_> {
+    fn new_net(&mut self) -> usize {
+        self.nets.push(format!("n{}", self.nets.len()));
+        self.nets.len() - 1
+    }
+
+    fn instance(&mut self, cell: usize, inputs: Vec<usize>) -> usize {
+        let output = self.new_net();
+        self.instances.push(CellInstance {
+            cell,
+            inputs,
+            output,
+        });
+        output
+    }
+
+    /// Returns the net of `lit`, inverting the other polarity if needed. The
+    /// node must already have one polarity.
+    fn signal(&mut self, lit: Lit) -> usize {
+        let node = lit >> 1;
+        let (want, other) = if (lit & 1) == 0 {
+            (self.pos[node], self.neg[node])
+        } else {
+            (self.neg[node], self.pos[node])
+        };
+        if let Some(net) = want {
+            return net
+        }
+        let net = self.instance(self.inv, vec![other.unwrap()]);
+        if (lit & 1) == 0 {
+            self.pos[node] = Some(net);
+        } else {
+            self.neg[node] = Some(net);
+        }
+        net
+    }
+
+    /// Maps the `And` nodes that `roots` depend on
+    fn map(&mut self, roots: &[Lit]) {
+        // the polarities needed of each node, found in reverse topological
+        // order so that the gate of a node can be chosen to match its consumers
+        let mut demand = vec![[false; 2]; self.aig.nodes.len()];
+        for root in roots {
+            demand[root >> 1][root & 1] = true;
+        }
+        let mut use_nor = vec![false; self.aig.nodes.len()];
+        for node in (0..self.aig.nodes.len()).rev() {
+            let [pos, neg] = demand[node];
+            if let (true, AigNode::And(a, b)) = (pos || neg, self.aig.nodes[node]) {
+                // `!a & !b` is `NOR(a, b)`, which is used unless only the
+                // complement is needed
+                if self.nor2.is_some() && ((a & b & 1) == 1) && pos {
+                    use_nor[node] = true;
+                    demand[a >> 1][0] = true;
+                    demand[b >> 1][0] = true;
+                } else {
+                    demand[a >> 1][a & 1] = true;
+                    demand[b >> 1][b & 1] = true;
+                }
+            }
+        }
+        // nodes are created after their operands
+        for node in 0..self.aig.nodes.len() {
+            if demand[node] == [false; 2] {
+                continue
+            }
+            match self.aig.nodes[node] {
+                AigNode::Const => {
+                    self.pos[node] = Some(0);
+                    self.neg[node] = Some(1);
+                }
+                AigNode::Leaf(net) => self.pos[node] = Some(net),
+                AigNode::And(a, b) => {
+                    if use_nor[node] {
+                        let inputs = vec![self.signal(a ^ 1), self.signal(b ^ 1)];
+                        self.pos[node] = Some(self.instance(self.nor2.unwrap(), inputs));
+                    } else {
+                        let inputs = vec![self.signal(a), self.signal(b)];
+                        self.neg[node] = Some(self.instance(self.nand2, inputs));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Ensemble {
+    /// Maps `self` to the standard cells of `library` instead of lookup
+    /// tables, for ASIC flows. This should be used after
+    /// [Epoch::optimize](crate::Epoch::optimize).
+    ///
+    /// Every `LNode` in the fan-in of the read only `RNode`s and registers is
+    /// decomposed into a structurally hashed And-Inverter graph, which is
+    /// mapped to NAND2 cells, NOR2 cells where both inputs of an AND are
+    /// inverted and its noninverted output is needed, and INV cells, using
+    /// the smallest cell of each function.
+    /// Zero delay `TNode`s become wires, and every unit of a nonzero delay
+    /// becomes a DFF cell. Initial values of registers are ignored, use
+    /// [Ensemble::add_reset] beforehand if they are needed. Writable
+    /// `RNode`s become input ports and read only `RNode`s become output
+    /// ports, named like in [Ensemble::write_blif]. `RNode`s that have not
+    /// been initialized are skipped, and `MulNode`s and `RomNode`s are mapped
+    /// as their `LNode` decompositions.
+    ///
+    /// # Errors
+    ///
+    /// If the library does not have INV and NAND2 cells (or a DFF cell when
+    /// there are registers), if there are `BlackBox`es, dynamic tables with
+    /// unknown entries, or combinational loops, or if a net that is not
+    /// constant does not have exactly one driver
+    pub fn map_to_cells(&self, library: &CellLibrary) -> Result<MappedNetlist, Error> {
+        let missing = |function: CellFunction| {
+            Error::OtherString(format!(
+                "could not map to cells, the library has no {function} cell"
+            ))
+        };
+        let inv = library
+            .smallest(CellFunction::Inv)
+            .ok_or_else(|| missing(CellFunction::Inv))?;
+        let nand2 = library
+            .smallest(CellFunction::Nand2)
+            .ok_or_else(|| missing(CellFunction::Nand2))?;
+        let nor2 = library.smallest(CellFunction::Nor2);
+        if !self.blackboxes.is_empty() {
+            return Err(Error::OtherStr(
+                "could not map to cells, `BlackBox`es are not supported",
+            ))
+        }
+
+        let mut ids: HashMap<PBack, usize> = HashMap::new();
+        let mut drivers: Vec<Option<Driver>> = vec![];
+        let mut num_drivers: Vec<usize> = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                ids.insert(p_back, drivers.len());
+                drivers.push(match self.backrefs.get_val(p_back).unwrap().val {
+                    Value::Const(b) => Some(Driver::Const(b)),
+                    Value::ConstUnknown => Some(Driver::Const(false)),
+                    Value::Unknown | Value::Dynam(_) => None,
+                });
+                num_drivers.push(0);
+            }
+        }
+        let e = |p_back: PBack| ids[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+
+        let mut nets = vec!["1'b0".to_owned(), "1'b1".to_owned()];
+        let mut inputs = vec![];
+        let mut used_names: HashSet<String> = HashSet::new();
+        let mut output_bits: Vec<(String, Vec<usize>)> = vec![];
+        // `MulNode`s and `RomNode`s are mapped as their decompositions
+        let macro_op_ports = self.macro_op_p_externals();
+        for (i, (_, p_external, rnode)) in self.notary.rnodes().into_iter().enumerate() {
+            if macro_op_ports.contains(p_external) {
+                continue
+            }
+            // nothing can depend on `RNode`s that have not been initialized, this
+            // includes assertion bits with pruned states
+            let Some(bits) = rnode.bits() else { continue };
+            let prefix = if rnode.read_only() { "out" } else { "in" };
+            let mut name = rnode
+                .debug_name()
+                .map(sanitize)
+                .unwrap_or_else(|| format!("{prefix}{i}"));
+            if !used_names.insert(name.clone()) {
+                name = format!("{prefix}{i}");
+                used_names.insert(name.clone());
+            }
+            if rnode.read_only() {
+                let bits = bits
+                    .iter()
+                    .map(|bit| bit.map(e).unwrap_or(usize::MAX))
+                    .collect();
+                output_bits.push((name, bits));
+            } else {
+                for (j, bit) in bits.iter().enumerate() {
+                    nets.push(format!("{name}[{j}]"));
+                    if let Some(bit) = bit {
+                        let id = e(*bit);
+                        num_drivers[id] += 1;
+                        drivers[id].get_or_insert(Driver::Leaf(nets.len() - 1));
+                    }
+                }
+                inputs.push((name, bits.len()));
+            }
+        }
+        let num_port_nets = nets.len();
+        for (p_lnode, lnode) in &self.lnodes {
+            let id = e(lnode.p_self);
+            num_drivers[id] += 1;
+            drivers[id].get_or_insert(Driver::LNode(p_lnode));
+        }
+        // the nets of the flip-flop outputs are allocated first
+        let mut registers = vec![];
+        for tnode in self.tnodes.vals() {
+            let id = e(tnode.p_self);
+            num_drivers[id] += 1;
+            if tnode.delay().is_zero() {
+                drivers[id].get_or_insert(Driver::Wire(e(tnode.p_driver)));
+            } else if drivers[id].is_none() {
+                nets.push(format!("n{}", nets.len()));
+                drivers[id] = Some(Driver::Leaf(nets.len() - 1));
+                registers.push((nets.len() - 1, e(tnode.p_driver), tnode.delay().amount()));
+            }
+        }
+        for (id, driver) in drivers.iter().enumerate() {
+            if !matches!(driver, Some(Driver::Const(_))) && (num_drivers[id] != 1) {
+                return Err(Error::OtherString(format!(
+                    "could not map to cells, an equivalence has {} drivers",
+                    num_drivers[id]
+                )))
+            }
+        }
+        let dff = if registers.is_empty() {
+            None
+        } else {
+            Some(
+                library
+                    .smallest(CellFunction::Dff)
+                    .ok_or_else(|| missing(CellFunction::Dff))?,
+            )
+        };
+
+        // build the literals of the needed equivalences in topological order
+        let mut aig = Aig::new();
+        let mut lits: Vec<Option<Lit>> = vec![None; drivers.len()];
+        let mut on_stack = vec![false; drivers.len()];
+        let mut stack: Vec<(usize, bool)> = vec![];
+        for (_, bits) in &output_bits {
+            stack.extend(
+                bits.iter()
+                    .filter(|id| **id != usize::MAX)
+                    .map(|id| (*id, false)),
+            );
+        }
+        stack.extend(registers.iter().map(|(_, id, _)| (*id, false)));
+        let lnode_deps = |lnode: &LNode| {
+            let mut deps = vec![];
+            lnode.inputs(|p_inp| deps.push(e(p_inp)));
+            deps
+        };
+        while let Some((id, expanded)) = stack.pop() {
+            if lits[id].is_some() {
+                continue
+            }
+            let deps = match drivers[id].unwrap() {
+                Driver::Const(b) => {
+                    lits[id] = Some(if b { TRUE } else { FALSE });
+                    continue
+                }
+                Driver::Leaf(net) => {
+                    lits[id] = Some(aig.leaf(net));
+                    continue
+                }
+                Driver::LNode(p_lnode) => lnode_deps(&self.lnodes[p_lnode]),
+                Driver::Wire(driver) => vec![driver],
+            };
+            if !expanded {
+                if on_stack[id] {
+                    return Err(Error::OtherStr(
+                        "could not map to cells, there is a combinational loop",
+                    ))
+                }
+                on_stack[id] = true;
+                stack.push((id, true));
+                for dep in deps {
+                    if lits[dep].is_none() {
+                        if on_stack[dep] {
+                            return Err(Error::OtherStr(
+                                "could not map to cells, there is a combinational loop",
+                            ))
+                        }
+                        stack.push((dep, false));
+                    }
+                }
+                continue
+            }
+            on_stack[id] = false;
+            let lit = |p: &PBack| lits[e(*p)].unwrap();
+            let res = match drivers[id].unwrap() {
+                Driver::Wire(driver) => lits[driver].unwrap(),
+                Driver::LNode(p_lnode) => match self.lnodes[p_lnode].kind {
+                    LNodeKind::Copy(inp) => lit(&inp),
+                    LNodeKind::Lut(ref inp, ref table) => {
+                        let entries: Vec<Lit> = (0..table.bw())
+                            .map(|i| if table.get(i).unwrap() { TRUE } else { FALSE })
+                            .collect();
+                        let index: Vec<Lit> = inp.iter().map(lit).collect();
+                        aig.select(&entries, &index)
+                    }
+                    LNodeKind::Carry(ref inp) => {
+                        let table = LNode::carry_lut();
+                        let entries: Vec<Lit> = (0..table.bw())
+                            .map(|i| if table.get(i).unwrap() { TRUE } else { FALSE })
+                            .collect();
+                        let index: Vec<Lit> = inp.iter().map(lit).collect();
+                        aig.select(&entries, &index)
+                    }
+                    LNodeKind::DynamicLut(ref inp, ref lut) => {
+                        let mut entries = vec![];
+                        for entry in lut {
+                            entries.push(match entry {
+                                DynamicValue::Const(b) => {
+                                    if *b {
+                                        TRUE
+                                    } else {
+                                        FALSE
+                                    }
+                                }
+                                DynamicValue::Dynam(p) => lit(p),
+                                DynamicValue::ConstUnknown => {
+                                    return Err(Error::OtherStr(
+                                        "could not map to cells, a dynamic table has unknown \
+                                         entries",
+                                    ))
+                                }
+                            });
+                        }
+                        let index: Vec<Lit> = inp.iter().map(lit).collect();
+                        aig.select(&entries, &index)
+                    }
+                },
+                Driver::Const(_) | Driver::Leaf(_) => unreachable!(),
+            };
+            lits[id] = Some(res);
+        }
+
+        let mut roots: Vec<Lit> = vec![];
+        for (_, bits) in &output_bits {
+            roots.extend(
+                bits.iter()
+                    .filter(|id| **id != usize::MAX)
+                    .map(|id| lits[*id].unwrap()),
+            );
+        }
+        roots.extend(registers.iter().map(|(_, id, _)| lits[*id].unwrap()));
+        let mut mapper = CellMapper {
+            aig: &aig,
+            library,
+            inv,
+            nand2,
+            nor2,
+            pos: vec![None; aig.nodes.len()],
+            neg: vec![None; aig.nodes.len()],
+            nets,
+            instances: vec![],
+        };
+        mapper.map(&roots);
+        let outputs = output_bits
+            .into_iter()
+            .map(|(name, bits)| {
+                let nets = bits
+                    .into_iter()
+                    .map(|id| {
+                        if id == usize::MAX {
+                            0
+                        } else {
+                            mapper.signal(lits[id].unwrap())
+                        }
+                    })
+                    .collect();
+                (name, nets)
+            })
+            .collect();
+        for (q, id, delay) in registers {
+            // a chain of flip-flops for longer delays
+            let mut prev = mapper.signal(lits[id].unwrap());
+            for _ in 1..delay {
+                prev = mapper.instance(dff.unwrap(), vec![prev]);
+            }
+            mapper.instances.push(CellInstance {
+                cell: dff.unwrap(),
+                inputs: vec![prev],
+                output: q,
+            });
+        }
+        Ok(MappedNetlist {
+            library: mapper.library.clone(),
+            nets: mapper.nets,
+            num_port_nets,
+            inputs,
+            outputs,
+            instances: mapper.instances,
+        })
+    }
+}
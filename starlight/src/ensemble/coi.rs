@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Ensemble, PBack, PExternal, Referent},
+    Error,
+};
+
+impl Ensemble {
+    /// Returns the equivalences in the cone of influence of `properties`,
+    /// which is everything that can affect them at any time. Unlike
+    /// [Ensemble::fanin_cone], this goes through the drivers of `TNode`s
+    /// regardless of their delay, so the registers that the properties depend
+    /// on and everything driving those registers are included.
+    fn cone_of_influence(&self, properties: &[PExternal]) -> Result<HashSet<PBack>, Error> {
+        let mut cone = HashSet::new();
+        let mut stack = vec![];
+        for p_external in properties.iter().copied() {
+            let (_, rnode) = self.notary.get_rnode(p_external)?;
+            let bits = rnode.bits().ok_or(Error::OtherStr(
+                "a property `RNode` has not been initialized",
+            ))?;
+            for p_bit in bits.iter().flatten() {
+                stack.push(self.backrefs.get_val(*p_bit).unwrap().p_self_equiv);
+            }
+        }
+        while let Some(p_equiv) = stack.pop() {
+            if !cone.insert(p_equiv) {
+                continue
+            }
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_back) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_back).unwrap() {
+                    Referent::ThisLNode(p_lnode) => {
+                        stack.extend(self.lnode_input_equivs(p_lnode));
+                    }
+                    Referent::ThisTNode(p_tnode) => {
+                        let p_driver = self.tnodes[p_tnode].p_driver;
+                        stack.push(self.backrefs.get_val(p_driver).unwrap().p_self_equiv);
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(cone)
+    }
+
+    /// Removes everything that is not in the cone of influence of the `RNode`s
+    /// of `properties`, including registers and their pending events. Other
+    /// `RNode`s are removed entirely if none of their bits are in the cone,
+    /// otherwise only their bits outside of the cone are removed. The
+    /// remaining `LazyAwi`s are the only inputs that can affect the
+    /// properties, and the behavior of the properties is unchanged. This
+    /// should be done on a lowered `Ensemble`, all states are removed and
+    /// [Ensemble::optimize_all] is run at the end.
+    ///
+    /// # Errors
+    ///
+    /// If any of `properties` are invalid or uninitialized, or if optimization
+    /// fails
+    pub fn coi_reduce(&mut self, properties: &[PExternal]) -> Result<(), Error> {
+        let cone = self.cone_of_influence(properties)?;
+        self.restart_request_phase()?;
+        let mut rnodes_to_remove = vec![];
+        let mut bits_to_remove = vec![];
+        for (p_rnode, _, rnode) in self.notary.rnodes() {
+            let Some(bits) = rnode.bits() else {
+                rnodes_to_remove.push(p_rnode);
+                continue
+            };
+            let mut any_in_cone = false;
+            for (i, p_bit) in bits.iter().enumerate() {
+                if let Some(p_bit) = p_bit {
+                    if cone.contains(&self.backrefs.get_val(*p_bit).unwrap().p_self_equiv) {
+                        any_in_cone = true;
+                    } else {
+                        bits_to_remove.push((p_rnode, i));
+                    }
+                }
+            }
+            if !any_in_cone {
+                rnodes_to_remove.push(p_rnode);
+            }
+        }
+        for (p_rnode, i) in bits_to_remove {
+            if rnodes_to_remove.contains(&p_rnode) {
+                continue
+            }
+            let rnode = self.notary.get_rnode_by_p_rnode_mut(p_rnode).unwrap();
+            let p_bit = rnode.bits_mut().unwrap()[i].take().unwrap();
+            self.backrefs.remove_key(p_bit).unwrap();
+        }
+        for p_rnode in rnodes_to_remove {
+            self.remove_rnode(p_rnode);
+        }
+        let mut adv = self.lnodes.advancer();
+        while let Some(p_lnode) = adv.advance(&self.lnodes) {
+            let p_self = self.lnodes[p_lnode].p_self;
+            if !cone.contains(&self.backrefs.get_val(p_self).unwrap().p_self_equiv) {
+                self.remove_lnode_not_p_self(p_lnode);
+                self.backrefs.remove_key(p_self).unwrap();
+            }
+        }
+        let mut adv = self.tnodes.advancer();
+        while let Some(p_tnode) = adv.advance(&self.tnodes) {
+            let p_self = self.tnodes[p_tnode].p_self;
+            if !cone.contains(&self.backrefs.get_val(p_self).unwrap().p_self_equiv) {
+                self.remove_tnode_not_p_self(p_tnode);
+                self.backrefs.remove_key(p_self).unwrap();
+            }
+        }
+        self.optimize_all()
+    }
+}
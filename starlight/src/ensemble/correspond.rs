@@ -1,5 +1,5 @@
 use core::fmt;
-use std::num::NonZeroUsize;
+use std::{num::NonZeroUsize, ops::Range};
 
 use awint::awint_dag::triple_arena::{Advancer, OrdArena, SurjectArena};
 
@@ -13,6 +13,8 @@ use crate::{
 pub struct Corresponder {
     a: OrdArena<PMeta, PExternal, PCorrespond>,
     c: SurjectArena<PCorrespond, PMeta, NonZeroUsize>,
+    // correspondences between bit ranges, these are not transitive
+    bits: Vec<(PExternal, Range<usize>, PExternal, Range<usize>)>,
 }
 
 impl Clone for Corresponder {
@@ -20,6 +22,7 @@ impl Clone for Corresponder {
         Self {
             a: self.a.clone(),
             c: self.c.clone(),
+            bits: self.bits.clone(),
         }
     }
 }
@@ -29,15 +32,65 @@ impl fmt::Debug for Corresponder {
         f.debug_struct("Corresponder")
             .field("a", &self.a)
             .field("c", &self.c)
+            .field("bits", &self.bits)
             .finish()
     }
 }
 
+/// Checks that `range0` and `range1` are nonempty, have the same length, and
+/// are within the bitwidths `w0` and `w1`
+fn check_ranges(
+    w0: NonZeroUsize,
+    range0: &Range<usize>,
+    w1: NonZeroUsize,
+    range1: &Range<usize>,
+) -> Result<(), Error> {
+    for (w, range) in [(w0, range0), (w1, range1)] {
+        if range.is_empty() || (range.end > w.get()) {
+            return Err(Error::OtherString(format!(
+                "bit range {range:?} is empty or out of bounds for a bitwidth of {w}"
+            )))
+        }
+    }
+    if range0.len() != range1.len() {
+        return Err(Error::BitwidthMismatch(range0.len(), range1.len()))
+    }
+    Ok(())
+}
+
+/// Returns the bit correspondences of packing a value of width `w` across
+/// values of the widths `targets`, as tuples of the range of the value and the
+/// index of the target. The range of the target always starts at 0.
+fn pack_ranges(
+    w: NonZeroUsize,
+    targets: &[NonZeroUsize],
+) -> Result<Vec<(Range<usize>, usize)>, Error> {
+    let total: usize = targets.iter().map(|w| w.get()).sum();
+    if total < w.get() {
+        return Err(Error::OtherString(format!(
+            "cannot pack a value with a bitwidth of {w} into targets with a total bitwidth of \
+             {total}"
+        )))
+    }
+    let mut v = vec![];
+    let mut start = 0;
+    for (target_i, target_w) in targets.iter().enumerate() {
+        if start >= w.get() {
+            break
+        }
+        let len = target_w.get().min(w.get() - start);
+        v.push((start..(start + len), target_i));
+        start += len;
+    }
+    Ok(v)
+}
+
 impl Corresponder {
     pub fn new() -> Self {
         Self {
             a: OrdArena::new(),
             c: SurjectArena::new(),
+            bits: vec![],
         }
     }
 
@@ -112,6 +165,122 @@ impl Corresponder {
         }
     }
 
+    /// Corresponds the bits `range0` of `l0` with the bits `range1` of `l1`,
+    /// for when a wide `LazyAwi` is split across multiple narrower ones. Unlike
+    /// [Corresponder::correspond_lazy], these correspondences are only between
+    /// the two and are not transitive.
+    ///
+    /// # Errors
+    ///
+    /// If the ranges are empty, out of bounds, or have different lengths
+    pub fn correspond_lazy_bits<
+        L0: std::borrow::Borrow<LazyAwi>,
+        L1: std::borrow::Borrow<LazyAwi>,
+    >(
+        &mut self,
+        l0: &L0,
+        range0: Range<usize>,
+        l1: &L1,
+        range1: Range<usize>,
+    ) -> Result<(), Error> {
+        let (l0, l1) = (l0.borrow(), l1.borrow());
+        check_ranges(l0.nzbw(), &range0, l1.nzbw(), &range1)?;
+        self.bits
+            .push((l0.p_external(), range0, l1.p_external(), range1));
+        Ok(())
+    }
+
+    /// Corresponds the bits `range0` of `e0` with the bits `range1` of `e1`,
+    /// see [Corresponder::correspond_lazy_bits]
+    ///
+    /// # Errors
+    ///
+    /// If the ranges are empty, out of bounds, or have different lengths
+    pub fn correspond_eval_bits<
+        E0: std::borrow::Borrow<EvalAwi>,
+        E1: std::borrow::Borrow<EvalAwi>,
+    >(
+        &mut self,
+        e0: &E0,
+        range0: Range<usize>,
+        e1: &E1,
+        range1: Range<usize>,
+    ) -> Result<(), Error> {
+        let (e0, e1) = (e0.borrow(), e1.borrow());
+        check_ranges(e0.nzbw(), &range0, e1.nzbw(), &range1)?;
+        self.bits
+            .push((e0.p_external(), range0, e1.p_external(), range1));
+        Ok(())
+    }
+
+    /// Corresponds the bits of `l` with the bits of `targets` packed together,
+    /// starting with the least significant bits of `l` and the first target.
+    /// This is for when the widths of a program value and of target values do
+    /// not match, e.x. a 13 bit program input can be packed into two 8 bit
+    /// target inputs, leaving the 3 most significant bits of the second target
+    /// input uncorresponded. This uses [Corresponder::correspond_lazy_bits].
+    ///
+    /// # Errors
+    ///
+    /// If the total bitwidth of `targets` is less than that of `l`
+    pub fn correspond_lazy_packed<
+        L0: std::borrow::Borrow<LazyAwi>,
+        L1: std::borrow::Borrow<LazyAwi>,
+    >(
+        &mut self,
+        l: &L0,
+        targets: &[&L1],
+    ) -> Result<(), Error> {
+        let l = l.borrow();
+        let widths: Vec<NonZeroUsize> = targets.iter().map(|t| (*t).borrow().nzbw()).collect();
+        for (range, target_i) in pack_ranges(l.nzbw(), &widths)? {
+            let len = range.len();
+            self.correspond_lazy_bits(l, range, targets[target_i], 0..len)?;
+        }
+        Ok(())
+    }
+
+    /// Corresponds the bits of `e` with the bits of `targets` packed together,
+    /// see [Corresponder::correspond_lazy_packed]
+    ///
+    /// # Errors
+    ///
+    /// If the total bitwidth of `targets` is less than that of `e`
+    pub fn correspond_eval_packed<
+        E0: std::borrow::Borrow<EvalAwi>,
+        E1: std::borrow::Borrow<EvalAwi>,
+    >(
+        &mut self,
+        e: &E0,
+        targets: &[&E1],
+    ) -> Result<(), Error> {
+        let e = e.borrow();
+        let widths: Vec<NonZeroUsize> = targets.iter().map(|t| (*t).borrow().nzbw()).collect();
+        for (range, target_i) in pack_ranges(e.nzbw(), &widths)? {
+            let len = range.len();
+            self.correspond_eval_bits(e, range, targets[target_i], 0..len)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all bit range correspondences with `p_external`, as tuples of
+    /// the range of `p_external`, the other `PExternal`, and the range of the
+    /// other
+    pub fn bit_correspondences(
+        &self,
+        p_external: PExternal,
+    ) -> Vec<(Range<usize>, PExternal, Range<usize>)> {
+        let mut v = vec![];
+        for (p0, range0, p1, range1) in &self.bits {
+            if *p0 == p_external {
+                v.push((range0.clone(), *p1, range1.clone()));
+            } else if *p1 == p_external {
+                v.push((range1.clone(), *p0, range0.clone()));
+            }
+        }
+        v
+    }
+
     /// Returns a vector of `LazyAwi`s for everything that was
     /// corresponded with `l` and is usable with the currently active `Epoch`.
     pub fn correspondences_lazy<L: std::borrow::Borrow<LazyAwi>>(
@@ -0,0 +1,348 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+use awint::awint_dag::{triple_arena::Advancer, Location, PState};
+
+use crate::ensemble::{Ensemble, PBack, PLNode, RNode, Referent};
+
+pub(crate) type LocationKey = Option<(&'static str, u32, u32)>;
+
+pub(crate) fn to_key(location: Option<Location>) -> LocationKey {
+    location.map(|location| (location.file, location.line, location.col))
+}
+
+pub(crate) fn from_key(key: LocationKey) -> Option<Location> {
+    key.map(|(file, line, col)| Location { file, line, col })
+}
+
+/// What a [DeadReport] or [crate::ensemble::PowerReport] attributes logic to.
+/// Ordinary operations do not record source locations, so logic is usually
+/// attributed to the nearest `RNode` (from a `LazyAwi` or some other handle)
+/// feeding it.
+#[derive(Debug, Clone)]
+pub enum Attribution {
+    /// The source location of the state that the logic came from, which is
+    /// only known for some states such as assertions
+    Location(Location),
+    /// The nearest `RNode` feeding the logic, with its debug name if it has one
+    /// and the location where it was created
+    RNode {
+        debug_name: Option<String>,
+        location: Option<Location>,
+    },
+    /// Nothing could be found, e.g. for logic fed only by constants
+    Unknown,
+}
+
+impl fmt::Display for Attribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Attribution::Location(location) => write_location(f, Some(*location)),
+            Attribution::RNode {
+                debug_name,
+                location,
+            } => {
+                write!(f, "the logic fed by ")?;
+                if let Some(debug_name) = debug_name {
+                    write!(f, "\"{debug_name}\" created at ")?;
+                } else {
+                    write!(f, "the `RNode` created at ")?;
+                }
+                write_location(f, *location)
+            }
+            Attribution::Unknown => write!(f, "(unknown source)"),
+        }
+    }
+}
+
+/// The hashable form of an [Attribution]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum AttributionKey {
+    Location((&'static str, u32, u32)),
+    RNode(Option<String>, LocationKey),
+    Unknown,
+}
+
+impl AttributionKey {
+    pub(crate) fn from_rnode(rnode: &RNode) -> Self {
+        AttributionKey::RNode(
+            rnode.debug_name().map(str::to_owned),
+            to_key(rnode.location()),
+        )
+    }
+
+    pub(crate) fn to_attribution(&self) -> Attribution {
+        match self {
+            AttributionKey::Location(key) => Attribution::Location(from_key(Some(*key)).unwrap()),
+            AttributionKey::RNode(debug_name, location) => Attribution::RNode {
+                debug_name: debug_name.clone(),
+                location: from_key(*location),
+            },
+            AttributionKey::Unknown => Attribution::Unknown,
+        }
+    }
+}
+
+/// Sorts by descending count, then by attribution
+fn sorted(map: &HashMap<AttributionKey, usize>) -> Vec<(Attribution, usize)> {
+    let mut v: Vec<(&AttributionKey, usize)> = map.iter().map(|(k, n)| (k, *n)).collect();
+    v.sort_unstable_by(|lhs, rhs| rhs.1.cmp(&lhs.1).then(lhs.0.cmp(rhs.0)));
+    v.into_iter()
+        .map(|(k, n)| (k.to_attribution(), n))
+        .collect()
+}
+
+/// A report of logic that was removed because nothing used it, grouped by
+/// [Attribution]. This is collected while enabled with
+/// [crate::Epoch::start_dead_report], and is useful for discovering things
+/// like a whole module's outputs accidentally being left unconnected.
+#[derive(Debug, Clone, Default)]
+pub struct DeadReport {
+    pruned_states: HashMap<AttributionKey, usize>,
+    removed_lnodes: HashMap<AttributionKey, usize>,
+    // snapshot of `LNode` attributions taken before optimization removes the
+    // states and starts changing the equivalences
+    lnode_attributions: HashMap<PLNode, AttributionKey>,
+}
+
+impl DeadReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of states removed by `prune_unused_states` for each
+    /// attribution, sorted by descending count
+    pub fn pruned_states(&self) -> Vec<(Attribution, usize)> {
+        sorted(&self.pruned_states)
+    }
+
+    /// Returns the number of `LNode`s removed by optimization because their
+    /// outputs were unused for each attribution, sorted by descending count
+    pub fn removed_lnodes(&self) -> Vec<(Attribution, usize)> {
+        sorted(&self.removed_lnodes)
+    }
+
+    pub fn total_pruned_states(&self) -> usize {
+        self.pruned_states.values().sum()
+    }
+
+    pub fn total_removed_lnodes(&self) -> usize {
+        self.removed_lnodes.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pruned_states.is_empty() && self.removed_lnodes.is_empty()
+    }
+
+    pub(crate) fn record_pruned_state(&mut self, key: AttributionKey) {
+        *self.pruned_states.entry(key).or_insert(0) += 1;
+    }
+
+    pub(crate) fn set_lnode_attributions(&mut self, attributions: HashMap<PLNode, AttributionKey>) {
+        self.lnode_attributions = attributions;
+    }
+
+    pub(crate) fn clear_lnode_attributions(&mut self) {
+        self.lnode_attributions.clear();
+        self.lnode_attributions.shrink_to_fit();
+    }
+
+    pub(crate) fn record_removed_lnode(&mut self, p_lnode: PLNode) {
+        let key = self
+            .lnode_attributions
+            .get(&p_lnode)
+            .cloned()
+            .unwrap_or(AttributionKey::Unknown);
+        *self.removed_lnodes.entry(key).or_insert(0) += 1;
+    }
+}
+
+pub(crate) fn write_location(
+    f: &mut fmt::Formatter<'_>,
+    location: Option<Location>,
+) -> fmt::Result {
+    if let Some(location) = location {
+        write!(f, "{}:{}:{}", location.file, location.line, location.col)
+    } else {
+        write!(f, "(unknown location)")
+    }
+}
+
+/// Writes one line per attribution, with the most removed logic first
+impl fmt::Display for DeadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (attribution, n) in self.pruned_states() {
+            writeln!(f, "pruned {n} unused states from {attribution}")?;
+        }
+        for (attribution, n) in self.removed_lnodes() {
+            writeln!(f, "removed {n} unused `LNode`s from {attribution}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ensemble {
+    /// Attributes every state to its own location if it has one, else to the
+    /// `RNode` associated with it, else to the attribution of its first
+    /// operand that has one
+    pub(crate) fn attribute_states(&self) -> HashMap<PState, AttributionKey> {
+        let mut rnode_states = HashMap::new();
+        for (_, _, rnode) in self.notary.rnodes() {
+            if let Some(p_state) = rnode.associated_state {
+                rnode_states
+                    .entry(p_state)
+                    .or_insert_with(|| AttributionKey::from_rnode(rnode));
+            }
+        }
+        let mut res: HashMap<PState, AttributionKey> = HashMap::new();
+        let mut in_progress = HashSet::new();
+        let mut adv = self.stator.states.advancer();
+        while let Some(p_root) = adv.advance(&self.stator.states) {
+            // iterative postorder DFS, a state is resolved after its operands
+            let mut stack = vec![p_root];
+            while let Some(p_state) = stack.last().copied() {
+                if res.contains_key(&p_state) {
+                    stack.pop();
+                    continue
+                }
+                let state = &self.stator.states[p_state];
+                if let Some(location) = to_key(state.location) {
+                    res.insert(p_state, AttributionKey::Location(location));
+                    stack.pop();
+                    continue
+                }
+                if let Some(key) = rnode_states.get(&p_state) {
+                    res.insert(p_state, key.clone());
+                    stack.pop();
+                    continue
+                }
+                if in_progress.insert(p_state) {
+                    for p_operand in state.op.operands() {
+                        if self.stator.states.contains(*p_operand)
+                            && !res.contains_key(p_operand)
+                            && !in_progress.contains(p_operand)
+                        {
+                            stack.push(*p_operand);
+                        }
+                    }
+                } else {
+                    in_progress.remove(&p_state);
+                    let key = state
+                        .op
+                        .operands()
+                        .iter()
+                        .filter_map(|p_operand| res.get(p_operand))
+                        .find(|key| !matches!(key, AttributionKey::Unknown))
+                        .cloned()
+                        .unwrap_or(AttributionKey::Unknown);
+                    res.insert(p_state, key);
+                    stack.pop();
+                }
+            }
+        }
+        res
+    }
+
+    /// Attributes every equivalence (by its `p_self_equiv`) to the nearest
+    /// `RNode` feeding it, using a breadth first search forward from the bits
+    /// of all `RNode`s through `LNode` inputs and `TNode` drivers
+    pub(crate) fn attribute_equivs(&self) -> HashMap<PBack, AttributionKey> {
+        let mut res = HashMap::new();
+        let mut front = VecDeque::new();
+        for (_, _, rnode) in self.notary.rnodes() {
+            let Some(bits) = rnode.bits() else { continue };
+            let key = AttributionKey::from_rnode(rnode);
+            for p_bit in bits.iter().copied().flatten() {
+                let p_equiv = self.backrefs.get_val(p_bit).unwrap().p_self_equiv;
+                if let Entry::Vacant(e) = res.entry(p_equiv) {
+                    e.insert(key.clone());
+                    front.push_back(p_equiv);
+                }
+            }
+        }
+        while let Some(p_equiv) = front.pop_front() {
+            let key = res[&p_equiv].clone();
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                let p_next = match *self.backrefs.get_key(p_ref).unwrap() {
+                    Referent::Input(p_lnode) => self.lnodes[p_lnode].p_self,
+                    Referent::Driver(p_tnode) => self.tnodes[p_tnode].p_self,
+                    _ => continue,
+                };
+                let p_next = self.backrefs.get_val(p_next).unwrap().p_self_equiv;
+                if let Entry::Vacant(e) = res.entry(p_next) {
+                    e.insert(key.clone());
+                    front.push_back(p_next);
+                }
+            }
+        }
+        res
+    }
+
+    /// Attributes every `LNode` to the location of the state it was lowered
+    /// from if it has one, else to the attribution of its output equivalence
+    pub(crate) fn attribute_lnodes(&self) -> HashMap<PLNode, AttributionKey> {
+        let equivs = self.attribute_equivs();
+        let mut res = HashMap::new();
+        for (p_lnode, lnode) in &self.lnodes {
+            let location = lnode
+                .lowered_from
+                .and_then(|p_state| self.stator.states.get(p_state))
+                .and_then(|state| to_key(state.location));
+            let key = if let Some(location) = location {
+                AttributionKey::Location(location)
+            } else {
+                let p_equiv = self.backrefs.get_val(lnode.p_self).unwrap().p_self_equiv;
+                equivs
+                    .get(&p_equiv)
+                    .cloned()
+                    .unwrap_or(AttributionKey::Unknown)
+            };
+            res.insert(p_lnode, key);
+        }
+        res
+    }
+
+    /// Records the attributions of the states in `p_states` that no longer
+    /// exist into the dead report, if it is enabled
+    pub(crate) fn record_pruned_states(&mut self, p_states: HashMap<PState, AttributionKey>) {
+        if let Some(ref mut report) = self.dead_report {
+            for (p_state, key) in p_states {
+                if !self.stator.states.contains(p_state) {
+                    report.record_pruned_state(key);
+                }
+            }
+        }
+    }
+
+    /// Returns the states that are candidates for pruning along with their
+    /// attributions, or an empty map if the dead report is not enabled
+    pub(crate) fn prunable_states(&self) -> HashMap<PState, AttributionKey> {
+        if self.dead_report.is_some() {
+            self.attribute_states()
+        } else {
+            HashMap::new()
+        }
+    }
+
+    /// Takes a snapshot of the `LNode` attributions for the dead report, if
+    /// it is enabled
+    pub(crate) fn snapshot_lnode_attributions(&mut self) {
+        if self.dead_report.is_none() {
+            return
+        }
+        let attributions = self.attribute_lnodes();
+        if let Some(ref mut report) = self.dead_report {
+            report.set_lnode_attributions(attributions);
+        }
+    }
+
+    /// Records that `p_lnode` is about to be removed for being unused
+    pub(crate) fn record_removed_lnode(&mut self, p_lnode: PLNode) {
+        if let Some(ref mut report) = self.dead_report {
+            report.record_removed_lnode(p_lnode);
+        }
+    }
+}
@@ -143,6 +143,11 @@ impl DebugNodeTrait<PBack> for NodeKind {
                             }
                             v
                         }
+                        LNodeKind::Carry(inp) => vec![
+                            (inp[0], "cin".to_owned()),
+                            (inp[1], "lhs".to_owned()),
+                            (inp[2], "rhs".to_owned()),
+                        ],
                     }
                 },
                 center: {
@@ -151,6 +156,7 @@ impl DebugNodeTrait<PBack> for NodeKind {
                         LNodeKind::Copy(_) => (),
                         LNodeKind::Lut(_, lut) => v.push(format!("{:?} ", lut)),
                         LNodeKind::DynamicLut(..) => v.push("dyn".to_owned()),
+                        LNodeKind::Carry(_) => v.push("carry".to_owned()),
                     }
                     if let Some(lowered_from) = lnode.lowered_from {
                         v.push(format!("{:?}", lowered_from));
@@ -0,0 +1,463 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+};
+
+use awint::{
+    awint_dag::{smallvec::SmallVec, Op, PState},
+    Awi,
+};
+
+use crate::{
+    ensemble::{
+        rewrite::{rewrite_rules, Pattern, RewriteOp, RewriteRule},
+        Ensemble,
+    },
+    Error,
+};
+
+/// Limits for [Ensemble::egraph_optimize_states]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EgraphConfig {
+    /// The maximum number of rule application iterations
+    pub max_iterations: usize,
+    /// Saturation stops early once the egraph has this many e-nodes
+    pub max_enodes: usize,
+}
+
+impl Default for EgraphConfig {
+    fn default() -> Self {
+        Self {
+            max_iterations: 16,
+            max_enodes: 1 << 14,
+        }
+    }
+}
+
+/// The result of [Ensemble::egraph_optimize_states]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EgraphReport {
+    /// The number of rule application iterations that were run
+    pub iterations: usize,
+    /// If no rule could make any more changes
+    pub saturated: bool,
+    /// The final number of e-nodes
+    pub num_enodes: usize,
+    /// The final number of e-classes
+    pub num_eclasses: usize,
+    /// The cost of the rewritable `State`s before, according to
+    /// [RewriteOp::cost]
+    pub cost_before: u64,
+    /// The cost of the extracted DAG, the `State`s are only rewritten if this
+    /// is less than `cost_before`
+    pub cost_after: u64,
+    /// The number of `State`s that were rewritten to use the extracted DAG
+    pub num_rewritten: usize,
+}
+
+type Id = usize;
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+enum ENode {
+    /// A `State` that cannot be rewritten
+    Leaf(PState),
+    Literal(Awi),
+    Op(RewriteOp, SmallVec<[Id; 2]>),
+}
+
+#[derive(Debug, Clone)]
+struct EClass {
+    nodes: Vec<ENode>,
+    nzbw: NonZeroUsize,
+}
+
+/// A minimal egraph with rebuilding by full recanonicalization, which is
+/// simple and good enough for experimenting on moderately sized designs
+#[derive(Debug, Clone, Default)]
+struct Egraph {
+    /// Union-find, classes are only valid at roots
+    unions: Vec<Id>,
+    classes: Vec<EClass>,
+    memo: HashMap<ENode, Id>,
+}
+
+type Subst = SmallVec<[Option<Id>; 4]>;
+
+impl Egraph {
+    fn find(&self, mut id: Id) -> Id {
+        while self.unions[id] != id {
+            id = self.unions[id];
+        }
+        id
+    }
+
+    fn canonicalize(&self, node: &ENode) -> ENode {
+        if let ENode::Op(op, children) = node {
+            ENode::Op(*op, children.iter().map(|id| self.find(*id)).collect())
+        } else {
+            node.clone()
+        }
+    }
+
+    fn add(&mut self, node: ENode, nzbw: NonZeroUsize) -> Id {
+        let node = self.canonicalize(&node);
+        if let Some(id) = self.memo.get(&node) {
+            return self.find(*id)
+        }
+        let id = self.classes.len();
+        self.unions.push(id);
+        self.classes.push(EClass {
+            nodes: vec![node.clone()],
+            nzbw,
+        });
+        self.memo.insert(node, id);
+        id
+    }
+
+    /// Returns if a change was made
+    fn union(&mut self, id0: Id, id1: Id) -> bool {
+        let (id0, id1) = (self.find(id0), self.find(id1));
+        if id0 == id1 {
+            return false
+        }
+        let nodes = std::mem::take(&mut self.classes[id1].nodes);
+        self.classes[id0].nodes.extend(nodes);
+        self.unions[id1] = id0;
+        true
+    }
+
+    /// Restores the congruence invariant
+    fn rebuild(&mut self) {
+        loop {
+            self.memo.clear();
+            let mut pending = vec![];
+            for id in 0..self.classes.len() {
+                if self.find(id) != id {
+                    continue
+                }
+                let mut nodes: Vec<ENode> = self.classes[id]
+                    .nodes
+                    .iter()
+                    .map(|node| self.canonicalize(node))
+                    .collect();
+                let mut seen = HashSet::new();
+                nodes.retain(|node| seen.insert(node.clone()));
+                for node in &nodes {
+                    if let Some(other) = self.memo.insert(node.clone(), id) {
+                        pending.push((other, id));
+                    }
+                }
+                self.classes[id].nodes = nodes;
+            }
+            let mut changed = false;
+            for (id0, id1) in pending {
+                changed |= self.union(id0, id1);
+            }
+            if !changed {
+                break
+            }
+        }
+    }
+
+    fn num_enodes(&self) -> usize {
+        (0..self.classes.len())
+            .filter(|id| self.find(*id) == *id)
+            .map(|id| self.classes[id].nodes.len())
+            .sum()
+    }
+
+    fn num_eclasses(&self) -> usize {
+        (0..self.classes.len())
+            .filter(|id| self.find(*id) == *id)
+            .count()
+    }
+
+    /// Returns all the substitutions extending `subst` with which `pattern`
+    /// matches the class `id`
+    fn ematch(&self, pattern: &Pattern, id: Id, subst: Subst) -> Vec<Subst> {
+        let id = self.find(id);
+        match pattern {
+            Pattern::Var(i) => {
+                let mut subst = subst;
+                match subst[*i] {
+                    Some(bound) if self.find(bound) != id => return vec![],
+                    Some(_) => (),
+                    None => subst[*i] = Some(id),
+                }
+                vec![subst]
+            }
+            Pattern::Zero | Pattern::Umax => {
+                let is_match = self.classes[id].nodes.iter().any(|node| match node {
+                    ENode::Literal(a) => {
+                        if let Pattern::Zero = pattern {
+                            a.is_zero()
+                        } else {
+                            a.is_umax()
+                        }
+                    }
+                    _ => false,
+                });
+                if is_match {
+                    vec![subst]
+                } else {
+                    vec![]
+                }
+            }
+            Pattern::Op(op, operands) => {
+                let mut res = vec![];
+                for node in &self.classes[id].nodes {
+                    if let ENode::Op(node_op, children) = node {
+                        if node_op != op {
+                            continue
+                        }
+                        let mut partial = vec![subst.clone()];
+                        for (pattern, child) in operands.iter().zip(children) {
+                            partial = partial
+                                .into_iter()
+                                .flat_map(|subst| self.ematch(pattern, *child, subst))
+                                .collect();
+                        }
+                        res.extend(partial);
+                    }
+                }
+                res
+            }
+        }
+    }
+
+    fn instantiate(&mut self, pattern: &Pattern, subst: &Subst, nzbw: NonZeroUsize) -> Id {
+        match pattern {
+            Pattern::Var(i) => subst[*i].unwrap(),
+            Pattern::Zero => self.add(ENode::Literal(Awi::zero(nzbw)), nzbw),
+            Pattern::Umax => self.add(ENode::Literal(Awi::umax(nzbw)), nzbw),
+            Pattern::Op(op, operands) => {
+                let children = operands
+                    .iter()
+                    .map(|pattern| self.instantiate(pattern, subst, nzbw))
+                    .collect();
+                self.add(ENode::Op(*op, children), nzbw)
+            }
+        }
+    }
+
+    /// Applies every rule once to every match, returns if anything changed
+    fn apply_rules(&mut self, rules: &[RewriteRule]) -> bool {
+        let mut matches = vec![];
+        for (rule_i, rule) in rules.iter().enumerate() {
+            for id in 0..self.classes.len() {
+                if self.find(id) != id {
+                    continue
+                }
+                let subst: Subst = SmallVec::from_elem(None, rule.num_vars);
+                for subst in self.ematch(&rule.lhs, id, subst) {
+                    matches.push((rule_i, id, subst));
+                }
+            }
+        }
+        let mut changed = false;
+        for (rule_i, id, subst) in matches {
+            let nzbw = self.classes[self.find(id)].nzbw;
+            let new = self.instantiate(&rules[rule_i].rhs, &subst, nzbw);
+            changed |= self.union(id, new);
+        }
+        self.rebuild();
+        changed
+    }
+
+    fn node_cost(&self, node: &ENode, nzbw: NonZeroUsize, costs: &[Option<u64>]) -> Option<u64> {
+        match node {
+            ENode::Leaf(_) | ENode::Literal(_) => Some(0),
+            ENode::Op(op, children) => {
+                let mut cost = op.cost(nzbw);
+                for child in children {
+                    cost = cost.saturating_add(costs[self.find(*child)]?);
+                }
+                Some(cost)
+            }
+        }
+    }
+
+    /// Returns the lowest tree cost node of every class
+    fn extract(&self) -> Vec<Option<(u64, ENode)>> {
+        let mut best: Vec<Option<(u64, ENode)>> = vec![None; self.classes.len()];
+        let mut costs: Vec<Option<u64>> = vec![None; self.classes.len()];
+        loop {
+            let mut changed = false;
+            for id in 0..self.classes.len() {
+                if self.find(id) != id {
+                    continue
+                }
+                let class = &self.classes[id];
+                for node in &class.nodes {
+                    if let Some(cost) = self.node_cost(node, class.nzbw, &costs) {
+                        if costs[id].map(|prev| cost < prev).unwrap_or(true) {
+                            costs[id] = Some(cost);
+                            best[id] = Some((cost, node.clone()));
+                            changed = true;
+                        }
+                    }
+                }
+            }
+            if !changed {
+                break
+            }
+        }
+        best
+    }
+}
+
+impl Ensemble {
+    /// Returns the e-class of `p_state`, adding it and its rewritable fan-in
+    fn add_state_to_egraph(
+        &self,
+        egraph: &mut Egraph,
+        ids: &mut HashMap<PState, Id>,
+        p_state: PState,
+    ) -> Id {
+        let mut stack = vec![(p_state, false)];
+        while let Some((p_state, expanded)) = stack.pop() {
+            if ids.contains_key(&p_state) {
+                continue
+            }
+            let state = &self.stator.states[p_state];
+            if !self.is_rewritable_state(p_state) {
+                let node = if let Op::Literal(ref a) = state.op {
+                    ENode::Literal(a.clone())
+                } else {
+                    ENode::Leaf(p_state)
+                };
+                ids.insert(p_state, egraph.add(node, state.nzbw));
+                continue
+            }
+            let operands = state.op.operands();
+            if !expanded {
+                stack.push((p_state, true));
+                for p_operand in operands {
+                    stack.push((*p_operand, false));
+                }
+                continue
+            }
+            let id = if let Op::Copy([p_source]) = state.op {
+                ids[&p_source]
+            } else {
+                let (op, operands) = RewriteOp::from_op(&state.op).unwrap();
+                let children = operands.iter().map(|p| ids[p]).collect();
+                egraph.add(ENode::Op(op, children), state.nzbw)
+            };
+            ids.insert(p_state, id);
+        }
+        ids[&p_state]
+    }
+
+    /// Creates `State`s for the extracted DAG of the class `id`
+    fn materialize_egraph_class(
+        &mut self,
+        egraph: &Egraph,
+        best: &[Option<(u64, ENode)>],
+        states: &mut HashMap<Id, PState>,
+        id: Id,
+    ) -> PState {
+        let id = egraph.find(id);
+        if let Some(p_state) = states.get(&id) {
+            return *p_state
+        }
+        let nzbw = egraph.classes[id].nzbw;
+        let p_state = match best[id].as_ref().unwrap().1.clone() {
+            ENode::Leaf(p_state) => p_state,
+            ENode::Literal(a) => self.make_state(nzbw, Op::Literal(a), None),
+            ENode::Op(op, children) => {
+                let operands: Vec<PState> = children
+                    .iter()
+                    .map(|child| self.materialize_egraph_class(egraph, best, states, *child))
+                    .collect();
+                self.make_state(nzbw, op.to_op(&operands), None)
+            }
+        };
+        states.insert(id, p_state);
+        p_state
+    }
+
+    /// Experimental equality saturation pass over the `State`s that have not
+    /// been lowered yet. The rewritable `State`s (see [RewriteOp]) are added to
+    /// an egraph, the rules of [rewrite_rules] are applied until saturation or
+    /// until a limit of `config` is reached, and the lowest cost DAG according
+    /// to [RewriteOp::cost] is extracted. The `State`s that are used by
+    /// anything else are then rewritten to use the extracted DAG, if it is
+    /// cheaper than the original.
+    pub fn egraph_optimize_states(&mut self, config: &EgraphConfig) -> Result<EgraphReport, Error> {
+        // find the rewritable `State`s that are used by something that cannot be
+        // rewritten
+        let mut used_externally = HashSet::new();
+        for (p_state, state) in &self.stator.states {
+            if state.extern_rc != 0 {
+                used_externally.insert(p_state);
+            }
+            if !self.is_rewritable_state(p_state) {
+                used_externally.extend(state.op.operands().iter().copied());
+            }
+        }
+        let mut roots: Vec<PState> = used_externally
+            .into_iter()
+            .filter(|p_state| self.is_rewritable_state(*p_state))
+            .collect();
+        roots.sort_unstable();
+        let mut egraph = Egraph::default();
+        let mut ids = HashMap::new();
+        let mut root_ids = vec![];
+        for p_state in roots.iter().copied() {
+            root_ids.push(self.add_state_to_egraph(&mut egraph, &mut ids, p_state));
+        }
+        let mut report = EgraphReport::default();
+        for p_state in ids.keys() {
+            let state = &self.stator.states[*p_state];
+            if let Some((op, _)) = RewriteOp::from_op(&state.op) {
+                if self.is_rewritable_state(*p_state) {
+                    report.cost_before = report.cost_before.saturating_add(op.cost(state.nzbw));
+                }
+            }
+        }
+        let rules = rewrite_rules();
+        report.saturated = true;
+        while report.iterations < config.max_iterations {
+            if egraph.num_enodes() >= config.max_enodes {
+                report.saturated = false;
+                break
+            }
+            report.iterations += 1;
+            if !egraph.apply_rules(&rules) {
+                break
+            }
+            if report.iterations == config.max_iterations {
+                report.saturated = false;
+            }
+        }
+        report.num_enodes = egraph.num_enodes();
+        report.num_eclasses = egraph.num_eclasses();
+        let best = egraph.extract();
+        // the cost of the extracted DAG with sharing
+        let mut visited = HashSet::new();
+        let mut stack: Vec<Id> = root_ids.iter().map(|id| egraph.find(*id)).collect();
+        while let Some(id) = stack.pop() {
+            if !visited.insert(id) {
+                continue
+            }
+            if let Some((_, ENode::Op(op, children))) = &best[id] {
+                report.cost_after = report
+                    .cost_after
+                    .saturating_add(op.cost(egraph.classes[id].nzbw));
+                stack.extend(children.iter().map(|id| egraph.find(*id)));
+            }
+        }
+        if report.cost_after >= report.cost_before {
+            report.cost_after = report.cost_before;
+            return Ok(report)
+        }
+        let mut states = HashMap::new();
+        for (p_state, id) in roots.iter().copied().zip(root_ids) {
+            let p_new = self.materialize_egraph_class(&egraph, &best, &mut states, id);
+            self.replace_state_with_copy(p_state, p_new);
+            report.num_rewritten += 1;
+        }
+        Ok(report)
+    }
+}
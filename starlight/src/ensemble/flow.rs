@@ -0,0 +1,139 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Ensemble, PBack, PExternal, Referent},
+    Error,
+};
+
+/// A path through which a secret bit can flow to an observable bit without
+/// passing through a declassifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowViolation {
+    /// The `PExternal` and bit index of the secret
+    pub secret: (PExternal, usize),
+    /// The `PExternal` and bit index of the observable
+    pub observable: (PExternal, usize),
+    /// The equivalences along the path, starting with the secret and ending
+    /// with the observable
+    pub path: Vec<PBack>,
+}
+
+/// The result of [Ensemble::information_flow]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowReport {
+    /// One violation for each observable bit that a secret can reach, with
+    /// the shortest path found
+    pub violations: Vec<FlowViolation>,
+}
+
+impl FlowReport {
+    /// Returns if no secret can flow to any observable
+    pub fn is_secure(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl Ensemble {
+    /// Returns the equivalences of the bits of the `RNode` of `p_external`
+    fn rnode_equivs(&self, p_external: PExternal) -> Result<Vec<Option<PBack>>, Error> {
+        let (_, rnode) = self.notary.get_rnode(p_external)?;
+        if let Some(bits) = rnode.bits() {
+            Ok(bits
+                .iter()
+                .map(|p_bit| p_bit.map(|p_bit| self.backrefs.get_val(p_bit).unwrap().p_self_equiv))
+                .collect())
+        } else {
+            Err(Error::OtherString(format!(
+                "`Ensemble::information_flow`: {p_external} has not been lowered yet"
+            )))
+        }
+    }
+
+    /// Conservative static information flow analysis. Every value that is
+    /// structurally downstream of a bit of the `secrets` through `LNode`s and
+    /// `TNode`s is considered secret, except that flow stops at the bits of
+    /// the `declassifiers`, which are considered public. Returns a violation
+    /// for every bit of the `observables` that is secret. Because this only
+    /// looks at structure, a reported flow may not be realizable for any
+    /// assignment of values, but a secure report means that the observables
+    /// cannot depend on the secrets.
+    ///
+    /// # Errors
+    ///
+    /// If any `PExternal` is not found or the `RNode`s have not been lowered
+    pub fn information_flow(
+        &self,
+        secrets: &[PExternal],
+        observables: &[PExternal],
+        declassifiers: &[PExternal],
+    ) -> Result<FlowReport, Error> {
+        let mut declassified = HashSet::new();
+        for p_external in declassifiers.iter().copied() {
+            declassified.extend(self.rnode_equivs(p_external)?.into_iter().flatten());
+        }
+        // multi-source breadth first search, recording where each tainted
+        // equivalence was reached from
+        let mut sources: HashMap<PBack, (PExternal, usize)> = HashMap::new();
+        let mut parents: HashMap<PBack, Option<PBack>> = HashMap::new();
+        let mut front = VecDeque::new();
+        for p_external in secrets.iter().copied() {
+            for (bit_i, p_equiv) in self.rnode_equivs(p_external)?.into_iter().enumerate() {
+                if let Some(p_equiv) = p_equiv {
+                    if let Entry::Vacant(e) = parents.entry(p_equiv) {
+                        e.insert(None);
+                        sources.insert(p_equiv, (p_external, bit_i));
+                        front.push_back(p_equiv);
+                    }
+                }
+            }
+        }
+        while let Some(p_equiv) = front.pop_front() {
+            let source = sources[&p_equiv];
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                let p_next = match *self.backrefs.get_key(p_ref).unwrap() {
+                    Referent::Input(p_lnode) => self.lnodes[p_lnode].p_self,
+                    Referent::Driver(p_tnode) => self.tnodes[p_tnode].p_self,
+                    _ => continue,
+                };
+                let p_next = self.backrefs.get_val(p_next).unwrap().p_self_equiv;
+                if declassified.contains(&p_next) {
+                    continue
+                }
+                if let Entry::Vacant(e) = parents.entry(p_next) {
+                    e.insert(Some(p_equiv));
+                    sources.insert(p_next, source);
+                    front.push_back(p_next);
+                }
+            }
+        }
+        let mut report = FlowReport::default();
+        for p_external in observables.iter().copied() {
+            for (bit_i, p_equiv) in self.rnode_equivs(p_external)?.into_iter().enumerate() {
+                let p_equiv = if let Some(p_equiv) = p_equiv {
+                    p_equiv
+                } else {
+                    continue
+                };
+                if !parents.contains_key(&p_equiv) {
+                    continue
+                }
+                let mut path = vec![p_equiv];
+                let mut p = p_equiv;
+                while let Some(p_parent) = parents[&p] {
+                    path.push(p_parent);
+                    p = p_parent;
+                }
+                path.reverse();
+                report.violations.push(FlowViolation {
+                    secret: sources[&p_equiv],
+                    observable: (p_external, bit_i),
+                    path,
+                });
+            }
+        }
+        Ok(report)
+    }
+}
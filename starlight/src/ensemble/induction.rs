@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use awint::Awi;
+
+use crate::{
+    ensemble::{Bdd, BddRef, Delay, Ensemble, PBack, PExternal, PTNode, Value},
+    Error,
+};
+
+/// The values of a counterexample found by [Ensemble::prove_invariant]. The
+/// values of things that do not matter are `false`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InductionTrace {
+    /// The values of the non-constant registers in the state before the step
+    pub registers: Vec<(PTNode, bool)>,
+    /// The values of the writable `RNode`s (such as those of `LazyAwi`s) that
+    /// the property or the next state depend on, in the state before the step
+    pub inputs: Vec<(PExternal, Awi)>,
+    /// The values of the writable `RNode`s in the state after the step, this
+    /// is empty for [Induction::BaseFailure]
+    pub next_inputs: Vec<(PExternal, Awi)>,
+}
+
+/// The result of [Ensemble::prove_invariant]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Induction {
+    /// The property holds in the initial state and is preserved by every
+    /// step, so it holds in all reachable states
+    Proven,
+    /// The property does not hold in the initial state
+    BaseFailure(InductionTrace),
+    /// A counterexample to induction, which is a state where the property
+    /// holds but does not hold after one step. The state may be unreachable,
+    /// in which case the property is still invariant but is not inductive by
+    /// itself and needs to be strengthened.
+    NotInductive(InductionTrace),
+}
+
+impl Induction {
+    /// Returns if the property was proven
+    pub fn is_proven(&self) -> bool {
+        matches!(self, Self::Proven)
+    }
+}
+
+impl Ensemble {
+    /// Returns the values of the writable `RNode`s that have bits with
+    /// functions in `funcs` that are variables in `assignment`, after the
+    /// variables are mapped through `map`
+    fn induction_inputs(
+        &self,
+        funcs: &HashMap<PBack, BddRef>,
+        bdd: &Bdd,
+        map: &HashMap<usize, usize>,
+        assignment: &HashMap<usize, bool>,
+    ) -> Vec<(PExternal, Awi)> {
+        let mut res = vec![];
+        for (_, p_external, rnode) in self.notary.rnodes() {
+            if rnode.read_only() {
+                continue
+            }
+            let Some(bits) = rnode.bits() else { continue };
+            let mut awi = Awi::zero(rnode.nzbw());
+            let mut in_cone = false;
+            for (i, p_bit) in bits.iter().enumerate() {
+                let Some(p_bit) = p_bit else { continue };
+                let p_equiv = self.backrefs.get_val(*p_bit).unwrap().p_self_equiv;
+                if let Some(var) = funcs.get(&p_equiv).and_then(|f| bdd.top_var(*f)) {
+                    let var = map.get(&var).copied().unwrap_or(var);
+                    in_cone = true;
+                    awi.set(i, assignment.get(&var).copied().unwrap_or(false))
+                        .unwrap();
+                }
+            }
+            if in_cone {
+                res.push((*p_external, awi));
+            }
+        }
+        res
+    }
+
+    /// Attempts to prove that all the bits of the read only `RNode` of
+    /// `p_external` (such as that of an `EvalAwi`) are set in every reachable
+    /// state by 1-induction. The property must hold in the initial state (the
+    /// current state, with registers of unknown value starting with any
+    /// value), and every step from a state where the property holds must lead
+    /// to a state where it holds. Registers are the `TNode`s with nonzero
+    /// delays and must all have the same delay, the same synchronous behavior
+    /// as [Ensemble::analyze_reachability] is assumed. Everything else that is
+    /// not a static `LNode` is treated as a free input that can have a
+    /// different value in each step.
+    ///
+    /// The transition relation and property are built as [Bdd]s with up to
+    /// `max_nodes` nodes like in [Ensemble::cone_bdds], beyond which
+    /// equivalences become free inputs. This keeps proofs sound, but can
+    /// produce counterexamples that are not possible in the real design.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a lowered `RNode`, if registers have different
+    /// delays, or if pending events could not be evaluated
+    pub fn prove_invariant(
+        &mut self,
+        p_external: PExternal,
+        max_nodes: usize,
+    ) -> Result<Induction, Error> {
+        // the initial values of `Loop`s are set by pending events
+        self.restart_request_phase()?;
+        let (_, rnode) = self.notary.get_rnode(p_external)?;
+        let Some(bits) = rnode.bits() else {
+            return Err(Error::OtherStr(
+                "`prove_invariant` was called on an `RNode` that has not been lowered",
+            ))
+        };
+        let mut roots: Vec<PBack> = bits.iter().copied().flatten().collect();
+        let num_property_bits = roots.len();
+        let mut registers = vec![];
+        let mut delay = None;
+        for (p_tnode, tnode) in &self.tnodes {
+            if tnode.delay().is_zero() {
+                continue
+            }
+            if delay.is_some_and(|delay: Delay| delay != tnode.delay()) {
+                return Err(Error::OtherStr(
+                    "`prove_invariant` requires all `TNode`s with nonzero delays to have the same \
+                     delay",
+                ))
+            }
+            delay = Some(tnode.delay());
+            registers.push(p_tnode);
+            roots.push(tnode.p_self);
+            roots.push(tnode.p_driver);
+        }
+
+        let mut bdd = Bdd::new();
+        let order = self.cone_bdds(&mut bdd, &roots, max_nodes);
+        let funcs: HashMap<PBack, BddRef> = order.iter().map(|(p, f, _)| (*p, *f)).collect();
+        let func = |p_back: PBack| funcs[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        let leaves: Vec<usize> = order
+            .iter()
+            .filter(|(_, _, is_leaf)| *is_leaf)
+            .map(|(_, f, _)| bdd.top_var(*f).unwrap())
+            .collect();
+        let num_leaves = leaves.len();
+        let mut property = BddRef::TRUE;
+        for p_bit in &roots[..num_property_bits] {
+            property = bdd.and(property, func(*p_bit));
+        }
+
+        // current state variable to next state variable
+        let mut next_vars: HashMap<usize, usize> = HashMap::new();
+        let mut init = BddRef::TRUE;
+        let mut transition = BddRef::TRUE;
+        for p_tnode in registers.iter().copied() {
+            let tnode = &self.tnodes[p_tnode];
+            let current = func(tnode.p_self);
+            // constant registers do not need state variables
+            let Some(var) = bdd.top_var(current) else {
+                continue
+            };
+            let next_var = if let Some(next_var) = next_vars.get(&var) {
+                *next_var
+            } else {
+                let next_var = num_leaves + next_vars.len();
+                next_vars.insert(var, next_var);
+                match self.backrefs.get_val(tnode.p_self).unwrap().val {
+                    Value::Const(b) | Value::Dynam(b) => {
+                        let literal = if b { current } else { bdd.not(current) };
+                        init = bdd.and(init, literal);
+                    }
+                    Value::Unknown | Value::ConstUnknown => (),
+                }
+                next_var
+            };
+            let next = bdd.var(next_var);
+            let driver = func(tnode.p_driver);
+            let differ = bdd.xor(next, driver);
+            let same = bdd.not(differ);
+            transition = bdd.and(transition, same);
+        }
+        let trace = |bdd: &Bdd,
+                     assignment: HashMap<usize, bool>,
+                     next_map: Option<&HashMap<usize, usize>>| {
+            let registers = registers
+                .iter()
+                .filter_map(|p_tnode| {
+                    let var = bdd.top_var(func(self.tnodes[*p_tnode].p_self))?;
+                    Some((*p_tnode, assignment.get(&var).copied().unwrap_or(false)))
+                })
+                .collect();
+            let inputs = self.induction_inputs(&funcs, bdd, &HashMap::new(), &assignment);
+            let next_inputs = next_map
+                .map(|map| self.induction_inputs(&funcs, bdd, map, &assignment))
+                .unwrap_or_default();
+            InductionTrace {
+                registers,
+                inputs,
+                next_inputs,
+            }
+        };
+
+        // base case
+        let not_property = bdd.not(property);
+        let bad = bdd.and(init, not_property);
+        if let Some(assignment) = bdd.satisfying_assignment(bad) {
+            return Ok(Induction::BaseFailure(trace(&bdd, assignment, None)))
+        }
+
+        // inductive step, the property after the step uses the next state
+        // variables and fresh variables for the free inputs
+        let mut next_map = next_vars.clone();
+        for var in leaves {
+            if !next_map.contains_key(&var) {
+                let fresh = num_leaves + next_map.len();
+                next_map.insert(var, fresh);
+            }
+        }
+        let next_property = bdd.rename(property, &next_map);
+        let not_next_property = bdd.not(next_property);
+        let step = bdd.and(property, transition);
+        let bad = bdd.and(step, not_next_property);
+        if let Some(assignment) = bdd.satisfying_assignment(bad) {
+            return Ok(Induction::NotInductive(trace(
+                &bdd,
+                assignment,
+                Some(&next_map),
+            )))
+        }
+        Ok(Induction::Proven)
+    }
+}
@@ -0,0 +1,646 @@
+use std::{collections::HashMap, fmt::Write, num::NonZeroUsize};
+
+use awint::{
+    awint_dag::{smallvec::smallvec, triple_arena::Advancer},
+    Awi,
+};
+
+use crate::{
+    ensemble::{
+        BlackBox, Delay, DynamicValue, Ensemble, Equiv, LNode, LNodeKind, PBack, PExternal, RNode,
+        Referent, RomNode, TNode, Value,
+    },
+    Error,
+};
+
+const SLIR_VERSION: &str = "slir 1";
+
+fn value_to_str(val: Value) -> &'static str {
+    match val {
+        Value::ConstUnknown => "const_unknown",
+        Value::Unknown => "unknown",
+        Value::Const(false) => "const 0",
+        Value::Const(true) => "const 1",
+        Value::Dynam(false) => "dynam 0",
+        Value::Dynam(true) => "dynam 1",
+    }
+}
+
+/// Writes `s` as a quoted string with `"` and `\` escaped
+pub(crate) fn write_quoted(res: &mut String, s: &str) {
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            _ => res.push(c),
+        }
+    }
+    res.push('"');
+}
+
+/// Splits a line into tokens separated by whitespace, handling quoted strings
+/// and comments
+pub(crate) fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut res = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == ';' {
+            break
+        } else if c.is_whitespace() {
+            continue
+        } else if c == '"' {
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('"') => s.push('"'),
+                        Some('\\') => s.push('\\'),
+                        Some('n') => s.push('\n'),
+                        _ => return Err("invalid escape in string".to_owned()),
+                    },
+                    Some(c) => s.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+            // distinguish strings from other tokens
+            res.push(format!("\"{s}"));
+        } else {
+            let mut s = String::from(c);
+            while let Some(c) = chars.peek() {
+                if c.is_whitespace() || (*c == ';') || (*c == '"') {
+                    break
+                }
+                s.push(*c);
+                chars.next();
+            }
+            res.push(s);
+        }
+    }
+    Ok(res)
+}
+
+/// Used by [Ensemble::parse_ir] for looking up equivalences
+struct Parser<'a> {
+    tokens: &'a [String],
+    equivs: &'a HashMap<usize, PBack>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<&'a str, String> {
+        if let Some((first, rest)) = self.tokens.split_first() {
+            self.tokens = rest;
+            Ok(first)
+        } else {
+            Err("unexpected end of line".to_owned())
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn equiv_id(s: &str) -> Result<usize, String> {
+        s.strip_prefix('e')
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| format!("expected an equivalence, found `{s}`"))
+    }
+
+    fn equiv(&mut self) -> Result<PBack, String> {
+        let s = self.next()?;
+        let id = Self::equiv_id(s)?;
+        self.equivs
+            .get(&id)
+            .copied()
+            .ok_or_else(|| format!("equivalence `{s}` is not declared"))
+    }
+
+    fn bit(&mut self) -> Result<bool, String> {
+        match self.next()? {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            s => Err(format!("expected `0` or `1`, found `{s}`")),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let s = self.next()?;
+        if s == expected {
+            Ok(())
+        } else {
+            Err(format!("expected `{expected}`, found `{s}`"))
+        }
+    }
+}
+
+impl Ensemble {
+    /// Writes the equivalences, `LNode`s, `TNode`s, `RNode`s, `BlackBox`es,
+    /// and `RomNode`s of `self` in the "slir" text format, which is a stable
+    /// and human readable dump intended for diffable artifacts and as an
+    /// interchange format that does not depend on internal layouts. Items are
+    /// written in arena order, which is canonical after
+    /// [Ensemble::optimize_all]. `State`s, `MulNode`s, pending evaluation
+    /// events, and pending `TNode` events are not included.
+    ///
+    /// Each line is one item, and `;` starts a comment:
+    ///
+    /// ```text
+    /// slir 1
+    /// ; equivalences with their current values, which are one of `unknown`,
+    /// ; `const_unknown`, `const 0`, `const 1`, `dynam 0`, or `dynam 1`
+    /// equiv e0 dynam 0
+    /// equiv e1 const 1
+    /// equiv e2 unknown
+    /// equiv e3 unknown
+    /// equiv e4 unknown
+    /// equiv e5 unknown
+    /// ; the driven equivalence comes first, static tables are in hexadecimal
+    /// ; with the first input in the least significant position
+    /// lnode e2 lut 8 e0 e1
+    /// lnode e3 copy e2
+    /// ; carries take the carry in, lhs bit, and rhs bit
+    /// lnode e4 carry e0 e1 e2
+    /// ; dynamic tables list their inputs, then `:`, then the table entries
+    /// ; which are `0`, `1`, `x` for permanently unknown, or equivalences
+    /// lnode e5 dlut e0 : e1 x
+    /// ; a temporal node driving `e0` from `e5` with a delay of 1, and an
+    /// ; optional declared initial value
+    /// tnode e0 e5 delay 1 init 0
+    /// ; `RNode`s are `ro` (read only) or `rw`, then the width, then the bits or
+    /// ; `uninit`, with `_` for pruned bits, then an optional name
+    /// rnode rw 1 e0 "x"
+    /// rnode ro 2 e3 _
+    /// ; a `BlackBox` instance of a module, the ports are `RNode`s referred to by
+    /// ; `r` and their order in the file, then the pin-to-pin delays are `path`
+    /// ; followed by the input port index, output port index, and delay
+    /// blackbox "vendor_ram" in r1 out r0 path 0 0 3
+    /// ; a preserved read only memory with the address and output `RNode`s,
+    /// ; then the table in hexadecimal with the word at address zero in the
+    /// ; least significant position
+    /// rom r0 r1 9
+    /// ```
+    pub fn write_ir(&self) -> String {
+        let mut res = String::new();
+        res.push_str(SLIR_VERSION);
+        res.push('\n');
+        let mut ids = HashMap::new();
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                let id = ids.len();
+                ids.insert(p_back, id);
+                let val = self.backrefs.get_val(p_back).unwrap().val;
+                writeln!(res, "equiv e{id} {}", value_to_str(val)).unwrap();
+            }
+        }
+        let e = |p_back: PBack| ids[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        for lnode in self.lnodes.vals() {
+            write!(res, "lnode e{} ", e(lnode.p_self)).unwrap();
+            match &lnode.kind {
+                LNodeKind::Copy(inp) => write!(res, "copy e{}", e(*inp)).unwrap(),
+                LNodeKind::Lut(inp, lut) => {
+                    let hex =
+                        Awi::bits_to_string_radix(lut, false, 16, false, lut.bw().div_ceil(4))
+                            .unwrap();
+                    write!(res, "lut {hex}").unwrap();
+                    for inp in inp {
+                        write!(res, " e{}", e(*inp)).unwrap();
+                    }
+                }
+                LNodeKind::DynamicLut(inp, lut) => {
+                    res.push_str("dlut");
+                    for inp in inp {
+                        write!(res, " e{}", e(*inp)).unwrap();
+                    }
+                    res.push_str(" :");
+                    for entry in lut {
+                        match entry {
+                            DynamicValue::ConstUnknown => res.push_str(" x"),
+                            DynamicValue::Const(b) => write!(res, " {}", u8::from(*b)).unwrap(),
+                            DynamicValue::Dynam(p) => write!(res, " e{}", e(*p)).unwrap(),
+                        }
+                    }
+                }
+                LNodeKind::Carry(inp) => {
+                    write!(res, "carry e{} e{} e{}", e(inp[0]), e(inp[1]), e(inp[2])).unwrap()
+                }
+            }
+            res.push('\n');
+        }
+        for tnode in self.tnodes.vals() {
+            write!(
+                res,
+                "tnode e{} e{} delay {}",
+                e(tnode.p_self),
+                e(tnode.p_driver),
+                tnode.delay().amount()
+            )
+            .unwrap();
+            if let Some(b) = tnode.initial {
+                write!(res, " init {}", u8::from(b)).unwrap();
+            }
+            res.push('\n');
+        }
+        let mut rnode_ids = HashMap::new();
+        for (_, p_external, rnode) in self.notary.rnodes() {
+            rnode_ids.insert(*p_external, rnode_ids.len());
+            let rw = if rnode.read_only() { "ro" } else { "rw" };
+            write!(res, "rnode {rw} {}", rnode.nzbw()).unwrap();
+            if let Some(bits) = rnode.bits() {
+                for bit in bits {
+                    if let Some(bit) = bit {
+                        write!(res, " e{}", e(*bit)).unwrap();
+                    } else {
+                        res.push_str(" _");
+                    }
+                }
+            } else {
+                res.push_str(" uninit");
+            }
+            if let Some(name) = rnode.debug_name() {
+                res.push(' ');
+                write_quoted(&mut res, name);
+            }
+            res.push('\n');
+        }
+        for blackbox in self.blackboxes.vals() {
+            res.push_str("blackbox ");
+            write_quoted(&mut res, &blackbox.name);
+            res.push_str(" in");
+            for p_external in &blackbox.inputs {
+                write!(res, " r{}", rnode_ids[p_external]).unwrap();
+            }
+            res.push_str(" out");
+            for p_external in &blackbox.outputs {
+                write!(res, " r{}", rnode_ids[p_external]).unwrap();
+            }
+            for (i, o, delay) in &blackbox.delays {
+                write!(res, " path {i} {o} {}", delay.amount()).unwrap();
+            }
+            res.push('\n');
+        }
+        for rom_node in self.rom_nodes.vals() {
+            let table = &rom_node.table;
+            let hex =
+                Awi::bits_to_string_radix(table, false, 16, false, table.bw().div_ceil(4)).unwrap();
+            writeln!(
+                res,
+                "rom r{} r{} {hex}",
+                rnode_ids[&rom_node.addr], rnode_ids[&rom_node.out]
+            )
+            .unwrap();
+        }
+        res
+    }
+
+    /// Parses the "slir" text format written by [Ensemble::write_ir] into a
+    /// new `Ensemble`. The `RNode`s get new `PExternal`s, but keep their names
+    /// so that they can be found with
+    /// [crate::ensemble::Notary::find_by_debug_name].
+    ///
+    /// # Errors
+    ///
+    /// If the text is malformed, including the line number of the problem
+    pub fn parse_ir(ir: &str) -> Result<Ensemble, Error> {
+        let mut ensemble = Ensemble::new();
+        let mut equivs: HashMap<usize, PBack> = HashMap::new();
+        let mut rnodes: Vec<PExternal> = vec![];
+        let mut found_version = false;
+        for (line_i, line) in ir.lines().enumerate() {
+            let res = (|| -> Result<(), String> {
+                let tokens = tokenize(line)?;
+                if tokens.is_empty() {
+                    return Ok(())
+                }
+                if !found_version {
+                    if tokens.join(" ") != SLIR_VERSION {
+                        return Err(format!("expected `{SLIR_VERSION}` header"))
+                    }
+                    found_version = true;
+                    return Ok(())
+                }
+                let mut p = Parser {
+                    tokens: &tokens,
+                    equivs: &equivs,
+                };
+                match p.next()? {
+                    "equiv" => {
+                        let id = Parser::equiv_id(p.next()?)?;
+                        let val = match p.next()? {
+                            "unknown" => Value::Unknown,
+                            "const_unknown" => Value::ConstUnknown,
+                            "const" => Value::Const(p.bit()?),
+                            "dynam" => Value::Dynam(p.bit()?),
+                            s => return Err(format!("invalid value `{s}`")),
+                        };
+                        if !p.is_empty() {
+                            return Err("trailing tokens".to_owned())
+                        }
+                        let p_equiv = ensemble.backrefs.insert_with(|p_self_equiv| {
+                            (Referent::ThisEquiv, Equiv::new(p_self_equiv, val))
+                        });
+                        if equivs.insert(id, p_equiv).is_some() {
+                            return Err(format!("equivalence `e{id}` is declared twice"))
+                        }
+                    }
+                    "lnode" => {
+                        let p_out = p.equiv()?;
+                        let kind = p.next()?;
+                        let mut inputs = vec![];
+                        let mut table = None;
+                        let mut dynamic_table = vec![];
+                        match kind {
+                            "copy" => inputs.push(p.equiv()?),
+                            "carry" => {
+                                for _ in 0..3 {
+                                    inputs.push(p.equiv()?);
+                                }
+                            }
+                            "lut" => {
+                                let hex = p.next()?;
+                                while !p.is_empty() {
+                                    inputs.push(p.equiv()?);
+                                }
+                                let w = u32::try_from(inputs.len())
+                                    .ok()
+                                    .and_then(|len| 1usize.checked_shl(len))
+                                    .and_then(NonZeroUsize::new)
+                                    .ok_or_else(|| "too many inputs".to_owned())?;
+                                if hex.len() != w.get().div_ceil(4) {
+                                    return Err(format!(
+                                        "table `{hex}` has the wrong number of digits for {} \
+                                         inputs",
+                                        inputs.len()
+                                    ))
+                                }
+                                table = Some(
+                                    Awi::from_str_radix(None, hex, 16, w)
+                                        .map_err(|e| format!("invalid table `{hex}`: {e:?}"))?,
+                                );
+                            }
+                            "dlut" => {
+                                loop {
+                                    if p.tokens.first().map(|s| s.as_str()) == Some(":") {
+                                        p.next()?;
+                                        break
+                                    }
+                                    inputs.push(p.equiv()?);
+                                }
+                                while !p.is_empty() {
+                                    let entry = match p.tokens[0].as_str() {
+                                        "x" => DynamicValue::ConstUnknown,
+                                        "0" => DynamicValue::Const(false),
+                                        "1" => DynamicValue::Const(true),
+                                        _ => {
+                                            dynamic_table.push(DynamicValue::Dynam(p.equiv()?));
+                                            continue
+                                        }
+                                    };
+                                    p.next()?;
+                                    dynamic_table.push(entry);
+                                }
+                                if u32::try_from(inputs.len())
+                                    .ok()
+                                    .and_then(|len| 1usize.checked_shl(len))
+                                    != Some(dynamic_table.len())
+                                {
+                                    return Err(format!(
+                                        "dynamic table has {} entries for {} inputs",
+                                        dynamic_table.len(),
+                                        inputs.len()
+                                    ))
+                                }
+                            }
+                            s => return Err(format!("invalid `LNode` kind `{s}`")),
+                        }
+                        if !p.is_empty() {
+                            return Err("trailing tokens".to_owned())
+                        }
+                        let table = table.map(|table| ensemble.lut_tables.intern(table));
+                        let backrefs = &mut ensemble.backrefs;
+                        ensemble.lnodes.insert_with(|p_lnode| {
+                            let p_self = backrefs
+                                .insert_key(p_out, Referent::ThisLNode(p_lnode))
+                                .unwrap();
+                            let mut inp = smallvec![];
+                            for p_inp in inputs {
+                                inp.push(
+                                    backrefs
+                                        .insert_key(p_inp, Referent::Input(p_lnode))
+                                        .unwrap(),
+                                );
+                            }
+                            let kind = match kind {
+                                "copy" => LNodeKind::Copy(inp[0]),
+                                "carry" => LNodeKind::Carry([inp[0], inp[1], inp[2]]),
+                                "lut" => LNodeKind::Lut(inp, table.unwrap()),
+                                _ => {
+                                    for entry in &mut dynamic_table {
+                                        if let DynamicValue::Dynam(p) = entry {
+                                            *p = backrefs
+                                                .insert_key(*p, Referent::Input(p_lnode))
+                                                .unwrap();
+                                        }
+                                    }
+                                    LNodeKind::DynamicLut(inp, dynamic_table)
+                                }
+                            };
+                            LNode::new(p_self, kind, None)
+                        });
+                    }
+                    "tnode" => {
+                        let p_self = p.equiv()?;
+                        let p_driver = p.equiv()?;
+                        p.expect("delay")?;
+                        let delay = p.next()?;
+                        let delay: u128 = delay
+                            .parse()
+                            .map_err(|_| format!("invalid delay `{delay}`"))?;
+                        let initial = if p.is_empty() {
+                            None
+                        } else {
+                            p.expect("init")?;
+                            Some(p.bit()?)
+                        };
+                        if !p.is_empty() {
+                            return Err("trailing tokens".to_owned())
+                        }
+                        let backrefs = &mut ensemble.backrefs;
+                        ensemble.tnodes.insert_with(|p_tnode| {
+                            let p_driver = backrefs
+                                .insert_key(p_driver, Referent::Driver(p_tnode))
+                                .unwrap();
+                            let p_self = backrefs
+                                .insert_key(p_self, Referent::ThisTNode(p_tnode))
+                                .unwrap();
+                            let mut tnode = TNode::new(p_self, p_driver, delay.into());
+                            tnode.initial = initial;
+                            tnode
+                        });
+                    }
+                    "rnode" => {
+                        let read_only = match p.next()? {
+                            "ro" => true,
+                            "rw" => false,
+                            s => return Err(format!("expected `ro` or `rw`, found `{s}`")),
+                        };
+                        let w = p.next()?;
+                        let nzbw = w
+                            .parse::<NonZeroUsize>()
+                            .map_err(|_| format!("invalid width `{w}`"))?;
+                        let mut bits = vec![];
+                        if p.tokens.first().map(|s| s.as_str()) == Some("uninit") {
+                            p.next()?;
+                        } else {
+                            for _ in 0..nzbw.get() {
+                                if p.tokens.first().map(|s| s.as_str()) == Some("_") {
+                                    p.next()?;
+                                    bits.push(None);
+                                } else {
+                                    bits.push(Some(p.equiv()?));
+                                }
+                            }
+                        }
+                        let debug_name = if p.is_empty() {
+                            None
+                        } else {
+                            let name = p.next()?;
+                            Some(
+                                name.strip_prefix('"')
+                                    .ok_or_else(|| format!("expected a name, found `{name}`"))?
+                                    .to_owned(),
+                            )
+                        };
+                        if !p.is_empty() {
+                            return Err("trailing tokens".to_owned())
+                        }
+                        let mut rnode = RNode::new(nzbw, read_only, 0, None, None, false);
+                        rnode.set_debug_name(debug_name);
+                        let (p_rnode, p_external) = ensemble.notary.insert_rnode(rnode);
+                        rnodes.push(p_external);
+                        for bit in bits {
+                            let bit = bit.map(|p_equiv| {
+                                ensemble
+                                    .backrefs
+                                    .insert_key(p_equiv, Referent::ThisRNode(p_rnode))
+                                    .unwrap()
+                            });
+                            ensemble.notary.rnodes[p_rnode].push_bit(bit);
+                        }
+                    }
+                    "blackbox" => {
+                        let name = p.next()?;
+                        let name = name
+                            .strip_prefix('"')
+                            .ok_or_else(|| format!("expected a name, found `{name}`"))?
+                            .to_owned();
+                        let rnode = |s: &str| -> Result<PExternal, String> {
+                            s.strip_prefix('r')
+                                .and_then(|s| s.parse::<usize>().ok())
+                                .and_then(|i| rnodes.get(i).copied())
+                                .ok_or_else(|| format!("expected a declared `RNode`, found `{s}`"))
+                        };
+                        p.expect("in")?;
+                        let mut inputs = vec![];
+                        while p.tokens.first().map(|s| s.as_str()) != Some("out") {
+                            inputs.push(rnode(p.next()?)?);
+                        }
+                        p.expect("out")?;
+                        let mut outputs = vec![];
+                        while !p.is_empty() && (p.tokens[0] != "path") {
+                            outputs.push(rnode(p.next()?)?);
+                        }
+                        let mut delays = vec![];
+                        while !p.is_empty() {
+                            p.expect("path")?;
+                            let mut nums = [0u128; 3];
+                            for num in &mut nums {
+                                let s = p.next()?;
+                                *num = s.parse().map_err(|_| format!("invalid number `{s}`"))?;
+                            }
+                            let i = usize::try_from(nums[0]).map_err(|_| "invalid port index")?;
+                            let o = usize::try_from(nums[1]).map_err(|_| "invalid port index")?;
+                            delays.push((i, o, Delay::from(nums[2])));
+                        }
+                        ensemble
+                            .insert_blackbox(BlackBox {
+                                name,
+                                inputs,
+                                outputs,
+                                delays,
+                            })
+                            .map_err(|e| format!("{e:?}"))?;
+                    }
+                    "rom" => {
+                        let mut ports = [PExternal::default(); 2];
+                        let mut widths = [0usize; 2];
+                        for (port, w) in ports.iter_mut().zip(widths.iter_mut()) {
+                            let s = p.next()?;
+                            *port = s
+                                .strip_prefix('r')
+                                .and_then(|s| s.parse::<usize>().ok())
+                                .and_then(|i| rnodes.get(i).copied())
+                                .ok_or_else(|| {
+                                    format!("expected a declared `RNode`, found `{s}`")
+                                })?;
+                            *w = ensemble
+                                .notary
+                                .get_rnode(*port)
+                                .map_err(|e| format!("{e:?}"))?
+                                .1
+                                .nzbw()
+                                .get();
+                        }
+                        let hex = p.next()?;
+                        if !p.is_empty() {
+                            return Err("trailing tokens".to_owned())
+                        }
+                        let w = u32::try_from(widths[0])
+                            .ok()
+                            .and_then(|addr_w| 1usize.checked_shl(addr_w))
+                            .and_then(|len| len.checked_mul(widths[1]))
+                            .and_then(NonZeroUsize::new)
+                            .ok_or_else(|| "the address is too wide".to_owned())?;
+                        if hex.len() != w.get().div_ceil(4) {
+                            return Err(format!(
+                                "table `{hex}` has the wrong number of digits for an address \
+                                 width of {} and a word width of {}",
+                                widths[0], widths[1]
+                            ))
+                        }
+                        let table = Awi::from_str_radix(None, hex, 16, w)
+                            .map_err(|e| format!("invalid table `{hex}`: {e:?}"))?;
+                        for port in ports {
+                            ensemble.rnode_inc_rc(port).map_err(|e| format!("{e:?}"))?;
+                        }
+                        ensemble.rom_nodes.insert(RomNode {
+                            addr: ports[0],
+                            out: ports[1],
+                            table,
+                            location: None,
+                        });
+                    }
+                    s => return Err(format!("invalid item `{s}`")),
+                }
+                Ok(())
+            })();
+            if let Err(e) = res {
+                return Err(Error::OtherString(format!(
+                    "`Ensemble::parse_ir` line {}: {e}",
+                    line_i + 1
+                )))
+            }
+        }
+        if !found_version {
+            return Err(Error::OtherStr(
+                "`Ensemble::parse_ir` did not find a header",
+            ))
+        }
+        // initialize the partial ordering used by the evaluator
+        ensemble.reinit_partial_orders()?;
+        ensemble.verify_integrity()?;
+        Ok(ensemble)
+    }
+}
@@ -0,0 +1,361 @@
+use std::{collections::HashMap, fmt, mem};
+
+use awint::awint_dag::{triple_arena::Arena, PState};
+
+use crate::{
+    ensemble::{
+        value::Evaluator, BlackBox, Delayer, Ensemble, Equiv, LNode, LNodeKind, LutInterner,
+        MacroOp, MulNode, Notary, Optimizer, PBack, PBlackBox, PLNode, PMulNode, PRomNode, PTNode,
+        Referent, ReplayEntry, RomNode, Stator, TNode,
+    },
+    triple_arena::SurjectArena,
+    Error,
+};
+
+/// The parts of an `Ensemble` that a coarse step can restructure, which
+/// determines what its [Snapshot] clones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StepScope {
+    /// The step can change anything, e.g. lowering and optimization
+    Everything,
+    /// The step only changes `LNode`s, equivalences, and evaluation, and
+    /// leaves the states, `RNode`s, `TNode`s, and other nodes alone
+    LNodes,
+}
+
+/// The structural parts of an `Ensemble` before a coarse step, which are
+/// swapped with the current parts when the step is undone or redone. Parts
+/// that the step does not change are not cloned.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    backrefs: SurjectArena<PBack, Referent, Equiv>,
+    lnodes: Arena<PLNode, LNode>,
+    evaluator: Evaluator,
+    delayer: Delayer,
+    optimizer: Optimizer,
+    lut_tables: LutInterner,
+    rest: Option<Box<SnapshotRest>>,
+}
+
+/// The parts of a [Snapshot] only cloned for [StepScope::Everything]
+#[derive(Debug, Clone)]
+struct SnapshotRest {
+    notary: Notary,
+    stator: Stator,
+    tnodes: Arena<PTNode, TNode>,
+    preserved_macro_ops: Vec<MacroOp>,
+    mul_nodes: Arena<PMulNode, MulNode>,
+    rom_nodes: Arena<PRomNode, RomNode>,
+    blackboxes: Arena<PBlackBox, BlackBox>,
+    translation: HashMap<PState, PState>,
+}
+
+impl Snapshot {
+    fn new(ensemble: &Ensemble, translation: &HashMap<PState, PState>, scope: StepScope) -> Self {
+        let rest = (scope == StepScope::Everything).then(|| {
+            Box::new(SnapshotRest {
+                notary: ensemble.notary.clone(),
+                stator: ensemble.stator.clone(),
+                tnodes: ensemble.tnodes.clone(),
+                preserved_macro_ops: ensemble.preserved_macro_ops.clone(),
+                mul_nodes: ensemble.mul_nodes.clone(),
+                rom_nodes: ensemble.rom_nodes.clone(),
+                blackboxes: ensemble.blackboxes.clone(),
+                translation: translation.clone(),
+            })
+        });
+        Self {
+            backrefs: ensemble.backrefs.clone(),
+            lnodes: ensemble.lnodes.clone(),
+            evaluator: ensemble.evaluator.clone(),
+            delayer: ensemble.delayer.clone(),
+            optimizer: ensemble.optimizer.clone(),
+            lut_tables: ensemble.lut_tables.clone(),
+            rest,
+        }
+    }
+
+    fn swap(&mut self, ensemble: &mut Ensemble, translation: &mut HashMap<PState, PState>) {
+        mem::swap(&mut self.backrefs, &mut ensemble.backrefs);
+        mem::swap(&mut self.lnodes, &mut ensemble.lnodes);
+        mem::swap(&mut self.evaluator, &mut ensemble.evaluator);
+        mem::swap(&mut self.delayer, &mut ensemble.delayer);
+        mem::swap(&mut self.optimizer, &mut ensemble.optimizer);
+        mem::swap(&mut self.lut_tables, &mut ensemble.lut_tables);
+        if let Some(ref mut rest) = self.rest {
+            mem::swap(&mut rest.notary, &mut ensemble.notary);
+            mem::swap(&mut rest.stator, &mut ensemble.stator);
+            mem::swap(&mut rest.tnodes, &mut ensemble.tnodes);
+            mem::swap(
+                &mut rest.preserved_macro_ops,
+                &mut ensemble.preserved_macro_ops,
+            );
+            mem::swap(&mut rest.mul_nodes, &mut ensemble.mul_nodes);
+            mem::swap(&mut rest.rom_nodes, &mut ensemble.rom_nodes);
+            mem::swap(&mut rest.blackboxes, &mut ensemble.blackboxes);
+            mem::swap(&mut rest.translation, translation);
+        }
+    }
+}
+
+/// A single invertible mutation recorded in a [Journal]
+#[derive(Debug, Clone)]
+pub enum JournalEntry {
+    /// A `make_state` call, with `p_state` being the `PState` at the time of
+    /// recording. `created` is false if an existing shared literal was
+    /// returned, in which case undoing does nothing.
+    MakeState { entry: ReplayEntry, created: bool },
+    /// An [Ensemble::replace_lut] call
+    ReplaceLut {
+        p_lnode: PLNode,
+        old: LNodeKind,
+        new: LNodeKind,
+    },
+    /// A coarse step like lowering or optimization that restructures too much
+    /// to be inverted piecewise, along with the state before (when in the undo
+    /// list) or after (when in the redo list) the step
+    Step {
+        name: &'static str,
+        snapshot: Box<Snapshot>,
+    },
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalEntry::MakeState { entry, .. } => {
+                write!(f, "make_state {:?} {:?}", entry.p_state, entry.op)
+            }
+            JournalEntry::ReplaceLut { p_lnode, .. } => write!(f, "replace_lut {p_lnode:?}"),
+            JournalEntry::Step { name, .. } => write!(f, "{name}"),
+        }
+    }
+}
+
+/// An undo and redo history of the mutations of an `Ensemble`, enabled with
+/// [Ensemble::start_journal]. State creation and [Ensemble::replace_lut] are
+/// recorded as cheap invertible operations, while coarse steps such as
+/// lowering and optimization record a [Snapshot] by cloning the structural
+/// parts of the `Ensemble` that the step can change. The snapshot is then
+/// swapped with the current parts on undo and redo. This means that every
+/// journaled coarse step costs a copy of those parts in time and memory, and
+/// steps that only change `LNode`s (such as [Ensemble::decompose_luts]) do not
+/// copy the states, `RNode`s, or `TNode`s.
+///
+/// Undoing a state creation removes the state, which invalidates the
+/// mimicking handles that point to it. Redoing recreates the state with a
+/// possibly different `PState`, and the `PState`s of later entries are
+/// translated accordingly. Lowering that happens on demand during evaluation
+/// is not recorded as its own step.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    undo: Vec<JournalEntry>,
+    redo: Vec<JournalEntry>,
+    // from the `PState`s at the time of recording to the current ones
+    translation: HashMap<PState, PState>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the entries that can be undone, with the most recent last
+    pub fn undo_entries(&self) -> &[JournalEntry] {
+        &self.undo
+    }
+
+    /// Returns the entries that can be redone, with the next one to redo last
+    pub fn redo_entries(&self) -> &[JournalEntry] {
+        &self.redo
+    }
+
+    /// Removes all entries
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.translation.clear();
+    }
+
+    fn translate(&self, p_state: PState) -> PState {
+        self.translation.get(&p_state).copied().unwrap_or(p_state)
+    }
+}
+
+impl Ensemble {
+    /// Starts journaling mutations into a [Journal] so that they can be
+    /// undone with [Ensemble::undo]. Any previous journal is discarded.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Journal::new());
+    }
+
+    /// Stops journaling and returns the journal, or `None` if journaling was
+    /// not started
+    pub fn stop_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Pushes `entry` if journaling is enabled, invalidating anything that
+    /// could have been redone
+    pub(crate) fn record_journal_entry(&mut self, entry: JournalEntry) {
+        if let Some(ref mut journal) = self.journal {
+            journal.undo.push(entry);
+            journal.redo.clear();
+        }
+    }
+
+    /// Records the start of a coarse step named `name` if journaling is
+    /// enabled, so that everything the step does can be undone as a unit.
+    /// This clones the structural parts of `self`, so it should only be
+    /// called before steps that restructure a large part of the `Ensemble`.
+    pub fn journal_step(&mut self, name: &'static str) {
+        self.journal_step_with(name, StepScope::Everything)
+    }
+
+    /// Like [Ensemble::journal_step], but for steps that only change `LNode`s,
+    /// equivalences, and evaluation
+    pub(crate) fn journal_lnode_step(&mut self, name: &'static str) {
+        self.journal_step_with(name, StepScope::LNodes)
+    }
+
+    fn journal_step_with(&mut self, name: &'static str, scope: StepScope) {
+        if let Some(ref journal) = self.journal {
+            let snapshot = Box::new(Snapshot::new(self, &journal.translation, scope));
+            self.record_journal_entry(JournalEntry::Step { name, snapshot });
+        }
+    }
+
+    /// Records a "lower" step if journaling is enabled and lowering for the
+    /// `RNode`s or `states_to_lower` has anything to do
+    pub(crate) fn journal_lowering(&mut self) {
+        if self.journal.is_some()
+            && (!self.stator.states_to_lower.is_empty()
+                || self
+                    .notary
+                    .rnodes()
+                    .vals()
+                    .any(|rnode| rnode.lower_before_pruning || rnode.bits().is_none()))
+        {
+            self.journal_step("lower");
+        }
+    }
+
+    /// Undoes up to `n` of the most recent journaled mutations, returning the
+    /// number that were undone. Returns 0 if journaling is not enabled.
+    ///
+    /// # Errors
+    ///
+    /// If a state to be removed has gained references from something that
+    /// was not journaled, in which case the entry is left in place
+    pub fn undo(&mut self, n: usize) -> Result<usize, Error> {
+        let Some(mut journal) = self.journal.take() else {
+            return Ok(0)
+        };
+        let mut res = Ok(0);
+        for i in 0..n {
+            let Some(mut entry) = journal.undo.pop() else {
+                break
+            };
+            if let Err(e) = self.undo_entry(&mut journal, &mut entry) {
+                journal.undo.push(entry);
+                res = Err(e);
+                break
+            }
+            journal.redo.push(entry);
+            res = Ok(i + 1);
+        }
+        self.journal = Some(journal);
+        res
+    }
+
+    /// Redoes up to `n` of the most recently undone mutations, returning the
+    /// number that were redone. Recording a new mutation after undoing
+    /// discards what could have been redone.
+    ///
+    /// # Errors
+    ///
+    /// If an operand of a state to be recreated no longer exists, in which
+    /// case the entry is left in place
+    pub fn redo(&mut self, n: usize) -> Result<usize, Error> {
+        let Some(mut journal) = self.journal.take() else {
+            return Ok(0)
+        };
+        let mut res = Ok(0);
+        for i in 0..n {
+            let Some(mut entry) = journal.redo.pop() else {
+                break
+            };
+            if let Err(e) = self.redo_entry(&mut journal, &mut entry) {
+                journal.redo.push(entry);
+                res = Err(e);
+                break
+            }
+            journal.undo.push(entry);
+            res = Ok(i + 1);
+        }
+        self.journal = Some(journal);
+        res
+    }
+
+    fn undo_entry(&mut self, journal: &mut Journal, entry: &mut JournalEntry) -> Result<(), Error> {
+        match entry {
+            JournalEntry::MakeState { entry, created } => {
+                if *created {
+                    let p_state = journal.translate(entry.p_state);
+                    match self.stator.states.get(p_state) {
+                        Some(state) if state.pruning_allowed() => {
+                            self.remove_single_state(p_state)?;
+                        }
+                        Some(_) => {
+                            return Err(Error::OtherString(format!(
+                                "`Ensemble::undo`: state {p_state:?} is still referenced"
+                            )))
+                        }
+                        None => return Err(Error::InvalidPtr),
+                    }
+                }
+            }
+            JournalEntry::ReplaceLut { p_lnode, old, .. } => {
+                self.swap_lnode_kind(*p_lnode, old.clone())
+            }
+            JournalEntry::Step { snapshot, .. } => snapshot.swap(self, &mut journal.translation),
+        }
+        Ok(())
+    }
+
+    fn redo_entry(&mut self, journal: &mut Journal, entry: &mut JournalEntry) -> Result<(), Error> {
+        match entry {
+            JournalEntry::MakeState { entry, created } => {
+                if *created {
+                    let mut op = entry.op.clone();
+                    for operand in op.operands_mut() {
+                        *operand = journal.translate(*operand);
+                        if !self.stator.states.contains(*operand) {
+                            return Err(Error::InvalidPtr)
+                        }
+                    }
+                    let len = self.stator.states.len();
+                    let p_state = self.make_state(entry.nzbw, op, entry.location);
+                    // a shared literal may have been created in the meantime
+                    *created = self.stator.states.len() > len;
+                    journal.translation.insert(entry.p_state, p_state);
+                }
+            }
+            JournalEntry::ReplaceLut { p_lnode, new, .. } => {
+                self.swap_lnode_kind(*p_lnode, new.clone())
+            }
+            JournalEntry::Step { snapshot, .. } => snapshot.swap(self, &mut journal.translation),
+        }
+        Ok(())
+    }
+
+    fn swap_lnode_kind(&mut self, p_lnode: PLNode, kind: LNodeKind) {
+        let p_self = self.lnodes[p_lnode].p_self;
+        let partial_ord_num = self
+            .backrefs
+            .get_val(p_self)
+            .unwrap()
+            .evaluator_partial_order;
+        self.set_lnode_kind(p_lnode, kind, partial_ord_num);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{
+        Bdd, BddRef, DynamicValue, Ensemble, LNodeKind, PBack, PLNode, PTNode, Referent, Value,
+    },
+    Error,
+};
+
+/// The behavior of a [CombinationalLoop]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoopKind {
+    /// The feedback never affects the value, so the loop is only structural
+    /// (e.x. a routing fabric configured to not use the loop)
+    FalseLoop,
+    /// For some inputs the loop holds its value, so it acts like a level
+    /// sensitive latch whose state depends on event ordering
+    Latch,
+    /// For some inputs the loop inverts its own value, so it oscillates
+    Oscillator,
+    /// The loop could not be analyzed, because it has multiple independent
+    /// feedback paths, unknown dynamic table entries, or exceeded the BDD
+    /// node limit
+    Unclassified,
+}
+
+/// A combinational loop found by [Ensemble::combinational_loops]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinationalLoop {
+    /// The `LNode`s in the loop
+    pub lnodes: Vec<PLNode>,
+    /// The zero delay `TNode`s in the loop
+    pub tnodes: Vec<PTNode>,
+    /// The equivalence at which the loop was cut for analysis
+    pub cut: PBack,
+    pub kind: LoopKind,
+}
+
+impl fmt::Display for CombinationalLoop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} combinational loop through {} `LNode`s and {} zero delay `TNode`s, cut at {:?}",
+            self.kind,
+            self.lnodes.len(),
+            self.tnodes.len(),
+            self.cut
+        )
+    }
+}
+
+/// What drives an equivalence inside of a loop
+#[derive(Debug, Clone, Copy)]
+enum LoopDriver {
+    LNode(PLNode),
+    TNode(PBack),
+}
+
+impl Ensemble {
+    /// Returns the strongly connected components of equivalences that have
+    /// feedback through `LNode`s and zero delay `TNode`s
+    fn combinational_sccs(&self) -> Vec<Vec<PBack>> {
+        let mut nodes: Vec<PBack> = vec![];
+        let mut index_of: HashMap<PBack, usize> = HashMap::new();
+        let mut edges: Vec<Vec<usize>> = vec![];
+        let mut add_edge = |from: PBack, to: PBack| {
+            let mut index = |p: PBack| {
+                *index_of.entry(p).or_insert_with(|| {
+                    nodes.push(p);
+                    edges.push(vec![]);
+                    nodes.len() - 1
+                })
+            };
+            let (from, to) = (index(from), index(to));
+            edges[from].push(to);
+        };
+        for (p_lnode, lnode) in &self.lnodes {
+            let p_out = self.backrefs.get_val(lnode.p_self).unwrap().p_self_equiv;
+            for p_inp in self.lnode_input_equivs(p_lnode) {
+                add_edge(p_inp, p_out);
+            }
+        }
+        for tnode in self.tnodes.vals() {
+            if tnode.delay().is_zero() {
+                let p_driver = self.backrefs.get_val(tnode.p_driver).unwrap().p_self_equiv;
+                let p_self = self.backrefs.get_val(tnode.p_self).unwrap().p_self_equiv;
+                add_edge(p_driver, p_self);
+            }
+        }
+
+        // iterative Tarjan's algorithm
+        let len = nodes.len();
+        let mut index = vec![usize::MAX; len];
+        let mut lowlink = vec![0; len];
+        let mut on_stack = vec![false; len];
+        let mut stack = vec![];
+        let mut next_index = 0;
+        let mut res = vec![];
+        for root in 0..len {
+            if index[root] != usize::MAX {
+                continue
+            }
+            // (node, position in its edges)
+            let mut call_stack = vec![(root, 0)];
+            index[root] = next_index;
+            lowlink[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            while let Some((v, i)) = call_stack.last().copied() {
+                if let Some(w) = edges[v].get(i).copied() {
+                    call_stack.last_mut().unwrap().1 += 1;
+                    if index[w] == usize::MAX {
+                        index[w] = next_index;
+                        lowlink[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        call_stack.push((w, 0));
+                    } else if on_stack[w] {
+                        lowlink[v] = lowlink[v].min(index[w]);
+                    }
+                    continue
+                }
+                call_stack.pop();
+                if let Some((u, _)) = call_stack.last() {
+                    lowlink[*u] = lowlink[*u].min(lowlink[v]);
+                }
+                if lowlink[v] == index[v] {
+                    let mut scc = vec![];
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        scc.push(w);
+                        if w == v {
+                            break
+                        }
+                    }
+                    if (scc.len() > 1) || edges[v].contains(&v) {
+                        scc.sort_unstable();
+                        res.push(scc.into_iter().map(|i| nodes[i]).collect());
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the function of the driver of `cut` in terms of variable 0 for
+    /// the value of `cut` and other variables for the inputs to the loop, or
+    /// `None` if this cannot be done
+    fn loop_feedback_bdd(
+        &self,
+        bdd: &mut Bdd,
+        drivers: &HashMap<PBack, LoopDriver>,
+        cut: PBack,
+        max_nodes: usize,
+    ) -> Option<BddRef> {
+        let mut funcs: HashMap<PBack, BddRef> = HashMap::new();
+        funcs.insert(cut, bdd.var(0));
+        let mut num_vars = 1;
+        let inputs_of = |driver: LoopDriver| match driver {
+            LoopDriver::LNode(p_lnode) => self.lnode_input_equivs(p_lnode),
+            LoopDriver::TNode(p_driver) => vec![p_driver],
+        };
+        let mut on_stack = HashSet::new();
+        let mut stack: Vec<(PBack, bool)> = inputs_of(drivers[&cut])
+            .into_iter()
+            .map(|p| (p, false))
+            .collect();
+        while let Some((p_equiv, expanded)) = stack.pop() {
+            if funcs.contains_key(&p_equiv) || bdd.len() > max_nodes {
+                continue
+            }
+            let Some(driver) = drivers.get(&p_equiv).copied() else {
+                // inputs from outside of the loop
+                let f = match self.backrefs.get_val(p_equiv).unwrap().val {
+                    Value::Const(b) => bdd.constant(b),
+                    _ => {
+                        num_vars += 1;
+                        bdd.var(num_vars - 1)
+                    }
+                };
+                funcs.insert(p_equiv, f);
+                continue
+            };
+            let inputs = inputs_of(driver);
+            if !expanded {
+                on_stack.insert(p_equiv);
+                stack.push((p_equiv, true));
+                for p_inp in inputs {
+                    if on_stack.contains(&p_inp) {
+                        // feedback that does not go through `cut`
+                        return None
+                    }
+                    if !funcs.contains_key(&p_inp) {
+                        stack.push((p_inp, false));
+                    }
+                }
+                continue
+            }
+            on_stack.remove(&p_equiv);
+            let f = self.loop_driver_bdd(bdd, driver, &inputs, &funcs)?;
+            funcs.insert(p_equiv, f);
+        }
+        if bdd.len() > max_nodes {
+            return None
+        }
+        let driver = drivers[&cut];
+        self.loop_driver_bdd(bdd, driver, &inputs_of(driver), &funcs)
+    }
+
+    fn loop_driver_bdd(
+        &self,
+        bdd: &mut Bdd,
+        driver: LoopDriver,
+        inputs: &[PBack],
+        funcs: &HashMap<PBack, BddRef>,
+    ) -> Option<BddRef> {
+        let input_funcs: Vec<BddRef> = inputs
+            .iter()
+            .map(|p| funcs.get(p).copied())
+            .collect::<Option<_>>()?;
+        match driver {
+            LoopDriver::LNode(p_lnode) => {
+                if let LNodeKind::DynamicLut(_, ref lut) = self.lnodes[p_lnode].kind {
+                    if lut.iter().any(|x| matches!(x, DynamicValue::ConstUnknown)) {
+                        return None
+                    }
+                }
+                Some(self.lnode_bdd(bdd, p_lnode, &input_funcs))
+            }
+            LoopDriver::TNode(_) => Some(input_funcs[0]),
+        }
+    }
+
+    /// Finds all combinational loops, which are cycles through `LNode`s and
+    /// zero delay `TNode`s that are not broken by a `TNode` with a nonzero
+    /// delay, and classifies their behavior. The value of such loops depends
+    /// on the ordering of evaluation events rather than defined semantics.
+    /// Each loop is cut at one equivalence (preferring the output of a zero
+    /// delay `TNode`, such as that of a `Loop` driven without delay), and the
+    /// function of the feedback is analyzed with a [Bdd] of up to `max_nodes`
+    /// nodes.
+    pub fn combinational_loops(&self, max_nodes: usize) -> Vec<CombinationalLoop> {
+        let mut res = vec![];
+        for scc in self.combinational_sccs() {
+            let mut lnodes = vec![];
+            let mut tnodes = vec![];
+            let mut drivers: HashMap<PBack, LoopDriver> = HashMap::new();
+            // equivalences driven by more than one thing cannot be analyzed
+            let mut multiple_drivers = false;
+            for p_equiv in scc.iter().copied() {
+                let mut adv = self.backrefs.advancer_surject(p_equiv);
+                while let Some(p_back) = adv.advance(&self.backrefs) {
+                    let driver = match *self.backrefs.get_key(p_back).unwrap() {
+                        Referent::ThisLNode(p_lnode) => {
+                            lnodes.push(p_lnode);
+                            LoopDriver::LNode(p_lnode)
+                        }
+                        Referent::ThisTNode(p_tnode) => {
+                            let tnode = &self.tnodes[p_tnode];
+                            if !tnode.delay().is_zero() {
+                                multiple_drivers = true;
+                                continue
+                            }
+                            tnodes.push(p_tnode);
+                            let p_driver =
+                                self.backrefs.get_val(tnode.p_driver).unwrap().p_self_equiv;
+                            LoopDriver::TNode(p_driver)
+                        }
+                        _ => continue,
+                    };
+                    multiple_drivers |= drivers.insert(p_equiv, driver).is_some();
+                }
+            }
+            let cut = if let Some(p_tnode) = tnodes.first() {
+                self.backrefs
+                    .get_val(self.tnodes[*p_tnode].p_self)
+                    .unwrap()
+                    .p_self_equiv
+            } else {
+                self.backrefs
+                    .get_val(self.lnodes[lnodes[0]].p_self)
+                    .unwrap()
+                    .p_self_equiv
+            };
+            let mut bdd = Bdd::new();
+            let feedback = if multiple_drivers {
+                None
+            } else {
+                self.loop_feedback_bdd(&mut bdd, &drivers, cut, max_nodes)
+            };
+            let kind = if let Some(f) = feedback {
+                let f0 = bdd.restrict(f, 0, false);
+                let f1 = bdd.restrict(f, 0, true);
+                let not_f0 = bdd.not(f0);
+                let not_f1 = bdd.not(f1);
+                if bdd.and(f0, not_f1) != BddRef::FALSE {
+                    LoopKind::Oscillator
+                } else if bdd.and(not_f0, f1) != BddRef::FALSE {
+                    LoopKind::Latch
+                } else {
+                    LoopKind::FalseLoop
+                }
+            } else {
+                LoopKind::Unclassified
+            };
+            res.push(CombinationalLoop {
+                lnodes,
+                tnodes,
+                cut,
+                kind,
+            });
+        }
+        res
+    }
+
+    /// Returns an error describing the first combinational loop found by
+    /// [Ensemble::combinational_loops] that is not a
+    /// [LoopKind::FalseLoop]
+    pub fn deny_latches(&self, max_nodes: usize) -> Result<(), Error> {
+        for comb_loop in self.combinational_loops(max_nodes) {
+            if comb_loop.kind != LoopKind::FalseLoop {
+                return Err(Error::OtherString(format!(
+                    "found a {comb_loop}, feedback should go through a `TNode` with a nonzero \
+                     delay"
+                )))
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+use std::fmt;
+
+use crate::Error;
+
+/// The functions of standard cells that [CellLibrary] recognizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CellFunction {
+    /// An inverter with one input
+    Inv,
+    /// A 2 input NAND gate
+    Nand2,
+    /// A 2 input NOR gate
+    Nor2,
+    /// A rising edge D flip-flop with a data input and a noninverted output
+    Dff,
+}
+
+impl fmt::Display for CellFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellFunction::Inv => write!(f, "INV"),
+            CellFunction::Nand2 => write!(f, "NAND2"),
+            CellFunction::Nor2 => write!(f, "NOR2"),
+            CellFunction::Dff => write!(f, "DFF"),
+        }
+    }
+}
+
+/// A standard cell in a [CellLibrary]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellDef {
+    pub name: String,
+    pub function: CellFunction,
+    /// The area in the units of the library
+    pub area: f64,
+    /// The delay from any input to the output, or from the clock to the
+    /// output for flip-flops, in the units of the library
+    pub delay: f64,
+    /// The names of the input pins, in the order of the operands of the
+    /// function. For `CellFunction::Dff` this is only the data pin.
+    pub inputs: Vec<String>,
+    /// The name of the output pin
+    pub output: String,
+    /// The name of the clock pin of a `CellFunction::Dff`
+    pub clock: Option<String>,
+}
+
+/// A library of standard cells for [Ensemble::map_to_cells], parsed from a
+/// simplified subset of the Liberty format by [CellLibrary::from_liberty].
+///
+/// ```text
+/// library (demo) {
+///   cell (NAND2_X1) {
+///     area : 1.0;
+///     pin (A) { direction : input; }
+///     pin (B) { direction : input; }
+///     pin (Y) { direction : output; function : "!(A & B)"; delay : 0.02; }
+///   }
+///   cell (DFF_X1) {
+///     area : 4.5;
+///     ff (IQ, IQN) { next_state : "D"; clocked_on : "CK"; }
+///     pin (D) { direction : input; }
+///     pin (CK) { direction : input; clock : true; }
+///     pin (Q) { direction : output; function : "IQ"; delay : 0.08; }
+///   }
+/// }
+/// ```
+///
+/// Cells with a single output pin whose `function` is an inverter, a 2 input
+/// NAND, or a 2 input NOR of the input pins are recognized, as are cells
+/// with an `ff` group whose `next_state` is an input pin and whose output
+/// pin is the noninverted state. Functions use the Liberty operators `!`,
+/// `'`, `&`, `*`, `|`, `+`, `^`, and juxtaposition for AND. The `delay`
+/// attribute is a simplification of timing tables, it can be on the output
+/// pin or the cell and defaults to zero. Other cells, groups, and attributes
+/// are ignored.
+///
+/// [Ensemble::map_to_cells]: crate::ensemble::Ensemble::map_to_cells
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CellLibrary {
+    pub name: String,
+    pub cells: Vec<CellDef>,
+}
+
+/// A group like `cell (NAND2) { ... }` with its simple attributes
+#[derive(Debug, Default)]
+struct Group {
+    kind: String,
+    args: Vec<String>,
+    attributes: Vec<(String, String)>,
+    groups: Vec<Group>,
+}
+
+impl Group {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<String>, Error> {
+    let mut res = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                loop {
+                    let Some(c) = chars.next() else {
+                        return Err(Error::OtherStr("unterminated comment in Liberty"))
+                    };
+                    if (prev == '*') && (c == '/') {
+                        break
+                    }
+                    prev = c;
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break
+                    }
+                }
+            }
+            '"' => {
+                let mut token = String::from('"');
+                loop {
+                    let Some(c) = chars.next() else {
+                        return Err(Error::OtherStr("unterminated string in Liberty"))
+                    };
+                    if c == '"' {
+                        break
+                    }
+                    token.push(c);
+                }
+                res.push(token);
+            }
+            // line continuations
+            '\\' => (),
+            '(' | ')' | '{' | '}' | ':' | ';' | ',' => res.push(c.to_string()),
+            c if c.is_whitespace() => (),
+            c => {
+                let mut token = String::from(c);
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || "(){}:;,\"".contains(*c) {
+                        break
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+                res.push(token);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Parses the items of a group body until a `}` or the end
+fn parse_items(tokens: &[String], i: &mut usize, group: &mut Group) -> Result<(), Error> {
+    let err = |s: &str| Error::OtherString(format!("could not parse Liberty, {s}"));
+    let value = |token: &str| token.strip_prefix('"').unwrap_or(token).to_owned();
+    while *i < tokens.len() {
+        if tokens[*i] == "}" {
+            return Ok(())
+        }
+        let name = tokens[*i].clone();
+        *i += 1;
+        match tokens.get(*i).map(|s| s.as_str()) {
+            Some(":") => {
+                *i += 1;
+                let v = tokens
+                    .get(*i)
+                    .ok_or_else(|| err(&format!("attribute `{name}` has no value")))?;
+                group.attributes.push((name, value(v)));
+                *i += 1;
+                if tokens.get(*i).map(|s| s.as_str()) == Some(";") {
+                    *i += 1;
+                }
+            }
+            Some("(") => {
+                *i += 1;
+                let mut args = vec![];
+                while let Some(token) = tokens.get(*i) {
+                    *i += 1;
+                    match token.as_str() {
+                        ")" => break,
+                        "," => (),
+                        _ => args.push(value(token)),
+                    }
+                }
+                match tokens.get(*i).map(|s| s.as_str()) {
+                    Some("{") => {
+                        *i += 1;
+                        let mut sub = Group {
+                            kind: name,
+                            args,
+                            ..Default::default()
+                        };
+                        parse_items(tokens, i, &mut sub)?;
+                        if tokens.get(*i).map(|s| s.as_str()) != Some("}") {
+                            return Err(err(&format!("group `{}` is not closed", sub.kind)))
+                        }
+                        *i += 1;
+                        group.groups.push(sub);
+                    }
+                    // complex attributes are ignored
+                    Some(";") => *i += 1,
+                    _ => (),
+                }
+            }
+            _ => return Err(err(&format!("unexpected token after `{name}`"))),
+        }
+    }
+    Ok(())
+}
+
+/// A parsed Liberty Boolean function
+#[derive(Debug)]
+enum Expr {
+    Const(bool),
+    Pin(usize),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Xor(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, inputs: usize) -> bool {
+        match self {
+            Expr::Const(b) => *b,
+            Expr::Pin(i) => ((inputs >> i) & 1) != 0,
+            Expr::Not(x) => !x.eval(inputs),
+            Expr::And(x, y) => x.eval(inputs) && y.eval(inputs),
+            Expr::Or(x, y) => x.eval(inputs) || y.eval(inputs),
+            Expr::Xor(x, y) => x.eval(inputs) ^ y.eval(inputs),
+        }
+    }
+}
+
+/// Recursive descent parser for Liberty functions, with precedence from
+/// lowest to highest of `|`, `^`, `&`, and then the unary operators
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    i: usize,
+    pins: &'a [String],
+}
+
+impl ExprParser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.i).is_some_and(|c| c.is_whitespace()) {
+            self.i += 1;
+        }
+        self.chars.get(self.i).copied()
+    }
+
+    fn or(&mut self) -> Option<Expr> {
+        let mut lhs = self.xor()?;
+        while matches!(self.peek(), Some('|' | '+')) {
+            self.i += 1;
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.xor()?));
+        }
+        Some(lhs)
+    }
+
+    fn xor(&mut self) -> Option<Expr> {
+        let mut lhs = self.and()?;
+        while self.peek() == Some('^') {
+            self.i += 1;
+            lhs = Expr::Xor(Box::new(lhs), Box::new(self.and()?));
+        }
+        Some(lhs)
+    }
+
+    fn and(&mut self) -> Option<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            match self.peek() {
+                Some('&' | '*') => self.i += 1,
+                // juxtaposition
+                Some(c) if (c == '!') || (c == '(') || c.is_alphanumeric() || (c == '_') => (),
+                _ => return Some(lhs),
+            }
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        let mut res = match self.peek()? {
+            '!' => {
+                self.i += 1;
+                Expr::Not(Box::new(self.unary()?))
+            }
+            '(' => {
+                self.i += 1;
+                let res = self.or()?;
+                if self.peek()? != ')' {
+                    return None
+                }
+                self.i += 1;
+                res
+            }
+            _ => {
+                let start = self.i;
+                while self
+                    .chars
+                    .get(self.i)
+                    .is_some_and(|c| c.is_alphanumeric() || (*c == '_'))
+                {
+                    self.i += 1;
+                }
+                let name: String = self.chars[start..self.i].iter().collect();
+                match name.as_str() {
+                    "" => return None,
+                    "0" => Expr::Const(false),
+                    "1" => Expr::Const(true),
+                    _ => Expr::Pin(self.pins.iter().position(|pin| *pin == name)?),
+                }
+            }
+        };
+        while self.peek() == Some('\'') {
+            self.i += 1;
+            res = Expr::Not(Box::new(res));
+        }
+        Some(res)
+    }
+}
+
+/// Parses `function` over `pins` into a truth table with the first pin as
+/// the least significant index bit
+fn truth_table(function: &str, pins: &[String]) -> Option<Vec<bool>> {
+    let mut parser = ExprParser {
+        chars: function.chars().collect(),
+        i: 0,
+        pins,
+    };
+    let expr = parser.or()?;
+    if parser.peek().is_some() {
+        return None
+    }
+    Some((0..(1 << pins.len())).map(|i| expr.eval(i)).collect())
+}
+
+/// Returns the value of the numeric attribute `name` of `group`, or `None` if
+/// the attribute is absent. Returns an error if the value is not a number.
+fn number(group: &Group, name: &str) -> Result<Option<f64>, Error> {
+    match group.attribute(name) {
+        Some(v) => v.parse::<f64>().map(Some).map_err(|_| {
+            Error::OtherString(format!(
+                "could not parse Liberty, `{name}` value \"{v}\" is not a number"
+            ))
+        }),
+        None => Ok(None),
+    }
+}
+
+impl CellLibrary {
+    /// Parses the simplified Liberty subset described in [CellLibrary]
+    ///
+    /// # Errors
+    ///
+    /// If the text could not be tokenized or groups are malformed, if there
+    /// is no `library` group, or if a recognized cell has an `area` or
+    /// `delay` that is not a number
+    pub fn from_liberty(liberty: &str) -> Result<Self, Error> {
+        let tokens = tokenize(liberty)?;
+        let mut top = Group::default();
+        let mut i = 0;
+        parse_items(&tokens, &mut i, &mut top)?;
+        if i != tokens.len() {
+            return Err(Error::OtherStr("could not parse Liberty, unmatched `}`"))
+        }
+        let Some(library) = top.groups.iter().find(|g| g.kind == "library") else {
+            return Err(Error::OtherStr(
+                "could not parse Liberty, there is no `library` group",
+            ))
+        };
+        let mut res = CellLibrary {
+            name: library.args.first().cloned().unwrap_or_default(),
+            cells: vec![],
+        };
+        for cell in library.groups.iter().filter(|g| g.kind == "cell") {
+            let Some(name) = cell.args.first() else {
+                continue
+            };
+            let pins: Vec<&Group> = cell.groups.iter().filter(|g| g.kind == "pin").collect();
+            let direction = |dir: &str| -> Vec<&Group> {
+                pins.iter()
+                    .copied()
+                    .filter(|pin| pin.attribute("direction") == Some(dir))
+                    .collect()
+            };
+            let (inputs, outputs) = (direction("input"), direction("output"));
+            let [output] = outputs[..] else { continue };
+            let Some(output_name) = output.args.first() else {
+                continue
+            };
+            let Some(output_function) = output.attribute("function") else {
+                continue
+            };
+            let mut input_names: Vec<String> = inputs
+                .iter()
+                .filter_map(|pin| pin.args.first().cloned())
+                .collect();
+            let mut clock = None;
+            let function = if let Some(ff) = cell.groups.iter().find(|g| g.kind == "ff") {
+                let (Some(state), Some(next_state), Some(clocked_on)) = (
+                    ff.args.first(),
+                    ff.attribute("next_state"),
+                    ff.attribute("clocked_on"),
+                ) else {
+                    continue
+                };
+                let (next_state, clocked_on) = (next_state.trim(), clocked_on.trim());
+                if (output_function.trim() != state)
+                    || !input_names.iter().any(|pin| pin == next_state)
+                    || !input_names.iter().any(|pin| pin == clocked_on)
+                    || (input_names.len() != 2)
+                {
+                    continue
+                }
+                input_names = vec![next_state.to_owned()];
+                clock = Some(clocked_on.to_owned());
+                CellFunction::Dff
+            } else {
+                let Some(table) = truth_table(output_function, &input_names) else {
+                    continue
+                };
+                match table[..] {
+                    [true, false] => CellFunction::Inv,
+                    [true, true, true, false] => CellFunction::Nand2,
+                    [true, false, false, false] => CellFunction::Nor2,
+                    _ => continue,
+                }
+            };
+            let area = number(cell, "area")?.unwrap_or(0.0);
+            let delay = match number(output, "delay")? {
+                Some(delay) => delay,
+                None => number(cell, "delay")?.unwrap_or(0.0),
+            };
+            res.cells.push(CellDef {
+                name: name.clone(),
+                function,
+                area,
+                delay,
+                inputs: input_names,
+                output: output_name.clone(),
+                clock,
+            });
+        }
+        Ok(res)
+    }
+
+    /// Returns the index of the cell with `function` with the smallest area,
+    /// with ties going to the smallest delay and then the first cell
+    pub fn smallest(&self, function: CellFunction) -> Option<usize> {
+        let mut res: Option<usize> = None;
+        for (i, cell) in self.cells.iter().enumerate() {
+            if cell.function != function {
+                continue
+            }
+            if let Some(j) = res {
+                let best = &self.cells[j];
+                if cell
+                    .area
+                    .total_cmp(&best.area)
+                    .then(cell.delay.total_cmp(&best.delay))
+                    .is_ge()
+                {
+                    continue
+                }
+            }
+            res = Some(i);
+        }
+        res
+    }
+}
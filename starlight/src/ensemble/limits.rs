@@ -0,0 +1,282 @@
+use std::{mem, num::NonZeroUsize};
+
+use awint::awint_dag::{smallvec::smallvec, Op, PState};
+
+use crate::{
+    ensemble::{Ensemble, Equiv, LNode, Referent, State, TNode, MAX_UNCHUNKED_LUT_INPUTS},
+    lower::LutDecomposition,
+    Error,
+};
+
+/// Limits on the resources an `Ensemble` is allowed to use, set with
+/// [crate::Epoch::set_limits]. `None` means that there is no limit. When a
+/// limit is exceeded, `Error::ResourceLimitExceeded` is returned from the
+/// next fallible operation instead of continuing to consume resources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of `State`s
+    pub max_states: Option<usize>,
+    /// The maximum number of `LNode`s
+    pub max_lnodes: Option<usize>,
+    /// The maximum number of inputs a single lookup table may have, lookup
+    /// tables with more inputs are lowered into trees of smaller tables. If
+    /// `None`, [crate::ensemble::MAX_UNCHUNKED_LUT_INPUTS] is used.
+    pub max_single_lut_inputs: Option<usize>,
+    /// How lookup tables with more than `max_single_lut_inputs` inputs are
+    /// split
+    pub lut_decomposition: LutDecomposition,
+    /// The maximum estimated number of bytes used by the arenas of the
+    /// `Ensemble`
+    pub max_memory_bytes: Option<usize>,
+    /// The maximum number of zero delay events that can be handled before the
+    /// evaluator settles at a single point in time, after which
+    /// `Error::ZeroDelayNonConvergence` is returned. If `None`, four events
+    /// per backreference are allowed.
+    pub max_zero_delay_events: Option<usize>,
+}
+
+impl Limits {
+    /// No limits on anything
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+}
+
+/// The number of elements an arena can have when the `u32_ptrs` feature is
+/// enabled
+pub const U32_PTR_CAPACITY: usize = (u32::MAX - 1) as usize;
+
+/// The number of elements an arena can have in this build. Note that debug
+/// builds always use full sized `Ptr`s.
+#[cfg(all(not(debug_assertions), feature = "u32_ptrs"))]
+pub const PTR_CAPACITY: usize = U32_PTR_CAPACITY;
+/// The number of elements an arena can have in this build. Note that debug
+/// builds always use full sized `Ptr`s.
+#[cfg(any(debug_assertions, not(feature = "u32_ptrs")))]
+pub const PTR_CAPACITY: usize = usize::MAX;
+
+/// A rough number of backreferences that lowering creates per `LNode`
+const BACKREFS_PER_LNODE: usize = 16;
+
+/// A rough estimate of the number of `LNode`s that lowering a state with `op`
+/// and output bitwidth `w` creates
+fn estimated_lnodes(ensemble: &Ensemble, op: &Op<PState>, w: usize) -> usize {
+    let operand_w = |i: usize| -> usize {
+        op.operands()
+            .get(i)
+            .and_then(|p| ensemble.stator.states.get(*p))
+            .map(|state| state.nzbw.get())
+            .unwrap_or(0)
+    };
+    match op {
+        Op::Opaque(..) | Op::Argument(_) | Op::Literal(_) | Op::Copy(_) => 0,
+        // quadratic in the bitwidth
+        Op::ArbMulAdd(_) | Op::UQuo(_) | Op::URem(_) | Op::IQuo(_) | Op::IRem(_) => {
+            w.saturating_mul(operand_w(1).max(operand_w(2)).max(w))
+        }
+        // each output bit can select from any input bit
+        Op::Funnel(_)
+        | Op::Shl(_)
+        | Op::Lshr(_)
+        | Op::Ashr(_)
+        | Op::Rotl(_)
+        | Op::Rotr(_)
+        | Op::Lut(_)
+        | Op::Get(_)
+        | Op::Set(_)
+        | Op::LutSet(_)
+        | Op::Field(_)
+        | Op::FieldTo(_)
+        | Op::FieldFrom(_)
+        | Op::FieldWidth(_)
+        | Op::FieldBit(_) => {
+            let operands_w = (0..op.operands_len()).fold(0usize, |acc, i| acc + operand_w(i));
+            w.saturating_mul(operands_w)
+        }
+        _ => w,
+    }
+}
+
+/// The arena sizes of an `Ensemble`, returned by
+/// [Ensemble::estimate_ptr_requirements]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtrRequirements {
+    pub states: usize,
+    pub backref_keys: usize,
+    pub backref_vals: usize,
+    pub lnodes: usize,
+    pub tnodes: usize,
+    pub rnodes: usize,
+    /// A rough estimate of the number of backreference keys (which is
+    /// normally the largest arena) after all the current states are lowered
+    pub estimated_lowered_backref_keys: usize,
+}
+
+impl PtrRequirements {
+    /// Arenas are considered to be near their capacity once they reach this
+    /// many elements
+    pub fn near_capacity(capacity: usize) -> usize {
+        capacity - (capacity / 16)
+    }
+
+    /// The names and current lengths of the arenas
+    pub fn arenas(&self) -> [(&'static str, usize); 6] {
+        [
+            ("states", self.states),
+            ("backref_keys", self.backref_keys),
+            ("backref_vals", self.backref_vals),
+            ("lnodes", self.lnodes),
+            ("tnodes", self.tnodes),
+            ("rnodes", self.rnodes),
+        ]
+    }
+
+    /// Returns the largest current or estimated arena length
+    pub fn max_len(&self) -> usize {
+        self.arenas()
+            .iter()
+            .map(|(_, len)| *len)
+            .max()
+            .unwrap()
+            .max(self.estimated_lowered_backref_keys)
+    }
+
+    /// Returns if the current and estimated arena lengths are not near
+    /// `capacity`. Use [U32_PTR_CAPACITY] to check if the `u32_ptrs` feature
+    /// can be used.
+    pub fn fits(&self, capacity: usize) -> bool {
+        self.max_len() < Self::near_capacity(capacity)
+    }
+
+    /// Returns `Error::PtrCapacityExceeded` if any current arena length is near
+    /// `capacity`
+    pub fn check(&self, capacity: usize) -> Result<(), Error> {
+        for (arena, len) in self.arenas() {
+            if len >= Self::near_capacity(capacity) {
+                return Err(Error::PtrCapacityExceeded(arena, len, capacity))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Ensemble {
+    /// Returns a rough estimate of the number of bytes used by the main arenas
+    /// and the distinct `LNode` tables of `self`
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let mut bytes = self
+            .stator
+            .states
+            .len()
+            .saturating_mul(mem::size_of::<State>());
+        bytes = bytes.saturating_add(
+            self.backrefs
+                .len_keys()
+                .saturating_mul(mem::size_of::<Referent>()),
+        );
+        bytes = bytes.saturating_add(
+            self.backrefs
+                .len_vals()
+                .saturating_mul(mem::size_of::<Equiv>()),
+        );
+        bytes = bytes.saturating_add(self.lnodes.len().saturating_mul(mem::size_of::<LNode>()));
+        bytes = bytes.saturating_add(self.lut_table_stats().bytes);
+        bytes.saturating_add(self.tnodes.len().saturating_mul(mem::size_of::<TNode>()))
+    }
+
+    fn current_ptr_requirements(&self) -> PtrRequirements {
+        PtrRequirements {
+            states: self.stator.states.len(),
+            backref_keys: self.backrefs.len_keys(),
+            backref_vals: self.backrefs.len_vals(),
+            lnodes: self.lnodes.len(),
+            tnodes: self.tnodes.len(),
+            rnodes: self.notary.rnodes().len(),
+            estimated_lowered_backref_keys: self.backrefs.len_keys(),
+        }
+    }
+
+    /// Returns the current arena sizes of `self` and a rough estimate of the
+    /// backreferences needed after lowering the current states. Before a long
+    /// lowering, [PtrRequirements::fits] can be used to check if a build with
+    /// the `u32_ptrs` feature would run out of capacity.
+    pub fn estimate_ptr_requirements(&self) -> PtrRequirements {
+        let mut res = self.current_ptr_requirements();
+        for state in self.stator.states.vals() {
+            if !state.lowered_to_lnodes {
+                let lnodes = estimated_lnodes(self, &state.op, state.nzbw.get());
+                res.estimated_lowered_backref_keys = res
+                    .estimated_lowered_backref_keys
+                    .saturating_add(lnodes.saturating_mul(BACKREFS_PER_LNODE));
+            }
+        }
+        res
+    }
+
+    /// Returns an error if any of the limits in `self.limits` are currently
+    /// exceeded, or if an arena is near [PTR_CAPACITY]
+    pub fn check_limits(&self) -> Result<(), Error> {
+        self.current_ptr_requirements().check(PTR_CAPACITY)?;
+        if let Some(max) = self.limits.max_states {
+            if self.stator.states.len() > max {
+                return Err(Error::ResourceLimitExceeded("max_states", max))
+            }
+        }
+        if let Some(max) = self.limits.max_lnodes {
+            if self.lnodes.len() > max {
+                return Err(Error::ResourceLimitExceeded("max_lnodes", max))
+            }
+        }
+        if let Some(max) = self.limits.max_memory_bytes {
+            if self.estimated_memory_bytes() > max {
+                return Err(Error::ResourceLimitExceeded("max_memory_bytes", max))
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the maximum number of inputs that lowering should give a single
+    /// lookup table, according to `self.limits.max_single_lut_inputs`
+    pub fn lut_chunk_inputs(&self) -> Result<usize, Error> {
+        match self.limits.max_single_lut_inputs {
+            // there is no way to split up tables with inputs into zero input tables
+            Some(0) => Err(Error::ResourceLimitExceeded("max_single_lut_inputs", 0)),
+            Some(max) => Ok(max),
+            None => Ok(MAX_UNCHUNKED_LUT_INPUTS),
+        }
+    }
+
+    /// Returns the number of zero delay events that can be handled before the
+    /// evaluator has to settle, according to
+    /// `self.limits.max_zero_delay_events`
+    pub fn zero_delay_event_gas(&self) -> usize {
+        self.limits
+            .max_zero_delay_events
+            .unwrap_or_else(|| self.backrefs.len_keys() * 4)
+    }
+
+    /// Returns the existing state that `make_state` stored a resource limit
+    /// error on for bitwidth `nzbw`, if it still exists
+    pub(crate) fn limit_state(&self, nzbw: NonZeroUsize) -> Option<PState> {
+        let p_state = *self.stator.limit_states.get(&nzbw)?;
+        let state = self.stator.states.get(p_state)?;
+        // the state could have been pruned and its slot reused
+        let is_limit_state = (state.nzbw == nzbw)
+            && matches!(
+                state.err,
+                Some(Error::ResourceLimitExceeded(..) | Error::PtrCapacityExceeded(..))
+            )
+            && matches!(state.op, Op::Opaque(ref v, None) if v.is_empty());
+        is_limit_state.then_some(p_state)
+    }
+
+    /// Used by `make_state` when the state limit is reached. Instead of the
+    /// requested `op`, the state becomes an operand-free `Opaque` with its
+    /// error set, so that it does not keep the rest of the graph alive.
+    pub(crate) fn limit_state_op(&mut self, op: Op<PState>) -> (Op<PState>, Option<Error>) {
+        match self.check_limits() {
+            Ok(()) => (op, None),
+            Err(e) => (Op::Opaque(smallvec![], None), Some(e)),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     cmp::max,
+    collections::{hash_map::Entry, HashMap, HashSet},
     mem,
     num::{NonZeroU64, NonZeroUsize},
 };
@@ -8,7 +9,7 @@ use awint::{
     awi,
     awint_dag::{
         smallvec,
-        triple_arena::{Recast, Recaster, SurjectArena},
+        triple_arena::{Advancer, Recast, Recaster, SurjectArena},
         PState,
     },
     Awi, Bits,
@@ -16,19 +17,44 @@ use awint::{
 use smallvec::{smallvec, SmallVec};
 
 use crate::{
-    ensemble::{DynamicValue, Ensemble, Equiv, PBack, PLNode, Referent, Value},
+    ensemble::{
+        ChangeKind, DynamicValue, Ensemble, Equiv, Event, JournalEntry, LutTable, PBack, PLNode,
+        Referent, Value,
+    },
+    lower::{decompose, LutDecomposition},
     Error,
 };
 
+/// Lookup tables with more inputs than this are lowered into trees of smaller
+/// lookup tables, unless `Limits::max_single_lut_inputs` is set
+pub const MAX_UNCHUNKED_LUT_INPUTS: usize = 16;
+
+/// The number of `LNode` inputs that are stored inline without a separate
+/// allocation, this is 6 with the `perf` feature and 4 otherwise
+#[cfg(feature = "perf")]
+pub const LNODE_INLINE_INPUTS: usize = 6;
+/// The number of `LNode` inputs that are stored inline without a separate
+/// allocation, this is 6 with the `perf` feature and 4 otherwise
+#[cfg(not(feature = "perf"))]
+pub const LNODE_INLINE_INPUTS: usize = 4;
+
+/// The inputs of an `LNode`
+pub type LNodeInputs = SmallVec<[PBack; LNODE_INLINE_INPUTS]>;
+
 #[derive(Debug, Clone)]
 pub enum LNodeKind {
     /// Copy a single input bit
     Copy(PBack),
-    /// Static Lookup Table that outputs one bit, the `Awi` is the table and the
-    /// `SmallVec` is the inputs
-    Lut(SmallVec<[PBack; 4]>, Awi),
+    /// Static Lookup Table that outputs one bit, the `LutTable` is the table
+    /// and the `SmallVec` is the inputs
+    Lut(LNodeInputs, LutTable),
     /// A Dynamic Lookup Table with the inputs and then the `Vec` is the table
-    DynamicLut(SmallVec<[PBack; 4]>, Vec<DynamicValue>),
+    DynamicLut(LNodeInputs, Vec<DynamicValue>),
+    /// One stage of a carry chain, outputs the majority of the carry in, lhs
+    /// bit, and rhs bit in that order. This is functionally the same as a `Lut`
+    /// with [LNode::carry_lut], but is kept distinct so that targets with
+    /// dedicated carry resources can map it directly.
+    Carry([PBack; 3]),
 }
 
 /// A lookup table node
@@ -262,6 +288,11 @@ impl LNode {
                     }
                 }
             }
+            LNodeKind::Carry(inp) => {
+                for inp in inp.iter() {
+                    f(*inp);
+                }
+            }
         }
     }
 
@@ -284,9 +315,20 @@ impl LNode {
                     }
                 }
             }
+            LNodeKind::Carry(inp) => {
+                for inp in inp.iter_mut() {
+                    f(inp);
+                }
+            }
         }
     }
 
+    /// The static lookup table equivalent to `LNodeKind::Carry`
+    pub fn carry_lut() -> Awi {
+        use awi::*;
+        awi!(1110_1000)
+    }
+
     /// Reduce a LUT in half by saving entries indexed by setting the `i`th
     /// input bit to `bit`
     pub fn reduce_lut(lut: &mut Awi, i: usize, bit: bool) {
@@ -446,7 +488,7 @@ impl Ensemble {
             }
             LNodeKind::Lut(inp, original_lut) => {
                 let len = inp.len();
-                let mut lut = original_lut.clone();
+                let mut lut = original_lut.to_awi();
                 let mut max_partial_ord_num = NonZeroU64::new(1).unwrap();
                 for i in (0..len).rev() {
                     let p_inp = inp[i];
@@ -531,6 +573,28 @@ impl Ensemble {
                 }
                 (Value::Unknown, max_partial_ord_num)
             }
+            LNodeKind::Carry(inp) => {
+                let mut max_partial_ord_num = NonZeroU64::new(1).unwrap();
+                let mut known_ones = 0;
+                let mut known_zeros = 0;
+                for p_inp in inp.iter().copied() {
+                    let equiv = self.backrefs.get_val(p_inp).unwrap();
+                    max_partial_ord_num = max(max_partial_ord_num, equiv.evaluator_partial_order);
+                    match equiv.val.known_value() {
+                        Some(true) => known_ones += 1,
+                        Some(false) => known_zeros += 1,
+                        None => (),
+                    }
+                }
+                // the majority is known as soon as two inputs agree
+                if known_ones >= 2 {
+                    (Value::Dynam(true), max_partial_ord_num)
+                } else if known_zeros >= 2 {
+                    (Value::Dynam(false), max_partial_ord_num)
+                } else {
+                    (Value::Unknown, max_partial_ord_num)
+                }
+            }
         })
     }
 
@@ -560,6 +624,7 @@ impl Ensemble {
                 Equiv::new(p_self_equiv, Value::Unknown),
             )
         });
+        let lut = self.lut_tables.intern_bits(lut);
         let p_lnode = self.lnodes.insert_with(|p_lnode| {
             let p_self = self
                 .backrefs
@@ -573,7 +638,7 @@ impl Ensemble {
                     .unwrap();
                 inp.push(p_back);
             }
-            LNode::new(p_self, LNodeKind::Lut(inp, Awi::from(lut)), lowered_from)
+            LNode::new(p_self, LNodeKind::Lut(inp, lut), lowered_from)
         });
         // For DFS lowering, we want to calculate the current `Lut` value and set it to
         // prevent issues about change events that would happen if we didn't simply
@@ -586,6 +651,257 @@ impl Ensemble {
         p_equiv
     }
 
+    /// Like [Ensemble::make_lut], except that if there are more than
+    /// `chunk_inputs` inputs, the table is split into a tree of lookup tables
+    /// that each have at most `chunk_inputs` inputs. The first level selects
+    /// from chunks of the table with the least significant inputs, and
+    /// subsequent levels select between the results of previous levels.
+    #[must_use]
+    pub fn make_chunked_lut(
+        &mut self,
+        p_inxs: &[Option<PBack>],
+        lut: &Bits,
+        chunk_inputs: usize,
+        lowered_from: Option<PState>,
+    ) -> PBack {
+        debug_assert!(chunk_inputs > 0);
+        if p_inxs.len() <= chunk_inputs {
+            return self.make_lut(p_inxs, lut, lowered_from)
+        }
+        let chunk_len = 1 << chunk_inputs;
+        let mut chunk = Awi::zero(NonZeroUsize::new(chunk_len).unwrap());
+        let mut values = vec![];
+        let mut from = 0;
+        while from < lut.bw() {
+            chunk.field_from(lut, from, chunk_len).unwrap();
+            let p_equiv = self.make_lut(&p_inxs[..chunk_inputs], &chunk, lowered_from);
+            values.push(DynamicValue::Dynam(p_equiv));
+            from += chunk_len;
+        }
+        self.make_chunked_dynamic_lut(&p_inxs[chunk_inputs..], &values, chunk_inputs, lowered_from)
+    }
+
+    /// Like [Ensemble::make_dynamic_lut], except that if there are more than
+    /// `chunk_inputs` inputs, the table is split into a tree of dynamic lookup
+    /// tables that each have at most `chunk_inputs` inputs.
+    #[must_use]
+    pub fn make_chunked_dynamic_lut(
+        &mut self,
+        p_inxs: &[Option<PBack>],
+        p_lut_bits: &[DynamicValue],
+        chunk_inputs: usize,
+        lowered_from: Option<PState>,
+    ) -> PBack {
+        debug_assert!(chunk_inputs > 0);
+        let mut p_inxs = p_inxs;
+        let mut values = p_lut_bits.to_vec();
+        while p_inxs.len() > chunk_inputs {
+            let chunk_len = 1 << chunk_inputs;
+            let mut next_values = Vec::with_capacity(values.len() / chunk_len);
+            for chunk in values.chunks(chunk_len) {
+                let p_equiv = self.make_dynamic_lut(&p_inxs[..chunk_inputs], chunk, lowered_from);
+                next_values.push(DynamicValue::Dynam(p_equiv));
+            }
+            values = next_values;
+            p_inxs = &p_inxs[chunk_inputs..];
+        }
+        self.make_dynamic_lut(p_inxs, &values, lowered_from)
+    }
+
+    /// Like [Ensemble::make_chunked_lut], except that the table is split
+    /// according to `decomposition`
+    #[must_use]
+    pub fn make_decomposed_lut(
+        &mut self,
+        p_inxs: &[Option<PBack>],
+        lut: &Bits,
+        chunk_inputs: usize,
+        decomposition: LutDecomposition,
+        lowered_from: Option<PState>,
+    ) -> PBack {
+        debug_assert!(chunk_inputs > 0);
+        if decomposition == LutDecomposition::Chunked {
+            return self.make_chunked_lut(p_inxs, lut, chunk_inputs, lowered_from)
+        }
+        // remove independent inputs first
+        let mut p_inxs = p_inxs.to_vec();
+        let mut lut = Awi::from(lut);
+        for i in (0..p_inxs.len()).rev() {
+            if (p_inxs.len() > 1) && decompose::is_independent(&lut, i) {
+                LNode::reduce_lut(&mut lut, i, false);
+                p_inxs.remove(i);
+            }
+        }
+        if p_inxs.len() <= chunk_inputs {
+            return self.make_lut(&p_inxs, &lut, lowered_from)
+        }
+        if decomposition == LutDecomposition::Curtis {
+            if let Some(decomp) = decompose::find_curtis_decomposition(&lut, chunk_inputs) {
+                let bound: Vec<Option<PBack>> = decomp.bound.iter().map(|i| p_inxs[*i]).collect();
+                let mut next_inxs: Vec<Option<PBack>> =
+                    decomp.free.iter().map(|i| p_inxs[*i]).collect();
+                for g in &decomp.bound_luts {
+                    next_inxs.push(Some(self.make_lut(&bound, g, lowered_from)));
+                }
+                return self.make_decomposed_lut(
+                    &next_inxs,
+                    &decomp.composition_lut,
+                    chunk_inputs,
+                    decomposition,
+                    lowered_from,
+                )
+            }
+        }
+        // Shannon expansion into a multiplexer
+        let i = decompose::shannon_variable(&lut);
+        let (lut0, lut1) = decompose::shannon_cofactors(&lut, i);
+        let p_var = p_inxs.remove(i);
+        let p_lut0 =
+            self.make_decomposed_lut(&p_inxs, &lut0, chunk_inputs, decomposition, lowered_from);
+        let p_lut1 =
+            self.make_decomposed_lut(&p_inxs, &lut1, chunk_inputs, decomposition, lowered_from);
+        // the multiplexer is chunked further if `chunk_inputs < 3`
+        self.make_chunked_lut(
+            &[Some(p_lut0), Some(p_lut1), p_var],
+            &Awi::from_u8(0b1100_1010),
+            chunk_inputs,
+            lowered_from,
+        )
+    }
+
+    /// Splits every static LUT with more than `max_inputs` inputs according to
+    /// `decomposition`, returning the number of LUTs that were split. This is
+    /// useful for mapping an optimized `Ensemble` to a target with smaller
+    /// LUTs than were used during lowering.
+    pub fn decompose_luts(
+        &mut self,
+        max_inputs: usize,
+        decomposition: LutDecomposition,
+    ) -> Result<usize, Error> {
+        if max_inputs == 0 {
+            return Err(Error::OtherStr(
+                "`Ensemble::decompose_luts` was given `max_inputs == 0`",
+            ))
+        }
+        self.journal_lnode_step("decompose_luts");
+        // pending events may refer to `LNode`s that get removed
+        self.restart_request_phase()?;
+        let mut wide = vec![];
+        let mut adv = self.lnodes.advancer();
+        while let Some(p_lnode) = adv.advance(&self.lnodes) {
+            if let LNodeKind::Lut(ref inp, _) = self.lnodes[p_lnode].kind {
+                if inp.len() > max_inputs {
+                    wide.push(p_lnode);
+                }
+            }
+        }
+        for p_lnode in wide.iter().copied() {
+            let lnode = self.lnodes.remove(p_lnode).unwrap();
+            let (inp, lut) = if let LNodeKind::Lut(inp, lut) = lnode.kind {
+                (inp, lut)
+            } else {
+                unreachable!()
+            };
+            let p_equiv = self.backrefs.get_val(lnode.p_self).unwrap().p_self_equiv;
+            let p_inxs: Vec<Option<PBack>> = inp
+                .iter()
+                .map(|p_inp| Some(self.backrefs.get_val(*p_inp).unwrap().p_self_equiv))
+                .collect();
+            let p_new = self.make_decomposed_lut(
+                &p_inxs,
+                &lut,
+                max_inputs,
+                decomposition,
+                lnode.lowered_from,
+            );
+            for p_inp in inp {
+                self.backrefs.remove_key(p_inp).unwrap();
+            }
+            self.backrefs.remove_key(lnode.p_self).unwrap();
+            self.union_equiv(p_equiv, p_new)?;
+        }
+        self.reinit_partial_orders()?;
+        Ok(wide.len())
+    }
+
+    /// Makes a `LNodeKind::Carry` from the carry in, lhs bit, and rhs bit
+    /// `p_inxs` and returns a `PBack` to it. Panics if any of the `p_inxs` are
+    /// invalid.
+    #[must_use]
+    pub fn make_carry(&mut self, p_inxs: [PBack; 3], lowered_from: Option<PState>) -> PBack {
+        let p_equiv = self.backrefs.insert_with(|p_self_equiv| {
+            (
+                Referent::ThisEquiv,
+                Equiv::new(p_self_equiv, Value::Unknown),
+            )
+        });
+        let p_lnode = self.lnodes.insert_with(|p_lnode| {
+            let p_self = self
+                .backrefs
+                .insert_key(p_equiv, Referent::ThisLNode(p_lnode))
+                .unwrap();
+            let inp = p_inxs.map(|p_inx| {
+                self.backrefs
+                    .insert_key(p_inx, Referent::Input(p_lnode))
+                    .unwrap()
+            });
+            LNode::new(p_self, LNodeKind::Carry(inp), lowered_from)
+        });
+        // same as in the static LUT case
+        let (init_val, source_partial_ordering) = self.calculate_lnode_value(p_lnode).unwrap();
+        let equiv = self.backrefs.get_val_mut(p_equiv).unwrap();
+        equiv.val = init_val;
+        equiv.evaluator_partial_order = source_partial_ordering.checked_add(1).unwrap();
+        p_equiv
+    }
+
+    /// Returns the `LNodeKind::Carry` driving the equivalence of `p_back`, if
+    /// there is one
+    fn carry_driving(&self, p_back: PBack) -> Option<PLNode> {
+        let mut adv = self.backrefs.advancer_surject(p_back);
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisLNode(p_lnode) = *self.backrefs.get_key(p_back).unwrap() {
+                if let LNodeKind::Carry(_) = self.lnodes[p_lnode].kind {
+                    return Some(p_lnode)
+                }
+            }
+        }
+        None
+    }
+
+    /// Groups all the `LNodeKind::Carry`s into chains, where each carry in is
+    /// driven by the previous carry of the chain. The chains are ordered from
+    /// the least significant stage to the most significant stage.
+    pub fn carry_chains(&self) -> Vec<Vec<PLNode>> {
+        let mut next: HashMap<PLNode, PLNode> = HashMap::new();
+        let mut has_prev: HashSet<PLNode> = HashSet::new();
+        for (p_lnode, lnode) in &self.lnodes {
+            if let LNodeKind::Carry(inp) = lnode.kind {
+                if let Some(p_prev) = self.carry_driving(inp[0]) {
+                    // if a carry out fans out to multiple carry ins, only one of them can
+                    // continue the chain and the others start their own chains
+                    if let Entry::Vacant(v) = next.entry(p_prev) {
+                        v.insert(p_lnode);
+                        has_prev.insert(p_lnode);
+                    }
+                }
+            }
+        }
+        let mut res = vec![];
+        for (p_lnode, lnode) in &self.lnodes {
+            if matches!(lnode.kind, LNodeKind::Carry(_)) && !has_prev.contains(&p_lnode) {
+                let mut chain = vec![p_lnode];
+                let mut p = p_lnode;
+                while let Some(p_next) = next.get(&p).copied() {
+                    chain.push(p_next);
+                    p = p_next;
+                }
+                res.push(chain);
+            }
+        }
+        res
+    }
+
     /// Creates separate unique `Referent::Input`s as necessary. Panics if the
     /// table length is incorrect or any of the `p_inxs` are invalid.
     #[must_use]
@@ -645,4 +961,165 @@ impl Ensemble {
         equiv.evaluator_partial_order = source_partial_ordering.checked_add(1).unwrap();
         p_equiv
     }
+
+    /// Directly instantiates lookup table `LNode`s with the concatenated bits
+    /// of the already lowered `p_inputs` as the index (the first input being
+    /// in the least significant position), and makes the bits of the
+    /// uninitialized `p_out` state be the outputs. Like `Op::Lut`, the entries
+    /// of `table` are `p_out`'s bitwidth wide. `p_out` is marked as lowered so
+    /// that its `Op` is ignored.
+    pub fn instantiate_lut(
+        &mut self,
+        p_inputs: &[PState],
+        table: &Bits,
+        p_out: PState,
+    ) -> Result<(), Error> {
+        let mut inx_bits = vec![];
+        for p_input in p_inputs.iter().copied() {
+            let state = self.stator.states.get(p_input).ok_or(Error::InvalidPtr)?;
+            if state.p_self_bits.is_empty() {
+                return Err(Error::OtherStr("an input state has not been lowered"))
+            }
+            for bit in &state.p_self_bits {
+                if bit.is_none() {
+                    return Err(Error::OtherStr("an input state has had bits removed"))
+                }
+                inx_bits.push(*bit);
+            }
+        }
+        let out = self.stator.states.get(p_out).ok_or(Error::InvalidPtr)?;
+        if !out.p_self_bits.is_empty() {
+            return Err(Error::OtherStr("the output state is already initialized"))
+        }
+        let out_bw = out.nzbw.get();
+        let num_entries = u32::try_from(inx_bits.len())
+            .ok()
+            .and_then(|w| 1usize.checked_shl(w))
+            .ok_or(Error::OtherStr("too many lookup table inputs"))?;
+        if num_entries.checked_mul(out_bw) != Some(table.bw()) {
+            return Err(Error::OtherString(format!(
+                "lookup table with {} inputs and {} outputs should have bitwidth {}, got {}",
+                inx_bits.len(),
+                out_bw,
+                num_entries.saturating_mul(out_bw),
+                table.bw()
+            )))
+        }
+        let chunk_inputs = self.lut_chunk_inputs()?;
+        let mut p_self_bits = smallvec![];
+        for bit_i in 0..out_bw {
+            let single_bit_lut = if out_bw == 1 {
+                Awi::from(table)
+            } else {
+                let mut val = Awi::zero(NonZeroUsize::new(num_entries).unwrap());
+                for i in 0..num_entries {
+                    val.set(i, table.get((i * out_bw) + bit_i).unwrap())
+                        .unwrap();
+                }
+                val
+            };
+            let p_equiv = self.make_decomposed_lut(
+                &inx_bits,
+                &single_bit_lut,
+                chunk_inputs,
+                self.limits.lut_decomposition,
+                Some(p_out),
+            );
+            p_self_bits.push(Some(
+                self.backrefs
+                    .insert_key(p_equiv, Referent::ThisStateBit(p_out, bit_i))
+                    .unwrap(),
+            ));
+        }
+        let out = &mut self.stator.states[p_out];
+        out.p_self_bits = p_self_bits;
+        out.lowered_to_elementary = true;
+        out.lowered_to_lnodes = true;
+        Ok(())
+    }
+
+    /// Returns the `LNode` that drives the equivalence of `p_back`, if there
+    /// is one
+    pub fn lnode_driving(&self, p_back: PBack) -> Option<PLNode> {
+        let mut adv = self.backrefs.advancer_surject(p_back);
+        while let Some(p_ref) = adv.advance(&self.backrefs) {
+            if let Referent::ThisLNode(p_lnode) = *self.backrefs.get_key(p_ref).unwrap() {
+                return Some(p_lnode)
+            }
+        }
+        None
+    }
+
+    /// Changes the function of the `LNode` at `p_lnode` in place to the lookup
+    /// table `new_table`, which must have the same bitwidth as the current
+    /// table. `Copy` and `Carry` nodes are converted into `Lut`s with the
+    /// equivalent 2 and 8 bit tables. The `LNode` is scheduled for
+    /// reevaluation, so only values that depend on it are changed.
+    ///
+    /// # Errors
+    ///
+    /// If `p_lnode` is invalid, if the bitwidths mismatch, if the `LNode` is a
+    /// `DynamicLut`, or if its output has been made constant
+    pub fn replace_lut(&mut self, p_lnode: PLNode, new_table: &Bits) -> Result<(), Error> {
+        let lnode = self.lnodes.get(p_lnode).ok_or(Error::InvalidPtr)?;
+        let p_self = lnode.p_self;
+        let equiv = self.backrefs.get_val(p_self).unwrap();
+        if equiv.val.is_const() {
+            return Err(Error::OtherStr(
+                "`Ensemble::replace_lut`: the output of the `LNode` is constant",
+            ))
+        }
+        let partial_ord_num = equiv.evaluator_partial_order;
+        let kind = match &lnode.kind {
+            LNodeKind::Copy(p_inp) => {
+                if new_table.bw() != 2 {
+                    return Err(Error::BitwidthMismatch(2, new_table.bw()))
+                }
+                LNodeKind::Lut(smallvec![*p_inp], self.lut_tables.intern_bits(new_table))
+            }
+            LNodeKind::Lut(inp, table) => {
+                if new_table.bw() != table.bw() {
+                    return Err(Error::BitwidthMismatch(table.bw(), new_table.bw()))
+                }
+                LNodeKind::Lut(inp.clone(), self.lut_tables.intern_bits(new_table))
+            }
+            LNodeKind::Carry(inp) => {
+                if new_table.bw() != 8 {
+                    return Err(Error::BitwidthMismatch(8, new_table.bw()))
+                }
+                LNodeKind::Lut(
+                    SmallVec::from_slice(inp),
+                    self.lut_tables.intern_bits(new_table),
+                )
+            }
+            LNodeKind::DynamicLut(..) => {
+                return Err(Error::OtherStr(
+                    "`Ensemble::replace_lut`: cannot replace the table of a `DynamicLut`",
+                ))
+            }
+        };
+        let new = self.journal.as_ref().map(|_| kind.clone());
+        let old = self.set_lnode_kind(p_lnode, kind, partial_ord_num);
+        if let Some(new) = new {
+            self.record_journal_entry(JournalEntry::ReplaceLut { p_lnode, old, new });
+        }
+        Ok(())
+    }
+
+    /// Sets the kind of `p_lnode` and schedules its reevaluation, returning
+    /// the old kind
+    pub(crate) fn set_lnode_kind(
+        &mut self,
+        p_lnode: PLNode,
+        kind: LNodeKind,
+        partial_ord_num: NonZeroU64,
+    ) -> LNodeKind {
+        let old = mem::replace(&mut self.lnodes[p_lnode].kind, kind);
+        self.evaluator.push_event(Event {
+            partial_ord_num,
+            change_kind: ChangeKind::LNode(p_lnode),
+        });
+        self.switch_to_change_phase();
+        old
+    }
 }
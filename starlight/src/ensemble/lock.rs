@@ -0,0 +1,127 @@
+use std::num::NonZeroUsize;
+
+use awint::{
+    awi,
+    awint_dag::{smallvec::smallvec, triple_arena::Advancer},
+    Awi,
+};
+
+use crate::{
+    ensemble::{Ensemble, Equiv, LNode, LNodeKind, PBack, PLNode, Referent},
+    utils::StarRng,
+    Error,
+};
+
+impl Ensemble {
+    /// Inserts a key gate after the `LNode` at `p_lnode`. The `LNode` is moved
+    /// to a new equivalence, and its old equivalence is instead driven by the
+    /// XOR (or XNOR if `correct_bit`) of the new equivalence with `p_key`.
+    fn insert_key_gate(
+        &mut self,
+        p_lnode: PLNode,
+        p_key: PBack,
+        correct_bit: bool,
+    ) -> Result<(), Error> {
+        let p_self = self.lnodes[p_lnode].p_self;
+        let equiv = self.backrefs.get_val(p_self).unwrap();
+        let (p_equiv, val, partial_order) =
+            (equiv.p_self_equiv, equiv.val, equiv.evaluator_partial_order);
+        // move the `LNode` to its own equivalence
+        let p_inner = self
+            .backrefs
+            .insert_with(|p_self_equiv| (Referent::ThisEquiv, Equiv::new(p_self_equiv, val)));
+        self.backrefs
+            .get_val_mut(p_inner)
+            .unwrap()
+            .evaluator_partial_order = partial_order;
+        self.backrefs.remove_key(p_self).unwrap();
+        self.lnodes[p_lnode].p_self = self
+            .backrefs
+            .insert_key(p_inner, Referent::ThisLNode(p_lnode))
+            .unwrap();
+        let lut = {
+            use awi::*;
+            if correct_bit {
+                awi!(1001)
+            } else {
+                awi!(0110)
+            }
+        };
+        let lut = self.lut_tables.intern(lut);
+        let p_gate = self.lnodes.insert_with(|p_gate| {
+            let p_self = self
+                .backrefs
+                .insert_key(p_equiv, Referent::ThisLNode(p_gate))
+                .unwrap();
+            let inp = smallvec![
+                self.backrefs
+                    .insert_key(p_inner, Referent::Input(p_gate))
+                    .unwrap(),
+                self.backrefs
+                    .insert_key(p_key, Referent::Input(p_gate))
+                    .unwrap()
+            ];
+            LNode::new(p_self, LNodeKind::Lut(inp, lut), None)
+        });
+        // the value changes if the key is not currently correct
+        let (val, source_partial_order) = self.calculate_lnode_value(p_gate)?;
+        self.change_value(p_equiv, val, source_partial_order)
+    }
+
+    /// Inserts key controlled XOR and XNOR gates after a random `density`
+    /// fraction of the non-constant, non-`Copy` `LNode`s, such that the
+    /// circuit only functions correctly when the equivalences of `p_keys`
+    /// are set to the returned key. The key gates use the key bits in order,
+    /// wrapping around if there are more gates than key bits. The key and the
+    /// locked `LNode`s are chosen with `rng`, so that locking can be
+    /// reproduced from a seed.
+    ///
+    /// # Errors
+    ///
+    /// If `density` is not in `0.0..=1.0` or `p_keys` is empty
+    pub fn lock_logic(
+        &mut self,
+        p_keys: &[PBack],
+        density: f64,
+        rng: &mut StarRng,
+    ) -> Result<Awi, Error> {
+        if !(0.0..=1.0).contains(&density) {
+            return Err(Error::OtherString(format!(
+                "`lock_logic` density {density} is not in `0.0..=1.0`"
+            )))
+        }
+        let key_w = if let Some(w) = NonZeroUsize::new(p_keys.len()) {
+            w
+        } else {
+            return Err(Error::OtherStr("`lock_logic` was given an empty key"))
+        };
+        let mut correct_key = Awi::zero(key_w);
+        rng.next_bits(&mut correct_key);
+        let mut candidates = vec![];
+        let mut adv = self.lnodes.advancer();
+        while let Some(p_lnode) = adv.advance(&self.lnodes) {
+            let lnode = &self.lnodes[p_lnode];
+            if let LNodeKind::Copy(_) = lnode.kind {
+                continue
+            }
+            if self.backrefs.get_val(lnode.p_self).unwrap().val.is_const() {
+                continue
+            }
+            candidates.push(p_lnode);
+        }
+        // a partial Fisher-Yates shuffle to choose the locked `LNode`s
+        let num = ((candidates.len() as f64) * density).round() as usize;
+        let num = num.min(candidates.len());
+        for i in 0..num {
+            let j = i + rng.index(candidates.len() - i).unwrap();
+            candidates.swap(i, j);
+        }
+        for (i, p_lnode) in candidates[..num].iter().copied().enumerate() {
+            let key_i = i % p_keys.len();
+            let p_key = self.backrefs.get_val(p_keys[key_i]).unwrap().p_self_equiv;
+            self.insert_key_gate(p_lnode, p_key, correct_key.get(key_i).unwrap())?;
+        }
+        self.reinit_partial_orders()?;
+        Ok(correct_key)
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    borrow::Borrow,
+    collections::HashSet,
+    fmt::{self, Debug},
+    mem,
+    ops::Deref,
+    sync::Arc,
+};
+
+use awint::{Awi, Bits};
+
+use crate::ensemble::{Ensemble, LNodeKind};
+
+/// The table of an [LNodeKind::Lut](crate::ensemble::LNodeKind::Lut). This
+/// dereferences to the `Awi` table and is cheap to clone, identical tables
+/// created through [LutInterner::intern] share the same allocation. Tables
+/// are never mutated in place, [LutTable::to_awi] can be used to get an owned
+/// copy to modify. This uses an `Arc` so that `Ensemble`s stay `Send`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct LutTable(Arc<Awi>);
+
+impl LutTable {
+    /// Creates a table that is not interned, prefer using [LutInterner::intern]
+    pub fn new(table: Awi) -> Self {
+        Self(Arc::new(table))
+    }
+
+    /// Returns an owned copy of the table
+    pub fn to_awi(&self) -> Awi {
+        Awi::from(self.as_ref())
+    }
+
+    /// Returns if `self` and `other` share the same allocation
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Deref for LutTable {
+    type Target = Awi;
+
+    fn deref(&self) -> &Awi {
+        &self.0
+    }
+}
+
+// the derived `Hash` and `Eq` are those of the inner `Awi`
+impl Borrow<Awi> for LutTable {
+    fn borrow(&self) -> &Awi {
+        &self.0
+    }
+}
+
+impl AsRef<Bits> for LutTable {
+    fn as_ref(&self) -> &Bits {
+        &self.0
+    }
+}
+
+impl From<Awi> for LutTable {
+    fn from(table: Awi) -> Self {
+        Self::new(table)
+    }
+}
+
+impl PartialEq<Awi> for LutTable {
+    fn eq(&self, other: &Awi) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl Debug for LutTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&*self.0, f)
+    }
+}
+
+/// Statistics about the `LutTable`s of an `Ensemble`, returned by
+/// [Ensemble::lut_table_stats](crate::ensemble::Ensemble::lut_table_stats)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LutTableStats {
+    /// The number of `LNodeKind::Lut`s
+    pub num_luts: usize,
+    /// The number of distinct table allocations used by the `LNodeKind::Lut`s
+    pub num_unique: usize,
+    /// The estimated number of bytes used by the distinct table allocations
+    pub bytes: usize,
+    /// The estimated number of bytes that the tables would use if each
+    /// `LNodeKind::Lut` had its own allocation
+    pub bytes_without_interning: usize,
+}
+
+/// Estimates the heap bytes of a table allocation
+fn table_bytes(table: &Bits) -> usize {
+    mem::size_of::<usize>()
+        .saturating_mul(2)
+        .saturating_add(mem::size_of::<Awi>())
+        .saturating_add(table.bw().div_ceil(8))
+}
+
+/// A pool of `LutTable`s keyed by their content, so that each distinct table
+/// is stored once
+#[derive(Debug, Clone, Default)]
+pub struct LutInterner {
+    tables: HashSet<LutTable>,
+}
+
+impl LutInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the interned `LutTable` equal to `table`, inserting it if it
+    /// does not exist yet
+    pub fn intern(&mut self, table: Awi) -> LutTable {
+        if let Some(interned) = self.tables.get(&table) {
+            interned.clone()
+        } else {
+            let table = LutTable::new(table);
+            self.tables.insert(table.clone());
+            table
+        }
+    }
+
+    /// Like [LutInterner::intern] but copies from `table`
+    pub fn intern_bits(&mut self, table: &Bits) -> LutTable {
+        self.intern(Awi::from(table))
+    }
+
+    /// Returns `current` if it is equal to `table`, otherwise interns `table`.
+    /// This avoids hashing when a table is usually unchanged.
+    pub fn reintern(&mut self, current: &LutTable, table: Awi) -> LutTable {
+        if **current == table {
+            current.clone()
+        } else {
+            self.intern(table)
+        }
+    }
+
+    /// The number of tables in the pool
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Removes the tables that are only referenced by the pool
+    pub fn prune(&mut self) {
+        self.tables.retain(|table| Arc::strong_count(&table.0) > 1);
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.clear();
+    }
+}
+
+impl Ensemble {
+    /// Returns statistics about the sharing of `LNodeKind::Lut` tables
+    pub fn lut_table_stats(&self) -> LutTableStats {
+        let mut unique = HashSet::new();
+        let mut res = LutTableStats {
+            num_luts: 0,
+            num_unique: 0,
+            bytes: 0,
+            bytes_without_interning: 0,
+        };
+        for lnode in self.lnodes.vals() {
+            if let LNodeKind::Lut(_, ref table) = lnode.kind {
+                let bytes = table_bytes(table);
+                res.num_luts += 1;
+                res.bytes_without_interning = res.bytes_without_interning.saturating_add(bytes);
+                if unique.insert(Arc::as_ptr(&table.0)) {
+                    res.bytes = res.bytes.saturating_add(bytes);
+                }
+            }
+        }
+        res.num_unique = unique.len();
+        res
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+
+use awint::{
+    awint_dag::{Location, Op, PState},
+    Awi,
+};
+
+use crate::{
+    ensemble::{Ensemble, PExternal, PMulNode, PRomNode},
+    Error,
+};
+
+/// Higher level operations that can be preserved through lowering and
+/// optimization with [crate::Epoch::preserve_macro_ops], so that routing and
+/// exporting can map them to dedicated hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MacroOp {
+    /// Multiplications, from `Op::ArbMulAdd` which `mul_add_`, `arb_umul_add_`,
+    /// and similar functions use
+    Mul,
+    /// Read only memories, from lookup tables with a literal table, a single
+    /// address operand, and a multibit output, which [crate::Rom::read]
+    /// creates
+    Rom,
+}
+
+/// A preserved multiply-add `out = add + (lhs * rhs)`, which can be mapped to
+/// DSP blocks. The fields are read only `RNode`s to the bits of the operands
+/// and output, which keep them from being optimized away. The `LNode`
+/// decomposition of the multiplication is still created alongside this, which
+/// is used for evaluation and serves as the fallback for targets without
+/// dedicated multipliers. Nothing maps `MulNode`s to dedicated hardware yet,
+/// so [crate::route::Router::new] removes them from its copy of the program
+/// and routes the decomposition, and [Ensemble::write_blif] and
+/// [Ensemble::map_to_cells] export the decomposition and do not make ports
+/// for the `RNode`s of `MulNode`s.
+#[derive(Debug, Clone)]
+pub struct MulNode {
+    pub add: PExternal,
+    pub lhs: PExternal,
+    pub rhs: PExternal,
+    pub out: PExternal,
+    pub location: Option<Location>,
+}
+
+impl MulNode {
+    /// Returns the `RNode`s in the order `add`, `lhs`, `rhs`, `out`
+    pub fn p_externals(&self) -> [PExternal; 4] {
+        [self.add, self.lhs, self.rhs, self.out]
+    }
+}
+
+/// A preserved read only memory `out = table[addr]`, which can be mapped to
+/// block RAMs or ROM primitives. `table` has the words concatenated with the
+/// word at address zero in the least significant position. Like `MulNode`s,
+/// `addr` and `out` are read only `RNode`s and the `LNode` decomposition is
+/// still created alongside this, which is what evaluation, routing, BLIF
+/// export, and cell mapping use. [Ensemble::write_ir] exports `RomNode`s with
+/// their tables.
+#[derive(Debug, Clone)]
+pub struct RomNode {
+    pub addr: PExternal,
+    pub out: PExternal,
+    pub table: Awi,
+    pub location: Option<Location>,
+}
+
+impl RomNode {
+    /// Returns the `RNode`s in the order `addr`, `out`
+    pub fn p_externals(&self) -> [PExternal; 2] {
+        [self.addr, self.out]
+    }
+}
+
+impl Ensemble {
+    /// Returns if `macro_op` is set to be preserved
+    pub fn is_macro_op_preserved(&self, macro_op: MacroOp) -> bool {
+        self.preserved_macro_ops.contains(&macro_op)
+    }
+
+    /// Returns the `RNode`s that belong to preserved macro operation nodes,
+    /// which are internal to the design and not ports
+    pub fn macro_op_p_externals(&self) -> HashSet<PExternal> {
+        let mut res = HashSet::new();
+        for mul_node in self.mul_nodes.vals() {
+            res.extend(mul_node.p_externals());
+        }
+        for rom_node in self.rom_nodes.vals() {
+            res.extend(rom_node.p_externals());
+        }
+        res
+    }
+
+    /// Makes a read only `RNode` for `p_state` with its bits initialized right
+    /// away, the later lowering of the states will union their equivalences
+    /// with the lowered bits
+    fn make_macro_op_rnode(
+        &mut self,
+        p_state: PState,
+        location: Option<Location>,
+    ) -> Result<PExternal, Error> {
+        let (p_external, _) = self.make_rnode_for_pstate(p_state, location, true, false)?;
+        let (p_rnode, _) = self.notary.get_rnode(p_external)?;
+        self.initialize_rnode_if_needed_no_lowering(p_rnode, false)?;
+        Ok(p_external)
+    }
+
+    /// Called before the state at `p_state` is lowered, makes a `MulNode` or
+    /// `RomNode` if it is a preserved macro operation. The operands must have
+    /// already been visited by the lowering DFS, so that `Copy`s have been
+    /// forwarded.
+    pub(crate) fn preserve_macro_op_if_needed(&mut self, p_state: PState) -> Result<(), Error> {
+        let state = &self.stator.states[p_state];
+        if let Op::StaticLut(ref concat, ref table) = state.op {
+            if !self.is_macro_op_preserved(MacroOp::Rom) || (state.nzbw.get() == 1) {
+                return Ok(())
+            }
+            // tables with concatenated addresses are from fusion and not `Rom::read`
+            let [addr] = concat.as_slice() else {
+                return Ok(())
+            };
+            let addr = *addr;
+            let table = table.clone();
+            let location = state.location;
+            let addr = self.make_macro_op_rnode(addr, location)?;
+            let out = self.make_macro_op_rnode(p_state, location)?;
+            self.rom_nodes.insert(RomNode {
+                addr,
+                out,
+                table,
+                location,
+            });
+            return Ok(())
+        }
+        if let Op::ArbMulAdd([add, lhs, rhs]) = state.op {
+            if !self.is_macro_op_preserved(MacroOp::Mul) {
+                return Ok(())
+            }
+            let location = state.location;
+            let mut p_externals = [PExternal::default(); 4];
+            for (i, p) in [add, lhs, rhs, p_state].into_iter().enumerate() {
+                p_externals[i] = self.make_macro_op_rnode(p, location)?;
+            }
+            self.mul_nodes.insert(MulNode {
+                add: p_externals[0],
+                lhs: p_externals[1],
+                rhs: p_externals[2],
+                out: p_externals[3],
+                location,
+            });
+        }
+        Ok(())
+    }
+
+    /// Removes the `MulNode` at `p_mul_node`, leaving only the `LNode`
+    /// decomposition of the multiplication
+    pub fn remove_mul_node(&mut self, p_mul_node: PMulNode) -> Result<(), Error> {
+        if let Some(mul_node) = self.mul_nodes.remove(p_mul_node) {
+            for p_external in mul_node.p_externals() {
+                self.rnode_dec_rc(p_external)?;
+            }
+            Ok(())
+        } else {
+            Err(Error::InvalidPtr)
+        }
+    }
+
+    /// Removes all `MulNode`s, this is what should happen for targets that do
+    /// not have dedicated multipliers
+    pub fn remove_all_mul_nodes(&mut self) -> Result<(), Error> {
+        for p_mul_node in self.mul_nodes.ptrs().collect::<Vec<_>>() {
+            self.remove_mul_node(p_mul_node)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the `RomNode` at `p_rom_node`, leaving only the `LNode`
+    /// decomposition of the table
+    pub fn remove_rom_node(&mut self, p_rom_node: PRomNode) -> Result<(), Error> {
+        if let Some(rom_node) = self.rom_nodes.remove(p_rom_node) {
+            for p_external in rom_node.p_externals() {
+                self.rnode_dec_rc(p_external)?;
+            }
+            Ok(())
+        } else {
+            Err(Error::InvalidPtr)
+        }
+    }
+
+    /// Removes all `RomNode`s, this is what should happen for targets that do
+    /// not have dedicated memories
+    pub fn remove_all_rom_nodes(&mut self) -> Result<(), Error> {
+        for p_rom_node in self.rom_nodes.ptrs().collect::<Vec<_>>() {
+            self.remove_rom_node(p_rom_node)?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::{mem, num::NonZeroUsize};
+use std::{collections::HashSet, mem, num::NonZeroUsize};
 
 use awint::{
     awint_dag::{
@@ -11,10 +11,11 @@ use awint::{
 
 use crate::{
     ensemble::{
-        DynamicValue, Ensemble, LNode, LNodeKind, PBack, PLNode, POpt, PTNode, Referent, Value,
+        DynamicValue, Ensemble, Equiv, LNode, LNodeKind, PBack, PLNode, POpt, PTNode, Referent,
+        Value,
     },
     triple_arena::OrdArena,
-    utils::SmallMap,
+    utils::{audit_visit, trace_span, SmallMap},
     Error,
 };
 
@@ -74,15 +75,32 @@ pub enum Optimization {
     //Fusion(u8, PBack)
 }
 
+/// Settings for optimizations that change the observable behavior of a
+/// design, set with [crate::Epoch::set_optimize_settings]. Everything is
+/// disabled by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OptimizeSettings {
+    /// Propagates constants through `TNode`s with nonzero delays. Normally the
+    /// output of a delay starts unknown and only takes on the value of a
+    /// constant driver after the delay has passed, so this changes the startup
+    /// behavior by skipping that initial transient. This shrinks sequential
+    /// designs with tied off registers.
+    pub const_through_delays: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Optimizer {
-    optimizations: OrdArena<POpt, Optimization, ()>,
+    pub(crate) optimizations: OrdArena<POpt, Optimization, ()>,
+    /// The `p_self_equiv`s of equivalences referenced by `RNode`s marked
+    /// `keep`, only valid during `Ensemble::optimize_all`
+    kept: HashSet<PBack>,
 }
 
 impl Optimizer {
     pub fn new() -> Self {
         Self {
             optimizations: OrdArena::new(),
+            kept: HashSet::new(),
         }
     }
 
@@ -93,12 +111,23 @@ impl Optimizer {
             return Err(Error::OtherStr("optimizations need to be empty"));
         }
         self.optimizations.clear_and_shrink();
+        self.kept = HashSet::new();
         Ok(())
     }
 
     pub fn insert(&mut self, optimization: Optimization) {
         let _ = self.optimizations.insert(optimization, ());
     }
+
+    /// Returns the value of `equiv` as its users should see it. Kept
+    /// equivalences are barriers, so their constants are not seen through.
+    pub fn barrier_val(&self, equiv: &Equiv) -> Value {
+        if equiv.val.is_const() && self.kept.contains(&equiv.p_self_equiv) {
+            Value::Unknown
+        } else {
+            equiv.val
+        }
+    }
 }
 
 impl Ensemble {
@@ -110,8 +139,8 @@ impl Ensemble {
         Ok(match &mut lnode.kind {
             LNodeKind::Copy(inp) => {
                 // wire propogation
-                let input_equiv = self.backrefs.get_val_mut(*inp).unwrap();
-                let val = input_equiv.val;
+                let input_equiv = self.backrefs.get_val(*inp).unwrap();
+                let val = self.optimizer.barrier_val(input_equiv);
                 if val.is_const() {
                     let equiv = self.backrefs.get_val_mut(lnode.p_self).unwrap();
                     equiv.val = val;
@@ -125,14 +154,14 @@ impl Ensemble {
                 }
             }
             LNodeKind::Lut(inp, original_lut) => {
-                let mut lut = original_lut.clone();
+                let mut lut = original_lut.to_awi();
                 // acquire LUT inputs, for every constant input reduce the LUT
                 let len = usize::from(u8::try_from(inp.len()).unwrap());
                 let mut encountered_const_unknown = false;
                 for i in (0..len).rev() {
                     let p_inp = inp[i];
                     let equiv = self.backrefs.get_val(p_inp).unwrap();
-                    match equiv.val {
+                    match self.optimizer.barrier_val(equiv) {
                         Value::ConstUnknown => encountered_const_unknown = true,
                         Value::Const(val) => {
                             // we will reducing the LUT and removing this input, mark it to be
@@ -223,7 +252,7 @@ impl Ensemble {
                     equiv.val = Value::Const(lut.to_bool());
                     // fix the `lut` to its new state, do this even if we are doing the constant
                     // optimization
-                    *original_lut = lut;
+                    *original_lut = self.lut_tables.reintern(original_lut, lut);
                     return Ok(true)
                 } else if (lut.bw() == 2) && lut.get(1).unwrap() {
                     // the only `lut.bw() == 2` cases that survive independence removal is identity
@@ -246,7 +275,7 @@ impl Ensemble {
                     for i in 0..len {
                         let p_inp = inp[i];
                         let equiv = self.backrefs.get_val(p_inp).unwrap();
-                        match equiv.val {
+                        match self.optimizer.barrier_val(equiv) {
                             Value::ConstUnknown => (),
                             Value::Const(_) | Value::Dynam(_) | Value::Unknown => {
                                 all_const_unknown = false;
@@ -257,11 +286,37 @@ impl Ensemble {
                     if all_const_unknown {
                         let equiv = self.backrefs.get_val_mut(lnode.p_self).unwrap();
                         equiv.val = Value::ConstUnknown;
-                        *original_lut = lut;
+                        *original_lut = self.lut_tables.reintern(original_lut, lut);
                         return Ok(true)
                     }
                 }
-                *original_lut = lut;
+                *original_lut = self.lut_tables.reintern(original_lut, lut);
+                false
+            }
+            LNodeKind::Carry(inp) => {
+                let inp = *inp;
+                // if any input is constant or there are duplicate inputs, the carry is no
+                // longer a real carry and we convert it into a regular LUT which will be
+                // simplified further
+                let mut reducible = false;
+                for i in 0..inp.len() {
+                    let equiv = self.backrefs.get_val(inp[i]).unwrap();
+                    if self.optimizer.barrier_val(equiv).is_const() {
+                        reducible = true;
+                    }
+                    for j in (i + 1)..inp.len() {
+                        if self.backrefs.in_same_set(inp[i], inp[j]).unwrap() {
+                            reducible = true;
+                        }
+                    }
+                }
+                if reducible {
+                    lnode.kind = LNodeKind::Lut(
+                        SmallVec::from_slice(&inp),
+                        self.lut_tables.intern(LNode::carry_lut()),
+                    );
+                    return self.const_eval_lnode(p_lnode)
+                }
                 false
             }
             LNodeKind::DynamicLut(inp, ref mut lut) => {
@@ -269,7 +324,7 @@ impl Ensemble {
                 for lut_bit in lut.iter_mut() {
                     if let DynamicValue::Dynam(p) = lut_bit {
                         let equiv = self.backrefs.get_val(*p).unwrap();
-                        match equiv.val {
+                        match self.optimizer.barrier_val(equiv) {
                             Value::ConstUnknown => {
                                 // we will be removing the input, mark it to be investigated
                                 self.optimizer
@@ -293,7 +348,7 @@ impl Ensemble {
                 for i in (0..len).rev() {
                     let p_inp = inp[i];
                     let equiv = self.backrefs.get_val(p_inp).unwrap();
-                    match equiv.val {
+                    match self.optimizer.barrier_val(equiv) {
                         Value::ConstUnknown => (),
                         Value::Const(val) => {
                             len -= 1;
@@ -445,7 +500,7 @@ impl Ensemble {
                             .insert(Optimization::ForwardEquiv(lnode.p_self));
                     } else {
                         let inp = mem::take(inp);
-                        lnode.kind = LNodeKind::Lut(inp, awi_lut);
+                        lnode.kind = LNodeKind::Lut(inp, self.lut_tables.intern(awi_lut));
                     }
                 }
                 false
@@ -453,16 +508,18 @@ impl Ensemble {
         })
     }
 
-    /// Assigns `Const` result if possible.
+    /// Assigns `Const` result if possible. Nonzero delays are only passed
+    /// through if `OptimizeSettings::const_through_delays` is set, since the
+    /// driver being constant means that the `TNode` is constant after its
+    /// initial transient.
     /// Returns if a `Const` result was assigned.
     pub fn const_eval_tnode(&mut self, p_tnode: PTNode) -> bool {
         let tnode = self.tnodes.get(p_tnode).unwrap();
-        // TODO have another parameter to enable const through some amount of delay
-        if tnode.delay().is_zero() {
+        if tnode.delay().is_zero() || self.optimize_settings.const_through_delays {
             let p_self = tnode.p_self;
             let p_driver = tnode.p_driver;
             let equiv = self.backrefs.get_val(p_driver).unwrap();
-            if equiv.val.is_const() {
+            if self.optimizer.barrier_val(equiv).is_const() {
                 self.backrefs.get_val_mut(p_self).unwrap().val = equiv.val;
                 true
             } else {
@@ -579,28 +636,81 @@ impl Ensemble {
     /// `Advancer`s.
     pub fn remove_tnode_not_p_self(&mut self, p_tnode: PTNode) {
         let tnode = self.tnodes.remove(p_tnode).unwrap();
+        if !tnode.delay().is_zero() {
+            self.delayer.remove_tnode_events(p_tnode);
+        }
         let p_equiv = self.backrefs.get_val(tnode.p_driver).unwrap().p_self_equiv;
         self.optimizer
             .insert(Optimization::InvestigateUsed(p_equiv));
         self.backrefs.remove_key(tnode.p_driver).unwrap();
     }
 
+    /// Sets the kept equivalences of the optimizer from the `RNode`s marked
+    /// `keep`
+    pub(crate) fn collect_kept_equivs(&mut self) {
+        self.optimizer.kept.clear();
+        for rnode in self.notary.rnodes().vals() {
+            if rnode.keep {
+                if let Some(bits) = rnode.bits() {
+                    for p_back in bits.iter().flatten() {
+                        let p_equiv = self.backrefs.get_val(*p_back).unwrap().p_self_equiv;
+                        self.optimizer.kept.insert(p_equiv);
+                    }
+                }
+            }
+        }
+    }
+
     /// Removes all states, optimizes, and shrinks allocations
     pub fn optimize_all(&mut self) -> Result<(), Error> {
+        self.optimize_all_with(false)
+    }
+
+    /// Implements [Ensemble::optimize_all] and, if `canonicalize`,
+    /// [Ensemble::optimize_all_deterministic]
+    pub(crate) fn optimize_all_with(&mut self, canonicalize: bool) -> Result<(), Error> {
+        let span = trace_span("optimize", "Ensemble::optimize_all");
+        self.check_limits()?;
+        self.journal_step("optimize_all");
         // empty current events because they will be invalidated and shrunk
         self.restart_request_phase()?;
+        self.evaluator.invalidate_cache();
+        self.snapshot_lnode_attributions();
         self.force_remove_all_states().unwrap();
+        // the optimization order is determined by arena order, so canonicalize it first
+        // (combinational loops or already queued optimizations prevent this but are
+        // otherwise allowed)
+        if canonicalize && self.optimizer.check_clear().is_ok() {
+            if let Ok(ids) = self.stable_ids() {
+                self.canonicalize_order_with(&ids)?;
+            }
+        }
+        span.count("lnodes_before", u64::try_from(self.lnodes.len()).unwrap());
+        self.collect_kept_equivs();
         // need to preinvestigate everything before starting a priority loop
         let mut adv = self.backrefs.advancer();
         while let Some(p_back) = adv.advance(&self.backrefs) {
             if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                audit_visit("Ensemble::optimize_all preinvestigate", p_back);
                 self.preinvestigate_equiv(p_back)?;
             }
         }
         while let Some(p_optimization) = self.optimizer.optimizations.first() {
+            audit_visit("Ensemble::optimize_all optimize", p_optimization);
+            span.count("optimizations", 1);
             self.optimize(p_optimization)?;
         }
-        self.recast_all_internal_ptrs()
+        span.count("lnodes_after", u64::try_from(self.lnodes.len()).unwrap());
+        if let Some(ref mut report) = self.dead_report {
+            report.clear_lnode_attributions();
+        }
+        self.recast_all_internal_ptrs()?;
+        if canonicalize {
+            if let Ok(ids) = self.stable_ids() {
+                self.canonicalize_order_with(&ids)?;
+            }
+        }
+        Ok(())
     }
 
     pub fn optimize(&mut self, p_optimization: POpt) -> Result<(), Error> {
@@ -629,6 +739,7 @@ impl Ensemble {
                             self.remove_state_bit_not_p_self(p_state, bit_i);
                         }
                         Referent::ThisLNode(p_lnode) => {
+                            self.record_removed_lnode(p_lnode);
                             self.remove_lnode_not_p_self(p_lnode);
                         }
                         Referent::ThisTNode(p_tnode) => {
@@ -641,6 +752,13 @@ impl Ensemble {
                 self.backrefs.remove(p_equiv).unwrap();
             }
             Optimization::ForwardEquiv(p_ident) => {
+                // a kept equivalence keeps its identity `LNode` so that its users are not moved
+                // to the source
+                if let Some(equiv) = self.backrefs.get_val(p_ident) {
+                    if self.optimizer.kept.contains(&equiv.p_self_equiv) {
+                        return Ok(())
+                    }
+                }
                 let p_source = if let Some(referent) = self.backrefs.get_key(p_ident) {
                     if let Referent::ThisLNode(p_lnode) = referent {
                         let lnode = &self.lnodes[p_lnode];
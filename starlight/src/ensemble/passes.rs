@@ -0,0 +1,130 @@
+use std::{fmt, time::Duration};
+
+use crate::{lower::LutDecomposition, sweep::SweepStats};
+
+/// A pass that can be run by
+/// [Epoch::optimize_passes_with_report](crate::Epoch::optimize_passes_with_report)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptimizePass {
+    /// [Epoch::peephole_states](crate::Epoch::peephole_states), which should
+    /// come before `Lower`
+    Peephole,
+    /// Lowers all states that need to be lowered for `RNode`s, which is the
+    /// first half of [Epoch::optimize](crate::Epoch::optimize)
+    Lower,
+    /// [Ensemble::optimize_all](crate::ensemble::Ensemble::optimize_all),
+    /// which is the second half of [Epoch::optimize](crate::Epoch::optimize)
+    OptimizeAll,
+    /// [Ensemble::merge_equivalent_cones](crate::ensemble::Ensemble::merge_equivalent_cones)
+    /// with the given maximum number of BDD nodes, followed by
+    /// `Ensemble::optimize_all` to remove what became unused
+    MergeEquivalentCones(usize),
+    /// [Ensemble::decompose_luts](crate::ensemble::Ensemble::decompose_luts)
+    /// with the given maximum number of inputs
+    DecomposeLuts(usize, LutDecomposition),
+}
+
+impl fmt::Display for OptimizePass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptimizePass::Peephole => write!(f, "peephole"),
+            OptimizePass::Lower => write!(f, "lower"),
+            OptimizePass::OptimizeAll => write!(f, "optimize_all"),
+            OptimizePass::MergeEquivalentCones(max_nodes) => {
+                write!(f, "merge_cones({max_nodes})")
+            }
+            OptimizePass::DecomposeLuts(max_inputs, decomposition) => {
+                write!(f, "decompose({max_inputs}, {decomposition:?})")
+            }
+        }
+    }
+}
+
+/// The statistics before and after a single pass, see [OptimizeReport]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassReport {
+    pub pass: OptimizePass,
+    pub before: SweepStats,
+    pub after: SweepStats,
+    /// The time the pass took, always zero on `wasm32` targets where
+    /// `Instant::now` is not supported
+    pub runtime: Duration,
+}
+
+impl PassReport {
+    /// Returns the change in the number of `LNode`s, negative if the pass
+    /// removed `LNode`s
+    pub fn lnode_delta(&self) -> isize {
+        (self.after.num_lnodes as isize).wrapping_sub(self.before.num_lnodes as isize)
+    }
+
+    /// Returns the change in the combinational depth
+    pub fn depth_delta(&self) -> isize {
+        (self.after.depth as isize).wrapping_sub(self.before.depth as isize)
+    }
+
+    /// Returns the change in the estimated delay
+    pub fn delay_delta(&self) -> i128 {
+        (self.after.estimated_delay.amount() as i128)
+            .wrapping_sub(self.before.estimated_delay.amount() as i128)
+    }
+}
+
+/// The result of
+/// [Epoch::optimize_with_report](crate::Epoch::optimize_with_report), with a
+/// [PassReport] for every pass in the order they were run. The statistics
+/// are calculated like [SweepStats::from_ensemble] with a unit `LNode` delay
+/// and zero carry delay.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptimizeReport {
+    passes: Vec<PassReport>,
+}
+
+impl OptimizeReport {
+    pub(crate) fn push(&mut self, pass: PassReport) {
+        self.passes.push(pass);
+    }
+
+    /// Returns the reports of the passes in the order they were run
+    pub fn passes(&self) -> &[PassReport] {
+        &self.passes
+    }
+
+    /// Returns the statistics after the last pass, or `None` if no passes
+    /// were run
+    pub fn final_stats(&self) -> Option<SweepStats> {
+        self.passes.last().map(|pass| pass.after)
+    }
+
+    /// Returns the sum of the runtimes of all passes
+    pub fn total_runtime(&self) -> Duration {
+        self.passes.iter().map(|pass| pass.runtime).sum()
+    }
+}
+
+/// Writes a table with one row per pass of the statistics after the pass and
+/// the deltas from before it
+impl fmt::Display for OptimizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<24} {:>10} {:>8} {:>8} {:>8} {:>10} {:>8} {:>12}",
+            "pass", "lnodes", "delta", "depth", "delta", "delay", "delta", "runtime"
+        )?;
+        for pass in &self.passes {
+            writeln!(
+                f,
+                "{:<24} {:>10} {:>+8} {:>8} {:>+8} {:>10} {:>+8} {:>12?}",
+                pass.pass.to_string(),
+                pass.after.num_lnodes,
+                pass.lnode_delta(),
+                pass.after.depth,
+                pass.depth_delta(),
+                pass.after.estimated_delay.amount(),
+                pass.delay_delta(),
+                pass.runtime
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Delay, Ensemble, Equiv, PBack, PExternal, PLNode, PTNode, Referent},
+    Error,
+};
+
+/// The `LNode` readers of an equivalence, along with their levels
+struct Readers {
+    p_equiv: PBack,
+    level: usize,
+    readers: Vec<(PBack, usize)>,
+}
+
+/// Chooses the levels after which to cut, such that no stage has more than
+/// `max_depth` levels and the total `costs` of the cuts is minimal. `costs[b]`
+/// is the cost of cutting after level `b` (`costs[0]` is unused), and the
+/// maximum level is `costs.len() - 1`.
+fn choose_cuts(costs: &[usize], max_depth: usize) -> Vec<usize> {
+    let max_level = costs.len() - 1;
+    if max_level <= max_depth {
+        return vec![]
+    }
+    // `dp[b]` is the minimal cost with the last cut after level `b`, along with
+    // the previous cut
+    let mut dp: Vec<(usize, usize)> = vec![(0, 0)];
+    for b in 1..max_level {
+        let mut best = (usize::MAX, 0);
+        for q in b.saturating_sub(max_depth)..b {
+            if dp[q].0 < best.0 {
+                best = (dp[q].0, q);
+            }
+        }
+        dp.push((best.0.saturating_add(costs[b]), best.1));
+    }
+    let mut last = max_level - max_depth;
+    for q in (max_level - max_depth)..max_level {
+        if dp[q].0 < dp[last].0 {
+            last = q;
+        }
+    }
+    let mut res = vec![];
+    while last != 0 {
+        res.push(last);
+        last = dp[last].1;
+    }
+    res.reverse();
+    res
+}
+
+impl Ensemble {
+    /// Inserts a chain of `len` delay 1 `TNode`s after the equivalence
+    /// `p_equiv`, returning the equivalences of the chain in order
+    fn make_register_chain(&mut self, p_equiv: PBack, len: usize) -> Vec<PBack> {
+        let val = self.backrefs.get_val(p_equiv).unwrap().val;
+        let mut res = Vec::with_capacity(len);
+        let mut p_prev = p_equiv;
+        for _ in 0..len {
+            let p_reg = self
+                .backrefs
+                .insert_with(|p_self_equiv| (Referent::ThisEquiv, Equiv::new(p_self_equiv, val)));
+            // the values are already equal, so no events need to be registered
+            let _ = self.make_tnode(p_reg, p_prev, Delay::from(1));
+            res.push(p_reg);
+            p_prev = p_reg;
+        }
+        res
+    }
+
+    /// Pipelines the `LNode`s so that no combinational path has more than
+    /// `max_depth` `LNode`s by cutting between `LNode` levels, see
+    /// [crate::Epoch::auto_pipeline]. Returns the
+    /// latency in cycles of each read-only `RNode`.
+    ///
+    /// # Errors
+    ///
+    /// If `max_depth == 0`, there are `State`s or `TNode`s, or there is a
+    /// combinational loop
+    pub fn auto_pipeline(&mut self, max_depth: usize) -> Result<HashMap<PExternal, usize>, Error> {
+        if max_depth == 0 {
+            return Err(Error::OtherStr(
+                "`auto_pipeline` called with a zero `max_depth`",
+            ))
+        }
+        self.stator.check_clear()?;
+        if !self.tnodes.is_empty() {
+            return Err(Error::OtherStr(
+                "`auto_pipeline` does not support designs that already have temporal nodes, \
+                 because the latency of loops would change",
+            ))
+        }
+        // the level of an `LNode` is the length of the longest path to it, and
+        // the level of an equivalence is that of its driver, or 0 for sources
+        let mut lnode_levels: HashMap<PLNode, usize> = HashMap::new();
+        let mut equiv_levels: HashMap<PBack, usize> = HashMap::new();
+        let mut num_levels = 1;
+        for p_lnode in self.topo_order()? {
+            let mut level = 0;
+            self.lnodes[p_lnode].inputs(|p_inp| {
+                let p_equiv = self.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+                level = level.max(equiv_levels.get(&p_equiv).copied().unwrap_or(0));
+            });
+            level += 1;
+            num_levels = num_levels.max(level + 1);
+            lnode_levels.insert(p_lnode, level);
+            let p_equiv = self
+                .backrefs
+                .get_val(self.lnodes[p_lnode].p_self)
+                .unwrap()
+                .p_self_equiv;
+            let entry = equiv_levels.entry(p_equiv).or_insert(0);
+            *entry = (*entry).max(level);
+        }
+
+        // find the `LNode` readers of each nonconstant equivalence, and the
+        // number of equivalences that would need a register for each cut
+        let mut all_readers = vec![];
+        let mut cost_diffs = vec![0isize; num_levels + 1];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_equiv) = adv.advance(&self.backrefs) {
+            if !matches!(self.backrefs.get_key(p_equiv).unwrap(), Referent::ThisEquiv) {
+                continue
+            }
+            if self.backrefs.get_val(p_equiv).unwrap().val.is_const() {
+                continue
+            }
+            let level = equiv_levels.get(&p_equiv).copied().unwrap_or(0);
+            let mut equiv_readers = vec![];
+            let mut max_level = 0;
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                if let Referent::Input(p_lnode) = *self.backrefs.get_key(p_ref).unwrap() {
+                    let reader_level = lnode_levels[&p_lnode];
+                    max_level = max_level.max(reader_level);
+                    equiv_readers.push((p_ref, reader_level));
+                }
+            }
+            let start = level.max(1);
+            if start < max_level {
+                cost_diffs[start] += 1;
+                cost_diffs[max_level] -= 1;
+            }
+            all_readers.push(Readers {
+                p_equiv,
+                level,
+                readers: equiv_readers,
+            });
+        }
+        let mut costs = Vec::with_capacity(num_levels);
+        let mut cost = 0isize;
+        for diff in cost_diffs.iter().take(num_levels) {
+            cost += diff;
+            costs.push(cost as usize);
+        }
+        let cuts = choose_cuts(&costs, max_depth);
+
+        // the stage of each level
+        let mut stages = Vec::with_capacity(num_levels);
+        let mut stage = 0;
+        for level in 0..num_levels {
+            stages.push(stage);
+            if cuts.contains(&level) {
+                stage += 1;
+            }
+        }
+
+        // insert the registers in front of the readers
+        for Readers {
+            p_equiv,
+            level,
+            readers,
+        } in all_readers
+        {
+            let stage = stages[level];
+            let mut len = 0;
+            for (_, reader_level) in readers.iter() {
+                len = len.max(stages[*reader_level] - stage);
+            }
+            if len == 0 {
+                continue
+            }
+            let chain = self.make_register_chain(p_equiv, len);
+            for (p_ref, reader_level) in readers {
+                let diff = stages[reader_level] - stage;
+                if diff > 0 {
+                    self.move_readers(&[p_ref], chain[diff - 1]);
+                }
+            }
+        }
+
+        // balance the bits of each output
+        let mut res = HashMap::new();
+        let mut outputs = vec![];
+        for (_, p_external, rnode) in self.notary.rnodes() {
+            if !rnode.read_only() {
+                continue
+            }
+            if let Some(bits) = rnode.bits() {
+                let mut bit_stages = vec![];
+                for bit in bits.iter().flatten() {
+                    let p_equiv = self.backrefs.get_val(*bit).unwrap().p_self_equiv;
+                    let level = equiv_levels.get(&p_equiv).copied().unwrap_or(0);
+                    bit_stages.push((*bit, p_equiv, stages[level]));
+                }
+                outputs.push((*p_external, bit_stages));
+            }
+        }
+        for (p_external, bit_stages) in outputs {
+            let latency = bit_stages.iter().map(|x| x.2).max().unwrap_or(0);
+            for (p_ref, p_equiv, stage) in bit_stages {
+                if (stage < latency) && !self.backrefs.get_val(p_equiv).unwrap().val.is_const() {
+                    let chain = self.make_register_chain(p_equiv, latency - stage);
+                    self.move_readers(&[p_ref], *chain.last().unwrap());
+                }
+            }
+            res.insert(p_external, latency);
+        }
+        Ok(res)
+    }
+
+    /// Inserts the interconnect `delays` (which are equivalences paired with
+    /// the delay added in front of their readers, such as from
+    /// [crate::route::Router::interconnect_delays]) along with extra
+    /// balancing delays as chains of delay 1 `TNode`s, so that every path
+    /// from a source to an `LNode` input, `TNode` driver, or read-only `RNode`
+    /// has the same delay as every other path to it. This means that all
+    /// read-only `RNode`s see the sources delayed by exactly the returned
+    /// latency, without values from different times mixing. See
+    /// [crate::route::Router::generate_latency_wrapper].
+    ///
+    /// Existing `TNode`s are kept and pass the lag of their drivers on to
+    /// their outputs, so registered designs are shifted as a whole. Loops
+    /// through `TNode`s are allowed as long as none of `delays` are along
+    /// them, but note that their initial values appear that much earlier
+    /// relative to the shifted sources. Nothing is changed if an error is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// If there are `State`s, if there is a combinational loop, if a loop
+    /// through `TNode`s has interconnect delay along it, or if any of `delays`
+    /// are invalid
+    pub fn balance_latency(&mut self, delays: &[(PBack, Delay)]) -> Result<Delay, Error> {
+        self.stator.check_clear()?;
+        // the interconnect delay in front of the readers of each equivalence
+        let mut extra: HashMap<PBack, u128> = HashMap::new();
+        for (p_back, delay) in delays.iter().copied() {
+            if let Some(equiv) = self.backrefs.get_val(p_back) {
+                let entry = extra.entry(equiv.p_self_equiv).or_insert(0);
+                *entry = entry
+                    .checked_add(delay.amount())
+                    .ok_or(Error::OtherStr("`balance_latency` delays overflowed"))?;
+            } else {
+                return Err(Error::InvalidPtr)
+            }
+        }
+        let extra = |p_equiv: PBack| extra.get(&p_equiv).copied().unwrap_or(0);
+
+        // the time at which each `LNode` and `TNode` sees its inputs, and the
+        // time at which each equivalence changes, which is 0 for sources.
+        // `TNode` outputs take on the time of their drivers, which can feed
+        // back into earlier `LNode`s of the topological order, so passes are
+        // repeated until nothing changes. Times only increase, and if they are
+        // still increasing after every `TNode` has had a chance to propagate
+        // then there is a loop that gains delay.
+        let order = self.topo_order()?;
+        let mut lnode_times: HashMap<PLNode, u128> = HashMap::new();
+        let mut tnode_times: HashMap<PTNode, u128> = HashMap::new();
+        let mut equiv_times: HashMap<PBack, u128> = HashMap::new();
+        let mut converged = false;
+        for _ in 0..=self.tnodes.len() {
+            for p_lnode in order.iter().copied() {
+                let mut time = 0;
+                self.lnodes[p_lnode].inputs(|p_inp| {
+                    let p_equiv = self.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+                    let arrival = equiv_times.get(&p_equiv).copied().unwrap_or(0) + extra(p_equiv);
+                    time = time.max(arrival);
+                });
+                lnode_times.insert(p_lnode, time);
+                let p_equiv = self
+                    .backrefs
+                    .get_val(self.lnodes[p_lnode].p_self)
+                    .unwrap()
+                    .p_self_equiv;
+                let entry = equiv_times.entry(p_equiv).or_insert(0);
+                *entry = (*entry).max(time);
+            }
+            let mut changed = false;
+            for (p_tnode, tnode) in &self.tnodes {
+                let p_driver = self.backrefs.get_val(tnode.p_driver).unwrap().p_self_equiv;
+                let time = equiv_times.get(&p_driver).copied().unwrap_or(0) + extra(p_driver);
+                tnode_times.insert(p_tnode, time);
+                let p_equiv = self.backrefs.get_val(tnode.p_self).unwrap().p_self_equiv;
+                let entry = equiv_times.entry(p_equiv).or_insert(0);
+                if time > *entry {
+                    *entry = time;
+                    changed = true;
+                }
+            }
+            if !changed {
+                converged = true;
+                break
+            }
+        }
+        if !converged {
+            return Err(Error::OtherStr(
+                "`balance_latency` found a loop through temporal nodes with interconnect delay \
+                 along it, which cannot be balanced",
+            ))
+        }
+
+        // find the total delay that each reader needs
+        let mut latency = 0;
+        let mut all_readers = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_equiv) = adv.advance(&self.backrefs) {
+            if !matches!(self.backrefs.get_key(p_equiv).unwrap(), Referent::ThisEquiv) {
+                continue
+            }
+            if self.backrefs.get_val(p_equiv).unwrap().val.is_const() {
+                continue
+            }
+            let time = equiv_times.get(&p_equiv).copied().unwrap_or(0);
+            let mut readers = vec![];
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_ref).unwrap() {
+                    Referent::Input(p_lnode) => {
+                        readers.push((p_ref, Some(lnode_times[&p_lnode] - time)));
+                    }
+                    Referent::Driver(p_tnode) => {
+                        readers.push((p_ref, Some(tnode_times[&p_tnode] - time)));
+                    }
+                    Referent::ThisRNode(p_rnode)
+                        if self.notary.rnodes().get_val(p_rnode).unwrap().read_only() =>
+                    {
+                        latency = latency.max(time + extra(p_equiv));
+                        // the delay is determined after the latency is known
+                        readers.push((p_ref, None));
+                    }
+                    _ => (),
+                }
+            }
+            all_readers.push((p_equiv, time, readers));
+        }
+
+        // find the registers needed in front of each reader before changing
+        // anything
+        let mut chains = vec![];
+        for (p_equiv, time, readers) in all_readers {
+            let mut taps = vec![];
+            for (p_ref, amount) in readers {
+                let amount = amount.unwrap_or(latency - time);
+                if amount != 0 {
+                    let amount = usize::try_from(amount).map_err(|_| {
+                        Error::OtherStr("`balance_latency` delay does not fit in a `usize`")
+                    })?;
+                    taps.push((p_ref, amount));
+                }
+            }
+            if !taps.is_empty() {
+                chains.push((p_equiv, taps));
+            }
+        }
+
+        // insert a chain of registers after each equivalence that is tapped by
+        // the readers, since a single `TNode` would sample its driver at the end
+        // of the delay rather than the value at the start
+        for (p_equiv, taps) in chains {
+            let len = taps.iter().map(|(_, amount)| *amount).max().unwrap();
+            let chain = self.make_register_chain(p_equiv, len);
+            for (p_ref, amount) in taps {
+                self.move_readers(&[p_ref], chain[amount - 1]);
+            }
+        }
+        Ok(Delay::from(latency))
+    }
+}
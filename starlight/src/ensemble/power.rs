@@ -0,0 +1,153 @@
+use std::{collections::HashMap, fmt};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{
+        dead::{to_key, AttributionKey},
+        Attribution, Ensemble, LNodeKind, Profile, Referent,
+    },
+    Error,
+};
+
+/// A rough capacitance model used by [crate::Epoch::estimate_power]. Every
+/// toggle of an equivalence is assumed to charge or discharge a net with
+/// `lut_capacitance` if it is driven by a lookup table (`LNodeKind::Copy`s
+/// are treated as wires and only contribute their fanout), plus
+/// `input_capacitance` for each lookup table input it fans out to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerModel {
+    /// Capacitance in farads of the output of a lookup table
+    pub lut_capacitance: f64,
+    /// Capacitance in farads of a single lookup table input
+    pub input_capacitance: f64,
+    /// Supply voltage in volts
+    pub voltage: f64,
+    /// The time in seconds that the activity was collected over, e.g. the
+    /// number of evaluated cycles times the clock period
+    pub duration: f64,
+}
+
+/// The dynamic power estimated by [crate::Epoch::estimate_power], broken down
+/// by [Attribution]. Each net is attributed to the location of the state that
+/// its driving `LNode` was lowered from if it has one, else to the nearest
+/// `RNode` (usually a `LazyAwi`) feeding it, which also works after
+/// optimization has removed the states.
+#[derive(Debug, Clone, Default)]
+pub struct PowerReport {
+    energy: HashMap<AttributionKey, f64>,
+    total_energy: f64,
+    duration: f64,
+}
+
+impl PowerReport {
+    /// Returns the total switching energy in joules
+    pub fn total_energy(&self) -> f64 {
+        self.total_energy
+    }
+
+    /// Returns the average dynamic power in watts
+    pub fn total_power(&self) -> f64 {
+        self.total_energy / self.duration
+    }
+
+    /// Returns the average dynamic power in watts for each attribution, sorted
+    /// by descending power
+    pub fn power_by_attribution(&self) -> Vec<(Attribution, f64)> {
+        let mut v: Vec<(&AttributionKey, f64)> = self
+            .energy
+            .iter()
+            .map(|(k, energy)| (k, *energy / self.duration))
+            .collect();
+        v.sort_unstable_by(|lhs, rhs| rhs.1.total_cmp(&lhs.1).then(lhs.0.cmp(rhs.0)));
+        v.into_iter()
+            .map(|(k, p)| (k.to_attribution(), p))
+            .collect()
+    }
+}
+
+/// Writes the total and then one line per attribution, with the most power
+/// first
+impl fmt::Display for PowerReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total {:e} W", self.total_power())?;
+        for (attribution, power) in self.power_by_attribution() {
+            writeln!(f, "{power:e} W from {attribution}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ensemble {
+    /// Estimates dynamic power from the toggle counts in `activity` using
+    /// `model`. Equivalences with cones that were changed by optimization
+    /// after `activity` was collected have no toggles.
+    ///
+    /// # Errors
+    ///
+    /// If `model.duration` is not positive, or if there is a combinational loop
+    /// of `LNode`s
+    pub fn estimate_power(
+        &self,
+        activity: &Profile,
+        model: &PowerModel,
+    ) -> Result<PowerReport, Error> {
+        if model.duration.is_nan() || (model.duration <= 0.0) {
+            return Err(Error::OtherString(format!(
+                "`estimate_power` duration {} is not positive",
+                model.duration
+            )))
+        }
+        let mut report = PowerReport {
+            duration: model.duration,
+            ..Default::default()
+        };
+        let ids = self.stable_ids()?;
+        let attributions = self.attribute_equivs();
+        // energy per toggle per farad
+        let half_v2 = 0.5 * model.voltage * model.voltage;
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if !matches!(self.backrefs.get_key(p_back).unwrap(), Referent::ThisEquiv) {
+                continue
+            }
+            let toggles = ids.equiv(p_back).map_or(0, |id| activity.toggles(id));
+            if toggles == 0 {
+                continue
+            }
+            let mut capacitance = 0.0;
+            let mut location = None;
+            let mut adv = self.backrefs.advancer_surject(p_back);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_ref).unwrap() {
+                    Referent::ThisLNode(p_lnode) => {
+                        let lnode = &self.lnodes[p_lnode];
+                        if !matches!(lnode.kind, LNodeKind::Copy(_)) {
+                            capacitance += model.lut_capacitance;
+                        }
+                        if location.is_none() {
+                            location = lnode
+                                .lowered_from
+                                .and_then(|p_state| self.stator.states.get(p_state))
+                                .and_then(|state| state.location);
+                        }
+                    }
+                    Referent::Input(_) => capacitance += model.input_capacitance,
+                    _ => (),
+                }
+            }
+            let key = if let Some(location) = to_key(location) {
+                AttributionKey::Location(location)
+            } else {
+                attributions
+                    .get(&p_back)
+                    .cloned()
+                    .unwrap_or(AttributionKey::Unknown)
+            };
+            let energy = half_v2 * capacitance * (toggles as f64);
+            *report.energy.entry(key).or_insert(0.0) += energy;
+            report.total_energy += energy;
+        }
+        Ok(report)
+    }
+}
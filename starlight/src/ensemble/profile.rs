@@ -0,0 +1,362 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+};
+
+use awint::{
+    awi,
+    awint_dag::{smallvec::SmallVec, triple_arena::Advancer},
+    Awi, Bits, InlAwi,
+};
+
+use crate::{
+    ensemble::{optimize::Optimization, Ensemble, LNodeInputs, LNodeKind, PBack, PLNode, Referent},
+    Error,
+};
+
+/// The maximum number of inputs that profile guided fusion will give a lookup
+/// table, this is further limited by `Limits::max_single_lut_inputs`
+pub const MAX_FUSED_LUT_INPUTS: usize = 6;
+
+/// The minimum number of chained carries that profile guided optimization
+/// turns into a carry select chain
+const MIN_CARRY_SELECT_LEN: usize = 4;
+
+/// Per-equivalence toggle counts collected by the evaluator while profiling
+/// is enabled with [crate::Epoch::start_profiling], or by
+/// [crate::ensemble::BatchSim::profile]. The counts are keyed by the
+/// [Ensemble::stable_ids] of the equivalences, so they stay valid when
+/// internal pointers are recast and can be used with clones of the profiled
+/// `Ensemble`. Equivalences with cones that are changed by later
+/// optimizations get new IDs, and then have no recorded toggles.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    toggles: HashMap<u64, u64>,
+    total: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes a profile from the toggle counts of equivalences with the given
+    /// stable IDs. Structurally identical equivalences share an ID and compute
+    /// the same values, so only the largest of their counts is kept.
+    pub(crate) fn from_id_toggles(toggles: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        let mut by_id: HashMap<u64, u64> = HashMap::new();
+        for (id, count) in toggles {
+            let entry = by_id.entry(id).or_insert(0);
+            *entry = (*entry).max(count);
+        }
+        let mut res = Self::new();
+        for (id, count) in by_id {
+            res.record_toggles(id, count);
+        }
+        res
+    }
+
+    /// Records that the equivalence with the stable ID `id` changed value
+    /// `count` times
+    pub fn record_toggles(&mut self, id: u64, count: u64) {
+        let toggles = self.toggles.entry(id).or_insert(0);
+        *toggles = toggles.saturating_add(count);
+        self.total = self.total.saturating_add(count);
+    }
+
+    /// Returns the number of times the equivalence with the stable ID `id`
+    /// toggled
+    pub fn toggles(&self, id: u64) -> u64 {
+        self.toggles.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Returns the total number of toggles recorded
+    pub fn total_toggles(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the number of equivalences that toggled at least once
+    pub fn num_toggled(&self) -> usize {
+        self.toggles.len()
+    }
+
+    /// Returns if the equivalence with the stable ID `id` toggled more than the
+    /// average of all equivalences that toggled at least once
+    pub fn is_hot(&self, id: u64) -> bool {
+        let toggles = self.toggles(id);
+        if toggles == 0 {
+            return false
+        }
+        // compare `toggles > total / num_toggled` without division
+        u128::from(toggles) * (self.toggles.len() as u128) > u128::from(self.total)
+    }
+}
+
+/// Returns the table of a LUT equivalent to the static LUT `lut_a` with its
+/// `i`th input driven by the static LUT `lut_b`. The inputs of the result are
+/// the inputs of `a` without `i`, followed by the inputs of `b`.
+pub(crate) fn fuse_lut_tables(lut_a: &Bits, i: usize, lut_b: &Bits) -> Awi {
+    let len_a = lut_a.bw().trailing_zeros() as usize;
+    let len_b = lut_b.bw().trailing_zeros() as usize;
+    let new_len = len_a - 1 + len_b;
+    // the new inputs are the inputs of `a` without `i`, followed by the inputs of
+    // `b`, calculate the new table by enumeration
+    let mut new_lut = Awi::zero(NonZeroUsize::new(1 << new_len).unwrap());
+    for k in 0..new_lut.bw() {
+        let k = InlAwi::from_usize(k);
+        let mut inx_b = 0usize;
+        for j in 0..len_b {
+            if k.get(len_a - 1 + j).unwrap() {
+                inx_b |= 1 << j;
+            }
+        }
+        let b_out = lut_b.get(inx_b).unwrap();
+        let mut inx_a = 0usize;
+        let mut from = 0;
+        for j in 0..len_a {
+            let bit = if j == i {
+                b_out
+            } else {
+                from += 1;
+                k.get(from - 1).unwrap()
+            };
+            if bit {
+                inx_a |= 1 << j;
+            }
+        }
+        new_lut
+            .set(k.to_usize(), lut_a.get(inx_a).unwrap())
+            .unwrap();
+    }
+    new_lut
+}
+
+impl Ensemble {
+    /// Converts toggle counts of equivalences into a [Profile] keyed by their
+    /// stable IDs
+    ///
+    /// # Errors
+    ///
+    /// If there is a combinational loop of `LNode`s
+    pub(crate) fn toggles_to_profile(
+        &self,
+        toggles: impl IntoIterator<Item = (PBack, u64)>,
+    ) -> Result<Profile, Error> {
+        let ids = self.stable_ids()?;
+        Ok(Profile::from_id_toggles(toggles.into_iter().filter_map(
+            |(p_back, count)| {
+                let p_equiv = self.backrefs.get_val(p_back)?.p_self_equiv;
+                Some((ids.equiv(p_equiv)?, count))
+            },
+        )))
+    }
+
+    /// If the equivalence of `p_back` is driven by a static `LNodeKind::Lut`,
+    /// returns it along with if the equivalence is used only by a single
+    /// `Referent::Input`
+    pub(crate) fn lut_driving(&self, p_back: PBack) -> Option<(PLNode, bool)> {
+        let mut driver = None;
+        let mut uses = 0usize;
+        let mut adv = self.backrefs.advancer_surject(p_back);
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            match *self.backrefs.get_key(p_back).unwrap() {
+                Referent::ThisEquiv => (),
+                Referent::ThisLNode(p_lnode) => {
+                    if driver.is_some() {
+                        // multiple drivers, be conservative
+                        return None
+                    }
+                    if let LNodeKind::Lut(..) = self.lnodes[p_lnode].kind {
+                        driver = Some(p_lnode);
+                    } else {
+                        return None
+                    }
+                }
+                Referent::ThisTNode(_) => return None,
+                Referent::Input(_) | Referent::Driver(_) => uses += 1,
+                // these need the equivalence to stay around
+                Referent::ThisStateBit(..) | Referent::ThisRNode(_) => uses += 2,
+            }
+        }
+        driver.map(|p_lnode| (p_lnode, uses == 1))
+    }
+
+    /// Replaces the carry chains (see [Ensemble::carry_chains]) with hot
+    /// carries (according to `hot`) by carry select chains, which trades area
+    /// for depth. Each block of the chain calculates its carries assuming both
+    /// a zero and a one carry in, and then the carry out of the previous block
+    /// selects between them. Cold chains are kept, since they have the least
+    /// area. Returns the number of replaced chains.
+    pub(crate) fn select_hot_carry_chains(&mut self, hot: &HashSet<PLNode>) -> usize {
+        let mut num_replaced = 0;
+        for chain in self.carry_chains() {
+            if (chain.len() < MIN_CARRY_SELECT_LEN) || !chain.iter().any(|p| hot.contains(p)) {
+                continue
+            }
+            // the depth is about `block_len + chain.len() / block_len`
+            let mut block_len = 1;
+            while block_len * block_len < chain.len() {
+                block_len += 1;
+            }
+            let (and, or, select) = {
+                use awi::*;
+                (awi!(1000), awi!(1110), awi!(1110_0100))
+            };
+            let mut p_cin = if let LNodeKind::Carry(inp) = self.lnodes[chain[0]].kind {
+                self.backrefs.get_val(inp[0]).unwrap().p_self_equiv
+            } else {
+                unreachable!()
+            };
+            for block in chain.chunks(block_len) {
+                let mut prev: Option<(PBack, PBack)> = None;
+                for p_lnode in block.iter().copied() {
+                    let lnode = &self.lnodes[p_lnode];
+                    let lowered_from = lnode.lowered_from;
+                    let inp = if let LNodeKind::Carry(inp) = lnode.kind {
+                        inp
+                    } else {
+                        unreachable!()
+                    };
+                    let (c0, c1) = if let Some((c0, c1)) = prev {
+                        (
+                            self.make_carry([c0, inp[1], inp[2]], lowered_from),
+                            self.make_carry([c1, inp[1], inp[2]], lowered_from),
+                        )
+                    } else {
+                        let ab = [Some(inp[1]), Some(inp[2])];
+                        (
+                            self.make_lut(&ab, &and, lowered_from),
+                            self.make_lut(&ab, &or, lowered_from),
+                        )
+                    };
+                    prev = Some((c0, c1));
+                    // the carry is now selected by the carry in of the block, which
+                    // computes the same function so the value does not change
+                    for p_inp in inp {
+                        self.backrefs.remove_key(p_inp).unwrap();
+                    }
+                    let mut new_inp: LNodeInputs = SmallVec::with_capacity(3);
+                    for p_equiv in [p_cin, c0, c1] {
+                        new_inp.push(
+                            self.backrefs
+                                .insert_key(p_equiv, Referent::Input(p_lnode))
+                                .unwrap(),
+                        );
+                    }
+                    self.lnodes[p_lnode].kind =
+                        LNodeKind::Lut(new_inp, self.lut_tables.intern_bits(&select));
+                }
+                let p_cout = self.lnodes[*block.last().unwrap()].p_self;
+                p_cin = self.backrefs.get_val(p_cout).unwrap().p_self_equiv;
+            }
+            num_replaced += 1;
+        }
+        num_replaced
+    }
+
+    /// Tries to fuse the static LUT driving one of the inputs of the static LUT
+    /// at `p_lnode` into it. Single use drivers are always fused since this
+    /// saves area and depth. Drivers with other uses are duplicated into `hot`
+    /// LUTs, which trades area for depth. Returns if a fusion happened.
+    fn try_fuse_lut(
+        &mut self,
+        p_lnode: PLNode,
+        hot: bool,
+        max_inputs: usize,
+    ) -> Result<bool, Error> {
+        let lnode = &self.lnodes[p_lnode];
+        let (inp_a, lut_a) = if let LNodeKind::Lut(ref inp, ref lut) = lnode.kind {
+            (inp.clone(), lut.clone())
+        } else {
+            return Ok(false)
+        };
+        for i in 0..inp_a.len() {
+            let (p_driver, single_use) = if let Some(x) = self.lut_driving(inp_a[i]) {
+                x
+            } else {
+                continue
+            };
+            if (p_driver == p_lnode) || !(single_use || hot) {
+                continue
+            }
+            let (inp_b, lut_b) =
+                if let LNodeKind::Lut(ref inp, ref lut) = self.lnodes[p_driver].kind {
+                    (inp.clone(), lut.clone())
+                } else {
+                    unreachable!()
+                };
+            let new_len = inp_a.len() - 1 + inp_b.len();
+            if new_len > max_inputs {
+                continue
+            }
+            let new_lut = fuse_lut_tables(&lut_a, i, &lut_b);
+            // rewire the referents
+            let p_equiv_b = self.backrefs.get_val(inp_a[i]).unwrap().p_self_equiv;
+            self.backrefs.remove_key(inp_a[i]).unwrap();
+            let mut new_inp: LNodeInputs = SmallVec::with_capacity(new_len);
+            for (j, p_inp) in inp_a.iter().copied().enumerate() {
+                if j != i {
+                    new_inp.push(p_inp);
+                }
+            }
+            for p_inp in inp_b.iter().copied() {
+                let p_equiv = self.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+                new_inp.push(
+                    self.backrefs
+                        .insert_key(p_equiv, Referent::Input(p_lnode))
+                        .unwrap(),
+                );
+            }
+            self.lnodes[p_lnode].kind = LNodeKind::Lut(new_inp, self.lut_tables.intern(new_lut));
+            // `b` may now be unused, and `a` may have duplicate inputs
+            self.optimizer
+                .insert(Optimization::InvestigateUsed(p_equiv_b));
+            self.optimizer
+                .insert(Optimization::InvestigateConst(p_lnode));
+            return Ok(true)
+        }
+        Ok(false)
+    }
+
+    /// Replaces hot carry chains and fuses static LUTs according to
+    /// `profile`, see [crate::Epoch::optimize_with_profile]. There must be no
+    /// `State`s, which is the case after [Ensemble::optimize_all].
+    ///
+    /// # Errors
+    ///
+    /// If there are `State`s or a combinational loop of `LNode`s
+    pub fn fuse_luts_with_profile(&mut self, profile: &Profile) -> Result<(), Error> {
+        self.stator.check_clear()?;
+        let max_inputs = self.lut_chunk_inputs()?.min(MAX_FUSED_LUT_INPUTS);
+        // the `LNode`s in hot cones are determined before anything changes their IDs,
+        // and `PLNode`s stay valid until the final `optimize_all`
+        let ids = self.stable_ids()?;
+        let mut hot = HashSet::new();
+        for (p_lnode, lnode) in &self.lnodes {
+            let p_equiv = self.backrefs.get_val(lnode.p_self).unwrap().p_self_equiv;
+            if ids.equiv(p_equiv).is_some_and(|id| profile.is_hot(id)) {
+                hot.insert(p_lnode);
+            }
+        }
+        self.restart_request_phase()?;
+        self.collect_kept_equivs();
+        if self.select_hot_carry_chains(&hot) != 0 {
+            self.reinit_partial_orders()?;
+        }
+        loop {
+            let mut fused = false;
+            let mut adv = self.lnodes.advancer();
+            while let Some(p_lnode) = adv.advance(&self.lnodes) {
+                fused |= self.try_fuse_lut(p_lnode, hot.contains(&p_lnode), max_inputs)?;
+            }
+            // clean up without recasting
+            while let Some(p_optimization) = self.optimizer.optimizations.first() {
+                self.optimize(p_optimization)?;
+            }
+            if !fused {
+                break
+            }
+        }
+        self.optimize_all()
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use awint::{awint_dag::triple_arena::Advancer, Awi, Bits};
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNode, LNodeKind, PBack, PExternal, Referent},
+    epoch::get_current_epoch,
+    Error,
+};
+
+/// The dynamic supports of evaluated `EvalAwi`s, recorded while provenance
+/// tracking is enabled with [crate::Epoch::start_provenance]
+#[derive(Debug, Clone, Default)]
+pub struct Provenance {
+    supports: HashMap<PExternal, Vec<(PExternal, usize)>>,
+}
+
+impl Provenance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the support recorded by the last evaluation of `p_external`
+    pub fn support(&self, p_external: PExternal) -> Option<&[(PExternal, usize)]> {
+        self.supports.get(&p_external).map(|v| v.as_slice())
+    }
+
+    pub fn insert(&mut self, p_external: PExternal, support: Vec<(PExternal, usize)>) {
+        self.supports.insert(p_external, support);
+    }
+}
+
+/// Returns if the static `lut` has a constant output given the `inputs`, where
+/// `None` inputs are treated as unknown
+fn is_determined(lut: &Bits, inputs: &[Option<bool>]) -> bool {
+    let mut lut = Awi::from(lut);
+    for i in (0..inputs.len()).rev() {
+        if let Some(b) = inputs[i] {
+            LNode::reduce_lut(&mut lut, i, b);
+        }
+    }
+    lut.is_zero() || lut.is_umax()
+}
+
+impl Ensemble {
+    /// Returns the inputs of the static `lut` whose current values determine
+    /// the output. Known inputs are greedily removed as long as the remaining
+    /// ones still determine the output, so that e.x. only one of the inputs of
+    /// an OR with two set inputs is included.
+    fn lut_support(&self, inp: &[PBack], lut: &Bits) -> Vec<PBack> {
+        let mut inputs: Vec<Option<bool>> = inp
+            .iter()
+            .map(|p_inp| self.backrefs.get_val(*p_inp).unwrap().val.known_value())
+            .collect();
+        if !is_determined(lut, &inputs) {
+            // conservatively include everything
+            return inp.to_vec()
+        }
+        for i in 0..inputs.len() {
+            if let Some(b) = inputs[i].take() {
+                if !is_determined(lut, &inputs) {
+                    inputs[i] = Some(b);
+                }
+            }
+        }
+        inp.iter()
+            .zip(inputs)
+            .filter_map(|(p_inp, b)| b.map(|_| *p_inp))
+            .collect()
+    }
+
+    /// Returns the dynamic support of the equivalences of `p_backs`, which are
+    /// the non read-only `RNode` bits (e.x. from `LazyAwi`s) whose current
+    /// values determined the current values of `p_backs`. Constants are not
+    /// included, and `TNode`s are followed to the current value of their
+    /// drivers. The result is sorted and deduplicated.
+    pub fn dynamic_support(&self, p_backs: &[PBack]) -> Vec<(PExternal, usize)> {
+        let mut res = vec![];
+        let mut visited = HashSet::new();
+        let mut stack: Vec<PBack> = p_backs.to_vec();
+        while let Some(p_back) = stack.pop() {
+            let equiv = self.backrefs.get_val(p_back).unwrap();
+            if equiv.val.is_const() || !visited.insert(equiv.p_self_equiv) {
+                continue
+            }
+            let mut adv = self.backrefs.advancer_surject(p_back);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_ref).unwrap() {
+                    Referent::ThisRNode(p_rnode) => {
+                        let (p_external, rnode) = self.notary.rnodes().get(p_rnode).unwrap();
+                        if !rnode.read_only() {
+                            if let Some(bits) = rnode.bits() {
+                                if let Some(bit_i) = bits.iter().position(|b| *b == Some(p_ref)) {
+                                    res.push((*p_external, bit_i));
+                                }
+                            }
+                        }
+                    }
+                    Referent::ThisLNode(p_lnode) => match self.lnodes[p_lnode].kind {
+                        LNodeKind::Copy(p_inp) => stack.push(p_inp),
+                        LNodeKind::Lut(ref inp, ref lut) => {
+                            stack.extend(self.lut_support(inp, lut));
+                        }
+                        LNodeKind::Carry(ref inp) => {
+                            stack.extend(self.lut_support(inp, &LNode::carry_lut()));
+                        }
+                        LNodeKind::DynamicLut(ref inp, ref lut) => {
+                            stack.extend(inp.iter().copied());
+                            for entry in lut {
+                                if let DynamicValue::Dynam(p) = entry {
+                                    stack.push(*p);
+                                }
+                            }
+                        }
+                    },
+                    Referent::ThisTNode(p_tnode) => stack.push(self.tnodes[p_tnode].p_driver),
+                    _ => (),
+                }
+            }
+        }
+        res.sort_unstable();
+        res.dedup();
+        res
+    }
+
+    /// Records the dynamic support of the `RNode` of `p_external` if
+    /// provenance tracking is enabled in the current `Epoch`
+    pub fn record_thread_local_rnode_support(p_external: PExternal) -> Result<(), Error> {
+        let epoch_shared = get_current_epoch()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let ensemble = &mut lock.ensemble;
+        if ensemble.evaluator.provenance.is_none() {
+            return Ok(())
+        }
+        let (_, rnode) = ensemble.notary.get_rnode(p_external)?;
+        let p_backs: Vec<PBack> = if let Some(bits) = rnode.bits() {
+            bits.iter().flatten().copied().collect()
+        } else {
+            vec![]
+        };
+        let support = ensemble.dynamic_support(&p_backs);
+        ensemble
+            .evaluator
+            .provenance
+            .as_mut()
+            .unwrap()
+            .insert(p_external, support);
+        Ok(())
+    }
+
+    /// Returns the support recorded by the last evaluation of `p_external`,
+    /// or `None` if it was not evaluated since provenance tracking was
+    /// enabled
+    pub fn thread_local_rnode_support(
+        p_external: PExternal,
+    ) -> Result<Option<Vec<(PExternal, usize)>>, Error> {
+        let epoch_shared = get_current_epoch()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        if let Some(ref provenance) = lock.ensemble.evaluator.provenance {
+            Ok(provenance.support(p_external).map(|s| s.to_vec()))
+        } else {
+            Err(Error::OtherStr(
+                "provenance tracking is not enabled, use `Epoch::start_provenance`",
+            ))
+        }
+    }
+}
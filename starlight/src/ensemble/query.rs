@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Ensemble, PBack, PExternal, PLNode, PTNode, Referent},
+    Error,
+};
+
+/// A node returned by the graph queries [Ensemble::fanout_of] and
+/// [Ensemble::fanin_cone]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeRef {
+    /// A lookup table or other combinational `LNode`
+    LNode(PLNode),
+    /// A temporal `TNode`
+    TNode(PTNode),
+    /// A bit of an `RNode` such as a `LazyAwi` or `EvalAwi`, given by its
+    /// `PExternal` and bit index
+    RNode(PExternal, usize),
+}
+
+impl Ensemble {
+    /// Returns the equivalence of bit `bit` of the `RNode` of `p_external`
+    fn rnode_bit(&self, p_external: PExternal, bit: usize) -> Result<PBack, Error> {
+        let (_, rnode) = self.notary.get_rnode(p_external)?;
+        let bits = rnode
+            .bits()
+            .ok_or(Error::OtherStr("the `RNode` has not been initialized"))?;
+        if let Some(p_back) = bits.get(bit) {
+            p_back.ok_or(Error::OtherStr("the `RNode` bit has been removed"))
+        } else {
+            Err(Error::OtherString(format!(
+                "bit {bit} is out of bounds for an `RNode` of bitwidth {}",
+                bits.len()
+            )))
+        }
+    }
+
+    /// Returns the `NodeRef::RNode` for the `RNode` referent `p_back`
+    fn rnode_ref(&self, p_back: PBack) -> Option<NodeRef> {
+        if let Referent::ThisRNode(p_rnode) = *self.backrefs.get_key(p_back)? {
+            let (p_external, rnode) = self.notary.rnodes().get(p_rnode)?;
+            let bit = rnode.bits()?.iter().position(|bit| *bit == Some(p_back))?;
+            Some(NodeRef::RNode(*p_external, bit))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the nodes that directly use bit `bit` of the `RNode` of
+    /// `p_external`, which are the `LNode`s using it as an input, the `TNode`s
+    /// it drives, and the other `RNode` bits that reference the same
+    /// equivalence. Each node is returned once.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is invalid, has not been initialized (which is done by
+    /// lowering), or `bit` is out of bounds
+    pub fn fanout_of(
+        &self,
+        p_external: PExternal,
+        bit: usize,
+    ) -> Result<impl Iterator<Item = NodeRef>, Error> {
+        let p_start = self.rnode_bit(p_external, bit)?;
+        let mut res = vec![];
+        let mut set = HashSet::new();
+        let mut adv = self.backrefs.advancer_surject(p_start);
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            let node = match *self.backrefs.get_key(p_back).unwrap() {
+                Referent::Input(p_lnode) => NodeRef::LNode(p_lnode),
+                Referent::Driver(p_tnode) => NodeRef::TNode(p_tnode),
+                Referent::ThisRNode(_) => {
+                    if p_back == p_start {
+                        continue
+                    }
+                    if let Some(node) = self.rnode_ref(p_back) {
+                        node
+                    } else {
+                        continue
+                    }
+                }
+                _ => continue,
+            };
+            if set.insert(node) {
+                res.push(node);
+            }
+        }
+        Ok(res.into_iter())
+    }
+
+    /// Returns the nodes in the fanin cone of bit `bit` of the `RNode` of
+    /// `p_external`, in breadth-first order. `depth` is the number of levels
+    /// of `LNode`s and `TNode`s that are traversed, a depth of 1 returns just
+    /// the nodes directly driving the bit. The `RNode` bits referencing the
+    /// equivalences that feed into the traversed nodes are also returned, so
+    /// that inputs such as `LazyAwi`s can be identified. Each node is returned
+    /// once.
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is invalid, has not been initialized (which is done by
+    /// lowering), or `bit` is out of bounds
+    pub fn fanin_cone(
+        &self,
+        p_external: PExternal,
+        bit: usize,
+        depth: usize,
+    ) -> Result<impl Iterator<Item = NodeRef>, Error> {
+        let p_start = self.rnode_bit(p_external, bit)?;
+        let mut res = vec![];
+        let mut set = HashSet::new();
+        let mut visited_equivs = HashSet::new();
+        visited_equivs.insert(self.backrefs.get_val(p_start).unwrap().p_self_equiv);
+        // equivalences along with their level
+        let mut front = VecDeque::from([(p_start, 0usize)]);
+        while let Some((p_equiv, level)) = front.pop_front() {
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_back) = adv.advance(&self.backrefs) {
+                let mut inputs = vec![];
+                let node = match *self.backrefs.get_key(p_back).unwrap() {
+                    Referent::ThisLNode(p_lnode) if level < depth => {
+                        self.lnodes[p_lnode].inputs(|p_inp| inputs.push(p_inp));
+                        NodeRef::LNode(p_lnode)
+                    }
+                    Referent::ThisTNode(p_tnode) if level < depth => {
+                        inputs.push(self.tnodes[p_tnode].p_driver);
+                        NodeRef::TNode(p_tnode)
+                    }
+                    Referent::ThisRNode(_) if level > 0 => {
+                        if let Some(node) = self.rnode_ref(p_back) {
+                            node
+                        } else {
+                            continue
+                        }
+                    }
+                    _ => continue,
+                };
+                if set.insert(node) {
+                    res.push(node);
+                }
+                for p_inp in inputs {
+                    let p_inp_equiv = self.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+                    if visited_equivs.insert(p_inp_equiv) {
+                        front.push_back((p_inp_equiv, level + 1));
+                    }
+                }
+            }
+        }
+        Ok(res.into_iter())
+    }
+
+    /// Pushes the `LNode`s driving the equivalence of `p_back` onto `res`
+    pub(crate) fn lnodes_driving(&self, p_back: PBack, res: &mut Vec<PLNode>) {
+        let mut adv = self.backrefs.advancer_surject(p_back);
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisLNode(p_lnode) = *self.backrefs.get_key(p_back).unwrap() {
+                res.push(p_lnode);
+            }
+        }
+    }
+
+    /// Reinitializes the partial ordering used by the evaluator after
+    /// structural changes. Does nothing if there is a combinational loop.
+    pub(crate) fn reinit_partial_orders(&mut self) -> Result<(), Error> {
+        self.evaluator.invalidate_cache();
+        if let Ok(order) = self.topo_order() {
+            for p_lnode in order {
+                let (_, partial_order) = self.calculate_lnode_value(p_lnode)?;
+                let p_self = self.lnodes[p_lnode].p_self;
+                self.backrefs
+                    .get_val_mut(p_self)
+                    .unwrap()
+                    .evaluator_partial_order = partial_order.checked_add(1).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all the `LNode`s in topological order
+    pub(crate) fn topo_order(&self) -> Result<Vec<PLNode>, Error> {
+        let mut res = Vec::with_capacity(self.lnodes.len());
+        // `false` if the `LNode` is still on the DFS stack
+        let mut done: HashMap<PLNode, bool> = HashMap::new();
+        let mut adv = self.lnodes.advancer();
+        while let Some(p_root) = adv.advance(&self.lnodes) {
+            if done.contains_key(&p_root) {
+                continue
+            }
+            // DFS with explicit stack of (node, visited inputs)
+            let mut stack: Vec<(PLNode, bool)> = vec![(p_root, false)];
+            while let Some((p_lnode, visited)) = stack.pop() {
+                if visited {
+                    done.insert(p_lnode, true);
+                    res.push(p_lnode);
+                    continue
+                }
+                if done.contains_key(&p_lnode) {
+                    continue
+                }
+                done.insert(p_lnode, false);
+                stack.push((p_lnode, true));
+                let mut drivers = vec![];
+                self.lnodes[p_lnode].inputs(|p_inp| self.lnodes_driving(p_inp, &mut drivers));
+                // reversed so that earlier inputs are visited first
+                for p_driver in drivers.into_iter().rev() {
+                    match done.get(&p_driver) {
+                        Some(true) => (),
+                        Some(false) => {
+                            return Err(Error::OtherString(format!(
+                                "found a combinational loop through {p_driver:?}"
+                            )))
+                        }
+                        None => stack.push((p_driver, false)),
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Returns the `LNode`s in topological order, so that every `LNode` comes
+    /// after all the `LNode`s driving its inputs. `TNode`s break paths, so
+    /// loops through `TNode`s are allowed and their outputs are treated like
+    /// inputs without drivers, as are undriven equivalences. The order only
+    /// depends on the structure and arena order of `self`, so it is stable
+    /// for identical `Ensemble`s.
+    ///
+    /// # Errors
+    ///
+    /// If a combinational loop not broken by a `TNode` is found
+    pub fn topo_iter(&self) -> Result<impl Iterator<Item = PLNode>, Error> {
+        Ok(self.topo_order()?.into_iter())
+    }
+
+    /// Like [Ensemble::topo_iter] but in reverse, so that every `LNode` comes
+    /// before all the `LNode`s driving its inputs
+    ///
+    /// # Errors
+    ///
+    /// If a combinational loop not broken by a `TNode` is found
+    pub fn topo_iter_rev(&self) -> Result<impl Iterator<Item = PLNode>, Error> {
+        Ok(self.topo_order()?.into_iter().rev())
+    }
+}
@@ -0,0 +1,224 @@
+use awint::{
+    awint_dag::{smallvec::SmallVec, triple_arena::Advancer},
+    Awi,
+};
+
+use crate::{
+    ensemble::{
+        profile::fuse_lut_tables, Ensemble, LNode, LNodeInputs, LNodeKind, PLNode, Referent,
+        MAX_FUSED_LUT_INPUTS,
+    },
+    utils::StarRng,
+    Error,
+};
+
+/// The number of each kind of transformation applied by
+/// [Ensemble::randomize]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RandomizeReport {
+    pub permutations: usize,
+    pub splits: usize,
+    pub merges: usize,
+}
+
+impl Ensemble {
+    /// Applies up to `num_transformations` random equivalence-preserving
+    /// transformations to the static LUT `LNode`s, deterministically seeded by
+    /// `seed`. This is meant for generating families of functionally
+    /// identical but structurally different benchmarks for the router and
+    /// optimizer from an optimized `Ensemble`. The transformations are
+    ///
+    /// - permuting two inputs of a LUT and rotating its table to match
+    /// - splitting a LUT by Shannon expansion on one of its inputs into two
+    ///   cofactor LUTs and a multiplexing LUT
+    /// - merging a LUT into the only LUT reading it, if the result has at most
+    ///   `MAX_FUSED_LUT_INPUTS` inputs
+    ///
+    /// Attempts that do not find an applicable `LNode` are skipped, so the
+    /// returned report may add up to less than `num_transformations`.
+    pub fn randomize(
+        &mut self,
+        seed: u64,
+        num_transformations: usize,
+    ) -> Result<RandomizeReport, Error> {
+        // pending events may refer to `LNode`s that get removed
+        self.restart_request_phase()?;
+        let max_inputs = self.lut_chunk_inputs()?.min(MAX_FUSED_LUT_INPUTS);
+        let mut rng = StarRng::new(seed);
+        let mut report = RandomizeReport::default();
+        let mut luts = vec![];
+        let mut adv = self.lnodes.advancer();
+        while let Some(p_lnode) = adv.advance(&self.lnodes) {
+            if let LNodeKind::Lut(..) = self.lnodes[p_lnode].kind {
+                luts.push(p_lnode);
+            }
+        }
+        for _ in 0..num_transformations {
+            // merged `LNode`s are lazily removed
+            let p_lnode = loop {
+                if let Some(i) = rng.index(luts.len()) {
+                    if self.lnodes.contains(luts[i]) {
+                        break Some(luts[i])
+                    }
+                    luts.swap_remove(i);
+                } else {
+                    break None
+                }
+            };
+            let p_lnode = if let Some(p_lnode) = p_lnode {
+                p_lnode
+            } else {
+                break
+            };
+            match rng.index(3).unwrap() {
+                0 => {
+                    if self.permute_lut(&mut rng, p_lnode) {
+                        report.permutations += 1;
+                    }
+                }
+                1 => {
+                    if let Some(cofactors) = self.split_lut(&mut rng, p_lnode) {
+                        luts.extend(cofactors);
+                        report.splits += 1;
+                    }
+                }
+                _ => {
+                    if self.merge_lut(&mut rng, p_lnode, max_inputs) {
+                        report.merges += 1;
+                    }
+                }
+            }
+        }
+        self.reinit_partial_orders()?;
+        Ok(report)
+    }
+
+    /// Swaps two random inputs of the static LUT at `p_lnode` and rotates its
+    /// table accordingly
+    fn permute_lut(&mut self, rng: &mut StarRng, p_lnode: PLNode) -> bool {
+        if let LNodeKind::Lut(ref mut inp, ref mut lut) = self.lnodes[p_lnode].kind {
+            if inp.len() < 2 {
+                return false
+            }
+            let i = rng.index(inp.len()).unwrap();
+            let j = (i + 1 + rng.index(inp.len() - 1).unwrap()) % inp.len();
+            inp.swap(i, j);
+            let mut table = lut.to_awi();
+            LNode::rotate_lut(&mut table, i, j);
+            *lut = self.lut_tables.intern(table);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Splits the static LUT at `p_lnode` by Shannon expansion on a random
+    /// input, returning the new cofactor `LNode`s
+    fn split_lut(&mut self, rng: &mut StarRng, p_lnode: PLNode) -> Option<[PLNode; 2]> {
+        let lnode = &self.lnodes[p_lnode];
+        let lowered_from = lnode.lowered_from;
+        let (inp, lut) = if let LNodeKind::Lut(ref inp, ref lut) = lnode.kind {
+            (inp.clone(), lut.clone())
+        } else {
+            return None
+        };
+        if inp.len() < 2 {
+            return None
+        }
+        let i = rng.index(inp.len()).unwrap();
+        let mut others = vec![];
+        for (j, p_inp) in inp.iter().copied().enumerate() {
+            if j != i {
+                others.push(Some(self.backrefs.get_val(p_inp).unwrap().p_self_equiv));
+            }
+        }
+        let cofactors = [false, true].map(|bit| {
+            let mut lut = lut.to_awi();
+            LNode::reduce_lut(&mut lut, i, bit);
+            self.make_lut(&others, &lut, lowered_from)
+        });
+        // the original becomes a multiplexer selecting between the cofactors
+        for (j, p_inp) in inp.iter().copied().enumerate() {
+            if j != i {
+                self.backrefs.remove_key(p_inp).unwrap();
+            }
+        }
+        let mut new_inp: LNodeInputs = SmallVec::with_capacity(3);
+        for p_cofactor in cofactors {
+            new_inp.push(
+                self.backrefs
+                    .insert_key(p_cofactor, Referent::Input(p_lnode))
+                    .unwrap(),
+            );
+        }
+        new_inp.push(inp[i]);
+        self.lnodes[p_lnode].kind =
+            LNodeKind::Lut(new_inp, self.lut_tables.intern(Awi::from_u8(0b1100_1010)));
+        Some(cofactors.map(|p_cofactor| {
+            let mut adv = self.backrefs.advancer_surject(p_cofactor);
+            let mut res = None;
+            while let Some(p_back) = adv.advance(&self.backrefs) {
+                if let Referent::ThisLNode(p_lnode) = *self.backrefs.get_key(p_back).unwrap() {
+                    res = Some(p_lnode);
+                }
+            }
+            res.unwrap()
+        }))
+    }
+
+    /// Merges a random single use static LUT driving the static LUT at
+    /// `p_lnode` into it
+    fn merge_lut(&mut self, rng: &mut StarRng, p_lnode: PLNode, max_inputs: usize) -> bool {
+        let inp_a = if let LNodeKind::Lut(ref inp, _) = self.lnodes[p_lnode].kind {
+            inp.clone()
+        } else {
+            return false
+        };
+        let i = if let Some(i) = rng.index(inp_a.len()) {
+            i
+        } else {
+            return false
+        };
+        let p_driver = match self.lut_driving(inp_a[i]) {
+            Some((p_driver, true)) if p_driver != p_lnode => p_driver,
+            _ => return false,
+        };
+        let (inp_b, lut_b) = if let LNodeKind::Lut(ref inp, ref lut) = self.lnodes[p_driver].kind {
+            (inp.clone(), lut.clone())
+        } else {
+            unreachable!()
+        };
+        if (inp_a.len() - 1 + inp_b.len()) > max_inputs {
+            return false
+        }
+        let new_lut = if let LNodeKind::Lut(_, ref lut_a) = self.lnodes[p_lnode].kind {
+            fuse_lut_tables(lut_a, i, &lut_b)
+        } else {
+            unreachable!()
+        };
+        // rewire `a`, then remove `b` and its equivalence which has no other uses
+        let p_equiv_b = self.backrefs.get_val(inp_a[i]).unwrap().p_self_equiv;
+        self.backrefs.remove_key(inp_a[i]).unwrap();
+        let mut new_inp: LNodeInputs = SmallVec::new();
+        for (j, p_inp) in inp_a.iter().copied().enumerate() {
+            if j != i {
+                new_inp.push(p_inp);
+            }
+        }
+        for p_inp in inp_b.iter().copied() {
+            let p_equiv = self.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+            new_inp.push(
+                self.backrefs
+                    .insert_key(p_equiv, Referent::Input(p_lnode))
+                    .unwrap(),
+            );
+        }
+        self.lnodes[p_lnode].kind = LNodeKind::Lut(new_inp, self.lut_tables.intern(new_lut));
+        let lnode_b = self.lnodes.remove(p_driver).unwrap();
+        lnode_b.inputs(|p_inp| {
+            self.backrefs.remove_key(p_inp).unwrap();
+        });
+        self.backrefs.remove(p_equiv_b).unwrap();
+        true
+    }
+}
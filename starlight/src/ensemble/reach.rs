@@ -0,0 +1,281 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Bdd, BddRef, Delay, Ensemble, PBack, PTNode, Referent, Value},
+    Error,
+};
+
+/// The reachable states of the registers with one delay amount, see
+/// [ReachabilityReport]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReachableStates {
+    /// The delay of the `TNode`s of the registers
+    pub delay: Delay,
+    /// The number of register bits that are not constant
+    pub registers: usize,
+    /// The number of reachable assignments of the register bits
+    pub reachable: f64,
+    /// The number of image computations it took to reach the fixed point
+    pub iterations: usize,
+    /// If the fixed point was reached within the node limit. If not,
+    /// `reachable` is a lower bound and the group was not used for pruning.
+    pub complete: bool,
+}
+
+/// The result of [Ensemble::analyze_reachability] and
+/// [Ensemble::prune_unreachable_states]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReachabilityReport {
+    /// One entry per distinct nonzero `TNode` delay, in increasing order of
+    /// delay
+    pub groups: Vec<ReachableStates>,
+    /// The number of equivalences that were found to be constant in all
+    /// reachable states and constified
+    pub constified: usize,
+    /// The number of equivalences that were found to be equal to another
+    /// equivalence in all reachable states and merged with it
+    pub merged: usize,
+}
+
+/// Writes one line per register group and then the pruning counts
+impl fmt::Display for ReachabilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for group in &self.groups {
+            write!(
+                f,
+                "delay {}: {} registers, {} reachable states after {} iterations",
+                group.delay.amount(),
+                group.registers,
+                group.reachable,
+                group.iterations
+            )?;
+            if !group.complete {
+                write!(f, " (incomplete)")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "constified {}, merged {}", self.constified, self.merged)
+    }
+}
+
+/// The shared state of the analysis
+struct Analysis {
+    bdd: Bdd,
+    /// The function of every visited equivalence, in topological order, and if
+    /// it is a leaf
+    order: Vec<(PBack, BddRef, bool)>,
+    /// The conjunction of the reachable sets of all complete groups
+    care: BddRef,
+    report: ReachabilityReport,
+}
+
+impl Ensemble {
+    fn reachability(&mut self, max_nodes: usize) -> Result<Analysis, Error> {
+        // the initial values of `Loop`s are set by pending events
+        self.restart_request_phase()?;
+        let mut groups: BTreeMap<Delay, Vec<PTNode>> = BTreeMap::new();
+        for (p_tnode, tnode) in &self.tnodes {
+            if !tnode.delay().is_zero() {
+                groups.entry(tnode.delay()).or_default().push(p_tnode);
+            }
+        }
+        let mut roots = vec![];
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if let Referent::ThisEquiv = self.backrefs.get_key(p_back).unwrap() {
+                roots.push(p_back);
+            }
+        }
+        let mut bdd = Bdd::new();
+        let order = self.cone_bdds(&mut bdd, &roots, max_nodes);
+        let funcs: HashMap<PBack, BddRef> = order.iter().map(|(p, f, _)| (*p, *f)).collect();
+        let func = |p_back: PBack| funcs[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        // all the leaves are quantified in image computations, the next state
+        // variables come after them
+        let leaves: Vec<usize> = order
+            .iter()
+            .filter(|(_, _, is_leaf)| *is_leaf)
+            .map(|(_, f, _)| bdd.top_var(*f).unwrap())
+            .collect();
+        let num_leaves = leaves.len();
+        let node_limit = bdd.len().saturating_add(max_nodes);
+
+        let mut report = ReachabilityReport::default();
+        let mut care = BddRef::TRUE;
+        for (delay, p_tnodes) in groups {
+            // current state variable to next state variable
+            let mut next_vars: HashMap<usize, usize> = HashMap::new();
+            let mut init = BddRef::TRUE;
+            let mut transition = BddRef::TRUE;
+            for p_tnode in p_tnodes {
+                let tnode = &self.tnodes[p_tnode];
+                let current = func(tnode.p_self);
+                // constant registers do not need state variables
+                let Some(var) = bdd.top_var(current) else {
+                    continue
+                };
+                let next_var = if let Some(next_var) = next_vars.get(&var) {
+                    *next_var
+                } else {
+                    let next_var = num_leaves + next_vars.len();
+                    next_vars.insert(var, next_var);
+                    match self.backrefs.get_val(tnode.p_self).unwrap().val {
+                        Value::Const(b) | Value::Dynam(b) => {
+                            let literal = if b { current } else { bdd.not(current) };
+                            init = bdd.and(init, literal);
+                        }
+                        Value::Unknown | Value::ConstUnknown => (),
+                    }
+                    next_var
+                };
+                let next = bdd.var(next_var);
+                let driver = func(tnode.p_driver);
+                let differ = bdd.xor(next, driver);
+                let same = bdd.not(differ);
+                transition = bdd.and(transition, same);
+            }
+            if next_vars.is_empty() {
+                continue
+            }
+            let rename: HashMap<usize, usize> = next_vars.iter().map(|(v, n)| (*n, *v)).collect();
+            let state_vars: Vec<usize> = next_vars.keys().copied().collect();
+            let mut reachable = init;
+            let mut iterations = 0;
+            let mut complete = true;
+            loop {
+                if bdd.len() > node_limit {
+                    complete = false;
+                    break
+                }
+                let step = bdd.and(reachable, transition);
+                let image = bdd.exists(step, &leaves);
+                let image = bdd.rename(image, &rename);
+                let next_reachable = bdd.or(reachable, image);
+                iterations += 1;
+                if next_reachable == reachable {
+                    break
+                }
+                reachable = next_reachable;
+            }
+            if complete {
+                care = bdd.and(care, reachable);
+            }
+            report.groups.push(ReachableStates {
+                delay,
+                registers: state_vars.len(),
+                reachable: bdd.sat_count(reachable, &state_vars),
+                iterations,
+                complete,
+            });
+        }
+        Ok(Analysis {
+            bdd,
+            order,
+            care,
+            report,
+        })
+    }
+
+    /// Computes the sets of register states that are reachable from the
+    /// current state. Registers are the `TNode`s with nonzero delays, such as
+    /// those of `Loop`s driven with a delay, and are analyzed in groups by
+    /// their delay. The analysis assumes synchronous behavior, where all the
+    /// registers of a group update together and inputs only change at
+    /// multiples of the delay (e.x. by calling `Epoch::run` with the delay
+    /// between changes). Everything that is not a register of the group or
+    /// a static `LNode` is treated as a free input, and registers with unknown
+    /// values can start with any value. Cones are analyzed up to `max_nodes`
+    /// BDD nodes like in [Ensemble::merge_equivalent_cones], and the fixed
+    /// point computation of a group is abandoned after `max_nodes` further
+    /// nodes.
+    ///
+    /// # Errors
+    ///
+    /// If pending events could not be evaluated
+    pub fn analyze_reachability(&mut self, max_nodes: usize) -> Result<ReachabilityReport, Error> {
+        Ok(self.reachability(max_nodes)?.report)
+    }
+
+    /// Uses [Ensemble::analyze_reachability] to find equivalences driven by
+    /// `LNode`s that are constant or equal to another equivalence in all
+    /// reachable register states, and constifies or merges them. This removes
+    /// logic that only matters for unreachable states, e.x. decoding of states
+    /// that are not one-hot in a one-hot state machine. The same synchronous
+    /// behavior is assumed, and unknown values that only occur in unreachable
+    /// states may become known. What becomes unused is removed by the next
+    /// optimization.
+    ///
+    /// # Errors
+    ///
+    /// If pending events could not be evaluated
+    pub fn prune_unreachable_states(
+        &mut self,
+        max_nodes: usize,
+    ) -> Result<ReachabilityReport, Error> {
+        let Analysis {
+            mut bdd,
+            order,
+            care,
+            mut report,
+        } = self.reachability(max_nodes)?;
+        if care == BddRef::TRUE {
+            return Ok(report)
+        }
+        let mut constify = vec![];
+        let mut merges = vec![];
+        // functions restricted to the care set are canonical, so equal keys
+        // mean that the functions are equal in all reachable states. Because
+        // of the topological order, a representative can never be in the
+        // fan-in of one of its duplicates.
+        let mut representatives: HashMap<BddRef, PBack> = HashMap::new();
+        for (p_equiv, f, is_leaf) in order {
+            let key = bdd.and(f, care);
+            if is_leaf || self.backrefs.get_val(p_equiv).unwrap().val.is_const() {
+                representatives.entry(key).or_insert(p_equiv);
+                continue
+            }
+            if key == BddRef::FALSE {
+                constify.push((p_equiv, false));
+            } else if key == care {
+                constify.push((p_equiv, true));
+            } else if let Some(p_rep) = representatives.get(&key) {
+                merges.push((*p_rep, p_equiv));
+            } else {
+                representatives.insert(key, p_equiv);
+            }
+        }
+        for (p_equiv, b) in constify.iter().copied() {
+            self.remove_sole_lnode_driver(p_equiv);
+            self.backrefs.get_val_mut(p_equiv).unwrap().val = Value::Const(b);
+        }
+        // `union_equiv` may remove the `ThisEquiv` of either side
+        let mut current: HashMap<PBack, PBack> = HashMap::new();
+        for (p_rep, p_dup) in merges.iter().copied() {
+            let p_cur = current.get(&p_rep).copied().unwrap_or(p_rep);
+            self.remove_sole_lnode_driver(p_dup);
+            self.union_equiv(p_cur, p_dup)?;
+            if !self.backrefs.contains(p_cur) {
+                current.insert(p_rep, p_dup);
+            }
+        }
+        self.reinit_partial_orders()?;
+        report.constified = constify.len();
+        report.merged = merges.len();
+        Ok(report)
+    }
+
+    /// Removes the single static `LNode` driving the equivalence of `p_equiv`
+    fn remove_sole_lnode_driver(&mut self, p_equiv: PBack) {
+        let p_lnode = self.sole_lnode_driver(p_equiv).unwrap();
+        let lnode = self.lnodes.remove(p_lnode).unwrap();
+        lnode.inputs(|p_inp| {
+            self.backrefs.remove_key(p_inp).unwrap();
+        });
+        self.backrefs.remove_key(lnode.p_self).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::num::NonZeroU64;
+
+use crate::{
+    ensemble::{Delay, Ensemble, PTNode, Value},
+    Error,
+};
+
+/// A checkpoint of the temporal state of an [Ensemble], returned by
+/// [Ensemble::dump_register_state]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegState {
+    /// The values held by the registers (`TNode`s with nonzero delays)
+    pub registers: Vec<(PTNode, Value)>,
+    /// The pending `TNode` drives and their times relative to the time of the
+    /// checkpoint
+    pub pending: Vec<(Delay, PTNode)>,
+}
+
+impl RegState {
+    /// Returns the value held by `p_tnode`, if it is in the checkpoint
+    pub fn get(&self, p_tnode: PTNode) -> Option<Value> {
+        self.registers
+            .iter()
+            .find(|(p, _)| *p == p_tnode)
+            .map(|(_, val)| *val)
+    }
+
+    /// Sets the value held by `p_tnode`, returning `None` if it is not in the
+    /// checkpoint. This can be used to initialize a simulation to an
+    /// interesting state.
+    pub fn set(&mut self, p_tnode: PTNode, val: Value) -> Option<()> {
+        let (_, v) = self.registers.iter_mut().find(|(p, _)| *p == p_tnode)?;
+        *v = val;
+        Some(())
+    }
+}
+
+impl Ensemble {
+    /// Captures the values held by all the registers (`TNode`s with nonzero
+    /// delays) and the pending temporal events, so that the simulation can be
+    /// resumed from this point with [Ensemble::load_register_state]
+    ///
+    /// # Errors
+    ///
+    /// If pending events could not be evaluated
+    pub fn dump_register_state(&mut self) -> Result<RegState, Error> {
+        self.restart_request_phase()?;
+        let mut registers = vec![];
+        for (p_tnode, tnode) in &self.tnodes {
+            if !tnode.delay().is_zero() {
+                let val = self.backrefs.get_val(tnode.p_self).unwrap().val;
+                registers.push((p_tnode, val));
+            }
+        }
+        let current_time = self.delayer.current_time;
+        let mut pending = vec![];
+        for (_, time, events) in &self.delayer.delayed_events {
+            let relative = Delay::from_amount(time.amount() - current_time.amount());
+            for p_tnode in events.tnode_drives.iter().copied() {
+                pending.push((relative, p_tnode));
+            }
+        }
+        Ok(RegState { registers, pending })
+    }
+
+    /// Restores the registers and pending temporal events of `state`, with
+    /// pending events made relative to the current time. Constant registers
+    /// are left alone if they already have the same value. Everything that
+    /// depends on the registers is reevaluated, but the changes caused by
+    /// restoring do not themselves schedule register drives, so that running
+    /// continues exactly as it would have from the checkpoint.
+    ///
+    /// # Errors
+    ///
+    /// If a `TNode` of `state` no longer exists or is not a register, if a
+    /// constant register would be changed, or if evaluation fails
+    pub fn load_register_state(&mut self, state: &RegState) -> Result<(), Error> {
+        let p_tnodes = state.registers.iter().map(|(p_tnode, _)| p_tnode);
+        for p_tnode in p_tnodes.chain(state.pending.iter().map(|(_, p_tnode)| p_tnode)) {
+            if !self
+                .tnodes
+                .get(*p_tnode)
+                .is_some_and(|tnode| !tnode.delay().is_zero())
+            {
+                return Err(Error::OtherStr(
+                    "`load_register_state` was given a `RegState` with a `TNode` that no longer \
+                     exists or is not a register",
+                ))
+            }
+        }
+        self.restart_request_phase()?;
+        for (p_tnode, val) in state.registers.iter().copied() {
+            let p_self = self.tnodes[p_tnode].p_self;
+            let current = self.backrefs.get_val(p_self).unwrap().val;
+            // constants are allowed to be in the checkpoint unchanged
+            if current.is_const() && (current == val) {
+                continue
+            }
+            // restored values are never constants
+            let val = match val {
+                Value::Const(b) => Value::Dynam(b),
+                Value::ConstUnknown => Value::Unknown,
+                val => val,
+            };
+            self.change_value(p_self, val, NonZeroU64::new(1).unwrap())?;
+        }
+        self.restart_request_phase()?;
+        // replace whatever was scheduled by the changes with the checkpointed events
+        self.delayer.delayed_events.clear();
+        for (delay, p_tnode) in state.pending.iter().copied() {
+            self.delayer.insert_delayed_tnode_event(p_tnode, delay);
+        }
+        Ok(())
+    }
+}
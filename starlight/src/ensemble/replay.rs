@@ -0,0 +1,539 @@
+use std::{collections::HashMap, fmt, num::NonZeroUsize};
+
+use awint::{
+    awint_dag::{triple_arena::Arena, ConcatFieldsType, ConcatType, Location, Op, PState},
+    Awi,
+};
+
+use crate::{
+    awi_structs::{DELAY, DELAYED_LOOP_SOURCE, LOOP_SOURCE, UNDRIVEN_LOOP_SOURCE},
+    ensemble::{
+        ir::{tokenize, write_quoted},
+        Ensemble, JournalEntry, PExternal,
+    },
+    lower::meta::CARRY,
+    Error,
+};
+
+const REPLAY_VERSION: &str = "slreplay 1";
+
+/// The names of `Op::Opaque`s that can be parsed, because `Opaque` names are
+/// `&'static str`s
+const OPAQUE_NAMES: [&str; 6] = [
+    "LazyOpaque",
+    DELAY,
+    UNDRIVEN_LOOP_SOURCE,
+    LOOP_SOURCE,
+    DELAYED_LOOP_SOURCE,
+    CARRY,
+];
+
+/// Defines `simple_op_name` and `parse_simple_op` for the `Op`s that consist
+/// of only an array of operands
+macro_rules! simple_ops {
+    ($($variant:ident $name:literal $n:literal),* $(,)?) => {
+        fn simple_op_name(op: &Op<PState>) -> Option<&'static str> {
+            match op {
+                $(Op::$variant(_) => Some($name),)*
+                _ => None,
+            }
+        }
+
+        fn parse_simple_op(name: &str, operands: &[PState]) -> Option<Result<Op<PState>, String>> {
+            match name {
+                $($name => Some(<[PState; $n]>::try_from(operands)
+                    .map(Op::$variant)
+                    .map_err(|_| format!("`{}` expects {} operands", $name, $n))),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+simple_ops!(
+    Assert "assert" 1,
+    Copy "copy" 1,
+    Repeat "repeat" 1,
+    Resize "resize" 2,
+    ZeroResize "zero_resize" 1,
+    SignResize "sign_resize" 1,
+    Lut "lut" 2,
+    Funnel "funnel" 2,
+    UQuo "uquo" 2,
+    URem "urem" 2,
+    IQuo "iquo" 2,
+    IRem "irem" 2,
+    ArbMulAdd "mul_add" 3,
+    CinSum "cin_sum" 3,
+    UnsignedOverflow "unsigned_overflow" 3,
+    SignedOverflow "signed_overflow" 3,
+    RangeOr "range_or" 3,
+    RangeAnd "range_and" 3,
+    RangeXor "range_xor" 3,
+    Not "not" 1,
+    Rev "rev" 1,
+    Abs "abs" 1,
+    IsZero "is_zero" 1,
+    IsUmax "is_umax" 1,
+    IsImax "is_imax" 1,
+    IsImin "is_imin" 1,
+    IsUone "is_uone" 1,
+    Lsb "lsb" 1,
+    Msb "msb" 1,
+    Lz "lz" 1,
+    Tz "tz" 1,
+    Sig "sig" 1,
+    CountOnes "count_ones" 1,
+    Or "or" 2,
+    And "and" 2,
+    Xor "xor" 2,
+    Shl "shl" 2,
+    Lshr "lshr" 2,
+    Ashr "ashr" 2,
+    Rotl "rotl" 2,
+    Rotr "rotr" 2,
+    Add "add" 2,
+    Sub "sub" 2,
+    Rsb "rsb" 2,
+    Eq "eq" 2,
+    Ne "ne" 2,
+    Ult "ult" 2,
+    Ule "ule" 2,
+    Ilt "ilt" 2,
+    Ile "ile" 2,
+    Inc "inc" 2,
+    IncCout "inc_cout" 2,
+    Dec "dec" 2,
+    DecCout "dec_cout" 2,
+    Neg "neg" 2,
+    Get "get" 2,
+    Set "set" 3,
+    Mux "mux" 3,
+    LutSet "lut_set" 3,
+    Field "field" 5,
+    FieldTo "field_to" 4,
+    FieldFrom "field_from" 4,
+    FieldWidth "field_width" 3,
+    FieldBit "field_bit" 4,
+);
+
+/// Writes `awi` in the form `0x<hex>_u<bitwidth>`
+fn awi_to_string(awi: &Awi) -> String {
+    let hex = Awi::bits_to_string_radix(awi, false, 16, false, 1).unwrap();
+    format!("0x{hex}_u{}", awi.bw())
+}
+
+fn parse_awi(s: &str) -> Result<Awi, String> {
+    let err = || format!("expected a `0x<hex>_u<bitwidth>` literal, found `{s}`");
+    let (hex, w) = s
+        .strip_prefix("0x")
+        .and_then(|s| s.split_once("_u"))
+        .ok_or_else(err)?;
+    let w = w
+        .parse::<usize>()
+        .ok()
+        .and_then(NonZeroUsize::new)
+        .ok_or_else(err)?;
+    Awi::from_str_radix(None, hex, 16, w).map_err(|_| err())
+}
+
+fn parse_usize(s: &str) -> Result<usize, String> {
+    s.parse()
+        .map_err(|_| format!("expected an integer, found `{s}`"))
+}
+
+fn parse_nzusize(s: &str) -> Result<NonZeroUsize, String> {
+    NonZeroUsize::new(parse_usize(s)?).ok_or_else(|| "expected a nonzero integer".to_owned())
+}
+
+/// A single recorded `make_state` call
+#[derive(Debug, Clone)]
+pub struct ReplayEntry {
+    /// The `PState` that the state had in the recording `Ensemble`
+    pub p_state: PState,
+    pub nzbw: NonZeroUsize,
+    /// The operation as it was requested, with operands pointing to the
+    /// `p_state`s of earlier entries
+    pub op: Op<PState>,
+    pub location: Option<Location>,
+}
+
+/// A log of every `make_state` call made while recording was enabled with
+/// [crate::Epoch::start_replay_recording]. This can be replayed into a fresh
+/// `Epoch` with [crate::Epoch::replay] to rebuild the same states without
+/// rerunning the code that originally generated them.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayLog {
+    pub entries: Vec<ReplayEntry>,
+    /// The `PState`s that read only `RNode`s (e.g. from `EvalAwi`s) were made
+    /// for while recording, which [Ensemble::rebuild_from_replay] keeps alive
+    pub roots: Vec<PState>,
+}
+
+impl ReplayLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl ReplayLog {
+    /// Parses the text format written by the `Display` implementation back
+    /// into a `ReplayLog`. The `PState`s of the entries are placeholders that
+    /// are only meaningful within the returned log. The file names of the
+    /// locations need to be `&'static str`s, so each distinct file name is
+    /// leaked once.
+    ///
+    /// # Errors
+    ///
+    /// If the text is malformed, including the line number of the problem
+    pub fn parse(text: &str) -> Result<ReplayLog, Error> {
+        let mut log = ReplayLog::new();
+        let mut placeholders: Arena<PState, ()> = Arena::new();
+        let mut ids: Vec<PState> = vec![];
+        let mut files: HashMap<String, &'static str> = HashMap::new();
+        let mut found_version = false;
+        for (line_i, line) in text.lines().enumerate() {
+            let res = (|| -> Result<(), String> {
+                let tokens = tokenize(line)?;
+                if tokens.is_empty() {
+                    return Ok(())
+                }
+                if !found_version {
+                    if tokens.join(" ") != REPLAY_VERSION {
+                        return Err(format!("expected `{REPLAY_VERSION}` header"))
+                    }
+                    found_version = true;
+                    return Ok(())
+                }
+                let mut tokens = tokens.iter().map(|s| s.as_str());
+                let mut next = || {
+                    tokens
+                        .next()
+                        .ok_or_else(|| "unexpected end of line".to_owned())
+                };
+                let id = next()?;
+                if id == "root" {
+                    let root = next()?;
+                    let p = root
+                        .strip_prefix('s')
+                        .and_then(|i| ids.get(parse_usize(i).ok()?).copied())
+                        .ok_or_else(|| format!("root `{root}` does not refer to an entry"))?;
+                    if let Ok(s) = next() {
+                        return Err(format!("trailing token `{s}`"))
+                    }
+                    log.roots.push(p);
+                    return Ok(())
+                }
+                if id != format!("s{}", ids.len()) {
+                    return Err(format!("expected `s{}`, found `{id}`", ids.len()))
+                }
+                let nzbw = parse_nzusize(next()?)?;
+                let name = next()?;
+                // the operands, parameters, and location
+                let mut operands = vec![];
+                let mut params = vec![];
+                let mut location = None;
+                while let Ok(s) = next() {
+                    if s == "@" {
+                        let file = next()?
+                            .strip_prefix('"')
+                            .ok_or_else(|| "expected a quoted file name".to_owned())?;
+                        let file = *files
+                            .entry(file.to_owned())
+                            .or_insert_with(|| Box::leak(file.to_owned().into_boxed_str()));
+                        let line =
+                            u32::try_from(parse_usize(next()?)?).map_err(|e| e.to_string())?;
+                        let col =
+                            u32::try_from(parse_usize(next()?)?).map_err(|e| e.to_string())?;
+                        location = Some(Location { file, line, col });
+                        if let Ok(s) = next() {
+                            return Err(format!("trailing token `{s}`"))
+                        }
+                        break
+                    } else if let Some(i) = s.strip_prefix('s') {
+                        if !params.is_empty() {
+                            return Err("operands must come before parameters".to_owned())
+                        }
+                        let i = parse_usize(i)?;
+                        let p = ids.get(i).copied().ok_or_else(|| {
+                            format!("operand `{s}` does not refer to an earlier entry")
+                        })?;
+                        operands.push(p);
+                    } else {
+                        params.push(s);
+                    }
+                }
+                let expect_params = |n: usize| {
+                    if params.len() == n {
+                        Ok(())
+                    } else {
+                        Err(format!("`{name}` expects {n} parameters"))
+                    }
+                };
+                let op = if let Some(op) = parse_simple_op(name, &operands) {
+                    expect_params(0)?;
+                    op?
+                } else {
+                    match name {
+                        "opaque" => {
+                            let name = match params.as_slice() {
+                                [] => None,
+                                [s] => {
+                                    let s = s
+                                        .strip_prefix('"')
+                                        .ok_or_else(|| "expected a quoted name".to_owned())?;
+                                    let name = OPAQUE_NAMES
+                                        .iter()
+                                        .find(|name| **name == s)
+                                        .ok_or_else(|| format!("unknown opaque name `{s}`"))?;
+                                    Some(*name)
+                                }
+                                _ => return Err("`opaque` expects at most one name".to_owned()),
+                            };
+                            Op::Opaque(operands.iter().copied().collect(), name)
+                        }
+                        "arg" | "literal" if operands.is_empty() => {
+                            expect_params(1)?;
+                            let awi = parse_awi(params[0])?;
+                            if name == "arg" {
+                                Op::Argument(awi)
+                            } else {
+                                Op::Literal(awi)
+                            }
+                        }
+                        "static_get" if operands.len() == 1 => {
+                            expect_params(1)?;
+                            Op::StaticGet([operands[0]], parse_usize(params[0])?)
+                        }
+                        "zero_resize_overflow" | "sign_resize_overflow" if operands.len() == 1 => {
+                            expect_params(1)?;
+                            let w = parse_nzusize(params[0])?;
+                            if name == "zero_resize_overflow" {
+                                Op::ZeroResizeOverflow([operands[0]], w)
+                            } else {
+                                Op::SignResizeOverflow([operands[0]], w)
+                            }
+                        }
+                        "concat" => {
+                            expect_params(0)?;
+                            Op::Concat(ConcatType::from_iter(operands.iter().copied()))
+                        }
+                        "static_lut" => {
+                            expect_params(1)?;
+                            Op::StaticLut(
+                                ConcatType::from_iter(operands.iter().copied()),
+                                parse_awi(params[0])?,
+                            )
+                        }
+                        "concat_fields" => {
+                            // each operand has a `from` and `width`
+                            expect_params(2 * operands.len())?;
+                            let mut fields = vec![];
+                            for (i, p) in operands.iter().copied().enumerate() {
+                                fields.push((
+                                    p,
+                                    parse_usize(params[2 * i])?,
+                                    parse_nzusize(params[(2 * i) + 1])?,
+                                ));
+                            }
+                            Op::ConcatFields(ConcatFieldsType::from_iter(fields))
+                        }
+                        _ => return Err(format!("invalid operation `{name}`")),
+                    }
+                };
+                let p_state = placeholders.insert(());
+                ids.push(p_state);
+                log.entries.push(ReplayEntry {
+                    p_state,
+                    nzbw,
+                    op,
+                    location,
+                });
+                Ok(())
+            })();
+            if let Err(e) = res {
+                return Err(Error::OtherString(format!(
+                    "`ReplayLog::parse` line {}: {e}",
+                    line_i + 1
+                )))
+            }
+        }
+        if !found_version {
+            return Err(Error::OtherStr("`ReplayLog::parse` did not find a header"))
+        }
+        Ok(log)
+    }
+}
+
+/// Writes a line based text format that can be read back with
+/// [ReplayLog::parse], suitable for attaching to bug reports. After a
+/// `slreplay 1` header, there is one entry per line in the form
+/// `s<index> <width> <operation> <operands> <parameters> @ "<file>" <line>
+/// <col>`, where the operands refer to earlier entries by index, literals
+/// are written like `0x1f_u5`, and the location is optional. Operands that
+/// do not refer to an earlier entry (because the state was created before
+/// recording started) are written as `s?` and cannot be parsed. The entries
+/// are followed by one `root s<index>` line per root.
+impl fmt::Display for ReplayLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{REPLAY_VERSION}")?;
+        let mut ids: HashMap<PState, usize> = HashMap::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let op = &entry.op;
+            let name = match op {
+                Op::Opaque(..) => "opaque",
+                Op::ZeroResizeOverflow(..) => "zero_resize_overflow",
+                Op::SignResizeOverflow(..) => "sign_resize_overflow",
+                op => simple_op_name(op).unwrap_or_else(|| op.operation_name()),
+            };
+            write!(f, "s{i} {} {name}", entry.nzbw)?;
+            for operand in op.operands() {
+                if let Some(id) = ids.get(operand) {
+                    write!(f, " s{id}")?;
+                } else {
+                    write!(f, " s?")?;
+                }
+            }
+            let mut s = String::new();
+            match op {
+                Op::Opaque(_, Some(name)) => {
+                    s.push(' ');
+                    write_quoted(&mut s, name);
+                }
+                Op::Argument(awi) | Op::Literal(awi) | Op::StaticLut(_, awi) => {
+                    s.push(' ');
+                    s.push_str(&awi_to_string(awi));
+                }
+                Op::StaticGet(_, inx) => s.push_str(&format!(" {inx}")),
+                Op::ZeroResizeOverflow(_, w) | Op::SignResizeOverflow(_, w) => {
+                    s.push_str(&format!(" {w}"))
+                }
+                Op::ConcatFields(fields) => {
+                    for (from, width) in fields.field_as_slice() {
+                        s.push_str(&format!(" {from} {width}"));
+                    }
+                }
+                _ => (),
+            }
+            if let Some(location) = entry.location {
+                s.push_str(" @ ");
+                write_quoted(&mut s, location.file);
+                s.push_str(&format!(" {} {}", location.line, location.col));
+            }
+            writeln!(f, "{s}")?;
+            ids.insert(entry.p_state, i);
+        }
+        for root in &self.roots {
+            if let Some(id) = ids.get(root) {
+                writeln!(f, "root s{id}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The result of [Ensemble::rebuild_from_replay]
+#[derive(Debug, Clone, Default)]
+pub struct Replayed {
+    /// The new `PState`s in the same order as the entries of the log
+    pub p_states: Vec<PState>,
+    /// The entry indexes of the `LazyAwi` opaques of the log along with the
+    /// `PExternal`s of new `RNode`s for them, which can be changed with
+    /// [crate::Epoch::poke]
+    pub inputs: Vec<(usize, PExternal)>,
+    /// The entry indexes of the roots of the log and of the states that no
+    /// later entry uses (other than `LazyAwi` opaques) along with the
+    /// `PExternal`s of new read only `RNode`s that keep them from being
+    /// pruned, which can be evaluated with [crate::Epoch::peek]
+    pub outputs: Vec<(usize, PExternal)>,
+}
+
+impl Ensemble {
+    /// Pushes an entry to `self.replay_log` and `self.journal` if recording is
+    /// enabled. `created` is false if `make_state` returned an existing state.
+    pub(crate) fn record_make_state(
+        &mut self,
+        p_state: PState,
+        nzbw: NonZeroUsize,
+        op: Op<PState>,
+        location: Option<Location>,
+        created: bool,
+    ) {
+        let entry = ReplayEntry {
+            p_state,
+            nzbw,
+            op,
+            location,
+        };
+        if self.journal.is_some() {
+            self.record_journal_entry(JournalEntry::MakeState {
+                entry: entry.clone(),
+                created,
+            });
+        }
+        if let Some(ref mut log) = self.replay_log {
+            log.entries.push(entry);
+        }
+    }
+
+    /// Recreates all the states recorded in `log` in order, translating the
+    /// operands to the newly created states. The `LazyAwi` opaques get
+    /// writable `RNode`s and the roots and states that nothing else in the
+    /// log uses get read only `RNode`s, so that the replayed design can be
+    /// driven and evaluated and is not pruned, see [Replayed].
+    ///
+    /// # Errors
+    ///
+    /// If an entry has an operand that does not refer to an earlier entry
+    pub fn rebuild_from_replay(&mut self, log: &ReplayLog) -> Result<Replayed, Error> {
+        let mut translation: HashMap<PState, PState> = HashMap::new();
+        let mut res = Replayed::default();
+        let mut used = vec![false; log.entries.len()];
+        let mut indexes: HashMap<PState, usize> = HashMap::new();
+        for (i, entry) in log.entries.iter().enumerate() {
+            let mut op = entry.op.clone();
+            for operand in op.operands_mut() {
+                if let Some(p) = translation.get(operand) {
+                    used[indexes[operand]] = true;
+                    *operand = *p;
+                } else {
+                    return Err(Error::OtherString(format!(
+                        "replay entry {i} has operand {operand:?} that does not refer to an \
+                         earlier entry"
+                    )))
+                }
+            }
+            let p_state = self.make_state(entry.nzbw, op, entry.location);
+            translation.insert(entry.p_state, p_state);
+            indexes.insert(entry.p_state, i);
+            res.p_states.push(p_state);
+        }
+        let mut is_root = vec![false; log.entries.len()];
+        for root in &log.roots {
+            if let Some(i) = indexes.get(root) {
+                is_root[*i] = true;
+            }
+        }
+        for (i, entry) in log.entries.iter().enumerate() {
+            let p_state = res.p_states[i];
+            if matches!(entry.op, Op::Opaque(_, Some("LazyOpaque"))) {
+                let (p_external, _) =
+                    self.make_rnode_for_pstate(p_state, entry.location, false, false)?;
+                res.inputs.push((i, p_external));
+            } else if is_root[i] || !used[i] {
+                let (p_external, _) =
+                    self.make_rnode_for_pstate(p_state, entry.location, true, true)?;
+                res.outputs.push((i, p_external));
+            }
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Ensemble, LNodeKind, PBack, PExternal, PLNode, Referent},
+    Error,
+};
+
+/// The logic in the fanin cone of a single output, see [OutputReport]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputCone {
+    /// The `PExternal` of the `EvalAwi`
+    pub p_external: PExternal,
+    /// The debug name of the `EvalAwi`, if it has one
+    pub debug_name: Option<String>,
+    /// The bitwidth of the `EvalAwi`
+    pub bw: usize,
+    /// The number of `LNode`s in the cone
+    pub lnodes: usize,
+    /// The maximum number of lookup tables on any path in the cone that is
+    /// not broken by a `TNode`. `LNodeKind::Copy`s are treated as wires and
+    /// do not add to the depth.
+    pub depth: usize,
+    /// The number of `TNode`s in the cone
+    pub tnodes: usize,
+    /// The `PExternal`s of the `LazyAwi`s and other writable `RNode`s that
+    /// the cone reads from, in arena order
+    pub inputs: Vec<PExternal>,
+}
+
+/// A report of the logic driving each live `EvalAwi`, made by
+/// [crate::Epoch::report_outputs]. The cones are traversed through `TNode`s,
+/// so they include everything that can influence an output over time. Cones
+/// of different outputs can overlap, so the counts do not add up to the
+/// totals of the `Ensemble`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputReport {
+    /// The cones of the outputs in arena order
+    pub outputs: Vec<OutputCone>,
+}
+
+impl OutputReport {
+    /// Returns the cone of the output `p_external`, if it is in the report
+    pub fn get(&self, p_external: PExternal) -> Option<&OutputCone> {
+        self.outputs
+            .iter()
+            .find(|cone| cone.p_external == p_external)
+    }
+}
+
+/// Writes one line per output, named by its debug name if it has one
+impl fmt::Display for OutputReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for cone in &self.outputs {
+            if let Some(ref name) = cone.debug_name {
+                write!(f, "{name}")?;
+            } else {
+                write!(f, "{}", cone.p_external)?;
+            }
+            write!(
+                f,
+                " ({} bits): {} lnodes, depth {}, {} tnodes, inputs [",
+                cone.bw, cone.lnodes, cone.depth, cone.tnodes
+            )?;
+            for (i, input) in cone.inputs.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{input}")?;
+            }
+            writeln!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+impl Ensemble {
+    /// Returns the number of lookup tables on the longest path ending at each
+    /// `LNode`, not counting `TNode`s or `LNodeKind::Copy`s
+    fn lnode_depths(&self) -> Result<HashMap<PLNode, usize>, Error> {
+        let mut depths = HashMap::with_capacity(self.lnodes.len());
+        let mut drivers = vec![];
+        for p_lnode in self.topo_order()? {
+            let lnode = &self.lnodes[p_lnode];
+            drivers.clear();
+            lnode.inputs(|p_inp| self.lnodes_driving(p_inp, &mut drivers));
+            let max = drivers.iter().map(|p| depths[p]).max().unwrap_or(0);
+            let depth = if matches!(lnode.kind, LNodeKind::Copy(_)) {
+                max
+            } else {
+                max + 1
+            };
+            depths.insert(p_lnode, depth);
+        }
+        Ok(depths)
+    }
+
+    /// Returns an [OutputReport] for all the read only `RNode`s (such as those
+    /// of `EvalAwi`s) that have been initialized, which is done by lowering.
+    ///
+    /// # Errors
+    ///
+    /// If there is a combinational loop not broken by a `TNode`
+    pub fn report_outputs(&self) -> Result<OutputReport, Error> {
+        let depths = self.lnode_depths()?;
+        let mut report = OutputReport::default();
+        for (p_rnode, p_external, rnode) in self.notary.rnodes() {
+            if !rnode.read_only() {
+                continue
+            }
+            let Some(bits) = rnode.bits() else { continue };
+            let mut cone = OutputCone {
+                p_external: *p_external,
+                debug_name: rnode.debug_name().map(str::to_owned),
+                bw: rnode.nzbw().get(),
+                lnodes: 0,
+                depth: 0,
+                tnodes: 0,
+                inputs: vec![],
+            };
+            let mut inputs = HashSet::new();
+            let mut visited = HashSet::new();
+            let mut front: Vec<PBack> = vec![];
+            for p_back in bits.iter().copied().flatten() {
+                let p_equiv = self.backrefs.get_val(p_back).unwrap().p_self_equiv;
+                if visited.insert(p_equiv) {
+                    front.push(p_equiv);
+                }
+            }
+            while let Some(p_equiv) = front.pop() {
+                let mut adv = self.backrefs.advancer_surject(p_equiv);
+                while let Some(p_ref) = adv.advance(&self.backrefs) {
+                    let mut next = vec![];
+                    match *self.backrefs.get_key(p_ref).unwrap() {
+                        Referent::ThisLNode(p_lnode) => {
+                            cone.lnodes += 1;
+                            cone.depth = cone.depth.max(depths[&p_lnode]);
+                            self.lnodes[p_lnode].inputs(|p_inp| next.push(p_inp));
+                        }
+                        Referent::ThisTNode(p_tnode) => {
+                            cone.tnodes += 1;
+                            next.push(self.tnodes[p_tnode].p_driver);
+                        }
+                        Referent::ThisRNode(p_input) if p_input != p_rnode => {
+                            let (p_input_external, input) =
+                                self.notary.rnodes().get(p_input).unwrap();
+                            if !input.read_only() {
+                                inputs.insert(*p_input_external);
+                            }
+                        }
+                        _ => (),
+                    }
+                    for p_inp in next {
+                        let p_inp_equiv = self.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+                        if visited.insert(p_inp_equiv) {
+                            front.push(p_inp_equiv);
+                        }
+                    }
+                }
+            }
+            // keep the arena order for determinism
+            for (_, p_input_external, _) in self.notary.rnodes() {
+                if inputs.contains(p_input_external) {
+                    cone.inputs.push(*p_input_external);
+                }
+            }
+            report.outputs.push(cone);
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,220 @@
+use std::{collections::HashMap, fmt};
+
+use awint::Awi;
+
+use crate::{
+    ensemble::{Bdd, BddRef, Ensemble, PBack, PExternal, PTNode},
+    Error,
+};
+
+/// The kind of a reset signal, see [Ensemble::add_reset]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResetKind {
+    /// Registers take their initial values at the end of a cycle in which the
+    /// reset is high
+    SyncActiveHigh,
+    /// Registers take their initial values at the end of a cycle in which the
+    /// reset is low
+    SyncActiveLow,
+}
+
+impl ResetKind {
+    /// Returns the value of the reset signal that resets
+    pub fn active_value(self) -> bool {
+        match self {
+            ResetKind::SyncActiveHigh => true,
+            ResetKind::SyncActiveLow => false,
+        }
+    }
+}
+
+/// The result of [Ensemble::check_reset]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResetReport {
+    /// The number of cycles the reset needs to be held for all the registers
+    /// that can be reset to be in a known state
+    pub cycles: usize,
+    /// The registers that are in a known state after `cycles`, and that state
+    pub reset: Vec<(PTNode, bool)>,
+    /// The registers that are not in a known state no matter how long the
+    /// reset is held
+    pub unreset: Vec<PTNode>,
+    /// The registers in `reset` whose state differs from their declared
+    /// initial value
+    pub mismatched: Vec<PTNode>,
+}
+
+impl ResetReport {
+    /// Returns if every register is reset to its declared initial value
+    pub fn is_complete(&self) -> bool {
+        self.unreset.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+impl fmt::Display for ResetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} registers reset after {} cycles, {} not reset, {} mismatched",
+            self.reset.len(),
+            self.cycles,
+            self.unreset.len(),
+            self.mismatched.len()
+        )
+    }
+}
+
+impl Ensemble {
+    /// Returns the equivalence of the single bit `RNode` of `p_external`
+    fn reset_equiv(&self, p_external: PExternal) -> Result<PBack, Error> {
+        let (_, rnode) = self.notary.get_rnode(p_external)?;
+        if rnode.nzbw().get() != 1 {
+            return Err(Error::BitwidthMismatch(1, rnode.nzbw().get()))
+        }
+        if let Some(Some(p_bit)) = rnode.bits().and_then(|bits| bits.first()) {
+            Ok(self.backrefs.get_val(*p_bit).unwrap().p_self_equiv)
+        } else {
+            Err(Error::OtherStr(
+                "the reset `RNode` has not been lowered or was pruned",
+            ))
+        }
+    }
+
+    /// Threads the single bit reset signal of the `RNode` of `p_external`
+    /// (such as that of a `LazyAwi`) into all the registers, so that they are
+    /// set to their declared initial values according to `kind`. Registers
+    /// are the `TNode`s with nonzero delays that were lowered from `Loop`s
+    /// with known initial values, the driver of each one is replaced by a
+    /// lookup table that selects between the initial value and the original
+    /// driver. Returns the number of registers that got a reset. This should
+    /// be applied after lowering, and [Ensemble::check_reset] can be used to
+    /// verify the result.
+    ///
+    /// # Errors
+    ///
+    /// If the `RNode` is not a lowered single bit
+    pub fn add_reset(&mut self, p_external: PExternal, kind: ResetKind) -> Result<usize, Error> {
+        let p_reset = self.reset_equiv(p_external)?;
+        let mut registers = vec![];
+        for (p_tnode, tnode) in &self.tnodes {
+            if let (false, Some(b)) = (tnode.delay().is_zero(), tnode.initial) {
+                registers.push((p_tnode, b));
+            }
+        }
+        for (p_tnode, b) in registers.iter().copied() {
+            let p_driver = self.tnodes[p_tnode].p_driver;
+            let p_old = self.backrefs.get_val(p_driver).unwrap().p_self_equiv;
+            // the first input is the original driver and the second is the reset
+            let mut lut = Awi::zero(awint::bw(4));
+            let (inactive, active) = if kind.active_value() { (0, 2) } else { (2, 0) };
+            lut.set(inactive + 1, true).unwrap();
+            lut.set(active, b).unwrap();
+            lut.set(active + 1, b).unwrap();
+            let p_new = self.make_lut(&[Some(p_old), Some(p_reset)], &lut, None);
+            self.move_readers(&[p_driver], p_new);
+        }
+        Ok(registers.len())
+    }
+
+    /// Checks which registers (`TNode`s with nonzero delays) are brought into
+    /// a known state by holding the reset of the single bit `RNode` of
+    /// `p_external` active according to `kind`, and if that state is their
+    /// declared initial value. Registers that are only reset through other
+    /// registers (e.x. a shift register where only the first stage is reset)
+    /// take more than one cycle, everything other than the reset and the
+    /// registers already in a known state is treated as a free input. Cones
+    /// are analyzed up to `max_nodes` BDD nodes like in
+    /// [Ensemble::cone_bdds], beyond which registers may be reported as not
+    /// reset.
+    ///
+    /// # Errors
+    ///
+    /// If the `RNode` is not a lowered single bit, or if the reset is not a
+    /// free input
+    pub fn check_reset(
+        &self,
+        p_external: PExternal,
+        kind: ResetKind,
+        max_nodes: usize,
+    ) -> Result<ResetReport, Error> {
+        let p_reset = self.reset_equiv(p_external)?;
+        let mut registers = vec![];
+        let mut roots = vec![p_reset];
+        for (p_tnode, tnode) in &self.tnodes {
+            if !tnode.delay().is_zero() {
+                registers.push(p_tnode);
+                roots.push(tnode.p_self);
+                roots.push(tnode.p_driver);
+            }
+        }
+        let mut bdd = Bdd::new();
+        let order = self.cone_bdds(&mut bdd, &roots, max_nodes);
+        let funcs: HashMap<PBack, BddRef> = order.iter().map(|(p, f, _)| (*p, *f)).collect();
+        let func = |p_back: PBack| funcs[&self.backrefs.get_val(p_back).unwrap().p_self_equiv];
+        let Some(reset_var) = bdd.top_var(func(p_reset)).filter(|_| {
+            order
+                .iter()
+                .any(|(p, _, is_leaf)| (*p == p_reset) && *is_leaf)
+        }) else {
+            return Err(Error::OtherStr(
+                "`check_reset` requires the reset to be a free input",
+            ))
+        };
+        let active = kind.active_value();
+
+        // the drivers while the reset is active
+        let mut drivers = vec![];
+        for p_tnode in registers.iter().copied() {
+            let f = func(self.tnodes[p_tnode].p_driver);
+            drivers.push(bdd.restrict(f, reset_var, active));
+        }
+        // the state variable and known value of each register
+        let mut known: Vec<Option<bool>> = vec![None; registers.len()];
+        let vars: Vec<Option<usize>> = registers
+            .iter()
+            .map(|p_tnode| bdd.top_var(func(self.tnodes[*p_tnode].p_self)))
+            .collect();
+        let mut cycles = 0;
+        loop {
+            let mut next = vec![None; registers.len()];
+            for (i, f) in drivers.iter().copied().enumerate() {
+                let mut f = f;
+                for (var, b) in vars.iter().zip(known.iter()) {
+                    if let (Some(var), Some(b)) = (var, b) {
+                        f = bdd.restrict(f, *var, *b);
+                    }
+                }
+                if f.is_const() {
+                    next[i] = Some(f == BddRef::TRUE);
+                }
+            }
+            // the set of known registers can only grow, but values may change
+            let grew = next.iter().filter(|b| b.is_some()).count()
+                > known.iter().filter(|b| b.is_some()).count();
+            known = next;
+            if !grew {
+                break
+            }
+            cycles += 1;
+        }
+
+        let mut report = ResetReport {
+            cycles,
+            ..Default::default()
+        };
+        for (p_tnode, b) in registers.iter().copied().zip(known) {
+            if let Some(b) = b {
+                report.reset.push((p_tnode, b));
+                if self.tnodes[p_tnode]
+                    .initial
+                    .is_some_and(|initial| initial != b)
+                {
+                    report.mismatched.push(p_tnode);
+                }
+            } else {
+                report.unreset.push(p_tnode);
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,475 @@
+use std::num::NonZeroUsize;
+
+use awint::{
+    awint_dag::{smallvec::SmallVec, Location, Op, PState},
+    Awi,
+};
+
+use crate::{ensemble::Ensemble, Error};
+
+/// The `State` operations that rewrite rules can match and create. These all
+/// have operands with the same bitwidth as their output.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RewriteOp {
+    Not,
+    And,
+    Or,
+    Xor,
+    Add,
+    Sub,
+}
+
+impl RewriteOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "not" => Self::Not,
+            "and" => Self::And,
+            "or" => Self::Or,
+            "xor" => Self::Xor,
+            "add" => Self::Add,
+            "sub" => Self::Sub,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Not => "not",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::Add => "add",
+            Self::Sub => "sub",
+        }
+    }
+
+    pub fn num_operands(self) -> usize {
+        if let Self::Not = self {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Returns if the operands can be swapped
+    pub fn is_commutative(self) -> bool {
+        matches!(self, Self::And | Self::Or | Self::Xor | Self::Add)
+    }
+
+    /// Returns the `RewriteOp` and operands of `op` if it is one
+    pub fn from_op(op: &Op<PState>) -> Option<(Self, SmallVec<[PState; 2]>)> {
+        let (res, operands) = match op {
+            Op::Not([a]) => return Some((Self::Not, SmallVec::from_slice(&[*a]))),
+            Op::And(v) => (Self::And, v),
+            Op::Or(v) => (Self::Or, v),
+            Op::Xor(v) => (Self::Xor, v),
+            Op::Add(v) => (Self::Add, v),
+            Op::Sub(v) => (Self::Sub, v),
+            _ => return None,
+        };
+        Some((res, SmallVec::from_slice(operands)))
+    }
+
+    /// Returns the `Op` of `self` with `operands`
+    pub fn to_op(self, operands: &[PState]) -> Op<PState> {
+        match self {
+            Self::Not => Op::Not([operands[0]]),
+            Self::And => Op::And([operands[0], operands[1]]),
+            Self::Or => Op::Or([operands[0], operands[1]]),
+            Self::Xor => Op::Xor([operands[0], operands[1]]),
+            Self::Add => Op::Add([operands[0], operands[1]]),
+            Self::Sub => Op::Sub([operands[0], operands[1]]),
+        }
+    }
+
+    /// The cost model used for comparing rewrites, which is an estimate of
+    /// the number of `LNode`s that the operation lowers to with bitwidth `w`.
+    /// Every operation costs at least 1 so that extraction is acyclic.
+    pub fn cost(self, w: NonZeroUsize) -> u64 {
+        let w = w.get() as u64;
+        match self {
+            Self::Not | Self::And | Self::Or | Self::Xor => w,
+            // a sum and carry per bit
+            Self::Add | Self::Sub => w.saturating_mul(2),
+        }
+    }
+}
+
+/// A pattern of a [RewriteRule]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// Matches anything, all occurances of the same variable must match the
+    /// same thing
+    Var(usize),
+    /// Matches a zero literal of the width of the context
+    Zero,
+    /// Matches an all ones literal of the width of the context
+    Umax,
+    Op(RewriteOp, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Returns the number of operations in `self`
+    pub fn num_ops(&self) -> usize {
+        if let Pattern::Op(_, v) = self {
+            1 + v.iter().map(|p| p.num_ops()).sum::<usize>()
+        } else {
+            0
+        }
+    }
+
+    /// Returns `self` with the operands of every combination of commutative
+    /// operations swapped
+    pub fn commuted_variants(&self) -> Vec<Pattern> {
+        match self {
+            Pattern::Op(op, v) => {
+                let mut res: Vec<Vec<Pattern>> = vec![vec![]];
+                for operand in v {
+                    let variants = operand.commuted_variants();
+                    let mut next = vec![];
+                    for prefix in &res {
+                        for variant in &variants {
+                            let mut tmp = prefix.clone();
+                            tmp.push(variant.clone());
+                            next.push(tmp);
+                        }
+                    }
+                    res = next;
+                }
+                let mut patterns = vec![];
+                for operands in res {
+                    if op.is_commutative() {
+                        let mut swapped = operands.clone();
+                        swapped.swap(0, 1);
+                        patterns.push(Pattern::Op(*op, swapped));
+                    }
+                    patterns.push(Pattern::Op(*op, operands));
+                }
+                patterns.dedup();
+                patterns
+            }
+            _ => vec![self.clone()],
+        }
+    }
+
+    /// Parses an s-expression like `(and ?a (not ?b))`, where `?` variables
+    /// are assigned indexes in `vars`
+    fn parse<'a>(s: &'a str, vars: &mut Vec<&'a str>) -> Result<Pattern, Error> {
+        let s = s.trim();
+        if let Some(name) = s.strip_prefix('?') {
+            let i = if let Some(i) = vars.iter().position(|v| *v == name) {
+                i
+            } else {
+                vars.push(name);
+                vars.len() - 1
+            };
+            return Ok(Pattern::Var(i))
+        }
+        match s {
+            "zero" => return Ok(Pattern::Zero),
+            "umax" => return Ok(Pattern::Umax),
+            _ => (),
+        }
+        let inner = s
+            .strip_prefix('(')
+            .and_then(|s| s.strip_suffix(')'))
+            .ok_or_else(|| Error::OtherString(format!("could not parse pattern \"{s}\"")))?;
+        // split the top level terms
+        let mut terms = vec![];
+        let mut depth = 0usize;
+        let mut start = None;
+        for (i, c) in inner.char_indices() {
+            match c {
+                '(' => {
+                    if depth == 0 {
+                        start = Some(i);
+                    }
+                    depth += 1;
+                }
+                ')' => {
+                    depth = depth.checked_sub(1).ok_or_else(|| {
+                        Error::OtherString(format!("unbalanced parenthesis in \"{s}\""))
+                    })?;
+                    if depth == 0 {
+                        terms.push(&inner[start.take().unwrap()..=i]);
+                    }
+                }
+                c if c.is_whitespace() => {
+                    if depth == 0 {
+                        if let Some(start) = start.take() {
+                            terms.push(&inner[start..i]);
+                        }
+                    }
+                }
+                _ => {
+                    if start.is_none() {
+                        start = Some(i);
+                    }
+                }
+            }
+        }
+        if depth != 0 {
+            return Err(Error::OtherString(format!(
+                "unbalanced parenthesis in \"{s}\""
+            )))
+        }
+        if let Some(start) = start {
+            terms.push(&inner[start..]);
+        }
+        let op = terms
+            .first()
+            .and_then(|name| RewriteOp::from_name(name))
+            .ok_or_else(|| Error::OtherString(format!("unknown operation in \"{s}\"")))?;
+        if terms.len() != (op.num_operands() + 1) {
+            return Err(Error::OtherString(format!(
+                "wrong number of operands in \"{s}\""
+            )))
+        }
+        let mut operands = vec![];
+        for term in &terms[1..] {
+            operands.push(Pattern::parse(term, vars)?);
+        }
+        Ok(Pattern::Op(op, operands))
+    }
+}
+
+/// An equivalence between two [Pattern]s that can be applied in the
+/// direction of `lhs` to `rhs`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteRule {
+    pub name: &'static str,
+    pub lhs: Pattern,
+    pub rhs: Pattern,
+    /// The number of variables
+    pub num_vars: usize,
+}
+
+impl RewriteRule {
+    /// Parses a rule from s-expressions, see [rewrite_rules] for examples
+    ///
+    /// # Errors
+    ///
+    /// If the patterns could not be parsed, or if `rhs` has a variable that
+    /// `lhs` does not have
+    pub fn parse(name: &'static str, lhs: &str, rhs: &str) -> Result<Self, Error> {
+        let mut vars = vec![];
+        let lhs = Pattern::parse(lhs, &mut vars)?;
+        let num_vars = vars.len();
+        let rhs = Pattern::parse(rhs, &mut vars)?;
+        if vars.len() != num_vars {
+            return Err(Error::OtherString(format!(
+                "rule \"{name}\" has variables on the right hand side that are not on the left \
+                 hand side"
+            )))
+        }
+        Ok(Self {
+            name,
+            lhs,
+            rhs,
+            num_vars,
+        })
+    }
+
+    /// Returns if applying `self` always removes operations
+    pub fn is_shrinking(&self) -> bool {
+        self.rhs.num_ops() < self.lhs.num_ops()
+    }
+}
+
+/// The rule set shared by [Ensemble::peephole_states] and the egraph pass.
+/// The peephole engine only applies the shrinking rules.
+pub const REWRITE_RULES: [(&str, &str, &str); 30] = [
+    ("and-comm", "(and ?a ?b)", "(and ?b ?a)"),
+    ("or-comm", "(or ?a ?b)", "(or ?b ?a)"),
+    ("xor-comm", "(xor ?a ?b)", "(xor ?b ?a)"),
+    ("add-comm", "(add ?a ?b)", "(add ?b ?a)"),
+    ("and-assoc", "(and (and ?a ?b) ?c)", "(and ?a (and ?b ?c))"),
+    ("or-assoc", "(or (or ?a ?b) ?c)", "(or ?a (or ?b ?c))"),
+    ("xor-assoc", "(xor (xor ?a ?b) ?c)", "(xor ?a (xor ?b ?c))"),
+    ("add-assoc", "(add (add ?a ?b) ?c)", "(add ?a (add ?b ?c))"),
+    ("not-not", "(not (not ?a))", "?a"),
+    ("and-idem", "(and ?a ?a)", "?a"),
+    ("or-idem", "(or ?a ?a)", "?a"),
+    ("xor-self", "(xor ?a ?a)", "zero"),
+    ("sub-self", "(sub ?a ?a)", "zero"),
+    ("and-zero", "(and ?a zero)", "zero"),
+    ("and-umax", "(and ?a umax)", "?a"),
+    ("or-zero", "(or ?a zero)", "?a"),
+    ("or-umax", "(or ?a umax)", "umax"),
+    ("xor-zero", "(xor ?a zero)", "?a"),
+    ("xor-umax", "(xor ?a umax)", "(not ?a)"),
+    ("add-zero", "(add ?a zero)", "?a"),
+    ("sub-zero", "(sub ?a zero)", "?a"),
+    ("sub-add", "(sub (add ?a ?b) ?b)", "?a"),
+    ("add-sub", "(add (sub ?a ?b) ?b)", "?a"),
+    (
+        "demorgan-and",
+        "(not (and ?a ?b))",
+        "(or (not ?a) (not ?b))",
+    ),
+    ("demorgan-or", "(not (or ?a ?b))", "(and (not ?a) (not ?b))"),
+    (
+        "factor-and",
+        "(or (and ?a ?b) (and ?a ?c))",
+        "(and ?a (or ?b ?c))",
+    ),
+    (
+        "factor-or",
+        "(and (or ?a ?b) (or ?a ?c))",
+        "(or ?a (and ?b ?c))",
+    ),
+    (
+        "factor-xor",
+        "(xor (and ?a ?b) (and ?a ?c))",
+        "(and ?a (xor ?b ?c))",
+    ),
+    ("absorb-and", "(and ?a (or ?a ?b))", "?a"),
+    ("absorb-or", "(or ?a (and ?a ?b))", "?a"),
+];
+
+/// Returns the parsed [REWRITE_RULES]
+pub fn rewrite_rules() -> Vec<RewriteRule> {
+    REWRITE_RULES
+        .iter()
+        .map(|(name, lhs, rhs)| RewriteRule::parse(name, lhs, rhs).unwrap())
+        .collect()
+}
+
+impl Ensemble {
+    /// Returns if the `State` of `p_state` can be rewritten, which requires
+    /// that it has not started lowering and is a `Copy` or a [RewriteOp]
+    pub fn is_rewritable_state(&self, p_state: PState) -> bool {
+        let state = &self.stator.states[p_state];
+        state.p_self_bits.is_empty()
+            && !state.lowered_to_elementary
+            && !state.lowered_to_lnodes
+            && state.err.is_none()
+            && (matches!(state.op, Op::Copy(_)) || RewriteOp::from_op(&state.op).is_some())
+    }
+
+    /// Follows rewritable `Copy`s to their source
+    fn resolve_state_copies(&self, mut p_state: PState) -> PState {
+        while let Op::Copy([p_source]) = self.stator.states[p_state].op {
+            if !self.is_rewritable_state(p_state) {
+                break
+            }
+            p_state = p_source;
+        }
+        p_state
+    }
+
+    fn match_state(
+        &self,
+        pattern: &Pattern,
+        p_state: PState,
+        subst: &mut [Option<PState>],
+    ) -> bool {
+        let p_state = self.resolve_state_copies(p_state);
+        match pattern {
+            Pattern::Var(i) => {
+                if let Some(p_bound) = subst[*i] {
+                    p_bound == p_state
+                } else {
+                    subst[*i] = Some(p_state);
+                    true
+                }
+            }
+            Pattern::Zero => {
+                matches!(self.stator.states[p_state].op, Op::Literal(ref a) if a.is_zero())
+            }
+            Pattern::Umax => {
+                matches!(self.stator.states[p_state].op, Op::Literal(ref a) if a.is_umax())
+            }
+            Pattern::Op(op, operands) => {
+                if !self.is_rewritable_state(p_state) {
+                    return false
+                }
+                if let Some((state_op, state_operands)) =
+                    RewriteOp::from_op(&self.stator.states[p_state].op)
+                {
+                    (state_op == *op)
+                        && operands
+                            .iter()
+                            .zip(state_operands)
+                            .all(|(pattern, p_operand)| self.match_state(pattern, p_operand, subst))
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn instantiate_state(
+        &mut self,
+        pattern: &Pattern,
+        subst: &[Option<PState>],
+        nzbw: NonZeroUsize,
+        location: Option<Location>,
+    ) -> PState {
+        match pattern {
+            Pattern::Var(i) => subst[*i].unwrap(),
+            Pattern::Zero => self.make_state(nzbw, Op::Literal(Awi::zero(nzbw)), location),
+            Pattern::Umax => self.make_state(nzbw, Op::Literal(Awi::umax(nzbw)), location),
+            Pattern::Op(op, operands) => {
+                let operands: Vec<PState> = operands
+                    .iter()
+                    .map(|pattern| self.instantiate_state(pattern, subst, nzbw, location))
+                    .collect();
+                self.make_state(nzbw, op.to_op(&operands), location)
+            }
+        }
+    }
+
+    /// Replaces the `Op` of `p_state` with a `Copy` of `p_source`
+    pub(crate) fn replace_state_with_copy(&mut self, p_state: PState, p_source: PState) {
+        let op = std::mem::replace(&mut self.stator.states[p_state].op, Op::Copy([p_source]));
+        self.stator.states[p_source].inc_rc();
+        for p_operand in op.operands() {
+            self.stator.states[*p_operand].dec_rc().unwrap();
+        }
+    }
+
+    /// Sequentially applies the shrinking rules of [rewrite_rules] to the
+    /// `State`s that have not been lowered yet, until no more apply. This is a
+    /// fast local alternative to the egraph pass. Returns the number of
+    /// rewrites.
+    pub fn peephole_states(&mut self) -> Result<usize, Error> {
+        let mut rules = vec![];
+        for rule in rewrite_rules() {
+            if rule.is_shrinking() {
+                for lhs in rule.lhs.commuted_variants() {
+                    rules.push((lhs, rule.rhs.clone(), rule.num_vars));
+                }
+            }
+        }
+        let mut num_rewrites = 0;
+        loop {
+            let mut progress = false;
+            let p_states: Vec<PState> = self.stator.states.ptrs().collect();
+            for p_state in p_states {
+                if !self.stator.states.contains(p_state)
+                    || !self.is_rewritable_state(p_state)
+                    || matches!(self.stator.states[p_state].op, Op::Copy(_))
+                {
+                    continue
+                }
+                for (lhs, rhs, num_vars) in &rules {
+                    let mut subst = vec![None; *num_vars];
+                    if self.match_state(lhs, p_state, &mut subst) {
+                        let state = &self.stator.states[p_state];
+                        let (nzbw, location) = (state.nzbw, state.location);
+                        let p_new = self.instantiate_state(rhs, &subst, nzbw, location);
+                        self.replace_state_with_copy(p_state, p_new);
+                        num_rewrites += 1;
+                        progress = true;
+                        break
+                    }
+                }
+            }
+            if !progress {
+                break
+            }
+        }
+        Ok(num_rewrites)
+    }
+}
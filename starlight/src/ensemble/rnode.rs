@@ -1,6 +1,7 @@
 use std::{
     fmt,
     num::{NonZeroU128, NonZeroU64, NonZeroUsize},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use awint::awint_dag::{
@@ -112,7 +113,7 @@ impl fmt::Debug for PExternal {
             if let Ok(epoch) = get_current_epoch() {
                 if let Ok(lock) = epoch.epoch_data.try_borrow() {
                     if let Ok((_, rnode)) = lock.ensemble.notary.get_rnode(*self) {
-                        if let Some(ref name) = rnode.debug_name {
+                        if let Some(name) = rnode.debug_name() {
                             tmp.field("debug_name", &DisplayStr(name));
                         }
                         /*if let Some(s) = lock.ensemble.get_state_debug(self.state()) {
@@ -146,10 +147,22 @@ pub struct RNode {
     pub associated_state: Option<PState>,
     /// If the associated state needs to be lowered before states are pruned
     pub lower_before_pruning: bool,
-    /// Location where this `RNode` was created
-    pub location: Option<Location>,
+    /// If set, the optimizer treats the bits as barriers that it does not
+    /// forward or propagate constants through, and the `RNode` is kept after
+    /// the `extern_rc` drops to zero
+    pub keep: bool,
+    /// Metadata that is not needed for evaluation, kept out of line so that
+    /// the `RNode` arena stays compact
+    cold: Option<Box<RNodeCold>>,
+}
+
+/// The cold metadata of an `RNode`
+#[derive(Debug, Clone, Default)]
+struct RNodeCold {
+    /// Location where the `RNode` was created
+    location: Option<Location>,
     /// Name used for debug renders and more
-    pub debug_name: Option<String>,
+    debug_name: Option<String>,
 }
 
 impl Recast<PBack> for RNode {
@@ -177,9 +190,33 @@ impl RNode {
             extern_rc,
             associated_state,
             lower_before_pruning,
-            location,
-            debug_name: None,
+            keep: false,
+            cold: location.map(|location| {
+                Box::new(RNodeCold {
+                    location: Some(location),
+                    debug_name: None,
+                })
+            }),
+        }
+    }
+
+    /// Location where this `RNode` was created
+    pub fn location(&self) -> Option<Location> {
+        self.cold.as_ref().and_then(|cold| cold.location)
+    }
+
+    /// Name used for debug renders and more
+    pub fn debug_name(&self) -> Option<&str> {
+        self.cold
+            .as_ref()
+            .and_then(|cold| cold.debug_name.as_deref())
+    }
+
+    pub fn set_debug_name(&mut self, debug_name: Option<String>) {
+        if debug_name.is_none() && self.cold.is_none() {
+            return
         }
+        self.cold.get_or_insert_with(Box::default).debug_name = debug_name;
     }
 
     pub fn nzbw(&self) -> NonZeroUsize {
@@ -208,6 +245,11 @@ impl RNode {
             Some(&mut self.bits)
         }
     }
+
+    /// Pushes a bit, used when constructing an already initialized `RNode`
+    pub(crate) fn push_bit(&mut self, bit: Option<PBack>) {
+        self.bits.push(bit);
+    }
 }
 
 /// Used for managing external references
@@ -226,11 +268,30 @@ impl Recast<PBack> for Notary {
     }
 }
 
+/// Returns the first `PExternal` index of a new `Notary`. This uses a global
+/// counter scrambled by a bijective mixer instead of system randomness, so that
+/// it works on targets without an entropy source such as
+/// `wasm32-unknown-unknown` while still spreading different `Notary`s far
+/// apart.
+fn next_external_start() -> NonZeroU128 {
+    static NOTARY_COUNTER: AtomicU64 = AtomicU64::new(0);
+    // splitmix64
+    let mix = |x: u64| {
+        let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    let x = NOTARY_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let start = (u128::from(mix(x.wrapping_mul(2))) << 64) | u128::from(mix(x.wrapping_mul(2) | 1));
+    NonZeroU128::new(start).unwrap_or(NonZeroU128::new(1).unwrap())
+}
+
 impl Notary {
     pub fn new() -> Self {
         Self {
             rnodes: OrdArena::new(),
-            next_external: rand::random(),
+            next_external: next_external_start(),
         }
     }
 
@@ -282,6 +343,19 @@ impl Notary {
         }
     }
 
+    /// Finds the `PExternal` of the first `RNode` with a `debug_name` equal to
+    /// `debug_name`
+    #[must_use]
+    pub fn find_by_debug_name(&self, debug_name: &str) -> Option<PExternal> {
+        for p_rnode in self.rnodes.ptrs() {
+            let (p_external, rnode) = self.rnodes.get(p_rnode).unwrap();
+            if rnode.debug_name() == Some(debug_name) {
+                return Some(*p_external)
+            }
+        }
+        None
+    }
+
     #[must_use]
     pub fn get_rnode_by_p_rnode_mut(&mut self, p_rnode: PRNode) -> Option<&mut RNode> {
         self.rnodes.get_val_mut(p_rnode)
@@ -300,6 +374,11 @@ impl Ensemble {
         if let Some(state) = self.stator.states.get_mut(p_state) {
             state.inc_extern_rc();
             let nzbw = state.nzbw;
+            if read_only {
+                if let Some(ref mut log) = self.replay_log {
+                    log.roots.push(p_state);
+                }
+            }
             let (_, p_external) = self.notary.insert_rnode(RNode::new(
                 nzbw,
                 read_only,
@@ -363,8 +442,12 @@ impl Ensemble {
         let rnode = lock.ensemble.notary.rnodes.get_val_mut(p_rnode).unwrap();
         if rnode.lower_before_pruning {
             rnode.lower_before_pruning = false;
-            let p_state = rnode.associated_state.unwrap();
-            if lock.ensemble.stator.states.contains(p_state) {
+            // the state may have already been pruned if the `RNode` was created
+            // during an earlier lowering
+            let p_state = rnode.associated_state;
+            if let Some(p_state) =
+                p_state.filter(|p_state| lock.ensemble.stator.states.contains(*p_state))
+            {
                 drop(lock);
                 Ensemble::dfs_lower(epoch_shared, p_state)?;
             } else {
@@ -401,11 +484,11 @@ impl Ensemble {
     }
 
     /// Decrements the `extern_rc` of the `RNode` pointed to by `p_external`,
-    /// removing it if the count drops to zero
+    /// removing it if the count drops to zero and it is not marked `keep`
     pub fn rnode_dec_rc(&mut self, p_external: PExternal) -> Result<(), Error> {
         let (p_rnode, rnode) = self.notary.get_rnode_mut(p_external)?;
         rnode.extern_rc = rnode.extern_rc.checked_sub(1).unwrap();
-        if rnode.extern_rc == 0 {
+        if (rnode.extern_rc == 0) && !rnode.keep {
             self.remove_rnode(p_rnode);
         }
         Ok(())
@@ -481,6 +564,42 @@ impl Ensemble {
         Ok(())
     }
 
+    /// Like `change_thread_local_rnode_value` but only changes the bit at
+    /// `bit_i` to `value`, or to an unknown if `value` is `None`
+    pub fn change_thread_local_rnode_bit(
+        p_external: PExternal,
+        bit_i: usize,
+        value: Option<bool>,
+    ) -> Result<(), Error> {
+        let epoch_shared = get_current_epoch()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        let (p_rnode, _) = lock.ensemble.notary.get_rnode(p_external)?;
+        drop(lock);
+        Ensemble::initialize_rnode_if_needed(&epoch_shared, p_rnode, true)?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let ensemble = &mut lock.ensemble;
+        let bits = &ensemble.notary.rnodes[p_rnode].bits;
+        if bits.is_empty() {
+            // the state was pruned
+            return Ok(())
+        }
+        let w = bits.len();
+        let Some(p_back) = *bits.get(bit_i).ok_or_else(|| {
+            Error::OtherString(format!(
+                "bit {bit_i} is out of range of {p_external} which has bitwidth {w}"
+            ))
+        })?
+        else {
+            return Ok(())
+        };
+        let value = if let Some(b) = value {
+            Value::Dynam(b)
+        } else {
+            Value::Unknown
+        };
+        ensemble.change_value(p_back, value, NonZeroU64::new(1).unwrap())
+    }
+
     pub fn request_thread_local_rnode_value(
         p_external: PExternal,
         bit_i: usize,
@@ -596,7 +715,15 @@ impl Ensemble {
             .rnodes
             .get_val_mut(p_rnode)
             .unwrap()
-            .debug_name = debug_name.map(|s| s.to_owned());
+            .set_debug_name(debug_name.map(|s| s.to_owned()));
+        Ok(())
+    }
+
+    pub fn thread_local_rnode_mark_keep(p_external: PExternal) -> Result<(), Error> {
+        let epoch_shared = get_current_epoch()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        let (_, rnode) = lock.ensemble.notary.get_rnode_mut(p_external)?;
+        rnode.keep = true;
         Ok(())
     }
 }
@@ -0,0 +1,148 @@
+use std::collections::HashSet;
+
+use awint::awint_dag::{triple_arena::Advancer, Location, PState};
+
+use crate::{ensemble::Ensemble, epoch::EpochShared, Error};
+
+/// A report of the errors encountered by [crate::Epoch::lower_best_effort]
+#[derive(Debug, Clone, Default)]
+pub struct LoweringReport {
+    errors: Vec<(PState, Option<Location>, Error)>,
+    failed_states: HashSet<PState>,
+    other_errors: Vec<Error>,
+    num_lowered: usize,
+    num_failed: usize,
+}
+
+impl LoweringReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns if no errors were encountered
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty() && self.other_errors.is_empty()
+    }
+
+    /// Returns the states that failed to lower along with their source
+    /// locations and errors (which are `Error::LoweringFailed` with context
+    /// about the state), in the order they were encountered. States that
+    /// depend on a failed state may also be included, but each state appears
+    /// at most once.
+    pub fn errors(&self) -> &[(PState, Option<Location>, Error)] {
+        &self.errors
+    }
+
+    /// Returns errors that could not be attributed to a particular state, such
+    /// as `Error::ResourceLimitExceeded` or a failed assertion
+    pub fn other_errors(&self) -> &[Error] {
+        &self.other_errors
+    }
+
+    /// Returns the number of trees (rooted at states that were queued for
+    /// lowering or at `RNode`s) that were fully lowered
+    pub fn num_lowered(&self) -> usize {
+        self.num_lowered
+    }
+
+    /// Returns the number of trees that could not be fully lowered
+    pub fn num_failed(&self) -> usize {
+        self.num_failed
+    }
+
+    fn record(&mut self, ensemble: &Ensemble, p_failed: Option<PState>, err: Error) {
+        self.num_failed += 1;
+        // the error is attributed to the state it was stored on, or else it is
+        // something like an exceeded limit
+        if let Some(p_state) = p_failed {
+            if let Some(state) = ensemble.stator.states.get(p_state) {
+                if self.failed_states.insert(p_state) {
+                    let err = if let Error::LoweringFailed(..) = err {
+                        err
+                    } else {
+                        ensemble.lowering_error_context(p_state, err)
+                    };
+                    self.errors.push((p_state, state.location, err));
+                }
+                return
+            }
+        }
+        if !self.other_errors.contains(&err) {
+            self.other_errors.push(err);
+        }
+    }
+}
+
+impl Ensemble {
+    /// Like [Ensemble::handle_states_to_lower] and
+    /// [Ensemble::lower_for_rnodes], except that errors are recorded in the
+    /// returned report and lowering continues with the other trees. Errors
+    /// from evaluating the assertions afterwards are also recorded.
+    pub fn lower_best_effort(epoch_shared: &EpochShared) -> LoweringReport {
+        let mut report = LoweringReport::new();
+        loop {
+            let mut lock = epoch_shared.epoch_data.borrow_mut();
+            if let Some(p_state) = lock.ensemble.stator.states_to_lower.pop() {
+                if let Some(state) = lock.ensemble.stator.states.get(p_state) {
+                    if !state.lowered_to_lnodes {
+                        lock.ensemble.stator.p_failed = None;
+                        drop(lock);
+                        let res = Ensemble::dfs_lower(epoch_shared, p_state);
+                        let mut lock = epoch_shared.epoch_data.borrow_mut();
+                        match res {
+                            Ok(()) => report.num_lowered += 1,
+                            Err(e) => {
+                                let p_failed = lock.ensemble.stator.p_failed.take();
+                                report.record(&lock.ensemble, p_failed, e)
+                            }
+                        }
+                    }
+                }
+            } else {
+                break
+            }
+        }
+        let lock = epoch_shared.epoch_data.borrow();
+        let mut adv = lock.ensemble.notary.rnodes().advancer();
+        drop(lock);
+        loop {
+            let mut lock = epoch_shared.epoch_data.borrow_mut();
+            if let Some(p_rnode) = adv.advance(lock.ensemble.notary.rnodes()) {
+                let lowering = lock
+                    .ensemble
+                    .notary
+                    .rnodes
+                    .get_val(p_rnode)
+                    .unwrap()
+                    .lower_before_pruning;
+                lock.ensemble.stator.p_failed = None;
+                let res = if lowering {
+                    drop(lock);
+                    let res = Ensemble::initialize_rnode_if_needed(epoch_shared, p_rnode, true);
+                    lock = epoch_shared.epoch_data.borrow_mut();
+                    res
+                } else {
+                    lock.ensemble
+                        .initialize_rnode_if_needed_no_lowering(p_rnode, true)
+                };
+                match res {
+                    Ok(_) => {
+                        if lowering {
+                            report.num_lowered += 1;
+                        }
+                    }
+                    Err(e) => {
+                        let p_failed = lock.ensemble.stator.p_failed.take();
+                        report.record(&lock.ensemble, p_failed, e)
+                    }
+                }
+            } else {
+                break
+            }
+        }
+        if let Err(e) = epoch_shared.assert_assertions(false) {
+            report.other_errors.push(e);
+        }
+        report
+    }
+}
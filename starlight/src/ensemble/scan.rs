@@ -0,0 +1,129 @@
+use std::fmt;
+
+use awint::{bw, Awi};
+
+use crate::{
+    ensemble::{Ensemble, PBack, PExternal, PTNode, RNode, Referent},
+    Error,
+};
+
+/// The result of [Ensemble::insert_scan_chains]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanReport {
+    /// The registers of each chain in shift order, the first register of a
+    /// chain takes the scan-in bit and the last one is seen at the scan-out
+    /// bit of the chain
+    pub chains: Vec<Vec<PTNode>>,
+    /// The read only `RNode` with one scan-out bit per chain
+    pub scan_out: PExternal,
+}
+
+impl ScanReport {
+    /// Returns the length of the longest chain, which is the number of shifts
+    /// needed to dump or load all the registers
+    pub fn max_len(&self) -> usize {
+        self.chains
+            .iter()
+            .map(|chain| chain.len())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Writes one line per chain
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, chain) in self.chains.iter().enumerate() {
+            writeln!(f, "chain {i}: {} registers", chain.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Ensemble {
+    /// Returns the equivalences of the bits of the lowered `RNode` of the scan
+    /// port `p_external`
+    fn scan_port_equivs(&self, p_external: PExternal) -> Result<Vec<PBack>, Error> {
+        let (_, rnode) = self.notary.get_rnode(p_external)?;
+        let mut res = vec![];
+        for p_bit in rnode.bits().unwrap_or(&[]) {
+            if let Some(p_bit) = p_bit {
+                res.push(self.backrefs.get_val(*p_bit).unwrap().p_self_equiv);
+            } else {
+                return Err(Error::OtherStr("a scan port bit was pruned"))
+            }
+        }
+        if res.len() != rnode.nzbw().get() {
+            return Err(Error::OtherStr("a scan port has not been lowered"))
+        }
+        Ok(res)
+    }
+
+    /// Stitches all the registers (`TNode`s with nonzero delays) into scan
+    /// chains, one per bit of the `RNode` of `p_scan_in` (such as that of a
+    /// `LazyAwi`). While the single bit `RNode` of `p_scan_enable` is set,
+    /// each register takes the value of the previous register in its chain
+    /// (or the scan-in bit for the first register) instead of the value of
+    /// its original driver. Registers are distributed over the chains in
+    /// arena order with chain lengths differing by at most one. A read only
+    /// `RNode` is created with the output of the last register of each
+    /// chain, or the scan-in bit if the chain is empty. The registers of a
+    /// chain can be dumped by reading the scan-out bit and shifting, which
+    /// yields them from last to first, and loaded by shifting in values from
+    /// last to first. This should be applied after lowering.
+    ///
+    /// # Errors
+    ///
+    /// If the `RNode`s are not lowered or `p_scan_enable` is not a single bit
+    pub fn insert_scan_chains(
+        &mut self,
+        p_scan_enable: PExternal,
+        p_scan_in: PExternal,
+    ) -> Result<ScanReport, Error> {
+        let scan_enable = self.scan_port_equivs(p_scan_enable)?;
+        if scan_enable.len() != 1 {
+            return Err(Error::BitwidthMismatch(1, scan_enable.len()))
+        }
+        let p_enable = scan_enable[0];
+        let scan_in = self.scan_port_equivs(p_scan_in)?;
+        let num_chains = scan_in.len();
+        let mut registers = vec![];
+        for (p_tnode, tnode) in &self.tnodes {
+            if !tnode.delay().is_zero() {
+                registers.push(p_tnode);
+            }
+        }
+        let mut chains = vec![];
+        let mut rest = registers.as_slice();
+        for i in 0..num_chains {
+            let len = rest.len().div_ceil(num_chains - i);
+            let (chain, tail) = rest.split_at(len);
+            chains.push(chain.to_vec());
+            rest = tail;
+        }
+
+        // the inputs are the original driver, the previous register, and the
+        // scan enable
+        let lut = Awi::from_u8(0xca);
+        let mut rnode = RNode::new(bw(num_chains), true, 0, None, None, false);
+        rnode.set_debug_name(Some("scan_out".to_owned()));
+        let (p_rnode, scan_out) = self.notary.insert_rnode(rnode);
+        for (chain, p_in) in chains.iter().zip(scan_in) {
+            let mut p_prev = p_in;
+            for p_tnode in chain.iter().copied() {
+                let tnode = &self.tnodes[p_tnode];
+                let (p_driver, p_self) = (tnode.p_driver, tnode.p_self);
+                let p_old = self.backrefs.get_val(p_driver).unwrap().p_self_equiv;
+                let p_new = self.make_lut(&[Some(p_old), Some(p_prev), Some(p_enable)], &lut, None);
+                self.move_readers(&[p_driver], p_new);
+                p_prev = self.backrefs.get_val(p_self).unwrap().p_self_equiv;
+            }
+            let p_bit = self
+                .backrefs
+                .insert_key(p_prev, Referent::ThisRNode(p_rnode))
+                .unwrap();
+            self.notary.rnodes[p_rnode].push_bit(Some(p_bit));
+        }
+        Ok(ScanReport { chains, scan_out })
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    io::Write as _,
+    num::NonZeroUsize,
+    process::{Command, Stdio},
+};
+
+use awint::{awint_internals::Digit, Awi, Bits};
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNode, LNodeKind, PBack, PExternal, Value},
+    Error,
+};
+
+/// Something that can run an SMT-LIB2 script and return what it printed, see
+/// [SmtCommand] for running external solvers like z3 or cvc5. This is also
+/// implemented for closures, which is useful for solvers that are linked in
+/// as libraries or for testing.
+pub trait SmtSolver {
+    /// Runs `script` and returns the standard output
+    fn run(&mut self, script: &str) -> Result<String, Error>;
+}
+
+impl<F: FnMut(&str) -> Result<String, Error>> SmtSolver for F {
+    fn run(&mut self, script: &str) -> Result<String, Error> {
+        self(script)
+    }
+}
+
+/// Runs an external solver process that reads an SMT-LIB2 script from its
+/// standard input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl SmtCommand {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_owned(),
+            args: args.iter().map(|s| (*s).to_owned()).collect(),
+        }
+    }
+
+    /// Runs `z3` from the `PATH`
+    pub fn z3() -> Self {
+        Self::new("z3", &["-in", "-smt2"])
+    }
+
+    /// Runs `cvc5` from the `PATH`
+    pub fn cvc5() -> Self {
+        Self::new("cvc5", &["--lang", "smt2", "--produce-models"])
+    }
+}
+
+impl SmtSolver for SmtCommand {
+    fn run(&mut self, script: &str) -> Result<String, Error> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::OtherString(format!("could not run `{}`: {e}", self.program)))?;
+        let mut stdin = child.stdin.take().unwrap();
+        // the solver may start printing before it has read everything
+        let output = std::thread::scope(|s| {
+            let writer = s.spawn(move || stdin.write_all(script.as_bytes()));
+            let output = child.wait_with_output();
+            (writer.join().unwrap(), output)
+        });
+        let output = match output {
+            (Ok(()), Ok(output)) => output,
+            (Err(e), _) | (_, Err(e)) => {
+                return Err(Error::OtherString(format!(
+                    "could not communicate with `{}`: {e}",
+                    self.program
+                )))
+            }
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if stdout.trim().is_empty() && !output.status.success() {
+            return Err(Error::OtherString(format!(
+                "`{}` failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+        Ok(stdout)
+    }
+}
+
+/// The result of running a query from an [SmtCone]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmtResult {
+    /// There is no counterexample, the outputs are equivalent or the
+    /// property holds
+    Unsat,
+    /// A counterexample with the values of the writable `RNode`s (such as
+    /// those of `LazyAwi`s) in the cone, those that the solver did not assign
+    /// are zero
+    Sat(Vec<(PExternal, Awi)>),
+    /// The solver printed something else such as `unknown` or an error, which
+    /// is included
+    Unknown(String),
+}
+
+impl SmtResult {
+    /// Returns if there is no counterexample
+    pub fn is_unsat(&self) -> bool {
+        matches!(self, Self::Unsat)
+    }
+}
+
+/// An SMT-LIB2 encoding of the combinational cones of some read only
+/// `RNode`s in the theory of fixed size bit-vectors (`QF_BV`), from
+/// [Ensemble::smt_cone].
+///
+/// Every equivalence in the cone is a `(_ BitVec 1)` function. Writable
+/// `RNode`s in the cone are declared as constants of their full bitwidth,
+/// and equivalences without a single static `LNode` driver (such as the
+/// outputs of `TNode`s or `BlackBox`es, or where combinational loops are
+/// broken) are declared as free 1 bit constants. Static tables are encoded
+/// as bit-vector literals that are shifted right by the concatenated inputs,
+/// and dynamic tables are concatenations of their entries. Each root is
+/// defined as the concatenation of its bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtCone {
+    definitions: String,
+    inputs: Vec<(PExternal, String, NonZeroUsize)>,
+    outputs: Vec<(PExternal, String, NonZeroUsize)>,
+}
+
+/// Returns `name` as an SMT-LIB2 symbol, quoting it if needed
+fn symbol(name: &str) -> String {
+    let simple = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "~!@$%^&*_-+=<>.?/".contains(c));
+    if simple {
+        name.to_owned()
+    } else {
+        let name: String = name
+            .chars()
+            .map(|c| if (c == '|') || (c == '\\') { '_' } else { c })
+            .collect();
+        format!("|{name}|")
+    }
+}
+
+/// Writes `bits` as a `#b` literal with the most significant bit first
+fn write_literal(res: &mut String, bits: &Bits) {
+    res.push_str("#b");
+    for i in (0..bits.bw()).rev() {
+        res.push(if bits.get(i).unwrap() { '1' } else { '0' });
+    }
+}
+
+/// Writes the concatenation of the 1 bit `terms`, the first being the least
+/// significant
+fn write_concat(res: &mut String, terms: &[String]) {
+    if terms.len() == 1 {
+        res.push_str(&terms[0]);
+    } else {
+        res.push_str("(concat");
+        for term in terms.iter().rev() {
+            write!(res, " {term}").unwrap();
+        }
+        res.push(')');
+    }
+}
+
+/// Writes the bit of a table of `table_bw` bits selected by the 1 bit
+/// `index` terms
+fn write_select(res: &mut String, table: &str, table_bw: usize, index: &[String]) {
+    if index.is_empty() {
+        write!(res, "((_ extract 0 0) {table})").unwrap();
+        return
+    }
+    let mut index_s = String::new();
+    write_concat(&mut index_s, index);
+    // the index is extended to the width of the table
+    let extension = table_bw - index.len();
+    if extension != 0 {
+        index_s = format!("((_ zero_extend {extension}) {index_s})");
+    }
+    write!(res, "((_ extract 0 0) (bvlshr {table} {index_s}))").unwrap();
+}
+
+/// Parses the value of a model entry like `#b0101`, `#x5`, or `(_ bv5 4)`
+fn parse_value(value: &str, nzbw: NonZeroUsize) -> Option<Awi> {
+    let mut res = Awi::zero(nzbw);
+    let set = |res: &mut Awi, digits: &str, radix: u32, digit_bw: usize| {
+        for (i, c) in digits.chars().rev().enumerate() {
+            let digit = c.to_digit(radix)?;
+            for j in 0..digit_bw {
+                if (digit >> j) & 1 == 1 {
+                    res.set((i * digit_bw) + j, true)?;
+                }
+            }
+        }
+        Some(())
+    };
+    if let Some(digits) = value.strip_prefix("#b") {
+        set(&mut res, digits, 2, 1)?;
+    } else if let Some(digits) = value.strip_prefix("#x") {
+        set(&mut res, digits, 16, 4)?;
+    } else {
+        let inner = value.strip_prefix('(')?.strip_suffix(')')?;
+        let mut tokens = inner.split_whitespace();
+        if tokens.next()? != "_" {
+            return None
+        }
+        let decimal = tokens.next()?.strip_prefix("bv")?;
+        for c in decimal.chars() {
+            let digit = c.to_digit(10)?;
+            res.digit_cin_mul_(digit as Digit, 10);
+        }
+    }
+    Some(res)
+}
+
+/// Splits an S-expression into tokens, with parenthesized groups that start
+/// with `_` kept as single tokens
+fn tokenize(s: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => {
+                if chars.peek() == Some(&'_') {
+                    let mut token = String::from("(");
+                    for c in chars.by_ref() {
+                        token.push(c);
+                        if c == ')' {
+                            break
+                        }
+                    }
+                    res.push(token);
+                } else {
+                    res.push("(".to_owned());
+                }
+            }
+            ')' => res.push(")".to_owned()),
+            '|' => {
+                let mut token = String::new();
+                for c in chars.by_ref() {
+                    if c == '|' {
+                        break
+                    }
+                    token.push(c);
+                }
+                res.push(token);
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut token = String::from(c);
+                while let Some(c) = chars.peek() {
+                    if c.is_whitespace() || (*c == '(') || (*c == ')') {
+                        break
+                    }
+                    token.push(*c);
+                    chars.next();
+                }
+                res.push(token);
+            }
+        }
+    }
+    res
+}
+
+impl SmtCone {
+    /// Returns the declarations and definitions of the cone, without any
+    /// assertions or commands
+    pub fn definitions(&self) -> &str {
+        &self.definitions
+    }
+
+    /// Returns the writable `RNode`s in the cone with the names and bitwidths
+    /// of their constants
+    pub fn inputs(&self) -> &[(PExternal, String, NonZeroUsize)] {
+        &self.inputs
+    }
+
+    /// Returns the roots of the cone with the names and bitwidths of their
+    /// definitions
+    pub fn outputs(&self) -> &[(PExternal, String, NonZeroUsize)] {
+        &self.outputs
+    }
+
+    fn output(&self, p_external: PExternal) -> Result<(&str, NonZeroUsize), Error> {
+        self.outputs
+            .iter()
+            .find(|(p, ..)| *p == p_external)
+            .map(|(_, name, nzbw)| (name.as_str(), *nzbw))
+            .ok_or(Error::InvalidPExternal(p_external))
+    }
+
+    fn script(&self, assertion: &str) -> String {
+        let mut res = String::from("(set-option :produce-models true)\n(set-logic QF_BV)\n");
+        res.push_str(&self.definitions);
+        writeln!(res, "(assert {assertion})\n(check-sat)\n(get-model)").unwrap();
+        res
+    }
+
+    /// Returns a script that is satisfiable if the roots `a` and `b` can
+    /// differ
+    ///
+    /// # Errors
+    ///
+    /// If `a` or `b` are not roots of the cone or have different bitwidths
+    pub fn equivalence_query(&self, a: PExternal, b: PExternal) -> Result<String, Error> {
+        let (a, a_w) = self.output(a)?;
+        let (b, b_w) = self.output(b)?;
+        if a_w != b_w {
+            return Err(Error::BitwidthMismatch(a_w.get(), b_w.get()))
+        }
+        Ok(self.script(&format!("(distinct {a} {b})")))
+    }
+
+    /// Returns a script that is satisfiable if the root `property` can have a
+    /// bit that is not set
+    ///
+    /// # Errors
+    ///
+    /// If `property` is not a root of the cone
+    pub fn property_query(&self, property: PExternal) -> Result<String, Error> {
+        let (property, w) = self.output(property)?;
+        let mut ones = String::new();
+        write_literal(&mut ones, &Awi::umax(w));
+        Ok(self.script(&format!("(distinct {property} {ones})")))
+    }
+
+    /// Parses what a solver printed for a script from this cone. The first
+    /// line decides the result, and the values of the inputs are taken from
+    /// the `define-fun`s of the model after it.
+    pub fn parse_result(&self, output: &str) -> SmtResult {
+        let output = output.trim();
+        let (first, rest) = output.split_once('\n').unwrap_or((output, ""));
+        match first.trim() {
+            "unsat" => return SmtResult::Unsat,
+            "sat" => (),
+            _ => return SmtResult::Unknown(output.to_owned()),
+        }
+        let mut values: Vec<(PExternal, Awi)> = self
+            .inputs
+            .iter()
+            .map(|(p_external, _, nzbw)| (*p_external, Awi::zero(*nzbw)))
+            .collect();
+        let tokens = tokenize(rest);
+        for (i, token) in tokens.iter().enumerate() {
+            // (define-fun name () (_ BitVec w) value)
+            if token != "define-fun" {
+                continue
+            }
+            let (Some(name), Some(value)) = (tokens.get(i + 1), tokens.get(i + 5)) else {
+                continue
+            };
+            let name = symbol(name);
+            if let Some(j) = self.inputs.iter().position(|(_, n, _)| *n == name) {
+                match parse_value(value, self.inputs[j].2) {
+                    Some(awi) => values[j].1 = awi,
+                    None => return SmtResult::Unknown(output.to_owned()),
+                }
+            }
+        }
+        SmtResult::Sat(values)
+    }
+
+    /// Runs `script` (from [SmtCone::equivalence_query] or
+    /// [SmtCone::property_query]) with `solver` and parses the result
+    pub fn check<S: SmtSolver>(&self, script: &str, solver: &mut S) -> Result<SmtResult, Error> {
+        let output = solver.run(script)?;
+        Ok(self.parse_result(&output))
+    }
+}
+
+impl Ensemble {
+    /// Encodes the combinational cones of the read only `RNode`s of `roots`
+    /// (such as those of `EvalAwi`s) into an [SmtCone] for external SMT
+    /// solvers. This should be used after lowering.
+    ///
+    /// # Errors
+    ///
+    /// If a root is not a lowered `RNode`
+    pub fn smt_cone(&self, roots: &[PExternal]) -> Result<SmtCone, Error> {
+        // the first writable `RNode` bit of every equivalence
+        let mut input_bits: HashMap<PBack, (usize, usize)> = HashMap::new();
+        let mut inputs = vec![];
+        for (i, (_, p_external, rnode)) in self.notary.rnodes().into_iter().enumerate() {
+            if rnode.read_only() {
+                continue
+            }
+            let Some(bits) = rnode.bits() else { continue };
+            for (j, p_bit) in bits.iter().enumerate() {
+                if let Some(p_bit) = p_bit {
+                    let p_equiv = self.backrefs.get_val(*p_bit).unwrap().p_self_equiv;
+                    input_bits.entry(p_equiv).or_insert((inputs.len(), j));
+                }
+            }
+            let name = match rnode.debug_name() {
+                Some(debug_name) => symbol(&format!("in{i}_{debug_name}")),
+                None => format!("in{i}"),
+            };
+            inputs.push((*p_external, name, rnode.nzbw()));
+        }
+        let mut used_inputs = vec![false; inputs.len()];
+
+        let mut root_bits = vec![];
+        let mut outputs = vec![];
+        for (i, p_external) in roots.iter().copied().enumerate() {
+            let (_, rnode) = self.notary.get_rnode(p_external)?;
+            let Some(bits) = rnode.bits() else {
+                return Err(Error::OtherStr(
+                    "`smt_cone` was called on an `RNode` that has not been lowered",
+                ))
+            };
+            root_bits.push(bits.to_owned());
+            outputs.push((p_external, format!("out{i}"), rnode.nzbw()));
+        }
+
+        // the definitions in topological order
+        let mut body = String::new();
+        let mut names: HashMap<PBack, String> = HashMap::new();
+        let mut on_stack = HashSet::new();
+        let mut stack: Vec<(PBack, bool)> = root_bits
+            .iter()
+            .flatten()
+            .rev()
+            .flatten()
+            .map(|p| (self.backrefs.get_val(*p).unwrap().p_self_equiv, false))
+            .collect();
+        while let Some((p_equiv, expanded)) = stack.pop() {
+            if names.contains_key(&p_equiv) {
+                continue
+            }
+            let name = format!("n{}", names.len());
+            let equiv = self.backrefs.get_val(p_equiv).unwrap();
+            if let Value::Const(b) = equiv.val {
+                writeln!(
+                    body,
+                    "(define-fun {name} () (_ BitVec 1) #b{})",
+                    if b { '1' } else { '0' }
+                )
+                .unwrap();
+                names.insert(p_equiv, name);
+                continue
+            }
+            let p_lnode = self.sole_lnode_driver(p_equiv).filter(|p_lnode| {
+                if let LNodeKind::DynamicLut(_, ref lut) = self.lnodes[*p_lnode].kind {
+                    !lut.iter().any(|x| matches!(x, DynamicValue::ConstUnknown))
+                } else {
+                    true
+                }
+            });
+            let mut expr = None;
+            if let Some(p_lnode) = p_lnode {
+                let inputs = self.lnode_input_equivs(p_lnode);
+                if !expanded {
+                    on_stack.insert(p_equiv);
+                    stack.push((p_equiv, true));
+                    for p_inp in inputs.into_iter().rev() {
+                        if !names.contains_key(&p_inp) && !on_stack.contains(&p_inp) {
+                            stack.push((p_inp, false));
+                        }
+                    }
+                    continue
+                }
+                on_stack.remove(&p_equiv);
+                // inputs that are not defined yet are from combinational loops
+                if inputs.iter().all(|p_inp| names.contains_key(p_inp)) {
+                    let n =
+                        |p: &PBack| names[&self.backrefs.get_val(*p).unwrap().p_self_equiv].clone();
+                    let mut s = String::new();
+                    match self.lnodes[p_lnode].kind {
+                        LNodeKind::Copy(inp) => s.push_str(&n(&inp)),
+                        LNodeKind::Lut(ref inp, ref lut) => {
+                            let mut table = String::new();
+                            write_literal(&mut table, lut);
+                            let index: Vec<String> = inp.iter().map(n).collect();
+                            write_select(&mut s, &table, lut.bw(), &index);
+                        }
+                        LNodeKind::Carry(ref inp) => {
+                            let lut = LNode::carry_lut();
+                            let mut table = String::new();
+                            write_literal(&mut table, &lut);
+                            let index: Vec<String> = inp.iter().map(n).collect();
+                            write_select(&mut s, &table, lut.bw(), &index);
+                        }
+                        LNodeKind::DynamicLut(ref inp, ref lut) => {
+                            let entries: Vec<String> = lut
+                                .iter()
+                                .map(|entry| match entry {
+                                    DynamicValue::Const(b) => {
+                                        format!("#b{}", if *b { '1' } else { '0' })
+                                    }
+                                    DynamicValue::Dynam(p) => n(p),
+                                    DynamicValue::ConstUnknown => unreachable!(),
+                                })
+                                .collect();
+                            let mut table = String::new();
+                            write_concat(&mut table, &entries);
+                            let index: Vec<String> = inp.iter().map(n).collect();
+                            write_select(&mut s, &table, lut.len(), &index);
+                        }
+                    }
+                    expr = Some(s);
+                }
+            }
+            if let Some(expr) = expr {
+                writeln!(body, "(define-fun {name} () (_ BitVec 1) {expr})").unwrap();
+            } else if let Some((i, j)) = input_bits.get(&p_equiv) {
+                used_inputs[*i] = true;
+                writeln!(
+                    body,
+                    "(define-fun {name} () (_ BitVec 1) ((_ extract {j} {j}) {}))",
+                    inputs[*i].1
+                )
+                .unwrap();
+            } else {
+                writeln!(body, "(declare-const {name} (_ BitVec 1))").unwrap();
+            }
+            names.insert(p_equiv, name);
+        }
+
+        let mut definitions = String::new();
+        let mut cone_inputs = vec![];
+        for (input, used) in inputs.into_iter().zip(used_inputs) {
+            if used {
+                writeln!(
+                    definitions,
+                    "(declare-const {} (_ BitVec {}))",
+                    input.1, input.2
+                )
+                .unwrap();
+                cone_inputs.push(input);
+            }
+        }
+        definitions.push_str(&body);
+        for (bits, (_, name, nzbw)) in root_bits.iter().zip(outputs.iter()) {
+            let mut free = 0;
+            let terms: Vec<String> = bits
+                .iter()
+                .map(|p_bit| match p_bit {
+                    Some(p_bit) => {
+                        names[&self.backrefs.get_val(*p_bit).unwrap().p_self_equiv].clone()
+                    }
+                    None => {
+                        // bits that were never lowered can be anything
+                        let term = format!("{name}_free{free}");
+                        writeln!(definitions, "(declare-const {term} (_ BitVec 1))").unwrap();
+                        free += 1;
+                        term
+                    }
+                })
+                .collect();
+            write!(definitions, "(define-fun {name} () (_ BitVec {nzbw}) ").unwrap();
+            write_concat(&mut definitions, &terms);
+            definitions.push_str(")\n");
+        }
+        Ok(SmtCone {
+            definitions,
+            inputs: cone_inputs,
+            outputs,
+        })
+    }
+
+    /// Uses `solver` to check if the read only `RNode`s `a` and `b` are
+    /// equivalent for all values of the writable `RNode`s, with everything
+    /// else in their cones being free, see [Ensemble::smt_cone]
+    ///
+    /// # Errors
+    ///
+    /// If the roots are not lowered `RNode`s with the same bitwidth, or if
+    /// `solver` fails to run
+    pub fn check_equivalence_smt<S: SmtSolver>(
+        &self,
+        a: PExternal,
+        b: PExternal,
+        solver: &mut S,
+    ) -> Result<SmtResult, Error> {
+        let cone = self.smt_cone(&[a, b])?;
+        let script = cone.equivalence_query(a, b)?;
+        cone.check(&script, solver)
+    }
+
+    /// Uses `solver` to check if all the bits of the read only `RNode`
+    /// `property` are set for all values of the writable `RNode`s, with
+    /// everything else in its cone being free, see [Ensemble::smt_cone]
+    ///
+    /// # Errors
+    ///
+    /// If `property` is not a lowered `RNode`, or if `solver` fails to run
+    pub fn check_property_smt<S: SmtSolver>(
+        &self,
+        property: PExternal,
+        solver: &mut S,
+    ) -> Result<SmtResult, Error> {
+        let cone = self.smt_cone(&[property])?;
+        let script = cone.property_query(property)?;
+        cone.check(&script, solver)
+    }
+}
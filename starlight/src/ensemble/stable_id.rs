@@ -0,0 +1,228 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{DynamicValue, Ensemble, LNodeKind, PBack, PExternal, PLNode, Referent, Value},
+    Error,
+};
+
+/// Stable content derived IDs for the equivalences and `LNode`s of an
+/// `Ensemble`, see [Ensemble::stable_ids]
+#[derive(Debug, Clone, Default)]
+pub struct StableIds {
+    equivs: HashMap<PBack, u64>,
+    lnodes: HashMap<PLNode, u64>,
+}
+
+impl StableIds {
+    /// Returns the ID of the equivalence `p_equiv` (which must be a
+    /// `p_self_equiv`)
+    pub fn equiv(&self, p_equiv: PBack) -> Option<u64> {
+        self.equivs.get(&p_equiv).copied()
+    }
+
+    pub fn lnode(&self, p_lnode: PLNode) -> Option<u64> {
+        self.lnodes.get(&p_lnode).copied()
+    }
+
+    /// Returns the number of `LNode`s with each ID
+    pub fn lnode_id_counts(&self) -> HashMap<u64, usize> {
+        let mut res = HashMap::new();
+        for id in self.lnodes.values().copied() {
+            *res.entry(id).or_insert(0) += 1;
+        }
+        res
+    }
+
+    /// Returns the number of equivalences with each ID
+    pub fn equiv_id_counts(&self) -> HashMap<u64, usize> {
+        let mut res = HashMap::new();
+        for id in self.equivs.values().copied() {
+            *res.entry(id).or_insert(0) += 1;
+        }
+        res
+    }
+}
+
+fn hash_of<T: Hash>(t: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    t.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Ensemble {
+    /// Returns the equivalences that the `LNode` at `p_lnode` reads, including
+    /// the dynamic table entries of `LNodeKind::DynamicLut`s
+    pub(crate) fn lnode_input_equivs(&self, p_lnode: PLNode) -> Vec<PBack> {
+        let mut res = vec![];
+        self.lnodes[p_lnode].inputs(|p_inp| {
+            res.push(self.backrefs.get_val(p_inp).unwrap().p_self_equiv);
+        });
+        if let LNodeKind::DynamicLut(_, ref lut) = self.lnodes[p_lnode].kind {
+            for entry in lut {
+                if let DynamicValue::Dynam(p) = entry {
+                    let p_equiv = self.backrefs.get_val(*p).unwrap().p_self_equiv;
+                    if !res.contains(&p_equiv) {
+                        res.push(p_equiv);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Computes the ID of the `LNode` at `p_lnode`, its input IDs must already
+    /// be in `equivs`
+    fn lnode_stable_id(&self, p_lnode: PLNode, equivs: &HashMap<PBack, u64>) -> u64 {
+        let id = |p_inp: &PBack| equivs[&self.backrefs.get_val(*p_inp).unwrap().p_self_equiv];
+        let mut hasher = DefaultHasher::new();
+        match self.lnodes[p_lnode].kind {
+            LNodeKind::Copy(ref inp) => {
+                "copy".hash(&mut hasher);
+                id(inp).hash(&mut hasher);
+            }
+            LNodeKind::Lut(ref inp, ref lut) => {
+                "lut".hash(&mut hasher);
+                for p_inp in inp {
+                    id(p_inp).hash(&mut hasher);
+                }
+                for i in 0..lut.bw() {
+                    lut.get(i).unwrap().hash(&mut hasher);
+                }
+            }
+            LNodeKind::DynamicLut(ref inp, ref lut) => {
+                "dynamic_lut".hash(&mut hasher);
+                for p_inp in inp {
+                    id(p_inp).hash(&mut hasher);
+                }
+                for entry in lut {
+                    match entry {
+                        DynamicValue::ConstUnknown => 0u64.hash(&mut hasher),
+                        DynamicValue::Const(b) => (1u64 + (*b as u64)).hash(&mut hasher),
+                        DynamicValue::Dynam(p) => (3u64, id(p)).hash(&mut hasher),
+                    }
+                }
+            }
+            LNodeKind::Carry(ref inp) => {
+                "carry".hash(&mut hasher);
+                for p_inp in inp {
+                    id(p_inp).hash(&mut hasher);
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Computes stable content derived IDs for all equivalences and `LNode`s.
+    /// The ID of an `LNode` is a hash of its kind, table, and the IDs of its
+    /// inputs. The ID of an equivalence driven by `LNode`s is derived from
+    /// their IDs, equivalences driven by `TNode`s get an ID from the delay
+    /// (which breaks cycles), and other equivalences get an ID from the
+    /// `RNode` bits referencing them or else their constant value. This means
+    /// that structurally identical logic gets the same IDs in different
+    /// `Ensemble`s regardless of arena pointers, so long as the `PExternal`s of
+    /// the `RNode`s are the same (e.g. when comparing an `Ensemble` to a clone
+    /// of itself that has been further optimized). The IDs are only stable
+    /// within builds using the same standard library hasher.
+    ///
+    /// # Errors
+    ///
+    /// If there is a combinational loop of `LNode`s
+    pub fn stable_ids(&self) -> Result<StableIds, Error> {
+        // `RNode` bits that reference each equivalence, for leaves
+        let mut rnode_bits: HashMap<PBack, Vec<(PExternal, usize)>> = HashMap::new();
+        for (_, p_external, rnode) in self.notary.rnodes() {
+            if let Some(bits) = rnode.bits() {
+                for (i, bit) in bits.iter().enumerate() {
+                    if let Some(bit) = bit {
+                        let p_equiv = self.backrefs.get_val(*bit).unwrap().p_self_equiv;
+                        rnode_bits
+                            .entry(p_equiv)
+                            .or_default()
+                            .push((*p_external, i));
+                    }
+                }
+            }
+        }
+        let mut res = StableIds::default();
+        let mut in_progress = HashSet::new();
+        let mut adv = self.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&self.backrefs) {
+            if !matches!(self.backrefs.get_key(p_back).unwrap(), Referent::ThisEquiv) {
+                continue
+            }
+            if res.equivs.contains_key(&p_back) {
+                continue
+            }
+            let mut stack = vec![(p_back, false)];
+            while let Some((p_equiv, expanded)) = stack.pop() {
+                if res.equivs.contains_key(&p_equiv) {
+                    continue
+                }
+                let mut lnodes = vec![];
+                let mut tnode_delays = vec![];
+                let mut adv = self.backrefs.advancer_surject(p_equiv);
+                while let Some(p_ref) = adv.advance(&self.backrefs) {
+                    match *self.backrefs.get_key(p_ref).unwrap() {
+                        Referent::ThisLNode(p_lnode) => lnodes.push(p_lnode),
+                        Referent::ThisTNode(p_tnode) => {
+                            tnode_delays.push(self.tnodes[p_tnode].delay().amount())
+                        }
+                        _ => (),
+                    }
+                }
+                if !expanded {
+                    if !in_progress.insert(p_equiv) {
+                        return Err(Error::OtherString(format!(
+                            "`Ensemble::stable_ids`: found a combinational loop through \
+                             {p_equiv:?}"
+                        )))
+                    }
+                    stack.push((p_equiv, true));
+                    for p_lnode in lnodes.iter().copied() {
+                        for p_inp in self.lnode_input_equivs(p_lnode) {
+                            if !res.equivs.contains_key(&p_inp) {
+                                if in_progress.contains(&p_inp) {
+                                    return Err(Error::OtherString(format!(
+                                        "`Ensemble::stable_ids`: found a combinational loop \
+                                         through {p_inp:?}"
+                                    )))
+                                }
+                                stack.push((p_inp, false));
+                            }
+                        }
+                    }
+                    continue
+                }
+                in_progress.remove(&p_equiv);
+                let mut driver_ids = vec![];
+                for p_lnode in lnodes.iter().copied() {
+                    let id = self.lnode_stable_id(p_lnode, &res.equivs);
+                    res.lnodes.insert(p_lnode, id);
+                    driver_ids.push(id);
+                }
+                for delay in tnode_delays {
+                    driver_ids.push(hash_of(("tnode", delay)));
+                }
+                let id = if !driver_ids.is_empty() {
+                    driver_ids.sort_unstable();
+                    hash_of(("driven", driver_ids))
+                } else if let Some(bits) = rnode_bits.get_mut(&p_equiv) {
+                    bits.sort_unstable();
+                    hash_of(("rnode", &*bits))
+                } else {
+                    match self.backrefs.get_val(p_equiv).unwrap().val {
+                        Value::Const(b) => hash_of(("const", b)),
+                        _ => hash_of("leaf"),
+                    }
+                };
+                res.equivs.insert(p_equiv, id);
+            }
+        }
+        Ok(res)
+    }
+}
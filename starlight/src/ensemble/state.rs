@@ -1,5 +1,7 @@
 use std::{
+    collections::HashMap,
     fmt::Write,
+    mem,
     num::{NonZeroU64, NonZeroUsize},
 };
 
@@ -17,8 +19,13 @@ use awint::{
 use crate::{
     awi,
     awi_structs::{DELAY, DELAYED_LOOP_SOURCE, LOOP_SOURCE, UNDRIVEN_LOOP_SOURCE},
-    ensemble::{ChangeKind, Delay, DynamicValue, Ensemble, Equiv, Event, PBack, Referent, Value},
+    ensemble::{
+        value::stable_hash, ChangeKind, Delay, DynamicValue, Ensemble, Equiv, Event, LutTable,
+        PBack, Referent, Value,
+    },
     epoch::EpochShared,
+    lower::{meta::CARRY, LowerOk},
+    utils::{audit_visit, trace_span},
     Error,
 };
 
@@ -77,10 +84,61 @@ impl State {
     }
 }
 
+/// Literal states with at least this many bits are shared by
+/// [Ensemble::make_state] with any existing literal state of the same value,
+/// so that large tables such as the contents of a [crate::Rom] read by
+/// multiple ports are only stored once. Literals the size of primitives are
+/// cheap enough to keep separate.
+pub const MIN_SHARED_LITERAL_BW: usize = 256;
+
+/// An operand of a `FusionKey`, literals are compared by value because
+/// lowerings create their own literal states. The literals are interned in
+/// `Ensemble::lut_tables` so that keys do not each hold a copy.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum FusionOperand {
+    State(PState),
+    Literal(LutTable),
+}
+
+/// Identifies states that compute the same thing, used for fusing states
+/// during lowering
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct FusionKey {
+    pub nzbw: NonZeroUsize,
+    pub name: &'static str,
+    pub operands: SmallVec<[FusionOperand; 4]>,
+    /// Any parameters that are not operands
+    pub params: SmallVec<[usize; 4]>,
+    pub table: Option<LutTable>,
+}
+
+impl FusionKey {
+    /// Returns the operands that are not literals
+    pub fn states(&self) -> impl Iterator<Item = PState> + '_ {
+        self.operands.iter().filter_map(|op| match op {
+            FusionOperand::State(p_state) => Some(*p_state),
+            FusionOperand::Literal(_) => None,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Stator {
     pub states: Arena<PState, State>,
     pub states_to_lower: Vec<PState>,
+    /// Lowered states that others with the same `FusionKey` can be fused into,
+    /// the operands of the keys have their reference counts incremented
+    pub(crate) fusion_memo: HashMap<FusionKey, PState>,
+    pub(crate) fusion_keys: HashMap<PState, FusionKey>,
+    /// Literal states of at least `MIN_SHARED_LITERAL_BW` bits keyed by the
+    /// hash of their value, see [Ensemble::make_state]
+    pub(crate) shared_literals: HashMap<u64, SmallVec<[PState; 1]>>,
+    /// The states with stored resource limit errors that `make_state` returns
+    /// for each bitwidth while a limit is exceeded
+    pub(crate) limit_states: HashMap<NonZeroUsize, PState>,
+    /// The last state that an error was stored on during lowering, so that
+    /// [Ensemble::lower_best_effort] can attribute errors
+    pub(crate) p_failed: Option<PState>,
 }
 
 impl Stator {
@@ -88,6 +146,11 @@ impl Stator {
         Self {
             states: Arena::new(),
             states_to_lower: vec![],
+            fusion_memo: HashMap::new(),
+            fusion_keys: HashMap::new(),
+            shared_literals: HashMap::new(),
+            limit_states: HashMap::new(),
+            p_failed: None,
         }
     }
 
@@ -99,32 +162,165 @@ impl Stator {
         self.states.clear_and_shrink();
         self.states_to_lower.clear();
         self.states_to_lower.shrink_to_fit();
+        self.fusion_memo = HashMap::new();
+        self.fusion_keys = HashMap::new();
+        self.shared_literals = HashMap::new();
+        self.limit_states = HashMap::new();
+        self.p_failed = None;
         Ok(())
     }
 }
 
 impl Ensemble {
+    /// Creates a new state. Literals of at least `MIN_SHARED_LITERAL_BW` bits
+    /// that are equal to an existing literal state return the existing state
+    /// instead, states are never mutated through their mimicking handles so
+    /// this is unobservable besides the memory savings. While a resource limit
+    /// is exceeded, a single state with the error is shared by all the calls
+    /// with the same bitwidth.
     pub fn make_state(
         &mut self,
         nzbw: NonZeroUsize,
         op: Op<PState>,
         location: Option<Location>,
     ) -> PState {
+        // if a resource limit is exceeded, we cannot return an error here because
+        // this is called from the mimicking callbacks, so the error is stored
+        let recorded_op = (self.replay_log.is_some() || self.journal.is_some()).then(|| op.clone());
+        let (op, err) = self.limit_state_op(op);
+        if err.is_some() {
+            // no more states are allocated while a limit is exceeded
+            if let Some(p_state) = self.limit_state(nzbw) {
+                if let Some(recorded_op) = recorded_op {
+                    self.record_make_state(p_state, nzbw, recorded_op, location, false);
+                }
+                return p_state
+            }
+        }
+        let shared_hash = match op {
+            Literal(ref lit) if err.is_none() && (lit.bw() >= MIN_SHARED_LITERAL_BW) => {
+                let hash = stable_hash(lit);
+                if let Some(p_state) = self.shared_literal(hash, lit) {
+                    if let Some(recorded_op) = recorded_op {
+                        self.record_make_state(p_state, nzbw, recorded_op, location, false);
+                    }
+                    return p_state
+                }
+                Some(hash)
+            }
+            _ => None,
+        };
         for operand in op.operands() {
             let state = self.stator.states.get_mut(*operand).unwrap();
             state.rc = state.rc.checked_add(1).unwrap();
         }
-        self.stator.states.insert(State {
+        let p_state = self.stator.states.insert(State {
             nzbw,
             p_self_bits: SmallVec::new(),
             op,
             location,
-            err: None,
+            err,
             rc: 0,
             extern_rc: 0,
             lowered_to_elementary: false,
             lowered_to_lnodes: false,
-        })
+        });
+        if self.stator.states[p_state].err.is_some() {
+            self.stator.limit_states.insert(nzbw, p_state);
+        }
+        if let Some(hash) = shared_hash {
+            self.stator
+                .shared_literals
+                .entry(hash)
+                .or_default()
+                .push(p_state);
+        }
+        if let Some(recorded_op) = recorded_op {
+            self.record_make_state(p_state, nzbw, recorded_op, location, true);
+        }
+        p_state
+    }
+
+    /// Returns an existing literal state equal to `lit` with the stable hash
+    /// `hash`
+    fn shared_literal(&self, hash: u64, lit: &Awi) -> Option<PState> {
+        self.stator
+            .shared_literals
+            .get(&hash)?
+            .iter()
+            .copied()
+            .find(|p_state| {
+                // the entries are only removed along with their states, but
+                // check anyway in case the `op` was replaced
+                matches!(
+                    self.stator.states.get(*p_state),
+                    Some(State { op: Literal(ref x), err: None, .. }) if x == lit
+                )
+            })
+    }
+
+    /// Removes `p_state` from the shared literals if it is one
+    fn remove_shared_literal(&mut self, p_state: PState, op: &Op<PState>) {
+        if let Literal(ref lit) = op {
+            if lit.bw() >= MIN_SHARED_LITERAL_BW {
+                let hash = stable_hash(lit);
+                if let Some(entries) = self.stator.shared_literals.get_mut(&hash) {
+                    entries.retain(|p| *p != p_state);
+                    if entries.is_empty() {
+                        self.stator.shared_literals.remove(&hash);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the single output bit table for output `bit_i` of the
+    /// `StaticLut` at `p_state`. If there is only one output bit, the table is
+    /// interned instead of copied, so that it shares its allocation with the
+    /// `LNode`s and fusion keys using the same table.
+    fn static_lut_slice(
+        &mut self,
+        p_state: PState,
+        bit_i: usize,
+        out_bw: usize,
+        num_entries: usize,
+    ) -> Result<LutTable, Error> {
+        let StaticLut(_, ref lut) = self.stator.states[p_state].op else {
+            unreachable!()
+        };
+        if out_bw == 1 {
+            Ok(self.lut_tables.intern_bits(lut))
+        } else {
+            let mut val = awi::Awi::zero(NonZeroUsize::new(num_entries).lower_ok()?);
+            for i in 0..num_entries {
+                val.set(i, lut.get((i * out_bw) + bit_i).lower_ok()?)
+                    .lower_ok()?;
+            }
+            Ok(LutTable::new(val))
+        }
+    }
+
+    /// Returns the value of the literal state at `p_state` that is about to
+    /// have its last reference removed. The value is moved out instead of
+    /// copied if nothing else references the state, otherwise the shared
+    /// literal is copied on write. Returns `None` if the state is not a
+    /// literal.
+    pub(crate) fn take_literal(&mut self, p_state: PState) -> Option<Awi> {
+        let state = &self.stator.states[p_state];
+        let Literal(ref lit) = state.op else {
+            return None
+        };
+        if (state.rc == 1) && (state.extern_rc == 0) {
+            let op = mem::replace(&mut self.stator.states[p_state].op, Invalid);
+            self.remove_shared_literal(p_state, &op);
+            if let Literal(lit) = op {
+                Some(lit)
+            } else {
+                unreachable!()
+            }
+        } else {
+            Some(lit.clone())
+        }
     }
 
     /// If `p_state_bits.is_empty`, this will create new equivalences and
@@ -149,7 +345,7 @@ impl Ensemble {
         let mut is_const = false;
         match state.op {
             Op::Literal(ref awi) => {
-                vals.copy_(awi).unwrap();
+                vals.copy_(awi).lower_ok()?;
                 known = true;
                 is_const = true;
             }
@@ -166,32 +362,31 @@ impl Ensemble {
         }
         let mut bits = smallvec![];
         for i in 0..state.nzbw.get() {
-            let p_equiv = self.backrefs.insert_with(|p_self_equiv| {
-                (
-                    Referent::ThisEquiv,
-                    Equiv::new(
-                        p_self_equiv,
-                        if is_const {
-                            if known {
-                                Value::Const(vals.get(i).unwrap())
-                            } else {
-                                Value::ConstUnknown
-                            }
-                        } else if known {
-                            Value::Dynam(vals.get(i).unwrap())
-                        } else {
-                            Value::Unknown
-                        },
-                    ),
-                )
-            });
+            let val = if is_const {
+                if known {
+                    Value::Const(vals.get(i).lower_ok()?)
+                } else {
+                    Value::ConstUnknown
+                }
+            } else if known {
+                Value::Dynam(vals.get(i).lower_ok()?)
+            } else {
+                Value::Unknown
+            };
+            let p_equiv = self
+                .backrefs
+                .insert_with(|p_self_equiv| (Referent::ThisEquiv, Equiv::new(p_self_equiv, val)));
             bits.push(Some(
                 self.backrefs
                     .insert_key(p_equiv, Referent::ThisStateBit(p_state, i))
-                    .unwrap(),
+                    .map_err(|_| Error::InvalidPtr)?,
             ));
         }
-        let state = self.stator.states.get_mut(p_state).unwrap();
+        let state = self
+            .stator
+            .states
+            .get_mut(p_state)
+            .ok_or(Error::InvalidPtr)?;
         state.p_self_bits = bits;
         Ok(())
     }
@@ -211,32 +406,63 @@ impl Ensemble {
                 }
             }
             if delete {
-                for i in 0..self.stator.states[p].op.operands_len() {
-                    let op = self.stator.states[p].op.operands()[i];
-                    if self.stator.states[op].dec_rc().is_none() {
-                        return Err(Error::OtherStr("tried to subtract a 0 reference count"))
-                    };
-                    pstate_stack.push(op);
-                }
-                let mut state = self.stator.states.remove(p).unwrap();
-                for p_self_state in state.p_self_bits.drain(..) {
-                    if let Some(p_self_state) = p_self_state {
-                        self.backrefs.remove_key(p_self_state).unwrap();
-                    }
-                }
+                pstate_stack.extend(self.remove_single_state(p)?);
             }
         }
         Ok(())
     }
 
+    /// Removes `p_state` and decrements the reference counts of its operands
+    /// (and the operands of its fusion key) without pruning them. Returns the
+    /// states whose reference counts were decremented.
+    pub(crate) fn remove_single_state(&mut self, p_state: PState) -> Result<Vec<PState>, Error> {
+        let mut decremented = vec![];
+        for i in 0..self.stator.states[p_state].op.operands_len() {
+            let op = self.stator.states[p_state].op.operands()[i];
+            if self.stator.states[op].dec_rc().is_none() {
+                return Err(Error::OtherStr("tried to subtract a 0 reference count"))
+            };
+            decremented.push(op);
+        }
+        let mut state = self
+            .stator
+            .states
+            .remove(p_state)
+            .ok_or(Error::InvalidPtr)?;
+        self.remove_shared_literal(p_state, &state.op);
+        if let Some(key) = self.stator.fusion_keys.remove(&p_state) {
+            self.stator.fusion_memo.remove(&key);
+            // the memo keeps the operands of the key alive
+            for op in key.states() {
+                if self.stator.states[op].dec_rc().is_none() {
+                    return Err(Error::OtherStr("tried to subtract a 0 reference count"))
+                };
+                decremented.push(op);
+            }
+        }
+        for p_self_state in state.p_self_bits.drain(..) {
+            if let Some(p_self_state) = p_self_state {
+                self.backrefs
+                    .remove_key(p_self_state)
+                    .ok_or(Error::InvalidPtr)?;
+            }
+        }
+        Ok(decremented)
+    }
+
     pub fn force_remove_all_states(&mut self) -> Result<(), Error> {
         // set associated states to none to help prevent issues when there are no
         // generation counters
         self.remove_all_rnode_associated_states();
+        self.stator.fusion_memo.clear();
+        self.stator.fusion_keys.clear();
+        self.stator.shared_literals.clear();
         for (_, mut state) in self.stator.states.drain() {
             for p_self_state in state.p_self_bits.drain(..) {
                 if let Some(p_self_state) = p_self_state {
-                    self.backrefs.remove_key(p_self_state).unwrap();
+                    self.backrefs
+                        .remove_key(p_self_state)
+                        .ok_or(Error::InvalidPtr)?;
                 }
             }
         }
@@ -281,10 +507,12 @@ impl Ensemble {
 
     /// Prunes all states with `pruning_allowed()`
     pub fn prune_unused_states(&mut self) -> Result<(), Error> {
+        let prunable = self.prunable_states();
         let mut adv = self.stator.states.advancer();
         while let Some(p_state) = adv.advance(&self.stator.states) {
-            self.remove_state_if_pruning_allowed(p_state).unwrap();
+            self.remove_state_if_pruning_allowed(p_state)?;
         }
+        self.record_pruned_states(prunable);
         Ok(())
     }
 
@@ -306,7 +534,7 @@ impl Ensemble {
                 let len = state.op.operands_len();
                 for i in 0..len {
                     let source = self.stator.states[p_state].op.operands()[i];
-                    self.state_dec_rc(source).unwrap();
+                    self.state_dec_rc(source)?;
                 }
                 // if the `op` is manually replaced outside of the specially handled lowering
                 // `Copy` replacements, we need to check the values or else this change could be
@@ -315,15 +543,17 @@ impl Ensemble {
                     debug_assert_eq!(self.stator.states[p_state].p_self_bits.len(), x.bw());
                     for i in 0..x.bw() {
                         if let Some(p_bit) = self.stator.states[p_state].p_self_bits[i] {
-                            let p_equiv = self.backrefs.get_val(p_bit).unwrap().p_self_equiv;
-                            // unwrap because this should never fail, events would process
-                            // incorrectly
+                            let p_equiv = self
+                                .backrefs
+                                .get_val(p_bit)
+                                .ok_or(Error::InvalidPtr)?
+                                .p_self_equiv;
+                            // this should never fail, events would process incorrectly
                             self.change_value(
                                 p_equiv,
-                                Value::Const(x.get(i).unwrap()),
-                                NonZeroU64::new(1).unwrap(),
-                            )
-                            .unwrap();
+                                Value::Const(x.get(i).lower_ok()?),
+                                NonZeroU64::MIN,
+                            )?;
                         }
                     }
                 }
@@ -334,7 +564,8 @@ impl Ensemble {
                 let operands = state.op.operands();
                 let mut s = String::new();
                 for op in operands {
-                    writeln!(s, "{:#?},", self.stator.states[op]).unwrap();
+                    // writing to a `String` cannot fail
+                    let _ = writeln!(s, "{:#?},", self.stator.states[op]);
                 }
                 Err(Error::OtherString(format!(
                     "`EvalResult::Noop` evaluation failure on state {} {:#?}\narguments: (\n{})",
@@ -346,9 +577,8 @@ impl Ensemble {
                 if let Assert([_]) = state.op {
                     // this can be done because `Assert` is a sink that should not be used by
                     // anything
-                    let state = self.stator.states.get_mut(p_state).unwrap();
-                    debug_assert_eq!(state.rc, 0);
-                    self.remove_state_if_pruning_allowed(p_state).unwrap();
+                    debug_assert_eq!(self.stator.states[p_state].rc, 0);
+                    self.remove_state_if_pruning_allowed(p_state)?;
                     Ok(())
                 } else {
                     unreachable!()
@@ -362,7 +592,7 @@ impl Ensemble {
                 let operands = state.op.operands();
                 let mut s = String::new();
                 for op in operands {
-                    writeln!(s, "{:?},", self.stator.states[op]).unwrap();
+                    let _ = writeln!(s, "{:?},", self.stator.states[op]);
                 }
                 Err(Error::OtherString(format!(
                     "`EvalResult::Error` evaluation failure (\n{:#?}\n) on state {} \
@@ -373,6 +603,25 @@ impl Ensemble {
         }
     }
 
+    /// Stores `err` on the state `p_state` and remembers it as the last state
+    /// that failed
+    pub(crate) fn set_state_err(&mut self, p_state: PState, err: Error) {
+        if let Some(state) = self.stator.states.get_mut(p_state) {
+            state.err = Some(err);
+            self.stator.p_failed = Some(p_state);
+        }
+    }
+
+    /// Returns the equivalence of the `i`th bit of `p_state`, or an error if
+    /// the state or its bit no longer exist
+    fn state_bit(&self, p_state: PState, i: usize) -> Result<PBack, Error> {
+        self.stator
+            .states
+            .get(p_state)
+            .and_then(|state| state.p_self_bits.get(i).copied().flatten())
+            .ok_or(Error::InvalidPtr)
+    }
+
     /// Assuming that the rootward tree from `p_state` is lowered down to the
     /// elementary `Op`s, this will create the `LNode` network
     pub fn dfs_lower_elementary_to_lnodes(&mut self, p_state: PState) -> Result<(), Error> {
@@ -385,6 +634,23 @@ impl Ensemble {
         }
         self.stator.states[p_state].lowered_to_lnodes = true;
         let mut path: Vec<(usize, PState)> = vec![(0, p_state)];
+        match self.dfs_lower_elementary_to_lnodes_path(&mut path) {
+            Ok(()) => Ok(()),
+            Err(e @ (Error::ResourceLimitExceeded(..) | Error::PtrCapacityExceeded(..))) => Err(e),
+            Err(e) => {
+                // attribute the error to the state that failed
+                if let Some(&(_, p_failed)) = path.last() {
+                    self.set_state_err(p_failed, e.clone());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn dfs_lower_elementary_to_lnodes_path(
+        &mut self,
+        path: &mut Vec<(usize, PState)>,
+    ) -> Result<(), Error> {
         loop {
             let (i, p_state) = path[path.len() - 1];
             let state = &self.stator.states[p_state];
@@ -427,15 +693,17 @@ impl Ensemble {
                     }
                     ref op => return Err(Error::OtherString(format!("cannot lower {op:?}"))),
                 }
-                path.pop().unwrap();
-                if path.is_empty() {
+                path.pop();
+                if let Some(last) = path.last_mut() {
+                    last.0 += 1;
+                } else {
                     break
                 }
-                path.last_mut().unwrap().0 += 1;
             } else if i >= ops.len() {
                 // checked all sources
+                self.check_limits()?;
                 lower_elementary_to_lnodes_intermediate(self, p_state)?;
-                path.pop().unwrap();
+                path.pop();
                 if path.is_empty() {
                     break
                 }
@@ -447,7 +715,9 @@ impl Ensemble {
                     // of `lower_elementary_to_lnodes_intermediate` could fail
                     self.initialize_state_bits_if_needed(p_next)?;
                     // do not visit
-                    path.last_mut().unwrap().0 += 1;
+                    if let Some(last) = path.last_mut() {
+                        last.0 += 1;
+                    }
                 } else {
                     self.stator.states[p_next].lowered_to_lnodes = true;
                     path.push((0, p_next));
@@ -459,8 +729,13 @@ impl Ensemble {
 
     /// Lowers the rootward tree from `p_state` down to `LNode`s
     pub fn dfs_lower(epoch_shared: &EpochShared, p_state: PState) -> Result<(), Error> {
-        Ensemble::dfs_lower_states_to_elementary(epoch_shared, p_state)?;
+        // the states created during lowering are part of the lowering and not
+        // separate journal entries
+        let journal = epoch_shared.epoch_data.borrow_mut().ensemble.journal.take();
+        let res = Ensemble::dfs_lower_states_to_elementary(epoch_shared, p_state);
         let mut lock = epoch_shared.epoch_data.borrow_mut();
+        lock.ensemble.journal = journal;
+        res?;
         // the state can get removed by the above step
         if lock.ensemble.stator.states.contains(p_state) {
             lock.ensemble.dfs_lower_elementary_to_lnodes(p_state)
@@ -471,15 +746,23 @@ impl Ensemble {
 
     /// Lowers `RNode`s with the `lower_before_pruning` flag
     pub fn lower_for_rnodes(epoch_shared: &EpochShared) -> Result<(), Error> {
+        let span = trace_span("lower", "Ensemble::lower_for_rnodes");
         let lock = epoch_shared.epoch_data.borrow();
         let mut adv = lock.ensemble.notary.rnodes().advancer();
         drop(lock);
         loop {
             let mut lock = epoch_shared.epoch_data.borrow_mut();
             if let Some(p_rnode) = adv.advance(lock.ensemble.notary.rnodes()) {
+                audit_visit("Ensemble::lower_for_rnodes", p_rnode);
                 // only lower state trees attached to rnodes that need lowering
-                let rnode = lock.ensemble.notary.rnodes.get_val_mut(p_rnode).unwrap();
+                let rnode = lock
+                    .ensemble
+                    .notary
+                    .rnodes
+                    .get_val_mut(p_rnode)
+                    .ok_or(Error::InvalidPtr)?;
                 if rnode.lower_before_pruning {
+                    span.count("rnodes_lowered", 1);
                     drop(lock);
                     Ensemble::initialize_rnode_if_needed(epoch_shared, p_rnode, true)?;
                 } else {
@@ -496,6 +779,8 @@ impl Ensemble {
     }
 
     pub fn handle_states_to_lower(epoch_shared: &EpochShared) -> Result<(), Error> {
+        let span = trace_span("lower", "Ensemble::handle_states_to_lower");
+        let lnodes_before = epoch_shared.epoch_data.borrow().ensemble.lnodes.len();
         // empty `states_to_lower`
         loop {
             let mut lock = epoch_shared.epoch_data.borrow_mut();
@@ -504,6 +789,7 @@ impl Ensemble {
                     // first check that it has not already been lowered
                     if !state.lowered_to_lnodes {
                         drop(lock);
+                        span.count("states_lowered", 1);
                         Ensemble::dfs_lower(epoch_shared, p_state)?;
                     }
                 }
@@ -511,10 +797,23 @@ impl Ensemble {
                 break
             }
         }
+        let lnodes = epoch_shared.epoch_data.borrow().ensemble.lnodes.len();
+        span.count(
+            "lnodes_created",
+            u64::try_from(lnodes.saturating_sub(lnodes_before)).unwrap_or(u64::MAX),
+        );
         Ok(())
     }
 }
 
+/// Returns the number of entries of a LUT with `inx_len` input bits
+fn lut_num_entries(inx_len: usize) -> Result<usize, Error> {
+    u32::try_from(inx_len)
+        .ok()
+        .and_then(|inx_len| 1usize.checked_shl(inx_len))
+        .ok_or(Error::OtherStr("a LUT has too many inputs to be lowered"))
+}
+
 fn lower_elementary_to_lnodes_intermediate(
     this: &mut Ensemble,
     p_state: PState,
@@ -527,9 +826,9 @@ fn lower_elementary_to_lnodes_intermediate(
             let len = this.stator.states[p_state].p_self_bits.len();
             debug_assert_eq!(len, this.stator.states[x].p_self_bits.len());
             for i in 0..len {
-                let p_equiv0 = this.stator.states[p_state].p_self_bits[i].unwrap();
-                let p_equiv1 = this.stator.states[x].p_self_bits[i].unwrap();
-                this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                let p_equiv0 = this.state_bit(p_state, i)?;
+                let p_equiv1 = this.state_bit(x, i)?;
+                this.union_equiv(p_equiv0, p_equiv1)?;
             }
         }
         Copy([x]) => {
@@ -538,19 +837,18 @@ fn lower_elementary_to_lnodes_intermediate(
             let len = this.stator.states[p_state].p_self_bits.len();
             debug_assert_eq!(len, this.stator.states[x].p_self_bits.len());
             for i in 0..len {
-                let p_equiv0 = this.stator.states[p_state].p_self_bits[i].unwrap();
-                let p_equiv1 = this.stator.states[x].p_self_bits[i].unwrap();
-                this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                let p_equiv0 = this.state_bit(p_state, i)?;
+                let p_equiv1 = this.state_bit(x, i)?;
+                this.union_equiv(p_equiv0, p_equiv1)?;
             }
         }
         StaticGet([bits], inx) => {
             let len = this.stator.states[bits].p_self_bits.len();
             debug_assert!(inx < len);
-            let p_self_bits = &this.stator.states[p_state].p_self_bits;
-            debug_assert_eq!(p_self_bits.len(), 1);
-            let p_equiv0 = p_self_bits[0].unwrap();
-            let p_equiv1 = this.stator.states[bits].p_self_bits[inx].unwrap();
-            this.union_equiv(p_equiv0, p_equiv1).unwrap();
+            debug_assert_eq!(this.stator.states[p_state].p_self_bits.len(), 1);
+            let p_equiv0 = this.state_bit(p_state, 0)?;
+            let p_equiv1 = this.state_bit(bits, inx)?;
+            this.union_equiv(p_equiv0, p_equiv1)?;
         }
         Concat(ref concat) => {
             let concat_len = concat.len();
@@ -564,9 +862,9 @@ fn lower_elementary_to_lnodes_intermediate(
                 };
                 let len = this.stator.states[c].p_self_bits.len();
                 for i in 0..len {
-                    let p_equiv0 = this.stator.states[p_state].p_self_bits[to + i].unwrap();
-                    let p_equiv1 = this.stator.states[c].p_self_bits[i].unwrap();
-                    this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                    let p_equiv0 = this.state_bit(p_state, to + i)?;
+                    let p_equiv1 = this.state_bit(c, i)?;
+                    this.union_equiv(p_equiv0, p_equiv1)?;
                 }
                 to += len;
             }
@@ -585,9 +883,9 @@ fn lower_elementary_to_lnodes_intermediate(
                     };
                 let len = width.get();
                 for i in 0..len {
-                    let p_equiv0 = this.stator.states[p_state].p_self_bits[to + i].unwrap();
-                    let p_equiv1 = this.stator.states[c].p_self_bits[from + i].unwrap();
-                    this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                    let p_equiv0 = this.state_bit(p_state, to + i)?;
+                    let p_equiv1 = this.state_bit(c, from + i)?;
+                    this.union_equiv(p_equiv0, p_equiv1)?;
                 }
                 to += len;
             }
@@ -601,15 +899,18 @@ fn lower_elementary_to_lnodes_intermediate(
                 if from >= x_w {
                     from = 0;
                 }
-                let p_equiv0 = this.stator.states[p_state].p_self_bits[to].unwrap();
-                let p_equiv1 = this.stator.states[x].p_self_bits[from].unwrap();
-                this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                let p_equiv0 = this.state_bit(p_state, to)?;
+                let p_equiv1 = this.state_bit(x, from)?;
+                this.union_equiv(p_equiv0, p_equiv1)?;
                 from += 1;
             }
         }
         StaticLut(ref concat, ref lut) => {
-            let lut = lut.clone();
+            // the table can be large, so only the slices for each output bit are
+            // materialized instead of cloning it
+            let lut_w = lut.bw();
             let concat_len = concat.len();
+            let chunk_inputs = this.lut_chunk_inputs()?;
             let mut inx_bits: SmallVec<[Option<PBack>; 8]> = smallvec![];
             for c_i in 0..concat_len {
                 let c = if let StaticLut(ref concat, _) = this.stator.states[p_state].op {
@@ -623,29 +924,28 @@ fn lower_elementary_to_lnodes_intermediate(
 
             let inx_len = inx_bits.len();
             let out_bw = this.stator.states[p_state].p_self_bits.len();
-            let num_entries = 1usize.checked_shl(u32::try_from(inx_len).unwrap()).unwrap();
+            let num_entries = lut_num_entries(inx_len)?;
             // this must be handled upstream
-            debug_assert_eq!(out_bw * num_entries, lut.bw());
+            debug_assert_eq!(out_bw * num_entries, lut_w);
             // convert from multiple out to single out bit lut
             for bit_i in 0..out_bw {
-                let single_bit_lut = if out_bw == 1 {
-                    lut.clone()
-                } else {
-                    let mut val = awi::Awi::zero(NonZeroUsize::new(num_entries).unwrap());
-                    for i in 0..num_entries {
-                        val.set(i, lut.get((i * out_bw) + bit_i).unwrap()).unwrap();
-                    }
-                    val
-                };
-                let p_equiv0 = this.make_lut(&inx_bits, &single_bit_lut, Some(p_state));
-                let p_equiv1 = this.stator.states[p_state].p_self_bits[bit_i].unwrap();
-                this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                let single_bit_lut = this.static_lut_slice(p_state, bit_i, out_bw, num_entries)?;
+                let p_equiv0 = this.make_decomposed_lut(
+                    &inx_bits,
+                    &single_bit_lut,
+                    chunk_inputs,
+                    this.limits.lut_decomposition,
+                    Some(p_state),
+                );
+                let p_equiv1 = this.state_bit(p_state, bit_i)?;
+                this.union_equiv(p_equiv0, p_equiv1)?;
             }
         }
         Lut([lut, inx]) => {
+            let chunk_inputs = this.lut_chunk_inputs()?;
             let inx_len = this.stator.states[inx].p_self_bits.len();
             let out_bw = this.stator.states[p_state].p_self_bits.len();
-            let num_entries = 1usize.checked_shl(u32::try_from(inx_len).unwrap()).unwrap();
+            let num_entries = lut_num_entries(inx_len)?;
             // this must be handled upstream
             debug_assert_eq!(
                 out_bw * num_entries,
@@ -664,9 +964,14 @@ fn lower_elementary_to_lnodes_intermediate(
                         p_lut_bits.push(DynamicValue::ConstUnknown);
                     }
                 }
-                let p_equiv0 = this.make_dynamic_lut(&inx_bits, &p_lut_bits, Some(p_state));
-                let p_equiv1 = this.stator.states[p_state].p_self_bits[bit_i].unwrap();
-                this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                let p_equiv0 = this.make_chunked_dynamic_lut(
+                    &inx_bits,
+                    &p_lut_bits,
+                    chunk_inputs,
+                    Some(p_state),
+                );
+                let p_equiv1 = this.state_bit(p_state, bit_i)?;
+                this.union_equiv(p_equiv0, p_equiv1)?;
             }
         }
         Mux([lhs, rhs, b]) => {
@@ -678,15 +983,15 @@ fn lower_elementary_to_lnodes_intermediate(
             let inx_bit = inx_bit[0];
 
             for bit_i in 0..out_bw {
-                let lut0 = this.stator.states[lhs].p_self_bits[bit_i].unwrap();
-                let lut1 = this.stator.states[rhs].p_self_bits[bit_i].unwrap();
+                let lut0 = this.state_bit(lhs, bit_i)?;
+                let lut1 = this.state_bit(rhs, bit_i)?;
                 let p_equiv0 = this.make_dynamic_lut(
                     &[inx_bit],
                     &[DynamicValue::Dynam(lut0), DynamicValue::Dynam(lut1)],
                     Some(p_state),
                 );
-                let p_equiv1 = this.stator.states[p_state].p_self_bits[bit_i].unwrap();
-                this.union_equiv(p_equiv0, p_equiv1).unwrap();
+                let p_equiv1 = this.state_bit(p_state, bit_i)?;
+                this.union_equiv(p_equiv0, p_equiv1)?;
             }
         }
         Opaque(ref v, name) => {
@@ -721,8 +1026,7 @@ fn lower_elementary_to_lnodes_intermediate(
                                 ))
                             };
                         for i in 0..w {
-                            let p_driver =
-                                this.stator.states[p_driver_state].p_self_bits[i].unwrap();
+                            let p_driver = this.state_bit(p_driver_state, i)?;
                             // We could potentially set the initial value to the initial value of
                             // the driver, but I suspect that unlike the `LazyAwi` driving case,
                             // this is fundamentally an ill defined issue when zero delay loops are
@@ -732,13 +1036,17 @@ fn lower_elementary_to_lnodes_intermediate(
 
                             // however we do want the initial value to detect immediate quiescence
                             // when the driver is already `Unknown`
-                            let init_val = this.backrefs.get_val(p_driver).unwrap().val;
-                            let p_source = this.stator.states[p_state].p_self_bits[i].unwrap();
+                            let init_val = this
+                                .backrefs
+                                .get_val(p_driver)
+                                .ok_or(Error::InvalidPtr)?
+                                .val;
+                            let p_source = this.state_bit(p_state, i)?;
 
                             let p_tnode = this.make_tnode(p_source, p_driver, delay);
                             if init_val != Value::Unknown {
                                 // setup the delayed drive
-                                this.eval_tnode(p_tnode).unwrap();
+                                this.eval_tnode(p_tnode)?;
                             }
                         }
                     }
@@ -773,12 +1081,14 @@ fn lower_elementary_to_lnodes_intermediate(
                             ))
                         }
                         for i in 0..w {
-                            let p_looper = this.stator.states[p_state].p_self_bits[i].unwrap();
-                            let p_driver =
-                                this.stator.states[p_driver_state].p_self_bits[i].unwrap();
-                            let p_initial =
-                                this.stator.states[p_initial_state].p_self_bits[i].unwrap();
-                            let init_val = this.backrefs.get_val(p_initial).unwrap().val;
+                            let p_looper = this.state_bit(p_state, i)?;
+                            let p_driver = this.state_bit(p_driver_state, i)?;
+                            let p_initial = this.state_bit(p_initial_state, i)?;
+                            let init_val = this
+                                .backrefs
+                                .get_val(p_initial)
+                                .ok_or(Error::InvalidPtr)?
+                                .val;
                             // the loop source is an internal `Opaque` root at this point, we
                             // initiate the initial event chain ourselves.
 
@@ -809,12 +1119,14 @@ fn lower_elementary_to_lnodes_intermediate(
                                     ));
                                 }
                             };
+                            this.tnodes[p_tnode].initial = init_val.known_value();
                             // initial event for the initial value, need to do this in general
                             // because the state bit can get optimized away before we actually use
-                            // it
-                            let p_back = this.backrefs.get_val(p_looper).unwrap().p_self_equiv;
+                            // it. The `TNode`'s own reference is used because the equivalence can
+                            // be unioned away before the event is handled.
+                            let p_back = this.tnodes[p_tnode].p_self;
                             this.evaluator.push_event(Event {
-                                partial_ord_num: NonZeroU64::new(1).unwrap(),
+                                partial_ord_num: NonZeroU64::MIN,
                                 change_kind: ChangeKind::Manual(p_back, init_val),
                             });
                         }
@@ -864,17 +1176,19 @@ fn lower_elementary_to_lnodes_intermediate(
                             return Err(Error::OtherStr("delayed loop source delay amount is zero"))
                         }
                         for i in 0..w {
-                            let p_looper = this.stator.states[p_state].p_self_bits[i].unwrap();
-                            let p_driver =
-                                this.stator.states[p_driver_state].p_self_bits[i].unwrap();
-                            let p_initial =
-                                this.stator.states[p_initial_state].p_self_bits[i].unwrap();
-                            let init_val = this.backrefs.get_val(p_initial).unwrap().val;
+                            let p_looper = this.state_bit(p_state, i)?;
+                            let p_driver = this.state_bit(p_driver_state, i)?;
+                            let p_initial = this.state_bit(p_initial_state, i)?;
+                            let init_val = this
+                                .backrefs
+                                .get_val(p_initial)
+                                .ok_or(Error::InvalidPtr)?
+                                .val;
 
                             let p_tnode = this.make_tnode(p_looper, p_driver, delay);
                             if !delay.is_zero() {
                                 // immediately setup an event
-                                this.eval_tnode(p_tnode).unwrap();
+                                this.eval_tnode(p_tnode)?;
                             } else {
                                 // least priority event for the reason specified in the `LoopSource`
                                 // case
@@ -896,13 +1210,31 @@ fn lower_elementary_to_lnodes_intermediate(
                                     ));
                                 }
                             };
-                            let p_back = this.backrefs.get_val(p_looper).unwrap().p_self_equiv;
+                            this.tnodes[p_tnode].initial = init_val.known_value();
+                            let p_back = this.tnodes[p_tnode].p_self;
                             this.evaluator.push_event(Event {
-                                partial_ord_num: NonZeroU64::new(1).unwrap(),
+                                partial_ord_num: NonZeroU64::MIN,
                                 change_kind: ChangeKind::Manual(p_back, init_val),
                             });
                         }
                     }
+                    CARRY => {
+                        if (v.len() != 3)
+                            || v.iter()
+                                .any(|p| this.stator.states[*p].p_self_bits.len() != 1)
+                        {
+                            return Err(Error::OtherStr(
+                                "carry does not have three single bit arguments",
+                            ))
+                        }
+                        let mut inx = [PBack::default(); 3];
+                        for (i, p) in v.iter().enumerate() {
+                            inx[i] = this.state_bit(*p, 0)?;
+                        }
+                        let p_equiv0 = this.make_carry(inx, Some(p_state));
+                        let p_equiv1 = this.state_bit(p_state, 0)?;
+                        this.union_equiv(p_equiv0, p_equiv1)?;
+                    }
                     _ => {
                         return Err(Error::OtherString(format!(
                             "cannot lower opaque with name {name:?}"
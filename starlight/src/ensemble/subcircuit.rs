@@ -0,0 +1,708 @@
+use std::collections::{HashMap, HashSet};
+
+use awint::{awint_dag::triple_arena::Advancer, Awi};
+
+use crate::{
+    ensemble::{
+        optimize::Optimization, Ensemble, Equiv, LNode, LNodeKind, PBack, PExternal, PLNode,
+        Referent, Value,
+    },
+    utils::StarRng,
+    Error,
+};
+
+/// The maximum number of pattern input bits for which
+/// [Ensemble::replace_subcircuits] checks the replacement exhaustively,
+/// beyond this random vectors are used
+const MAX_EXHAUSTIVE_REPLACEMENT_INPUTS: usize = 16;
+
+/// An instance of a pattern found by [Ensemble::find_subcircuits]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// Pairs of a pattern `LNode` and the `LNode` of `self` that it matched,
+    /// one for every `LNode` of the pattern
+    pub lnodes: Vec<(PLNode, PLNode)>,
+    /// For every bit of the writable pattern `RNode`s (the pattern inputs)
+    /// that is used by the pattern, the `PExternal` and bit index of the
+    /// pattern `RNode` and the equivalence of `self` that it corresponds to
+    pub inputs: Vec<(PExternal, usize, PBack)>,
+    /// Like `inputs` but for the read only pattern `RNode`s (the pattern
+    /// outputs)
+    pub outputs: Vec<(PExternal, usize, PBack)>,
+}
+
+/// Returns the table and inputs of an `LNode` for matching, a `Carry` is
+/// treated as its equivalent `Lut` so that they can match each other
+fn lnode_function(lnode: &LNode) -> Option<(Vec<PBack>, Awi)> {
+    match &lnode.kind {
+        LNodeKind::Copy(inp) => Some((vec![*inp], {
+            use awint::awi::*;
+            awi!(10)
+        })),
+        LNodeKind::Lut(inp, table) => Some((inp.to_vec(), Awi::from_bits(table))),
+        LNodeKind::Carry(inp) => Some((inp.to_vec(), LNode::carry_lut())),
+        LNodeKind::DynamicLut(..) => None,
+    }
+}
+
+/// Returns all the permutations of `0..k`
+fn permutations(k: usize) -> Vec<Vec<usize>> {
+    let mut res = vec![];
+    let mut perm: Vec<usize> = (0..k).collect();
+    // Heap's algorithm
+    let mut c = vec![0; k];
+    res.push(perm.clone());
+    let mut i = 0;
+    while i < k {
+        if c[i] < i {
+            if (i % 2) == 0 {
+                perm.swap(0, i);
+            } else {
+                perm.swap(c[i], i);
+            }
+            res.push(perm.clone());
+            c[i] += 1;
+            i = 0;
+        } else {
+            c[i] = 0;
+            i += 1;
+        }
+    }
+    res
+}
+
+/// Returns if `pattern` with input `i` connected to input `perm[i]` of `host`
+/// is the same function as `host`
+fn tables_match(pattern: &Awi, host: &Awi, perm: &[usize]) -> bool {
+    if pattern.bw() != host.bw() {
+        return false
+    }
+    for x in 0..pattern.bw() {
+        let mut y = 0;
+        for (i, j) in perm.iter().enumerate() {
+            if (x & (1 << i)) != 0 {
+                y |= 1 << j;
+            }
+        }
+        if pattern.get(x).unwrap() != host.get(y).unwrap() {
+            return false
+        }
+    }
+    true
+}
+
+struct SubcircuitSearch<'a> {
+    host: &'a Ensemble,
+    pattern: &'a Ensemble,
+    /// the pattern `LNode`s in the order that they are matched, with their
+    /// functions
+    order: Vec<(PLNode, PBack, Vec<PBack>, Awi)>,
+    /// bound pattern equivalences and their host equivalences
+    equivs: HashMap<PBack, PBack>,
+    host_equivs: HashMap<PBack, PBack>,
+    trail: Vec<PBack>,
+    lnodes: Vec<(PLNode, PLNode)>,
+    used: HashSet<PLNode>,
+    found: Vec<Match>,
+    found_sets: HashSet<Vec<PLNode>>,
+    permutations: HashMap<usize, Vec<Vec<usize>>>,
+}
+
+impl SubcircuitSearch<'_> {
+    fn bind(&mut self, pattern_equiv: PBack, host_equiv: PBack) -> bool {
+        if let Some(bound) = self.equivs.get(&pattern_equiv) {
+            return *bound == host_equiv
+        }
+        if self.host_equivs.contains_key(&host_equiv) {
+            return false
+        }
+        // constants in the pattern must be the same constants
+        let pattern_val = self.pattern.backrefs.get_val(pattern_equiv).unwrap().val;
+        if pattern_val.is_const() {
+            let host_val = self.host.backrefs.get_val(host_equiv).unwrap().val;
+            if !host_val.is_const() || (host_val.known_value() != pattern_val.known_value()) {
+                return false
+            }
+        }
+        self.equivs.insert(pattern_equiv, host_equiv);
+        self.host_equivs.insert(host_equiv, pattern_equiv);
+        self.trail.push(pattern_equiv);
+        true
+    }
+
+    fn unbind_to(&mut self, len: usize) {
+        while self.trail.len() > len {
+            let pattern_equiv = self.trail.pop().unwrap();
+            let host_equiv = self.equivs.remove(&pattern_equiv).unwrap();
+            self.host_equivs.remove(&host_equiv);
+        }
+    }
+
+    fn host_equiv(&self, p_back: PBack) -> PBack {
+        self.host.backrefs.get_val(p_back).unwrap().p_self_equiv
+    }
+
+    /// Returns the host `LNode`s that could match the `i`th pattern `LNode`
+    fn candidates(&self, i: usize) -> Vec<PLNode> {
+        let (_, p_self, ref inputs, _) = self.order[i];
+        let mut res = vec![];
+        if let Some(host_equiv) = self.equivs.get(&p_self) {
+            self.host.lnodes_driving(*host_equiv, &mut res);
+        } else if let Some(host_equiv) = inputs.iter().find_map(|p| self.equivs.get(p)) {
+            let mut adv = self.host.backrefs.advancer_surject(*host_equiv);
+            while let Some(p_back) = adv.advance(&self.host.backrefs) {
+                if let Referent::Input(p_lnode) = *self.host.backrefs.get_key(p_back).unwrap() {
+                    if !res.contains(&p_lnode) {
+                        res.push(p_lnode);
+                    }
+                }
+            }
+        } else {
+            res.extend(self.host.lnodes.ptrs());
+        }
+        res
+    }
+
+    fn search(&mut self, i: usize) {
+        if i == self.order.len() {
+            self.record();
+            return
+        }
+        let (pattern_lnode, p_self, inputs, table) = self.order[i].clone();
+        for host_lnode in self.candidates(i) {
+            if self.used.contains(&host_lnode) {
+                continue
+            }
+            let host = &self.host.lnodes[host_lnode];
+            let Some((host_inputs, host_table)) = lnode_function(host) else {
+                continue
+            };
+            if host_inputs.len() != inputs.len() {
+                continue
+            }
+            let host_p_self = self.host_equiv(host.p_self);
+            let host_inputs: Vec<PBack> = host_inputs.iter().map(|p| self.host_equiv(*p)).collect();
+            let perms = self
+                .permutations
+                .entry(inputs.len())
+                .or_insert_with(|| permutations(inputs.len()))
+                .clone();
+            for perm in perms {
+                if !tables_match(&table, &host_table, &perm) {
+                    continue
+                }
+                let len = self.trail.len();
+                let mut ok = self.bind(p_self, host_p_self);
+                for (inp, j) in inputs.iter().zip(perm.iter()) {
+                    ok = ok && self.bind(*inp, host_inputs[*j]);
+                }
+                if ok {
+                    self.used.insert(host_lnode);
+                    self.lnodes.push((pattern_lnode, host_lnode));
+                    self.search(i + 1);
+                    self.lnodes.pop();
+                    self.used.remove(&host_lnode);
+                }
+                self.unbind_to(len);
+            }
+        }
+    }
+
+    fn record(&mut self) {
+        let mut set: Vec<PLNode> = self.lnodes.iter().map(|(_, h)| *h).collect();
+        set.sort_unstable();
+        if !self.found_sets.insert(set) {
+            return
+        }
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        for (_, p_external, rnode) in self.pattern.notary.rnodes() {
+            let Some(bits) = rnode.bits() else { continue };
+            for (bit_i, p_bit) in bits.iter().enumerate() {
+                let Some(p_bit) = p_bit else { continue };
+                let pattern_equiv = self.pattern.backrefs.get_val(*p_bit).unwrap().p_self_equiv;
+                if let Some(host_equiv) = self.equivs.get(&pattern_equiv) {
+                    if rnode.read_only() {
+                        outputs.push((*p_external, bit_i, *host_equiv));
+                    } else {
+                        inputs.push((*p_external, bit_i, *host_equiv));
+                    }
+                }
+            }
+        }
+        self.found.push(Match {
+            lnodes: self.lnodes.clone(),
+            inputs,
+            outputs,
+        });
+    }
+}
+
+impl Ensemble {
+    /// Finds all the instances of the combinational `pattern` in `self`, such
+    /// as every full adder in a design, for reporting or for replacing them.
+    /// The `pattern` is an `Ensemble` (usually of an optimized `Epoch`) whose
+    /// `LazyAwi`s are the inputs and `EvalAwi`s are the outputs, and its
+    /// `LNode`s are matched one-to-one to `LNode`s of `self` with the same
+    /// connectivity. Lookup tables match if they are the same function under
+    /// some permutation of their inputs, so commuted and reordered inputs are
+    /// found, and a `Carry` can match the equivalent `Lut`. Dynamic lookup
+    /// tables never match.
+    ///
+    /// Instances can overlap, but every set of `LNode`s of `self` is only
+    /// reported once even if the pattern has symmetries that match it in
+    /// multiple ways. Internal values of an instance may have other uses in
+    /// `self`. Returns an empty `Vec` if the pattern has no `LNode`s.
+    pub fn find_subcircuits(&self, pattern: &Ensemble) -> Vec<Match> {
+        let pattern_equiv = |p_back: PBack| pattern.backrefs.get_val(p_back).unwrap().p_self_equiv;
+        // order the pattern `LNode`s by DFS from the outputs so that most
+        // `LNode`s are reached through an already bound equivalence
+        let mut order = vec![];
+        let mut visited = HashSet::new();
+        let mut roots = vec![];
+        for rnode in pattern.notary.rnodes().vals() {
+            if rnode.read_only() {
+                for p_bit in rnode.bits().unwrap_or(&[]).iter().flatten() {
+                    roots.push(pattern_equiv(*p_bit));
+                }
+            }
+        }
+        roots.extend(
+            pattern
+                .lnodes
+                .vals()
+                .map(|lnode| pattern_equiv(lnode.p_self)),
+        );
+        for root in roots {
+            let mut stack = vec![root];
+            while let Some(p_equiv) = stack.pop() {
+                let mut drivers = vec![];
+                pattern.lnodes_driving(p_equiv, &mut drivers);
+                for p_lnode in drivers {
+                    if !visited.insert(p_lnode) {
+                        continue
+                    }
+                    let lnode = &pattern.lnodes[p_lnode];
+                    let Some((inputs, table)) = lnode_function(lnode) else {
+                        return vec![]
+                    };
+                    let inputs: Vec<PBack> = inputs.iter().map(|p| pattern_equiv(*p)).collect();
+                    stack.extend(inputs.iter().rev());
+                    order.push((p_lnode, pattern_equiv(lnode.p_self), inputs, table));
+                }
+            }
+        }
+        if order.is_empty() {
+            return vec![]
+        }
+        let mut search = SubcircuitSearch {
+            host: self,
+            pattern,
+            order,
+            equivs: HashMap::new(),
+            host_equivs: HashMap::new(),
+            trail: vec![],
+            lnodes: vec![],
+            used: HashSet::new(),
+            found: vec![],
+            found_sets: HashSet::new(),
+            permutations: HashMap::new(),
+        };
+        search.search(0);
+        search.found
+    }
+}
+
+/// The interface of a pattern or replacement, the named `RNode`s with whether
+/// they are read only and their bitwidths
+type Interface = HashMap<String, (PExternal, bool, usize)>;
+
+fn interface(ensemble: &Ensemble, what: &str) -> Result<Interface, Error> {
+    let mut res = HashMap::new();
+    for (_, p_external, rnode) in ensemble.notary.rnodes() {
+        let Some(name) = rnode.debug_name() else {
+            return Err(Error::OtherString(format!(
+                "`replace_subcircuits`: every `RNode` of the {what} needs a debug name"
+            )))
+        };
+        let entry = (*p_external, rnode.read_only(), rnode.nzbw().get());
+        if res.insert(name.to_owned(), entry).is_some() {
+            return Err(Error::OtherString(format!(
+                "`replace_subcircuits`: the {what} has multiple `RNode`s named \"{name}\""
+            )))
+        }
+    }
+    Ok(res)
+}
+
+/// Returns the equivalences of the bits of `p_external` in `ensemble`
+fn rnode_equivs(ensemble: &Ensemble, p_external: PExternal) -> Vec<Option<PBack>> {
+    let (_, rnode) = ensemble.notary.get_rnode(p_external).unwrap();
+    rnode
+        .bits()
+        .unwrap_or(&[])
+        .iter()
+        .map(|p_bit| p_bit.map(|p| ensemble.backrefs.get_val(p).unwrap().p_self_equiv))
+        .collect()
+}
+
+/// Returns if the equivalence of `p_equiv` is read by an `LNode`
+fn is_read(ensemble: &Ensemble, p_equiv: PBack) -> bool {
+    let mut adv = ensemble.backrefs.advancer_surject(p_equiv);
+    while let Some(p_back) = adv.advance(&ensemble.backrefs) {
+        if let Referent::Input(_) = ensemble.backrefs.get_key(p_back).unwrap() {
+            return true
+        }
+    }
+    false
+}
+
+/// Checks that `replacement` computes the same outputs as `pattern`
+fn check_equivalence(
+    pattern: &Ensemble,
+    replacement: &Ensemble,
+    pattern_interface: &Interface,
+    replacement_interface: &Interface,
+) -> Result<(), Error> {
+    let mut names: Vec<&String> = pattern_interface.keys().collect();
+    names.sort_unstable();
+    let mut pattern_sim = pattern.batch_sim()?;
+    let mut replacement_sim = replacement.batch_sim()?;
+    let num_inputs: usize = names
+        .iter()
+        .map(|name| pattern_interface[*name])
+        .filter(|(_, read_only, _)| !read_only)
+        .map(|(_, _, w)| w)
+        .sum();
+    let exhaustive = num_inputs <= MAX_EXHAUSTIVE_REPLACEMENT_INPUTS;
+    let rounds = if exhaustive {
+        ((1usize << num_inputs) / 64).max(1)
+    } else {
+        64
+    };
+    let mut rng = StarRng::new(0);
+    for round in 0..rounds {
+        let mut k = 0;
+        for name in &names {
+            let (p_pattern, read_only, w) = pattern_interface[*name];
+            if read_only {
+                continue
+            }
+            let words: Vec<u64> = (0..w)
+                .map(|j| {
+                    if exhaustive {
+                        // lane `lane` gets input combination `round * 64 + lane`
+                        let mut word = 0u64;
+                        for lane in 0..64 {
+                            if (((round * 64) + lane) >> (k + j)) & 1 != 0 {
+                                word |= 1 << lane;
+                            }
+                        }
+                        word
+                    } else {
+                        rng.next_u64()
+                    }
+                })
+                .collect();
+            k += w;
+            pattern_sim.set_words(p_pattern, &words)?;
+            replacement_sim.set_words(replacement_interface[*name].0, &words)?;
+        }
+        pattern_sim.evaluate();
+        replacement_sim.evaluate();
+        for name in &names {
+            let (p_pattern, read_only, _) = pattern_interface[*name];
+            if read_only
+                && (pattern_sim.get_words(p_pattern)?
+                    != replacement_sim.get_words(replacement_interface[*name].0)?)
+            {
+                return Err(Error::OtherString(format!(
+                    "`replace_subcircuits`: the replacement is not equivalent to the pattern at \
+                     output \"{name}\""
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Ensemble {
+    /// Returns if the internal equivalences of `m` (those not bound to a
+    /// pattern output) are only used by the `LNode`s of `m`, so that `m` can
+    /// be removed without affecting anything else
+    fn is_isolated_match(&self, m: &Match) -> bool {
+        let host_lnodes: HashSet<PLNode> = m.lnodes.iter().map(|(_, h)| *h).collect();
+        let outputs: HashSet<PBack> = m.outputs.iter().map(|(_, _, p)| *p).collect();
+        for (_, p_lnode) in &m.lnodes {
+            let p_self = self.lnodes[*p_lnode].p_self;
+            let p_equiv = self.backrefs.get_val(p_self).unwrap().p_self_equiv;
+            if outputs.contains(&p_equiv) {
+                continue
+            }
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_back) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_back).unwrap() {
+                    Referent::ThisEquiv => (),
+                    Referent::ThisLNode(p) | Referent::Input(p) if host_lnodes.contains(&p) => (),
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+
+    /// Replaces the instances of `pattern` found by
+    /// [Ensemble::find_subcircuits] with `replacement`, for swapping in hand
+    /// optimized implementations such as replacing ripple carry adders with
+    /// carry skip adders. The boundaries of the `pattern` and `replacement`
+    /// correspond by the debug names of their `RNode`s (e.x. set with
+    /// `LazyAwi::set_debug_name`), which must have the same bitwidths and
+    /// directions. `self` should be optimized beforehand, and the `pattern`
+    /// and `replacement` should be optimized Ensembles.
+    ///
+    /// Instances are skipped if they overlap an instance that was already
+    /// replaced, or if any of their internal values is used outside of the
+    /// instance. Returns the number of replaced instances.
+    ///
+    /// # Errors
+    ///
+    /// If the interfaces do not correspond, if the `replacement` has
+    /// `TNode`s, black boxes, or dynamic lookup tables, if the `replacement`
+    /// uses an input bit that the `pattern` does not, or if the
+    /// `replacement` is not functionally equivalent to the `pattern`. This is
+    /// checked exhaustively for up to 16 input bits and on random vectors
+    /// otherwise. Nothing is changed if there is an error.
+    pub fn replace_subcircuits(
+        &mut self,
+        pattern: &Ensemble,
+        replacement: &Ensemble,
+    ) -> Result<usize, Error> {
+        let pattern_interface = interface(pattern, "pattern")?;
+        let replacement_interface = interface(replacement, "replacement")?;
+        if pattern_interface.len() != replacement_interface.len() {
+            return Err(Error::OtherStr(
+                "`replace_subcircuits`: the pattern and replacement have different numbers of \
+                 `RNode`s",
+            ))
+        }
+        for (name, (_, read_only, w)) in &pattern_interface {
+            let Some((_, replacement_read_only, replacement_w)) = replacement_interface.get(name)
+            else {
+                return Err(Error::OtherString(format!(
+                    "`replace_subcircuits`: the replacement has no `RNode` named \"{name}\""
+                )))
+            };
+            if read_only != replacement_read_only {
+                return Err(Error::OtherString(format!(
+                    "`replace_subcircuits`: \"{name}\" is an input in one of the pattern and \
+                     replacement and an output in the other"
+                )))
+            }
+            if w != replacement_w {
+                return Err(Error::BitwidthMismatch(*w, *replacement_w))
+            }
+        }
+        if !replacement.tnodes.is_empty() || !replacement.blackboxes.is_empty() {
+            return Err(Error::OtherStr(
+                "`replace_subcircuits`: the replacement must be combinational",
+            ))
+        }
+        if replacement
+            .lnodes
+            .vals()
+            .any(|lnode| matches!(lnode.kind, LNodeKind::DynamicLut(..)))
+        {
+            return Err(Error::OtherStr(
+                "`replace_subcircuits`: the replacement cannot have dynamic lookup tables",
+            ))
+        }
+        // every input bit that the replacement uses must be bound in matches, and
+        // every output bit must be lowered
+        let mut replacement_outputs = HashSet::new();
+        for (name, (p_replacement, read_only, _)) in &replacement_interface {
+            if *read_only {
+                for p_equiv in rnode_equivs(replacement, *p_replacement) {
+                    let Some(p_equiv) = p_equiv else {
+                        return Err(Error::OtherString(format!(
+                            "`replace_subcircuits`: the output \"{name}\" of the replacement is \
+                             not lowered"
+                        )))
+                    };
+                    replacement_outputs.insert(p_equiv);
+                }
+            }
+        }
+        for (name, (p_replacement, read_only, _)) in &replacement_interface {
+            if *read_only {
+                continue
+            }
+            let pattern_equivs = rnode_equivs(pattern, pattern_interface[name].0);
+            for (p_equiv, pattern_equiv) in rnode_equivs(replacement, *p_replacement)
+                .into_iter()
+                .zip(pattern_equivs)
+            {
+                let Some(p_equiv) = p_equiv else { continue };
+                let used = is_read(replacement, p_equiv) || replacement_outputs.contains(&p_equiv);
+                if used && !pattern_equiv.is_some_and(|p| is_read(pattern, p)) {
+                    return Err(Error::OtherString(format!(
+                        "`replace_subcircuits`: the replacement uses a bit of \"{name}\" that the \
+                         pattern does not"
+                    )))
+                }
+            }
+        }
+        check_equivalence(
+            pattern,
+            replacement,
+            &pattern_interface,
+            &replacement_interface,
+        )?;
+        let order = replacement.topo_order()?;
+
+        let matches = self.find_subcircuits(pattern);
+        self.journal_lnode_step("replace_subcircuits");
+        // pending events may refer to `LNode`s that get removed
+        self.restart_request_phase()?;
+        let replacement_equiv =
+            |p_back: PBack| replacement.backrefs.get_val(p_back).unwrap().p_self_equiv;
+        let mut replaced = HashSet::new();
+        let mut num_replaced = 0;
+        for m in matches {
+            if m.lnodes.iter().any(|(_, p)| replaced.contains(p)) || !self.is_isolated_match(&m) {
+                continue
+            }
+            // map replacement equivalences to equivalences of `self`
+            let mut map: HashMap<PBack, PBack> = HashMap::new();
+            for (p_external, bit_i, p_equiv) in m.inputs.iter().copied() {
+                let name = pattern.notary.get_rnode(p_external).unwrap().1.debug_name();
+                let p_replacement = replacement_interface[name.unwrap()].0;
+                if let Some(p) = rnode_equivs(replacement, p_replacement)[bit_i] {
+                    map.insert(p, p_equiv);
+                }
+            }
+            let mut outputs = vec![];
+            for (p_external, bit_i, p_equiv) in m.outputs.iter().copied() {
+                let name = pattern.notary.get_rnode(p_external).unwrap().1.debug_name();
+                let p_replacement = replacement_interface[name.unwrap()].0;
+                let p = rnode_equivs(replacement, p_replacement)[bit_i].unwrap();
+                outputs.push((p, p_equiv));
+            }
+            // the outputs driven by replacement `LNode`s directly
+            let mut driven: HashMap<PBack, PBack> = HashMap::new();
+            for (p, p_equiv) in outputs.iter().copied() {
+                let mut drivers = vec![];
+                replacement.lnodes_driving(p, &mut drivers);
+                if !drivers.is_empty() && !driven.contains_key(&p) && !map.contains_key(&p) {
+                    driven.insert(p, p_equiv);
+                }
+            }
+            // remove the instance
+            let mut internal = vec![];
+            for (_, p_lnode) in m.lnodes.iter().copied() {
+                replaced.insert(p_lnode);
+                let p_self = self.lnodes[p_lnode].p_self;
+                internal.push(self.backrefs.get_val(p_self).unwrap().p_self_equiv);
+                self.remove_lnode_not_p_self(p_lnode);
+                self.backrefs.remove_key(p_self).unwrap();
+            }
+            // insert the replacement `LNode`s
+            let mut new_equivs = HashSet::new();
+            let mut new_lnodes = vec![];
+            for p_replacement_lnode in order.iter().copied() {
+                let lnode = &replacement.lnodes[p_replacement_lnode];
+                let p_out = replacement_equiv(lnode.p_self);
+                let p_target = if let Some(p_equiv) = driven.get(&p_out) {
+                    *p_equiv
+                } else {
+                    let p_equiv = self.backrefs.insert_with(|p_self_equiv| {
+                        (
+                            Referent::ThisEquiv,
+                            Equiv::new(p_self_equiv, Value::Unknown),
+                        )
+                    });
+                    new_equivs.insert(p_equiv);
+                    p_equiv
+                };
+                map.insert(p_out, p_target);
+                let mut sources = vec![];
+                lnode.inputs(|p_inp| {
+                    let p_inp = replacement_equiv(p_inp);
+                    let p_source = if let Some(p_equiv) = map.get(&p_inp) {
+                        *p_equiv
+                    } else {
+                        // a constant
+                        let val = replacement.backrefs.get_val(p_inp).unwrap().val;
+                        self.backrefs.insert_with(|p_self_equiv| {
+                            (Referent::ThisEquiv, Equiv::new(p_self_equiv, val))
+                        })
+                    };
+                    sources.push(p_source);
+                });
+                let mut lnode = lnode.clone();
+                lnode.lowered_from = None;
+                if let LNodeKind::Lut(_, ref mut table) = lnode.kind {
+                    *table = self.lut_tables.intern_bits(table);
+                }
+                new_lnodes.push(self.lnodes.insert_with(|p_lnode| {
+                    lnode.p_self = self
+                        .backrefs
+                        .insert_key(p_target, Referent::ThisLNode(p_lnode))
+                        .unwrap();
+                    let mut i = 0;
+                    lnode.inputs_mut(|p_inp| {
+                        *p_inp = self
+                            .backrefs
+                            .insert_key(sources[i], Referent::Input(p_lnode))
+                            .unwrap();
+                        i += 1;
+                    });
+                    lnode
+                }));
+            }
+            // outputs that are wires or constants in the replacement
+            for (p, p_equiv) in outputs.iter().copied() {
+                if driven.get(&p) == Some(&p_equiv) {
+                    continue
+                }
+                if let Some(p_source) = map.get(&p).copied() {
+                    self.lnodes.insert_with(|p_lnode| {
+                        let p_self = self
+                            .backrefs
+                            .insert_key(p_equiv, Referent::ThisLNode(p_lnode))
+                            .unwrap();
+                        let p_inp = self
+                            .backrefs
+                            .insert_key(p_source, Referent::Input(p_lnode))
+                            .unwrap();
+                        LNode::new(p_self, LNodeKind::Copy(p_inp), None)
+                    });
+                } else {
+                    let val = replacement.backrefs.get_val(p).unwrap().val;
+                    self.backrefs.get_val_mut(p_equiv).unwrap().val = val;
+                }
+            }
+            // the new internal values
+            for p_lnode in new_lnodes {
+                let p_self = self.lnodes[p_lnode].p_self;
+                let p_equiv = self.backrefs.get_val(p_self).unwrap().p_self_equiv;
+                if new_equivs.contains(&p_equiv) {
+                    let (val, _) = self.calculate_lnode_value(p_lnode)?;
+                    self.backrefs.get_val_mut(p_equiv).unwrap().val = val;
+                }
+            }
+            for p_equiv in internal {
+                if !outputs.iter().any(|(_, p)| *p == p_equiv) {
+                    self.optimizer
+                        .insert(Optimization::InvestigateUsed(p_equiv));
+                }
+            }
+            num_replaced += 1;
+        }
+        self.reinit_partial_orders()?;
+        // remove what became unused
+        while let Some(p_optimization) = self.optimizer.optimizations.first() {
+            self.optimize(p_optimization)?;
+        }
+        Ok(num_replaced)
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{
+        Ensemble, Equiv, LNode, LNodeInputs, LNodeKind, PBack, PExternal, PLNode, Referent,
+    },
+    Error,
+};
+
+impl Ensemble {
+    /// Applies triple modular redundancy to the combinational fan-in cones of
+    /// the `RNode`s of `roots`. The cones stop at `TNode`s and at anything
+    /// without a driving `LNode` such as `LazyAwi`s. Each selected `LNode` is
+    /// replaced by three replicas, where the replicas only read from the same
+    /// replica of other selected `LNode`s, and the original `LNode` becomes a
+    /// majority voter over its three replicas. This means that the external
+    /// interface is preserved and anything outside of the selection only sees
+    /// voted values. `LNodeKind::Copy`s are not triplicated because they are
+    /// only wires. Returns the number of triplicated `LNode`s.
+    ///
+    /// # Errors
+    ///
+    /// If a `PExternal` is invalid or its `RNode` has not been lowered
+    pub fn apply_tmr(&mut self, roots: &[PExternal]) -> Result<usize, Error> {
+        // select the `LNode`s of the cones, only one per equivalence
+        let mut front = vec![];
+        for p_external in roots.iter().copied() {
+            let (_, rnode) = self.notary.get_rnode(p_external)?;
+            let Some(bits) = rnode.bits() else {
+                return Err(Error::OtherString(format!(
+                    "`apply_tmr`: the `RNode` of {p_external:?} has not been lowered"
+                )))
+            };
+            front.extend(bits.iter().copied().flatten());
+        }
+        let mut selected: Vec<(PLNode, PBack)> = vec![];
+        let mut visited = HashSet::new();
+        while let Some(p_back) = front.pop() {
+            let p_equiv = self.backrefs.get_val(p_back).unwrap().p_self_equiv;
+            if !visited.insert(p_equiv) {
+                continue
+            }
+            let mut selected_lnode = false;
+            let mut adv = self.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&self.backrefs) {
+                if let Referent::ThisLNode(p_lnode) = *self.backrefs.get_key(p_ref).unwrap() {
+                    let lnode = &self.lnodes[p_lnode];
+                    lnode.inputs(|p_inp| front.push(p_inp));
+                    if !selected_lnode && !matches!(lnode.kind, LNodeKind::Copy(_)) {
+                        selected_lnode = true;
+                        selected.push((p_lnode, p_equiv));
+                    }
+                }
+            }
+        }
+        selected.sort_unstable();
+        // create the equivalences for the replicas, the replicas compute the same
+        // function so they start with the same value
+        let mut replicas: HashMap<PBack, [PBack; 3]> = HashMap::new();
+        for (_, p_equiv) in selected.iter().copied() {
+            let equiv = self.backrefs.get_val(p_equiv).unwrap();
+            let (val, partial_order) = (equiv.val, equiv.evaluator_partial_order);
+            let copies = [(); 3].map(|_| {
+                let p_copy = self.backrefs.insert_with(|p_self_equiv| {
+                    (Referent::ThisEquiv, Equiv::new(p_self_equiv, val))
+                });
+                self.backrefs
+                    .get_val_mut(p_copy)
+                    .unwrap()
+                    .evaluator_partial_order = partial_order;
+                p_copy
+            });
+            replicas.insert(p_equiv, copies);
+        }
+        // create the replica `LNode`s
+        for (p_lnode, p_equiv) in selected.iter().copied() {
+            let copies = replicas[&p_equiv];
+            let mut sources = vec![];
+            self.lnodes[p_lnode].inputs(|p_inp| {
+                sources.push(self.backrefs.get_val(p_inp).unwrap().p_self_equiv);
+            });
+            for (k, p_copy) in copies.iter().copied().enumerate() {
+                let mut lnode = self.lnodes[p_lnode].clone();
+                self.lnodes.insert_with(|p_replica| {
+                    lnode.p_self = self
+                        .backrefs
+                        .insert_key(p_copy, Referent::ThisLNode(p_replica))
+                        .unwrap();
+                    let mut i = 0;
+                    lnode.inputs_mut(|p_inp| {
+                        let p_source = replicas
+                            .get(&sources[i])
+                            .map(|r| r[k])
+                            .unwrap_or(sources[i]);
+                        *p_inp = self
+                            .backrefs
+                            .insert_key(p_source, Referent::Input(p_replica))
+                            .unwrap();
+                        i += 1;
+                    });
+                    lnode
+                });
+            }
+        }
+        // turn the originals into voters
+        for (p_lnode, p_equiv) in selected.iter().copied() {
+            let mut inputs = vec![];
+            self.lnodes[p_lnode].inputs(|p_inp| inputs.push(p_inp));
+            for p_inp in inputs {
+                self.backrefs.remove_key(p_inp).unwrap();
+            }
+            let inp: LNodeInputs = replicas[&p_equiv]
+                .iter()
+                .map(|p_copy| {
+                    self.backrefs
+                        .insert_key(*p_copy, Referent::Input(p_lnode))
+                        .unwrap()
+                })
+                .collect();
+            self.lnodes[p_lnode].kind =
+                LNodeKind::Lut(inp, self.lut_tables.intern(LNode::carry_lut()));
+        }
+        self.reinit_partial_orders()?;
+        Ok(selected.len())
+    }
+}
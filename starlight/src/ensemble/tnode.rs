@@ -1,9 +1,10 @@
-use std::num::NonZeroU64;
+use std::{cmp::Ordering, num::NonZeroU64};
 
-use awint::awint_dag::triple_arena::{OrdArena, Recast, Recaster};
+use awint::awint_dag::triple_arena::{Advancer, OrdArena, Recast, Recaster};
 
 use crate::{
-    ensemble::{Ensemble, PBack, PSimEvent, PTNode, Referent},
+    ensemble::{value::stable_hash, Ensemble, Equiv, PBack, PSimEvent, PTNode, Referent, TieBreak},
+    utils::trace_span,
     Error,
 };
 
@@ -47,6 +48,9 @@ pub struct TNode {
     pub p_self: PBack,
     pub p_driver: PBack,
     pub delay: Delay,
+    /// The declared initial value of the `Loop` this was lowered from, if it
+    /// is known
+    pub initial: Option<bool>,
 }
 
 impl Recast<PBack> for TNode {
@@ -65,6 +69,7 @@ impl TNode {
             p_self,
             p_driver,
             delay,
+            initial: None,
         }
     }
 
@@ -163,6 +168,23 @@ impl Delayer {
         }
     }
 
+    /// Removes all pending events of `p_tnode`, which is needed when a
+    /// `TNode` is removed
+    pub fn remove_tnode_events(&mut self, p_tnode: PTNode) {
+        let mut empty = vec![];
+        let mut adv = self.delayed_events.advancer();
+        while let Some(p) = adv.advance(&self.delayed_events) {
+            let events = self.delayed_events.get_val_mut(p).unwrap();
+            events.tnode_drives.retain(|p_drive| *p_drive != p_tnode);
+            if events.tnode_drives.is_empty() {
+                empty.push(p);
+            }
+        }
+        for p in empty {
+            self.delayed_events.remove(p).unwrap();
+        }
+    }
+
     pub fn are_delayed_events_empty(&self) -> bool {
         self.delayed_events.is_empty()
     }
@@ -198,8 +220,101 @@ impl Ensemble {
         })
     }
 
+    /// Moves the `Input`, `Driver`, and `ThisRNode` referents `p_refs` to the
+    /// equivalence `p_to`, updating the `LNode`s, `TNode`s, and `RNode`s that
+    /// they belong to. Panics if something is invalid.
+    pub(crate) fn move_readers(&mut self, p_refs: &[PBack], p_to: PBack) {
+        for p_ref in p_refs.iter().copied() {
+            let referent = *self.backrefs.get_key(p_ref).unwrap();
+            self.backrefs.remove_key(p_ref).unwrap();
+            let p_new = self.backrefs.insert_key(p_to, referent).unwrap();
+            match referent {
+                Referent::Input(p_lnode) => {
+                    self.lnodes.get_mut(p_lnode).unwrap().inputs_mut(|inp| {
+                        if *inp == p_ref {
+                            *inp = p_new;
+                        }
+                    });
+                }
+                Referent::Driver(p_tnode) => {
+                    self.tnodes.get_mut(p_tnode).unwrap().p_driver = p_new;
+                }
+                Referent::ThisRNode(p_rnode) => {
+                    let rnode = self.notary.get_rnode_by_p_rnode_mut(p_rnode).unwrap();
+                    for bit in rnode.bits_mut().unwrap() {
+                        if *bit == Some(p_ref) {
+                            *bit = Some(p_new);
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Inserts a `TNode` with `delay` in front of all the `LNode`, `TNode`, and
+    /// read-only `RNode` readers of the equivalence of `p_equiv`, so that they
+    /// see its value delayed. This is used for back-annotating interconnect
+    /// delays. Returns `None` if there were no readers.
+    pub fn insert_delay_before_readers(
+        &mut self,
+        p_equiv: PBack,
+        delay: Delay,
+    ) -> Result<Option<PTNode>, Error> {
+        let p_equiv = if let Some(equiv) = self.backrefs.get_val(p_equiv) {
+            equiv.p_self_equiv
+        } else {
+            return Err(Error::InvalidPtr)
+        };
+        let mut readers = vec![];
+        let mut adv = self.backrefs.advancer_surject(p_equiv);
+        while let Some(p_ref) = adv.advance(&self.backrefs) {
+            match *self.backrefs.get_key(p_ref).unwrap() {
+                Referent::Input(_) | Referent::Driver(_) => readers.push(p_ref),
+                Referent::ThisRNode(p_rnode) => {
+                    if self.notary.rnodes().get_val(p_rnode).unwrap().read_only() {
+                        readers.push(p_ref);
+                    }
+                }
+                Referent::ThisEquiv
+                | Referent::ThisLNode(_)
+                | Referent::ThisTNode(_)
+                | Referent::ThisStateBit(..) => (),
+            }
+        }
+        if readers.is_empty() {
+            return Ok(None)
+        }
+        let val = self.backrefs.get_val(p_equiv).unwrap().val;
+        let p_delayed = self
+            .backrefs
+            .insert_with(|p_self_equiv| (Referent::ThisEquiv, Equiv::new(p_self_equiv, val)));
+        // move the readers over to the delayed equivalence
+        self.move_readers(&readers, p_delayed);
+        // the values are already equal, so no events need to be registered
+        Ok(Some(self.make_tnode(p_delayed, p_equiv, delay)))
+    }
+
+    /// Orders `tnode_drives` according to `self.evaluator.tie_break`, the
+    /// drives are applied in this order so the last drive of an equivalence
+    /// wins
+    fn order_simultaneous_tnode_drives(&self, tnode_drives: &mut [PTNode]) {
+        match self.evaluator.tie_break {
+            TieBreak::SourceOrder => (),
+            TieBreak::StableHash => tnode_drives.sort_by_cached_key(stable_hash),
+            TieBreak::Comparator(cmp) => tnode_drives.sort_by(|lhs, rhs| {
+                match (self.tnodes.get(*lhs), self.tnodes.get(*rhs)) {
+                    (Some(lhs), Some(rhs)) => cmp(lhs, rhs),
+                    // some optimizations can remove tnodes
+                    _ => Ordering::Equal,
+                }
+            }),
+        }
+    }
+
     /// Runs temporal evaluation until `delay` has passed since the current time
     pub fn run(&mut self, delay: Delay) -> Result<(), Error> {
+        let span = trace_span("eval", "Ensemble::run");
         // this needs to be called in the beginning to fill up the delayed events queue
         // if there are evaluator events to process, in between each simultaneous
         // processing, and at the very end of the last iteration to check for infinite
@@ -212,8 +327,14 @@ impl Ensemble {
             if next_time > final_time {
                 break
             }
-            let (time, events) = self.delayer.pop_next_simultaneous_events().unwrap();
+            let (time, mut events) = self.delayer.pop_next_simultaneous_events().unwrap();
             self.delayer.current_time = time;
+            span.count("timestamps", 1);
+            span.count(
+                "tnode_drives",
+                u64::try_from(events.tnode_drives.len()).unwrap(),
+            );
+            self.order_simultaneous_tnode_drives(&mut events.tnode_drives);
             for p_tnode in events.tnode_drives.iter().copied() {
                 // this is conditional because some optimizations can remove tnodes
                 if let Some(tnode) = self.tnodes.get(p_tnode) {
@@ -221,15 +342,20 @@ impl Ensemble {
                     self.request_value(p_driver)?;
                 }
             }
+            // all the values are sampled before any are changed, so that `TNode`s directly
+            // driven by other `TNode`s see the old values
+            let mut changes = vec![];
             for p_tnode in events.tnode_drives.iter().copied() {
                 if let Some(tnode) = self.tnodes.get(p_tnode) {
                     let val = self.backrefs.get_val(tnode.p_driver).unwrap().val;
-                    let p_self = tnode.p_self;
-                    // TODO if we don't unwrap, we need to reregister events
-                    self.change_value(p_self, val, NonZeroU64::new(1).unwrap())
-                        .unwrap();
+                    changes.push((tnode.p_self, val));
                 }
             }
+            for (p_self, val) in changes {
+                // TODO if we don't unwrap, we need to reregister events
+                self.change_value(p_self, val, NonZeroU64::new(1).unwrap())
+                    .unwrap();
+            }
             self.restart_request_phase()?;
         }
         self.delayer.current_time = final_time;
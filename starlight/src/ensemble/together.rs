@@ -8,9 +8,11 @@ use awint::awint_dag::{
 use super::Delayer;
 use crate::{
     ensemble::{
-        value::Evaluator, LNode, LNodeKind, Notary, Optimizer, PBack, PLNode, PRNode, PTNode,
-        Stator, TNode, Value,
+        value::Evaluator, BlackBox, DeadReport, Journal, LNode, LNodeKind, Limits, LutInterner,
+        MacroOp, MulNode, Notary, OptimizeSettings, Optimizer, PBack, PBlackBox, PLNode, PMulNode,
+        PRNode, PRomNode, PTNode, ReplayLog, RomNode, Stator, TNode, Value,
     },
+    lower::meta::FunnelStrategy,
     triple_arena::{Arena, SurjectArena},
     Error,
 };
@@ -79,6 +81,17 @@ pub struct Ensemble {
     pub evaluator: Evaluator,
     pub delayer: Delayer,
     pub optimizer: Optimizer,
+    pub limits: Limits,
+    pub optimize_settings: OptimizeSettings,
+    pub replay_log: Option<ReplayLog>,
+    pub journal: Option<Journal>,
+    pub dead_report: Option<DeadReport>,
+    pub preserved_macro_ops: Vec<MacroOp>,
+    pub funnel_strategy: FunnelStrategy,
+    pub mul_nodes: Arena<PMulNode, MulNode>,
+    pub rom_nodes: Arena<PRomNode, RomNode>,
+    pub blackboxes: Arena<PBlackBox, BlackBox>,
+    pub lut_tables: LutInterner,
     pub debug_counter: u64,
 }
 
@@ -93,6 +106,17 @@ impl Ensemble {
             evaluator: Evaluator::new(),
             delayer: Delayer::new(),
             optimizer: Optimizer::new(),
+            limits: Limits::unlimited(),
+            optimize_settings: OptimizeSettings::default(),
+            replay_log: None,
+            journal: None,
+            dead_report: None,
+            preserved_macro_ops: vec![],
+            funnel_strategy: FunnelStrategy::default(),
+            mul_nodes: Arena::new(),
+            rom_nodes: Arena::new(),
+            blackboxes: Arena::new(),
+            lut_tables: LutInterner::new(),
             debug_counter: 0,
         }
     }
@@ -326,7 +350,7 @@ impl Ensemble {
         // non-pointer invariants
         for lnode in self.lnodes.vals() {
             match &lnode.kind {
-                LNodeKind::Copy(_) => (),
+                LNodeKind::Copy(_) | LNodeKind::Carry(_) => (),
                 LNodeKind::Lut(inp, lut) => {
                     if inp.is_empty() {
                         return Err(Error::OtherStr("no inputs for lookup table"))
@@ -367,6 +391,16 @@ impl Ensemble {
                 counts[*operand].0 = counts[operand].0.checked_add(1).unwrap();
             }
         }
+        for (p_state, key) in &self.stator.fusion_keys {
+            if !self.stator.states.contains(*p_state) {
+                return Err(Error::OtherString(format!(
+                    "fusion memo has a removed state {p_state}"
+                )))
+            }
+            for operand in key.states() {
+                counts[operand].0 = counts[operand].0.checked_add(1).unwrap();
+            }
+        }
         for rnode in self.notary.rnodes().vals() {
             if let Some(p_state) = rnode.associated_state {
                 counts[p_state].1 = counts[p_state].1.checked_add(1).unwrap();
@@ -406,6 +440,8 @@ impl Ensemble {
         }
 
         let p_lnode_recaster = self.lnodes.compress_and_shrink_recaster();
+        // tables of removed `LNode`s are only kept alive by the pool
+        self.lut_tables.prune();
         let p_rnode_recaster = self.notary.recast_p_rnode();
 
         for referent in self.backrefs.keys_mut() {
@@ -471,6 +507,18 @@ impl Ensemble {
                 "recast error with {e} in the tnodes"
             )));
         }
+        // toggles of removed equivalences are dropped
+        if let Some(ref mut toggles) = self.evaluator.toggles {
+            *toggles = toggles
+                .drain()
+                .filter_map(|(mut p_equiv, count)| {
+                    p_equiv
+                        .recast(&p_back_recaster)
+                        .ok()
+                        .map(|_| (p_equiv, count))
+                })
+                .collect();
+        }
         Ok(())
     }
 
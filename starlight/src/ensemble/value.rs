@@ -1,13 +1,15 @@
 use std::{
     cmp::{Ordering, Reverse},
-    collections::BinaryHeap,
+    collections::{BinaryHeap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
     num::{NonZeroU64, NonZeroUsize},
 };
 
 use awint::{awi::*, awint_dag::triple_arena::Advancer};
 
 use crate::{
-    ensemble::{Ensemble, PBack, PLNode, PTNode, Referent},
+    ensemble::{Ensemble, EvalCache, PBack, PLNode, PTNode, Provenance, Referent, TNode},
+    utils::{trace_event, trace_span},
     Error,
 };
 
@@ -61,6 +63,9 @@ impl BasicValue {
 pub enum CommonValue<'a> {
     Bits(&'a Bits),
     Basic(BasicValue),
+    /// The bits of the second `Bits` where the first `Bits` (the mask) is set,
+    /// and unknown values elsewhere. The bitwidths must be equal.
+    Partial(&'a Bits, &'a Bits),
 }
 
 impl<'a> CommonValue<'a> {
@@ -68,6 +73,7 @@ impl<'a> CommonValue<'a> {
         match self {
             CommonValue::Bits(x) => x.nzbw(),
             CommonValue::Basic(basic) => basic.nzbw(),
+            CommonValue::Partial(_, value) => value.nzbw(),
         }
     }
 
@@ -79,6 +85,10 @@ impl<'a> CommonValue<'a> {
         match self {
             CommonValue::Bits(bits) => bits.get(inx).map(Some),
             CommonValue::Basic(basic) => basic.get(inx),
+            CommonValue::Partial(mask, value) => {
+                let b = value.get(inx)?;
+                Some(if mask.get(inx)? { Some(b) } else { None })
+            }
         }
     }
 }
@@ -188,7 +198,7 @@ pub enum EvalPhase {
     Request,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Hash)]
 pub enum ChangeKind {
     LNode(PLNode),
     TNode(PTNode),
@@ -229,12 +239,49 @@ impl Ord for Event {
     }
 }
 
+/// How ties are broken between events that happen at the same time and with
+/// the same priority. This does not change the final values of programs
+/// without races, but it determines which of several simultaneous `TNode`
+/// drives of the same equivalence wins (the last one to be applied), and the
+/// order of glitches that profiling records.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum TieBreak {
+    /// Events are handled in the order that they were scheduled
+    #[default]
+    SourceOrder,
+    /// Events are ordered by a hash of what they change, which is independent
+    /// of the order that they were scheduled in but is still reproducible for
+    /// the same `Ensemble`
+    StableHash,
+    /// Simultaneous `TNode` drives are applied in the order given by the
+    /// comparator, other events are handled in source order
+    Comparator(fn(&TNode, &TNode) -> Ordering),
+}
+
+/// A hash that is the same between runs of the same program
+pub(crate) fn stable_hash<T: Hash>(t: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    t.hash(&mut hasher);
+    hasher.finish()
+}
+
 #[derive(Debug, Clone)]
 pub struct Evaluator {
     phase: EvalPhase,
     /// Events that can accumulate during `Change` phase, but must all be
-    /// processed before `Request` phase can start
-    events: BinaryHeap<Reverse<Event>>,
+    /// processed before `Request` phase can start. The second element breaks
+    /// ties according to `tie_break`.
+    events: BinaryHeap<Reverse<(Event, u64)>>,
+    /// Used for `TieBreak::SourceOrder`
+    num_events_pushed: u64,
+    pub tie_break: TieBreak,
+    /// If `Some`, value changes are counted for a
+    /// [crate::ensemble::Profile]
+    pub toggles: Option<HashMap<PBack, u64>>,
+    /// If `Some`, requested values are memoized
+    pub cache: Option<EvalCache>,
+    /// If `Some`, the dynamic supports of evaluated `EvalAwi`s are recorded
+    pub provenance: Option<Provenance>,
 }
 
 impl Evaluator {
@@ -242,6 +289,11 @@ impl Evaluator {
         Self {
             phase: EvalPhase::Change,
             events: BinaryHeap::new(),
+            num_events_pushed: 0,
+            tie_break: TieBreak::default(),
+            toggles: None,
+            cache: None,
+            provenance: None,
         }
     }
 
@@ -252,6 +304,8 @@ impl Evaluator {
         }
         self.events.clear();
         self.events.shrink_to_fit();
+        self.num_events_pushed = 0;
+        self.invalidate_cache();
         Ok(())
     }
 
@@ -260,12 +314,28 @@ impl Evaluator {
     }
 
     pub fn push_event(&mut self, event: Event) {
-        self.events.push(Reverse(event))
+        let tie = match self.tie_break {
+            TieBreak::StableHash => stable_hash(&event.change_kind),
+            TieBreak::SourceOrder | TieBreak::Comparator(_) => {
+                let tie = self.num_events_pushed;
+                self.num_events_pushed = tie.wrapping_add(1);
+                tie
+            }
+        };
+        self.events.push(Reverse((event, tie)))
+    }
+
+    /// Clears the cache if it is enabled, this needs to be called on any
+    /// structural change to the `Ensemble`
+    pub fn invalidate_cache(&mut self) {
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
     }
 
     #[must_use]
     pub fn pop_event(&mut self) -> Option<Event> {
-        self.events.pop().map(|e| e.0)
+        self.events.pop().map(|e| e.0 .0)
     }
 }
 
@@ -280,26 +350,32 @@ impl Ensemble {
     /// `switch_to_request_phase` will do nothing if the phase is already
     /// `Request`, this will always run the event clearing
     pub fn restart_request_phase(&mut self) -> Result<(), Error> {
+        let span = trace_span("eval", "Ensemble::restart_request_phase");
         // TODO think more about this, handle redundant change cases
 
         // FIXME there are certainly constructed cases where the initial priority
         // ordering is bad and can lead to repeated cascades. We know from the halting
-        // problem that this is an impossible problem to solve in general, but there
-        // might be a good approximate way to detect nonhalting. In either case we need
-        // a way to specify event gas.
-        let mut event_gas = self.backrefs.len_keys() * 4;
+        // problem that this is an impossible problem to solve in general, so we use
+        // event gas and diagnose what is still oscillating when it runs out.
+        let mut event_gas = self.zero_delay_event_gas();
         while let Some(event) = self.evaluator.pop_event() {
+            if let Some(x) = event_gas.checked_sub(1) {
+                event_gas = x;
+            } else {
+                self.evaluator.push_event(event);
+                trace_event("eval", "zero delay non-convergence", &[(
+                    "events",
+                    u64::try_from(self.zero_delay_event_gas()).unwrap(),
+                )]);
+                return Err(self.diagnose_zero_delay_non_convergence());
+            }
+            span.count("events", 1);
             let res = self.handle_event(event);
             if res.is_err() {
                 // need to reinsert
                 self.evaluator.push_event(event)
             }
             res?;
-            if let Some(x) = event_gas.checked_sub(1) {
-                event_gas = x;
-            } else {
-                return Err(Error::OtherStr("ran out of event gas"));
-            }
         }
 
         // handle_event will keep in change phase, only afterwards do we switch
@@ -307,6 +383,57 @@ impl Ensemble {
         Ok(())
     }
 
+    /// Called when zero delay events did not settle within the event gas. This
+    /// handles a window of further events to find out which equivalences are
+    /// still changing and what is driving them.
+    fn diagnose_zero_delay_non_convergence(&mut self) -> Error {
+        let events = self.zero_delay_event_gas();
+        let mut changed = vec![];
+        // a window of one event per backreference covers at least one period of any
+        // oscillation
+        for _ in 0..self.backrefs.len_keys() {
+            let Some(event) = self.evaluator.pop_event() else {
+                break
+            };
+            let p_back = match event.change_kind {
+                ChangeKind::LNode(p_lnode) => self.lnodes.get(p_lnode).unwrap().p_self,
+                ChangeKind::TNode(p_tnode) => self.tnodes.get(p_tnode).unwrap().p_self,
+                ChangeKind::Manual(p_back, _) => p_back,
+            };
+            let old = self.backrefs.get_val(p_back).unwrap().val;
+            if let Err(e) = self.handle_event(event) {
+                self.evaluator.push_event(event);
+                return e
+            }
+            let equiv = self.backrefs.get_val(p_back).unwrap();
+            if equiv.val != old {
+                changed.push(equiv.p_self_equiv);
+            }
+        }
+        changed.sort_unstable();
+        changed.dedup();
+        let mut lnodes = vec![];
+        let mut tnodes = vec![];
+        for p_equiv in &changed {
+            let mut adv = self.backrefs.advancer_surject(*p_equiv);
+            while let Some(p_back) = adv.advance(&self.backrefs) {
+                match *self.backrefs.get_key(p_back).unwrap() {
+                    Referent::ThisLNode(p_lnode) => lnodes.push(p_lnode),
+                    Referent::ThisTNode(p_tnode) => tnodes.push(p_tnode),
+                    _ => (),
+                }
+            }
+        }
+        lnodes.sort_unstable();
+        tnodes.sort_unstable();
+        Error::ZeroDelayNonConvergence {
+            events,
+            equivs: changed,
+            lnodes,
+            tnodes,
+        }
+    }
+
     /// Switches to request phase if not already in that phase, clears events
     pub fn switch_to_request_phase(&mut self) -> Result<(), Error> {
         if self.evaluator.phase != EvalPhase::Request {
@@ -341,6 +468,13 @@ impl Ensemble {
                 ))
             }
             equiv.val = value;
+            if let Some(ref mut toggles) = self.evaluator.toggles {
+                let count = toggles.entry(equiv.p_self_equiv).or_insert(0);
+                *count = count.saturating_add(1);
+            }
+            if let Some(ref mut cache) = self.evaluator.cache {
+                cache.next_generation();
+            }
             if equiv.evaluator_partial_order <= source_partial_ord_num {
                 equiv.evaluator_partial_order = source_partial_ord_num.checked_add(1).unwrap();
             }
@@ -421,8 +555,24 @@ impl Ensemble {
             if equiv.val.is_const() {
                 return Ok(equiv.val)
             }
+            let p_equiv = equiv.p_self_equiv;
+            // the cache is only valid if there are no pending events, which would
+            // always have started a new generation anyway
+            if let Some(ref mut cache) = self.evaluator.cache {
+                if self.evaluator.phase == EvalPhase::Request {
+                    if let Some(val) = cache.get(p_equiv) {
+                        return Ok(val)
+                    }
+                } else {
+                    cache.record_miss();
+                }
+            }
             self.switch_to_request_phase()?;
-            Ok(self.backrefs.get_val(p_back).unwrap().val)
+            let val = self.backrefs.get_val(p_back).unwrap().val;
+            if let Some(ref mut cache) = self.evaluator.cache {
+                cache.insert(p_equiv, val);
+            }
+            Ok(val)
         } else {
             Err(Error::InvalidPtr)
         }
@@ -0,0 +1,345 @@
+//! A reference interpreter for `Op`s. This evaluates `Op<Awi>`s directly in
+//! terms of `awint::awi` operations, independently of both the lowering in
+//! `starlight::lower` and the `Op::eval` of `awint_dag`, so that the three can
+//! be cross checked against each other.
+
+use std::{collections::HashMap, fmt::Debug, num::NonZeroUsize};
+
+use awint::{
+    awint_dag::{
+        DummyDefault,
+        Op::{self, *},
+        PState,
+    },
+    Awi, Bits,
+};
+
+use crate::{ensemble::Ensemble, Error};
+
+/// Evaluates `op` whose operands have already been evaluated to `Awi`s,
+/// returning an `Awi` of bitwidth `nzbw`.
+///
+/// # Errors
+///
+/// Returns `Error::Unevaluatable` for `Opaque`s and `Argument`s. Returns an
+/// error if the operands are invalid for the operation (e.g. out of range
+/// shifts, division by zero, or mismatched bitwidths), if an `Assert` fails,
+/// or for `Op::Invalid`.
+pub fn eval_op(op: &Op<Awi>, nzbw: NonZeroUsize) -> Result<Awi, Error> {
+    eval_op_with(op, nzbw, op.operation_name(), |x| x.clone())
+}
+
+/// Evaluates `op` using `v` to get the values of the operands, `name` is the
+/// `operation_name` of `op` used in errors
+fn eval_op_with<T: Clone + Debug + DummyDefault, F: Fn(&T) -> Awi>(
+    op: &Op<T>,
+    nzbw: NonZeroUsize,
+    name: &'static str,
+    v: F,
+) -> Result<Awi, Error> {
+    let mut out = Awi::zero(nzbw);
+    let res = match op {
+        Opaque(..) | Argument(_) => return Err(Error::Unevaluatable),
+        Literal(lit) => out.copy_(lit),
+        Assert([x]) => {
+            if !v(x).to_bool() {
+                return Err(Error::OtherStr(
+                    "`Assert` failed in the reference interpreter",
+                ))
+            }
+            out.copy_(&v(x))
+        }
+        Copy([x]) => out.copy_(&v(x)),
+        StaticGet([x], inx) => v(x).get(*inx).map(|b| out.bool_(b)),
+        Concat(concat) => {
+            let mut to = 0;
+            let mut res = Some(());
+            for x in concat.as_slice() {
+                let x = v(x);
+                res = out.field_to(to, &x, x.bw());
+                if res.is_none() {
+                    break
+                }
+                to += x.bw();
+            }
+            res.filter(|_| to == out.bw())
+        }
+        ConcatFields(concat) => {
+            let mut to = 0;
+            let mut res = Some(());
+            for (x, (from, width)) in concat
+                .t_as_slice()
+                .iter()
+                .zip(concat.field_as_slice().iter())
+            {
+                res = out.field(to, &v(x), *from, width.get());
+                if res.is_none() {
+                    break
+                }
+                to += width.get();
+            }
+            res.filter(|_| to == out.bw())
+        }
+        Repeat([x]) => {
+            let x = v(x);
+            let mut to = 0;
+            while to < out.bw() {
+                let width = x.bw().min(out.bw() - to);
+                out.field_to(to, &x, width).unwrap();
+                to += width;
+            }
+            Some(())
+        }
+        StaticLut(concat, lut) => {
+            let mut inx = 0usize;
+            let mut shift = 0;
+            for x in concat.as_slice() {
+                let x = v(x);
+                if (shift + x.bw()) > (usize::BITS as usize) {
+                    return Err(invalid(name))
+                }
+                inx |= x.to_usize() << shift;
+                shift += x.bw();
+            }
+            let w = out.bw();
+            inx.checked_mul(w)
+                .and_then(|from| out.field_from(lut, from, w))
+        }
+        Lut([lut, inx]) => out.lut_(&v(lut), &v(inx)),
+        LutSet([table, entry, inx]) => out
+            .copy_(&v(table))
+            .and_then(|_| out.lut_set(&v(entry), &v(inx))),
+        Get([x, inx]) => v(x).get(v(inx).to_usize()).map(|b| out.bool_(b)),
+        Set([x, inx, bit]) => out
+            .copy_(&v(x))
+            .and_then(|_| out.set(v(inx).to_usize(), v(bit).to_bool())),
+        FieldBit([lhs, to, rhs, from]) => out
+            .copy_(&v(lhs))
+            .and_then(|_| out.field_bit(v(to).to_usize(), &v(rhs), v(from).to_usize())),
+        Field([lhs, to, rhs, from, width]) => out.copy_(&v(lhs)).and_then(|_| {
+            out.field(
+                v(to).to_usize(),
+                &v(rhs),
+                v(from).to_usize(),
+                v(width).to_usize(),
+            )
+        }),
+        FieldTo([lhs, to, rhs, width]) => out
+            .copy_(&v(lhs))
+            .and_then(|_| out.field_to(v(to).to_usize(), &v(rhs), v(width).to_usize())),
+        FieldFrom([lhs, rhs, from, width]) => out
+            .copy_(&v(lhs))
+            .and_then(|_| out.field_from(&v(rhs), v(from).to_usize(), v(width).to_usize())),
+        FieldWidth([lhs, rhs, width]) => out
+            .copy_(&v(lhs))
+            .and_then(|_| out.field_width(&v(rhs), v(width).to_usize())),
+        ZeroResize([x]) => {
+            out.zero_resize_(&v(x));
+            Some(())
+        }
+        SignResize([x]) => {
+            out.sign_resize_(&v(x));
+            Some(())
+        }
+        Resize([x, b]) => {
+            out.resize_(&v(x), v(b).to_bool());
+            Some(())
+        }
+        ZeroResizeOverflow([x], w) => {
+            let mut tmp = Awi::zero(*w);
+            out.bool_(tmp.zero_resize_(&v(x)));
+            Some(())
+        }
+        SignResizeOverflow([x], w) => {
+            let mut tmp = Awi::zero(*w);
+            out.bool_(tmp.sign_resize_(&v(x)));
+            Some(())
+        }
+        Lsb([x]) => {
+            out.bool_(v(x).lsb());
+            Some(())
+        }
+        Msb([x]) => {
+            out.bool_(v(x).msb());
+            Some(())
+        }
+        IsZero([x]) => {
+            out.bool_(v(x).is_zero());
+            Some(())
+        }
+        IsUmax([x]) => {
+            out.bool_(v(x).is_umax());
+            Some(())
+        }
+        IsImax([x]) => {
+            out.bool_(v(x).is_imax());
+            Some(())
+        }
+        IsImin([x]) => {
+            out.bool_(v(x).is_imin());
+            Some(())
+        }
+        IsUone([x]) => {
+            out.bool_(v(x).is_uone());
+            Some(())
+        }
+        Not([x]) => out.copy_(&v(x)).map(|_| out.not_()),
+        Or([lhs, rhs]) => out.copy_(&v(lhs)).and_then(|_| out.or_(&v(rhs))),
+        And([lhs, rhs]) => out.copy_(&v(lhs)).and_then(|_| out.and_(&v(rhs))),
+        Xor([lhs, rhs]) => out.copy_(&v(lhs)).and_then(|_| out.xor_(&v(rhs))),
+        Shl([x, s]) => out.copy_(&v(x)).and_then(|_| out.shl_(v(s).to_usize())),
+        Lshr([x, s]) => out.copy_(&v(x)).and_then(|_| out.lshr_(v(s).to_usize())),
+        Ashr([x, s]) => out.copy_(&v(x)).and_then(|_| out.ashr_(v(s).to_usize())),
+        Rotl([x, s]) => out.copy_(&v(x)).and_then(|_| out.rotl_(v(s).to_usize())),
+        Rotr([x, s]) => out.copy_(&v(x)).and_then(|_| out.rotr_(v(s).to_usize())),
+        Rev([x]) => out.copy_(&v(x)).map(|_| out.rev_()),
+        Funnel([x, s]) => out.funnel_(&v(x), &v(s)),
+        RangeOr([x, start, end]) => out
+            .copy_(&v(x))
+            .and_then(|_| out.range_or_(v(start).to_usize()..v(end).to_usize())),
+        RangeAnd([x, start, end]) => out
+            .copy_(&v(x))
+            .and_then(|_| out.range_and_(v(start).to_usize()..v(end).to_usize())),
+        RangeXor([x, start, end]) => out
+            .copy_(&v(x))
+            .and_then(|_| out.range_xor_(v(start).to_usize()..v(end).to_usize())),
+        Inc([x, cin]) => out.copy_(&v(x)).map(|_| {
+            out.inc_(v(cin).to_bool());
+        }),
+        Dec([x, cin]) => out.copy_(&v(x)).map(|_| {
+            out.dec_(v(cin).to_bool());
+        }),
+        IncCout([x, cin]) => {
+            let cout = v(x).inc_(v(cin).to_bool());
+            out.bool_(cout);
+            Some(())
+        }
+        DecCout([x, cin]) => {
+            let cout = v(x).dec_(v(cin).to_bool());
+            out.bool_(cout);
+            Some(())
+        }
+        CinSum([cin, lhs, rhs]) => out.cin_sum_(v(cin).to_bool(), &v(lhs), &v(rhs)).map(|_| ()),
+        UnsignedOverflow([cin, lhs, rhs]) => {
+            let lhs = v(lhs);
+            let mut tmp = Awi::zero(lhs.nzbw());
+            tmp.cin_sum_(v(cin).to_bool(), &lhs, &v(rhs))
+                .map(|(unsigned, _)| out.bool_(unsigned))
+        }
+        SignedOverflow([cin, lhs, rhs]) => {
+            let lhs = v(lhs);
+            let mut tmp = Awi::zero(lhs.nzbw());
+            tmp.cin_sum_(v(cin).to_bool(), &lhs, &v(rhs))
+                .map(|(_, signed)| out.bool_(signed))
+        }
+        Neg([x, neg]) => out.copy_(&v(x)).map(|_| out.neg_(v(neg).to_bool())),
+        Abs([x]) => out.copy_(&v(x)).map(|_| out.abs_()),
+        Add([lhs, rhs]) => out.copy_(&v(lhs)).and_then(|_| out.add_(&v(rhs))),
+        Sub([lhs, rhs]) => out.copy_(&v(lhs)).and_then(|_| out.sub_(&v(rhs))),
+        Rsb([lhs, rhs]) => out.copy_(&v(lhs)).and_then(|_| out.rsb_(&v(rhs))),
+        ArbMulAdd([add, lhs, rhs]) => out
+            .copy_(&v(add))
+            .map(|_| out.arb_umul_add_(&v(lhs), &v(rhs))),
+        Eq([lhs, rhs]) => v(lhs).const_eq(&v(rhs)).map(|b| out.bool_(b)),
+        Ne([lhs, rhs]) => v(lhs).const_ne(&v(rhs)).map(|b| out.bool_(b)),
+        Ult([lhs, rhs]) => v(lhs).ult(&v(rhs)).map(|b| out.bool_(b)),
+        Ule([lhs, rhs]) => v(lhs).ule(&v(rhs)).map(|b| out.bool_(b)),
+        Ilt([lhs, rhs]) => v(lhs).ilt(&v(rhs)).map(|b| out.bool_(b)),
+        Ile([lhs, rhs]) => v(lhs).ile(&v(rhs)).map(|b| out.bool_(b)),
+        CountOnes([x]) => {
+            out.usize_(v(x).count_ones());
+            Some(())
+        }
+        Lz([x]) => {
+            out.usize_(v(x).lz());
+            Some(())
+        }
+        Tz([x]) => {
+            out.usize_(v(x).tz());
+            Some(())
+        }
+        Sig([x]) => {
+            out.usize_(v(x).sig());
+            Some(())
+        }
+        Mux([x0, x1, inx]) => {
+            if v(inx).to_bool() {
+                out.copy_(&v(x1))
+            } else {
+                out.copy_(&v(x0))
+            }
+        }
+        UQuo([duo, div]) => {
+            let duo = v(duo);
+            let mut rem = Awi::zero(duo.nzbw());
+            Bits::udivide(&mut out, &mut rem, &duo, &v(div))
+        }
+        URem([duo, div]) => {
+            let duo = v(duo);
+            let mut quo = Awi::zero(duo.nzbw());
+            Bits::udivide(&mut quo, &mut out, &duo, &v(div))
+        }
+        IQuo([duo, div]) => {
+            let mut duo = v(duo);
+            let mut rem = Awi::zero(duo.nzbw());
+            Bits::idivide(&mut out, &mut rem, &mut duo, &mut v(div))
+        }
+        IRem([duo, div]) => {
+            let mut duo = v(duo);
+            let mut quo = Awi::zero(duo.nzbw());
+            Bits::idivide(&mut quo, &mut out, &mut duo, &mut v(div))
+        }
+        _ => {
+            return Err(Error::OtherString(format!(
+                "the reference interpreter does not support `{name}`"
+            )))
+        }
+    };
+    if res.is_none() {
+        Err(invalid(name))
+    } else {
+        Ok(out)
+    }
+}
+
+fn invalid(name: &str) -> Error {
+    Error::OtherString(format!(
+        "invalid operands or bitwidths for `{name}` in the reference interpreter"
+    ))
+}
+
+impl Ensemble {
+    /// Evaluates the state DAG rooted at `p_state` with the reference
+    /// interpreter [eval_op], without lowering anything. Every state the root
+    /// depends on must bottom out in `Literal`s.
+    ///
+    /// # Errors
+    ///
+    /// If a `PState` is invalid or if [eval_op] fails on any of the states
+    pub fn interpret_state(&self, p_state: PState) -> Result<Awi, Error> {
+        let mut values: HashMap<PState, Awi> = HashMap::new();
+        // DFS with an explicit stack so that deep DAGs do not overflow
+        let mut path: Vec<(usize, PState)> = vec![(0, p_state)];
+        loop {
+            let (i, p) = *path.last().unwrap();
+            let state = self.stator.states.get(p).ok_or(Error::InvalidPtr)?;
+            let ops = state.op.operands();
+            if let Some(p_next) = ops.get(i).copied() {
+                path.last_mut().unwrap().0 += 1;
+                if !values.contains_key(&p_next) {
+                    path.push((0, p_next));
+                }
+            } else {
+                // all operands have been evaluated
+                let val = eval_op_with(&state.op, state.nzbw, state.op.operation_name(), |p| {
+                    values[p].clone()
+                })?;
+                path.pop().unwrap();
+                if path.is_empty() {
+                    break Ok(val)
+                }
+                values.insert(p, val);
+            }
+        }
+    }
+}
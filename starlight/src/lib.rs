@@ -19,7 +19,14 @@
 //! There are several features on this crate that enable `awint` features. The
 //! `u32_ptrs` feature reduces the memory consumption of the algorithms
 //! significantly, but limits the number of possible internal references to
-//! about 4 billion, which the largest circuits might not fit in.
+//! about 4 billion, which the largest circuits might not fit in. Arenas that
+//! get near their capacity cause `Error::PtrCapacityExceeded`, and
+//! `Ensemble::estimate_ptr_requirements` can be used to check beforehand.
+//!
+//! The `perf` feature tunes data structures for the evaluation of large
+//! netlists, currently by storing up to 6 `LNode` inputs inline so that 6
+//! input LUTs do not need separate allocations, at the cost of larger
+//! `LNode`s.
 //!
 //! ```rust
 //! use std::num::NonZeroUsize;
@@ -128,7 +135,7 @@
 //! ```
 //!
 //! ```rust
-//! use starlight::{dag, awi, Epoch, EvalAwi};
+//! use starlight::{dag, awi, sel_, Epoch, EvalAwi};
 //!
 //! use dag::*;
 //!
@@ -150,17 +157,15 @@
 //!
 //! // A little more cumbersome, but we get to use all the features of
 //! // normal Rust in metaprogramming and don't have to support an entire DSL.
-//! // In the future we will have more macros to help with this.
-//!
-//! let lt = lhs.ult(&rhs).unwrap();
+//! // All the branches are computed, and then `sel_!` selects between them
+//! // with the semantics of an `if`/`else if`/`else` chain. See also `mux_!`
+//! // for `match` semantics.
 //!
 //! let mut tmp0 = output;
 //! tmp0.xor_(&x).unwrap();
-//! output.mux_(&tmp0, lt).unwrap();
-//!
 //! let mut tmp1 = output;
 //! tmp1.lshr_(y.to_usize()).unwrap();
-//! output.mux_(&tmp1, !lt).unwrap();
+//! let output = sel_!(lhs.ult(&rhs).unwrap() => tmp0, _ => tmp1).unwrap();
 //!
 //! let output_eval = EvalAwi::from(&output);
 //!
@@ -177,21 +182,35 @@
 #![allow(clippy::comparison_chain)]
 
 mod awi_structs;
+pub mod cli;
+pub mod demo;
 /// Data structure internals used by this crate
 pub mod ensemble;
+pub mod interp;
 /// Internal definitions used in lowering
 pub mod lower;
+#[cfg(feature = "python")]
+pub mod python;
 /// WIP routing functionality
 pub mod route;
+pub mod sweep;
 /// Miscellanious utilities
 pub mod utils;
 pub use awi_structs::{
-    delay, epoch, Assertions, Drive, Epoch, EvalAwi, In, LazyAwi, Loop, Net, Out, SuspendedEpoch,
+    clock_crossing, crossbar, csr, delay, epoch, match_mux, priority_mux, pulse_synchronizer,
+    synchronizer, Assertions, Cam, Channel, CoSim, Csr, CsrAccess, CsrMap, Decoder, DecoderPattern,
+    Drive, Enum, EnumVariants, Epoch, EpochInfo, EvalAwi, Fifo, In, InOut, LazyAwi, Loop, Mem, Net,
+    Out, OverflowArith, Phase, Ports, Rom, Scope, ShrinkReport, Sim, SuspendedEpoch, ValueChange,
 };
 #[cfg(feature = "debug")]
 pub use awint::awint_dag::triple_arena_render;
 pub use awint::{self, awint_dag, awint_dag::triple_arena};
-pub use ensemble::{Corresponder, Delay};
+pub use ensemble::{
+    Attribution, Corresponder, DeadReport, Delay, EvalCacheStats, FlowReport, Limits,
+    LoweringReport, LutTableStats, MacroOp, OptimizePass, OptimizeReport, OptimizeSettings,
+    OutputReport, PowerModel, PowerReport, Profile, Provenance, ReachabilityReport, ReplayLog,
+    Replayed, TieBreak,
+};
 pub use utils::Error;
 
 /// Reexports all the regular arbitrary width integer structs, macros, common
@@ -211,6 +230,8 @@ pub mod dag {
         Result::{Err, Ok},
         *,
     };
+
+    pub use crate::csr;
 }
 
 // TODO use modified Lagrangians that appear different to nets with different
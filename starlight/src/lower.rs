@@ -1,5 +1,39 @@
+pub mod decompose;
 mod lower_op;
 mod lower_state;
 pub mod meta;
 
+pub use decompose::LutDecomposition;
 pub use lower_op::{lower_op, LowerManagement};
+pub use meta::FunnelStrategy;
+
+use crate::{dag, Error};
+
+/// Converts the `None` of a mimicking operation or width calculation into an
+/// error. The `Op`s are validated before lowering, so this is only reached if
+/// a bitwidth invariant of the lowering functions is broken.
+pub(crate) trait LowerOk<T> {
+    fn lower_ok(self) -> Result<T, Error>;
+}
+
+const BROKEN_INVARIANT: Error =
+    Error::OtherStr("a bitwidth invariant was broken while lowering an `Op`");
+
+impl<T> LowerOk<T> for Option<T> {
+    fn lower_ok(self) -> Result<T, Error> {
+        self.ok_or(BROKEN_INVARIANT)
+    }
+}
+
+impl<T> LowerOk<T> for dag::Option<T> {
+    #[track_caller]
+    fn lower_ok(self) -> Result<T, Error> {
+        if self.is_none_at_runtime() {
+            Err(BROKEN_INVARIANT)
+        } else {
+            // an `Opaque` from a dynamic condition always holds a value, and
+            // becomes an assertion bit the same way as with `unwrap`
+            Ok(self.unwrap())
+        }
+    }
+}
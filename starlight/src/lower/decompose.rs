@@ -0,0 +1,217 @@
+//! Truth table decompositions for splitting lookup tables that have more
+//! inputs than a target allows
+
+use std::num::NonZeroUsize;
+
+use awint::awi::*;
+
+use crate::ensemble::{Bdd, LNode};
+
+/// The maximum number of bound sets that [find_curtis_decomposition] tries
+pub const MAX_CURTIS_CANDIDATES: usize = 256;
+
+/// How lookup tables with more inputs than
+/// [Limits::max_single_lut_inputs](crate::Limits::max_single_lut_inputs) are
+/// split into smaller ones during lowering and by
+/// [Epoch::decompose_luts](crate::Epoch::decompose_luts)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LutDecomposition {
+    /// Split the table into chunks indexed by the least significant inputs
+    /// which are then selected between by the most significant inputs. This
+    /// is fast and has a predictable shape, but does not take advantage of
+    /// any structure in the function.
+    #[default]
+    Chunked,
+    /// Recursively use Shannon expansion on the input that leaves the most
+    /// inputs of the cofactors independent, and remove independent inputs
+    Shannon,
+    /// Use Ashenhurst-Curtis decomposition when a bound set of inputs can be
+    /// encoded into fewer intermediate signals, falling back to `Shannon`
+    /// expansion otherwise
+    Curtis,
+}
+
+/// Returns the number of inputs of `lut`
+fn num_inputs(lut: &Bits) -> usize {
+    debug_assert!(lut.bw().is_power_of_two());
+    lut.bw().trailing_zeros() as usize
+}
+
+/// Returns if the output of `lut` does not depend on input `i`
+pub fn is_independent(lut: &Bits, i: usize) -> bool {
+    let (lut0, lut1) = shannon_cofactors(lut, i);
+    lut0 == lut1
+}
+
+/// Returns the negative and positive cofactors of `lut` with respect to input
+/// `i`, which are the tables with input `i` fixed to `false` and `true`
+/// respectively and removed from the inputs
+pub fn shannon_cofactors(lut: &Bits, i: usize) -> (Awi, Awi) {
+    let mut lut0 = Awi::from(lut);
+    let mut lut1 = Awi::from(lut);
+    LNode::reduce_lut(&mut lut0, i, false);
+    LNode::reduce_lut(&mut lut1, i, true);
+    (lut0, lut1)
+}
+
+/// Returns the input that is best to use for Shannon expansion, which is the
+/// one that leaves the most inputs of the cofactors independent, preferring
+/// more significant inputs. `lut` must have at least one input.
+pub fn shannon_variable(lut: &Bits) -> usize {
+    let n = num_inputs(lut);
+    let mut best = (0, n - 1);
+    for i in (0..n).rev() {
+        let (lut0, lut1) = shannon_cofactors(lut, i);
+        let mut independent = 0;
+        for j in 0..(n - 1) {
+            independent += usize::from(is_independent(&lut0, j));
+            independent += usize::from(is_independent(&lut1, j));
+        }
+        if independent > best.0 {
+            best = (independent, i);
+        }
+    }
+    best.1
+}
+
+/// A disjoint Ashenhurst-Curtis decomposition `f(X) = h(F, g(B))`, where the
+/// inputs `X` are split into the bound set `B` and free set `F`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurtisDecomposition {
+    /// The indexes of the inputs in the bound set, in increasing order
+    pub bound: Vec<usize>,
+    /// The indexes of the inputs in the free set, in increasing order
+    pub free: Vec<usize>,
+    /// The tables of the functions `g` which encode the bound set, each with
+    /// the bound set as inputs
+    pub bound_luts: Vec<Awi>,
+    /// The table of `h`, which has the free set followed by the outputs of
+    /// the `bound_luts` as inputs
+    pub composition_lut: Awi,
+}
+
+/// Scatters the bits of `x` to the positions of `inxs`
+fn scatter(x: usize, inxs: &[usize]) -> usize {
+    let mut res = 0;
+    for (j, i) in inxs.iter().copied().enumerate() {
+        res |= ((x >> j) & 1) << i;
+    }
+    res
+}
+
+/// Attempts a disjoint Ashenhurst-Curtis decomposition of `lut` with the
+/// inputs `bound` as the bound set, returning `None` if the column multiplicity
+/// is too high for the decomposition to have fewer inputs than `lut`, or if
+/// the free set would be empty
+pub fn curtis_decomposition(lut: &Bits, bound: &[usize]) -> Option<CurtisDecomposition> {
+    let n = num_inputs(lut);
+    let mut bound = bound.to_vec();
+    bound.sort_unstable();
+    bound.dedup();
+    if bound.is_empty() || bound.iter().any(|i| *i >= n) {
+        return None
+    }
+    let free: Vec<usize> = (0..n).filter(|i| !bound.contains(i)).collect();
+    if free.is_empty() {
+        return None
+    }
+    // find the distinct columns of the decomposition chart
+    let column_w = NonZeroUsize::new(1 << free.len()).unwrap();
+    let mut columns: Vec<Awi> = vec![];
+    let mut classes = vec![];
+    for b in 0..(1usize << bound.len()) {
+        let b_inx = scatter(b, &bound);
+        let mut column = Awi::zero(column_w);
+        for f in 0..column_w.get() {
+            column
+                .set(f, lut.get(b_inx | scatter(f, &free)).unwrap())
+                .unwrap();
+        }
+        if let Some(class) = columns.iter().position(|c| *c == column) {
+            classes.push(class);
+        } else {
+            classes.push(columns.len());
+            columns.push(column);
+        }
+    }
+    // the number of bits needed to encode the column classes
+    let r = encoding_bits(columns.len());
+    if r >= bound.len() {
+        return None
+    }
+    let bound_w = NonZeroUsize::new(1 << bound.len()).unwrap();
+    let mut bound_luts = vec![];
+    for j in 0..r {
+        let mut g = Awi::zero(bound_w);
+        for (b, class) in classes.iter().copied().enumerate() {
+            g.set(b, ((class >> j) & 1) != 0).unwrap();
+        }
+        bound_luts.push(g);
+    }
+    // unused codes are don't-cares, set them to the first column
+    let mut composition_lut = Awi::zero(NonZeroUsize::new(1 << (free.len() + r)).unwrap());
+    for c in 0..(1usize << r) {
+        let column = columns.get(c).unwrap_or(&columns[0]);
+        for f in 0..column_w.get() {
+            composition_lut
+                .set(f | (c << free.len()), column.get(f).unwrap())
+                .unwrap();
+        }
+    }
+    Some(CurtisDecomposition {
+        bound,
+        free,
+        bound_luts,
+        composition_lut,
+    })
+}
+
+/// Returns the number of bits needed to encode `num` distinct values
+fn encoding_bits(num: usize) -> usize {
+    (num - 1)
+        .checked_ilog2()
+        .map(|x| x as usize + 1)
+        .unwrap_or(0)
+}
+
+/// Searches bound sets of `max_bound` inputs (or one less than the number of
+/// inputs of `lut` if smaller) for the Ashenhurst-Curtis decomposition with
+/// the fewest encoding functions, trying at most [MAX_CURTIS_CANDIDATES]
+/// bound sets. The candidates are compared by column multiplicity with a
+/// [Bdd], and only the best one is tabulated. Returns `None` if no beneficial
+/// decomposition was found.
+pub fn find_curtis_decomposition(lut: &Bits, max_bound: usize) -> Option<CurtisDecomposition> {
+    let n = num_inputs(lut);
+    let s = max_bound.min(n.checked_sub(1)?);
+    if s == 0 {
+        return None
+    }
+    let mut bdd = Bdd::new();
+    let f = bdd.apply_lut_vars(lut);
+    // the best number of encoding functions and its bound set
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    // iterate over combinations in lexicographic order
+    let mut bound: Vec<usize> = (0..s).collect();
+    'outer: for _ in 0..MAX_CURTIS_CANDIDATES {
+        let r = encoding_bits(bdd.column_multiplicity(f, &bound));
+        if (r < s) && best.as_ref().map(|best| r < best.0).unwrap_or(true) {
+            best = Some((r, bound.clone()));
+        }
+        // advance to the next combination
+        let mut i = s;
+        loop {
+            if i == 0 {
+                break 'outer
+            }
+            i -= 1;
+            if bound[i] < (n - s + i) {
+                break
+            }
+        }
+        bound[i] += 1;
+        for j in (i + 1)..s {
+            bound[j] = bound[j - 1] + 1;
+        }
+    }
+    best.and_then(|(_, bound)| curtis_decomposition(lut, &bound))
+}
@@ -16,16 +16,20 @@ use awint::{
 };
 use dag::{awi, inlawi, Awi, Bits, InlAwi};
 
-use super::meta::*;
+use super::{meta::*, LowerOk};
 use crate::{awi, dag, Error};
 
+/// The interface that `lower_op` uses to interact with the state graph.
+/// Implementors should return errors instead of panicking on user reachable
+/// conditions.
 pub trait LowerManagement<P: Ptr + DummyDefault> {
-    fn graft(&mut self, output_and_operands: &[PState]);
+    fn graft(&mut self, output_and_operands: &[PState]) -> Result<(), Error>;
     fn get_nzbw(&self, p: P) -> NonZeroUsize;
     fn is_literal(&self, p: P) -> bool;
-    fn usize(&self, p: P) -> usize;
-    fn bool(&self, p: P) -> bool;
-    fn dec_rc(&mut self, p: P);
+    fn usize(&self, p: P) -> Result<usize, Error>;
+    fn bool(&self, p: P) -> Result<bool, Error>;
+    fn dec_rc(&mut self, p: P) -> Result<(), Error>;
+    fn funnel_strategy(&self) -> FunnelStrategy;
 }
 
 /// Returns if the lowering is done
@@ -47,8 +51,8 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let mut out = Awi::zero(out_w);
                 let lut = Awi::opaque(m.get_nzbw(lut));
                 let inx = Awi::opaque(m.get_nzbw(inx));
-                dynamic_to_static_lut(&mut out, &lut, &inx);
-                m.graft(&[out.state(), lut.state(), inx.state()]);
+                dynamic_to_static_lut(&mut out, &lut, &inx)?;
+                m.graft(&[out.state(), lut.state(), inx.state()])?;
             }
         }
         Get([bits, inx]) => {
@@ -59,8 +63,8 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             } else {
                 let bits = Awi::opaque(m.get_nzbw(bits));
                 let inx = Awi::opaque(m.get_nzbw(inx));
-                let out = dynamic_to_static_get(&bits, &inx);
-                m.graft(&[out.state(), bits.state(), inx.state()]);
+                let out = dynamic_to_static_get(&bits, &inx)?;
+                m.graft(&[out.state(), bits.state(), inx.state()])?;
             }
         }
         Set([bits, inx, bit]) => {
@@ -72,92 +76,92 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let bits = Awi::opaque(m.get_nzbw(bits));
                 let inx = Awi::opaque(m.get_nzbw(inx));
                 let bit = Awi::opaque(m.get_nzbw(bit));
-                let out = dynamic_to_static_set(&bits, &inx, &bit);
-                m.graft(&[out.state(), bits.state(), inx.state(), bit.state()]);
+                let out = dynamic_to_static_set(&bits, &inx, &bit)?;
+                m.graft(&[out.state(), bits.state(), inx.state(), bit.state()])?;
             }
         }
         FieldBit([lhs, to, rhs, from]) => {
             let rhs = Awi::opaque(m.get_nzbw(rhs));
             let from = Awi::opaque(m.get_nzbw(from));
-            let bit = rhs.get(from.to_usize()).unwrap();
+            let bit = rhs.get(from.to_usize()).lower_ok()?;
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let to = Awi::opaque(m.get_nzbw(to));
             // keep `lhs` the same, `out` has the set bit
             let mut out = lhs.clone();
-            out.set(to.to_usize(), bit).unwrap();
+            out.set(to.to_usize(), bit).lower_ok()?;
             m.graft(&[
                 out.state(),
                 lhs.state(),
                 to.state(),
                 rhs.state(),
                 from.state(),
-            ]);
+            ])?;
         }
         ZeroResize([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = resize(&x, out_w, false);
-            m.graft(&[out.state(), x.state()]);
+            let out = resize(&x, out_w, false)?;
+            m.graft(&[out.state(), x.state()])?;
         }
         SignResize([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = resize(&x, out_w, true);
-            m.graft(&[out.state(), x.state()]);
+            let out = resize(&x, out_w, true)?;
+            m.graft(&[out.state(), x.state()])?;
         }
         Resize([x, b]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let b = Awi::opaque(m.get_nzbw(b));
-            let out = resize_cond(&x, out_w, &b);
-            m.graft(&[out.state(), x.state(), b.state()]);
+            let out = resize_cond(&x, out_w, &b)?;
+            m.graft(&[out.state(), x.state(), b.state()])?;
         }
         Lsb([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = x.get(0).unwrap();
-            m.graft(&[out.state(), x.state()]);
+            let out = x.get(0).lower_ok()?;
+            m.graft(&[out.state(), x.state()])?;
         }
         Msb([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = x.get(x.bw() - 1).unwrap();
-            m.graft(&[out.state(), x.state()]);
+            let out = x.get(x.bw() - 1).lower_ok()?;
+            m.graft(&[out.state(), x.state()])?;
         }
         FieldWidth([lhs, rhs, width]) => {
             let lhs_w = m.get_nzbw(lhs);
             let rhs_w = m.get_nzbw(rhs);
             let width_w = m.get_nzbw(width);
             if m.is_literal(width) {
-                let width_u = m.usize(width);
+                let width_u = m.usize(width)?;
                 let lhs = Awi::opaque(lhs_w);
                 let rhs = Awi::opaque(rhs_w);
                 // If `width_u` is out of bounds `out` is created as a no-op of `lhs` as
                 // expected
-                let out = Bits::static_field(&lhs, 0, &rhs, 0, width_u).unwrap();
+                let out = Bits::static_field(&lhs, 0, &rhs, 0, width_u).lower_ok()?;
                 m.graft(&[
                     out.state(),
                     lhs.state(),
                     rhs.state(),
                     Awi::opaque(width_w).state(),
-                ]);
+                ])?;
             } else {
                 let lhs = Awi::opaque(lhs_w);
                 let rhs = Awi::opaque(rhs_w);
                 let width = Awi::opaque(width_w);
                 let max = min(lhs_w, rhs_w).get();
                 let success = Bits::efficient_ule(width.to_usize(), max);
-                let max_width_w = Bits::nontrivial_bits(max).unwrap();
+                let max_width_w = Bits::nontrivial_bits(max).lower_ok()?;
                 let width_small =
                     Bits::static_field(&Awi::zero(max_width_w), 0, &width, 0, max_width_w.get())
-                        .unwrap();
+                        .lower_ok()?;
                 // to achieve a no-op we simply set the width to zero
                 let mut tmp_width = Awi::zero(max_width_w);
-                tmp_width.mux_(&width_small, success.is_some()).unwrap();
-                let out = field_width(&lhs, &rhs, &tmp_width);
-                m.graft(&[out.state(), lhs.state(), rhs.state(), width.state()]);
+                tmp_width.mux_(&width_small, success.is_some()).lower_ok()?;
+                let out = field_width(&lhs, &rhs, &tmp_width)?;
+                m.graft(&[out.state(), lhs.state(), rhs.state(), width.state()])?;
             }
         }
         Funnel([x, s]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let s = Awi::opaque(m.get_nzbw(s));
-            let out = funnel(&x, &s);
-            m.graft(&[out.state(), x.state(), s.state()]);
+            let out = funnel_with(&x, &s, m.funnel_strategy())?;
+            m.graft(&[out.state(), x.state(), s.state()])?;
         }
         RangeOr([x, start, end]) => {
             let x = Awi::opaque(m.get_nzbw(x));
@@ -166,15 +170,16 @@ pub fn lower_op<P: Ptr + DummyDefault>(
 
             let success = Bits::efficient_ule(start.to_usize(), x.bw()).is_some()
                 & Bits::efficient_ule(end.to_usize(), x.bw()).is_some();
-            let max_w = Bits::nontrivial_bits(x.bw()).unwrap();
+            let max_w = Bits::nontrivial_bits(x.bw()).lower_ok()?;
             let start_small =
-                Bits::static_field(&Awi::zero(max_w), 0, &start, 0, max_w.get()).unwrap();
-            let end_small = Bits::static_field(&Awi::zero(max_w), 0, &end, 0, max_w.get()).unwrap();
+                Bits::static_field(&Awi::zero(max_w), 0, &start, 0, max_w.get()).lower_ok()?;
+            let end_small =
+                Bits::static_field(&Awi::zero(max_w), 0, &end, 0, max_w.get()).lower_ok()?;
             // to achieve a no-op we just need to set the end to 0
             let mut tmp_end = Awi::zero(max_w);
-            tmp_end.mux_(&end_small, success).unwrap();
-            let out = range_or(&x, &start_small, &tmp_end);
-            m.graft(&[out.state(), x.state(), start.state(), end.state()]);
+            tmp_end.mux_(&end_small, success).lower_ok()?;
+            let out = range_or(&x, &start_small, &tmp_end)?;
+            m.graft(&[out.state(), x.state(), start.state(), end.state()])?;
         }
         RangeAnd([x, start, end]) => {
             let x = Awi::opaque(m.get_nzbw(x));
@@ -183,19 +188,20 @@ pub fn lower_op<P: Ptr + DummyDefault>(
 
             let success = Bits::efficient_ule(start.to_usize(), x.bw()).is_some()
                 & Bits::efficient_ule(end.to_usize(), x.bw()).is_some();
-            let max_w = Bits::nontrivial_bits(x.bw()).unwrap();
+            let max_w = Bits::nontrivial_bits(x.bw()).lower_ok()?;
             let start_small =
-                Bits::static_field(&Awi::zero(max_w), 0, &start, 0, max_w.get()).unwrap();
-            let end_small = Bits::static_field(&Awi::zero(max_w), 0, &end, 0, max_w.get()).unwrap();
+                Bits::static_field(&Awi::zero(max_w), 0, &start, 0, max_w.get()).lower_ok()?;
+            let end_small =
+                Bits::static_field(&Awi::zero(max_w), 0, &end, 0, max_w.get()).lower_ok()?;
             // to achieve a no-op we need to set a full range with `start` being zero and
             // `end` being `x.bw()`
             let mut tmp_start = Awi::zero(max_w);
-            tmp_start.mux_(&start_small, success).unwrap();
+            tmp_start.mux_(&start_small, success).lower_ok()?;
             let mut tmp_end = Awi::zero(max_w);
             tmp_end.usize_(x.bw());
-            tmp_end.mux_(&end_small, success).unwrap();
-            let out = range_and(&x, &tmp_start, &tmp_end);
-            m.graft(&[out.state(), x.state(), start.state(), end.state()]);
+            tmp_end.mux_(&end_small, success).lower_ok()?;
+            let out = range_and(&x, &tmp_start, &tmp_end)?;
+            m.graft(&[out.state(), x.state(), start.state(), end.state()])?;
         }
         RangeXor([x, start, end]) => {
             let x = Awi::opaque(m.get_nzbw(x));
@@ -204,15 +210,16 @@ pub fn lower_op<P: Ptr + DummyDefault>(
 
             let success = Bits::efficient_ule(start.to_usize(), x.bw()).is_some()
                 & Bits::efficient_ule(end.to_usize(), x.bw()).is_some();
-            let max_w = Bits::nontrivial_bits(x.bw()).unwrap();
+            let max_w = Bits::nontrivial_bits(x.bw()).lower_ok()?;
             let start_small =
-                Bits::static_field(&Awi::zero(max_w), 0, &start, 0, max_w.get()).unwrap();
-            let end_small = Bits::static_field(&Awi::zero(max_w), 0, &end, 0, max_w.get()).unwrap();
+                Bits::static_field(&Awi::zero(max_w), 0, &start, 0, max_w.get()).lower_ok()?;
+            let end_small =
+                Bits::static_field(&Awi::zero(max_w), 0, &end, 0, max_w.get()).lower_ok()?;
             // to achieve a no-op we just need to set the end to 0
             let mut tmp_end = Awi::zero(max_w);
-            tmp_end.mux_(&end_small, success).unwrap();
-            let out = range_xor(&x, &start_small, &tmp_end);
-            m.graft(&[out.state(), x.state(), start.state(), end.state()]);
+            tmp_end.mux_(&end_small, success).lower_ok()?;
+            let out = range_xor(&x, &start_small, &tmp_end)?;
+            m.graft(&[out.state(), x.state(), start.state(), end.state()])?;
         }
         FieldFrom([lhs, rhs, from, width]) => {
             let lhs_w = m.get_nzbw(lhs);
@@ -222,7 +229,7 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let lhs = Awi::opaque(lhs_w);
                 let rhs = Awi::opaque(rhs_w);
                 let width = Awi::opaque(m.get_nzbw(width));
-                let from_u = m.usize(from);
+                let from_u = m.usize(from)?;
                 let out = if rhs.bw() <= from_u {
                     lhs.clone()
                 } else {
@@ -247,7 +254,7 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                     rhs.state(),
                     Awi::opaque(m.get_nzbw(from)).state(),
                     width.state(),
-                ]);
+                ])?;
             } else {
                 let lhs = Awi::opaque(lhs_w);
                 let rhs = Awi::opaque(rhs_w);
@@ -257,35 +264,35 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let success =
                     Bits::efficient_add_then_ule(from.to_usize(), width.to_usize(), rhs.bw());
                 let max = min(lhs.bw(), rhs.bw());
-                let max_width_w = Bits::nontrivial_bits(max).unwrap();
+                let max_width_w = Bits::nontrivial_bits(max).lower_ok()?;
                 let width_small =
                     Bits::static_field(&Awi::zero(max_width_w), 0, &width, 0, max_width_w.get())
-                        .unwrap();
+                        .lower_ok()?;
                 // to achieve a no-op we simply set the width to zero
                 let mut tmp_width = Awi::zero(max_width_w);
-                tmp_width.mux_(&width_small, success.is_some()).unwrap();
+                tmp_width.mux_(&width_small, success.is_some()).lower_ok()?;
                 // the optimizations on `width` are done later on an inner `field_width` call
-                let out = field_from(&lhs, &rhs, &from, &tmp_width);
+                let out = field_from(&lhs, &rhs, &from, &tmp_width, m.funnel_strategy())?;
                 m.graft(&[
                     out.state(),
                     lhs.state(),
                     rhs.state(),
                     from.state(),
                     width.state(),
-                ]);
+                ])?;
             }
         }
         Shl([x, s]) => {
             if m.is_literal(s) {
                 let x = Awi::opaque(m.get_nzbw(x));
-                let s_u = m.usize(s);
+                let s_u = m.usize(s)?;
                 let out = if (s_u == 0) || (x.bw() <= s_u) {
                     x.clone()
                 } else {
                     let tmp = Awi::zero(x.nzbw());
-                    Bits::static_field(&tmp, s_u, &x, 0, x.bw() - s_u).unwrap()
+                    Bits::static_field(&tmp, s_u, &x, 0, x.bw() - s_u).lower_ok()?
                 };
-                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()]);
+                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()])?;
             } else {
                 let x = Awi::opaque(m.get_nzbw(x));
                 let s = Awi::opaque(m.get_nzbw(s));
@@ -293,25 +300,25 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let success = Bits::efficient_ule(s.to_usize(), x.bw() - 1);
                 let max_s_w = Bits::nontrivial_bits(x.bw() - 1).unwrap_or(bw(1));
                 let s_small =
-                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).unwrap();
+                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).lower_ok()?;
                 // to achieve a no-op we simply set the shift to zero
                 let mut tmp_s = Awi::zero(max_s_w);
-                tmp_s.mux_(&s_small, success.is_some()).unwrap();
-                let out = shl(&x, &tmp_s);
-                m.graft(&[out.state(), x.state(), s.state()]);
+                tmp_s.mux_(&s_small, success.is_some()).lower_ok()?;
+                let out = shl(&x, &tmp_s, m.funnel_strategy())?;
+                m.graft(&[out.state(), x.state(), s.state()])?;
             }
         }
         Lshr([x, s]) => {
             if m.is_literal(s) {
                 let x = Awi::opaque(m.get_nzbw(x));
-                let s_u = m.usize(s);
+                let s_u = m.usize(s)?;
                 let out = if (s_u == 0) || (x.bw() <= s_u) {
                     x.clone()
                 } else {
                     let tmp = Awi::zero(x.nzbw());
-                    Bits::static_field(&tmp, 0, &x, s_u, x.bw() - s_u).unwrap()
+                    Bits::static_field(&tmp, 0, &x, s_u, x.bw() - s_u).lower_ok()?
                 };
-                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()]);
+                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()])?;
             } else {
                 let x = Awi::opaque(m.get_nzbw(x));
                 let s = Awi::opaque(m.get_nzbw(s));
@@ -319,27 +326,27 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let success = Bits::efficient_ule(s.to_usize(), x.bw() - 1);
                 let max_s_w = Bits::nontrivial_bits(x.bw() - 1).unwrap_or(bw(1));
                 let s_small =
-                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).unwrap();
+                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).lower_ok()?;
                 let mut tmp_s = Awi::zero(max_s_w);
-                tmp_s.mux_(&s_small, success.is_some()).unwrap();
-                let out = lshr(&x, &tmp_s);
-                m.graft(&[out.state(), x.state(), s.state()]);
+                tmp_s.mux_(&s_small, success.is_some()).lower_ok()?;
+                let out = lshr(&x, &tmp_s, m.funnel_strategy())?;
+                m.graft(&[out.state(), x.state(), s.state()])?;
             }
         }
         Ashr([x, s]) => {
             if m.is_literal(s) {
                 let x = Awi::opaque(m.get_nzbw(x));
-                let s_u = m.usize(s);
+                let s_u = m.usize(s)?;
                 let out = if (s_u == 0) || (x.bw() <= s_u) {
                     x.clone()
                 } else {
                     let mut tmp = Awi::zero(x.nzbw());
                     for i in 0..x.bw() {
-                        tmp.set(i, x.msb()).unwrap();
+                        tmp.set(i, x.msb()).lower_ok()?;
                     }
-                    Bits::static_field(&tmp, 0, &x, s_u, x.bw() - s_u).unwrap()
+                    Bits::static_field(&tmp, 0, &x, s_u, x.bw() - s_u).lower_ok()?
                 };
-                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()]);
+                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()])?;
             } else {
                 let x = Awi::opaque(m.get_nzbw(x));
                 let s = Awi::opaque(m.get_nzbw(s));
@@ -347,25 +354,25 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let success = Bits::efficient_ule(s.to_usize(), x.bw() - 1);
                 let max_s_w = Bits::nontrivial_bits(x.bw() - 1).unwrap_or(bw(1));
                 let s_small =
-                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).unwrap();
+                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).lower_ok()?;
                 let mut tmp_s = Awi::zero(max_s_w);
-                tmp_s.mux_(&s_small, success.is_some()).unwrap();
-                let out = ashr(&x, &tmp_s);
-                m.graft(&[out.state(), x.state(), s.state()]);
+                tmp_s.mux_(&s_small, success.is_some()).lower_ok()?;
+                let out = ashr(&x, &tmp_s, m.funnel_strategy())?;
+                m.graft(&[out.state(), x.state(), s.state()])?;
             }
         }
         Rotl([x, s]) => {
             if m.is_literal(s) {
                 let x = Awi::opaque(m.get_nzbw(x));
-                let s_u = m.usize(s);
+                let s_u = m.usize(s)?;
                 let out = if (s_u == 0) || (x.bw() <= s_u) {
                     x.clone()
                 } else {
-                    let tmp =
-                        Bits::static_field(&Awi::zero(x.nzbw()), s_u, &x, 0, x.bw() - s_u).unwrap();
-                    Bits::static_field(&tmp, 0, &x, x.bw() - s_u, s_u).unwrap()
+                    let tmp = Bits::static_field(&Awi::zero(x.nzbw()), s_u, &x, 0, x.bw() - s_u)
+                        .lower_ok()?;
+                    Bits::static_field(&tmp, 0, &x, x.bw() - s_u, s_u).lower_ok()?
                 };
-                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()]);
+                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()])?;
             } else {
                 let x = Awi::opaque(m.get_nzbw(x));
                 let s = Awi::opaque(m.get_nzbw(s));
@@ -373,25 +380,25 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let success = Bits::efficient_ule(s.to_usize(), x.bw() - 1);
                 let max_s_w = Bits::nontrivial_bits(x.bw() - 1).unwrap_or(bw(1));
                 let s_small =
-                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).unwrap();
+                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).lower_ok()?;
                 let mut tmp_s = Awi::zero(max_s_w);
-                tmp_s.mux_(&s_small, success.is_some()).unwrap();
-                let out = rotl(&x, &tmp_s);
-                m.graft(&[out.state(), x.state(), s.state()]);
+                tmp_s.mux_(&s_small, success.is_some()).lower_ok()?;
+                let out = rotl(&x, &tmp_s, m.funnel_strategy())?;
+                m.graft(&[out.state(), x.state(), s.state()])?;
             }
         }
         Rotr([x, s]) => {
             if m.is_literal(s) {
                 let x = Awi::opaque(m.get_nzbw(x));
-                let s_u = m.usize(s);
+                let s_u = m.usize(s)?;
                 let out = if (s_u == 0) || (x.bw() <= s_u) {
                     x.clone()
                 } else {
-                    let tmp =
-                        Bits::static_field(&Awi::zero(x.nzbw()), 0, &x, s_u, x.bw() - s_u).unwrap();
-                    Bits::static_field(&tmp, x.bw() - s_u, &x, 0, s_u).unwrap()
+                    let tmp = Bits::static_field(&Awi::zero(x.nzbw()), 0, &x, s_u, x.bw() - s_u)
+                        .lower_ok()?;
+                    Bits::static_field(&tmp, x.bw() - s_u, &x, 0, s_u).lower_ok()?
                 };
-                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()]);
+                m.graft(&[out.state(), x.state(), Awi::opaque(m.get_nzbw(s)).state()])?;
             } else {
                 let x = Awi::opaque(m.get_nzbw(x));
                 let s = Awi::opaque(m.get_nzbw(s));
@@ -399,17 +406,17 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let success = Bits::efficient_ule(s.to_usize(), x.bw() - 1);
                 let max_s_w = Bits::nontrivial_bits(x.bw() - 1).unwrap_or(bw(1));
                 let s_small =
-                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).unwrap();
+                    Bits::static_field(&Awi::zero(max_s_w), 0, &s, 0, max_s_w.get()).lower_ok()?;
                 let mut tmp_s = Awi::zero(max_s_w);
-                tmp_s.mux_(&s_small, success.is_some()).unwrap();
-                let out = rotr(&x, &tmp_s);
-                m.graft(&[out.state(), x.state(), s.state()]);
+                tmp_s.mux_(&s_small, success.is_some()).lower_ok()?;
+                let out = rotr(&x, &tmp_s, m.funnel_strategy())?;
+                m.graft(&[out.state(), x.state(), s.state()])?;
             }
         }
         Not([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = bitwise_not(&x);
-            m.graft(&[out.state(), x.state()]);
+            let out = bitwise_not(&x)?;
+            m.graft(&[out.state(), x.state()])?;
         }
         Or([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
@@ -417,8 +424,8 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let out = bitwise(&lhs, &rhs, {
                 use awi::*;
                 awi!(1110)
-            });
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            })?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         And([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
@@ -426,8 +433,8 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let out = bitwise(&lhs, &rhs, {
                 use awi::*;
                 awi!(1000)
-            });
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            })?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Xor([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
@@ -435,72 +442,72 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let out = bitwise(&lhs, &rhs, {
                 use awi::*;
                 awi!(0110)
-            });
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            })?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Inc([x, cin]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let cin = Awi::opaque(m.get_nzbw(cin));
-            let out = incrementer(&x, &cin, false).0;
-            m.graft(&[out.state(), x.state(), cin.state()]);
+            let out = incrementer(&x, &cin, false)?.0;
+            m.graft(&[out.state(), x.state(), cin.state()])?;
         }
         IncCout([x, cin]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let cin = Awi::opaque(m.get_nzbw(cin));
-            let out = incrementer(&x, &cin, false).1;
-            m.graft(&[out.state(), x.state(), cin.state()]);
+            let out = incrementer(&x, &cin, false)?.1;
+            m.graft(&[out.state(), x.state(), cin.state()])?;
         }
         Dec([x, cin]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let cin = Awi::opaque(m.get_nzbw(cin));
-            let out = incrementer(&x, &cin, true).0;
-            m.graft(&[out.state(), x.state(), cin.state()]);
+            let out = incrementer(&x, &cin, true)?.0;
+            m.graft(&[out.state(), x.state(), cin.state()])?;
         }
         DecCout([x, cin]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let cin = Awi::opaque(m.get_nzbw(cin));
-            let out = incrementer(&x, &cin, true).1;
-            m.graft(&[out.state(), x.state(), cin.state()]);
+            let out = incrementer(&x, &cin, true)?.1;
+            m.graft(&[out.state(), x.state(), cin.state()])?;
         }
         CinSum([cin, lhs, rhs]) => {
             let cin = Awi::opaque(m.get_nzbw(cin));
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let out = cin_sum(&cin, &lhs, &rhs).0;
-            m.graft(&[out.state(), cin.state(), lhs.state(), rhs.state()]);
+            let out = cin_sum(&cin, &lhs, &rhs)?.0;
+            m.graft(&[out.state(), cin.state(), lhs.state(), rhs.state()])?;
         }
         UnsignedOverflow([cin, lhs, rhs]) => {
             let cin = Awi::opaque(m.get_nzbw(cin));
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let out = cin_sum(&cin, &lhs, &rhs).1;
-            m.graft(&[out.state(), cin.state(), lhs.state(), rhs.state()]);
+            let out = cin_sum(&cin, &lhs, &rhs)?.1;
+            m.graft(&[out.state(), cin.state(), lhs.state(), rhs.state()])?;
         }
         SignedOverflow([cin, lhs, rhs]) => {
             let cin = Awi::opaque(m.get_nzbw(cin));
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let out = cin_sum(&cin, &lhs, &rhs).2;
-            m.graft(&[out.state(), cin.state(), lhs.state(), rhs.state()]);
+            let out = cin_sum(&cin, &lhs, &rhs)?.2;
+            m.graft(&[out.state(), cin.state(), lhs.state(), rhs.state()])?;
         }
         Neg([x, neg]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let neg = Awi::opaque(m.get_nzbw(neg));
             debug_assert_eq!(neg.bw(), 1);
-            let out = negator(&x, &neg);
-            m.graft(&[out.state(), x.state(), neg.state()]);
+            let out = negator(&x, &neg)?;
+            m.graft(&[out.state(), x.state(), neg.state()])?;
         }
         Abs([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let mut out = x.clone();
             out.neg_(x.msb());
-            m.graft(&[out.state(), x.state()]);
+            m.graft(&[out.state(), x.state()])?;
         }
         Add([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let out = cin_sum(&inlawi!(0), &lhs, &rhs).0;
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            let out = cin_sum(&inlawi!(0), &lhs, &rhs)?.0;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Sub([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
@@ -508,23 +515,23 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut rhs_tmp = rhs.clone();
             rhs_tmp.neg_(true);
             let mut out = lhs.clone();
-            out.add_(&rhs_tmp).unwrap();
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            out.add_(&rhs_tmp).lower_ok()?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Rsb([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
             let mut out = lhs.clone();
             out.neg_(true);
-            out.add_(&rhs).unwrap();
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            out.add_(&rhs).lower_ok()?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         FieldTo([lhs, to, rhs, width]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
             let width = Awi::opaque(m.get_nzbw(width));
             if m.is_literal(to) {
-                let to_u = m.usize(to);
+                let to_u = m.usize(to)?;
 
                 let out = if lhs.bw() < to_u {
                     lhs.clone()
@@ -533,7 +540,7 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                         Bits::static_field(&Awi::zero(w), 0, &lhs, to_u, w.get())
                     {
                         let _ = lhs_hi.field_width(&rhs, width.to_usize());
-                        Bits::static_field(&lhs, to_u, &lhs_hi, 0, w.get()).unwrap()
+                        Bits::static_field(&lhs, to_u, &lhs_hi, 0, w.get()).lower_ok()?
                     } else {
                         lhs.clone()
                     }
@@ -546,29 +553,29 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                     Awi::opaque(m.get_nzbw(to)).state(),
                     rhs.state(),
                     width.state(),
-                ]);
+                ])?;
             } else {
                 let to = Awi::opaque(m.get_nzbw(to));
 
                 let success =
                     Bits::efficient_add_then_ule(to.to_usize(), width.to_usize(), lhs.bw());
                 let max = min(lhs.bw(), rhs.bw());
-                let max_width_w = Bits::nontrivial_bits(max).unwrap();
+                let max_width_w = Bits::nontrivial_bits(max).lower_ok()?;
                 let width_small =
                     Bits::static_field(&Awi::zero(max_width_w), 0, &width, 0, max_width_w.get())
-                        .unwrap();
+                        .lower_ok()?;
                 // to achieve a no-op we simply set the width to zero
                 let mut tmp_width = Awi::zero(max_width_w);
-                tmp_width.mux_(&width_small, success.is_some()).unwrap();
+                tmp_width.mux_(&width_small, success.is_some()).lower_ok()?;
 
-                let out = field_to(&lhs, &to, &rhs, &tmp_width);
+                let out = field_to(&lhs, &to, &rhs, &tmp_width, m.funnel_strategy())?;
                 m.graft(&[
                     out.state(),
                     lhs.state(),
                     to.state(),
                     rhs.state(),
                     width.state(),
-                ]);
+                ])?;
             }
         }
         Field([lhs, to, rhs, from, width]) => {
@@ -579,7 +586,7 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                 let to = Awi::opaque(m.get_nzbw(to));
                 let from = Awi::opaque(m.get_nzbw(from));
                 let min_w = min(lhs.bw(), rhs.bw());
-                let mut tmp = Awi::zero(NonZeroUsize::new(min_w).unwrap());
+                let mut tmp = Awi::zero(NonZeroUsize::new(min_w).lower_ok()?);
                 let _ = tmp.field_from(&rhs, from.to_usize(), width.to_usize());
                 let mut out = lhs.clone();
                 let _ = out.field_to(to.to_usize(), &tmp, width.to_usize());
@@ -591,7 +598,7 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                     rhs.state(),
                     from.state(),
                     width.state(),
-                ]);
+                ])?;
             } else {
                 let to = Awi::opaque(m.get_nzbw(to));
                 let from = Awi::opaque(m.get_nzbw(from));
@@ -602,15 +609,15 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                         & Bits::efficient_add_then_ule(from.to_usize(), width.to_usize(), rhs.bw())
                             .is_some();
                 let max = min(lhs.bw(), rhs.bw());
-                let max_width_w = Bits::nontrivial_bits(max).unwrap();
+                let max_width_w = Bits::nontrivial_bits(max).lower_ok()?;
                 let width_small =
                     Bits::static_field(&Awi::zero(max_width_w), 0, &width, 0, max_width_w.get())
-                        .unwrap();
+                        .lower_ok()?;
                 // to achieve a no-op we simply set the width to zero
                 let mut tmp_width = Awi::zero(max_width_w);
-                tmp_width.mux_(&width_small, success).unwrap();
+                tmp_width.mux_(&width_small, success).lower_ok()?;
 
-                let out = field(&lhs, &to, &rhs, &from, &tmp_width);
+                let out = field(&lhs, &to, &rhs, &from, &tmp_width, m.funnel_strategy())?;
                 m.graft(&[
                     out.state(),
                     lhs.state(),
@@ -618,26 +625,26 @@ pub fn lower_op<P: Ptr + DummyDefault>(
                     rhs.state(),
                     from.state(),
                     width.state(),
-                ]);
+                ])?;
             }
         }
         Rev([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = reverse(&x);
-            m.graft(&[out.state(), x.state()]);
+            let out = reverse(&x)?;
+            m.graft(&[out.state(), x.state()])?;
         }
         Eq([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let out = equal(&lhs, &rhs);
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            let out = equal(&lhs, &rhs)?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Ne([lhs, rhs]) => {
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let mut out = equal(&lhs, &rhs);
+            let mut out = equal(&lhs, &rhs)?;
             out.not_();
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Ult([lhs, rhs]) => {
             let w = m.get_nzbw(lhs);
@@ -648,8 +655,8 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut tmp = Awi::zero(w);
             // TODO should probably use some short termination circuit like what
             // `tsmear_inx` uses
-            let (out, _) = tmp.cin_sum_(false, &not_lhs, &rhs).unwrap();
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            let (out, _) = tmp.cin_sum_(false, &not_lhs, &rhs).lower_ok()?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Ule([lhs, rhs]) => {
             let w = m.get_nzbw(lhs);
@@ -658,8 +665,8 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut not_lhs = lhs.clone();
             not_lhs.not_();
             let mut tmp = Awi::zero(w);
-            let (out, _) = tmp.cin_sum_(true, &not_lhs, &rhs).unwrap();
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            let (out, _) = tmp.cin_sum_(true, &not_lhs, &rhs).lower_ok()?;
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Ilt([lhs, rhs]) => {
             let w = m.get_nzbw(lhs);
@@ -668,22 +675,22 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut out = inlawi!(0);
             if w.get() == 1 {
                 let mut tmp = inlawi!(00);
-                tmp.set(0, lhs.msb()).unwrap();
-                tmp.set(1, rhs.msb()).unwrap();
-                out.lut_(&inlawi!(0010), &tmp).unwrap();
+                tmp.set(0, lhs.msb()).lower_ok()?;
+                tmp.set(1, rhs.msb()).lower_ok()?;
+                out.lut_(&inlawi!(0010), &tmp).lower_ok()?;
             } else {
-                let lhs_lo = awi!(lhs[..(lhs.bw() - 1)]).unwrap();
-                let rhs_lo = awi!(rhs[..(rhs.bw() - 1)]).unwrap();
-                let lo_lt = lhs_lo.ult(&rhs_lo).unwrap();
+                let lhs_lo = awi!(lhs[..(lhs.bw() - 1)]).lower_ok()?;
+                let rhs_lo = awi!(rhs[..(rhs.bw() - 1)]).lower_ok()?;
+                let lo_lt = lhs_lo.ult(&rhs_lo).lower_ok()?;
                 let mut tmp = inlawi!(000);
-                tmp.set(0, lo_lt).unwrap();
-                tmp.set(1, lhs.msb()).unwrap();
-                tmp.set(2, rhs.msb()).unwrap();
+                tmp.set(0, lo_lt).lower_ok()?;
+                tmp.set(1, lhs.msb()).lower_ok()?;
+                tmp.set(2, rhs.msb()).lower_ok()?;
                 // if `lhs.msb() != rhs.msb()` then `lhs.msb()` determines signed-less-than,
                 // otherwise `lo_lt` determines
-                out.lut_(&inlawi!(10001110), &tmp).unwrap();
+                out.lut_(&inlawi!(10001110), &tmp).lower_ok()?;
             }
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         Ile([lhs, rhs]) => {
             let w = m.get_nzbw(lhs);
@@ -692,69 +699,69 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut out = inlawi!(0);
             if w.get() == 1 {
                 let mut tmp = inlawi!(00);
-                tmp.set(0, lhs.msb()).unwrap();
-                tmp.set(1, rhs.msb()).unwrap();
-                out.lut_(&inlawi!(1011), &tmp).unwrap();
+                tmp.set(0, lhs.msb()).lower_ok()?;
+                tmp.set(1, rhs.msb()).lower_ok()?;
+                out.lut_(&inlawi!(1011), &tmp).lower_ok()?;
             } else {
-                let lhs_lo = awi!(lhs[..(lhs.bw() - 1)]).unwrap();
-                let rhs_lo = awi!(rhs[..(rhs.bw() - 1)]).unwrap();
-                let lo_lt = lhs_lo.ule(&rhs_lo).unwrap();
+                let lhs_lo = awi!(lhs[..(lhs.bw() - 1)]).lower_ok()?;
+                let rhs_lo = awi!(rhs[..(rhs.bw() - 1)]).lower_ok()?;
+                let lo_lt = lhs_lo.ule(&rhs_lo).lower_ok()?;
                 let mut tmp = inlawi!(000);
-                tmp.set(0, lo_lt).unwrap();
-                tmp.set(1, lhs.msb()).unwrap();
-                tmp.set(2, rhs.msb()).unwrap();
-                out.lut_(&inlawi!(10001110), &tmp).unwrap();
+                tmp.set(0, lo_lt).lower_ok()?;
+                tmp.set(1, lhs.msb()).lower_ok()?;
+                tmp.set(2, rhs.msb()).lower_ok()?;
+                out.lut_(&inlawi!(10001110), &tmp).lower_ok()?;
             }
-            m.graft(&[out.state(), lhs.state(), rhs.state()]);
+            m.graft(&[out.state(), lhs.state(), rhs.state()])?;
         }
         op @ (IsZero(_) | IsUmax(_) | IsImax(_) | IsImin(_) | IsUone(_)) => {
             let x = Awi::opaque(m.get_nzbw(op.operands()[0]));
             let w = x.nzbw();
             let out = InlAwi::from(match op {
-                IsZero(_) => x.const_eq(&Awi::zero(w)).unwrap(),
-                IsUmax(_) => x.const_eq(&Awi::umax(w)).unwrap(),
-                IsImax(_) => x.const_eq(&Awi::imax(w)).unwrap(),
-                IsImin(_) => x.const_eq(&Awi::imin(w)).unwrap(),
-                IsUone(_) => x.const_eq(&Awi::uone(w)).unwrap(),
+                IsZero(_) => x.const_eq(&Awi::zero(w)).lower_ok()?,
+                IsUmax(_) => x.const_eq(&Awi::umax(w)).lower_ok()?,
+                IsImax(_) => x.const_eq(&Awi::imax(w)).lower_ok()?,
+                IsImin(_) => x.const_eq(&Awi::imin(w)).lower_ok()?,
+                IsUone(_) => x.const_eq(&Awi::uone(w)).lower_ok()?,
                 _ => unreachable!(),
             });
-            m.graft(&[out.state(), x.state()]);
+            m.graft(&[out.state(), x.state()])?;
         }
         CountOnes([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = count_ones(&x).to_usize();
-            m.graft(&[out.state(), x.state()]);
+            let out = count_ones(&x)?.to_usize();
+            m.graft(&[out.state(), x.state()])?;
         }
         Lz([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = leading_zeros(&x).to_usize();
-            m.graft(&[out.state(), x.state()]);
+            let out = leading_zeros(&x)?.to_usize();
+            m.graft(&[out.state(), x.state()])?;
         }
         Tz([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = trailing_zeros(&x).to_usize();
-            m.graft(&[out.state(), x.state()]);
+            let out = trailing_zeros(&x)?.to_usize();
+            m.graft(&[out.state(), x.state()])?;
         }
         Sig([x]) => {
             let x = Awi::opaque(m.get_nzbw(x));
-            let out = significant_bits(&x).to_usize();
-            m.graft(&[out.state(), x.state()]);
+            let out = significant_bits(&x)?.to_usize();
+            m.graft(&[out.state(), x.state()])?;
         }
         LutSet([table, entry, inx]) => {
             let table = Awi::opaque(m.get_nzbw(table));
             let entry = Awi::opaque(m.get_nzbw(entry));
             let inx = Awi::opaque(m.get_nzbw(inx));
-            let out = lut_set(&table, &entry, &inx);
-            m.graft(&[out.state(), table.state(), entry.state(), inx.state()]);
+            let out = lut_set(&table, &entry, &inx)?;
+            m.graft(&[out.state(), table.state(), entry.state(), inx.state()])?;
         }
         ZeroResizeOverflow([x], w) => {
             let x = Awi::opaque(m.get_nzbw(x));
             let mut out = Awi::zero(bw(1));
             let w = w.get();
             if w < x.bw() {
-                out.bool_(!awi!(x[w..]).unwrap().is_zero());
+                out.bool_(!awi!(x[w..]).lower_ok()?.is_zero());
             }
-            m.graft(&[out.state(), x.state()]);
+            m.graft(&[out.state(), x.state()])?;
         }
         SignResizeOverflow([x], w) => {
             let x = Awi::opaque(m.get_nzbw(x));
@@ -762,51 +769,52 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let w = w.get();
             if w < x.bw() {
                 // the new msb and the bits above it should equal the old msb
-                let critical = awi!(x[(w - 1)..]).unwrap();
+                let critical = awi!(x[(w - 1)..]).lower_ok()?;
                 let mut tmp = inlawi!(00);
-                tmp.set(0, critical.is_zero()).unwrap();
-                tmp.set(1, critical.is_umax()).unwrap();
-                out.lut_(&inlawi!(1001), &tmp).unwrap();
+                tmp.set(0, critical.is_zero()).lower_ok()?;
+                tmp.set(1, critical.is_umax()).lower_ok()?;
+                out.lut_(&inlawi!(1001), &tmp).lower_ok()?;
             }
-            m.graft(&[out.state(), x.state()]);
+            m.graft(&[out.state(), x.state()])?;
         }
         ArbMulAdd([add, lhs, rhs]) => {
             let w = m.get_nzbw(add);
             let add = Awi::opaque(w);
             let lhs = Awi::opaque(m.get_nzbw(lhs));
             let rhs = Awi::opaque(m.get_nzbw(rhs));
-            let out = mul_add(w, Some(&add), &lhs, &rhs);
-            m.graft(&[out.state(), add.state(), lhs.state(), rhs.state()]);
+            let out = mul_add(w, Some(&add), &lhs, &rhs)?;
+            m.graft(&[out.state(), add.state(), lhs.state(), rhs.state()])?;
         }
         Mux([x0, x1, inx]) => {
             let x0 = Awi::opaque(m.get_nzbw(x0));
             let x1 = Awi::opaque(m.get_nzbw(x1));
             let inx_tmp = Awi::opaque(m.get_nzbw(inx));
             let out = if m.is_literal(inx) {
-                let b = m.bool(inx);
+                let b = m.bool(inx)?;
                 if b {
                     x1.clone()
                 } else {
                     x0.clone()
                 }
             } else {
-                static_mux(&x0, &x1, &inx_tmp)
+                static_mux(&x0, &x1, &inx_tmp)?
             };
-            m.graft(&[out.state(), x0.state(), x1.state(), inx_tmp.state()]);
+            m.graft(&[out.state(), x0.state(), x1.state(), inx_tmp.state()])?;
         }
-        // TODO in the divisions especially and in other operations, we need to look at the
-        // operand tree and combine multiple ops together in a single lowering operation
+        // identical states are fused during lowering (see `Ensemble::fuse_state`), but
+        // TODO in the divisions especially we need to look at the operand tree and combine
+        // different ops such as `UQuo` and `URem` together in a single lowering operation
         UQuo([duo, div]) => {
             let duo = Awi::opaque(m.get_nzbw(duo));
             let div = Awi::opaque(m.get_nzbw(div));
-            let quo = division(&duo, &div).0;
-            m.graft(&[quo.state(), duo.state(), div.state()]);
+            let quo = division(&duo, &div)?.0;
+            m.graft(&[quo.state(), duo.state(), div.state()])?;
         }
         URem([duo, div]) => {
             let duo = Awi::opaque(m.get_nzbw(duo));
             let div = Awi::opaque(m.get_nzbw(div));
-            let rem = division(&duo, &div).1;
-            m.graft(&[rem.state(), duo.state(), div.state()]);
+            let rem = division(&duo, &div)?.1;
+            m.graft(&[rem.state(), duo.state(), div.state()])?;
         }
         IQuo([duo, div]) => {
             let duo = Awi::opaque(m.get_nzbw(duo));
@@ -818,12 +826,12 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut tmp_div = div.clone();
             tmp_duo.neg_(duo_msb);
             tmp_div.neg_(div_msb);
-            let mut quo = division(&tmp_duo, &tmp_div).0;
+            let mut quo = division(&tmp_duo, &tmp_div)?.0;
             let mut tmp0 = InlAwi::from(duo_msb);
             let tmp1 = InlAwi::from(div_msb);
-            tmp0.xor_(&tmp1).unwrap();
+            tmp0.xor_(&tmp1).lower_ok()?;
             quo.neg_(tmp0.to_bool());
-            m.graft(&[quo.state(), duo.state(), div.state()]);
+            m.graft(&[quo.state(), duo.state(), div.state()])?;
         }
         IRem([duo, div]) => {
             let duo = Awi::opaque(m.get_nzbw(duo));
@@ -835,9 +843,9 @@ pub fn lower_op<P: Ptr + DummyDefault>(
             let mut tmp_div = div.clone();
             tmp_duo.neg_(duo_msb);
             tmp_div.neg_(div_msb);
-            let mut rem = division(&tmp_duo, &tmp_div).1;
+            let mut rem = division(&tmp_duo, &tmp_div)?.1;
             rem.neg_(duo_msb);
-            m.graft(&[rem.state(), duo.state(), div.state()]);
+            m.graft(&[rem.state(), duo.state(), div.state()])?;
         }
     }
     Ok(false)
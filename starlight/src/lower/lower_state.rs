@@ -1,14 +1,20 @@
-use std::num::NonZeroUsize;
+use std::{mem, num::NonZeroUsize};
 
 use awint::{
-    awint_dag::{smallvec::smallvec, ConcatFieldsType, ConcatType, Op::*, PState},
+    awint_dag::{
+        smallvec::{smallvec, SmallVec},
+        ConcatFieldsType, ConcatType,
+        Op::*,
+        PState,
+    },
+    awint_internals::USIZE_BITS,
     bw,
 };
 
 use crate::{
-    ensemble::Ensemble,
+    ensemble::{Ensemble, FusionKey, FusionOperand},
     epoch::EpochShared,
-    lower::{lower_op, LowerManagement},
+    lower::{lower_op, meta::FunnelStrategy, LowerManagement},
     Error,
 };
 
@@ -49,7 +55,7 @@ impl Ensemble {
         // graft input
         for i in 1..operands.len() {
             let grafted = operands[i];
-            let graftee = self.stator.states.get(p_state).unwrap().op.operands()[i - 1];
+            let graftee = self.stator.states[p_state].op.operands()[i - 1];
             if let Some(grafted) = self.stator.states.get_mut(grafted) {
                 // change the grafted `Opaque` into a `Copy` that routes to the graftee instead
                 // of needing to change all the operands of potentially many nodes
@@ -57,31 +63,60 @@ impl Ensemble {
             } else {
                 // else the operand is not used because it was optimized away, this is removing
                 // a tree outside of the grafted part
-                self.state_dec_rc(graftee).unwrap();
+                self.state_dec_rc(graftee)?;
             }
         }
 
         // graft output
         let grafted = operands[0];
-        self.stator.states.get_mut(p_state).unwrap().op = Copy([grafted]);
+        self.stator.states[p_state].op = Copy([grafted]);
         self.stator.states[grafted].inc_rc();
 
         Ok(())
     }
 
+    /// Wraps `err` into an `Error::LoweringFailed` with context about the
+    /// state at `p_state`
+    pub fn lowering_error_context(&self, p_state: PState, err: Error) -> Error {
+        if let Some(state) = self.stator.states.get(p_state) {
+            let mut operand_widths = vec![];
+            for p_operand in state.op.operands() {
+                if let Some(operand) = self.stator.states.get(*p_operand) {
+                    operand_widths.push(operand.nzbw.get());
+                }
+            }
+            let location = if let Some(ref location) = state.location {
+                format!("{}:{}:{}", location.file, location.line, location.col)
+            } else {
+                "an unknown location".to_owned()
+            };
+            Error::LoweringFailed(
+                format!(
+                    "{} with output bitwidth {} and operand bitwidths {:?} created at {}",
+                    state.op.operation_name(),
+                    state.nzbw,
+                    operand_widths,
+                    location
+                ),
+                Box::new(err),
+            )
+        } else {
+            err
+        }
+    }
+
     pub fn lower_op(epoch_shared: &EpochShared, p_state: PState) -> Result<bool, Error> {
         struct Tmp<'a> {
             ptr: PState,
             epoch_shared: &'a EpochShared,
         }
         impl<'a> LowerManagement<PState> for Tmp<'a> {
-            fn graft(&mut self, operands: &[PState]) {
+            fn graft(&mut self, operands: &[PState]) -> Result<(), Error> {
                 self.epoch_shared
                     .epoch_data
                     .borrow_mut()
                     .ensemble
                     .graft(self.ptr, operands)
-                    .unwrap();
             }
 
             fn get_nzbw(&self, p: PState) -> NonZeroUsize {
@@ -109,59 +144,65 @@ impl Ensemble {
                     .is_literal()
             }
 
-            fn usize(&self, p: PState) -> usize {
-                if let Literal(ref lit) = self
-                    .epoch_shared
-                    .epoch_data
-                    .borrow()
-                    .ensemble
-                    .stator
-                    .states
-                    .get(p)
-                    .unwrap()
-                    .op
-                {
-                    if lit.bw() != 64 {
-                        panic!()
+            fn usize(&self, p: PState) -> Result<usize, Error> {
+                let lock = self.epoch_shared.epoch_data.borrow();
+                if let Some(state) = lock.ensemble.stator.states.get(p) {
+                    if let Literal(ref lit) = state.op {
+                        if lit.bw() != USIZE_BITS {
+                            return Err(Error::BitwidthMismatch(lit.bw(), USIZE_BITS))
+                        }
+                        Ok(lit.to_usize())
+                    } else {
+                        Err(Error::OtherStr(
+                            "expected a literal `usize` operand during lowering",
+                        ))
                     }
-                    lit.to_usize()
                 } else {
-                    panic!()
+                    Err(Error::InvalidPtr)
                 }
             }
 
-            fn bool(&self, p: PState) -> bool {
-                if let Literal(ref lit) = self
-                    .epoch_shared
-                    .epoch_data
-                    .borrow()
-                    .ensemble
-                    .stator
-                    .states
-                    .get(p)
-                    .unwrap()
-                    .op
-                {
-                    if lit.bw() != 1 {
-                        panic!()
+            fn bool(&self, p: PState) -> Result<bool, Error> {
+                let lock = self.epoch_shared.epoch_data.borrow();
+                if let Some(state) = lock.ensemble.stator.states.get(p) {
+                    if let Literal(ref lit) = state.op {
+                        if lit.bw() != 1 {
+                            return Err(Error::BitwidthMismatch(lit.bw(), 1))
+                        }
+                        Ok(lit.to_bool())
+                    } else {
+                        Err(Error::OtherStr(
+                            "expected a literal `bool` operand during lowering",
+                        ))
                     }
-                    lit.to_bool()
                 } else {
-                    panic!()
+                    Err(Error::InvalidPtr)
                 }
             }
 
-            fn dec_rc(&mut self, p: PState) {
+            fn dec_rc(&mut self, p: PState) -> Result<(), Error> {
                 self.epoch_shared
                     .epoch_data
                     .borrow_mut()
                     .ensemble
                     .state_dec_rc(p)
-                    .unwrap()
+            }
+
+            fn funnel_strategy(&self) -> FunnelStrategy {
+                self.epoch_shared
+                    .epoch_data
+                    .borrow()
+                    .ensemble
+                    .funnel_strategy
             }
         }
         let lock = epoch_shared.epoch_data.borrow();
-        let state = lock.ensemble.stator.states.get(p_state).unwrap();
+        let state = lock
+            .ensemble
+            .stator
+            .states
+            .get(p_state)
+            .ok_or(Error::InvalidPtr)?;
         let start_op = state.op.clone();
         let out_w = state.nzbw;
         drop(lock);
@@ -171,6 +212,93 @@ impl Ensemble {
         })
     }
 
+    /// Returns the `FusionKey` of the state at `p_state` with its operands
+    /// forwarded through `Copy`s, or `None` if the state is a source or
+    /// otherwise should not be fused
+    fn fusion_key(&mut self, p_state: PState) -> Option<FusionKey> {
+        let state = &self.stator.states[p_state];
+        let mut params = SmallVec::new();
+        let mut table = None;
+        match state.op {
+            Opaque(..) | Argument(_) | Literal(_) | Assert(_) | Copy(_) | Invalid => return None,
+            StaticGet(_, inx) => params.push(inx),
+            ConcatFields(ref concat) => {
+                for (from, width) in concat.field_as_slice() {
+                    params.push(*from);
+                    params.push(width.get());
+                }
+            }
+            StaticLut(_, ref lut) => table = Some(self.lut_tables.intern_bits(lut)),
+            ZeroResizeOverflow(_, w) | SignResizeOverflow(_, w) => params.push(w.get()),
+            _ => (),
+        }
+        let mut operands = SmallVec::new();
+        for mut op in state.op.operands().iter().copied() {
+            while let Copy([a]) = self.stator.states[op].op {
+                op = a;
+            }
+            if let Literal(ref lit) = self.stator.states[op].op {
+                operands.push(FusionOperand::Literal(self.lut_tables.intern_bits(lit)));
+            } else {
+                operands.push(FusionOperand::State(op));
+            }
+        }
+        Some(FusionKey {
+            nzbw: state.nzbw,
+            name: state.op.operation_name(),
+            operands,
+            params,
+            table,
+        })
+    }
+
+    /// If an equivalent state has already been lowered, the state at `p_state`
+    /// is fused into it by becoming a `Copy` of it and `true` is returned.
+    /// Otherwise, the state is recorded for later states to fuse into. This is
+    /// what lets for example the same comparison feeding multiple `mux_`es or
+    /// the masks of chained `field` operations be shared instead of being
+    /// lowered separately.
+    fn fuse_state(&mut self, p_state: PState) -> Result<bool, Error> {
+        if self.stator.fusion_keys.contains_key(&p_state) {
+            // the DFS was restarted on this state
+            return Ok(false)
+        }
+        let Some(key) = self.fusion_key(p_state) else {
+            return Ok(false)
+        };
+        if let Some(p_equiv) = self.stator.fusion_memo.get(&key).copied() {
+            let old_op = mem::replace(&mut self.stator.states[p_state].op, Copy([p_equiv]));
+            self.stator.states[p_equiv].inc_rc();
+            for op in old_op.operands() {
+                self.state_dec_rc(*op)?;
+            }
+            return Ok(true)
+        }
+        for op in key.states() {
+            self.stator.states[op].inc_rc();
+        }
+        self.stator.fusion_keys.insert(p_state, key.clone());
+        self.stator.fusion_memo.insert(key, p_state);
+        Ok(false)
+    }
+
+    /// Clears the fusion memo and prunes the operands it was keeping alive
+    pub(crate) fn clear_fusion_memo(&mut self) -> Result<(), Error> {
+        self.stator.fusion_memo.clear();
+        let keys: Vec<FusionKey> = self
+            .stator
+            .fusion_keys
+            .drain()
+            .map(|(_, key)| key)
+            .collect();
+        for key in keys {
+            for op in key.states() {
+                self.state_dec_rc(op)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Lowers the rootward tree from `p_state` down to the elementary `Op`s
     pub fn dfs_lower_states_to_elementary(
         epoch_shared: &EpochShared,
@@ -195,17 +323,18 @@ impl Ensemble {
             let ops = state.op.operands();
             if ops.is_empty() {
                 // reached a root
-                path.pop().unwrap();
-                if path.is_empty() {
+                path.pop();
+                if let Some(last) = path.last_mut() {
+                    last.0 += 1;
+                } else {
                     break
                 }
-                path.last_mut().unwrap().0 += 1;
             } else if i >= ops.len() {
                 // checked all sources, attempt evaluation first, this is crucial in preventing
                 // wasted work in multiple layer lowerings
                 match lock.ensemble.eval_state(p_state) {
                     Ok(()) => {
-                        path.pop().unwrap();
+                        path.pop();
                         if path.is_empty() {
                             break
                         } else {
@@ -215,18 +344,21 @@ impl Ensemble {
                     // Continue on to lowering
                     Err(Error::Unevaluatable) => (),
                     Err(e) => {
-                        lock.ensemble.stator.states[p_state].err = Some(e.clone());
+                        lock.ensemble.set_state_err(p_state, e.clone());
                         return Err(e)
                     }
                 }
                 let needs_lower = match lock.ensemble.stator.states[p_state].op {
                     Opaque(..) | Argument(_) | Literal(_) | Assert(_) | Copy(_) | StaticGet(..)
-                    | Repeat(_) | StaticLut(..) => false,
+                    | Repeat(_) => false,
+                    StaticLut(..) => {
+                        lock.ensemble.preserve_macro_op_if_needed(p_state)?;
+                        false
+                    }
                     // for dynamic LUTs
                     Mux(_) => false,
                     Lut([lut, inx]) => {
-                        if let Literal(ref lit) = lock.ensemble.stator.states[lut].op {
-                            let lit = lit.clone();
+                        if let Some(lit) = lock.ensemble.take_literal(lut) {
                             let out_w = lock.ensemble.stator.states[p_state].nzbw.get();
                             let inx_w = lock.ensemble.stator.states[inx].nzbw.get();
                             let no_op = if let Ok(inx_w) = u32::try_from(inx_w) {
@@ -242,12 +374,12 @@ impl Ensemble {
                                 // TODO should I add the extra arg to `Lut` to fix this edge case?
                                 // or `Unknown` it?
                                 lock.ensemble.stator.states[p_state].op = Opaque(smallvec![], None);
-                                lock.ensemble.state_dec_rc(inx).unwrap();
+                                lock.ensemble.state_dec_rc(inx)?;
                             } else {
                                 lock.ensemble.stator.states[p_state].op =
                                     StaticLut(ConcatType::from_iter([inx]), lit);
                             }
-                            lock.ensemble.state_dec_rc(lut).unwrap();
+                            lock.ensemble.state_dec_rc(lut)?;
                         }
                         // else it is a dynamic LUT that could be lowered on the
                         // `LNode` side if needed
@@ -265,13 +397,13 @@ impl Ensemble {
 
                                 // or TODO does it just cause `Unknown`?
                                 lock.ensemble.stator.states[p_state].op = Opaque(smallvec![], None);
-                                lock.ensemble.state_dec_rc(bits).unwrap();
+                                lock.ensemble.state_dec_rc(bits)?;
                             } else {
                                 lock.ensemble.stator.states[p_state].op = ConcatFields(
                                     ConcatFieldsType::from_iter([(bits, lit_u, bw(1))]),
                                 );
                             }
-                            lock.ensemble.state_dec_rc(inx).unwrap();
+                            lock.ensemble.state_dec_rc(inx)?;
                             false
                         } else {
                             true
@@ -285,7 +417,7 @@ impl Ensemble {
                             if lit_u >= bits_w {
                                 // no-op
                                 lock.ensemble.stator.states[p_state].op = Copy([bits]);
-                                lock.ensemble.state_dec_rc(bit).unwrap();
+                                lock.ensemble.state_dec_rc(bit)?;
                             } else if let Some(lo_rem) = NonZeroUsize::new(lit_u) {
                                 if let Some(hi_rem) = NonZeroUsize::new(bits_w - 1 - lit_u) {
                                     lock.ensemble.stator.states[p_state].op =
@@ -312,9 +444,9 @@ impl Ensemble {
                             } else {
                                 // setting a single bit
                                 lock.ensemble.stator.states[p_state].op = Copy([bit]);
-                                lock.ensemble.state_dec_rc(bits).unwrap();
+                                lock.ensemble.state_dec_rc(bits)?;
                             }
-                            lock.ensemble.state_dec_rc(inx).unwrap();
+                            lock.ensemble.state_dec_rc(inx)?;
                             false
                         } else {
                             true
@@ -322,6 +454,11 @@ impl Ensemble {
                     }
                     _ => true,
                 };
+                let needs_lower = !lock.ensemble.fuse_state(p_state)? && needs_lower;
+                if needs_lower {
+                    lock.ensemble.check_limits()?;
+                    lock.ensemble.preserve_macro_op_if_needed(p_state)?;
+                }
                 drop(lock);
                 let lowering_done = if needs_lower {
                     // this is used to be able to remove ultimately unused temporaries
@@ -330,9 +467,10 @@ impl Ensemble {
                     let lowering_done = match Ensemble::lower_op(&temporary, p_state) {
                         Ok(lowering_done) => lowering_done,
                         Err(e) => {
-                            temporary.remove_as_current().unwrap();
+                            temporary.remove_as_current()?;
                             let mut lock = epoch_shared.epoch_data.borrow_mut();
-                            lock.ensemble.stator.states[p_state].err = Some(e.clone());
+                            let e = lock.ensemble.lowering_error_context(p_state, e);
+                            lock.ensemble.set_state_err(p_state, e.clone());
                             return Err(e)
                         }
                     };
@@ -340,37 +478,37 @@ impl Ensemble {
                     // TODO make sure there is no meta lowering using assertions assert!(temporary.
                     // assertions_empty());
                     let states = temporary.take_states_added();
-                    temporary.remove_as_current().unwrap();
+                    temporary.remove_as_current()?;
                     let mut lock = epoch_shared.epoch_data.borrow_mut();
                     for p_state in states {
-                        lock.ensemble
-                            .remove_state_if_pruning_allowed(p_state)
-                            .unwrap();
+                        lock.ensemble.remove_state_if_pruning_allowed(p_state)?;
                     }
                     lowering_done
                 } else {
                     true
                 };
                 if lowering_done {
-                    path.pop().unwrap();
+                    path.pop();
                     if path.is_empty() {
                         break
                     }
-                } else {
+                } else if let Some(last) = path.last_mut() {
                     // else do not call `path.pop`, restart the DFS here
-                    path.last_mut().unwrap().0 = 0;
+                    last.0 = 0;
                 }
             } else {
                 let mut p_next = ops[i];
                 if lock.ensemble.stator.states[p_next].lowered_to_elementary {
                     // do not visit
-                    path.last_mut().unwrap().0 += 1;
+                    if let Some(last) = path.last_mut() {
+                        last.0 += 1;
+                    }
                 } else {
                     while let Copy([a]) = lock.ensemble.stator.states[p_next].op {
                         // special optimization case: forward Copies
                         lock.ensemble.stator.states[p_state].op.operands_mut()[i] = a;
                         lock.ensemble.stator.states[a].inc_rc();
-                        lock.ensemble.state_dec_rc(p_next).unwrap();
+                        lock.ensemble.state_dec_rc(p_next)?;
                         p_next = a;
                     }
                     lock.ensemble.stator.states[p_next].lowered_to_elementary = true;
@@ -380,6 +518,11 @@ impl Ensemble {
             }
         }
 
-        Ok(())
+        // the memo is only kept for one DFS so that it does not keep states alive
+        epoch_shared
+            .epoch_data
+            .borrow_mut()
+            .ensemble
+            .clear_fusion_memo()
     }
 }
@@ -16,9 +16,15 @@ use crate::{
     awint_dag::{ConcatType, Lineage, Op},
     dag,
     ensemble::LNode,
+    lower::LowerOk,
+    Error,
 };
 const USIZE_BITS: usize = usize::BITS as usize;
 
+/// The name of the `Opaque` that adders use for their carries, which lowers
+/// to `LNodeKind::Carry` instead of a generic LUT
+pub(crate) const CARRY: &str = "starlight::carry";
+
 // This code here is especially messy because we do not want to get into
 // infinite lowering loops. These first few functions need to use manual
 // concatenation and only literal macros within loop blocks.
@@ -36,8 +42,7 @@ pub fn create_static_lut(
     mut lut: awi::Awi,
 ) -> Result<Op<PState>, PState> {
     // acquire LUT inputs, for every constant input reduce the LUT
-    let len = usize::from(u8::try_from(inxs.len()).unwrap());
-    for i in (0..len).rev() {
+    for i in (0..inxs.len()).rev() {
         let p_state = inxs[i];
         if let Some(bit) = p_state.try_get_as_awi() {
             debug_assert_eq!(bit.bw(), 1);
@@ -63,7 +68,7 @@ pub fn create_static_lut(
         } else {
             Ok(Op::Literal(awi::Awi::umax(bw(1))))
         }
-    } else if (lut.bw() == 2) && lut.get(1).unwrap() {
+    } else if (lut.bw() == 2) && (lut.get(1) == Some(true)) {
         Err(inxs[0])
     } else {
         Ok(Op::StaticLut(
@@ -88,7 +93,7 @@ macro_rules! static_lut {
                 $lhs.update_state(
                     bw(1),
                     op,
-                ).unwrap_at_runtime();
+                ).lower_ok()?;
             }
             Err(copy) => {
                 $lhs.set_state(copy);
@@ -105,32 +110,36 @@ fn concat(nzbw: NonZeroUsize, vec: SmallVec<[PState; 4]>) -> Awi {
     }
 }
 
-fn concat_update(bits: &mut Bits, nzbw: NonZeroUsize, vec: SmallVec<[PState; 4]>) {
+fn concat_update(
+    bits: &mut Bits,
+    nzbw: NonZeroUsize,
+    vec: SmallVec<[PState; 4]>,
+) -> Result<(), Error> {
     if vec.len() == 1 {
         bits.set_state(vec[0]);
     } else {
         bits.update_state(nzbw, Op::Concat(ConcatType::from_smallvec(vec)))
-            .unwrap_at_runtime();
+            .lower_ok()?;
     }
+    Ok(())
 }
 
-pub fn reverse(x: &Bits) -> Awi {
+pub fn reverse(x: &Bits) -> Result<Awi, Error> {
     let nzbw = x.nzbw();
     let mut out = SmallVec::with_capacity(nzbw.get());
     for i in 0..x.bw() {
-        out.push(x.get(x.bw() - 1 - i).unwrap().state())
+        out.push(x.get(x.bw() - 1 - i).lower_ok()?.state())
     }
-    concat(nzbw, out)
+    Ok(concat(nzbw, out))
 }
 
-pub fn selector(inx: &Bits, cap: Option<usize>) -> Vec<inlawi_ty!(1)> {
+pub fn selector(inx: &Bits, cap: Option<usize>) -> Result<Vec<inlawi_ty!(1)>, Error> {
     let num = cap.unwrap_or_else(|| 1usize << inx.bw());
     if num == 0 {
-        // not sure if this should be reachable
-        panic!();
+        return Err(Error::OtherStr("a selector needs at least one signal"))
     }
     if num == 1 {
-        return vec![inlawi!(1)]
+        return Ok(vec![inlawi!(1)])
     }
     let lb_num = num.next_power_of_two().trailing_zeros() as usize;
     let mut signals = Vec::with_capacity(num);
@@ -139,61 +148,60 @@ pub fn selector(inx: &Bits, cap: Option<usize>) -> Vec<inlawi_ty!(1)> {
         for j in 0..lb_num {
             // depending on the `j`th bit of `i`, keep the signal line true
             if (i & (1 << j)) == 0 {
-                static_lut!(signal; 0100; inx.get(j).unwrap(), signal);
+                static_lut!(signal; 0100; inx.get(j).lower_ok()?, signal);
             } else {
-                static_lut!(signal; 1000; inx.get(j).unwrap(), signal);
+                static_lut!(signal; 1000; inx.get(j).lower_ok()?, signal);
             }
         }
         signals.push(signal);
     }
-    signals
+    Ok(signals)
 }
 
-pub fn selector_awi(inx: &Bits, cap: Option<usize>) -> Awi {
+pub fn selector_awi(inx: &Bits, cap: Option<usize>) -> Result<Awi, Error> {
     let num = cap.unwrap_or_else(|| 1usize << inx.bw());
     if num == 0 {
-        // not sure if this should be reachable
-        panic!();
+        return Err(Error::OtherStr("a selector needs at least one signal"))
     }
     if num == 1 {
-        return awi!(1)
+        return Ok(awi!(1))
     }
     let lb_num = num.next_power_of_two().trailing_zeros() as usize;
-    let nzbw = NonZeroUsize::new(num).unwrap();
+    let nzbw = NonZeroUsize::new(num).lower_ok()?;
     let mut signals = SmallVec::with_capacity(num);
     for i in 0..num {
         let mut signal = inlawi!(1);
         for j in 0..lb_num {
             // depending on the `j`th bit of `i`, keep the signal line true
             if (i & (1 << j)) == 0 {
-                static_lut!(signal; 0100; inx.get(j).unwrap(), signal);
+                static_lut!(signal; 0100; inx.get(j).lower_ok()?, signal);
             } else {
-                static_lut!(signal; 1000; inx.get(j).unwrap(), signal);
+                static_lut!(signal; 1000; inx.get(j).lower_ok()?, signal);
             }
         }
         signals.push(signal.state());
     }
-    concat(nzbw, signals)
+    Ok(concat(nzbw, signals))
 }
 
-pub fn static_mux(x0: &Bits, x1: &Bits, inx: &Bits) -> Awi {
+pub fn static_mux(x0: &Bits, x1: &Bits, inx: &Bits) -> Result<Awi, Error> {
     debug_assert_eq!(x0.bw(), x1.bw());
     debug_assert_eq!(inx.bw(), 1);
     let nzbw = x0.nzbw();
     let mut signals = SmallVec::with_capacity(nzbw.get());
     for i in 0..x0.bw() {
         let mut tmp = inlawi!(0);
-        static_lut!(tmp; 1100_1010; x0.get(i).unwrap(), x1.get(i).unwrap(), inx);
+        static_lut!(tmp; 1100_1010; x0.get(i).lower_ok()?, x1.get(i).lower_ok()?, inx);
         signals.push(tmp.state());
     }
-    concat(nzbw, signals)
+    Ok(concat(nzbw, signals))
 }
 
 // uses dynamic LUTs to wholesale multiplex one or more inputs
-pub fn general_mux(inputs: &[Awi], inx: &Bits) -> Awi {
+pub fn general_mux(inputs: &[Awi], inx: &Bits) -> Result<Awi, Error> {
     debug_assert!(!inputs.is_empty());
     let nzbw = inputs[0].nzbw();
-    let lut_w = NonZeroUsize::new(inputs.len().next_power_of_two()).unwrap();
+    let lut_w = NonZeroUsize::new(inputs.len().next_power_of_two()).lower_ok()?;
     debug_assert_eq!(1 << inx.bw(), lut_w.get());
     let mut out_signals = SmallVec::with_capacity(nzbw.get());
     let unknown = Awi::opaque(bw(1));
@@ -212,13 +220,72 @@ pub fn general_mux(inputs: &[Awi], inx: &Bits) -> Awi {
         );
         out_signals.push(Awi::new(bw(1), Op::Lut([lut.state(), inx.state()])).state());
     }
-    concat(nzbw, out_signals)
+    Ok(concat(nzbw, out_signals))
+}
+
+/// Selects from `inputs` using the one-hot `onehot`, where the `i`th bit of
+/// `onehot` corresponds to `inputs[i]`. If `onehot` is not actually one-hot,
+/// the result is the bitwise OR of all selected inputs.
+pub fn onehot_mux(inputs: &[Awi], onehot: &Bits) -> Result<Awi, Error> {
+    debug_assert!(!inputs.is_empty());
+    debug_assert_eq!(inputs.len(), onehot.bw());
+    let nzbw = inputs[0].nzbw();
+    let mut out_signals = SmallVec::with_capacity(nzbw.get());
+    for out_i in 0..nzbw.get() {
+        // output bar for ORing
+        let mut out_bar = inlawi!(0);
+        for (i, input) in inputs.iter().enumerate() {
+            static_lut!(out_bar; 1111_1000;
+                input.get(out_i).lower_ok()?,
+                onehot.get(i).lower_ok()?,
+                out_bar
+            );
+        }
+        out_signals.push(out_bar.state());
+    }
+    Ok(concat(nzbw, out_signals))
+}
+
+/// Returns if exactly one bit of `x` is set
+pub fn is_onehot(x: &Bits) -> Result<inlawi_ty!(1), Error> {
+    // tuples of if any bit is set and if multiple bits are set
+    let mut ranks = vec![vec![]];
+    for i in 0..x.bw() {
+        let mut any = inlawi!(0);
+        static_lut!(any; 10; x.get(i).lower_ok()?);
+        ranks[0].push((any, inlawi!(0)));
+    }
+    // binary tree reduce
+    let (any, multiple) = loop {
+        let prev_rank = ranks.last().lower_ok()?;
+        let rank_len = prev_rank.len();
+        if rank_len == 1 {
+            break prev_rank[0]
+        }
+        let mut next_rank = vec![];
+        for i in 0..(rank_len / 2) {
+            let (any0, multiple0) = prev_rank[2 * i];
+            let (any1, multiple1) = prev_rank[2 * i + 1];
+            let mut any = inlawi!(0);
+            static_lut!(any; 1110; any0, any1);
+            let mut multiple = inlawi!(0);
+            static_lut!(multiple; 1111_1111_1111_1000; any0, any1, multiple0, multiple1);
+            next_rank.push((any, multiple));
+        }
+        if (rank_len & 1) != 0 {
+            next_rank.push(*prev_rank.last().lower_ok()?)
+        }
+        ranks.push(next_rank);
+    };
+    let mut res = inlawi!(0);
+    static_lut!(res; 0010; any, multiple);
+    Ok(res)
 }
 
 // uses dynamic LUTs under the hood
-pub fn dynamic_to_static_get(bits: &Bits, inx: &Bits) -> inlawi_ty!(1) {
+pub fn dynamic_to_static_get(bits: &Bits, inx: &Bits) -> Result<inlawi_ty!(1), Error> {
     if bits.bw() == 1 {
-        return InlAwi::from(bits.to_bool())
+        return Ok(InlAwi::from(bits.to_bool()))
     }
     /*let signals = selector(inx, Some(bits.bw()));
     let mut out = inlawi!(0);
@@ -226,18 +293,19 @@ pub fn dynamic_to_static_get(bits: &Bits, inx: &Bits) -> inlawi_ty!(1) {
         static_lut!(out; 1111_1000; signal, bits.get(i).unwrap(), out);
     }
     out*/
-    let lut_w = NonZeroUsize::new(bits.bw().next_power_of_two()).unwrap();
-    let inx_w = NonZeroUsize::new(lut_w.get().trailing_zeros() as usize).unwrap();
+    let lut_w = NonZeroUsize::new(bits.bw().next_power_of_two()).lower_ok()?;
+    let inx_w = NonZeroUsize::new(lut_w.get().trailing_zeros() as usize).lower_ok()?;
     let mut true_inx = Awi::zero(inx_w);
-    true_inx.field_width(inx, inx_w.get()).unwrap();
+    true_inx.field_width(inx, inx_w.get()).lower_ok()?;
     let base = if bits.bw() == lut_w.get() {
         Awi::from(bits)
     } else {
-        let unknowns =
-            Awi::opaque(NonZeroUsize::new(lut_w.get().checked_sub(bits.bw()).unwrap()).unwrap());
+        let unknowns = Awi::opaque(
+            NonZeroUsize::new(lut_w.get().checked_sub(bits.bw()).lower_ok()?).lower_ok()?,
+        );
         concat(lut_w, smallvec![bits.state(), unknowns.state()])
     };
-    InlAwi::new(Op::Lut([base.state(), true_inx.state()]))
+    Ok(InlAwi::new(Op::Lut([base.state(), true_inx.state()])))
 }
 
 /// Trailing smear, given the value of `inx` it will set all bits in the vector
@@ -245,7 +313,7 @@ pub fn dynamic_to_static_get(bits: &Bits, inx: &Bits) -> inlawi_ty!(1) {
 /// `inx.to_usize() == 0` sets no bits, and `inx.to_usize() == num_bits` sets
 /// all the bits. Beware of off-by-one errors, if there are `n` bits then there
 /// are `n + 1` possible unique smears.
-pub fn tsmear_inx(inx: &Bits, num_signals: usize) -> Vec<inlawi_ty!(1)> {
+pub fn tsmear_inx(inx: &Bits, num_signals: usize) -> Result<Vec<inlawi_ty!(1)>, Error> {
     let next_pow = num_signals.next_power_of_two();
     let mut lb_num = next_pow.trailing_zeros() as usize;
     if next_pow == num_signals {
@@ -264,29 +332,29 @@ pub fn tsmear_inx(inx: &Bits, num_signals: usize) -> Vec<inlawi_ty!(1)> {
                 // update equality, and if the prefix is true and the `j` bit of `inx` is set
                 // then the signal is set
 
-                let inx_j = inx.get(j).unwrap();
+                let inx_j = inx.get(j).lower_ok()?;
                 static_lut!(signal; 11111000; inx_j, prefix_equal, signal);
 
                 static_lut!(prefix_equal; 0100; inx_j, prefix_equal);
             } else {
                 // just update equality, the `j`th bit of `i` is 1 and cannot be less than
                 // whatever the `inx` bit is
-                static_lut!(prefix_equal; 1000; inx.get(j).unwrap(), prefix_equal);
+                static_lut!(prefix_equal; 1000; inx.get(j).lower_ok()?, prefix_equal);
             }
         }
         signals.push(signal);
     }
-    signals
+    Ok(signals)
 }
 
-pub fn tsmear_awi(inx: &Bits, num_signals: usize) -> Awi {
+pub fn tsmear_awi(inx: &Bits, num_signals: usize) -> Result<Awi, Error> {
     let next_pow = num_signals.next_power_of_two();
     let mut lb_num = next_pow.trailing_zeros() as usize;
     if next_pow == num_signals {
         // need extra bit to get all `n + 1`
         lb_num += 1;
     }
-    let nzbw = NonZeroUsize::new(num_signals).unwrap();
+    let nzbw = NonZeroUsize::new(num_signals).lower_ok()?;
     let mut signals = SmallVec::with_capacity(num_signals);
     for i in 0..num_signals {
         // if `inx < i`
@@ -299,19 +367,19 @@ pub fn tsmear_awi(inx: &Bits, num_signals: usize) -> Awi {
                 // update equality, and if the prefix is true and the `j` bit of `inx` is set
                 // then the signal is set
 
-                let inx_j = inx.get(j).unwrap();
+                let inx_j = inx.get(j).lower_ok()?;
                 static_lut!(signal; 11111000; inx_j, prefix_equal, signal);
 
                 static_lut!(prefix_equal; 0100; inx_j, prefix_equal);
             } else {
                 // just update equality, the `j`th bit of `i` is 1 and cannot be less than
                 // whatever the `inx` bit is
-                static_lut!(prefix_equal; 1000; inx.get(j).unwrap(), prefix_equal);
+                static_lut!(prefix_equal; 1000; inx.get(j).lower_ok()?, prefix_equal);
             }
         }
         signals.push(signal.state());
     }
-    concat(nzbw, signals)
+    Ok(concat(nzbw, signals))
 }
 
 /*
@@ -333,104 +401,137 @@ y_0 = (s_0 && x_0_0) || (s_1 && x_0_1) || ...
 y_1 = (s_0 && x_1_0) || (s_1 && x_1_1) || ...
 ...
 */
-pub fn dynamic_to_static_lut(out: &mut Bits, table: &Bits, inx: &Bits) {
+pub fn dynamic_to_static_lut(out: &mut Bits, table: &Bits, inx: &Bits) -> Result<(), Error> {
     // if this is broken it breaks a lot of stuff
-    debug_assert!(table.bw() == (out.bw().checked_mul(1 << inx.bw()).unwrap()));
-    let signals = selector(inx, None);
+    debug_assert!(Some(table.bw()) == out.bw().checked_mul(1 << inx.bw()));
+    let signals = selector(inx, None)?;
     let nzbw = out.nzbw();
     let mut tmp_output = SmallVec::with_capacity(nzbw.get());
     for j in 0..out.bw() {
         let mut column = inlawi!(0);
         for (i, signal) in signals.iter().enumerate() {
-            static_lut!(column; 1111_1000; signal, table.get((i * out.bw()) + j).unwrap(), column);
+            static_lut!(column; 1111_1000; signal, table.get((i * out.bw()) + j).lower_ok()?, column);
         }
         tmp_output.push(column.state());
     }
     concat_update(out, nzbw, tmp_output)
 }
 
-pub fn dynamic_to_static_set(bits: &Bits, inx: &Bits, bit: &Bits) -> Awi {
+pub fn dynamic_to_static_set(bits: &Bits, inx: &Bits, bit: &Bits) -> Result<Awi, Error> {
     if bits.bw() == 1 {
-        return Awi::from(bit)
+        return Ok(Awi::from(bit))
     }
-    let signals = selector(inx, Some(bits.bw()));
+    let signals = selector(inx, Some(bits.bw()))?;
     let nzbw = bits.nzbw();
     let mut out = SmallVec::with_capacity(nzbw.get());
     for (i, signal) in signals.iter().enumerate() {
         // multiplex between using `bits` or the `bit` depending on the signal
         let mut tmp = inlawi!(0);
-        static_lut!(tmp; 1101_1000; signal, bit, bits.get(i).unwrap());
+        static_lut!(tmp; 1101_1000; signal, bit, bits.get(i).lower_ok()?);
         out.push(tmp.state());
     }
-    concat(nzbw, out)
+    Ok(concat(nzbw, out))
 }
 
-pub fn resize(x: &Bits, w: NonZeroUsize, signed: bool) -> Awi {
+pub fn resize(x: &Bits, w: NonZeroUsize, signed: bool) -> Result<Awi, Error> {
     if w == x.nzbw() {
-        Awi::from_bits(x)
+        Ok(Awi::from_bits(x))
     } else if w < x.nzbw() {
-        Awi::new(
+        Ok(Awi::new(
             w,
             Op::ConcatFields(ConcatFieldsType::from_iter([(x.state(), 0usize, w)])),
-        )
+        ))
     } else if signed {
         let extension = Awi::new(
-            NonZeroUsize::new(w.get() - x.bw()).unwrap(),
+            NonZeroUsize::new(w.get() - x.bw()).lower_ok()?,
             Op::Repeat([x.msb().state()]),
         );
-        concat(w, smallvec![x.state(), extension.state()])
+        Ok(concat(w, smallvec![x.state(), extension.state()]))
     } else {
-        let zero = Awi::zero(NonZeroUsize::new(w.get() - x.bw()).unwrap());
-        concat(w, smallvec![x.state(), zero.state()])
+        let zero = Awi::zero(NonZeroUsize::new(w.get() - x.bw()).lower_ok()?);
+        Ok(concat(w, smallvec![x.state(), zero.state()]))
     }
 }
 
-pub fn resize_cond(x: &Bits, w: NonZeroUsize, signed: &Bits) -> Awi {
+pub fn resize_cond(x: &Bits, w: NonZeroUsize, signed: &Bits) -> Result<Awi, Error> {
     debug_assert_eq!(signed.bw(), 1);
     if w == x.nzbw() {
-        Awi::from_bits(x)
+        Ok(Awi::from_bits(x))
     } else if w < x.nzbw() {
-        Awi::new(
+        Ok(Awi::new(
             w,
             Op::ConcatFields(ConcatFieldsType::from_iter([(x.state(), 0usize, w)])),
-        )
+        ))
     } else {
         let extension = Awi::new(
-            NonZeroUsize::new(w.get() - x.bw()).unwrap(),
+            NonZeroUsize::new(w.get() - x.bw()).lower_ok()?,
             Op::Repeat([signed.state()]),
         );
-        concat(w, smallvec![x.state(), extension.state()])
+        Ok(concat(w, smallvec![x.state(), extension.state()]))
     }
 }
 
 /// This does not handle invalid arguments; set `width` to zero to cause no-ops
-pub fn field_width(lhs: &Bits, rhs: &Bits, width: &Bits) -> Awi {
+pub fn field_width(lhs: &Bits, rhs: &Bits, width: &Bits) -> Result<Awi, Error> {
     let min_w = min(lhs.bw(), rhs.bw());
-    let signals = tsmear_inx(width, min_w);
-    let nzbw = NonZeroUsize::new(signals.len()).unwrap();
+    let signals = tsmear_inx(width, min_w)?;
+    let nzbw = NonZeroUsize::new(signals.len()).lower_ok()?;
     let mut mux_part = SmallVec::with_capacity(nzbw.get());
     for (i, signal) in signals.into_iter().enumerate() {
         // mux_ between `lhs` or `rhs` based on the signal
         let mut tmp = inlawi!(0);
-        static_lut!(tmp; 1100_1010; lhs.get(i).unwrap(), rhs.get(i).unwrap(), signal);
+        static_lut!(tmp; 1100_1010; lhs.get(i).lower_ok()?, rhs.get(i).lower_ok()?, signal);
         mux_part.push(tmp.state());
     }
     let mux_part = concat(nzbw, mux_part);
     if let Some(lhs_rem_hi) = NonZeroUsize::new(lhs.bw() - nzbw.get()) {
-        Awi::new(
+        Ok(Awi::new(
             lhs.nzbw(),
             Op::ConcatFields(ConcatFieldsType::from_iter([
                 (mux_part.state(), 0usize, nzbw),
                 (lhs.state(), nzbw.get(), lhs_rem_hi),
             ])),
-        )
+        ))
     } else {
-        mux_part
+        Ok(mux_part)
+    }
+}
+
+/// How funnel shifts (and the shifts, rotates, and field operations with
+/// dynamic offsets that are lowered through them) are lowered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FunnelStrategy {
+    /// Each output bit is a dynamic LUT indexed by the shift amount, which has
+    /// `(1 << s.bw()) + s.bw()` inputs
+    #[default]
+    DynamicLut,
+    /// A crossbar of static LUTs controlled by a one-hot decoding of the shift
+    /// amount, which only uses LUTs with up to 3 inputs
+    Crossbar,
+    /// Uses `DynamicLut` if the dynamic LUTs fit in target LUTs with this many
+    /// inputs, otherwise `Crossbar`. Dynamic LUTs that do not fit must be
+    /// decomposed, which produces worse results than the crossbar on small LUT
+    /// targets.
+    Auto(usize),
+}
+
+impl FunnelStrategy {
+    /// Returns if a funnel with a shift amount of bitwidth `s_w` should be
+    /// lowered with a crossbar
+    pub fn use_crossbar(self, s_w: usize) -> bool {
+        match self {
+            FunnelStrategy::DynamicLut => false,
+            FunnelStrategy::Crossbar => true,
+            FunnelStrategy::Auto(lut_inputs) => {
+                if s_w >= (USIZE_BITS - 1) {
+                    return true
+                }
+                (1usize << s_w).saturating_add(s_w) > lut_inputs
+            }
+        }
     }
 }
 
-// old static strategy if we need it
-/*
 /// Given the diagonal control lines and input of a crossbar with output width
 /// s.t. `input.bw() + out.bw() - 1 = signals.bw()`, returns the output. The
 /// `i`th input bit and `j`th output bit are controlled by the
@@ -441,7 +542,7 @@ pub fn crossbar(
     input: &Bits,
     signals: &[inlawi_ty!(1)],
     signal_range: (usize, usize),
-) {
+) -> Result<(), Error> {
     debug_assert!(signal_range.0 < signal_range.1);
     debug_assert_eq!(signal_range.1 - signal_range.0, signals.len());
 
@@ -454,7 +555,7 @@ pub fn crossbar(
             let signal_inx = output.bw() - 1 + i - j;
             if (signal_inx >= signal_range.0) && (signal_inx < signal_range.1) {
                 static_lut!(out_bar; 1111_1000;
-                    input.get(i).unwrap(),
+                    input.get(i).lower_ok()?,
                     signals[signal_inx - signal_range.0],
                     out_bar
                 );
@@ -464,23 +565,22 @@ pub fn crossbar(
     }
     concat_update(output, nzbw, tmp_output)
 }
-*/
 
-/*pub fn funnel(x: &Bits, s: &Bits) -> Awi {
-    debug_assert_eq!(x.bw() & 1, 0);
-    debug_assert_eq!(x.bw() / 2, 1 << s.bw());
-    let mut out = Awi::zero(NonZeroUsize::new(x.bw() / 2).unwrap());
-    let signals = selector(s, None);
+/// The same as [funnel], but uses a crossbar of static LUTs
+pub fn funnel_crossbar(x: &Bits, s: &Bits) -> Result<Awi, Error> {
+    debug_assert!((s.bw() < (USIZE_BITS - 1)) && ((2usize << s.bw()) == x.bw()));
+    let mut out = Awi::zero(NonZeroUsize::new(1 << s.bw()).lower_ok()?);
+    let signals = selector(s, None)?;
     // select zero should connect the zeroeth crossbars, so the offset is `out.bw()
     // - 1 + 0 - 0`
     let range = (out.bw() - 1, out.bw() - 1 + out.bw());
-    crossbar(&mut out, x, &signals, range);
-    out
-}*/
+    crossbar(&mut out, x, &signals, range)?;
+    Ok(out)
+}
 
-pub fn funnel(x: &Bits, s: &Bits) -> Awi {
+pub fn funnel(x: &Bits, s: &Bits) -> Result<Awi, Error> {
     debug_assert!((s.bw() < (USIZE_BITS - 1)) && ((2usize << s.bw()) == x.bw()));
-    let out_w = NonZeroUsize::new(1 << s.bw()).unwrap();
+    let out_w = NonZeroUsize::new(1 << s.bw()).lower_ok()?;
     let mut output = SmallVec::with_capacity(out_w.get());
     for j in 0..out_w.get() {
         let lut = Awi::new(
@@ -489,105 +589,120 @@ pub fn funnel(x: &Bits, s: &Bits) -> Awi {
         );
         output.push(Awi::new(bw(1), Op::Lut([lut.state(), s.state()])).state());
     }
-    concat(out_w, output)
+    Ok(concat(out_w, output))
+}
+
+/// Uses [funnel] or [funnel_crossbar] depending on `strategy`
+pub fn funnel_with(x: &Bits, s: &Bits, strategy: FunnelStrategy) -> Result<Awi, Error> {
+    if strategy.use_crossbar(s.bw()) {
+        funnel_crossbar(x, s)
+    } else {
+        funnel(x, s)
+    }
 }
 
 /// Assumes that `start` and `end` are their small versions. Setting `end` to 0
 /// guarantees a no-op.
-pub fn range_or(x: &Bits, start: &Bits, end: &Bits) -> Awi {
+pub fn range_or(x: &Bits, start: &Bits, end: &Bits) -> Result<Awi, Error> {
     // trailing mask that trails `start`, exclusive
-    let tmask0 = tsmear_inx(start, x.bw());
+    let tmask0 = tsmear_inx(start, x.bw())?;
     // trailing mask that trails `end`, exclusive
-    let tmask1 = tsmear_inx(end, x.bw());
+    let tmask1 = tsmear_inx(end, x.bw())?;
 
     // or with `x` based on the masks, note that any case where `tmask1` is zero
     // needs to result in no-op
     let mut out = SmallVec::with_capacity(x.bw());
     for i in 0..x.bw() {
         let mut signal = inlawi!(0);
-        static_lut!(signal; 1111_0100; tmask0[i], tmask1[i], x.get(i).unwrap());
+        static_lut!(signal; 1111_0100; tmask0[i], tmask1[i], x.get(i).lower_ok()?);
         out.push(signal.state());
     }
-    concat(x.nzbw(), out)
+    Ok(concat(x.nzbw(), out))
 }
 
 /// Assumes that `start` and `end` are their small versions. Must be set to a
 /// full range for a no-op
-pub fn range_and(x: &Bits, start: &Bits, end: &Bits) -> Awi {
+pub fn range_and(x: &Bits, start: &Bits, end: &Bits) -> Result<Awi, Error> {
     // trailing mask that trails `start`, exclusive
-    let tmask0 = tsmear_inx(start, x.bw());
+    let tmask0 = tsmear_inx(start, x.bw())?;
     // trailing mask that trails `end`, exclusive
-    let tmask1 = tsmear_inx(end, x.bw());
+    let tmask1 = tsmear_inx(end, x.bw())?;
 
     // and with `x` based on the masks, the fourth case can be any bit we choose
     let mut out = SmallVec::with_capacity(x.bw());
     for i in 0..x.bw() {
         let mut signal = inlawi!(0);
-        static_lut!(signal; 0100_0000; tmask0[i], tmask1[i], x.get(i).unwrap());
+        static_lut!(signal; 0100_0000; tmask0[i], tmask1[i], x.get(i).lower_ok()?);
         out.push(signal.state());
     }
-    concat(x.nzbw(), out)
+    Ok(concat(x.nzbw(), out))
 }
 
 /// Assumes that `start` and `end` are their small versions. Setting `end` to 0
 /// guarantees a no-op.
-pub fn range_xor(x: &Bits, start: &Bits, end: &Bits) -> Awi {
+pub fn range_xor(x: &Bits, start: &Bits, end: &Bits) -> Result<Awi, Error> {
     // trailing mask that trails `start`, exclusive
-    let tmask0 = tsmear_inx(start, x.bw());
+    let tmask0 = tsmear_inx(start, x.bw())?;
     // trailing mask that trails `end`, exclusive
-    let tmask1 = tsmear_inx(end, x.bw());
+    let tmask1 = tsmear_inx(end, x.bw())?;
 
     // xor with `x` based on the masks, note that any case where `tmask1` is zero
     // needs to result in no-op
     let mut out = SmallVec::with_capacity(x.bw());
     for i in 0..x.bw() {
         let mut signal = inlawi!(0);
-        static_lut!(signal; 1011_0100; tmask0[i], tmask1[i], x.get(i).unwrap());
+        static_lut!(signal; 1011_0100; tmask0[i], tmask1[i], x.get(i).lower_ok()?);
         out.push(signal.state());
     }
-    concat(x.nzbw(), out)
+    Ok(concat(x.nzbw(), out))
 }
 
 /// Assumes that `from` and `width` is in range, however setting `width` to 0
 /// guarantees that nothing happens to `lhs` even with `from` being out of range
-pub fn field_from(lhs: &Bits, rhs: &Bits, from: &Bits, width: &Bits) -> Awi {
+pub fn field_from(
+    lhs: &Bits,
+    rhs: &Bits,
+    from: &Bits,
+    width: &Bits,
+    strategy: FunnelStrategy,
+) -> Result<Awi, Error> {
     let mut out = Awi::from_bits(lhs);
     // the max shift value that can be anything but an effective no-op
     if let Some(s_w) = Bits::nontrivial_bits(rhs.bw() - 1) {
         let mut s = Awi::zero(s_w);
         s.resize_(from, false);
-        let mut x = Awi::opaque(NonZeroUsize::new(2 << s_w.get()).unwrap());
+        let mut x = Awi::opaque(NonZeroUsize::new(2 << s_w.get()).lower_ok()?);
         // this is done on purpose so there are opaque bits
         let w = rhs.bw();
         let _ = x.field_width(rhs, w);
-        let tmp = funnel(&x, &s);
+        let tmp = funnel_with(&x, &s, strategy)?;
 
         let max_width = min(lhs.bw(), rhs.bw());
-        let mut small_width = Awi::zero(Bits::nontrivial_bits(max_width).unwrap());
+        let mut small_width = Awi::zero(Bits::nontrivial_bits(max_width).lower_ok()?);
         small_width.resize_(width, false);
         let _ = out.field_width(&tmp, small_width.to_usize());
     } else {
         let small_width = Awi::from_bool(width.lsb());
         let _ = out.field_width(rhs, small_width.to_usize());
     }
-    out
+    Ok(out)
 }
 
 /// Assumes that `s` is in range
-pub fn shl(x: &Bits, s: &Bits) -> Awi {
+pub fn shl(x: &Bits, s: &Bits, strategy: FunnelStrategy) -> Result<Awi, Error> {
     let mut out = Awi::zero(x.nzbw());
     if let Some(small_s_w) = Bits::nontrivial_bits(x.bw() - 1) {
         let mut small_s = Awi::zero(small_s_w);
         small_s.resize_(s, false);
-        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).unwrap());
+        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).lower_ok()?);
         // need zeros for the bits that are shifted in
         let _ = wide_x.field_to(x.bw(), &Awi::zero(x.nzbw()), x.bw() - 1);
         let mut rev_x = Awi::zero(x.nzbw());
-        rev_x.copy_(x).unwrap();
+        rev_x.copy_(x).lower_ok()?;
         // we have two reversals so that the shift acts leftward
         rev_x.rev_();
         let _ = wide_x.field_width(&rev_x, x.bw());
-        let tmp = funnel(&wide_x, &small_s);
+        let tmp = funnel_with(&wide_x, &small_s, strategy)?;
         out.resize_(&tmp, false);
         out.rev_();
     } else {
@@ -595,36 +710,36 @@ pub fn shl(x: &Bits, s: &Bits) -> Awi {
         out.resize_(x, false);
         let _ = out.field_width(x, small_width.to_usize());
     }
-    out
+    Ok(out)
 }
 
 /// Assumes that `s` is in range
-pub fn lshr(x: &Bits, s: &Bits) -> Awi {
+pub fn lshr(x: &Bits, s: &Bits, strategy: FunnelStrategy) -> Result<Awi, Error> {
     let mut out = Awi::zero(x.nzbw());
     if let Some(small_s_w) = Bits::nontrivial_bits(x.bw() - 1) {
         let mut small_s = Awi::zero(small_s_w);
         small_s.resize_(s, false);
-        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).unwrap());
+        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).lower_ok()?);
         // need zeros for the bits that are shifted in
         let _ = wide_x.field_to(x.bw(), &Awi::zero(x.nzbw()), x.bw() - 1);
         let _ = wide_x.field_width(x, x.bw());
-        let tmp = funnel(&wide_x, &small_s);
+        let tmp = funnel_with(&wide_x, &small_s, strategy)?;
         out.resize_(&tmp, false);
     } else {
         let small_width = Awi::from_bool(s.lsb());
         out.resize_(x, false);
         let _ = out.field_width(x, small_width.to_usize());
     }
-    out
+    Ok(out)
 }
 
 /// Assumes that `s` is in range
-pub fn ashr(x: &Bits, s: &Bits) -> Awi {
+pub fn ashr(x: &Bits, s: &Bits, strategy: FunnelStrategy) -> Result<Awi, Error> {
     let mut out = Awi::zero(x.nzbw());
     if let Some(small_s_w) = Bits::nontrivial_bits(x.bw() - 1) {
         let mut small_s = Awi::zero(small_s_w);
         small_s.resize_(s, false);
-        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).unwrap());
+        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).lower_ok()?);
         // extension for the bits that are shifted in
         let _ = wide_x.field_to(
             x.bw(),
@@ -632,31 +747,31 @@ pub fn ashr(x: &Bits, s: &Bits) -> Awi {
             x.bw() - 1,
         );
         let _ = wide_x.field_width(x, x.bw());
-        let tmp = funnel(&wide_x, &small_s);
+        let tmp = funnel_with(&wide_x, &small_s, strategy)?;
         out.resize_(&tmp, false);
     } else {
         let small_width = Awi::from_bool(s.lsb());
         out.resize_(x, false);
         let _ = out.field_width(x, small_width.to_usize());
     }
-    out
+    Ok(out)
 }
 
-pub fn rotl(x: &Bits, s: &Bits) -> Awi {
+pub fn rotl(x: &Bits, s: &Bits, strategy: FunnelStrategy) -> Result<Awi, Error> {
     let mut out = Awi::zero(x.nzbw());
     if let Some(small_s_w) = Bits::nontrivial_bits(x.bw() - 1) {
         let mut small_s = Awi::zero(small_s_w);
         small_s.resize_(s, false);
 
         let mut rev_x = Awi::zero(x.nzbw());
-        rev_x.copy_(x).unwrap();
+        rev_x.copy_(x).lower_ok()?;
         rev_x.rev_();
 
-        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).unwrap());
+        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).lower_ok()?);
         // extension for the bits that are shifted in
         let _ = wide_x.field_to(x.bw(), &rev_x, x.bw() - 1);
         let _ = wide_x.field_width(&rev_x, x.bw());
-        let tmp = funnel(&wide_x, &small_s);
+        let tmp = funnel_with(&wide_x, &small_s, strategy)?;
         out.resize_(&tmp, false);
         out.rev_();
     } else {
@@ -664,40 +779,40 @@ pub fn rotl(x: &Bits, s: &Bits) -> Awi {
         out.resize_(x, false);
         let _ = out.field_width(x, small_width.to_usize());
     }
-    out
+    Ok(out)
 }
 
-pub fn rotr(x: &Bits, s: &Bits) -> Awi {
+pub fn rotr(x: &Bits, s: &Bits, strategy: FunnelStrategy) -> Result<Awi, Error> {
     let mut out = Awi::zero(x.nzbw());
     if let Some(small_s_w) = Bits::nontrivial_bits(x.bw() - 1) {
         let mut small_s = Awi::zero(small_s_w);
         small_s.resize_(s, false);
-        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).unwrap());
+        let mut wide_x = Awi::opaque(NonZeroUsize::new(2 << small_s_w.get()).lower_ok()?);
         // extension for the bits that are shifted in
         let _ = wide_x.field_to(x.bw(), x, x.bw() - 1);
         let _ = wide_x.field_width(x, x.bw());
-        let tmp = funnel(&wide_x, &small_s);
+        let tmp = funnel_with(&wide_x, &small_s, strategy)?;
         out.resize_(&tmp, false);
     } else {
         let small_width = Awi::from_bool(s.lsb());
         out.resize_(x, false);
         let _ = out.field_width(x, small_width.to_usize());
     }
-    out
+    Ok(out)
 }
 
-pub fn bitwise_not(x: &Bits) -> Awi {
+pub fn bitwise_not(x: &Bits) -> Result<Awi, Error> {
     let nzbw = x.nzbw();
     let mut out = SmallVec::with_capacity(nzbw.get());
     for i in 0..x.bw() {
         let mut tmp = inlawi!(0);
-        static_lut!(tmp; 01; x.get(i).unwrap());
+        static_lut!(tmp; 01; x.get(i).lower_ok()?);
         out.push(tmp.state());
     }
-    concat(nzbw, out)
+    Ok(concat(nzbw, out))
 }
 
-pub fn bitwise(lhs: &Bits, rhs: &Bits, lut: awi::Awi) -> Awi {
+pub fn bitwise(lhs: &Bits, rhs: &Bits, lut: awi::Awi) -> Result<Awi, Error> {
     debug_assert_eq!(lhs.bw(), rhs.bw());
     debug_assert_eq!(lut.bw(), 4);
     let nzbw = lhs.nzbw();
@@ -707,17 +822,20 @@ pub fn bitwise(lhs: &Bits, rhs: &Bits, lut: awi::Awi) -> Awi {
         tmp.update_state(
             bw(1),
             Op::StaticLut(
-                ConcatType::from_iter([lhs.get(i).unwrap().state(), rhs.get(i).unwrap().state()]),
+                ConcatType::from_iter([
+                    lhs.get(i).lower_ok()?.state(),
+                    rhs.get(i).lower_ok()?.state(),
+                ]),
                 lut.clone(),
             ),
         )
-        .unwrap_at_runtime();
+        .lower_ok()?;
         out.push(tmp.state());
     }
-    concat(nzbw, out)
+    Ok(concat(nzbw, out))
 }
 
-pub fn incrementer(x: &Bits, cin: &Bits, dec: bool) -> (Awi, inlawi_ty!(1)) {
+pub fn incrementer(x: &Bits, cin: &Bits, dec: bool) -> Result<(Awi, inlawi_ty!(1)), Error> {
     debug_assert_eq!(cin.bw(), 1);
     let nzbw = x.nzbw();
     let mut out = SmallVec::with_capacity(nzbw.get());
@@ -725,7 +843,7 @@ pub fn incrementer(x: &Bits, cin: &Bits, dec: bool) -> (Awi, inlawi_ty!(1)) {
     if dec {
         for i in 0..x.bw() {
             let mut tmp = inlawi!(0);
-            let b = x.get(i).unwrap();
+            let b = x.get(i).lower_ok()?;
             // half subtractor
             static_lut!(tmp; 1001; carry, b);
             out.push(tmp.state());
@@ -734,14 +852,14 @@ pub fn incrementer(x: &Bits, cin: &Bits, dec: bool) -> (Awi, inlawi_ty!(1)) {
     } else {
         for i in 0..x.bw() {
             let mut tmp = inlawi!(0);
-            let b = x.get(i).unwrap();
+            let b = x.get(i).lower_ok()?;
             // half adder
             static_lut!(tmp; 0110; carry, b);
             out.push(tmp.state());
             static_lut!(carry; 1000; carry, b);
         }
     }
-    (concat(nzbw, out), carry)
+    Ok((concat(nzbw, out), carry))
 }
 
 // TODO select carry adder
@@ -762,7 +880,11 @@ for i in 0..lb {
     c1_i = c1_tmp;
 }
 */
-pub fn cin_sum(cin: &Bits, lhs: &Bits, rhs: &Bits) -> (Awi, inlawi_ty!(1), inlawi_ty!(1)) {
+pub fn cin_sum(
+    cin: &Bits,
+    lhs: &Bits,
+    rhs: &Bits,
+) -> Result<(Awi, inlawi_ty!(1), inlawi_ty!(1)), Error> {
     debug_assert_eq!(cin.bw(), 1);
     debug_assert_eq!(lhs.bw(), rhs.bw());
     let w = lhs.bw();
@@ -774,21 +896,27 @@ pub fn cin_sum(cin: &Bits, lhs: &Bits, rhs: &Bits) -> (Awi, inlawi_ty!(1), inlaw
         let mut next_carry = inlawi!(0);
         static_lut!(sum; 1001_0110;
             carry,
-            lhs.get(i).unwrap(),
-            rhs.get(i).unwrap()
-        );
-        static_lut!(next_carry; 1110_1000;
-            carry,
-            lhs.get(i).unwrap(),
-            rhs.get(i).unwrap()
+            lhs.get(i).lower_ok()?,
+            rhs.get(i).lower_ok()?
         );
+        let a = InlAwi::from(lhs.get(i).lower_ok()?);
+        let b = InlAwi::from(rhs.get(i).lower_ok()?);
+        if [&carry, &a, &b]
+            .iter()
+            .any(|x| x.state().try_get_as_awi().is_some())
+        {
+            // constants reduce the carry to a simpler LUT
+            static_lut!(next_carry; 1110_1000; carry, a, b);
+        } else {
+            next_carry.set_state(Awi::opaque_with(bw(1), CARRY, &[&carry, &a, &b]).state());
+        }
         out.push(sum.state());
         carry = next_carry;
     }
     let mut signed_overflow = inlawi!(0);
-    let a = lhs.get(w - 1).unwrap().state();
-    let b = rhs.get(w - 1).unwrap().state();
-    let c = *out.get(w - 1).unwrap();
+    let a = lhs.get(w - 1).lower_ok()?.state();
+    let b = rhs.get(w - 1).lower_ok()?.state();
+    let c = *out.get(w - 1).lower_ok()?;
     signed_overflow
         .update_state(
             bw(1),
@@ -797,11 +925,11 @@ pub fn cin_sum(cin: &Bits, lhs: &Bits, rhs: &Bits) -> (Awi, inlawi_ty!(1), inlaw
                 awi!(0001_1000)
             }),
         )
-        .unwrap_at_runtime();
-    (concat(nzbw, out), carry, signed_overflow)
+        .lower_ok()?;
+    Ok((concat(nzbw, out), carry, signed_overflow))
 }
 
-pub fn negator(x: &Bits, neg: &Bits) -> Awi {
+pub fn negator(x: &Bits, neg: &Bits) -> Result<Awi, Error> {
     debug_assert_eq!(neg.bw(), 1);
     let nzbw = x.nzbw();
     let mut out = SmallVec::with_capacity(nzbw.get());
@@ -810,25 +938,31 @@ pub fn negator(x: &Bits, neg: &Bits) -> Awi {
         let mut sum = inlawi!(0);
         let mut next_carry = inlawi!(0);
         // half adder with input inversion control
-        static_lut!(sum; 1001_0110; carry, x.get(i).unwrap(), neg);
-        static_lut!(next_carry; 0010_1000; carry, x.get(i).unwrap(), neg);
+        static_lut!(sum; 1001_0110; carry, x.get(i).lower_ok()?, neg);
+        static_lut!(next_carry; 0010_1000; carry, x.get(i).lower_ok()?, neg);
         out.push(sum.state());
         carry = next_carry;
     }
-    concat(nzbw, out)
+    Ok(concat(nzbw, out))
 }
 
 /// Setting `width` to 0 guarantees that nothing happens even with other
 /// arguments being invalid
-pub fn field_to(lhs: &Bits, to: &Bits, rhs: &Bits, width: &Bits) -> Awi {
+pub fn field_to(
+    lhs: &Bits,
+    to: &Bits,
+    rhs: &Bits,
+    width: &Bits,
+    strategy: FunnelStrategy,
+) -> Result<Awi, Error> {
     // the max shift value that can be anything but an effective no-op
     if let Some(s_w) = Bits::nontrivial_bits(lhs.bw() - 1) {
         // first, create the shifted image of `rhs`
         let mut s = Awi::zero(s_w);
         s.resize_(to, false);
-        let mut wide_rhs = Awi::opaque(NonZeroUsize::new(2 << s_w.get()).unwrap());
+        let mut wide_rhs = Awi::opaque(NonZeroUsize::new(2 << s_w.get()).lower_ok()?);
         let mut rev_rhs = Awi::zero(rhs.nzbw());
-        rev_rhs.copy_(rhs).unwrap();
+        rev_rhs.copy_(rhs).lower_ok()?;
         rev_rhs.rev_();
         if let Some(field_to) = lhs.bw().checked_sub(rhs.bw()) {
             let _ = wide_rhs.field_to(field_to, &rev_rhs, rhs.bw());
@@ -836,7 +970,7 @@ pub fn field_to(lhs: &Bits, to: &Bits, rhs: &Bits, width: &Bits) -> Awi {
             let field_from = rhs.bw().wrapping_sub(lhs.bw());
             let _ = wide_rhs.field_from(&rev_rhs, field_from, lhs.bw());
         }
-        let tmp = funnel(&wide_rhs, &s);
+        let tmp = funnel_with(&wide_rhs, &s, strategy)?;
         let mut funnel_res = Awi::zero(lhs.nzbw());
         funnel_res.resize_(&tmp, false);
         funnel_res.rev_();
@@ -845,19 +979,19 @@ pub fn field_to(lhs: &Bits, to: &Bits, rhs: &Bits, width: &Bits) -> Awi {
         // placed
 
         // need an extra bit for the `tsmear_inx` to work in all circumstances
-        let s_w = NonZeroUsize::new(s_w.get().checked_add(1).unwrap()).unwrap();
+        let s_w = NonZeroUsize::new(s_w.get().checked_add(1).lower_ok()?).lower_ok()?;
         let mut small_to = Awi::zero(s_w);
         small_to.usize_(to.to_usize());
         let mut small_width = Awi::zero(s_w);
         small_width.usize_(width.to_usize());
         // to + width
         let mut to_plus_width = small_width;
-        to_plus_width.add_(&small_to).unwrap();
+        to_plus_width.add_(&small_to).lower_ok()?;
         // trailing mask that trails `to + width`, exclusive
-        let tmask = tsmear_inx(&to_plus_width, lhs.bw());
+        let tmask = tsmear_inx(&to_plus_width, lhs.bw())?;
         // leading mask that leads `to`, inclusive, implemented by negating a trailing
         // mask of `to`
-        let lmask = tsmear_inx(&small_to, lhs.bw());
+        let lmask = tsmear_inx(&small_to, lhs.bw())?;
 
         // third, multiplex based on the masks
         let mut out = SmallVec::with_capacity(lhs.bw());
@@ -865,23 +999,30 @@ pub fn field_to(lhs: &Bits, to: &Bits, rhs: &Bits, width: &Bits) -> Awi {
             let mut signal = inlawi!(0);
             static_lut!(
                 signal; 1111_1011_0100_0000;
-                lmask[i], tmask[i], funnel_res.get(i).unwrap(), lhs.get(i).unwrap()
+                lmask[i], tmask[i], funnel_res.get(i).lower_ok()?, lhs.get(i).lower_ok()?
             );
             out.push(signal.state());
         }
 
-        concat(lhs.nzbw(), out)
+        Ok(concat(lhs.nzbw(), out))
     } else {
         let mut out = Awi::from_bits(lhs);
         let small_width = Awi::from_bool(width.lsb());
         let _ = out.field_width(rhs, small_width.to_usize());
-        out
+        Ok(out)
     }
 }
 
 /// Setting `width` to 0 guarantees that nothing happens even with other
 /// arguments being invalid
-pub fn field(lhs: &Bits, to: &Bits, rhs: &Bits, from: &Bits, width: &Bits) -> Awi {
+pub fn field(
+    lhs: &Bits,
+    to: &Bits,
+    rhs: &Bits,
+    from: &Bits,
+    width: &Bits,
+    strategy: FunnelStrategy,
+) -> Result<Awi, Error> {
     // we can shift both ways now, from the msb of `rhs` to the lsb of `lhs` and the
     // lsb of `rhs` to the msb of `lhs`.
     if let Some(s_w) = Bits::nontrivial_bits(lhs.bw() + rhs.bw() - 2) {
@@ -897,16 +1038,16 @@ pub fn field(lhs: &Bits, to: &Bits, rhs: &Bits, from: &Bits, width: &Bits) -> Aw
         small_from.resize_(from, false);
         small_to.resize_(to, false);
         s.usize_(rhs.bw() - 1);
-        s.sub_(&small_from).unwrap();
-        s.add_(&small_to).unwrap();
+        s.sub_(&small_from).lower_ok()?;
+        s.add_(&small_to).lower_ok()?;
 
         // first, create the shifted image of `rhs`
-        let mut wide_rhs = Awi::opaque(NonZeroUsize::new(2 << s_w.get()).unwrap());
+        let mut wide_rhs = Awi::opaque(NonZeroUsize::new(2 << s_w.get()).lower_ok()?);
         let mut rev_rhs = Awi::zero(rhs.nzbw());
-        rev_rhs.copy_(rhs).unwrap();
+        rev_rhs.copy_(rhs).lower_ok()?;
         rev_rhs.rev_();
         let _ = wide_rhs.field_to(lhs.bw() - 1, &rev_rhs, rhs.bw());
-        let tmp = funnel(&wide_rhs, &s);
+        let tmp = funnel_with(&wide_rhs, &s, strategy)?;
         let mut funnel_res = Awi::zero(lhs.nzbw());
         funnel_res.resize_(&tmp, false);
         funnel_res.rev_();
@@ -915,19 +1056,19 @@ pub fn field(lhs: &Bits, to: &Bits, rhs: &Bits, from: &Bits, width: &Bits) -> Aw
         // placed
 
         // need an extra bit for the `tsmear_inx` to work in all circumstances
-        let s_w = NonZeroUsize::new(s_w.get().checked_add(1).unwrap()).unwrap();
+        let s_w = NonZeroUsize::new(s_w.get().checked_add(1).lower_ok()?).lower_ok()?;
         let mut small_to = Awi::zero(s_w);
         small_to.usize_(to.to_usize());
         let mut small_width = Awi::zero(s_w);
         small_width.usize_(width.to_usize());
         // to + width
         let mut to_plus_width = small_width;
-        to_plus_width.add_(&small_to).unwrap();
+        to_plus_width.add_(&small_to).lower_ok()?;
         // trailing mask that trails `to + width`, exclusive
-        let tmask = tsmear_inx(&to_plus_width, lhs.bw());
+        let tmask = tsmear_inx(&to_plus_width, lhs.bw())?;
         // leading mask that leads `to`, inclusive, implemented by negating a trailing
         // mask of `to`
-        let lmask = tsmear_inx(&small_to, lhs.bw());
+        let lmask = tsmear_inx(&small_to, lhs.bw())?;
 
         // third, multiplex based on the masks
         let mut out = SmallVec::with_capacity(lhs.bw());
@@ -935,30 +1076,30 @@ pub fn field(lhs: &Bits, to: &Bits, rhs: &Bits, from: &Bits, width: &Bits) -> Aw
             let mut signal = inlawi!(0);
             static_lut!(
                 signal; 1111_1011_0100_0000;
-                lmask[i], tmask[i], funnel_res.get(i).unwrap(), lhs.get(i).unwrap()
+                lmask[i], tmask[i], funnel_res.get(i).lower_ok()?, lhs.get(i).lower_ok()?
             );
             out.push(signal.state());
         }
 
-        concat(lhs.nzbw(), out)
+        Ok(concat(lhs.nzbw(), out))
     } else {
         let mut out = Awi::from_bits(lhs);
         let small_width = Awi::from_bool(width.lsb());
         let _ = out.field_width(rhs, small_width.to_usize());
-        out
+        Ok(out)
     }
 }
 
-pub fn equal(lhs: &Bits, rhs: &Bits) -> inlawi_ty!(1) {
+pub fn equal(lhs: &Bits, rhs: &Bits) -> Result<inlawi_ty!(1), Error> {
     let mut ranks = vec![vec![]];
     for i in 0..lhs.bw() {
         let mut tmp1 = inlawi!(0);
-        static_lut!(tmp1; 1001; lhs.get(i).unwrap(), rhs.get(i).unwrap());
+        static_lut!(tmp1; 1001; lhs.get(i).lower_ok()?, rhs.get(i).lower_ok()?);
         ranks[0].push(tmp1);
     }
     // binary tree reduce
-    loop {
-        let prev_rank = ranks.last().unwrap();
+    Ok(loop {
+        let prev_rank = ranks.last().lower_ok()?;
         let rank_len = prev_rank.len();
         if rank_len == 1 {
             break prev_rank[0]
@@ -970,22 +1111,22 @@ pub fn equal(lhs: &Bits, rhs: &Bits) -> inlawi_ty!(1) {
             next_rank.push(tmp1);
         }
         if (rank_len & 1) != 0 {
-            next_rank.push(*prev_rank.last().unwrap())
+            next_rank.push(*prev_rank.last().lower_ok()?)
         }
         ranks.push(next_rank);
-    }
+    })
 }
 
 /// Uses the minimum number of bits to handle all cases, you may need to call
 /// `to_usize` on the result
-pub fn count_ones(x: &Bits) -> Awi {
+pub fn count_ones(x: &Bits) -> Result<Awi, Error> {
     // a tuple of an intermediate sum and the max possible value of that sum
     let mut ranks: Vec<Vec<(Awi, awi::Awi)>> = vec![vec![]];
     for i in 0..x.bw() {
-        ranks[0].push((Awi::from(x.get(i).unwrap()), awi::Awi::from(true)));
+        ranks[0].push((Awi::from(x.get(i).lower_ok()?), awi::Awi::from(true)));
     }
-    loop {
-        let prev_rank = ranks.last().unwrap();
+    Ok(loop {
+        let prev_rank = ranks.last().lower_ok()?;
         let rank_len = prev_rank.len();
         if rank_len == 1 {
             break prev_rank[0].0.clone()
@@ -1014,66 +1155,101 @@ pub fn count_ones(x: &Bits) -> Awi {
                     if tmp
                         .cin_sum_(
                             false,
-                            &awi!(zero: .., prev_rank[i].1; ..w).unwrap(),
+                            &awi!(zero: .., prev_rank[i].1; ..w).lower_ok()?,
                             &next_max,
                         )
-                        .unwrap()
+                        .lower_ok()?
                         .0
                     {
                         // do not add another previous sum to this sum because of overflow
                         break
                     }
-                    cc!(tmp; next_max).unwrap();
+                    cc!(tmp; next_max).lower_ok()?;
                 }
                 next_sum
-                    .add_(&awi!(zero: .., prev_rank[i].0; ..w).unwrap())
-                    .unwrap();
+                    .add_(&awi!(zero: .., prev_rank[i].0; ..w).lower_ok()?)
+                    .lower_ok()?;
             }
             next_rank.push((next_sum, next_max));
         }
         ranks.push(next_rank);
-    }
+    })
 }
 
 // If there is a set bit, it and the bits less significant than it will be set
-pub fn tsmear(x: &Bits) -> Awi {
+pub fn tsmear(x: &Bits) -> Result<Awi, Error> {
     let mut tmp0 = Awi::from(x);
     let mut lvl = 0;
     // exponentially OR cascade the smear
-    loop {
+    Ok(loop {
         let s = 1 << lvl;
         if s >= x.bw() {
             break tmp0
         }
         let mut tmp1 = tmp0.clone();
-        tmp1.lshr_(s).unwrap();
-        tmp0.or_(&tmp1).unwrap();
+        tmp1.lshr_(s).lower_ok()?;
+        tmp0.or_(&tmp1).lower_ok()?;
         lvl += 1;
+    })
+}
+
+/// Isolates the least significant set bit of `x` with a logarithmic depth,
+/// the result is zero if `x` is zero
+pub fn lowest_set(x: &Bits) -> Result<Awi, Error> {
+    let mut tmp = Awi::from_bits(x);
+    tmp.rev_();
+    // the lowest set bit and all bits more significant than it
+    let mut tmp = tsmear(&tmp)?;
+    tmp.rev_();
+    if x.bw() > 1 {
+        let mut above = tmp.clone();
+        above.shl_(1).lower_ok()?;
+        above.not_();
+        tmp.and_(&above).lower_ok()?;
+    }
+    Ok(tmp)
+}
+
+/// Encodes the one-hot `onehot` into the index of its set bit with bitwidth
+/// `w`, with an OR tree for each output bit. If `onehot` is not actually
+/// one-hot, the result is the bitwise OR of the indexes of all set bits.
+pub fn onehot_encode(onehot: &Bits, w: NonZeroUsize) -> Result<Awi, Error> {
+    let mut res = Awi::zero(w);
+    for j in 0..w.get() {
+        let mut bit = Awi::zero(bw(1));
+        for i in 0..onehot.bw() {
+            if (j < USIZE_BITS) && (((i >> j) & 1) != 0) {
+                bit.or_(&Awi::from_bool(onehot.get(i).lower_ok()?))
+                    .lower_ok()?;
+            }
+        }
+        res.set(j, bit.to_bool()).lower_ok()?;
     }
+    Ok(res)
 }
 
-pub fn leading_zeros(x: &Bits) -> Awi {
-    let mut tmp = tsmear(x);
+pub fn leading_zeros(x: &Bits) -> Result<Awi, Error> {
+    let mut tmp = tsmear(x)?;
     tmp.not_();
     count_ones(&tmp)
 }
 
-pub fn trailing_zeros(x: &Bits) -> Awi {
+pub fn trailing_zeros(x: &Bits) -> Result<Awi, Error> {
     let mut tmp = Awi::from_bits(x);
     tmp.rev_();
-    let mut tmp = tsmear(&tmp);
+    let mut tmp = tsmear(&tmp)?;
     tmp.not_();
     count_ones(&tmp)
 }
 
-pub fn significant_bits(x: &Bits) -> Awi {
-    count_ones(&tsmear(x))
+pub fn significant_bits(x: &Bits) -> Result<Awi, Error> {
+    count_ones(&tsmear(x)?)
 }
 
-pub fn lut_set(table: &Bits, entry: &Bits, inx: &Bits) -> Awi {
+pub fn lut_set(table: &Bits, entry: &Bits, inx: &Bits) -> Result<Awi, Error> {
     let num_entries = 1 << inx.bw();
     debug_assert_eq!(table.bw(), entry.bw() * num_entries);
-    let signals = selector(inx, Some(num_entries));
+    let signals = selector(inx, Some(num_entries))?;
     let mut out = Awi::from_bits(table);
     for (j, signal) in signals.into_iter().enumerate() {
         for i in 0..entry.bw() {
@@ -1081,17 +1257,22 @@ pub fn lut_set(table: &Bits, entry: &Bits, inx: &Bits) -> Awi {
             // mux_ between `lhs` or `entry` based on the signal
             let mut tmp1 = inlawi!(0);
             static_lut!(tmp1; 1100_1010;
-                table.get(lut_inx).unwrap(),
-                entry.get(i).unwrap(),
+                table.get(lut_inx).lower_ok()?,
+                entry.get(i).lower_ok()?,
                 signal
             );
-            out.set(lut_inx, tmp1.to_bool()).unwrap();
+            out.set(lut_inx, tmp1.to_bool()).lower_ok()?;
         }
     }
-    out
+    Ok(out)
 }
 
-pub fn mul_add(out_w: NonZeroUsize, add: Option<&Bits>, lhs: &Bits, rhs: &Bits) -> Awi {
+pub fn mul_add(
+    out_w: NonZeroUsize,
+    add: Option<&Bits>,
+    lhs: &Bits,
+    rhs: &Bits,
+) -> Result<Awi, Error> {
     // make `rhs` the smaller side, column size will be minimized
     let (lhs, rhs) = if lhs.bw() < rhs.bw() {
         (rhs, lhs)
@@ -1106,11 +1287,11 @@ pub fn mul_add(out_w: NonZeroUsize, add: Option<&Bits>, lhs: &Bits, rhs: &Bits)
         place_map1.push(vec![]);
     }
     for j in 0..rhs.bw() {
-        let rhs_j = rhs.get(j).unwrap();
+        let rhs_j = rhs.get(j).lower_ok()?;
         for i in 0..lhs.bw() {
             if let Some(place) = place_map0.get_mut(i + j) {
                 let mut ji = inlawi!(0);
-                static_lut!(ji; 1000; rhs_j, lhs.get(i).unwrap());
+                static_lut!(ji; 1000; rhs_j, lhs.get(i).lower_ok()?);
                 place.push(ji);
             }
         }
@@ -1118,7 +1299,7 @@ pub fn mul_add(out_w: NonZeroUsize, add: Option<&Bits>, lhs: &Bits, rhs: &Bits)
     if let Some(add) = add {
         for i in 0..add.bw() {
             if let Some(place) = place_map0.get_mut(i) {
-                place.push(inlawi!(add[i]).unwrap());
+                place.push(inlawi!(add[i]).lower_ok()?);
             }
         }
     }
@@ -1143,12 +1324,12 @@ pub fn mul_add(out_w: NonZeroUsize, add: Option<&Bits>, lhs: &Bits, rhs: &Bits)
             if let Some(w) = NonZeroUsize::new(place_map0[i].len()) {
                 let mut column = Awi::zero(w);
                 for (i, bit) in place_map0[i].drain(..).enumerate() {
-                    column.set(i, bit.to_bool()).unwrap();
+                    column.set(i, bit.to_bool()).lower_ok()?;
                 }
-                let row = count_ones(&column);
+                let row = count_ones(&column)?;
                 for j in 0..row.bw() {
                     if let Some(place) = place_map1.get_mut(i + j) {
-                        place.push(inlawi!(row[j]).unwrap())
+                        place.push(inlawi!(row[j]).lower_ok()?)
                     }
                 }
             }
@@ -1161,16 +1342,16 @@ pub fn mul_add(out_w: NonZeroUsize, add: Option<&Bits>, lhs: &Bits, rhs: &Bits)
     for i in 0..out.bw() {
         for (j, bit) in place_map0[i].iter().enumerate() {
             if j == 0 {
-                out.set(i, bit.to_bool()).unwrap();
+                out.set(i, bit.to_bool()).lower_ok()?;
             } else if j == 1 {
-                tmp.set(i, bit.to_bool()).unwrap();
+                tmp.set(i, bit.to_bool()).lower_ok()?;
             } else {
                 unreachable!()
             }
         }
     }
-    out.add_(&tmp).unwrap();
-    out
+    out.add_(&tmp).lower_ok()?;
+    Ok(out)
 }
 
 /// DAG version of division, most implementations should probably use a fast
@@ -1179,7 +1360,7 @@ pub fn mul_add(out_w: NonZeroUsize, add: Option<&Bits>, lhs: &Bits, rhs: &Bits)
 /// enough divisions sharing the same divisor, use fixed point inverses and
 /// multiplication. TODO try out other algorithms in the `specialized-div-rem`
 /// crate for this implementation.
-pub fn division(duo: &Bits, div: &Bits) -> (Awi, Awi) {
+pub fn division(duo: &Bits, div: &Bits) -> Result<(Awi, Awi), Error> {
     debug_assert_eq!(duo.bw(), div.bw());
 
     // this uses the nonrestoring SWAR algorithm, with `duo` and `div` extended by
@@ -1187,7 +1368,7 @@ pub fn division(duo: &Bits, div: &Bits) -> (Awi, Awi) {
     // remove or optimize more of the prelude?
 
     let original_w = duo.nzbw();
-    let w = NonZeroUsize::new(original_w.get() + 1).unwrap();
+    let w = NonZeroUsize::new(original_w.get() + 1).lower_ok()?;
     let mut tmp = Awi::zero(w);
     tmp.resize_(duo, false);
     let duo = tmp;
@@ -1249,41 +1430,41 @@ pub fn division(duo: &Bits, div: &Bits) -> (Awi, Awi) {
     return ((duo & mask) | quo, duo >> shl);
     */
 
-    let duo_lt_div = duo.ult(&div).unwrap();
+    let duo_lt_div = duo.ult(&div).lower_ok()?;
 
     // if there is a shortcut value it gets put in here and the `short`cut flag is
     // set to disable downstream shortcuts
     let mut short_quo = Awi::zero(w);
     let mut short_rem = Awi::zero(w);
     // leave `short_quo` as zero in both cases
-    short_rem.mux_(&duo, duo_lt_div).unwrap();
+    short_rem.mux_(&duo, duo_lt_div).lower_ok()?;
     let mut short = duo_lt_div;
 
-    let mut shl = leading_zeros(&div);
-    shl.sub_(&leading_zeros(&duo)).unwrap();
+    let mut shl = leading_zeros(&div)?;
+    shl.sub_(&leading_zeros(&duo)?).lower_ok()?;
     // if duo < (div << shl)
     let mut shifted_div = Awi::from_bits(&div);
-    shifted_div.shl_(shl.to_usize()).unwrap();
-    let reshift = duo.ult(&shifted_div).unwrap();
+    shifted_div.shl_(shl.to_usize()).lower_ok()?;
+    let reshift = duo.ult(&shifted_div).lower_ok()?;
     shl.dec_(!reshift);
 
     // if we need to reshift to correct for the shl decrement
     let mut reshifted = shifted_div.clone();
-    reshifted.lshr_(1).unwrap();
+    reshifted.lshr_(1).lower_ok()?;
     let mut div = shifted_div;
-    div.mux_(&reshifted, reshift).unwrap();
+    div.mux_(&reshifted, reshift).lower_ok()?;
 
     let mut duo = Awi::from_bits(&duo);
-    duo.sub_(&div).unwrap();
+    duo.sub_(&div).lower_ok()?;
     // 1 << shl efficiently
-    let tmp = selector_awi(&shl, Some(w.get()));
+    let tmp = selector_awi(&shl, Some(w.get()))?;
     let mut quo = Awi::zero(w);
     quo.resize_(&tmp, false);
 
     // if duo < div_original
-    let b = duo.ult(&div_original).unwrap();
-    short_quo.mux_(&quo, b & !short).unwrap();
-    short_rem.mux_(&duo, b & !short).unwrap();
+    let b = duo.ult(&div_original).lower_ok()?;
+    short_quo.mux_(&quo, b & !short).lower_ok()?;
+    short_rem.mux_(&duo, b & !short).lower_ok()?;
     short |= b;
     let mut mask = quo.clone();
     mask.dec_(false);
@@ -1300,29 +1481,29 @@ pub fn division(duo: &Bits, div: &Bits) -> (Awi, Awi) {
         let mut tmp0 = div.clone();
         tmp0.neg_(!duo.msb());
         let mut tmp1 = duo.clone();
-        tmp1.shl_(1).unwrap();
-        tmp1.add_(&tmp0).unwrap();
-        duo.mux_(&tmp1, !b).unwrap();
+        tmp1.shl_(1).lower_ok()?;
+        tmp1.add_(&tmp0).lower_ok()?;
+        duo.mux_(&tmp1, !b).lower_ok()?;
     }
     // final restore
     let mut tmp = Awi::zero(w);
-    tmp.mux_(&div, duo.msb()).unwrap();
-    duo.add_(&tmp).unwrap();
+    tmp.mux_(&div, duo.msb()).lower_ok()?;
+    duo.add_(&tmp).lower_ok()?;
 
     // unpack
 
     let mut tmp_quo = duo.clone();
-    tmp_quo.and_(&mask).unwrap();
-    tmp_quo.or_(&quo).unwrap();
+    tmp_quo.and_(&mask).lower_ok()?;
+    tmp_quo.or_(&quo).lower_ok()?;
     let mut tmp_rem = duo.clone();
-    tmp_rem.lshr_(shl.to_usize()).unwrap();
+    tmp_rem.lshr_(shl.to_usize()).lower_ok()?;
 
-    short_quo.mux_(&tmp_quo, !short).unwrap();
-    short_rem.mux_(&tmp_rem, !short).unwrap();
+    short_quo.mux_(&tmp_quo, !short).lower_ok()?;
+    short_rem.mux_(&tmp_rem, !short).lower_ok()?;
 
     let mut tmp0 = Awi::zero(original_w);
     let mut tmp1 = Awi::zero(original_w);
     tmp0.resize_(&short_quo, false);
     tmp1.resize_(&short_rem, false);
-    (tmp0, tmp1)
+    Ok((tmp0, tmp1))
 }
@@ -0,0 +1,445 @@
+//! PyO3 bindings, enabled by the "python" feature. This module defines a
+//! Python module named `starlight` with [starlight_module]. To build it, make
+//! an extension crate with a `cdylib` named `starlight` that depends on this
+//! crate and on `pyo3` with "extension-module" enabled, with just
+//! `pub use starlight::python::starlight_module;` as its source, and build it
+//! with e.x. `maturin`. The bindings live here so that they are updated
+//! together with the API they wrap.
+//!
+//! The classes mirror their Rust counterparts:
+//!
+//! - `Epoch()` creates and enters a new `Epoch`. Because Python does not drop
+//!   objects in a stacklike order, it has to be explicitly closed with
+//!   `close()` or used as a context manager, and `Epoch`s are closed in the
+//!   reverse order of their creation. It has `lower()`, `optimize()`,
+//!   `run(delay)`, `quiesced()`, `assert_assertions(strict)`, and `write_ir()`.
+//! - `Awi` is the mimicking `dag::Awi`, built with `Awi.zero(w)`,
+//!   `Awi.umax(w)`, `Awi.constant(value, w)`, or `LazyAwi.awi()`. The Python
+//!   operators `~ & | ^ + - * << >>` create new `Awi`s, and there are methods
+//!   for comparisons, multiplexing, and resizing.
+//! - `LazyAwi(w)` is an opaque input with `retro(value)`, `retro_unknown()`,
+//!   and `retro_const(value)`.
+//! - `EvalAwi(awi)` is an output with `eval()`, which returns a Python `int`.
+//! - `delay(awi, delay)` returns a delayed `Awi`, like [crate::delay].
+//! - `route(program_ir, target_ir, config_prefix)` routes designs written by
+//!   `Epoch.write_ir()` like [crate::cli::route_configs] does, returning a list
+//!   of `(name, value)` pairs.
+//!
+//! Values are Python `int`s, which are zero or sign extended to the bitwidth.
+//! Errors are raised as `RuntimeError`s, and invalid arguments as
+//! `ValueError`s.
+
+// the `PyResult`s returned by methods trigger this inside of `#[pymethods]`
+#![allow(clippy::useless_conversion)]
+
+use std::num::NonZeroUsize;
+
+pub use pyo3;
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyLong,
+};
+
+use crate::{awi, cli, dag, Error};
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        PyRuntimeError::new_err(e.to_string())
+    }
+}
+
+fn nzbw(w: usize) -> PyResult<NonZeroUsize> {
+    NonZeroUsize::new(w).ok_or_else(|| PyValueError::new_err("bitwidth cannot be zero"))
+}
+
+/// Converts a Python `int` into an `awi::Awi` of bitwidth `w`
+fn int_to_awi(value: &Bound<'_, PyAny>, w: NonZeroUsize) -> PyResult<awi::Awi> {
+    let hex: String = value
+        .downcast::<PyLong>()?
+        .call_method1("__format__", ("x",))?
+        .extract()?;
+    let res = if let Some(hex) = hex.strip_prefix('-') {
+        awi::Awi::from_str_radix(Some(true), hex, 16, w)
+    } else {
+        awi::Awi::from_str_radix(None, &hex, 16, w)
+    };
+    res.map_err(|e| PyValueError::new_err(format!("{value} does not fit in {w} bits: {e:?}")))
+}
+
+/// Converts `bits` into an unsigned Python `int`
+fn awi_to_int(py: Python, bits: &awi::Bits) -> PyResult<PyObject> {
+    let hex = awi::Awi::bits_to_string_radix(bits, false, 16, false, 1).unwrap();
+    Ok(py
+        .import_bound("builtins")?
+        .getattr("int")?
+        .call1((hex, 16))?
+        .into())
+}
+
+fn check_bw(lhs: usize, rhs: usize) -> PyResult<()> {
+    if lhs == rhs {
+        Ok(())
+    } else {
+        Err(Error::BitwidthMismatch(lhs, rhs).into())
+    }
+}
+
+/// See the module level documentation
+#[pyclass(unsendable, name = "Epoch")]
+pub struct PyEpoch {
+    epoch: Option<crate::Epoch>,
+}
+
+impl PyEpoch {
+    fn get(&self) -> PyResult<&crate::Epoch> {
+        self.epoch
+            .as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("the `Epoch` has already been closed"))
+    }
+}
+
+#[pymethods]
+impl PyEpoch {
+    #[new]
+    fn new() -> Self {
+        Self {
+            epoch: Some(crate::Epoch::new()),
+        }
+    }
+
+    /// Drops the `Epoch`, this should be done in the reverse order of
+    /// creation
+    fn close(&mut self) {
+        drop(self.epoch.take());
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _ty: &Bound<'_, PyAny>,
+        _value: &Bound<'_, PyAny>,
+        _traceback: &Bound<'_, PyAny>,
+    ) {
+        self.close();
+    }
+
+    fn lower(&self) -> PyResult<()> {
+        Ok(self.get()?.lower()?)
+    }
+
+    fn optimize(&self) -> PyResult<()> {
+        Ok(self.get()?.optimize()?)
+    }
+
+    fn run(&self, delay: u128) -> PyResult<()> {
+        Ok(self.get()?.run(delay)?)
+    }
+
+    fn quiesced(&self) -> PyResult<bool> {
+        Ok(self.get()?.quiesced()?)
+    }
+
+    fn assert_assertions(&self, strict: bool) -> PyResult<()> {
+        Ok(self.get()?.assert_assertions(strict)?)
+    }
+
+    /// Returns the `Ensemble` in the "slir" text format
+    fn write_ir(&self) -> PyResult<String> {
+        Ok(self.get()?.ensemble(|ensemble| ensemble.write_ir()))
+    }
+}
+
+/// See the module level documentation
+#[pyclass(unsendable, name = "Awi")]
+#[derive(Clone)]
+pub struct PyAwi {
+    awi: dag::Awi,
+}
+
+impl PyAwi {
+    fn binary<F: FnOnce(&mut dag::Awi, &dag::Awi)>(&self, rhs: &Self, f: F) -> PyResult<Self> {
+        check_bw(self.awi.bw(), rhs.awi.bw())?;
+        let mut awi = self.awi.clone();
+        f(&mut awi, &rhs.awi);
+        Ok(Self { awi })
+    }
+
+    fn compare<F: FnOnce(&dag::Awi, &dag::Awi) -> dag::bool>(
+        &self,
+        rhs: &Self,
+        f: F,
+    ) -> PyResult<Self> {
+        check_bw(self.awi.bw(), rhs.awi.bw())?;
+        let mut awi = dag::Awi::zero(awi::bw(1));
+        awi.bool_(f(&self.awi, &rhs.awi));
+        Ok(Self { awi })
+    }
+
+    fn shift<F: FnOnce(&mut dag::Awi, usize)>(&self, s: usize, f: F) -> PyResult<Self> {
+        if s >= self.awi.bw() {
+            return Err(PyValueError::new_err(format!(
+                "shift of {s} is not less than the bitwidth {}",
+                self.awi.bw()
+            )))
+        }
+        let mut awi = self.awi.clone();
+        f(&mut awi, s);
+        Ok(Self { awi })
+    }
+}
+
+#[pymethods]
+impl PyAwi {
+    #[staticmethod]
+    fn zero(w: usize) -> PyResult<Self> {
+        Ok(Self {
+            awi: dag::Awi::zero(nzbw(w)?),
+        })
+    }
+
+    #[staticmethod]
+    fn umax(w: usize) -> PyResult<Self> {
+        Ok(Self {
+            awi: dag::Awi::umax(nzbw(w)?),
+        })
+    }
+
+    #[staticmethod]
+    fn constant(value: &Bound<'_, PyAny>, w: usize) -> PyResult<Self> {
+        Ok(Self {
+            awi: dag::Awi::from(&int_to_awi(value, nzbw(w)?)?),
+        })
+    }
+
+    #[getter]
+    fn bw(&self) -> usize {
+        self.awi.bw()
+    }
+
+    fn __invert__(&self) -> Self {
+        let mut awi = self.awi.clone();
+        awi.not_();
+        Self { awi }
+    }
+
+    fn __and__(&self, rhs: &Self) -> PyResult<Self> {
+        self.binary(rhs, |lhs, rhs| lhs.and_(rhs).unwrap())
+    }
+
+    fn __or__(&self, rhs: &Self) -> PyResult<Self> {
+        self.binary(rhs, |lhs, rhs| lhs.or_(rhs).unwrap())
+    }
+
+    fn __xor__(&self, rhs: &Self) -> PyResult<Self> {
+        self.binary(rhs, |lhs, rhs| lhs.xor_(rhs).unwrap())
+    }
+
+    fn __add__(&self, rhs: &Self) -> PyResult<Self> {
+        self.binary(rhs, |lhs, rhs| lhs.add_(rhs).unwrap())
+    }
+
+    fn __sub__(&self, rhs: &Self) -> PyResult<Self> {
+        self.binary(rhs, |lhs, rhs| lhs.sub_(rhs).unwrap())
+    }
+
+    /// Wrapping multiplication
+    fn __mul__(&self, rhs: &Self) -> PyResult<Self> {
+        self.binary(rhs, |lhs, rhs| {
+            let tmp = lhs.clone();
+            lhs.zero_();
+            lhs.mul_add_(&tmp, rhs).unwrap();
+        })
+    }
+
+    fn __lshift__(&self, s: usize) -> PyResult<Self> {
+        self.shift(s, |awi, s| awi.shl_(s).unwrap())
+    }
+
+    /// Logical right shift
+    fn __rshift__(&self, s: usize) -> PyResult<Self> {
+        self.shift(s, |awi, s| awi.lshr_(s).unwrap())
+    }
+
+    /// Arithmetic right shift
+    fn ashr(&self, s: usize) -> PyResult<Self> {
+        self.shift(s, |awi, s| awi.ashr_(s).unwrap())
+    }
+
+    /// Returns a single bit `Awi` that is set if `self == rhs`
+    fn eq(&self, rhs: &Self) -> PyResult<Self> {
+        self.compare(rhs, |lhs, rhs| lhs.const_eq(rhs).unwrap())
+    }
+
+    fn ne(&self, rhs: &Self) -> PyResult<Self> {
+        self.compare(rhs, |lhs, rhs| lhs.const_ne(rhs).unwrap())
+    }
+
+    fn ult(&self, rhs: &Self) -> PyResult<Self> {
+        self.compare(rhs, |lhs, rhs| lhs.ult(rhs).unwrap())
+    }
+
+    fn ule(&self, rhs: &Self) -> PyResult<Self> {
+        self.compare(rhs, |lhs, rhs| lhs.ule(rhs).unwrap())
+    }
+
+    fn ilt(&self, rhs: &Self) -> PyResult<Self> {
+        self.compare(rhs, |lhs, rhs| lhs.ilt(rhs).unwrap())
+    }
+
+    fn ile(&self, rhs: &Self) -> PyResult<Self> {
+        self.compare(rhs, |lhs, rhs| lhs.ile(rhs).unwrap())
+    }
+
+    /// Returns bit `i` as a single bit `Awi`
+    fn get(&self, i: usize) -> PyResult<Self> {
+        if i >= self.awi.bw() {
+            return Err(PyValueError::new_err(format!(
+                "index {i} is out of bounds for bitwidth {}",
+                self.awi.bw()
+            )))
+        }
+        let mut awi = dag::Awi::zero(awi::bw(1));
+        awi.bool_(self.awi.get(i).unwrap());
+        Ok(Self { awi })
+    }
+
+    /// Returns `rhs` if the single bit `cond` is set, otherwise `self`
+    fn mux(&self, rhs: &Self, cond: &Self) -> PyResult<Self> {
+        check_bw(cond.awi.bw(), 1)?;
+        let cond = cond.awi.get(0).unwrap();
+        self.binary(rhs, |lhs, rhs| lhs.mux_(rhs, cond).unwrap())
+    }
+
+    fn zero_resize(&self, w: usize) -> PyResult<Self> {
+        let mut awi = dag::Awi::zero(nzbw(w)?);
+        awi.zero_resize_(&self.awi);
+        Ok(Self { awi })
+    }
+
+    fn sign_resize(&self, w: usize) -> PyResult<Self> {
+        let mut awi = dag::Awi::zero(nzbw(w)?);
+        awi.sign_resize_(&self.awi);
+        Ok(Self { awi })
+    }
+}
+
+/// See the module level documentation
+#[pyclass(unsendable, name = "LazyAwi")]
+pub struct PyLazyAwi {
+    lazy: crate::LazyAwi,
+}
+
+#[pymethods]
+impl PyLazyAwi {
+    #[new]
+    fn new(w: usize) -> PyResult<Self> {
+        Ok(Self {
+            lazy: crate::LazyAwi::opaque(nzbw(w)?),
+        })
+    }
+
+    #[getter]
+    fn bw(&self) -> usize {
+        self.lazy.bw()
+    }
+
+    /// Returns the `Awi` for building logic with the value of `self`
+    fn awi(&self) -> PyAwi {
+        PyAwi {
+            awi: dag::Awi::from(&self.lazy),
+        }
+    }
+
+    fn retro(&self, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        Ok(self.lazy.retro_(&int_to_awi(value, self.lazy.nzbw())?)?)
+    }
+
+    fn retro_unknown(&self) -> PyResult<()> {
+        Ok(self.lazy.retro_unknown_()?)
+    }
+
+    fn retro_const(&self, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        Ok(self
+            .lazy
+            .retro_const_(&int_to_awi(value, self.lazy.nzbw())?)?)
+    }
+
+    fn set_debug_name(&self, debug_name: &str) -> PyResult<()> {
+        Ok(self.lazy.set_debug_name(debug_name)?)
+    }
+}
+
+/// See the module level documentation
+#[pyclass(unsendable, name = "EvalAwi")]
+pub struct PyEvalAwi {
+    eval: crate::EvalAwi,
+}
+
+#[pymethods]
+impl PyEvalAwi {
+    #[new]
+    fn new(awi: &PyAwi) -> Self {
+        Self {
+            eval: crate::EvalAwi::from(&awi.awi),
+        }
+    }
+
+    #[getter]
+    fn bw(&self) -> usize {
+        self.eval.bw()
+    }
+
+    /// Evaluates to an unsigned `int`, raising an error if any bit is unknown
+    fn eval(&self, py: Python) -> PyResult<PyObject> {
+        awi_to_int(py, &self.eval.eval()?)
+    }
+
+    fn eval_is_all_unknown(&self) -> PyResult<bool> {
+        Ok(self.eval.eval_is_all_unknown()?)
+    }
+
+    fn set_debug_name(&self, debug_name: &str) -> PyResult<()> {
+        Ok(self.eval.set_debug_name(debug_name)?)
+    }
+}
+
+/// Returns `awi` delayed by `delay`
+#[pyfunction]
+fn delay(awi: &PyAwi, delay: u128) -> PyAwi {
+    let mut awi = awi.awi.clone();
+    crate::delay(&mut awi, delay);
+    PyAwi { awi }
+}
+
+/// Routes two designs in the "slir" text format and returns the `(name, value)`
+/// of every configuration value of the target
+#[pyfunction]
+#[pyo3(signature = (program_ir, target_ir, config_prefix = "config"))]
+fn route(
+    py: Python,
+    program_ir: &str,
+    target_ir: &str,
+    config_prefix: &str,
+) -> PyResult<Vec<(String, PyObject)>> {
+    cli::route_configs(program_ir, target_ir, config_prefix)?
+        .into_iter()
+        .map(|(name, value)| Ok((name, awi_to_int(py, &value)?)))
+        .collect()
+}
+
+/// The `starlight` Python module
+#[pymodule]
+#[pyo3(name = "starlight")]
+pub fn starlight_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEpoch>()?;
+    m.add_class::<PyAwi>()?;
+    m.add_class::<PyLazyAwi>()?;
+    m.add_class::<PyEvalAwi>()?;
+    m.add_function(wrap_pyfunction!(delay, m)?)?;
+    m.add_function(wrap_pyfunction!(route, m)?)?;
+    Ok(())
+}
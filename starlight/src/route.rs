@@ -2,12 +2,19 @@ mod cedge;
 mod channel;
 mod cnode;
 mod config;
+mod constant;
 #[cfg(feature = "debug")]
 mod debug;
 mod embed;
+pub mod fabrics;
+mod latency;
+mod pack;
 mod path;
+mod region;
 mod router;
 mod routing;
+mod schedule;
+mod verify;
 
 #[allow(unused)]
 use std::num::NonZeroU32;
@@ -15,12 +22,18 @@ use std::num::NonZeroU32;
 use awint::awint_dag::triple_arena::ptr_struct;
 pub use cedge::{CEdge, ChannelWidths, Programmability, SelectorLut};
 pub use channel::{Channeler, Referent};
-pub use cnode::CNode;
-pub use config::{Config, Configurator};
+pub use cnode::{CNode, ClusteringHeuristic, HierarchyConfig};
+pub use config::{Config, ConfigDelta, Configurator};
+pub use constant::{ConstantOrigin, ConstantRoute};
 pub use embed::{Embedding, EmbeddingKind};
+pub use latency::LatencyWrapper;
+pub use pack::Packing;
 pub use path::{Edge, EdgeKind, HyperPath, Path};
+pub use region::RegionReport;
 pub use router::Router;
 pub(crate) use routing::route;
+pub use schedule::{EmulationSchedule, Emulator, ScheduleConfig, SlotSource, StateSlot, TimeSlice};
+pub use verify::Divergence;
 
 #[cfg(any(
     debug_assertions,
@@ -14,11 +14,11 @@ use crate::{
     ensemble::{DynamicValue, Ensemble, LNodeKind, PBack},
     route::{
         channel::Referent,
-        cnode::{generate_hierarchy, InternalBehavior},
+        cnode::{generate_hierarchy, HierarchyConfig, InternalBehavior},
         CNode, Channeler, Configurator, PConfig, PEmbedding,
     },
     utils::SmallSet,
-    Error, SuspendedEpoch,
+    Delay, Error, SuspendedEpoch,
 };
 
 /// The selector can use its configuration bits to arbitrarily select from any
@@ -84,10 +84,15 @@ impl ChannelWidths {
 
 #[derive(Debug, Clone)]
 pub enum Programmability {
-    TNode,
+    /// A temporal edge with the same delay as the `TNode` it was created from
+    TNode(Delay),
 
     StaticLut(Awi),
 
+    /// A dedicated carry chain element with the carry in, lhs bit, and rhs bit
+    /// as sources, outputting the carry out
+    Carry,
+
     // `DynamicLut`s can go in one of two ways: the table bits all directly connect with unique
     // configurable bits and thus it can behave as an `ArbitraryLut`, or the inx bits directly
     // connect with configurable bits and thus can behave as `SelectorLut`s. Other cases must
@@ -106,8 +111,9 @@ impl Programmability {
     pub fn debug_strings(&self) -> Vec<String> {
         let mut v = vec![];
         match self {
-            Programmability::TNode => v.push("tnode".to_owned()),
+            Programmability::TNode(delay) => v.push(format!("tnode {}", delay.amount())),
             Programmability::StaticLut(lut) => v.push(format!("{}", lut)),
+            Programmability::Carry => v.push("carry".to_owned()),
             Programmability::ArbitraryLut(arbitrary_lut) => {
                 v.push(format!("ArbLut {}", arbitrary_lut.lut_config.len()))
             }
@@ -145,7 +151,8 @@ pub struct CEdge<PCNode: Ptr> {
     /// The weight needs to be at least 1 to prevent the algorithm from doing
     /// very bad routes
     pub delay_weight: NonZeroU32,
-    /// The lagrangian multiplier, fixed point such that (1 << 16) is 1.0
+    /// The lagrangian multiplier, fixed point such that (1 << 16) is 1.0.
+    /// `u32::MAX` forbids the router from using the edge.
     pub lagrangian: u32,
 
     /// Used by algorithms
@@ -153,6 +160,18 @@ pub struct CEdge<PCNode: Ptr> {
 }
 
 impl<PCNode: Ptr> CEdge<PCNode> {
+    /// If the router is not allowed to use this edge
+    pub fn is_forbidden(&self) -> bool {
+        self.lagrangian == u32::MAX
+    }
+
+    /// If the router can pass a signal from one source to the sink through
+    /// this edge. This is not possible for `Programmability::Carry`, because
+    /// the carry out depends on all of the sources.
+    pub fn is_traversable(&self) -> bool {
+        !(self.is_forbidden() || matches!(self.programmability, Programmability::Carry))
+    }
+
     pub fn programmability(&self) -> &Programmability {
         &self.programmability
     }
@@ -227,6 +246,16 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
         target_epoch.ensemble(|ensemble| Self::new(ensemble, configurator))
     }
 
+    /// Like [Channeler::from_target] but with a custom `HierarchyConfig`
+    pub fn from_target_with_config(
+        target_epoch: &SuspendedEpoch,
+        configurator: &Configurator,
+        hierarchy_config: &HierarchyConfig,
+    ) -> Result<Self, Error> {
+        target_epoch
+            .ensemble(|ensemble| Self::new_with_config(ensemble, configurator, hierarchy_config))
+    }
+
     pub fn from_program(target_epoch: &SuspendedEpoch) -> Result<Self, Error> {
         target_epoch.ensemble(|ensemble| Self::new(ensemble, &Configurator::new()))
     }
@@ -255,6 +284,16 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
 
     /// Assumes that the ensemble has been optimized
     pub fn new(ensemble: &Ensemble, configurator: &Configurator) -> Result<Self, Error> {
+        Self::new_with_config(ensemble, configurator, &HierarchyConfig::default())
+    }
+
+    /// Like [Channeler::new] but with a custom `HierarchyConfig` controlling
+    /// how the hierarchy is generated
+    pub fn new_with_config(
+        ensemble: &Ensemble,
+        configurator: &Configurator,
+        hierarchy_config: &HierarchyConfig,
+    ) -> Result<Self, Error> {
         let mut channeler = Self::empty();
 
         // for each equivalence make a `CNode` with associated `EnsembleBackref`, unless
@@ -326,7 +365,25 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
                     channeler.make_cedge(
                         &v,
                         p_self,
-                        Programmability::StaticLut(awi.clone()),
+                        Programmability::StaticLut(awi.to_awi()),
+                        NonZeroU32::new(1).unwrap(),
+                    );
+                }
+                LNodeKind::Carry(inp) => {
+                    let mut v = SmallVec::<[PCNode; 8]>::with_capacity(inp.len());
+                    for input in inp {
+                        let (p_equiv, p_cnode) = channeler.translate(ensemble, *input);
+                        if configurator.find(p_equiv).is_some() {
+                            return Err(Error::OtherStr(
+                                "configurable bits driving a carry chain are not supported",
+                            ))
+                        }
+                        v.push(p_cnode.unwrap());
+                    }
+                    channeler.make_cedge(
+                        &v,
+                        p_self,
+                        Programmability::Carry,
                         NonZeroU32::new(1).unwrap(),
                     );
                 }
@@ -389,10 +446,13 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
                                     }
                                     sources.push(p_cnode.unwrap());
                                 }
-                                // target ensemble is not correct
-                                DynamicValue::ConstUnknown | DynamicValue::Const(_) => {
-                                    unreachable!()
+                                // a tied off selector input, which the router can use as a
+                                // source of constants
+                                DynamicValue::Const(b) => {
+                                    sources.push(channeler.constant_cnode(b));
                                 }
+                                // target ensemble is not correct
+                                DynamicValue::ConstUnknown => unreachable!(),
                             }
                         }
                         channeler.make_cedge(
@@ -420,7 +480,7 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
             channeler.make_cedge(
                 &v,
                 channeler.translate(ensemble, tnode.p_self).1.unwrap(),
-                Programmability::TNode,
+                Programmability::TNode(tnode.delay()),
                 NonZeroU32::new(
                     u32::try_from(
                         tnode
@@ -435,7 +495,7 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
             );
         }
 
-        generate_hierarchy(&mut channeler)?;
+        generate_hierarchy(&mut channeler, hierarchy_config)?;
 
         Ok(channeler)
     }
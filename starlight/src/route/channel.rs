@@ -4,7 +4,7 @@ use awint::awint_dag::triple_arena::{Arena, OrdArena, Ptr, SurjectArena};
 
 use crate::{
     ensemble::PBack,
-    route::{CEdge, CNode, PBackrefToBackref, PTopLevel, Programmability},
+    route::{cnode::InternalBehavior, CEdge, CNode, PBackrefToBackref, PTopLevel, Programmability},
     Error,
 };
 
@@ -27,6 +27,10 @@ pub struct Channeler<PCNode: Ptr, PCEdge: Ptr> {
     pub top_level_cnodes: OrdArena<PTopLevel, PCNode, ()>,
     // needed for the unit edges to find incidences
     pub ensemble_backref_to_channeler_backref: OrdArena<PBackrefToBackref, PBack, PCNode>,
+    /// The `CNode`s that are sources of constant `false` and `true` values,
+    /// indexed by the value. These are created for `DynamicValue::Const` table
+    /// entries of target selectors, and have no ensemble backref.
+    pub constant_cnodes: [Option<PCNode>; 2],
     // used by algorithms to avoid `OrdArena`s
     pub alg_visit: NonZeroU64,
 }
@@ -38,6 +42,7 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
             cedges: Arena::new(),
             top_level_cnodes: OrdArena::new(),
             ensemble_backref_to_channeler_backref: OrdArena::new(),
+            constant_cnodes: [None, None],
             alg_visit: NonZeroU64::new(2).unwrap(),
         }
     }
@@ -58,6 +63,25 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
         Some(self.cnodes.get_val(p_ref).unwrap().p_this_cnode)
     }
 
+    /// Returns the `CNode` that is a source of the constant `b`, if there is
+    /// one
+    pub fn find_constant_cnode(&self, b: bool) -> Option<PCNode> {
+        self.constant_cnodes[usize::from(b)]
+    }
+
+    /// Returns the `CNode` that is a source of the constant `b`, creating it if
+    /// it does not exist yet. This should only be used before the hierarchy is
+    /// generated.
+    pub fn constant_cnode(&mut self, b: bool) -> PCNode {
+        if let Some(p_cnode) = self.find_constant_cnode(b) {
+            p_cnode
+        } else {
+            let p_cnode = self.make_top_level_cnode(vec![], 0, InternalBehavior::empty());
+            self.constant_cnodes[usize::from(b)] = Some(p_cnode);
+            p_cnode
+        }
+    }
+
     pub fn verify_integrity(&self) -> Result<(), Error> {
         // return errors in order of most likely to be root cause
 
@@ -143,6 +167,13 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
                 )))
             }
         }
+        for p_cnode in self.constant_cnodes.iter().flatten().copied() {
+            if !self.cnodes.contains(p_cnode) {
+                return Err(Error::OtherString(format!(
+                    "constant_cnodes {p_cnode:?} is invalid"
+                )))
+            }
+        }
         // Other roundtrips from `backrefs` direction to ensure bijection
         for p_back in self.cnodes.ptrs() {
             let referent = self.cnodes.get_key(p_back).unwrap();
@@ -189,7 +220,8 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
             let cedge = self.cedges.get(p_cedge).unwrap();
             let sources_len = cedge.sources().len();
             let ok = match cedge.programmability() {
-                Programmability::TNode => sources_len == 1,
+                Programmability::TNode(_) => sources_len == 1,
+                Programmability::Carry => sources_len == 3,
                 Programmability::StaticLut(lut) => {
                     // TODO find every place I did the trailing zeros thing and have a function that
                     // does the more efficient thing the core `lut_` function does
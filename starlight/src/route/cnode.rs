@@ -1,6 +1,7 @@
 use std::{
     cmp::max,
     collections::BinaryHeap,
+    fmt::Write,
     num::{NonZeroU32, NonZeroU64},
 };
 
@@ -31,6 +32,62 @@ impl InternalBehavior {
     }
 }
 
+/// The order in which `CNode`s of a level are considered for concentration
+/// while generating the hierarchy. The first `CNode`s considered get to
+/// concentrate with all of their neighbors, while later ones may be blocked by
+/// neighbors that have already been concentrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClusteringHeuristic {
+    /// `CNode`s with the widest channels are considered first, which keeps
+    /// heavily connected regions together
+    #[default]
+    WidestChannel,
+    /// `CNode`s with the narrowest channels are considered first
+    NarrowestChannel,
+    /// `CNode`s with the fewest bottom level subnodes are considered first,
+    /// which keeps the tree balanced on irregular targets
+    SmallestSubtree,
+}
+
+impl ClusteringHeuristic {
+    fn priority(self, channel_exit_width: usize, subnodes_in_tree: usize) -> usize {
+        match self {
+            ClusteringHeuristic::WidestChannel => channel_exit_width,
+            ClusteringHeuristic::NarrowestChannel => usize::MAX - channel_exit_width,
+            ClusteringHeuristic::SmallestSubtree => usize::MAX - subnodes_in_tree,
+        }
+    }
+}
+
+/// Parameters controlling how a `Channeler` generates its hierarchy of
+/// concentrated `CNode`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HierarchyConfig {
+    /// The maximum number of `CNode`s that can be concentrated into a single
+    /// supernode, must be at least 2. This does not apply to the single final
+    /// `CNode`, see `max_levels`.
+    pub max_branching: usize,
+    /// The maximum number of levels generated before all the remaining top
+    /// level `CNode`s are concentrated into a single final `CNode`, must be at
+    /// least 1. The final `CNode` has every remaining top level `CNode` as a
+    /// direct subnode regardless of `max_branching`, because there are no
+    /// `CEdge`s at its level that would allow routing between intermediate
+    /// supernodes. If `max_levels` stops the generation early, this can be a
+    /// very wide node.
+    pub max_levels: u16,
+    pub heuristic: ClusteringHeuristic,
+}
+
+impl Default for HierarchyConfig {
+    fn default() -> Self {
+        Self {
+            max_branching: usize::MAX,
+            max_levels: u16::MAX,
+            heuristic: ClusteringHeuristic::default(),
+        }
+    }
+}
+
 /// A channel node
 #[derive(Debug, Clone)]
 pub struct CNode<PCNode: Ptr, PCEdge: Ptr> {
@@ -90,6 +147,46 @@ impl<PCNode: Ptr, PCEdge: Ptr> Channeler<PCNode, PCEdge> {
         p_cnode
     }
 
+    /// Returns a summary of each level of the hierarchy, with the number of
+    /// `CNode`s, the `CEdge`s with sinks on the level, and their capacities in
+    /// terms of bottom level subnodes, LUT bits, and total channel exit width
+    pub fn debug_hierarchy(&self) -> String {
+        // cnodes, subnodes in tree, lut bits, cedges, channel exit width
+        let num_levels = self
+            .cnodes
+            .vals()
+            .map(|cnode| cnode.lvl)
+            .max()
+            .map_or(0, |lvl| usize::from(lvl) + 1);
+        let mut levels = vec![[0usize; 5]; num_levels];
+        for cnode in self.cnodes.vals() {
+            let lvl = usize::from(cnode.lvl);
+            levels[lvl][0] += 1;
+            levels[lvl][1] += cnode.internal_behavior.subnodes_in_tree;
+            levels[lvl][2] += cnode.internal_behavior.lut_bits;
+        }
+        for cedge in self.cedges.vals() {
+            let lvl = usize::from(self.cnodes.get_val(cedge.sink()).unwrap().lvl);
+            levels[lvl][3] += 1;
+            levels[lvl][4] += match cedge.programmability() {
+                Programmability::Bulk(bulk) => bulk.channel_exit_width,
+                _ => 1,
+            };
+        }
+        let mut s = String::new();
+        for (lvl, [cnodes, subnodes, lut_bits, cedges, exit_width]) in
+            levels.into_iter().enumerate()
+        {
+            writeln!(
+                s,
+                "lvl {lvl}: {cnodes} cnodes ({subnodes} subnodes in trees, {lut_bits} LUT bits), \
+                 {cedges} cedges (channel exit width {exit_width})"
+            )
+            .unwrap();
+        }
+        s
+    }
+
     #[must_use]
     pub fn get_supernode_referent(&self, p: PCNode) -> Option<PCNode> {
         self.cnodes.get_val(p)?.p_supernode
@@ -209,7 +306,18 @@ dilution of paths to different cedges when necessary.
 /// a graph of unit channel nodes and edges
 pub fn generate_hierarchy<PCNode: Ptr, PCEdge: Ptr>(
     channeler: &mut Channeler<PCNode, PCEdge>,
+    config: &HierarchyConfig,
 ) -> Result<(), Error> {
+    if config.max_branching < 2 {
+        return Err(Error::OtherStr(
+            "`HierarchyConfig::max_branching` must be at least 2",
+        ))
+    }
+    if config.max_levels == 0 {
+        return Err(Error::OtherStr(
+            "`HierarchyConfig::max_levels` must be at least 1",
+        ))
+    }
     // when a `CNode` ends up with no edges to anything
     let mut final_top_level_cnodes = Vec::<PCNode>::new();
     let mut possibly_single_subnode = Vec::<PCNode>::new();
@@ -238,10 +346,15 @@ pub fn generate_hierarchy<PCNode: Ptr, PCEdge: Ptr>(
             generate_hierarchy_level(
                 current_lvl,
                 channeler,
+                config,
                 &mut priority,
                 &mut possibly_single_subnode,
                 &mut next_level_cnodes,
             )?;
+            if current_lvl >= config.max_levels {
+                // the remaining top level nodes are concentrated below
+                break
+            }
             continue;
         };
         let cnode = channeler.cnodes.get_val(p_consider).unwrap();
@@ -253,12 +366,14 @@ pub fn generate_hierarchy<PCNode: Ptr, PCEdge: Ptr>(
         // For each cnode on a given level, we will attempt to concentrate it and all
         // its neighbors. If any neighbor has a supernode already, it skips the cnode
 
-        let related = channeler.related_nodes(p_consider);
+        let mut related = channeler.related_nodes(p_consider);
         if related.len() == 1 {
             // the node is disconnected
             final_top_level_cnodes.push(p_consider);
             continue
         }
+        // `p_consider` is always first
+        related.truncate(config.max_branching);
         let mut subnodes_in_tree = 0usize;
         let mut lut_bits = 0usize;
         // check if any related nodes have supernodes
@@ -294,8 +409,9 @@ pub fn generate_hierarchy<PCNode: Ptr, PCEdge: Ptr>(
     // TODO optimize to just use `final_top_level_cnodes` in this final step,
     // avoiding all other uses of `top_level_cnodes` in the middle
 
-    // if there are multiple cnodes are left in an anticlique, concentrate them into
-    // a single top level node
+    // if there are multiple cnodes are left in an anticlique or because of
+    // `max_levels`, concentrate them into a single top level node. This ignores
+    // `max_branching` as documented in `HierarchyConfig`.
     if channeler.top_level_cnodes.len() > 1 {
         let mut set = vec![];
         let mut max_lvl = 0;
@@ -323,6 +439,7 @@ pub fn generate_hierarchy<PCNode: Ptr, PCEdge: Ptr>(
 pub fn generate_hierarchy_level<PCNode: Ptr, PCEdge: Ptr>(
     current_lvl: u16,
     channeler: &mut Channeler<PCNode, PCEdge>,
+    config: &HierarchyConfig,
     priority: &mut BinaryHeap<(usize, PCNode)>,
     possibly_single_subnode: &mut Vec<PCNode>,
     next_level_cnodes: &mut Vec<PCNode>,
@@ -378,7 +495,7 @@ pub fn generate_hierarchy_level<PCNode: Ptr, PCEdge: Ptr>(
                         let cedge = channeler.cedges.get_mut(p_cedge).unwrap();
 
                         let w = match cedge.programmability() {
-                            Programmability::TNode => 1,
+                            Programmability::TNode(_) | Programmability::Carry => 1,
                             Programmability::StaticLut(lut) => {
                                 lut_bits = lut_bits.checked_add(lut.bw()).unwrap();
                                 1
@@ -409,8 +526,9 @@ pub fn generate_hierarchy_level<PCNode: Ptr, PCEdge: Ptr>(
                                     source_set.push(supernode.p_this_cnode);
                                 }
                                 let w = match cedge.programmability() {
-                                    Programmability::TNode
+                                    Programmability::TNode(_)
                                     | Programmability::StaticLut(_)
+                                    | Programmability::Carry
                                     | Programmability::ArbitraryLut(_)
                                     | Programmability::SelectorLut(_) => 1,
                                     Programmability::Bulk(bulk) => bulk.channel_entry_widths[i],
@@ -438,7 +556,13 @@ pub fn generate_hierarchy_level<PCNode: Ptr, PCEdge: Ptr>(
         // because everything that doesn't have overlap issues will be concentrated
         // every round.
         let channel_exit_width = channel_widths.channel_exit_width;
-        priority.push((channel_exit_width, p_consider));
+        let subnodes_in_tree = internal_behavior.subnodes_in_tree;
+        priority.push((
+            config
+                .heuristic
+                .priority(channel_exit_width, subnodes_in_tree),
+            p_consider,
+        ));
         // create the edge
         if !source_set.is_empty() {
             for source in source_set.iter().cloned() {
@@ -1,9 +1,18 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroU64,
+};
+
 use awint::{awint_dag::triple_arena::OrdArena, Awi};
 
 use crate::{
-    ensemble::{Ensemble, PBack, PExternal},
+    awi_structs::{schedule_event, Scheduled},
+    ensemble::{Delay, Ensemble, PBack, PExternal, Value},
     epoch::get_current_epoch,
-    route::{EdgeKind, EmbeddingKind, PConfig, Programmability, Router},
+    route::{
+        Channeler, ConstantOrigin, EdgeKind, EmbeddingKind, PConfig, Programmability, QCEdge,
+        QCNode, Router,
+    },
     Error, LazyAwi,
 };
 
@@ -16,6 +25,43 @@ pub struct Config {
     /// The bit value the configuration wants. `None` is for not yet determined
     /// or for if the value can be set to `Value::Unknown`.
     pub value: Option<bool>,
+    /// The partial reconfiguration region that this bit belongs to, `None`
+    /// is for the static part of the target
+    pub region: Option<usize>,
+}
+
+/// Changes to configuration bits of a target, used with
+/// [Router::apply_config_at]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDelta {
+    /// The `RNode`s, bit indexes, and new values of the changed bits. `None`
+    /// values change the bit to unknown.
+    pub changes: Vec<(PExternal, usize, Option<bool>)>,
+}
+
+impl ConfigDelta {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a change of the bit at `bit_i` of `config` to `value`
+    pub fn push<L: std::borrow::Borrow<LazyAwi>>(
+        &mut self,
+        config: &L,
+        bit_i: usize,
+        value: Option<bool>,
+    ) {
+        self.changes
+            .push((config.borrow().p_external(), bit_i, value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
 }
 
 /// The channeler for the target needs to know which bits the router can use to
@@ -49,11 +95,36 @@ impl Configurator {
         self.ensemble_make_configurable(ensemble, config)
     }
 
+    /// Like [Configurator::configurable], but also declares that the bits
+    /// belong to the partial reconfiguration region `region`, see
+    /// [Router::route_into_region]. Uses the currently active `Epoch`.
+    pub fn configurable_in_region<L: std::borrow::Borrow<LazyAwi>>(
+        &mut self,
+        config: &L,
+        region: usize,
+    ) -> Result<(), Error> {
+        let epoch_shared = get_current_epoch()?;
+        let lock = epoch_shared.epoch_data.borrow();
+        let ensemble = &lock.ensemble;
+        self.ensemble_make_configurable_in(ensemble, config, Some(region))
+    }
+
     /// Tell the router what bits it can use for programming the target
     pub fn ensemble_make_configurable<L: std::borrow::Borrow<LazyAwi>>(
         &mut self,
         ensemble: &Ensemble,
         config: &L,
+    ) -> Result<(), Error> {
+        self.ensemble_make_configurable_in(ensemble, config, None)
+    }
+
+    /// Tell the router what bits it can use for programming the target and
+    /// what partial reconfiguration region they belong to
+    pub fn ensemble_make_configurable_in<L: std::borrow::Borrow<LazyAwi>>(
+        &mut self,
+        ensemble: &Ensemble,
+        config: &L,
+        region: Option<usize>,
     ) -> Result<(), Error> {
         let config = config.borrow();
         let p_external = config.p_external();
@@ -66,6 +137,7 @@ impl Configurator {
                         p_external,
                         bit_i,
                         value: None,
+                        region,
                     });
                     // we may want to allow this, if we have a mechanism to make sure they are
                     // set to the same thing
@@ -89,14 +161,122 @@ impl Configurator {
 }
 
 impl Router {
-    /// Requires that the target epoch be resumed and is the active epoch
+    /// After routing is done, this sets the configurable bits of the target
+    /// to the configuration found by the router. Bits that did not need to be
+    /// specified are set to `Unknown`. Requires that the target epoch be
+    /// resumed and is the active epoch.
     pub fn config_target(&self) -> Result<(), Error> {
-        todo!()
+        let epoch_shared = get_current_epoch()?;
+        let mut lock = epoch_shared.epoch_data.borrow_mut();
+        self.ensemble_config_target(&mut lock.ensemble)
     }
 
-    /*pub fn ensemble_config_target(&self, ensemble: &mut Ensemble) -> Result<(), Error> {
+    /// Like [Router::config_target], but sets the bits in `ensemble` which
+    /// should be the target ensemble or a clone of it
+    pub fn ensemble_config_target(&self, ensemble: &mut Ensemble) -> Result<(), Error> {
+        for config in self.configurator.configurations.vals() {
+            let (_, rnode) = ensemble.notary.get_rnode(config.p_external)?;
+            let p_bit =
+                if let Some(Some(p_bit)) = rnode.bits().and_then(|bits| bits.get(config.bit_i)) {
+                    *p_bit
+                } else {
+                    return Err(Error::OtherString(format!(
+                        "`config_target`: the configuration bit {} of {:#?} is not in the target, \
+                         the target was probably mutated after the router was created",
+                        config.bit_i, config.p_external
+                    )))
+                };
+            let value = if let Some(b) = config.value {
+                Value::Dynam(b)
+            } else {
+                Value::Unknown
+            };
+            ensemble.change_value(p_bit, value, NonZeroU64::new(1).unwrap())?;
+        }
         Ok(())
-    }*/
+    }
+
+    /// Returns the changes needed to go from the configuration found by `self`
+    /// to the configuration found by `other`, which should be a `Router` for
+    /// the same target, e.x. one that routed a different program into a
+    /// partial reconfiguration region. Bits that `other` does not specify are
+    /// not changed.
+    pub fn config_delta(&self, other: &Router) -> ConfigDelta {
+        let mut current = HashMap::new();
+        for config in self.configurator.configurations.vals() {
+            current.insert((config.p_external, config.bit_i), config.value);
+        }
+        let mut res = ConfigDelta::new();
+        for config in other.configurator.configurations.vals() {
+            if config.value.is_some()
+                && (current.get(&(config.p_external, config.bit_i)) != Some(&config.value))
+            {
+                res.changes
+                    .push((config.p_external, config.bit_i, config.value));
+            }
+        }
+        res
+    }
+
+    /// Schedules the changes of `config_delta` to happen when the target
+    /// `Epoch` is run to the absolute `time` (see
+    /// [Epoch::current_time](crate::Epoch::current_time)), for simulating
+    /// reconfiguration at runtime after [Router::config_target]. The changes
+    /// become events like those of
+    /// [Epoch::schedule_external_event](crate::Epoch::schedule_external_event)
+    /// and are applied in order. Glitches during partial reconfiguration can
+    /// be observed by splitting a delta and scheduling the parts at different
+    /// times. Requires that the target epoch be resumed and is the active
+    /// epoch.
+    ///
+    /// # Errors
+    ///
+    /// If a changed bit is not configurable in the configurator of `self`, or
+    /// if `time` is before the current time. Nothing is scheduled if an error
+    /// is returned.
+    pub fn apply_config_at<D: Into<Delay>>(
+        &self,
+        time: D,
+        config_delta: &ConfigDelta,
+    ) -> Result<(), Error> {
+        let time = time.into();
+        let epoch_shared = get_current_epoch()?;
+        {
+            let lock = epoch_shared.epoch_data.borrow();
+            let configurable: HashSet<(PExternal, usize)> = self
+                .configurator
+                .configurations
+                .vals()
+                .map(|config| (config.p_external, config.bit_i))
+                .collect();
+            for (p_external, bit_i, _) in config_delta.changes.iter().copied() {
+                if !configurable.contains(&(p_external, bit_i)) {
+                    return Err(Error::OtherString(format!(
+                        "`apply_config_at`: bit {bit_i} of {p_external} is not configurable in \
+                         the configurator"
+                    )))
+                }
+            }
+            let current_time = lock.ensemble.delayer.current_time;
+            if time < current_time {
+                return Err(Error::OtherString(format!(
+                    "`apply_config_at`: time {} is before the current time {}",
+                    time.amount(),
+                    current_time.amount()
+                )))
+            }
+        }
+        for (p_external, bit_i, value) in config_delta.changes.iter().copied() {
+            schedule_event(
+                &epoch_shared,
+                "apply_config_at",
+                time,
+                p_external,
+                Scheduled::Bit(bit_i, value),
+            )?;
+        }
+        Ok(())
+    }
 
     /// Sets all the configurations derived from final embeddings
     pub(crate) fn set_configurations(&mut self) -> Result<(), Error> {
@@ -110,49 +290,11 @@ impl Router {
                     // follow the `SelectorLut`s of the hyperpath
                     for path in embedding.target_hyperpath.paths() {
                         for edge in path.edges() {
-                            match edge.kind {
-                                EdgeKind::Transverse(q_cedge, source_i) => {
-                                    let cedge = self.target_channeler.cedges.get(q_cedge).unwrap();
-                                    match cedge.programmability() {
-                                        // no-op with respect to configuration
-                                        Programmability::TNode => (),
-                                        // there are identity like cases where we might want to
-                                        // traverse these kinds
-                                        Programmability::StaticLut(_) => todo!(),
-                                        Programmability::ArbitraryLut(_) => todo!(),
-                                        Programmability::SelectorLut(selector_lut) => {
-                                            let inx_config = selector_lut.inx_config();
-                                            assert!(source_i < (1 << inx_config.len()));
-                                            let i = Awi::from_usize(source_i);
-                                            for (inx_i, p_config) in
-                                                inx_config.iter().copied().enumerate()
-                                            {
-                                                let value = &mut self
-                                                    .configurator
-                                                    .configurations
-                                                    .get_val_mut(p_config)
-                                                    .unwrap()
-                                                    .value;
-                                                let desired_value = Some(i.get(inx_i).unwrap());
-                                                if value.is_some() && (*value != desired_value) {
-                                                    // means hyperpaths or base embeddings are
-                                                    // conflicting
-                                                    panic!(
-                                                        "bug in router, a configuration bit has \
-                                                         already been set and contradicts another \
-                                                         desired configuration"
-                                                    );
-                                                }
-                                                *value = desired_value;
-                                            }
-                                        }
-                                        // the hyperpath should be fully lowered
-                                        Programmability::Bulk(_) => unreachable!(),
-                                    }
-                                }
-                                // the hyperpath should be fully lowered into base level traversals
-                                EdgeKind::Concentrate | EdgeKind::Dilute => unreachable!(),
-                            }
+                            set_edge_configuration(
+                                &self.target_channeler,
+                                &mut self.configurator,
+                                edge.kind,
+                            )?;
                         }
                     }
                 }
@@ -161,10 +303,85 @@ impl Router {
             }
         }
 
+        for route in &self.constant_routes {
+            if let ConstantOrigin::Tie(q_cedge) = route.origin {
+                let cedge = self.target_channeler.cedges.get(q_cedge).unwrap();
+                if let Programmability::ArbitraryLut(arbitrary_lut) = cedge.programmability() {
+                    for p_config in arbitrary_lut.lut_config().iter().copied() {
+                        set_configuration(&mut self.configurator, p_config, route.value);
+                    }
+                } else {
+                    unreachable!()
+                }
+            }
+            for edge in route.path.edges() {
+                set_edge_configuration(&self.target_channeler, &mut self.configurator, edge.kind)?;
+            }
+        }
+
         Ok(())
     }
 }
 
+fn set_configuration(configurator: &mut Configurator, p_config: PConfig, desired_value: bool) {
+    let value = &mut configurator
+        .configurations
+        .get_val_mut(p_config)
+        .unwrap()
+        .value;
+    let desired_value = Some(desired_value);
+    if value.is_some() && (*value != desired_value) {
+        // means hyperpaths or base embeddings are conflicting
+        panic!(
+            "bug in router, a configuration bit has already been set and contradicts another \
+             desired configuration"
+        );
+    }
+    *value = desired_value;
+}
+
+/// Sets the configuration needed to traverse a base level edge
+fn set_edge_configuration(
+    target_channeler: &Channeler<QCNode, QCEdge>,
+    configurator: &mut Configurator,
+    edge_kind: EdgeKind<QCEdge>,
+) -> Result<(), Error> {
+    match edge_kind {
+        EdgeKind::Transverse(q_cedge, source_i) => {
+            let cedge = target_channeler.cedges.get(q_cedge).unwrap();
+            match cedge.programmability() {
+                // no-op with respect to configuration
+                Programmability::TNode(_) => (),
+                // there are identity like cases where we might want to
+                // traverse these kinds
+                Programmability::StaticLut(_) => todo!(),
+                // the carry out depends on all of the sources, the path searches do not
+                // traverse these
+                Programmability::Carry => {
+                    return Err(Error::OtherString(format!(
+                        "a routed path traverses the dedicated carry element {q_cedge:?}, which \
+                         cannot pass a signal through"
+                    )))
+                }
+                Programmability::ArbitraryLut(_) => todo!(),
+                Programmability::SelectorLut(selector_lut) => {
+                    let inx_config = selector_lut.inx_config();
+                    assert!(source_i < (1 << inx_config.len()));
+                    let i = Awi::from_usize(source_i);
+                    for (inx_i, p_config) in inx_config.iter().copied().enumerate() {
+                        set_configuration(configurator, p_config, i.get(inx_i).unwrap());
+                    }
+                }
+                // the hyperpath should be fully lowered
+                Programmability::Bulk(_) => unreachable!(),
+            }
+        }
+        // the hyperpath should be fully lowered into base level traversals
+        EdgeKind::Concentrate | EdgeKind::Dilute => unreachable!(),
+    }
+    Ok(())
+}
+
 impl Default for Configurator {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,208 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::Value,
+    route::{Edge, EdgeKind, PMapping, Path, Programmability, QCEdge, QCNode, Referent, Router},
+    utils::{audit_visit, trace_span},
+    Error,
+};
+
+/// Where the path of a [ConstantRoute] starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstantOrigin {
+    /// A constant source of the target, such as a tied off selector input
+    Source(QCNode),
+    /// The output of an `ArbitraryLut` that has all of its configuration bits
+    /// tied to the constant
+    Tie(QCEdge),
+    /// A `CNode` that already carries the same constant for an earlier route
+    Shared(QCNode),
+}
+
+/// The routing of a target sink that is mapped to a constant program bit.
+/// Constants are not embedded like other program values, instead each sink is
+/// connected to the nearest target constant through selectors, so that no
+/// target LUTs are used except for ones that can be tied off entirely.
+#[derive(Debug, Clone)]
+pub struct ConstantRoute {
+    pub p_mapping: PMapping,
+    /// The constant value
+    pub value: bool,
+    /// The base level `CNode` that the path starts from
+    pub source: QCNode,
+    pub origin: ConstantOrigin,
+    /// Consists only of `EdgeKind::Transverse` edges
+    pub path: Path<QCNode, QCEdge>,
+}
+
+impl Router {
+    /// If the mapping has no target source and the program bit is a known
+    /// constant, this returns the constant. These mappings do not get
+    /// embeddings and are instead handled by [Router::route_constants].
+    pub(crate) fn constant_mapping_value(&self, p_mapping: PMapping) -> Option<bool> {
+        let (program_p_equiv, mapping) = self.mappings.get(p_mapping).unwrap();
+        if mapping.target_source.is_some() {
+            return None
+        }
+        if let Value::Const(b) = self
+            .program_ensemble()
+            .backrefs
+            .get_val(*program_p_equiv)
+            .unwrap()
+            .val
+        {
+            Some(b)
+        } else {
+            None
+        }
+    }
+
+    /// The routes found for the sinks of constant program bits
+    pub fn constant_routes(&self) -> &[ConstantRoute] {
+        &self.constant_routes
+    }
+
+    /// Routes the sinks of constant program bits after the embeddings have
+    /// been routed, avoiding the base level `CNode`s used by the embeddings
+    pub(crate) fn route_constants(&mut self) -> Result<(), Error> {
+        let span = trace_span("route", "Router::route_constants");
+        self.constant_routes.clear();
+        // `None` if used by an embedding, otherwise the constant that the `CNode`
+        // carries which later routes of the same constant can share
+        let mut carried = HashMap::<QCNode, Option<bool>>::new();
+        for embedding in self.embeddings.vals() {
+            let hyperpath = &embedding.target_hyperpath;
+            carried.insert(hyperpath.source(), None);
+            for path in hyperpath.paths() {
+                for edge in path.edges() {
+                    carried.insert(edge.to, None);
+                }
+            }
+        }
+        let mut adv = self.mappings.advancer();
+        while let Some(p_mapping) = adv.advance(&self.mappings) {
+            let Some(b) = self.constant_mapping_value(p_mapping) else {
+                continue
+            };
+            audit_visit("Router::route_constants", p_mapping);
+            span.count("constant_mappings", 1);
+            let num_sinks = self.mappings.get_val(p_mapping).unwrap().target_sinks.len();
+            for i in 0..num_sinks {
+                let target_p_equiv =
+                    self.mappings.get_val(p_mapping).unwrap().target_sinks[i].target_p_equiv;
+                let q_sink = self
+                    .target_channeler()
+                    .find_channeler_cnode(target_p_equiv)
+                    .unwrap();
+                let Some(route) = self.route_constant(&carried, p_mapping, b, q_sink) else {
+                    let s = self.debug_mapping(p_mapping);
+                    return Err(Error::OtherString(format!(
+                        "could not route the constant {b} to sink {i} of a mapping, there is no \
+                         constant source, free `ArbitraryLut`, or bit already carrying the \
+                         constant that can reach the sink through selectors. The mapping is:\n{s}"
+                    )))
+                };
+                carried.insert(route.source, Some(b));
+                for edge in route.path.edges() {
+                    carried.insert(edge.to, Some(b));
+                }
+                self.constant_routes.push(route);
+            }
+        }
+        Ok(())
+    }
+
+    /// Does a Dijkstra search backwards from `q_sink` through selectors and
+    /// temporal edges until a way to produce the constant `b` is found
+    fn route_constant(
+        &mut self,
+        carried: &HashMap<QCNode, Option<bool>>,
+        p_mapping: PMapping,
+        b: bool,
+        q_sink: QCNode,
+    ) -> Option<ConstantRoute> {
+        let channeler = &mut self.target_channeler;
+        let q_constant = channeler.find_constant_cnode(b);
+        let front_visit = channeler.next_alg_visit();
+        let mut priority = BinaryHeap::new();
+        priority.push(Reverse((0u32, q_sink, None::<QCEdge>, 0usize)));
+        while let Some(Reverse((cost, q_cnode, q_cedge, source_i))) = priority.pop() {
+            let cnode = channeler.cnodes.get_val_mut(q_cnode).unwrap();
+            if cnode.alg_visit == front_visit {
+                continue
+            }
+            cnode.alg_visit = front_visit;
+            // unlike in `route_path_on_level`, this points forwards towards the sink
+            cnode.alg_edge = (q_cedge, source_i);
+            let mut origin = match carried.get(&q_cnode) {
+                Some(Some(carried_b)) if *carried_b == b => Some(ConstantOrigin::Shared(q_cnode)),
+                // used by something else
+                Some(_) => continue,
+                None if Some(q_cnode) == q_constant => Some(ConstantOrigin::Source(q_cnode)),
+                None => None,
+            };
+            if origin.is_none() {
+                // look at the drivers of the `CNode`
+                let mut adv = channeler.cnodes.advancer_surject(q_cnode);
+                while let Some(q_referent) = adv.advance(&channeler.cnodes) {
+                    let Referent::CEdgeIncidence(q_cedge1, None) =
+                        *channeler.cnodes.get_key(q_referent).unwrap()
+                    else {
+                        continue
+                    };
+                    let cedge = channeler.cedges.get(q_cedge1).unwrap();
+                    if cedge.is_forbidden() {
+                        continue
+                    }
+                    let cost1 = cost
+                        .saturating_add(cedge.delay_weight.get())
+                        .saturating_add(cedge.lagrangian);
+                    match cedge.programmability() {
+                        Programmability::ArbitraryLut(_) => {
+                            origin = Some(ConstantOrigin::Tie(q_cedge1));
+                            break
+                        }
+                        Programmability::TNode(_) | Programmability::SelectorLut(_) => {
+                            for (source_i1, q_source) in cedge.sources().iter().enumerate() {
+                                let q_source = channeler.cnodes.get_val(*q_source).unwrap();
+                                priority.push(Reverse((
+                                    cost1,
+                                    q_source.p_this_cnode,
+                                    Some(q_cedge1),
+                                    source_i1,
+                                )));
+                            }
+                        }
+                        // these can not pass a constant through without using logic resources
+                        Programmability::StaticLut(_)
+                        | Programmability::Carry
+                        | Programmability::Bulk(_) => (),
+                    }
+                }
+            }
+            if let Some(origin) = origin {
+                let mut path = Path::new(q_sink);
+                let mut q = q_cnode;
+                while let (Some(q_cedge), source_i) = channeler.cnodes.get_val(q).unwrap().alg_edge
+                {
+                    let q_next = channeler.cedges.get(q_cedge).unwrap().sink();
+                    q = channeler.cnodes.get_val(q_next).unwrap().p_this_cnode;
+                    path.push(Edge::new(EdgeKind::Transverse(q_cedge, source_i), q));
+                }
+                return Some(ConstantRoute {
+                    p_mapping,
+                    value: b,
+                    source: q_cnode,
+                    origin,
+                    path,
+                })
+            }
+        }
+        None
+    }
+}
@@ -2,7 +2,7 @@ use awint::awint_dag::triple_arena::{Advancer, Ptr};
 
 use super::{Edge, EdgeKind, PCEdge, PCNode, PEmbedding, PMapping, Path, QCEdge, QCNode};
 use crate::{
-    route::{HyperPath, Router},
+    route::{HyperPath, Referent, Router},
     Error,
 };
 
@@ -67,6 +67,11 @@ impl Router {
 
     /// Makes a minimal embedding to express the given mapping.
     fn make_embedding1(&mut self, p_mapping: PMapping) -> Result<(), Error> {
+        if self.constant_mapping_value(p_mapping).is_some() {
+            // constant program bits do not need target logic, they are handled by
+            // `route_constants`
+            return Ok(())
+        }
         let (program_p_equiv, mapping) = self.mappings.get(p_mapping).unwrap();
         let program_p_equiv = *program_p_equiv;
         let program_cnode = self
@@ -156,6 +161,10 @@ impl Router {
                 target_hyperpath: hyperpath,
             })
             .unwrap();
+        } else if mapping.target_sinks.is_empty() && !self.program_cnode_has_cedges(program_cnode) {
+            // the program input does not affect anything, for example if it was
+            // tied off in the program, so it does not need an
+            // embedding
         } else {
             // If the mapping has just a source, then a hyper path needs to go concentrating
             // to a root node. If the mapping just has sinks, then a hyper path
@@ -172,6 +181,18 @@ impl Router {
         Ok(())
     }
 
+    /// Returns if the program `CNode` is incident to any `CEdge`s
+    fn program_cnode_has_cedges(&self, p_cnode: PCNode) -> bool {
+        let channeler = self.program_channeler();
+        let mut adv = channeler.cnodes.advancer_surject(p_cnode);
+        while let Some(p_referent) = adv.advance(&channeler.cnodes) {
+            if let Referent::CEdgeIncidence(..) = channeler.cnodes.get_key(p_referent).unwrap() {
+                return true
+            }
+        }
+        false
+    }
+
     pub(crate) fn initialize_embeddings(&mut self) -> Result<(), Error> {
         // Mappings will stay static because they are used for figuring out translating
         // program IO to target IO. Embeddings will represent bulk programmings of the
@@ -180,7 +201,7 @@ impl Router {
         // by making those embeddings.
         let mut adv = self.mappings.advancer();
         while let Some(p_mapping) = adv.advance(&self.mappings) {
-            self.make_embedding1(p_mapping).unwrap()
+            self.make_embedding1(p_mapping)?;
         }
         Ok(())
     }
@@ -0,0 +1,218 @@
+//! Generators for common target fabrics. These are useful as routing
+//! benchmarks and as templates for describing custom fabrics.
+
+use std::num::NonZeroUsize;
+
+use crate::{
+    awi, dag,
+    route::{Channeler, Configurator, QCEdge, QCNode},
+    utils::Grid,
+    Epoch, Error, In, LazyAwi, Net, Out, SuspendedEpoch,
+};
+
+/// The configurable state of a single tile of an [Island]
+#[derive(Debug)]
+pub struct IslandTile {
+    /// The truth table of the `lut_k` input LUT of the tile
+    pub lut: LazyAwi,
+    /// The selectors of the input multiplexers of the LUT, which choose from
+    /// the channel wires of the tile
+    pub lut_input_selectors: Vec<LazyAwi>,
+    /// The selectors of the switch box multiplexers driving each channel wire
+    /// of the tile
+    pub wire_selectors: Vec<LazyAwi>,
+    /// The selector of the output pad multiplexer if this is a perimeter tile
+    pub output_selector: Option<LazyAwi>,
+}
+
+/// An island-style FPGA target generated by [island]. Every field needs to be
+/// kept alive for as long as the target is used.
+#[derive(Debug)]
+pub struct Island {
+    /// The suspended target epoch
+    pub epoch: SuspendedEpoch,
+    /// The `Configurator` with every configuration bit of the target
+    pub configurator: Configurator,
+    /// The target `Channeler`, which can be used with
+    /// [Router::new_from_channelers](crate::route::Router::new_from_channelers)
+    pub channeler: Channeler<QCNode, QCEdge>,
+    /// The configurable state of each tile
+    pub tiles: Grid<IslandTile>,
+    /// The input pads, one for each perimeter tile in row-major order
+    pub inputs: Vec<In<1>>,
+    /// The output pads, one for each perimeter tile in row-major order
+    pub outputs: Vec<Out<1>>,
+}
+
+/// Drives `net` with a new selector, which is returned. The ports are padded
+/// to a power of two by repeating them so that the selector does not have any
+/// range checking logic, because the router requires configuration bits to
+/// only drive the multiplexer itself and the multiplexer to only select
+/// dynamic values.
+fn drive_with_selector(mut net: Net, ports: &[&dag::Bits]) -> LazyAwi {
+    let len = ports.len().max(2).next_power_of_two();
+    for i in 0..len {
+        net.push(ports[i % ports.len()]).unwrap();
+    }
+    let selector = LazyAwi::opaque(dag::Bits::nontrivial_bits(len - 1).unwrap());
+    net.drive(&selector).unwrap();
+    selector
+}
+
+/// Generates an island-style FPGA of `nx` by `ny` tiles. Each tile has a
+/// logic block with a `lut_k` input LUT, and a channel of `wires_per_channel`
+/// single-length wires. Each wire is driven by a switch box multiplexer that
+/// selects between the wires of the same track in the orthogonally adjacent
+/// tiles (a disjoint switch box), the output of the tile's LUT, and the input
+/// pad of the tile if it is on the perimeter. Each LUT input and perimeter
+/// output pad is driven by a connection box multiplexer that selects between
+/// the wires of the tile.
+///
+/// The target is created in its own `Epoch` which is suspended before
+/// returning, and the target `Ensemble` is optimized.
+///
+/// # Errors
+///
+/// If any of the arguments are zero, or if `lut_k` is too large for the LUT
+/// truth table to be represented
+pub fn island(
+    nx: usize,
+    ny: usize,
+    lut_k: usize,
+    wires_per_channel: usize,
+) -> Result<Island, Error> {
+    if (nx == 0) || (ny == 0) || (lut_k == 0) || (wires_per_channel == 0) {
+        return Err(Error::OtherStr(
+            "`island` was given a zero dimension, LUT size, or channel width",
+        ))
+    }
+    let lut_w = u32::try_from(lut_k)
+        .ok()
+        .and_then(|k| 1usize.checked_shl(k))
+        .and_then(NonZeroUsize::new)
+        .ok_or(Error::OtherStr(
+            "`island` was given a `lut_k` that is too large",
+        ))?;
+    let is_perimeter =
+        |(i, j): (usize, usize)| (i == 0) || (j == 0) || (i == nx - 1) || (j == ny - 1);
+
+    let epoch = Epoch::new();
+    // the temporal values of the wires are created first so that the switch
+    // boxes can refer to the wires of adjacent tiles
+    let mut wires = Grid::new((nx, ny), |_| {
+        (0..wires_per_channel)
+            .map(|_| Net::opaque(awi::bw(1)))
+            .collect::<Vec<Net>>()
+    })
+    .unwrap();
+    let wire_values = Grid::new((nx, ny), |ij| {
+        wires
+            .get(ij)
+            .unwrap()
+            .iter()
+            .map(|wire| dag::Awi::from(wire.as_ref()))
+            .collect::<Vec<dag::Awi>>()
+    })
+    .unwrap();
+
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+    let mut tiles = vec![];
+    // in the same row-major order as `Grid`
+    for j in 0..ny {
+        for i in 0..nx {
+            let tile_wires: Vec<&dag::Bits> = wire_values
+                .get((i, j))
+                .unwrap()
+                .iter()
+                .map(|w| w.as_ref())
+                .collect();
+            // the logic block
+            let lut = LazyAwi::opaque(lut_w);
+            let mut lut_inx = dag::Awi::zero(NonZeroUsize::new(lut_k).unwrap());
+            let mut lut_input_selectors = vec![];
+            for k in 0..lut_k {
+                let net = Net::opaque(awi::bw(1));
+                lut_inx.set(k, net.lsb()).unwrap();
+                lut_input_selectors.push(drive_with_selector(net, &tile_wires));
+            }
+            let mut lut_out = dag::Awi::zero(awi::bw(1));
+            lut_out.lut_(&lut, &lut_inx).unwrap();
+
+            // the pads
+            let mut input = None;
+            let mut output_selector = None;
+            if is_perimeter((i, j)) {
+                let pad = In::<1>::opaque();
+                pad.set_debug_name(format!("in.({i}, {j})")).unwrap();
+                input = Some(dag::Awi::from(pad.as_ref()));
+                inputs.push(pad);
+                let net = Net::opaque(awi::bw(1));
+                let pad = Out::<1>::from_bits(&net).unwrap();
+                pad.set_debug_name(format!("out.({i}, {j})")).unwrap();
+                outputs.push(pad);
+                output_selector = Some(drive_with_selector(net, &tile_wires));
+            }
+
+            // the switch box
+            let mut wire_selectors = vec![];
+            let tile_nets = std::mem::take(wires.get_mut((i, j)).unwrap());
+            for (track, net) in tile_nets.into_iter().enumerate() {
+                let mut ports: Vec<&dag::Bits> = vec![];
+                let neighbors = [
+                    i.checked_sub(1).map(|i| (i, j)),
+                    i.checked_add(1).map(|i| (i, j)),
+                    j.checked_sub(1).map(|j| (i, j)),
+                    j.checked_add(1).map(|j| (i, j)),
+                ];
+                for ij in neighbors.into_iter().flatten() {
+                    if let Some(neighbor) = wire_values.get(ij) {
+                        ports.push(&neighbor[track]);
+                    }
+                }
+                ports.push(&lut_out);
+                if let Some(ref input) = input {
+                    ports.push(input);
+                }
+                wire_selectors.push(drive_with_selector(net, &ports));
+            }
+
+            tiles.push(IslandTile {
+                lut,
+                lut_input_selectors,
+                wire_selectors,
+                output_selector,
+            });
+        }
+    }
+    let mut tiles = tiles.into_iter();
+    let tiles = Grid::new((nx, ny), |_| tiles.next().unwrap()).unwrap();
+    drop(wire_values);
+    epoch.optimize()?;
+
+    let mut configurator = Configurator::new();
+    let mut res = Ok(());
+    tiles.for_each(|tile, _| {
+        for config in [&tile.lut]
+            .into_iter()
+            .chain(&tile.lut_input_selectors)
+            .chain(&tile.wire_selectors)
+            .chain(&tile.output_selector)
+        {
+            if res.is_ok() {
+                res = configurator.configurable(config);
+            }
+        }
+    });
+    res?;
+    let epoch = epoch.suspend();
+    let channeler = Channeler::from_target(&epoch, &configurator)?;
+    Ok(Island {
+        epoch,
+        configurator,
+        channeler,
+        tiles,
+        inputs,
+        outputs,
+    })
+}
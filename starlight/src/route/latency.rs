@@ -0,0 +1,49 @@
+use crate::{dag, delay, ensemble::Ensemble, route::Router, Delay, Error};
+
+/// Returned by [Router::generate_latency_wrapper], the balanced program
+/// behaves like the original program except that every output lags the
+/// inputs by [LatencyWrapper::latency].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyWrapper {
+    latency: Delay,
+}
+
+impl LatencyWrapper {
+    pub fn new(latency: Delay) -> Self {
+        Self { latency }
+    }
+
+    /// Returns the delay between a change to the inputs of the program and the
+    /// corresponding change of the outputs
+    pub fn latency(&self) -> Delay {
+        self.latency
+    }
+
+    /// Delays `valid` by the latency, so that a valid bit accompanying the
+    /// inputs of the program stays in lockstep with the outputs. This is a
+    /// mimicking operation that needs to be used in the program `Epoch`. Like
+    /// the balancing delays, this is a chain of delay 1 registers so that a
+    /// new valid value can be started every cycle.
+    pub fn delay_valid(&self, valid: &dag::Bits) -> dag::Awi {
+        let mut res = dag::Awi::from(valid);
+        for _ in 0..self.latency.amount() {
+            delay(&mut res, 1);
+        }
+        res
+    }
+}
+
+impl Router {
+    /// Like [Router::back_annotate], except that extra delays are also
+    /// inserted to balance every path (see
+    /// [crate::ensemble::Ensemble::balance_latency]), so that the returned
+    /// copy of the program behaves identically to the original program modulo
+    /// the returned latency. Like `back_annotate`, the copy is made from the
+    /// program as it was when the router was created, so the program epoch is
+    /// never changed and the `PExternal`s of the program stay valid.
+    pub fn generate_latency_wrapper(&self) -> Result<(Ensemble, LatencyWrapper), Error> {
+        let mut ensemble = self.program_ensemble().clone();
+        let latency = ensemble.balance_latency(&self.interconnect_delays())?;
+        Ok((ensemble, LatencyWrapper::new(latency)))
+    }
+}
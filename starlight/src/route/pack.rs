@@ -0,0 +1,302 @@
+use std::collections::{BTreeSet, HashMap};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Ensemble, PBack, PLNode, Referent},
+    Error,
+};
+
+/// The maximum number of Fiduccia-Mattheyses refinement passes per bisection
+const MAX_FM_PASSES: usize = 8;
+
+/// A clustering of the `LNode`s of a program `Ensemble` into groups of at most
+/// `max_cluster_size`, intended for eventually mapping whole clusters onto
+/// target `CNode`s. Note that the `Router` does not use this yet, because it
+/// cannot embed program `LNode`s. The clusters are found by recursive bisection
+/// with Fiduccia-Mattheyses refinement minimizing the number of nets
+/// (equivalences) that cross between clusters.
+#[derive(Debug, Clone)]
+pub struct Packing {
+    clusters: Vec<Vec<PLNode>>,
+    cut_nets: usize,
+    max_cluster_size: usize,
+}
+
+/// Returns the number of nets in `nets` with vertices on both sides
+fn cut_size(nets: &[Vec<usize>], side: &[bool]) -> usize {
+    nets.iter()
+        .filter(|net| {
+            let first = side[net[0]];
+            net.iter().any(|v| side[*v] != first)
+        })
+        .count()
+}
+
+/// Bisects the `n` vertices of the hypergraph `nets` into two sides of
+/// roughly equal size with a small cut, returning the side of each vertex
+fn fm_bisect(n: usize, nets: &[Vec<usize>]) -> Vec<bool> {
+    let mut vertex_nets: Vec<Vec<usize>> = vec![vec![]; n];
+    for (i, net) in nets.iter().enumerate() {
+        for v in net.iter().copied() {
+            vertex_nets[v].push(i);
+        }
+    }
+    // the vertices are in arena order which usually has some locality already
+    let mut side: Vec<bool> = (0..n).map(|v| v >= (n / 2)).collect();
+    // how far a side may deviate from half
+    let tolerance = (n / 10).max(1);
+    let min_side = (n / 2).saturating_sub(tolerance).max(1);
+    let max_side = n - min_side;
+    let mut best_cut = cut_size(nets, &side);
+    for _ in 0..MAX_FM_PASSES {
+        // number of vertices of each net on the `false` and `true` sides
+        let mut counts: Vec<[usize; 2]> = nets
+            .iter()
+            .map(|net| {
+                let ones = net.iter().filter(|v| side[**v]).count();
+                [net.len() - ones, ones]
+            })
+            .collect();
+        let gain = |v: usize, side: &[bool], counts: &[[usize; 2]]| -> i64 {
+            let from = side[v] as usize;
+            let mut g = 0i64;
+            for net in vertex_nets[v].iter().copied() {
+                if counts[net][from] == 1 {
+                    g += 1;
+                }
+                if counts[net][1 - from] == 0 {
+                    g -= 1;
+                }
+            }
+            g
+        };
+        let mut gains: Vec<i64> = (0..n).map(|v| gain(v, &side, &counts)).collect();
+        let mut unlocked: BTreeSet<(i64, usize)> = (0..n).map(|v| (gains[v], v)).collect();
+        let mut size_true = side.iter().filter(|s| **s).count();
+        let mut moves = vec![];
+        let mut cut = best_cut;
+        let mut best_prefix = 0;
+        let mut pass_best_cut = best_cut;
+        loop {
+            // find the highest gain vertex that can move without breaking balance
+            let mut chosen = None;
+            for (g, v) in unlocked.iter().rev().copied() {
+                let new_size_true = if side[v] {
+                    size_true - 1
+                } else {
+                    size_true + 1
+                };
+                if (new_size_true >= min_side) && (new_size_true <= max_side) {
+                    chosen = Some((g, v));
+                    break
+                }
+            }
+            let (g, v) = if let Some(x) = chosen { x } else { break };
+            unlocked.remove(&(g, v));
+            let from = side[v] as usize;
+            for net in vertex_nets[v].iter().copied() {
+                counts[net][from] -= 1;
+                counts[net][1 - from] += 1;
+            }
+            side[v] = !side[v];
+            if side[v] {
+                size_true += 1;
+            } else {
+                size_true -= 1;
+            }
+            cut = usize::try_from((cut as i64) - g).unwrap();
+            moves.push(v);
+            if cut < pass_best_cut {
+                pass_best_cut = cut;
+                best_prefix = moves.len();
+            }
+            // update the gains of unlocked neighbors
+            for net in vertex_nets[v].iter().copied() {
+                for u in nets[net].iter().copied() {
+                    if unlocked.remove(&(gains[u], u)) {
+                        gains[u] = gain(u, &side, &counts);
+                        unlocked.insert((gains[u], u));
+                    }
+                }
+            }
+        }
+        // roll back to the best prefix of the pass
+        for v in moves[best_prefix..].iter().copied() {
+            side[v] = !side[v];
+        }
+        if pass_best_cut >= best_cut {
+            break
+        }
+        best_cut = pass_best_cut;
+    }
+    side
+}
+
+impl Packing {
+    /// Packs the `LNode`s of `ensemble` into clusters of at most
+    /// `max_cluster_size` `LNode`s.
+    ///
+    /// # Errors
+    ///
+    /// If `max_cluster_size` is zero
+    pub fn new(ensemble: &Ensemble, max_cluster_size: usize) -> Result<Self, Error> {
+        if max_cluster_size == 0 {
+            return Err(Error::OtherStr(
+                "`Packing::new` was called with a `max_cluster_size` of zero",
+            ))
+        }
+        let mut vertices: Vec<PLNode> = vec![];
+        let mut vertex_inx: HashMap<PLNode, usize> = HashMap::new();
+        let mut adv = ensemble.lnodes.advancer();
+        while let Some(p_lnode) = adv.advance(&ensemble.lnodes) {
+            vertex_inx.insert(p_lnode, vertices.len());
+            vertices.push(p_lnode);
+        }
+        // every equivalence touching multiple `LNode`s is a net
+        let mut nets: Vec<Vec<usize>> = vec![];
+        let mut adv = ensemble.backrefs.advancer();
+        while let Some(p_back) = adv.advance(&ensemble.backrefs) {
+            if !matches!(
+                ensemble.backrefs.get_key(p_back).unwrap(),
+                Referent::ThisEquiv
+            ) {
+                continue
+            }
+            let mut net = vec![];
+            let mut adv_surject = ensemble.backrefs.advancer_surject(p_back);
+            while let Some(p_ref) = adv_surject.advance(&ensemble.backrefs) {
+                match *ensemble.backrefs.get_key(p_ref).unwrap() {
+                    Referent::ThisLNode(p_lnode) | Referent::Input(p_lnode) => {
+                        net.push(vertex_inx[&p_lnode])
+                    }
+                    _ => (),
+                }
+            }
+            net.sort_unstable();
+            net.dedup();
+            if net.len() > 1 {
+                nets.push(net);
+            }
+        }
+        let all: Vec<usize> = (0..vertices.len()).collect();
+        let mut clusters_inx = vec![];
+        Self::recursive_bisect(&all, &nets, max_cluster_size, &mut clusters_inx);
+        // count the final cut
+        let mut cluster_of = vec![0; vertices.len()];
+        for (i, cluster) in clusters_inx.iter().enumerate() {
+            for v in cluster.iter().copied() {
+                cluster_of[v] = i;
+            }
+        }
+        let cut_nets = nets
+            .iter()
+            .filter(|net| {
+                let first = cluster_of[net[0]];
+                net.iter().any(|v| cluster_of[*v] != first)
+            })
+            .count();
+        let clusters = clusters_inx
+            .into_iter()
+            .map(|cluster| cluster.into_iter().map(|v| vertices[v]).collect())
+            .collect();
+        Ok(Self {
+            clusters,
+            cut_nets,
+            max_cluster_size,
+        })
+    }
+
+    fn recursive_bisect(
+        subset: &[usize],
+        nets: &[Vec<usize>],
+        max_cluster_size: usize,
+        clusters: &mut Vec<Vec<usize>>,
+    ) {
+        if subset.is_empty() {
+            return
+        }
+        if subset.len() <= max_cluster_size {
+            clusters.push(subset.to_vec());
+            return
+        }
+        // project the nets onto the subset with local indexes
+        let local: HashMap<usize, usize> = subset
+            .iter()
+            .copied()
+            .enumerate()
+            .map(|(i, v)| (v, i))
+            .collect();
+        let mut local_nets = vec![];
+        for net in nets {
+            let local_net: Vec<usize> = net.iter().filter_map(|v| local.get(v).copied()).collect();
+            if local_net.len() > 1 {
+                local_nets.push(local_net);
+            }
+        }
+        let side = fm_bisect(subset.len(), &local_nets);
+        let mut lhs = vec![];
+        let mut rhs = vec![];
+        for (i, v) in subset.iter().copied().enumerate() {
+            if side[i] {
+                rhs.push(v);
+            } else {
+                lhs.push(v);
+            }
+        }
+        let lhs_nets: Vec<Vec<usize>> = local_nets
+            .iter()
+            .filter(|net| net.iter().all(|v| !side[*v]))
+            .map(|net| net.iter().map(|v| subset[*v]).collect())
+            .collect();
+        let rhs_nets: Vec<Vec<usize>> = local_nets
+            .iter()
+            .filter(|net| net.iter().all(|v| side[*v]))
+            .map(|net| net.iter().map(|v| subset[*v]).collect())
+            .collect();
+        Self::recursive_bisect(&lhs, &lhs_nets, max_cluster_size, clusters);
+        Self::recursive_bisect(&rhs, &rhs_nets, max_cluster_size, clusters);
+    }
+
+    /// Returns the clusters, every `LNode` of the ensemble is in exactly one
+    /// cluster
+    pub fn clusters(&self) -> &[Vec<PLNode>] {
+        &self.clusters
+    }
+
+    /// Returns the number of nets that cross between different clusters
+    pub fn cut_nets(&self) -> usize {
+        self.cut_nets
+    }
+
+    pub fn max_cluster_size(&self) -> usize {
+        self.max_cluster_size
+    }
+
+    /// Returns the index of the cluster containing `p_lnode`
+    pub fn find_cluster(&self, p_lnode: PLNode) -> Option<usize> {
+        self.clusters
+            .iter()
+            .position(|cluster| cluster.contains(&p_lnode))
+    }
+
+    /// Returns the indexes of the clusters that drive or read the equivalence
+    /// of `p_back` in `ensemble`
+    pub fn clusters_of_net(&self, ensemble: &Ensemble, p_back: PBack) -> Vec<usize> {
+        let mut res = vec![];
+        let mut adv = ensemble.backrefs.advancer_surject(p_back);
+        while let Some(p_ref) = adv.advance(&ensemble.backrefs) {
+            match *ensemble.backrefs.get_key(p_ref).unwrap() {
+                Referent::ThisLNode(p_lnode) | Referent::Input(p_lnode) => {
+                    if let Some(i) = self.find_cluster(p_lnode) {
+                        res.push(i);
+                    }
+                }
+                _ => (),
+            }
+        }
+        res.sort_unstable();
+        res.dedup();
+        res
+    }
+}
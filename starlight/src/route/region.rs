@@ -0,0 +1,138 @@
+use awint::Awi;
+
+use crate::{
+    route::{Config, Programmability, Router},
+    Error,
+};
+
+/// Which partial reconfiguration regions a routing uses, returned by
+/// [Router::region_report]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionReport {
+    /// The regions that have configuration bits that the routing needs to
+    /// set, in increasing order
+    pub touched: Vec<usize>,
+    /// The declared regions that the routing does not need, in increasing
+    /// order
+    pub untouched: Vec<usize>,
+    /// If the routing needs to set configuration bits that are not in any
+    /// region
+    pub touches_static: bool,
+}
+
+impl Router {
+    /// Returns the partial reconfiguration regions declared with
+    /// [Configurator::configurable_in_region](crate::route::Configurator::configurable_in_region),
+    /// in increasing order
+    pub fn regions(&self) -> Vec<usize> {
+        let mut res: Vec<usize> = self
+            .configurator
+            .configurations
+            .vals()
+            .filter_map(|config| config.region)
+            .collect();
+        res.sort_unstable();
+        res.dedup();
+        res
+    }
+
+    /// After routing is done, this returns which regions the program touches
+    pub fn region_report(&self) -> RegionReport {
+        let mut touched = vec![];
+        let mut touches_static = false;
+        for config in self.configurator.configurations.vals() {
+            if config.value.is_some() {
+                if let Some(region) = config.region {
+                    touched.push(region);
+                } else {
+                    touches_static = true;
+                }
+            }
+        }
+        touched.sort_unstable();
+        touched.dedup();
+        let untouched = self
+            .regions()
+            .into_iter()
+            .filter(|region| touched.binary_search(region).is_err())
+            .collect();
+        RegionReport {
+            touched,
+            untouched,
+            touches_static,
+        }
+    }
+
+    /// After routing is done, this returns the bitstream segment of `region`,
+    /// which is the configuration of every bit in the region ordered by
+    /// `PExternal` and then bit index. A partial reconfiguration of the region
+    /// writes all of these bits, including the ones the routing did not need
+    /// (which have `value: None`).
+    pub fn region_segment(&self, region: usize) -> Vec<Config> {
+        let mut res: Vec<Config> = self
+            .configurator
+            .configurations
+            .vals()
+            .filter(|config| config.region == Some(region))
+            .cloned()
+            .collect();
+        res.sort_unstable_by_key(|config| (config.p_external, config.bit_i));
+        res
+    }
+
+    /// Like [Router::region_segment], but packs the values into an `Awi`. Note
+    /// that bits that are not necessarily set to anything are zero. Returns
+    /// `None` if the region has no bits.
+    pub fn region_bitstream(&self, region: usize) -> Option<Awi> {
+        let segment = self.region_segment(region);
+        let mut res = Awi::zero(segment.len().try_into().ok()?);
+        for (i, config) in segment.iter().enumerate() {
+            res.set(i, config.value.unwrap_or(false)).unwrap();
+        }
+        Some(res)
+    }
+
+    /// Like [Router::route], but only allows the program to use the target
+    /// resources configured by the bits of `region`, so that the result can
+    /// be loaded by partially reconfiguring just that region. Edges configured
+    /// by bits in other regions or the static part are forbidden during
+    /// routing.
+    ///
+    /// # Errors
+    ///
+    /// If `region` has no bits, if the routing fails, or if the routing could
+    /// not avoid setting configuration bits outside of the region
+    pub fn route_into_region(&mut self, region: usize) -> Result<(), Error> {
+        if self.regions().binary_search(&region).is_err() {
+            return Err(Error::OtherString(format!(
+                "`route_into_region({region})`: no configuration bits were declared for the region"
+            )))
+        }
+        let mut forbidden = vec![];
+        for (q_cedge, cedge) in &mut self.target_channeler.cedges {
+            if let Programmability::SelectorLut(selector_lut) = cedge.programmability() {
+                let outside = selector_lut.inx_config().iter().any(|p_config| {
+                    let config = self.configurator.configurations.get_val(*p_config).unwrap();
+                    config.region != Some(region)
+                });
+                if outside {
+                    forbidden.push((q_cedge, cedge.lagrangian));
+                    cedge.lagrangian = u32::MAX;
+                }
+            }
+        }
+        let res = self.route();
+        for (q_cedge, lagrangian) in forbidden {
+            self.target_channeler.cedges[q_cedge].lagrangian = lagrangian;
+        }
+        res?;
+        let report = self.region_report();
+        if report.touches_static || report.touched.iter().any(|r| *r != region) {
+            return Err(Error::OtherString(format!(
+                "`route_into_region({region})`: the routing could not be contained within the \
+                 region, {report:?}"
+            )))
+        }
+        Ok(())
+    }
+}
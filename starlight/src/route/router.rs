@@ -1,4 +1,4 @@
-use std::fmt::Write;
+use std::{cmp::max, fmt::Write, ops::Range};
 
 use awint::{
     awint_dag::triple_arena::{Advancer, OrdArena},
@@ -10,11 +10,12 @@ use crate::{
     ensemble::{Ensemble, PBack, PExternal},
     epoch::get_current_epoch,
     route::{
-        Channeler, EdgeKind, Embedding, EmbeddingKind, PCEdge, PCNode, PEmbedding, PMapping,
-        QCEdge, QCNode,
+        Channeler, ConstantRoute, EdgeKind, Embedding, EmbeddingKind, HierarchyConfig, PCEdge,
+        PCNode, PEmbedding, PMapping, Programmability, QCEdge, QCNode,
     },
     triple_arena::Arena,
-    Corresponder, Error, LazyAwi, SuspendedEpoch,
+    utils::trace_span,
+    Corresponder, Delay, Error, LazyAwi, SuspendedEpoch,
 };
 
 #[derive(Debug, Clone)]
@@ -49,6 +50,8 @@ pub struct Router {
     pub(crate) mappings: OrdArena<PMapping, PBack, Mapping>,
     // routing embedding of part of the program in the target
     pub(crate) embeddings: Arena<PEmbedding, Embedding<PCNode, PCEdge, QCNode, QCEdge>>,
+    // routes of the sinks of constant program bits
+    pub(crate) constant_routes: Vec<ConstantRoute>,
 }
 
 impl Router {
@@ -78,7 +81,9 @@ impl Router {
     ///    structure does not change. If you do more mimicking operations to
     ///    them afterwards or do any special modifications beyond `retro_`
     ///    assigning and `eval`uating, the router will not know about their new
-    ///    structure and later configures may be wrong.
+    ///    structure and later configures may be wrong. Preserved `MulNode`s and
+    ///    `RomNode`s are removed from the copy of the program and their
+    ///    decompositions are routed instead.
     ///
     /// 4. `route` is called. If an error is returned then there may be an issue
     ///    with the setup above, a bug with the router itself, or the target may
@@ -101,8 +106,37 @@ impl Router {
         program_epoch: &SuspendedEpoch,
         corresponder: &Corresponder,
     ) -> Result<Self, Error> {
-        let target_channeler = Channeler::from_target(target_epoch, configurator)?;
-        let program_channeler = Channeler::from_program(program_epoch)?;
+        Self::new_with_config(
+            target_epoch,
+            configurator,
+            program_epoch,
+            corresponder,
+            &HierarchyConfig::default(),
+        )
+    }
+
+    /// Like [Router::new], but `hierarchy_config` controls how the hierarchy
+    /// of the target `Channeler` is generated. [Channeler::debug_hierarchy] on
+    /// [Router::target_channeler] can be used to inspect the result.
+    pub fn new_with_config(
+        target_epoch: &SuspendedEpoch,
+        configurator: &Configurator,
+        program_epoch: &SuspendedEpoch,
+        corresponder: &Corresponder,
+        hierarchy_config: &HierarchyConfig,
+    ) -> Result<Self, Error> {
+        let target_channeler =
+            Channeler::from_target_with_config(target_epoch, configurator, hierarchy_config)?;
+        // no target `Programmability` corresponds to `MulNode`s or `RomNode`s yet, so
+        // they are removed from the copy of the program in favor of their
+        // decompositions
+        let mut program_ensemble = program_epoch.ensemble(|ensemble| ensemble.clone());
+        if !(program_ensemble.mul_nodes.is_empty() && program_ensemble.rom_nodes.is_empty()) {
+            program_ensemble.remove_all_mul_nodes()?;
+            program_ensemble.remove_all_rom_nodes()?;
+            program_ensemble.optimize_all()?;
+        }
+        let program_channeler = Channeler::new(&program_ensemble, &Configurator::new())?;
         let mut router = Self::new_from_channelers(
             target_epoch,
             target_channeler,
@@ -110,6 +144,7 @@ impl Router {
             program_epoch,
             program_channeler,
         );
+        router.program_ensemble = program_ensemble;
         // use the corresponder to find `map_rnodes` points, coordinating from the
         // program side since it should be one-to-many at most from that direction
         let mut adv = router.program_ensemble().notary.rnodes().advancer();
@@ -153,6 +188,43 @@ impl Router {
                     }
                 }
             }
+            for (program_bits, target_p_external, target_bits) in
+                corresponder.bit_correspondences(program_p_external)
+            {
+                if let Some(target_p_rnode) = router
+                    .target_ensemble()
+                    .notary
+                    .rnodes()
+                    .find_key(&target_p_external)
+                {
+                    let target_rnode = router
+                        .target_ensemble()
+                        .notary
+                        .rnodes()
+                        .get_val(target_p_rnode)
+                        .unwrap();
+                    if (!is_driver) != target_rnode.read_only() {
+                        return Err(Error::OtherString(format!(
+                            "in `Router::new()`, it appears that a bit correspondence is between \
+                             a `LazyAwi` and a `EvalAwi` which shouldn't be possible, the two \
+                             sides were {program_p_external:#?} and {target_p_external:#?}"
+                        )));
+                    }
+                    router.map_rnode_bits(
+                        program_p_external,
+                        program_bits,
+                        target_p_external,
+                        target_bits,
+                        is_driver,
+                    )?;
+                } else {
+                    return Err(Error::OtherString(format!(
+                        "in `Router::new()`, found a bit correspondence with program `RNode` \
+                         {program_p_external:#?} that is not contained in the target, the \
+                         correspondence was {target_p_external:#?}"
+                    )))
+                }
+            }
         }
         Ok(router)
     }
@@ -174,6 +246,7 @@ impl Router {
             program_channeler,
             mappings: OrdArena::new(),
             embeddings: Arena::new(),
+            constant_routes: vec![],
         }
     }
 
@@ -185,6 +258,16 @@ impl Router {
         &self.program_ensemble
     }
 
+    pub fn configurator(&self) -> &Configurator {
+        &self.configurator
+    }
+
+    /// Mutable access to the configurations found by routing, e.x. for
+    /// injecting faults before [Router::verify_by_simulation]
+    pub fn configurator_mut(&mut self) -> &mut Configurator {
+        &mut self.configurator
+    }
+
     pub fn target_channeler(&self) -> &Channeler<QCNode, QCEdge> {
         &self.target_channeler
     }
@@ -394,11 +477,11 @@ impl Router {
                 writeln!(
                     s,
                     "{p_rnode:?} {p_external:#?} debug_name: {:?}",
-                    rnode.debug_name,
+                    rnode.debug_name(),
                 )
                 .unwrap();
                 if locations {
-                    writeln!(s, "{:#?}", rnode.location).unwrap()
+                    writeln!(s, "{:#?}", rnode.location()).unwrap()
                 }
             }
             if let Some(bits) = rnode.bits() {
@@ -415,11 +498,11 @@ impl Router {
                                 writeln!(
                                     s,
                                     "{p_rnode:?} {p_external:#?} debug_name: {:?}",
-                                    rnode.debug_name
+                                    rnode.debug_name()
                                 )
                                 .unwrap();
                                 if locations {
-                                    writeln!(s, "{:#?}", rnode.location).unwrap()
+                                    writeln!(s, "{:#?}", rnode.location()).unwrap()
                                 }
                                 init = true;
                             }
@@ -450,10 +533,10 @@ impl Router {
             .get_rnode(mapping.program_p_external)
             .unwrap()
             .1;
-        if let Some(ref debug_name) = rnode.debug_name {
+        if let Some(debug_name) = rnode.debug_name() {
             writeln!(s, "debug_name: {debug_name}").unwrap();
         }
-        if let Some(location) = rnode.location {
+        if let Some(location) = rnode.location() {
             writeln!(s, "{location:#?}").unwrap();
         }
         if let Some(q_cnode) = self.target_channeler().find_channeler_cnode(*p_back) {
@@ -472,10 +555,10 @@ impl Router {
                 source.target_p_external, source.target_bit_i, source.target_p_equiv
             )
             .unwrap();
-            if let Some(ref debug_name) = rnode.debug_name {
+            if let Some(debug_name) = rnode.debug_name() {
                 writeln!(s, "debug_name: {debug_name}").unwrap();
             }
-            if let Some(location) = rnode.location {
+            if let Some(location) = rnode.location() {
                 writeln!(s, "{location:#?}").unwrap();
             }
             if let Some(q_cnode) = self
@@ -498,10 +581,10 @@ impl Router {
                 sink.target_p_external, sink.target_bit_i, sink.target_p_equiv
             )
             .unwrap();
-            if let Some(ref debug_name) = rnode.debug_name {
+            if let Some(debug_name) = rnode.debug_name() {
                 writeln!(s, "debug_name: {debug_name}").unwrap();
             }
-            if let Some(location) = rnode.location {
+            if let Some(location) = rnode.location() {
                 writeln!(s, "{location:#?}").unwrap();
             }
             if let Some(q_cnode) = self
@@ -530,6 +613,38 @@ impl Router {
         program: PExternal,
         target: PExternal,
         is_driver: bool,
+    ) -> Result<(), Error> {
+        self.map_rnodes_impl(program, None, target, None, is_driver)
+    }
+
+    /// Like [Router::map_rnodes], but maps only the bits `program_bits` of the
+    /// program `RNode` to the bits `target_bits` of the target `RNode`. This
+    /// is automatically handled by `Router::new` for bit range
+    /// correspondences.
+    pub fn map_rnode_bits(
+        &mut self,
+        program: PExternal,
+        program_bits: Range<usize>,
+        target: PExternal,
+        target_bits: Range<usize>,
+        is_driver: bool,
+    ) -> Result<(), Error> {
+        self.map_rnodes_impl(
+            program,
+            Some(program_bits),
+            target,
+            Some(target_bits),
+            is_driver,
+        )
+    }
+
+    fn map_rnodes_impl(
+        &mut self,
+        program: PExternal,
+        program_bits: Option<Range<usize>>,
+        target: PExternal,
+        target_bits: Option<Range<usize>>,
+        is_driver: bool,
     ) -> Result<(), Error> {
         if let Ok((_, program_rnode)) = self.program_ensemble.notary.get_rnode(program) {
             let program_rnode_bits = if let Some(bits) = program_rnode.bits() {
@@ -551,20 +666,32 @@ impl Router {
                             .to_owned(),
                     ));
                 };
-                let len0 = program_rnode_bits.len();
-                let len1 = target_rnode_bits.len();
+                let program_bits = program_bits.unwrap_or(0..program_rnode_bits.len());
+                let target_bits = target_bits.unwrap_or(0..target_rnode_bits.len());
+                if (program_bits.end > program_rnode_bits.len())
+                    || (target_bits.end > target_rnode_bits.len())
+                {
+                    return Err(Error::OtherString(format!(
+                        "when mapping bits, found that the bit ranges {program_bits:?} of \
+                         {program:#?} or {target_bits:?} of {target:#?} are out of bounds"
+                    )));
+                }
+                let len0 = program_bits.len();
+                let len1 = target_bits.len();
                 if len0 != len1 {
                     return Err(Error::OtherString(format!(
                         "when mapping bits, found that the bitwidths of {program:#?} ({len0}) and \
                          {target:#?} ({len1}) differ"
                     )));
                 }
-                for (bit_i, the_two) in program_rnode_bits
+                for (k, the_two) in program_rnode_bits[program_bits.clone()]
                     .iter()
                     .copied()
-                    .zip(target_rnode_bits.iter().copied())
+                    .zip(target_rnode_bits[target_bits.clone()].iter().copied())
                     .enumerate()
                 {
+                    let program_bit_i = program_bits.start + k;
+                    let target_bit_i = target_bits.start + k;
                     match the_two {
                         (Some(program_bit), Some(target_bit)) => {
                             let program_p_equiv = self
@@ -583,7 +710,7 @@ impl Router {
                             // insert new mapping target
                             let mapping_target = MappingTarget {
                                 target_p_external: target,
-                                target_bit_i: bit_i,
+                                target_bit_i,
                                 target_p_equiv,
                             };
                             if let Some(p_map) = self.mappings.find_key(&program_p_equiv) {
@@ -618,14 +745,14 @@ impl Router {
                                 let mapping = if is_driver {
                                     Mapping {
                                         program_p_external: program,
-                                        program_bit_i: bit_i,
+                                        program_bit_i,
                                         target_source: Some(mapping_target),
                                         target_sinks: vec![],
                                     }
                                 } else {
                                     Mapping {
                                         program_p_external: program,
-                                        program_bit_i: bit_i,
+                                        program_bit_i,
                                         target_source: None,
                                         target_sinks: vec![mapping_target],
                                     }
@@ -664,8 +791,10 @@ impl Router {
     ///
     /// If the routing is infeasible an error is returned.
     pub fn route(&mut self) -> Result<(), Error> {
+        let _span = trace_span("route", "Router::route");
         self.initialize_embeddings()?;
         route(self)?;
+        self.route_constants()?;
         self.set_configurations()?;
         Ok(())
     }
@@ -724,4 +853,79 @@ impl Router {
         }
         Ok(res)
     }
+
+    /// After routing is done, this calculates the delays that the target
+    /// interconnect adds to routed program bits. The delay of an embedding is
+    /// the sum of the target `TNode` delays along a path of its hyperpath,
+    /// maximized over the paths to different sinks. The returned `PBack`s are
+    /// equivalences in the program ensemble, and bits that get no delay are not
+    /// included.
+    pub fn interconnect_delays(&self) -> Vec<(PBack, Delay)> {
+        // for finding the base level program equivalences of a program `CNode`
+        let mut cnode_to_equiv: Vec<(PCNode, PBack)> = vec![];
+        let backref_map = &self.program_channeler.ensemble_backref_to_channeler_backref;
+        for p in backref_map.ptrs() {
+            let (p_equiv, p_ref) = backref_map.get(p).unwrap();
+            let p_cnode = self
+                .program_channeler
+                .cnodes
+                .get_val(*p_ref)
+                .unwrap()
+                .p_this_cnode;
+            cnode_to_equiv.push((p_cnode, *p_equiv));
+        }
+        cnode_to_equiv.sort_unstable();
+
+        let mut res = vec![];
+        for embedding in self.embeddings.vals() {
+            let program_cnode = match embedding.program {
+                EmbeddingKind::Node(p_cnode) => p_cnode,
+                // TODO when custom `CEdge` embeddings are supported, the delay should be
+                // added to the corresponding program `LNode` output
+                EmbeddingKind::Edge(_) => continue,
+            };
+            let mut delay = Delay::zero();
+            for path in embedding.target_hyperpath.paths() {
+                let mut path_delay = Delay::zero();
+                for edge in path.edges() {
+                    if let EdgeKind::Transverse(q_cedge, _) = edge.kind {
+                        let cedge = self.target_channeler.cedges.get(q_cedge).unwrap();
+                        if let Programmability::TNode(tnode_delay) = cedge.programmability() {
+                            path_delay = path_delay.checked_add(*tnode_delay).unwrap();
+                        }
+                    }
+                }
+                delay = max(delay, path_delay);
+            }
+            if delay.is_zero() {
+                continue
+            }
+            let start = cnode_to_equiv.partition_point(|(p_cnode, _)| *p_cnode < program_cnode);
+            for (p_cnode, p_equiv) in &cnode_to_equiv[start..] {
+                if *p_cnode != program_cnode {
+                    break
+                }
+                res.push((*p_equiv, delay));
+            }
+        }
+        res
+    }
+
+    /// After routing is done, this returns a copy of the program `Ensemble`
+    /// with the delays from [Router::interconnect_delays] inserted as `TNode`
+    /// delays in front of the readers of the corresponding bits, so that
+    /// temporal simulation of the program reflects the routed target. The copy
+    /// is made from the program as it was when the router was created, so the
+    /// program epoch is never changed and repeated calls return the same
+    /// result instead of accumulating delays. The `PExternal`s of the program
+    /// stay valid, so the result can be simulated with
+    /// [crate::Epoch::from_ensemble] and [crate::Epoch::poke] and
+    /// [crate::Epoch::peek].
+    pub fn back_annotate(&self) -> Result<Ensemble, Error> {
+        let mut ensemble = self.program_ensemble.clone();
+        for (p_equiv, delay) in self.interconnect_delays() {
+            ensemble.insert_delay_before_readers(p_equiv, delay)?;
+        }
+        Ok(ensemble)
+    }
 }
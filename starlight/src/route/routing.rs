@@ -8,10 +8,12 @@ use awint::awint_dag::triple_arena::Advancer;
 
 use crate::{
     route::{Edge, EdgeKind, EmbeddingKind, PEmbedding, QCNode, Referent, Router},
+    utils::{audit_visit, trace_event, trace_span},
     Error,
 };
 
 pub(crate) fn route(router: &mut Router) -> Result<(), Error> {
+    let _span = trace_span("route", "route");
     // see cnode.rs for the overall idea
 
     // property: if a program CNode is embedded in a certain target CNode, the
@@ -78,6 +80,8 @@ pub(crate) fn route(router: &mut Router) -> Result<(), Error> {
 }
 
 fn route_level(router: &mut Router, max_lvl: u16) -> Result<(), Error> {
+    let span = trace_span("route", "route_level");
+    span.count("lvl", u64::from(max_lvl));
     // things we may need to consider:
 
     // - something analogous to adaboost at first, but adaboost deals with
@@ -100,11 +104,17 @@ fn route_level(router: &mut Router, max_lvl: u16) -> Result<(), Error> {
     // - Currently, `route_embedding` relies on there being
 
     let max_loops = 1u64;
-    for _ in 0..max_loops {
+    for iteration in 0..max_loops {
         let violations = false;
+        trace_event("route", "route_level iteration", &[
+            ("lvl", u64::from(max_lvl)),
+            ("iteration", iteration),
+        ]);
 
         let mut adv = router.embeddings().advancer();
         while let Some(p_embedding) = adv.advance(router.embeddings()) {
+            audit_visit("route embedding", p_embedding);
+            span.count("embeddings", 1);
             route_embedding(router, max_lvl, p_embedding)?;
         }
 
@@ -421,6 +431,9 @@ fn route_path_on_level(
             *router.target_channeler.cnodes.get_key(q_referent).unwrap()
         {
             let cedge = router.target_channeler.cedges.get(q_cedge).unwrap();
+            if !cedge.is_traversable() {
+                continue
+            }
             priority.push(Reverse((
                 cedge.delay_weight.get().saturating_add(cedge.lagrangian),
                 q_cedge,
@@ -480,6 +493,9 @@ fn route_path_on_level(
                         *router.target_channeler.cnodes.get_key(q_referent1).unwrap()
                     {
                         let cedge = router.target_channeler.cedges.get(q_cedge1).unwrap();
+                        if !cedge.is_traversable() {
+                            continue
+                        }
                         priority.push(Reverse((
                             cost.saturating_add(cedge.delay_weight.get())
                                 .saturating_add(cedge.lagrangian),
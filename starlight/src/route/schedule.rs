@@ -0,0 +1,711 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
+
+use awint::{awint_dag::triple_arena::Advancer, bw, Awi, Bits};
+
+use crate::{
+    ensemble::{BatchSim, Ensemble, PBack, PExternal, PLNode, RNode, Referent},
+    route::{Channeler, Configurator, QCEdge, QCNode, Router},
+    Epoch, Error, SuspendedEpoch,
+};
+
+/// Limits on every time slice of an [EmulationSchedule]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleConfig {
+    /// The maximum number of program `LNode`s in a slice, which should be
+    /// about the number of lookup tables that the target can implement at
+    /// once
+    pub max_lnodes: usize,
+    /// The maximum number of bits restored into a slice, which is the number
+    /// of target input pads available for the slice
+    pub max_inputs: usize,
+    /// The maximum number of bits saved from a slice, which is the number of
+    /// target output pads available for the slice
+    pub max_outputs: usize,
+}
+
+/// Where the value of a [StateSlot] comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotSource {
+    /// A constant, or an equivalence that nothing drives
+    Const(bool),
+    /// Bit `bit_i` of the writable program `RNode` of the `PExternal`, which
+    /// the host writes before each pass
+    Input(PExternal, usize),
+    /// The output of a register (a `TNode` with a delay of 1), which is saved
+    /// from the slot of its driver at the end of every cycle
+    Register {
+        /// The slot of the driver of the register
+        driver: usize,
+    },
+    /// Saved from the time slice with this index
+    Slice(usize),
+}
+
+/// A bit of the state memory that carries values between the time slices of
+/// an [EmulationSchedule] and between cycles
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateSlot {
+    /// The program equivalence that the slot holds
+    pub p_equiv: PBack,
+    pub source: SlotSource,
+    /// The value of the slot before the first cycle
+    pub initial: bool,
+}
+
+/// One pass of an [EmulationSchedule], a part of the program small enough to
+/// be configured on the target at once
+#[derive(Debug, Clone)]
+pub struct TimeSlice {
+    ensemble: Ensemble,
+    lnodes: Vec<PLNode>,
+    restore: Vec<(PExternal, usize)>,
+    save: Vec<(PExternal, usize)>,
+}
+
+impl TimeSlice {
+    /// The program of the slice, where every restored or saved bit is a
+    /// single bit `RNode`
+    pub fn ensemble(&self) -> &Ensemble {
+        &self.ensemble
+    }
+
+    /// The `LNode`s of the original program evaluated by the slice, in
+    /// topological order
+    pub fn lnodes(&self) -> &[PLNode] {
+        &self.lnodes
+    }
+
+    /// The writable `RNode`s of the slice program paired with the state slots
+    /// that are restored into them before the slice is evaluated. When routed
+    /// with [EmulationSchedule::slice_router], the `i`th of these is mapped to
+    /// the `i`th input pad.
+    pub fn restore(&self) -> &[(PExternal, usize)] {
+        &self.restore
+    }
+
+    /// The read only `RNode`s of the slice program paired with the state
+    /// slots that they are saved into after the slice is evaluated. When
+    /// routed with [EmulationSchedule::slice_router], the `i`th of these is
+    /// mapped to the `i`th output pad.
+    pub fn save(&self) -> &[(PExternal, usize)] {
+        &self.save
+    }
+}
+
+/// A time-multiplexed schedule for emulating a program that does not fit
+/// spatially on the target. The combinational logic of the program is
+/// partitioned in topological order into [TimeSlice]s that each fit within a
+/// [ScheduleConfig], and the values that cross between slices, the
+/// registers, and the program inputs are kept in a state memory of
+/// [StateSlot]s. One cycle of the program is emulated by configuring the
+/// target with each slice in order, restoring its inputs from the state
+/// memory, evaluating, and saving its outputs back to the state memory. At
+/// the end of the cycle the registers are updated from the slots of their
+/// drivers.
+///
+/// [EmulationSchedule::route_slices] generates the configuration of every
+/// slice, and [EmulationSchedule::emulator] runs the schedule in software to
+/// check it against the original program. The state memory and the logic that
+/// saves and restores it between passes are not generated for the target,
+/// the host is expected to do this through the pads of each slice the same
+/// way that the [Emulator] does.
+#[derive(Debug, Clone)]
+pub struct EmulationSchedule {
+    slots: Vec<StateSlot>,
+    slices: Vec<TimeSlice>,
+    inputs: HashMap<PExternal, Vec<Option<usize>>>,
+    outputs: HashMap<PExternal, Vec<Option<usize>>>,
+}
+
+impl EmulationSchedule {
+    /// Partitions the program of `program_epoch`, which should be optimized,
+    /// into time slices that each satisfy the limits of `config`.
+    ///
+    /// # Errors
+    ///
+    /// If the program has `BlackBox`es, `TNode`s with a delay other than 1,
+    /// combinational loops, or equivalences with multiple drivers, or if a
+    /// single `LNode` does not fit within `config`
+    pub fn new(program_epoch: &SuspendedEpoch, config: &ScheduleConfig) -> Result<Self, Error> {
+        program_epoch.ensemble(|ensemble| Self::from_ensemble(ensemble, config))
+    }
+
+    /// Like [EmulationSchedule::new] but uses the program `ensemble` directly
+    pub fn from_ensemble(program: &Ensemble, config: &ScheduleConfig) -> Result<Self, Error> {
+        if config.max_lnodes == 0 {
+            return Err(Error::OtherStr(
+                "`EmulationSchedule`: `max_lnodes` needs to be at least 1",
+            ))
+        }
+        if !program.blackboxes.is_empty() {
+            return Err(Error::OtherStr(
+                "`EmulationSchedule`: the program has `BlackBox`es which cannot be emulated",
+            ))
+        }
+        let equiv = |p_back: PBack| program.backrefs.get_val(p_back).unwrap().p_self_equiv;
+        // the `LNode` driving each equivalence
+        let mut lnode_drivers: HashMap<PBack, PLNode> = HashMap::new();
+        for (p_lnode, lnode) in &program.lnodes {
+            if lnode_drivers.insert(equiv(lnode.p_self), p_lnode).is_some() {
+                return Err(Error::OtherString(format!(
+                    "`EmulationSchedule`: equivalence {:?} has multiple drivers",
+                    equiv(lnode.p_self)
+                )))
+            }
+        }
+        for tnode in program.tnodes.vals() {
+            if tnode.delay().amount() != 1 {
+                return Err(Error::OtherString(format!(
+                    "`EmulationSchedule`: found a `TNode` with delay {}, only registers with a \
+                     delay of 1 are supported",
+                    tnode.delay().amount()
+                )))
+            }
+            if lnode_drivers.contains_key(&equiv(tnode.p_self)) {
+                return Err(Error::OtherString(format!(
+                    "`EmulationSchedule`: equivalence {:?} has multiple drivers",
+                    equiv(tnode.p_self)
+                )))
+            }
+        }
+
+        // equivalences that always need to be saved when computed
+        let mut external_uses: HashSet<PBack> = HashSet::new();
+        for tnode in program.tnodes.vals() {
+            external_uses.insert(equiv(tnode.p_driver));
+        }
+        for rnode in program.notary.rnodes().vals() {
+            if rnode.read_only() {
+                for p_bit in rnode.bits().unwrap_or(&[]).iter().flatten() {
+                    external_uses.insert(equiv(*p_bit));
+                }
+            }
+        }
+        let input_equivs = |p_lnode: PLNode| {
+            let mut res = program.lnode_input_equivs(p_lnode);
+            res.sort_unstable();
+            res.dedup();
+            res
+        };
+        // the number of `LNode` inputs that use each equivalence
+        let num_uses = |p_equiv: PBack| {
+            let mut res = 0usize;
+            let mut adv = program.backrefs.advancer_surject(p_equiv);
+            while let Some(p_back) = adv.advance(&program.backrefs) {
+                if let Referent::Input(_) = program.backrefs.get_key(p_back).unwrap() {
+                    res += 1;
+                }
+            }
+            res
+        };
+
+        // greedily fill slices in topological order. The restored and saved counts
+        // of the current slice are maintained incrementally, because of the order an
+        // added `LNode` can only use the outputs of the slice and not the other way
+        // around.
+        let mut partition: Vec<Vec<PLNode>> = vec![];
+        let mut current: Vec<PLNode> = vec![];
+        let mut current_set: HashSet<PLNode> = HashSet::new();
+        let mut restored: HashSet<PBack> = HashSet::new();
+        // the number of uses outside of the current slice for the outputs of its
+        // `LNode`s
+        let mut outside_uses: HashMap<PBack, usize> = HashMap::new();
+        let mut saved = 0usize;
+        for p_lnode in program.topo_order()? {
+            loop {
+                let mut new_restored = vec![];
+                let mut uses_inside: HashMap<PBack, usize> = HashMap::new();
+                for p_equiv in program.lnode_input_equivs(p_lnode) {
+                    if lnode_drivers
+                        .get(&p_equiv)
+                        .is_some_and(|p| current_set.contains(p))
+                    {
+                        *uses_inside.entry(p_equiv).or_default() += 1;
+                    } else if !(program.backrefs.get_val(p_equiv).unwrap().val.is_const()
+                        || restored.contains(&p_equiv)
+                        || new_restored.contains(&p_equiv))
+                    {
+                        new_restored.push(p_equiv);
+                    }
+                }
+                let p_out = equiv(program.lnodes[p_lnode].p_self);
+                let out_uses = num_uses(p_out);
+                let mut new_saved =
+                    saved + usize::from(external_uses.contains(&p_out) || (out_uses != 0));
+                for (p_equiv, uses) in &uses_inside {
+                    // the output is no longer needed outside of the slice
+                    if (outside_uses[p_equiv] == *uses) && !external_uses.contains(p_equiv) {
+                        new_saved -= 1;
+                    }
+                }
+                if (current.len() < config.max_lnodes)
+                    && ((restored.len() + new_restored.len()) <= config.max_inputs)
+                    && (new_saved <= config.max_outputs)
+                {
+                    current.push(p_lnode);
+                    current_set.insert(p_lnode);
+                    restored.extend(new_restored);
+                    for (p_equiv, uses) in uses_inside {
+                        *outside_uses.get_mut(&p_equiv).unwrap() -= uses;
+                    }
+                    outside_uses.insert(p_out, out_uses);
+                    saved = new_saved;
+                    break
+                }
+                if current.is_empty() {
+                    return Err(Error::OtherString(format!(
+                        "`EmulationSchedule`: `LNode` {p_lnode:?} by itself does not fit within \
+                         {config:?}"
+                    )))
+                }
+                // start a new slice and try again
+                partition.push(mem::take(&mut current));
+                current_set.clear();
+                restored.clear();
+                outside_uses.clear();
+                saved = 0;
+            }
+        }
+        if !current.is_empty() {
+            partition.push(current);
+        }
+
+        // assign the state slots
+        let mut slots: Vec<StateSlot> = vec![];
+        let mut slot_of: HashMap<PBack, usize> = HashMap::new();
+        let initial = |p_equiv: PBack| {
+            program
+                .backrefs
+                .get_val(p_equiv)
+                .unwrap()
+                .val
+                .known_value()
+                .unwrap_or(false)
+        };
+        let mut inputs = HashMap::new();
+        let mut outputs = HashMap::new();
+        for (_, p_external, rnode) in program.notary.rnodes() {
+            if rnode.read_only() {
+                continue
+            }
+            let mut bits = vec![];
+            for (bit_i, p_bit) in rnode.bits().unwrap_or(&[]).iter().enumerate() {
+                bits.push(p_bit.map(|p_bit| {
+                    let p_equiv = equiv(p_bit);
+                    *slot_of.entry(p_equiv).or_insert_with(|| {
+                        slots.push(StateSlot {
+                            p_equiv,
+                            source: SlotSource::Input(*p_external, bit_i),
+                            initial: initial(p_equiv),
+                        });
+                        slots.len() - 1
+                    })
+                }));
+            }
+            inputs.insert(*p_external, bits);
+        }
+        let mut registers = vec![];
+        for tnode in program.tnodes.vals() {
+            let p_equiv = equiv(tnode.p_self);
+            slot_of.insert(p_equiv, slots.len());
+            registers.push((slots.len(), equiv(tnode.p_driver)));
+            slots.push(StateSlot {
+                p_equiv,
+                source: SlotSource::Register { driver: usize::MAX },
+                initial: initial(p_equiv),
+            });
+        }
+        let mut slice_io = vec![];
+        for (slice_i, slice) in partition.iter().enumerate() {
+            let set: HashSet<PLNode> = slice.iter().copied().collect();
+            let mut restore = vec![];
+            for p_lnode in slice.iter().copied() {
+                for p_equiv in input_equivs(p_lnode) {
+                    if lnode_drivers.get(&p_equiv).is_some_and(|p| set.contains(p)) {
+                        continue
+                    }
+                    // constants and undriven equivalences stay in the slice program
+                    if let Some(slot) = slot_of.get(&p_equiv) {
+                        if !restore.contains(slot) {
+                            restore.push(*slot);
+                        }
+                    }
+                }
+            }
+            let mut save = vec![];
+            for p_lnode in slice.iter().copied() {
+                let p_equiv = equiv(program.lnodes[p_lnode].p_self);
+                let mut needed = external_uses.contains(&p_equiv);
+                let mut adv = program.backrefs.advancer_surject(p_equiv);
+                while let Some(p_back) = adv.advance(&program.backrefs) {
+                    if let Referent::Input(p_user) = program.backrefs.get_key(p_back).unwrap() {
+                        if !set.contains(p_user) {
+                            needed = true;
+                        }
+                    }
+                }
+                if needed {
+                    slot_of.insert(p_equiv, slots.len());
+                    save.push(slots.len());
+                    slots.push(StateSlot {
+                        p_equiv,
+                        source: SlotSource::Slice(slice_i),
+                        initial: initial(p_equiv),
+                    });
+                }
+            }
+            slice_io.push((restore, save));
+        }
+        let mut const_slot = |p_equiv: PBack, slots: &mut Vec<StateSlot>| {
+            *slot_of.entry(p_equiv).or_insert_with(|| {
+                let val = initial(p_equiv);
+                slots.push(StateSlot {
+                    p_equiv,
+                    source: SlotSource::Const(val),
+                    initial: val,
+                });
+                slots.len() - 1
+            })
+        };
+        for (slot, p_driver) in registers {
+            let driver = const_slot(p_driver, &mut slots);
+            slots[slot].source = SlotSource::Register { driver };
+        }
+        for (_, p_external, rnode) in program.notary.rnodes() {
+            if !rnode.read_only() {
+                continue
+            }
+            let Some(bits) = rnode.bits() else { continue };
+            let bits = bits
+                .iter()
+                .map(|p_bit| p_bit.map(|p_bit| const_slot(equiv(p_bit), &mut slots)))
+                .collect();
+            outputs.insert(*p_external, bits);
+        }
+
+        let mut slices = vec![];
+        for (lnodes, (restore, save)) in partition.into_iter().zip(slice_io) {
+            slices.push(Self::slice_program(
+                program, &slots, lnodes, &restore, &save,
+            )?);
+        }
+        Ok(Self {
+            slots,
+            slices,
+            inputs,
+            outputs,
+        })
+    }
+
+    /// Makes the program of a slice by removing everything else from a clone
+    /// of the program and adding `RNode`s for the restored and saved slots
+    fn slice_program(
+        program: &Ensemble,
+        slots: &[StateSlot],
+        lnodes: Vec<PLNode>,
+        restore: &[usize],
+        save: &[usize],
+    ) -> Result<TimeSlice, Error> {
+        let mut ensemble = program.clone();
+        ensemble.journal = None;
+        ensemble.replay_log = None;
+        ensemble.dead_report = None;
+        ensemble.restart_request_phase()?;
+        let p_rnodes: Vec<_> = ensemble.notary.rnodes().ptrs().collect();
+        for p_rnode in p_rnodes {
+            ensemble.remove_rnode(p_rnode);
+        }
+        let mut adv = ensemble.tnodes.advancer();
+        while let Some(p_tnode) = adv.advance(&ensemble.tnodes) {
+            let p_self = ensemble.tnodes[p_tnode].p_self;
+            ensemble.remove_tnode_not_p_self(p_tnode);
+            ensemble.backrefs.remove_key(p_self).unwrap();
+        }
+        let keep: HashSet<PLNode> = lnodes.iter().copied().collect();
+        let mut adv = ensemble.lnodes.advancer();
+        while let Some(p_lnode) = adv.advance(&ensemble.lnodes) {
+            if !keep.contains(&p_lnode) {
+                let p_self = ensemble.lnodes[p_lnode].p_self;
+                ensemble.remove_lnode_not_p_self(p_lnode);
+                ensemble.backrefs.remove_key(p_self).unwrap();
+            }
+        }
+        let add_rnode = |ensemble: &mut Ensemble, slot: usize, read_only: bool| {
+            let (p_rnode, p_external) =
+                ensemble
+                    .notary
+                    .insert_rnode(RNode::new(bw(1), read_only, 1, None, None, false));
+            let p_bit = ensemble
+                .backrefs
+                .insert_key(slots[slot].p_equiv, Referent::ThisRNode(p_rnode))
+                .unwrap();
+            let rnode = ensemble.notary.get_rnode_by_p_rnode_mut(p_rnode).unwrap();
+            rnode.push_bit(Some(p_bit));
+            let prefix = if read_only { "save" } else { "restore" };
+            rnode.set_debug_name(Some(format!("{prefix}{slot}")));
+            (p_external, slot)
+        };
+        let restore = restore
+            .iter()
+            .map(|slot| add_rnode(&mut ensemble, *slot, false))
+            .collect();
+        let save = save
+            .iter()
+            .map(|slot| add_rnode(&mut ensemble, *slot, true))
+            .collect();
+        ensemble.optimize_all()?;
+        Ok(TimeSlice {
+            ensemble,
+            lnodes,
+            restore,
+            save,
+        })
+    }
+
+    /// Returns the state slots, the state memory needed by the emulation
+    pub fn slots(&self) -> &[StateSlot] {
+        &self.slots
+    }
+
+    /// Returns the time slices in the order that they are evaluated in every
+    /// cycle
+    pub fn slices(&self) -> &[TimeSlice] {
+        &self.slices
+    }
+
+    /// Returns the slots of the bits of the writable program `RNode` of
+    /// `p_external`, or `None` for bits that were removed
+    pub fn input_slots(&self, p_external: PExternal) -> Result<&[Option<usize>], Error> {
+        self.inputs
+            .get(&p_external)
+            .map(|bits| bits.as_slice())
+            .ok_or(Error::InvalidPExternal(p_external))
+    }
+
+    /// Returns the slots of the bits of the read only program `RNode` of
+    /// `p_external`, or `None` for bits that were removed
+    pub fn output_slots(&self, p_external: PExternal) -> Result<&[Option<usize>], Error> {
+        self.outputs
+            .get(&p_external)
+            .map(|bits| bits.as_slice())
+            .ok_or(Error::InvalidPExternal(p_external))
+    }
+
+    /// Returns a `Router` for routing slice `slice_i` onto the target. The
+    /// `i`th restored bit of the slice (see [TimeSlice::restore]) is mapped to
+    /// the bit of `input_pads[i]`, given as a target `LazyAwi` and a bit
+    /// index, and likewise the saved bits are mapped to `output_pads`. The
+    /// host restores and saves the state memory through these pads.
+    ///
+    /// # Errors
+    ///
+    /// If `slice_i` is out of range, if there are not enough pads, or if
+    /// creating the `Router` or mapping fails
+    pub fn slice_router(
+        &self,
+        slice_i: usize,
+        target_epoch: &SuspendedEpoch,
+        configurator: &Configurator,
+        input_pads: &[(PExternal, usize)],
+        output_pads: &[(PExternal, usize)],
+    ) -> Result<Router, Error> {
+        let target_channeler = Channeler::from_target(target_epoch, configurator)?;
+        self.slice_router_with_channeler(
+            slice_i,
+            target_epoch,
+            target_channeler,
+            configurator,
+            input_pads,
+            output_pads,
+        )
+    }
+
+    fn slice_router_with_channeler(
+        &self,
+        slice_i: usize,
+        target_epoch: &SuspendedEpoch,
+        target_channeler: Channeler<QCNode, QCEdge>,
+        configurator: &Configurator,
+        input_pads: &[(PExternal, usize)],
+        output_pads: &[(PExternal, usize)],
+    ) -> Result<Router, Error> {
+        let slice = self.slices.get(slice_i).ok_or_else(|| {
+            Error::OtherString(format!(
+                "`EmulationSchedule::slice_router`: slice {slice_i} is out of range"
+            ))
+        })?;
+        if slice.restore.len() > input_pads.len() || slice.save.len() > output_pads.len() {
+            return Err(Error::OtherString(format!(
+                "`EmulationSchedule::slice_router`: slice {slice_i} needs {} input pads and {} \
+                 output pads but only {} and {} were given",
+                slice.restore.len(),
+                slice.save.len(),
+                input_pads.len(),
+                output_pads.len()
+            )))
+        }
+        let program_epoch = Epoch::from_ensemble(slice.ensemble.clone()).suspend();
+        let program_channeler = Channeler::from_program(&program_epoch)?;
+        let mut router = Router::new_from_channelers(
+            target_epoch,
+            target_channeler,
+            configurator,
+            &program_epoch,
+            program_channeler,
+        );
+        drop(program_epoch);
+        for ((p_external, _), (pad, bit_i)) in slice.restore.iter().zip(input_pads) {
+            router.map_rnode_bits(*p_external, 0..1, *pad, *bit_i..(*bit_i + 1), true)?;
+        }
+        for ((p_external, _), (pad, bit_i)) in slice.save.iter().zip(output_pads) {
+            router.map_rnode_bits(*p_external, 0..1, *pad, *bit_i..(*bit_i + 1), false)?;
+        }
+        Ok(router)
+    }
+
+    /// Routes every slice with [EmulationSchedule::slice_router] and
+    /// [Router::route], returning the routers in slice order. The
+    /// configuration of each slice can then be loaded with
+    /// [Router::config_target], or [Router::config_delta] can be used to
+    /// reconfigure from one slice to the next.
+    ///
+    /// # Errors
+    ///
+    /// If [EmulationSchedule::slice_router] or the routing of any slice fails
+    pub fn route_slices(
+        &self,
+        target_epoch: &SuspendedEpoch,
+        configurator: &Configurator,
+        input_pads: &[(PExternal, usize)],
+        output_pads: &[(PExternal, usize)],
+    ) -> Result<Vec<Router>, Error> {
+        let target_channeler = Channeler::from_target(target_epoch, configurator)?;
+        let mut res = vec![];
+        for slice_i in 0..self.slices.len() {
+            let mut router = self.slice_router_with_channeler(
+                slice_i,
+                target_epoch,
+                target_channeler.clone(),
+                configurator,
+                input_pads,
+                output_pads,
+            )?;
+            router.route()?;
+            res.push(router);
+        }
+        Ok(res)
+    }
+
+    /// Returns an [Emulator] that runs the schedule in software with every
+    /// slot at its initial value
+    ///
+    /// # Errors
+    ///
+    /// If [Ensemble::batch_sim] fails on a slice program
+    pub fn emulator(&self) -> Result<Emulator<'_>, Error> {
+        let mut sims = vec![];
+        for slice in &self.slices {
+            sims.push(slice.ensemble.batch_sim()?);
+        }
+        let mut res = Emulator {
+            schedule: self,
+            state: self.slots.iter().map(|slot| slot.initial).collect(),
+            sims,
+        };
+        res.evaluate();
+        Ok(res)
+    }
+}
+
+/// Runs an [EmulationSchedule] in software the same way that the target
+/// would, by evaluating the slices in order and passing values between them
+/// only through the state memory
+#[derive(Debug, Clone)]
+pub struct Emulator<'a> {
+    schedule: &'a EmulationSchedule,
+    state: Vec<bool>,
+    sims: Vec<BatchSim>,
+}
+
+impl Emulator<'_> {
+    /// Returns the state memory
+    pub fn state(&self) -> &[bool] {
+        &self.state
+    }
+
+    /// Writes the slots of the writable program `RNode` of `p_external`,
+    /// which takes effect at the next [Emulator::evaluate] or
+    /// [Emulator::step]
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a writable program `RNode` or if the bitwidths
+    /// do not match
+    pub fn set(&mut self, p_external: PExternal, bits: &Bits) -> Result<(), Error> {
+        let slots = self.schedule.input_slots(p_external)?;
+        if slots.len() != bits.bw() {
+            return Err(Error::BitwidthMismatch(slots.len(), bits.bw()))
+        }
+        for (bit_i, slot) in slots.iter().enumerate() {
+            if let Some(slot) = slot {
+                self.state[*slot] = bits.get(bit_i).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the slots of the read only program `RNode` of `p_external`, bits
+    /// that were removed are zero
+    ///
+    /// # Errors
+    ///
+    /// If `p_external` is not a read only program `RNode`
+    pub fn get(&self, p_external: PExternal) -> Result<Awi, Error> {
+        let slots = self.schedule.output_slots(p_external)?;
+        let mut res = Awi::zero(bw(slots.len()));
+        for (bit_i, slot) in slots.iter().enumerate() {
+            if let Some(slot) = slot {
+                res.set(bit_i, self.state[*slot]).unwrap();
+            }
+        }
+        Ok(res)
+    }
+
+    /// Runs one pass of every slice, restoring and saving through the state
+    /// memory
+    pub fn evaluate(&mut self) {
+        for (slice, sim) in self.schedule.slices.iter().zip(self.sims.iter_mut()) {
+            for (p_external, slot) in &slice.restore {
+                sim.set(*p_external, 0, &Awi::from_bool(self.state[*slot]))
+                    .unwrap();
+            }
+            sim.evaluate();
+            for (p_external, slot) in &slice.save {
+                self.state[*slot] = sim.get(*p_external, 0).unwrap().to_bool();
+            }
+        }
+    }
+
+    /// Ends the cycle by updating every register from the slot of its driver,
+    /// and then evaluates. This corresponds to
+    /// [Epoch::run](crate::Epoch::run) with a delay of 1.
+    pub fn step(&mut self) {
+        let next: Vec<(usize, bool)> = self
+            .schedule
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, state_slot)| match state_slot.source {
+                SlotSource::Register { driver } => Some((slot, self.state[driver])),
+                _ => None,
+            })
+            .collect();
+        for (slot, val) in next {
+            self.state[slot] = val;
+        }
+        self.evaluate();
+    }
+}
@@ -0,0 +1,150 @@
+use std::{fmt, num::NonZeroU64};
+
+use awint::Awi;
+
+use crate::{
+    ensemble::{Delay, PExternal, Value},
+    route::Router,
+    Error,
+};
+
+/// The first output mismatch found by [Router::verify_by_simulation]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The cycle in which the outputs diverged, counting from zero
+    pub cycle: usize,
+    /// The stimulus of the cycle, in the order of [Router::simulation_inputs]
+    pub inputs: Vec<(PExternal, Awi)>,
+    /// The program `RNode` and bit that has the expected value
+    pub program: (PExternal, usize),
+    /// The target `RNode` and bit that diverged
+    pub target: (PExternal, usize),
+    /// The known value of the program bit
+    pub expected: bool,
+    /// The value of the target bit
+    pub found: Value,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "cycle {}: program {} bit {} is {} but target {} bit {} is {:?}",
+            self.cycle,
+            self.program.0,
+            self.program.1,
+            self.expected,
+            self.target.0,
+            self.target.1,
+            self.found
+        )?;
+        for (p_external, awi) in &self.inputs {
+            writeln!(f, "input {p_external}: {awi}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Router {
+    /// Returns the writable program `RNode`s (such as those of `LazyAwi`s)
+    /// that have bits mapped to the target, in the order that
+    /// [Router::verify_by_simulation] expects stimulus for them
+    pub fn simulation_inputs(&self) -> Vec<PExternal> {
+        let mut res = vec![];
+        let ensemble = self.program_ensemble();
+        for (_, p_external, rnode) in ensemble.notary.rnodes() {
+            if rnode.read_only() {
+                continue
+            }
+            let is_mapped = rnode.bits().unwrap_or(&[]).iter().flatten().any(|p_bit| {
+                let p_equiv = ensemble.backrefs.get_val(*p_bit).unwrap().p_self_equiv;
+                self.mappings
+                    .find_key(&p_equiv)
+                    .is_some_and(|p| self.mappings.get_val(p).unwrap().target_source.is_some())
+            });
+            if is_mapped {
+                res.push(*p_external);
+            }
+        }
+        res
+    }
+
+    /// After routing is done, this simulates clones of the program and the
+    /// configured target side by side. Each item of `vectors` is the stimulus
+    /// of one cycle, with one value per [Router::simulation_inputs]. The
+    /// stimulus is assigned to the program and carried to the target through
+    /// the mappings, then every mapped program output bit with a known value
+    /// is compared against all of its target bits, and then both are run for
+    /// one unit of time. Returns the first [Divergence], or `None` if all
+    /// outputs matched in every cycle.
+    ///
+    /// # Errors
+    ///
+    /// If a vector has the wrong number of values or bitwidths, or if
+    /// evaluation fails
+    pub fn verify_by_simulation<I: IntoIterator<Item = Vec<Awi>>>(
+        &self,
+        vectors: I,
+    ) -> Result<Option<Divergence>, Error> {
+        let inputs = self.simulation_inputs();
+        let mut program = self.program_ensemble().clone();
+        let mut target = self.target_ensemble().clone();
+        self.ensemble_config_target(&mut target)?;
+        let one = NonZeroU64::new(1).unwrap();
+        for (cycle, vector) in vectors.into_iter().enumerate() {
+            if vector.len() != inputs.len() {
+                return Err(Error::OtherString(format!(
+                    "`Router::verify_by_simulation`: the vector for cycle {cycle} has {} values, \
+                     but there are {} inputs",
+                    vector.len(),
+                    inputs.len()
+                )))
+            }
+            for (p_external, val) in inputs.iter().zip(vector.iter()) {
+                let (_, rnode) = program.notary.get_rnode(*p_external)?;
+                if rnode.nzbw() != val.nzbw() {
+                    return Err(Error::BitwidthMismatch(rnode.nzbw().get(), val.bw()))
+                }
+                let bits = rnode.bits().unwrap().to_vec();
+                for (bit_i, p_bit) in bits.into_iter().enumerate() {
+                    if let Some(p_bit) = p_bit {
+                        program.change_value(p_bit, Value::Dynam(val.get(bit_i).unwrap()), one)?;
+                    }
+                }
+            }
+            for (p_equiv, mapping) in self.mappings.iter().map(|(_, k, v)| (k, v)) {
+                if let Some(source) = mapping.target_source.as_ref() {
+                    let val = match program.request_value(*p_equiv)?.known_value() {
+                        Some(b) => Value::Dynam(b),
+                        None => Value::Unknown,
+                    };
+                    target.change_value(source.target_p_equiv, val, one)?;
+                }
+            }
+            for (p_equiv, mapping) in self.mappings.iter().map(|(_, k, v)| (k, v)) {
+                if mapping.target_sinks.is_empty() {
+                    continue
+                }
+                let Some(expected) = program.request_value(*p_equiv)?.known_value() else {
+                    continue
+                };
+                for sink in &mapping.target_sinks {
+                    let found = target.request_value(sink.target_p_equiv)?;
+                    if found.known_value() != Some(expected) {
+                        return Ok(Some(Divergence {
+                            cycle,
+                            inputs: inputs.iter().copied().zip(vector).collect(),
+                            program: (mapping.program_p_external, mapping.program_bit_i),
+                            target: (sink.target_p_external, sink.target_bit_i),
+                            expected,
+                            found,
+                        }))
+                    }
+                }
+            }
+            program.run(Delay::from(1))?;
+            target.run(Delay::from(1))?;
+        }
+        Ok(None)
+    }
+}
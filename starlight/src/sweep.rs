@@ -0,0 +1,370 @@
+//! Utilities for sweeping a design over many generate time parameters and
+//! collecting statistics of the optimized result, e.g. for choosing FIFO
+//! depths or data widths.
+//!
+//! ```
+//! use starlight::{dag, sweep::Sweep, EvalAwi, LazyAwi};
+//!
+//! let results = Sweep::new().threads(2).run(vec![4usize, 8, 16], |w| {
+//!     let a = LazyAwi::opaque(dag::bw(*w));
+//!     let b = LazyAwi::opaque(dag::bw(*w));
+//!     let mut sum = dag::Awi::from(&a);
+//!     sum.add_(&b).unwrap();
+//!     // the returned value is kept alive until the statistics are collected
+//!     Ok(EvalAwi::from(&sum))
+//! });
+//! for result in &results {
+//!     assert!(result.stats.as_ref().unwrap().num_lnodes > 0);
+//! }
+//! println!("{}", starlight::sweep::format_table(&results));
+//! ```
+
+use std::{
+    cmp::max,
+    collections::HashMap,
+    fmt::{Debug, Write},
+    thread,
+};
+
+use awint::awint_dag::triple_arena::Advancer;
+
+use crate::{
+    ensemble::{Ensemble, LNodeKind, PBack, PBlackBox, PExternal, PLNode, Referent},
+    Delay, Epoch, Error,
+};
+
+/// Statistics of an optimized design
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SweepStats {
+    pub num_lnodes: usize,
+    pub num_tnodes: usize,
+    /// The number of equivalences (unique bits)
+    pub num_equivs: usize,
+    /// The maximum number of `LNode`s on a combinational path
+    pub depth: usize,
+    /// The maximum delay of a combinational path, weighting `LNode`s by
+    /// [Sweep::lnode_delay] and [Sweep::carry_delay] and adding the delays
+    /// of black-boxes
+    pub estimated_delay: Delay,
+}
+
+/// The result for a single parameter of a sweep
+#[derive(Debug)]
+pub struct SweepResult<P> {
+    pub param: P,
+    pub stats: Result<SweepStats, Error>,
+}
+
+/// A harness for building a design for many parameters, see the module level
+/// documentation
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    threads: usize,
+    lnode_delay: Delay,
+    carry_delay: Delay,
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A node on a combinational path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    LNode(PLNode),
+    /// An output port of a `BlackBox`
+    BlackBoxOutput(PBlackBox, usize),
+}
+
+/// Finds the `LNode` driving the equivalence of `p_back`, if any
+fn lnode_driving(ensemble: &Ensemble, p_back: PBack) -> Option<PLNode> {
+    let mut adv = ensemble.backrefs.advancer_surject(p_back);
+    while let Some(p_back) = adv.advance(&ensemble.backrefs) {
+        if let Referent::ThisLNode(p_lnode) = *ensemble.backrefs.get_key(p_back).unwrap() {
+            return Some(p_lnode)
+        }
+    }
+    None
+}
+
+/// Returns the bits of the `RNode` of `p_external`, or nothing if it has not
+/// been lowered
+fn rnode_bits(ensemble: &Ensemble, p_external: PExternal) -> impl Iterator<Item = PBack> + '_ {
+    ensemble
+        .notary
+        .get_rnode(p_external)
+        .ok()
+        .and_then(|(_, rnode)| rnode.bits())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .copied()
+}
+
+impl SweepStats {
+    /// Calculates the statistics of `ensemble`, which should usually have
+    /// been optimized. `TNode`s break combinational paths, and
+    /// [crate::ensemble::BlackBox]es add their pin-to-pin delays to the
+    /// paths through them.
+    ///
+    /// # Errors
+    ///
+    /// If a combinational loop is found
+    pub fn from_ensemble(
+        ensemble: &Ensemble,
+        lnode_delay: Delay,
+        carry_delay: Delay,
+    ) -> Result<Self, Error> {
+        // the equivalences driven by `BlackBox` outputs
+        let mut blackbox_outputs: HashMap<PBack, Node> = HashMap::new();
+        for (p_blackbox, blackbox) in &ensemble.blackboxes {
+            for (j, p_external) in blackbox.outputs.iter().enumerate() {
+                for p_bit in rnode_bits(ensemble, *p_external) {
+                    let p_equiv = ensemble.backrefs.get_val(p_bit).unwrap().p_self_equiv;
+                    blackbox_outputs.insert(p_equiv, Node::BlackBoxOutput(p_blackbox, j));
+                }
+            }
+        }
+        let driving = |p_back: PBack| -> Option<Node> {
+            if let Some(p_lnode) = lnode_driving(ensemble, p_back) {
+                Some(Node::LNode(p_lnode))
+            } else {
+                let p_equiv = ensemble.backrefs.get_val(p_back).unwrap().p_self_equiv;
+                blackbox_outputs.get(&p_equiv).copied()
+            }
+        };
+        // the drivers of a node with the delay added on the way from each one,
+        // and the delay of the node from inputs without drivers
+        let inputs_of = |node: Node| -> (Vec<(Node, Delay)>, Delay) {
+            let mut inputs = vec![];
+            let mut base = Delay::zero();
+            match node {
+                Node::LNode(p_lnode) => {
+                    ensemble.lnodes[p_lnode].inputs(|p_inp| {
+                        if let Some(p_driver) = driving(p_inp) {
+                            inputs.push((p_driver, Delay::zero()));
+                        }
+                    });
+                }
+                Node::BlackBoxOutput(p_blackbox, j) => {
+                    let blackbox = &ensemble.blackboxes[p_blackbox];
+                    for (i, o, delay) in blackbox.delays.iter().copied() {
+                        if o != j {
+                            continue
+                        }
+                        base = max(base, delay);
+                        for p_bit in rnode_bits(ensemble, blackbox.inputs[i]) {
+                            if let Some(p_driver) = driving(p_bit) {
+                                inputs.push((p_driver, delay));
+                            }
+                        }
+                    }
+                }
+            }
+            (inputs, base)
+        };
+
+        // the depth and delay of the path ending at each node, `None` if it is
+        // still on the DFS stack
+        let mut paths: HashMap<Node, Option<(usize, Delay)>> = HashMap::new();
+        let mut depth = 0;
+        let mut estimated_delay = Delay::zero();
+        let roots = ensemble
+            .lnodes
+            .ptrs()
+            .map(Node::LNode)
+            .chain(blackbox_outputs.values().copied());
+        for root in roots {
+            if paths.contains_key(&root) {
+                continue
+            }
+            // DFS with explicit stack of (node, visited inputs)
+            let mut stack: Vec<(Node, bool)> = vec![(root, false)];
+            while let Some((node, visited)) = stack.pop() {
+                let (inputs, base) = inputs_of(node);
+                if visited {
+                    let mut res = (0usize, base);
+                    for (p_driver, delay) in inputs {
+                        let (d, t) = paths[&p_driver].unwrap();
+                        res.0 = max(res.0, d);
+                        res.1 = max(res.1, t.checked_add(delay).unwrap());
+                    }
+                    if let Node::LNode(p_lnode) = node {
+                        let weight = if let LNodeKind::Carry(_) = ensemble.lnodes[p_lnode].kind {
+                            carry_delay
+                        } else {
+                            lnode_delay
+                        };
+                        res.0 += 1;
+                        res.1 = res.1.checked_add(weight).unwrap();
+                    }
+                    depth = max(depth, res.0);
+                    estimated_delay = max(estimated_delay, res.1);
+                    paths.insert(node, Some(res));
+                } else {
+                    if paths.contains_key(&node) {
+                        continue
+                    }
+                    paths.insert(node, None);
+                    stack.push((node, true));
+                    for (p_driver, _) in inputs {
+                        match paths.get(&p_driver) {
+                            Some(Some(_)) => (),
+                            Some(None) => {
+                                return Err(Error::OtherString(format!(
+                                    "`SweepStats::from_ensemble`: found a combinational loop \
+                                     through {p_driver:?}"
+                                )))
+                            }
+                            None => stack.push((p_driver, false)),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            num_lnodes: ensemble.lnodes.len(),
+            num_tnodes: ensemble.tnodes.len(),
+            num_equivs: ensemble.backrefs.len_vals(),
+            depth,
+            estimated_delay,
+        })
+    }
+}
+
+impl Sweep {
+    /// Creates a sweep that runs on a single thread, with a delay of 1 per
+    /// `LNode` and 0 per `LNodeKind::Carry`
+    pub fn new() -> Self {
+        Self {
+            threads: 1,
+            lnode_delay: Delay::from_amount(1),
+            carry_delay: Delay::zero(),
+        }
+    }
+
+    /// Sets the number of threads to build designs on. Each design is built
+    /// in its own `Epoch`, so designs on different threads do not interact.
+    /// Zero is treated as one, and this is ignored on `wasm32` targets where
+    /// threads are not generally available.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = max(threads, 1);
+        self
+    }
+
+    /// Sets the delay that each `LNode` adds to the estimated delay
+    pub fn lnode_delay(mut self, lnode_delay: Delay) -> Self {
+        self.lnode_delay = lnode_delay;
+        self
+    }
+
+    /// Sets the delay that each `LNodeKind::Carry` adds to the estimated
+    /// delay, this is usually much smaller than a general `LNode` on targets
+    /// with dedicated carry chains
+    pub fn carry_delay(mut self, carry_delay: Delay) -> Self {
+        self.carry_delay = carry_delay;
+        self
+    }
+
+    fn run_single<P, T, F>(&self, param: &P, f: &F) -> Result<SweepStats, Error>
+    where
+        F: Fn(&P) -> Result<T, Error>,
+    {
+        let epoch = Epoch::new();
+        let keep = f(param);
+        let res = match keep {
+            Ok(keep) => {
+                let res = epoch.optimize().and_then(|_| {
+                    epoch.ensemble(|ensemble| {
+                        SweepStats::from_ensemble(ensemble, self.lnode_delay, self.carry_delay)
+                    })
+                });
+                drop(keep);
+                res
+            }
+            Err(e) => Err(e),
+        };
+        drop(epoch);
+        res
+    }
+
+    /// For each of `params`, creates a new `Epoch`, calls `f` to build the
+    /// design, optimizes, and collects statistics. Anything the design needs
+    /// to keep alive (e.g. the `EvalAwi`s of its outputs) should be returned
+    /// from `f`. The results are in the same order as `params`. Errors from
+    /// `f` or from optimization are returned in the corresponding
+    /// `SweepResult`.
+    pub fn run<P, T, F>(&self, params: Vec<P>, f: F) -> Vec<SweepResult<P>>
+    where
+        P: Send + Sync,
+        F: Fn(&P) -> Result<T, Error> + Sync,
+    {
+        if (self.threads <= 1) || cfg!(target_arch = "wasm32") {
+            return params
+                .into_iter()
+                .map(|param| {
+                    let stats = self.run_single(&param, &f);
+                    SweepResult { param, stats }
+                })
+                .collect()
+        }
+        let chunk_len = params.len().div_ceil(self.threads).max(1);
+        let stats: Vec<Result<SweepStats, Error>> = thread::scope(|s| {
+            let handles: Vec<_> = params
+                .chunks(chunk_len)
+                .map(|chunk| {
+                    let f = &f;
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|param| self.run_single(param, f))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        params
+            .into_iter()
+            .zip(stats)
+            .map(|(param, stats)| SweepResult { param, stats })
+            .collect()
+    }
+}
+
+/// Runs a default [Sweep] over `params`, see [Sweep::run]
+pub fn sweep<P, T, F>(params: Vec<P>, f: F) -> Vec<SweepResult<P>>
+where
+    P: Send + Sync,
+    F: Fn(&P) -> Result<T, Error> + Sync,
+{
+    Sweep::new().run(params, f)
+}
+
+/// Formats the results as a table with one row per parameter
+pub fn format_table<P: Debug>(results: &[SweepResult<P>]) -> String {
+    let mut s = String::new();
+    writeln!(s, "param\tlnodes\ttnodes\tequivs\tdepth\tdelay").unwrap();
+    for result in results {
+        write!(s, "{:?}\t", result.param).unwrap();
+        match result.stats {
+            Ok(ref stats) => writeln!(
+                s,
+                "{}\t{}\t{}\t{}\t{}",
+                stats.num_lnodes,
+                stats.num_tnodes,
+                stats.num_equivs,
+                stats.depth,
+                stats.estimated_delay.amount()
+            )
+            .unwrap(),
+            Err(ref e) => writeln!(s, "error: {e}").unwrap(),
+        }
+    }
+    s
+}
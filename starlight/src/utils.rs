@@ -1,10 +1,20 @@
+#[cfg(feature = "audit")]
+mod audit;
+mod diff;
 mod error;
 mod grid;
 mod ortho;
 mod render;
 mod rng;
 mod small_map;
+#[cfg(feature = "trace")]
+mod trace;
 
+#[cfg(feature = "audit")]
+pub(crate) use audit::audit_visit;
+#[cfg(feature = "audit")]
+pub use audit::{AuditDivergence, AuditEntry, IterationAudit};
+pub use diff::{diff_ensembles, EnsembleDiff};
 pub use error::Error;
 pub(crate) use error::{DisplayStr, HexadecimalNonZeroU128};
 pub use grid::Grid;
@@ -12,3 +22,40 @@ pub use ortho::{Ortho, OrthoArray};
 pub use render::Render;
 pub use rng::StarRng;
 pub use small_map::{binary_search_similar_by, SmallMap, SmallSet};
+#[cfg(feature = "trace")]
+pub(crate) use trace::{trace_event, trace_span};
+#[cfg(feature = "trace")]
+pub use trace::{Trace, TraceEvent, TraceSpan};
+
+/// Records that `x` was visited at the iteration site `site` for
+/// `IterationAudit`, which is a no-op without the `audit` feature
+#[cfg(not(feature = "audit"))]
+#[inline(always)]
+pub(crate) fn audit_visit<T: std::hash::Hash>(_site: &'static str, _x: T) {}
+
+/// Closes a span when dropped, which is a no-op without the `trace` feature
+#[cfg(not(feature = "trace"))]
+pub(crate) struct TraceGuard;
+
+#[cfg(not(feature = "trace"))]
+impl TraceGuard {
+    #[inline(always)]
+    pub fn count(&self, _key: &'static str, _x: u64) {}
+}
+
+/// Enters a span for `Trace`, which is a no-op without the `trace` feature
+#[cfg(not(feature = "trace"))]
+#[inline(always)]
+pub(crate) fn trace_span(_target: &'static str, _name: &'static str) -> TraceGuard {
+    TraceGuard
+}
+
+/// Records an event for `Trace`, which is a no-op without the `trace` feature
+#[cfg(not(feature = "trace"))]
+#[inline(always)]
+pub(crate) fn trace_event(
+    _target: &'static str,
+    _name: &'static str,
+    _fields: &[(&'static str, u64)],
+) {
+}
@@ -0,0 +1,184 @@
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+thread_local!(
+    /// The audit currently being recorded by [IterationAudit::record]
+    static CURRENT_AUDIT: RefCell<Option<IterationAudit>> = const { RefCell::new(None) };
+);
+
+/// Hashes `x` with fixed keys so that the hash is the same across runs
+fn deterministic_hash<T: Hash>(x: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    x.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Records that `x` was visited at the iteration site `site`, if an
+/// [IterationAudit] is being recorded
+pub(crate) fn audit_visit<T: Hash>(site: &'static str, x: T) {
+    CURRENT_AUDIT.with(|audit| {
+        if let Some(ref mut audit) = *audit.borrow_mut() {
+            let hash = deterministic_hash(x);
+            match audit.entries.last_mut() {
+                Some(entry) if entry.site == site => entry.visits.push(hash),
+                _ => audit.entries.push(AuditEntry {
+                    site,
+                    visits: vec![hash],
+                }),
+            }
+        }
+    })
+}
+
+/// A run of consecutive visits at the same iteration site
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuditEntry {
+    /// The name of the iteration site
+    pub site: &'static str,
+    /// The hashes of the visited `Ptr`s in order
+    pub visits: Vec<u64>,
+}
+
+/// Where two [IterationAudit]s first diverged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditDivergence {
+    /// The index of the first entry that differs
+    pub entry_i: usize,
+    /// The sites of the entries in the two audits, `None` if an audit ran out
+    /// of entries
+    pub sites: (Option<&'static str>, Option<&'static str>),
+    /// The index of the first visit within the entries that differs
+    pub visit_i: usize,
+}
+
+impl fmt::Display for AuditDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let site = |site: Option<&'static str>| site.unwrap_or("<no more entries>");
+        write!(
+            f,
+            "iteration order first diverged at entry {} on visit {}, the sites were `{}` and `{}`",
+            self.entry_i,
+            self.visit_i,
+            site(self.sites.0),
+            site(self.sites.1)
+        )
+    }
+}
+
+/// A record of the order in which `Ptr`s were visited at iteration sites in
+/// lowering, optimization, and routing, for finding sources of
+/// nondeterminism. Running the same thing twice should result in equal
+/// audits, use [IterationAudit::first_divergence] to find where they differ.
+/// Requires the `audit` feature.
+///
+/// Note that `Epoch`s constructed separately in the same process get
+/// different `PExternal`s, which the canonical order of an `Ensemble` depends
+/// on (see [Ensemble::stable_ids](crate::ensemble::Ensemble::stable_ids)).
+/// Within a process, compare runs on clones of the same `Ensemble`, and across
+/// processes compare [IterationAudit::digest]s.
+///
+/// ```
+/// use starlight::{dag::*, utils::IterationAudit, Epoch, EvalAwi, LazyAwi};
+///
+/// let epoch = Epoch::new();
+/// let x = LazyAwi::opaque(bw(8));
+/// let mut y = awi!(x);
+/// y.rotl_(3).unwrap();
+/// let _y = EvalAwi::from(&y);
+/// epoch.lower().unwrap();
+/// let ensemble = epoch.ensemble(|ensemble| ensemble.clone());
+/// let run = || {
+///     let mut ensemble = ensemble.clone();
+///     IterationAudit::record(|| ensemble.optimize_all().unwrap()).1
+/// };
+/// let audit = run();
+/// assert!(!audit.entries().is_empty());
+/// assert!(audit.first_divergence(&run()).is_none());
+/// drop(epoch);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IterationAudit {
+    entries: Vec<AuditEntry>,
+}
+
+/// Restores the outer audit when dropped, so that it is restored even if the
+/// closure of [IterationAudit::record] panics
+struct AuditGuard(Option<IterationAudit>);
+
+impl Drop for AuditGuard {
+    fn drop(&mut self) {
+        let outer = self.0.take();
+        CURRENT_AUDIT.with(|audit| audit.replace(outer));
+    }
+}
+
+impl IterationAudit {
+    /// Runs `f` while recording the iteration order of everything that it
+    /// does on the current thread. Recordings can be nested, the outer
+    /// recording does not see what the inner one records.
+    pub fn record<O, F: FnOnce() -> O>(f: F) -> (O, Self) {
+        let guard = AuditGuard(CURRENT_AUDIT.with(|audit| audit.replace(Some(Self::default()))));
+        let res = f();
+        let audit = CURRENT_AUDIT.with(|audit| audit.take()).unwrap();
+        drop(guard);
+        (res, audit)
+    }
+
+    /// Returns the recorded entries in order
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    /// Returns a hash of all the entries, which can be compared across
+    /// processes
+    pub fn digest(&self) -> u64 {
+        deterministic_hash(&self.entries)
+    }
+
+    /// Returns where `self` and `other` first diverged, or `None` if they are
+    /// equal
+    pub fn first_divergence(&self, other: &Self) -> Option<AuditDivergence> {
+        let len = self.entries.len().max(other.entries.len());
+        for entry_i in 0..len {
+            let entry0 = self.entries.get(entry_i);
+            let entry1 = other.entries.get(entry_i);
+            if entry0 == entry1 {
+                continue
+            }
+            let visit_i = match (entry0, entry1) {
+                (Some(entry0), Some(entry1)) if entry0.site == entry1.site => entry0
+                    .visits
+                    .iter()
+                    .zip(entry1.visits.iter())
+                    .position(|(visit0, visit1)| visit0 != visit1)
+                    .unwrap_or(entry0.visits.len().min(entry1.visits.len())),
+                _ => 0,
+            };
+            return Some(AuditDivergence {
+                entry_i,
+                sites: (entry0.map(|e| e.site), entry1.map(|e| e.site)),
+                visit_i,
+            })
+        }
+        None
+    }
+}
+
+impl fmt::Display for IterationAudit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}: {} visits, hash {:016x}",
+                entry.site,
+                entry.visits.len(),
+                deterministic_hash(&entry.visits)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    ensemble::{Ensemble, PExternal},
+    Error,
+};
+
+/// The differences between two `Ensemble`s, see [diff_ensembles]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnsembleDiff {
+    /// Stable IDs of `LNode`s in `b` but not `a`, repeated if multiple
+    /// identical `LNode`s were added
+    pub added_lnodes: Vec<u64>,
+    /// Stable IDs of `LNode`s in `a` but not `b`, repeated if multiple
+    /// identical `LNode`s were removed
+    pub removed_lnodes: Vec<u64>,
+    /// Stable IDs of equivalences in `b` but not `a`
+    pub added_equivs: Vec<u64>,
+    /// Stable IDs of equivalences in `a` but not `b`
+    pub removed_equivs: Vec<u64>,
+    /// `RNode` bits present in both ensembles whose logic changed, or that
+    /// were optimized away in only one of them
+    pub changed_rnode_bits: Vec<(PExternal, usize)>,
+}
+
+impl EnsembleDiff {
+    /// Returns if there are no differences
+    pub fn is_empty(&self) -> bool {
+        self.added_lnodes.is_empty()
+            && self.removed_lnodes.is_empty()
+            && self.added_equivs.is_empty()
+            && self.removed_equivs.is_empty()
+            && self.changed_rnode_bits.is_empty()
+    }
+}
+
+impl fmt::Display for EnsembleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "+{} -{} LNodes, +{} -{} equivalences",
+            self.added_lnodes.len(),
+            self.removed_lnodes.len(),
+            self.added_equivs.len(),
+            self.removed_equivs.len()
+        )?;
+        for id in &self.removed_lnodes {
+            writeln!(f, "- LNode {id:016x}")?;
+        }
+        for id in &self.added_lnodes {
+            writeln!(f, "+ LNode {id:016x}")?;
+        }
+        for (p_external, bit_i) in &self.changed_rnode_bits {
+            writeln!(f, "changed {p_external} bit {bit_i}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the `(removed, added)` IDs between the multisets `a` and `b`
+fn diff_counts(a: &HashMap<u64, usize>, b: &HashMap<u64, usize>) -> (Vec<u64>, Vec<u64>) {
+    let mut removed = vec![];
+    let mut added = vec![];
+    for (id, n) in a {
+        let m = b.get(id).copied().unwrap_or(0);
+        for _ in m..*n {
+            removed.push(*id);
+        }
+    }
+    for (id, m) in b {
+        let n = a.get(id).copied().unwrap_or(0);
+        for _ in n..*m {
+            added.push(*id);
+        }
+    }
+    removed.sort_unstable();
+    added.sort_unstable();
+    (removed, added)
+}
+
+/// Reports the added, removed, and changed nodes between `a` and `b` using
+/// [Ensemble::stable_ids]. This is intended for seeing what an optimization
+/// pass changed, by diffing a clone of an `Ensemble` from before the pass
+/// with the `Ensemble` after.
+///
+/// # Errors
+///
+/// If the stable IDs of either ensemble could not be computed
+pub fn diff_ensembles(a: &Ensemble, b: &Ensemble) -> Result<EnsembleDiff, Error> {
+    let ids_a = a.stable_ids()?;
+    let ids_b = b.stable_ids()?;
+    let (removed_lnodes, added_lnodes) =
+        diff_counts(&ids_a.lnode_id_counts(), &ids_b.lnode_id_counts());
+    let (removed_equivs, added_equivs) =
+        diff_counts(&ids_a.equiv_id_counts(), &ids_b.equiv_id_counts());
+    let mut changed_rnode_bits = vec![];
+    for (_, p_external, rnode_a) in a.notary.rnodes() {
+        let rnode_b = if let Ok((_, rnode_b)) = b.notary.get_rnode(*p_external) {
+            rnode_b
+        } else {
+            continue
+        };
+        let (bits_a, bits_b) = match (rnode_a.bits(), rnode_b.bits()) {
+            (Some(bits_a), Some(bits_b)) => (bits_a, bits_b),
+            _ => continue,
+        };
+        for (i, (bit_a, bit_b)) in bits_a.iter().zip(bits_b.iter()).enumerate() {
+            let id_a = bit_a.and_then(|p| ids_a.equiv(a.backrefs.get_val(p).unwrap().p_self_equiv));
+            let id_b = bit_b.and_then(|p| ids_b.equiv(b.backrefs.get_val(p).unwrap().p_self_equiv));
+            if id_a != id_b {
+                changed_rnode_bits.push((*p_external, i));
+            }
+        }
+    }
+    changed_rnode_bits.sort_unstable();
+    Ok(EnsembleDiff {
+        added_lnodes,
+        removed_lnodes,
+        added_equivs,
+        removed_equivs,
+        changed_rnode_bits,
+    })
+}
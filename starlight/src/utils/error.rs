@@ -1,7 +1,10 @@
 use core::fmt;
 use std::{fmt::Debug, num::NonZeroU128};
 
-use crate::ensemble::PExternal;
+use crate::{
+    ensemble::{PBack, PExternal, PLNode, PTNode},
+    EpochInfo,
+};
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, thiserror::Error)]
 pub enum Error {
@@ -34,6 +37,17 @@ pub enum Error {
          `Epoch` operations require that `self` is the current `Epoch`"
     )]
     WrongCurrentlyActiveEpoch,
+    /// If an `EvalAwi` or `LazyAwi` was used while an `Epoch` other than the
+    /// one it was created in is current
+    #[error(
+        "an `EvalAwi` or `LazyAwi` created in {created_in} was used while {current} is the \
+         current `Epoch`, handles can only be used while the `Epoch` they were created in is \
+         current"
+    )]
+    HandleFromDifferentEpoch {
+        created_in: EpochInfo,
+        current: EpochInfo,
+    },
     /// If an `RNode` was requested that cannot be found
     #[error(
         "could not find thread local `RNode` corresponding to {0:#?}, probably an `EvalAwi` or \
@@ -61,6 +75,38 @@ pub enum Error {
          `EvalAwi` or `LazyAwi`"
     )]
     CorrespondenceNotATranspose(PExternal),
+    /// If a limit set with `Epoch::set_limits` was exceeded
+    #[error("the resource limit `{0}` of {1} was exceeded")]
+    ResourceLimitExceeded(&'static str, usize),
+    /// If zero delay evaluation at a single point in time did not settle
+    /// within the number of events allowed by
+    /// `Limits::max_zero_delay_events`, which happens when there is a
+    /// combinational loop without any delay that oscillates. Includes the
+    /// equivalences that were still changing and the `LNode`s and `TNode`s
+    /// driving them.
+    #[error(
+        "zero delay evaluation did not converge after {events} events, the equivalences \
+         {equivs:?} driven by the `LNode`s {lnodes:?} and `TNode`s {tnodes:?} were still changing"
+    )]
+    ZeroDelayNonConvergence {
+        events: usize,
+        equivs: Vec<PBack>,
+        lnodes: Vec<PLNode>,
+        tnodes: Vec<PTNode>,
+    },
+    /// If an arena is near the capacity of its `Ptr`s, includes the name of
+    /// the arena, its length, and the capacity. With the `u32_ptrs` feature
+    /// this means that the feature needs to be disabled, see
+    /// [crate::ensemble::Ensemble::estimate_ptr_requirements].
+    #[error(
+        "the `{0}` arena has {1} elements which is near the `Ptr` capacity of {2}, the `u32_ptrs` \
+         feature may need to be disabled"
+    )]
+    PtrCapacityExceeded(&'static str, usize, usize),
+    /// If the lowering of a state failed, includes a description of the state
+    /// and the underlying error
+    #[error("lowering of {0} failed: {1}")]
+    LoweringFailed(String, Box<Error>),
     /// For miscellanious errors
     #[error("{0}")]
     OtherStr(&'static str),
@@ -17,7 +17,7 @@ pub struct Grid<T> {
 
 impl<T> Grid<T> {
     /// Returns `None` if any of the side lengths are zero
-    pub fn new<F: Fn((usize, usize)) -> T>(len: (usize, usize), fill: F) -> Option<Self> {
+    pub fn new<F: FnMut((usize, usize)) -> T>(len: (usize, usize), mut fill: F) -> Option<Self> {
         let nzlen = (NonZeroUsize::new(len.0)?, NonZeroUsize::new(len.1)?);
         // unwrap because you would be in allocation failure territory anyways
         let elen = len.0.checked_mul(len.1).unwrap();
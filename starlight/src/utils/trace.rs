@@ -0,0 +1,355 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Write},
+    time::{Duration, Instant},
+};
+
+thread_local!(
+    /// The trace currently being recorded by [Trace::record]
+    static CURRENT_TRACE: RefCell<Option<Recording>> = const { RefCell::new(None) };
+);
+
+#[derive(Debug)]
+struct Recording {
+    trace: Trace,
+    start: Instant,
+    /// The indexes of the currently open spans
+    stack: Vec<usize>,
+}
+
+/// Restores the outer recording when dropped, so that it is restored even if
+/// the closure of [Trace::record] panics
+struct RecordingGuard(Option<Recording>);
+
+impl Drop for RecordingGuard {
+    fn drop(&mut self) {
+        let outer = self.0.take();
+        CURRENT_TRACE.with(|recording| recording.replace(outer));
+    }
+}
+
+/// A span of time spent in some part of lowering, optimization, evaluation,
+/// or routing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceSpan {
+    /// The subsystem, one of "lower", "optimize", "eval", or "route"
+    pub target: &'static str,
+    pub name: &'static str,
+    /// The index of the span this was entered within
+    pub parent: Option<usize>,
+    /// The time from the start of the recording to when the span was entered
+    pub start: Duration,
+    /// The time spent in the span including its children
+    pub duration: Duration,
+    /// Counts of things done within the span, such as the number of events
+    /// handled
+    pub counts: Vec<(&'static str, u64)>,
+}
+
+/// An event that happened at a single point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// The subsystem, one of "lower", "optimize", "eval", or "route"
+    pub target: &'static str,
+    pub name: &'static str,
+    /// The index of the span this happened within
+    pub span: Option<usize>,
+    /// The time from the start of the recording
+    pub time: Duration,
+    pub fields: Vec<(&'static str, u64)>,
+}
+
+/// Closes a span when dropped, returned by `trace_span`
+#[derive(Debug)]
+pub(crate) struct TraceGuard {
+    /// The index of the span, `None` if nothing is being recorded
+    span: Option<usize>,
+}
+
+impl TraceGuard {
+    /// Adds `x` to the count named `key` of the span
+    pub fn count(&self, key: &'static str, x: u64) {
+        if let Some(span) = self.span {
+            CURRENT_TRACE.with(|recording| {
+                if let Some(ref mut recording) = *recording.borrow_mut() {
+                    let counts = &mut recording.trace.spans[span].counts;
+                    if let Some((_, count)) = counts.iter_mut().find(|(k, _)| *k == key) {
+                        *count = count.saturating_add(x);
+                    } else {
+                        counts.push((key, x));
+                    }
+                }
+            })
+        }
+    }
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        if let Some(span) = self.span {
+            CURRENT_TRACE.with(|recording| {
+                if let Some(ref mut recording) = *recording.borrow_mut() {
+                    // spans are closed in order because the guards are scoped
+                    if recording.stack.last() == Some(&span) {
+                        recording.stack.pop();
+                        let span = &mut recording.trace.spans[span];
+                        span.duration = recording.start.elapsed().saturating_sub(span.start);
+                    }
+                }
+            })
+        }
+    }
+}
+
+/// Enters a span that is closed when the returned guard is dropped, if a
+/// [Trace] is being recorded
+pub(crate) fn trace_span(target: &'static str, name: &'static str) -> TraceGuard {
+    let span = CURRENT_TRACE.with(|recording| {
+        if let Some(ref mut recording) = *recording.borrow_mut() {
+            let i = recording.trace.spans.len();
+            recording.trace.spans.push(TraceSpan {
+                target,
+                name,
+                parent: recording.stack.last().copied(),
+                start: recording.start.elapsed(),
+                duration: Duration::ZERO,
+                counts: vec![],
+            });
+            recording.stack.push(i);
+            Some(i)
+        } else {
+            None
+        }
+    });
+    TraceGuard { span }
+}
+
+/// Records an event if a [Trace] is being recorded
+pub(crate) fn trace_event(
+    target: &'static str,
+    name: &'static str,
+    fields: &[(&'static str, u64)],
+) {
+    CURRENT_TRACE.with(|recording| {
+        if let Some(ref mut recording) = *recording.borrow_mut() {
+            let event = TraceEvent {
+                target,
+                name,
+                span: recording.stack.last().copied(),
+                time: recording.start.elapsed(),
+                fields: fields.to_vec(),
+            };
+            recording.trace.events.push(event);
+        }
+    })
+}
+
+/// A record of spans and events in lowering, optimization, evaluation, and
+/// routing, with counts and durations. Requires the `trace` feature.
+///
+/// Every span and event has a target naming its subsystem, which can be used
+/// for filtering, e.g. [Trace::filter] with "route" gives only the router
+/// iterations. [Trace::folded] gives the collapsed stack format that
+/// flamegraph tools such as `inferno` accept.
+///
+/// ```
+/// use starlight::{dag::*, utils::Trace, Epoch, EvalAwi, LazyAwi};
+///
+/// let epoch = Epoch::new();
+/// let x = LazyAwi::opaque(bw(8));
+/// let mut y = awi!(x);
+/// y.add_(&x).unwrap();
+/// let y = EvalAwi::from(&y);
+/// let ((), trace) = Trace::record(|| {
+///     epoch.optimize().unwrap();
+///     x.retro_u8_(3).unwrap();
+///     assert_eq!(y.eval_u8().unwrap(), 6);
+/// });
+/// assert!(trace.spans().iter().any(|span| span.target == "lower"));
+/// assert!(trace.total_count("optimize", "lnodes_before") > 0);
+/// let eval = trace.filter("eval");
+/// assert!(eval.spans().iter().all(|span| span.target == "eval"));
+/// assert!(trace.folded().contains("optimize::Ensemble::optimize_all"));
+/// println!("{trace}");
+/// drop(epoch);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Trace {
+    spans: Vec<TraceSpan>,
+    events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Runs `f` while recording the spans and events of everything that it
+    /// does on the current thread. Recordings can be nested, the outer
+    /// recording does not see what the inner one records.
+    pub fn record<O, F: FnOnce() -> O>(f: F) -> (O, Self) {
+        let guard = RecordingGuard(CURRENT_TRACE.with(|recording| {
+            recording.replace(Some(Recording {
+                trace: Self::default(),
+                start: Instant::now(),
+                stack: vec![],
+            }))
+        }));
+        let res = f();
+        let recording = CURRENT_TRACE.with(|recording| recording.take()).unwrap();
+        drop(guard);
+        (res, recording.trace)
+    }
+
+    /// Returns the spans in the order they were entered
+    pub fn spans(&self) -> &[TraceSpan] {
+        &self.spans
+    }
+
+    /// Returns the events in the order they happened
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    /// Returns only the spans and events with the given target. The parents of
+    /// spans and the spans of events are reindexed, becoming `None` if they
+    /// were filtered out.
+    pub fn filter(&self, target: &str) -> Self {
+        let mut reindex = HashMap::new();
+        let mut res = Self::default();
+        for (i, span) in self.spans.iter().enumerate() {
+            if span.target == target {
+                reindex.insert(i, res.spans.len());
+                let mut span = span.clone();
+                span.parent = span.parent.and_then(|p| reindex.get(&p).copied());
+                res.spans.push(span);
+            }
+        }
+        for event in &self.events {
+            if event.target == target {
+                let mut event = event.clone();
+                event.span = event.span.and_then(|p| reindex.get(&p).copied());
+                res.events.push(event);
+            }
+        }
+        res
+    }
+
+    /// Returns the sum of the counts named `key` over all spans with the given
+    /// target
+    pub fn total_count(&self, target: &str, key: &str) -> u64 {
+        let mut res = 0u64;
+        for span in &self.spans {
+            if span.target == target {
+                for (k, x) in &span.counts {
+                    if *k == key {
+                        res = res.saturating_add(*x);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the total duration of the outermost spans with the given
+    /// target, not counting spans nested within other spans of the target
+    pub fn total_duration(&self, target: &str) -> Duration {
+        let mut res = Duration::ZERO;
+        for span in &self.spans {
+            if span.target != target {
+                continue
+            }
+            let mut nested = false;
+            let mut parent = span.parent;
+            while let Some(p) = parent {
+                if self.spans[p].target == target {
+                    nested = true;
+                    break
+                }
+                parent = self.spans[p].parent;
+            }
+            if !nested {
+                res = res.saturating_add(span.duration);
+            }
+        }
+        res
+    }
+
+    /// Returns the spans in the collapsed stack format used by flamegraph
+    /// tools, one line per unique stack with the self time in microseconds
+    pub fn folded(&self) -> String {
+        let mut self_times: Vec<Duration> = self.spans.iter().map(|span| span.duration).collect();
+        for span in &self.spans {
+            if let Some(p) = span.parent {
+                self_times[p] = self_times[p].saturating_sub(span.duration);
+            }
+        }
+        let mut stacks: Vec<(String, u128)> = vec![];
+        let mut stack_indexes: HashMap<String, usize> = HashMap::new();
+        for i in 0..self.spans.len() {
+            let mut frames = vec![];
+            let mut current = Some(i);
+            while let Some(p) = current {
+                frames.push(format!("{}::{}", self.spans[p].target, self.spans[p].name));
+                current = self.spans[p].parent;
+            }
+            frames.reverse();
+            let stack = frames.join(";");
+            let micros = self_times[i].as_micros();
+            if let Some(j) = stack_indexes.get(&stack) {
+                stacks[*j].1 = stacks[*j].1.saturating_add(micros);
+            } else {
+                stack_indexes.insert(stack.clone(), stacks.len());
+                stacks.push((stack, micros));
+            }
+        }
+        let mut s = String::new();
+        for (stack, micros) in stacks {
+            writeln!(s, "{stack} {micros}").unwrap();
+        }
+        s
+    }
+}
+
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &[(&'static str, u64)]) -> fmt::Result {
+    for (key, x) in fields {
+        write!(f, " {key}={x}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Trace {
+    /// Writes a log with a line for every span and event in the order they
+    /// started, indented by nesting
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let depth = |mut parent: Option<usize>| {
+            let mut depth = 0;
+            while let Some(p) = parent {
+                depth += 1;
+                parent = self.spans[p].parent;
+            }
+            depth
+        };
+        let write_event = |f: &mut fmt::Formatter<'_>, event: &TraceEvent| {
+            let indent = 2 * depth(event.span);
+            write!(f, "{:indent$}[{}] {}", "", event.target, event.name)?;
+            write_fields(f, &event.fields)?;
+            writeln!(f)
+        };
+        let mut events = self.events.iter().peekable();
+        for span in &self.spans {
+            while let Some(event) = events.next_if(|event| event.time < span.start) {
+                write_event(f, event)?;
+            }
+            let indent = 2 * depth(span.parent);
+            write!(
+                f,
+                "{:indent$}[{}] {} {:?}",
+                "", span.target, span.name, span.duration
+            )?;
+            write_fields(f, &span.counts)?;
+            writeln!(f)?;
+        }
+        for event in events {
+            write_event(f, event)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+use starlight::{
+    awi, awi::*, dag, ensemble::NodeRef, utils::diff_ensembles, Attribution, Epoch, Error, EvalAwi,
+    LazyAwi, PowerModel,
+};
+
+mod common;
+use common::*;
+
+#[test]
+fn shrink_failure() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(4));
+    {
+        use dag::*;
+        let mut x = Awi::from(&a);
+        x.and_(&b).unwrap();
+        mimick::assert!(x.is_zero() | c.is_zero());
+    }
+    a.retro_u8_(0xff).unwrap();
+    b.retro_u8_(0x81).unwrap();
+    c.retro_(&awi!(0101)).unwrap();
+    let report = epoch.shrink_failure(&[&a, &b, &c]).unwrap();
+    assert_eq!(report.values[0], awi!(0x80u8));
+    assert_eq!(report.values[1], awi!(0x80u8));
+    assert_eq!(report.values[2], awi!(0100));
+    assert!(report.num_attempts > 0);
+    // the inputs are left at the minimized values
+    assert!(epoch.assert_assertions(false).is_err());
+    a.retro_u8_(0x01).unwrap();
+    epoch.assert_assertions(false).unwrap();
+    assert!(epoch.shrink_failure(&[&a]).is_err());
+    drop(epoch);
+}
+
+#[test]
+fn dead_report() {
+    let epoch = Epoch::new();
+    epoch.start_dead_report();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(8));
+    c.set_debug_name("c").unwrap();
+    // accidentally unconnected logic
+    {
+        let mut unused = dag::Awi::from(&a);
+        unused.or_(&b).unwrap();
+    }
+    // unconnected logic fed only by `c`
+    {
+        let mut unused = dag::Awi::from(&c);
+        unused.rotl_(3).unwrap();
+        unused.neg_(true);
+    }
+    // only one bit of this is used
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    let mut x0 = dag::Awi::zero(bw(1));
+    x0.field_from(&x, 0, 1).unwrap();
+    let y = EvalAwi::from(&x0);
+    epoch.prune_unused_states().unwrap();
+    epoch.optimize().unwrap();
+    let report = epoch.stop_dead_report().unwrap();
+    assert!(epoch.stop_dead_report().is_none());
+    assert!(report.total_pruned_states() > 0);
+    assert!(report.total_removed_lnodes() > 0);
+    // the logic is grouped by the `LazyAwi` feeding it
+    let pruned = report.pruned_states();
+    let mut lines = vec![];
+    for (attribution, _) in &pruned {
+        match attribution {
+            Attribution::RNode {
+                debug_name,
+                location,
+            } => {
+                let location = location.unwrap();
+                assert!(location.file.ends_with("analysis.rs"));
+                lines.push((debug_name.clone(), location.line));
+            }
+            // constants used by the unconnected logic
+            Attribution::Unknown => (),
+            Attribution::Location(_) => panic!("{attribution}"),
+        }
+    }
+    lines.sort();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].0, None);
+    assert_eq!(lines[1].0.as_deref(), Some("c"));
+    assert_eq!(lines[0].1 + 2, lines[1].1);
+    assert!(report
+        .removed_lnodes()
+        .iter()
+        .all(|(attribution, _)| matches!(attribution, Attribution::RNode { .. })));
+    let s = report.to_string();
+    assert!(s.contains("\"c\" created at"));
+    {
+        use awi::*;
+        a.retro_(&awi!(0b1u8)).unwrap();
+        b.retro_(&awi!(0b0u8)).unwrap();
+        assert!(y.eval_bool().unwrap());
+    }
+    drop(epoch);
+}
+
+#[test]
+fn ensemble_diff() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    x.not_();
+    x.and_(&a).unwrap();
+    // this is removed by optimization
+    x.or_(&dag::Awi::zero(bw(8))).unwrap();
+    let _x = EvalAwi::from(&x);
+    epoch.lower().unwrap();
+    let lowered = epoch.ensemble(|ensemble| ensemble.clone());
+    // ids do not depend on anything but the content
+    let diff = diff_ensembles(&lowered, &lowered).unwrap();
+    assert!(diff.is_empty());
+    let ids = lowered.stable_ids().unwrap();
+    let counts = ids.lnode_id_counts();
+    assert_eq!(counts.values().sum::<usize>(), lowered.lnodes.len());
+    epoch.optimize().unwrap();
+    let optimized = epoch.ensemble(|ensemble| ensemble.clone());
+    let diff = diff_ensembles(&lowered, &optimized).unwrap();
+    assert!(!diff.is_empty());
+    assert!(!diff.removed_lnodes.is_empty());
+    assert!(optimized.lnodes.len() + diff.removed_lnodes.len() >= lowered.lnodes.len());
+    assert_eq!(
+        lowered.lnodes.len() + diff.added_lnodes.len() - diff.removed_lnodes.len(),
+        optimized.lnodes.len()
+    );
+    assert!(!diff.to_string().is_empty());
+    drop(epoch);
+}
+
+#[test]
+fn provenance() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let b = LazyAwi::opaque(bw(4));
+    let c = LazyAwi::opaque(bw(1));
+    let mut x = dag::Awi::from(&a);
+    x.and_(&b).unwrap();
+    let x = EvalAwi::from(&x);
+    let y = EvalAwi::from(&c);
+    epoch.optimize().unwrap();
+    a.retro_(&awi!(0011)).unwrap();
+    b.retro_(&awi!(1101)).unwrap();
+    c.retro_bool_(true).unwrap();
+    assert!(x.last_eval_support().is_err());
+    epoch.start_provenance();
+    assert_eq!(x.last_eval_support().unwrap(), None);
+    assert_eq!(x.eval().unwrap(), awi!(0001));
+    // where a bit of `a` or `b` is zero it alone determines the result, and
+    // where both are set both are needed
+    let (pa, pb) = (a.p_external(), b.p_external());
+    assert_eq!(x.last_eval_support().unwrap().unwrap(), vec![
+        (pa, 0),
+        (pa, 2),
+        (pa, 3),
+        (pb, 0),
+        (pb, 1)
+    ]);
+    assert!(y.eval_bool().unwrap());
+    assert_eq!(y.last_eval_support().unwrap().unwrap(), vec![(
+        c.p_external(),
+        0
+    )]);
+    assert!(epoch.stop_provenance().is_some());
+    assert!(epoch.stop_provenance().is_none());
+    drop(epoch);
+}
+
+#[test]
+fn estimate_power() {
+    let (epoch, a, b, x) = optimized_xor_rotl_and();
+    epoch.start_profiling();
+    check_xor_rotl_and(&a, &b, &x);
+    let profile = epoch.stop_profiling().unwrap();
+    let mut model = PowerModel {
+        lut_capacitance: 2.0e-15,
+        input_capacitance: 1.0e-15,
+        voltage: 1.0,
+        duration: 4.0e-8,
+    };
+    let report = epoch.estimate_power(&profile, &model).unwrap();
+    assert!(report.total_energy() > 0.0);
+    assert_eq!(report.total_power(), report.total_energy() / model.duration);
+    let by_attribution = report.power_by_attribution();
+    let sum: f64 = by_attribution.iter().map(|(_, p)| *p).sum();
+    assert!((sum - report.total_power()).abs() <= 1.0e-9 * report.total_power());
+    // the states are gone after optimization, but the nets can still be
+    // attributed to the `RNode`s of `xor_rotl_and`
+    assert!(by_attribution.len() > 1);
+    for (attribution, _) in &by_attribution {
+        match attribution {
+            Attribution::RNode { location, .. } => {
+                assert!(location.unwrap().file.ends_with("mod.rs"))
+            }
+            _ => panic!("{attribution}"),
+        }
+    }
+    assert!(report.to_string().contains("mod.rs"));
+    // energy scales with the square of the voltage
+    model.voltage = 2.0;
+    let energy = epoch
+        .estimate_power(&profile, &model)
+        .unwrap()
+        .total_energy();
+    assert!((energy - 4.0 * report.total_energy()).abs() <= 1.0e-9 * energy);
+    model.duration = 0.0;
+    assert!(epoch.estimate_power(&profile, &model).is_err());
+    drop(epoch);
+}
+
+#[test]
+fn report_outputs() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(1));
+    let _unused = LazyAwi::opaque(bw(8));
+    let mut sum = dag::Awi::from(&a);
+    sum.add_(&b).unwrap();
+    let mut reg = sum.clone();
+    starlight::delay(&mut reg, 1);
+    let mut y = dag::Awi::from(&a);
+    y.mux_(&reg, c.to_bool()).unwrap();
+    let sum = EvalAwi::from(&sum);
+    sum.set_debug_name("sum").unwrap();
+    let y = EvalAwi::from(&y);
+    let copy = EvalAwi::from(&a);
+    epoch.optimize().unwrap();
+    let report = epoch.report_outputs().unwrap();
+    assert_eq!(report.outputs.len(), 3);
+
+    let cone = report.get(sum.p_external()).unwrap();
+    assert_eq!(cone.debug_name.as_deref(), Some("sum"));
+    assert_eq!(cone.bw, 8);
+    assert_eq!(cone.inputs, vec![a.p_external(), b.p_external()]);
+    assert_eq!(cone.tnodes, 0);
+    assert!(cone.lnodes > 0);
+    // the carry chain makes the highest bit depend on all lower bits
+    assert!(cone.depth > 1);
+    assert!(cone.depth <= cone.lnodes);
+
+    // the cone goes through the `TNode`s
+    let cone_y = report.get(y.p_external()).unwrap();
+    assert_eq!(cone_y.inputs, vec![
+        a.p_external(),
+        b.p_external(),
+        c.p_external()
+    ]);
+    assert_eq!(cone_y.tnodes, 8);
+    assert!(cone_y.lnodes > cone.lnodes);
+
+    // a direct copy of an input
+    let cone_copy = report.get(copy.p_external()).unwrap();
+    assert_eq!(cone_copy.depth, 0);
+    assert_eq!(cone_copy.tnodes, 0);
+    assert_eq!(cone_copy.inputs, vec![a.p_external()]);
+
+    let s = report.to_string();
+    assert_eq!(s.lines().count(), 3);
+    assert!(s.contains("sum (8 bits): "));
+    drop(epoch);
+}
+
+#[test]
+fn fanin_fanout() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(1));
+    let b = LazyAwi::opaque(bw(1));
+    let c = LazyAwi::opaque(bw(1));
+    let mut x = dag::Awi::from(&a);
+    x.and_(&b).unwrap();
+    let mut y = x.clone();
+    y.xor_(&c).unwrap();
+    let x = EvalAwi::from(&x);
+    let y = EvalAwi::from(&y);
+    // not initialized yet
+    assert!(epoch
+        .ensemble(|ensemble| ensemble.fanout_of(a.p_external(), 0).map(|_| ()))
+        .is_err());
+    epoch.optimize().unwrap();
+    epoch.ensemble(|ensemble| {
+        assert!(ensemble.fanout_of(a.p_external(), 1).is_err());
+        let x_cone: Vec<NodeRef> = ensemble
+            .fanin_cone(x.p_external(), 0, 10)
+            .unwrap()
+            .collect();
+        let p_and = x_cone[0];
+        assert!(matches!(p_and, NodeRef::LNode(_)));
+        assert_eq!(x_cone.len(), 3);
+        assert!(x_cone.contains(&NodeRef::RNode(a.p_external(), 0)));
+        assert!(x_cone.contains(&NodeRef::RNode(b.p_external(), 0)));
+        let fanout: Vec<NodeRef> = ensemble.fanout_of(a.p_external(), 0).unwrap().collect();
+        assert_eq!(fanout, [p_and]);
+        // `x` feeds into the `LNode` of `y`
+        let fanout: Vec<NodeRef> = ensemble.fanout_of(x.p_external(), 0).unwrap().collect();
+        assert_eq!(fanout.len(), 1);
+        let cone: Vec<NodeRef> = ensemble.fanin_cone(y.p_external(), 0, 1).unwrap().collect();
+        assert_eq!(cone.len(), 3);
+        assert!(cone.contains(&NodeRef::RNode(x.p_external(), 0)));
+        assert!(cone.contains(&NodeRef::RNode(c.p_external(), 0)));
+        assert!(!cone.contains(&p_and));
+        let cone: Vec<NodeRef> = ensemble
+            .fanin_cone(y.p_external(), 0, 10)
+            .unwrap()
+            .collect();
+        assert_eq!(cone.len(), 6);
+        assert!(cone.contains(&p_and));
+        assert!(cone.contains(&NodeRef::RNode(a.p_external(), 0)));
+        assert_eq!(
+            ensemble.fanin_cone(y.p_external(), 0, 0).unwrap().count(),
+            0
+        );
+    });
+    drop(epoch);
+}
+
+#[test]
+fn find_input_for() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let b = LazyAwi::opaque(bw(4));
+    let c = LazyAwi::opaque(bw(1));
+    let _unused = LazyAwi::opaque(bw(4));
+    let mut product = dag::Awi::zero(bw(8));
+    product.arb_umul_add_(&dag::Awi::from(&a), &dag::Awi::from(&b));
+    let product = EvalAwi::from(&product);
+    // `c & !c` can never be set
+    let never = EvalAwi::from_bool(c.to_bool() & !c.to_bool());
+    let copy = EvalAwi::from(&a);
+    epoch.optimize().unwrap();
+
+    // factor 35
+    let inputs = epoch
+        .find_input_for(&product, &awi!(35_u8))
+        .unwrap()
+        .unwrap();
+    assert_eq!(inputs.len(), 2);
+    for (p_external, value) in &inputs {
+        if *p_external == a.p_external() {
+            a.retro_(value).unwrap();
+        } else {
+            assert_eq!(*p_external, b.p_external());
+            b.retro_(value).unwrap();
+        }
+    }
+    assert_eq!(product.eval().unwrap(), awi!(35_u8));
+    let a_val = copy.eval().unwrap().to_usize();
+    assert!((a_val == 5) || (a_val == 7));
+
+    // the largest product of 4 bit factors is 225
+    assert!(epoch
+        .find_input_for(&product, &awi!(0xf1_u8))
+        .unwrap()
+        .is_none());
+    assert!(epoch.find_input_for(&never, &awi!(1)).unwrap().is_none());
+    let inputs = epoch.find_input_for(&never, &awi!(0)).unwrap().unwrap();
+    assert_eq!(inputs.len(), 1);
+    assert_eq!(inputs[0].0, c.p_external());
+    assert!(matches!(
+        epoch.find_input_for(&product, &awi!(0_u4)),
+        Err(Error::BitwidthMismatch(8, 4))
+    ));
+    drop(epoch);
+}
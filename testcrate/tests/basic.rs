@@ -199,3 +199,79 @@ fn all_variations() {
 
     drop(epoch);
 }
+
+#[test]
+fn peek_poke() {
+    use dag::*;
+    let epoch = Epoch::new();
+
+    let x = LazyAwi::opaque(bw(8));
+    let mut y = awi!(x);
+    y.neg_(true);
+    let y = EvalAwi::from(&y);
+    x.set_debug_name("x").unwrap();
+    y.set_debug_name("y").unwrap();
+
+    {
+        use awi::*;
+
+        let p_x = epoch.find_external_by_name("x").unwrap();
+        let p_y = epoch.find_external_by_name("y").unwrap();
+        assert_eq!(p_x, x.p_external());
+        assert!(epoch.find_external_by_name("z").is_none());
+        assert_eq!(epoch.peek(p_y).unwrap(), None);
+        epoch.poke(p_x, &awi!(5u8)).unwrap();
+        assert_eq!(epoch.peek(p_x).unwrap(), Some(awi!(5u8)));
+        assert_eq!(epoch.peek(p_y).unwrap(), Some(awi!(-5i8)));
+        // read-only and bitwidth mismatch
+        assert!(epoch.poke(p_y, &awi!(5u8)).is_err());
+        assert!(epoch.poke(p_x, &awi!(5u4)).is_err());
+    }
+    drop(epoch);
+}
+
+#[test]
+fn partial_unknown() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let sel = LazyAwi::opaque(bw(1));
+    let (sum, muxed) = {
+        use dag::*;
+        let mut sum = Awi::from(&a);
+        sum.add_(&b).unwrap();
+        let mut muxed = Awi::from(&a);
+        muxed.mux_(&b, sel.to_bool()).unwrap();
+        (EvalAwi::from(&sum), EvalAwi::from(&muxed))
+    };
+
+    assert!(a.retro_partial_unknown_(&awi!(0_u4), &awi!(0_u8)).is_err());
+    // only the lower nibbles are known, so the carry into the upper nibble is
+    // also known
+    a.retro_partial_unknown_(&awi!(0x0f_u8), &awi!(0x07_u8))
+        .unwrap();
+    b.retro_partial_unknown_(&awi!(0x0f_u8), &awi!(0x05_u8))
+        .unwrap();
+    sel.retro_unknown_().unwrap();
+    assert!(sum.eval().is_err());
+    assert_eq!(sum.eval_partial().unwrap(), (awi!(0x0f_u8), awi!(0x0c_u8)));
+    // both sides of the mux agree on bits 0, 2, and 3
+    let (mask, value) = muxed.eval_partial().unwrap();
+    assert_eq!(mask, awi!(0x0d_u8));
+    assert_eq!(value, awi!(0x05_u8));
+    // a known select does not depend on the unknown side
+    sel.retro_bool_(true).unwrap();
+    a.retro_unknown_().unwrap();
+    b.retro_partial_unknown_(&awi!(0xff_u8), &awi!(0x12_u8))
+        .unwrap();
+    assert_eq!(muxed.eval().unwrap(), awi!(0x12_u8));
+    // fully known and fully unknown masks are the same as `retro_` and
+    // `retro_unknown_`
+    a.retro_partial_unknown_(&awi!(0xff_u8), &awi!(0x30_u8))
+        .unwrap();
+    assert_eq!(sum.eval().unwrap(), awi!(0x42_u8));
+    b.retro_partial_unknown_(&awi!(0_u8), &awi!(0xff_u8))
+        .unwrap();
+    assert!(sum.eval_is_all_unknown().unwrap());
+    drop(epoch);
+}
@@ -0,0 +1,43 @@
+//! Fixtures shared by the test files of individual features
+
+// not every test file uses every fixture
+#![allow(dead_code)]
+
+use starlight::{dag, Epoch, EvalAwi, LazyAwi};
+
+/// Input values that exercise [xor_rotl_and] well
+pub const INPUTS: [(u8, u8); 4] = [(0, 0), (0x5a, 0x33), (0xff, 0x0f), (0x81, 0x7e)];
+
+/// The reference function of [xor_rotl_and]
+pub fn xor_rotl_and_ref(a: u8, b: u8) -> u8 {
+    (a ^ b).rotate_left(3) & a
+}
+
+/// A small design with a few levels of logic in the current `Epoch`, returns
+/// two 8 bit inputs and `((a ^ b).rotl(3)) & a`
+pub fn xor_rotl_and() -> (LazyAwi, LazyAwi, EvalAwi) {
+    let a = LazyAwi::opaque(dag::bw(8));
+    let b = LazyAwi::opaque(dag::bw(8));
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    x.rotl_(3).unwrap();
+    x.and_(&a).unwrap();
+    (a, b, EvalAwi::from(&x))
+}
+
+/// Checks `x` against [xor_rotl_and_ref] for all of [INPUTS]
+pub fn check_xor_rotl_and(a: &LazyAwi, b: &LazyAwi, x: &EvalAwi) {
+    for (va, vb) in INPUTS {
+        a.retro_u8_(va).unwrap();
+        b.retro_u8_(vb).unwrap();
+        assert_eq!(x.eval_u8().unwrap(), xor_rotl_and_ref(va, vb));
+    }
+}
+
+/// Creates an `Epoch` with [xor_rotl_and] and optimizes it
+pub fn optimized_xor_rotl_and() -> (Epoch, LazyAwi, LazyAwi, EvalAwi) {
+    let epoch = Epoch::new();
+    let (a, b, x) = xor_rotl_and();
+    epoch.optimize().unwrap();
+    (epoch, a, b, x)
+}
@@ -181,3 +181,107 @@ fn correspond_inbetween() {
         drop(program_epoch);
     }
 }
+
+#[test]
+fn correspond_bits() {
+    use dag::*;
+    let epoch = Epoch::new();
+
+    let wide = LazyAwi::opaque(bw(8));
+    let lo = LazyAwi::opaque(bw(4));
+    let hi = LazyAwi::opaque(bw(4));
+    let wide_eval = EvalAwi::opaque(bw(8));
+    let narrow_eval = EvalAwi::opaque(bw(2));
+
+    {
+        use awi::*;
+        let mut corresponder = Corresponder::new();
+        corresponder
+            .correspond_lazy_bits(&wide, 0..4, &lo, 0..4)
+            .unwrap();
+        corresponder
+            .correspond_lazy_bits(&hi, 0..4, &wide, 4..8)
+            .unwrap();
+        corresponder
+            .correspond_eval_bits(&wide_eval, 6..8, &narrow_eval, 0..2)
+            .unwrap();
+        assert!(matches!(
+            corresponder.correspond_lazy_bits(&wide, 0..3, &lo, 0..4),
+            Err(Error::BitwidthMismatch(3, 4))
+        ));
+        assert!(matches!(
+            corresponder.correspond_lazy_bits(&wide, 6..10, &lo, 0..4),
+            Err(Error::OtherString(_))
+        ));
+        assert!(matches!(
+            corresponder.correspond_eval_bits(&wide_eval, 0..0, &narrow_eval, 0..0),
+            Err(Error::OtherString(_))
+        ));
+
+        // the ranges are oriented with the queried value first
+        assert_eq!(corresponder.bit_correspondences(wide.p_external()), vec![
+            (0..4, lo.p_external(), 0..4),
+            (4..8, hi.p_external(), 0..4)
+        ]);
+        assert_eq!(corresponder.bit_correspondences(hi.p_external()), vec![(
+            0..4,
+            wide.p_external(),
+            4..8
+        )]);
+        assert_eq!(
+            corresponder.bit_correspondences(narrow_eval.p_external()),
+            vec![(0..2, wide_eval.p_external(), 6..8)]
+        );
+        // bit correspondences are not whole correspondences
+        assert!(corresponder.correspondences(wide.p_external()).is_err());
+    }
+
+    drop(epoch);
+}
+
+#[test]
+fn correspond_packed() {
+    use dag::*;
+    let epoch = Epoch::new();
+
+    let program = LazyAwi::opaque(bw(13));
+    let t0 = LazyAwi::opaque(bw(8));
+    let t1 = LazyAwi::opaque(bw(8));
+    let t2 = LazyAwi::opaque(bw(8));
+    let program_eval = EvalAwi::opaque(bw(4));
+    let target_eval = EvalAwi::opaque(bw(8));
+
+    {
+        use awi::*;
+        let mut corresponder = Corresponder::new();
+        corresponder
+            .correspond_lazy_packed(&program, &[&t0, &t1, &t2])
+            .unwrap();
+        corresponder
+            .correspond_eval_packed(&program_eval, &[&target_eval])
+            .unwrap();
+        // the last target is not needed
+        assert_eq!(
+            corresponder.bit_correspondences(program.p_external()),
+            vec![
+                (0..8, t0.p_external(), 0..8),
+                (8..13, t1.p_external(), 0..5)
+            ]
+        );
+        assert!(corresponder.bit_correspondences(t2.p_external()).is_empty());
+        assert_eq!(
+            corresponder.bit_correspondences(program_eval.p_external()),
+            vec![(0..4, target_eval.p_external(), 0..4)]
+        );
+        assert!(matches!(
+            corresponder.correspond_lazy_packed(&program, &[&t0]),
+            Err(Error::OtherString(_))
+        ));
+        assert!(matches!(
+            corresponder.correspond_lazy_packed::<LazyAwi, LazyAwi>(&program, &[]),
+            Err(Error::OtherString(_))
+        ));
+    }
+
+    drop(epoch);
+}
@@ -1,5 +1,7 @@
+use std::cell::RefCell;
+
 use dag::*;
-use starlight::{awi, dag, Epoch, Error, EvalAwi, LazyAwi, Loop};
+use starlight::{awi, dag, CoSim, Epoch, Error, EvalAwi, LazyAwi, Loop, Phase, Sim};
 
 #[test]
 #[should_panic]
@@ -226,6 +228,119 @@ fn epoch_suspension3() {
     let _epoch1 = epoch1.suspend();
 }
 
+#[test]
+fn epoch_scope() {
+    let epoch0 = Epoch::new();
+    let (lazy0, eval0) = ex();
+    let epoch1 = Epoch::new();
+    let (lazy1, eval1) = epoch0.scope(|_| ex());
+    {
+        use awi::*;
+        // `epoch1` is current
+        assert!(lazy0.retro_(&awi!(01)).is_err());
+        assert!(lazy1.retro_(&awi!(01)).is_err());
+    }
+    epoch0.scope(|epoch| {
+        use awi::*;
+        lazy0.retro_(&awi!(01)).unwrap();
+        lazy1.retro_(&awi!(00)).unwrap();
+        assert_eq!(eval0.eval().unwrap(), awi!(10));
+        assert_eq!(eval1.eval().unwrap(), awi!(01));
+        epoch.assert_assertions(true).unwrap();
+    });
+    let (lazy2, eval2) = ex();
+    let epoch1 = epoch1.suspend();
+    let (epoch1, _) = epoch1.scope(|epoch| {
+        use awi::*;
+        lazy2.retro_(&awi!(01)).unwrap();
+        assert_eq!(eval2.eval().unwrap(), awi!(10));
+        epoch.assert_assertions(true).unwrap();
+    });
+    drop(lazy2);
+    drop(eval2);
+    drop(epoch1);
+    drop(lazy0);
+    drop(eval0);
+    drop(lazy1);
+    drop(eval1);
+    drop(epoch0);
+}
+
+#[test]
+fn epoch_scope_panic() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let epoch0 = Epoch::new();
+    let (lazy0, eval0) = ex();
+    let epoch1 = Epoch::new();
+    assert!(catch_unwind(AssertUnwindSafe(|| epoch0.scope(|_| panic!()))).is_err());
+    {
+        use awi::*;
+        // `epoch1` is still current
+        assert!(lazy0.retro_(&awi!(01)).is_err());
+    }
+    let epoch1 = epoch1.suspend();
+    assert!(catch_unwind(AssertUnwindSafe(|| epoch1.scope(|_| panic!()))).is_err());
+    // `epoch0` is current again
+    {
+        use awi::*;
+        lazy0.retro_(&awi!(01)).unwrap();
+        assert_eq!(eval0.eval().unwrap(), awi!(10));
+    }
+    drop(lazy0);
+    drop(eval0);
+    drop(epoch0);
+}
+
+#[test]
+fn epoch_detached() {
+    let epoch0 = Epoch::new_detached();
+    assert!(epoch0.is_detached());
+    // nothing is current outside of `scope`
+    assert!(matches!(
+        epoch0.optimize(),
+        std::result::Result::Err(Error::NoCurrentlyActiveEpoch)
+    ));
+    let (lazy0, eval0) = epoch0.scope(|_| ex());
+    let epoch1 = Epoch::new();
+    let (lazy1, eval1) = ex();
+    let epoch2 = Epoch::new_detached();
+    let (lazy2, eval2) = epoch2.scope(|_| ex());
+    epoch0.scope(|epoch| {
+        use awi::*;
+        lazy0.retro_(&awi!(01)).unwrap();
+        assert_eq!(eval0.eval().unwrap(), awi!(10));
+        epoch.assert_assertions(true).unwrap();
+    });
+    {
+        use awi::*;
+        // `epoch1` is still current
+        lazy1.retro_(&awi!(00)).unwrap();
+        assert_eq!(eval1.eval().unwrap(), awi!(01));
+        assert!(lazy2.retro_(&awi!(01)).is_err());
+    }
+    // detached `Epoch`s can be dropped in any order, including before the
+    // `Epoch` created after them
+    epoch0.scope(|_| {
+        drop(lazy0);
+        drop(eval0);
+    });
+    drop(epoch0);
+    epoch2.scope(|epoch| {
+        use awi::*;
+        lazy2.retro_(&awi!(00)).unwrap();
+        assert_eq!(eval2.eval().unwrap(), awi!(01));
+        epoch.assert_assertions(true).unwrap();
+        epoch.optimize().unwrap();
+    });
+    drop(lazy1);
+    drop(eval1);
+    drop(epoch1);
+    // dropping the handles outside of their `Epoch` only leaks
+    drop(lazy2);
+    drop(eval2);
+    drop(epoch2);
+}
+
 #[test]
 fn epoch_fallible_inactive_errors() {
     let epoch = Epoch::new();
@@ -245,19 +360,31 @@ fn epoch_fallible_inactive_errors() {
         use awi::*;
         assert!(matches!(
             x.retro_(&awi!(0)),
-            Err(Error::InvalidPExternal(_))
+            Err(Error::HandleFromDifferentEpoch { .. })
         ));
         assert!(matches!(
             x.retro_bool_(false),
-            Err(Error::InvalidPExternal(_))
+            Err(Error::HandleFromDifferentEpoch { .. })
+        ));
+        assert!(matches!(
+            x.retro_u8_(0),
+            Err(Error::HandleFromDifferentEpoch { .. })
+        ));
+        assert!(matches!(
+            y.eval(),
+            Err(Error::HandleFromDifferentEpoch { .. })
+        ));
+        assert!(matches!(
+            y.eval_bool(),
+            Err(Error::HandleFromDifferentEpoch { .. })
+        ));
+        assert!(matches!(
+            y.eval_u8(),
+            Err(Error::HandleFromDifferentEpoch { .. })
         ));
-        assert!(matches!(x.retro_u8_(0), Err(Error::InvalidPExternal(_))));
-        assert!(matches!(y.eval(), Err(Error::InvalidPExternal(_))));
-        assert!(matches!(y.eval_bool(), Err(Error::InvalidPExternal(_))));
-        assert!(matches!(y.eval_u8(), Err(Error::InvalidPExternal(_))));
         assert!(matches!(
             z0.drive_with_delay(&y1, 0),
-            Err(Error::InvalidPExternal(_))
+            Err(Error::HandleFromDifferentEpoch { .. })
         ));
         // this might be an issue, but I think this should be like a normal mimick
         //assert!(matches!(.unwrap(), Err(Error::InvalidPExternal(_))));
@@ -305,3 +432,265 @@ fn epoch_fallible_inactive_errors() {
     }
     drop(epoch);
 }
+
+#[test]
+fn epoch_handle_from_different_epoch() {
+    let epoch0 = Epoch::new();
+    let x = LazyAwi::opaque(bw(8));
+    let y = EvalAwi::from(&x);
+    assert_eq!(x.created_in(), epoch0.info());
+    assert_eq!(y.created_in(), epoch0.info());
+
+    let epoch1 = Epoch::new();
+    assert_ne!(epoch0.info().id, epoch1.info().id);
+    let err = y.eval().unwrap_err();
+    assert_eq!(err, Error::HandleFromDifferentEpoch {
+        created_in: epoch0.info(),
+        current: epoch1.info(),
+    });
+    // both `Epoch`s and where they were created are in the message
+    let msg = format!("{err}");
+    assert!(msg.contains(&format!("`Epoch` {}", epoch0.info().id)));
+    assert!(msg.contains(&format!("`Epoch` {}", epoch1.info().id)));
+    assert!(msg.contains(file!()));
+    {
+        use awi::*;
+        assert!(matches!(
+            x.set_debug_name("x"),
+            Err(Error::HandleFromDifferentEpoch { .. })
+        ));
+        assert!(matches!(
+            x.try_clone(),
+            Err(Error::HandleFromDifferentEpoch { .. })
+        ));
+    }
+    drop(epoch1);
+
+    // usable again once `epoch0` is current
+    x.retro_u8_(5).unwrap();
+    assert_eq!(y.eval_u8().unwrap(), 5);
+    drop(x);
+    drop(y);
+    drop(epoch0);
+}
+
+#[test]
+fn epoch_specialize() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let config = LazyAwi::opaque(bw(1));
+    let mut y = Awi::from(&a);
+    y.add_(&b).unwrap();
+    let mut tmp = Awi::from(&a);
+    tmp.xor_(&b).unwrap();
+    y.mux_(&tmp, config.to_bool()).unwrap();
+    let y = EvalAwi::from(&y);
+    epoch.optimize().unwrap();
+    let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+
+    let sum = epoch
+        .specialize(&[(&config, awi::Awi::from_bool(false))])
+        .unwrap();
+    let xor = epoch
+        .specialize(&[(&config, awi::Awi::from_bool(true))])
+        .unwrap();
+    // the original is unchanged and still current
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.lnodes.len()), num_lnodes);
+    assert!(sum.ensemble(|ensemble| ensemble.lnodes.len()) < num_lnodes);
+    assert!(
+        xor.ensemble(|ensemble| ensemble.lnodes.len())
+            < sum.ensemble(|ensemble| ensemble.lnodes.len())
+    );
+    let (sum, _) = sum.scope(|specialized| {
+        // handles of `epoch` can be used in specializations of it
+        assert_ne!(specialized.info().id, epoch.info().id);
+        assert_eq!(specialized.info().lineage, epoch.info().id);
+        a.retro_u8_(100).unwrap();
+        b.retro_u8_(27).unwrap();
+        assert_eq!(y.eval_u8().unwrap(), 127);
+        // the assigned input is constant in the specialization
+        assert!(config.retro_bool_(true).is_err());
+    });
+    let (xor, _) = xor.scope(|_| {
+        a.retro_u8_(0b1100).unwrap();
+        b.retro_u8_(0b1010).unwrap();
+        assert_eq!(y.eval_u8().unwrap(), 0b0110);
+    });
+    {
+        use awi::*;
+        assert!(matches!(
+            epoch.specialize(&[(&config, awi::Awi::from_u8(0))]),
+            Err(Error::BitwidthMismatch(1, 8))
+        ));
+    }
+    config.retro_bool_(false).unwrap();
+    a.retro_u8_(1).unwrap();
+    b.retro_u8_(2).unwrap();
+    assert_eq!(y.eval_u8().unwrap(), 3);
+    drop(xor);
+    drop(sum);
+    drop(epoch);
+}
+
+/// An accumulator that adds its input every cycle
+fn accumulator() -> (LazyAwi, EvalAwi) {
+    let input = LazyAwi::opaque(bw(8));
+    let acc = Loop::zero(bw(8));
+    let mut next = awi!(acc);
+    next.add_(&input).unwrap();
+    let out = EvalAwi::from(&acc);
+    acc.drive_with_delay(&next, 1).unwrap();
+    (input, out)
+}
+
+#[test]
+fn epoch_cosim() {
+    let reference = Epoch::new_detached();
+    let (ref_in, ref_out) = reference.scope(|_| accumulator());
+    let design = Epoch::new_detached();
+    let (design_in, design_out) = design.scope(|_| accumulator());
+    // the input is zero until the delayed stimulus arrives
+    design.scope(|_| design_in.retro_u8_(0)).unwrap();
+    // the testbench is a normal current `Epoch`
+    let testbench = Epoch::new();
+    let stimulus = {
+        let counter = Loop::zero(bw(8));
+        let mut next = awi!(counter);
+        next.inc_(true);
+        let stimulus = EvalAwi::from(&counter);
+        counter.drive_with_delay(&next, 1).unwrap();
+        stimulus
+    };
+    let wrong_width = LazyAwi::opaque(bw(4));
+
+    let mut cosim = CoSim::new();
+    let r = cosim.add_epoch(&reference);
+    let d = cosim.add_epoch(&design);
+    let t = cosim.add_epoch(&testbench);
+    assert!(cosim.connect(t, &stimulus, 3, &ref_in, 0).is_err());
+    assert!(matches!(
+        cosim.connect(t, &stimulus, t, &wrong_width, 0),
+        std::result::Result::Err(Error::BitwidthMismatch(8, 4))
+    ));
+    cosim.connect(t, &stimulus, r, &ref_in, 0).unwrap();
+    // the design sees the stimulus two cycles late
+    cosim.connect(t, &stimulus, d, &design_in, 2).unwrap();
+    let mut history = vec![];
+    for _ in 0..10 {
+        cosim.step().unwrap();
+        history.push(reference.scope(|_| ref_out.eval_u8()).unwrap());
+    }
+    assert_eq!(cosim.time(), 10);
+    assert_eq!(history[9], 45);
+    cosim.run(2).unwrap();
+    // the design sees the same sequence two cycles late
+    assert_eq!(design.scope(|_| design_out.eval_u8()).unwrap(), history[9]);
+    drop(cosim);
+
+    // zero delay connections that oscillate are detected
+    let inverter = Epoch::new_detached();
+    let (inv_in, inv_out) = inverter.scope(|_| {
+        let input = LazyAwi::opaque(bw(1));
+        let mut x = awi!(input);
+        x.not_();
+        (input, EvalAwi::from(&x))
+    });
+    let (buf_in, buf_out) = {
+        let input = LazyAwi::opaque(bw(1));
+        let out = EvalAwi::from(&input);
+        (input, out)
+    };
+    {
+        use awi::*;
+        buf_in.retro_(&awi!(0)).unwrap();
+    }
+    let mut cosim = CoSim::new();
+    let i = cosim.add_epoch(&inverter);
+    let t = cosim.add_epoch(&testbench);
+    cosim.connect(i, &inv_out, t, &buf_in, 0).unwrap();
+    cosim.connect(t, &buf_out, i, &inv_in, 0).unwrap();
+    assert!(cosim.step().is_err());
+    drop(cosim);
+
+    drop(stimulus);
+    drop(wrong_width);
+    drop(buf_in);
+    drop(buf_out);
+    drop(testbench);
+    reference.scope(|_| {
+        drop(ref_in);
+        drop(ref_out);
+    });
+    design.scope(|_| {
+        drop(design_in);
+        drop(design_out);
+    });
+    inverter.scope(|_| {
+        drop(inv_in);
+        drop(inv_out);
+    });
+}
+
+#[test]
+fn epoch_sim() {
+    use awi::*;
+    let epoch = Epoch::new_detached();
+    let (input, out) = epoch.scope(|_| accumulator());
+    let log = RefCell::new(vec![]);
+    let mut sim = Sim::new(&epoch);
+    assert!(sim.add_phase(Phase::EveryCycle(0), |_| Ok(())).is_err());
+    sim.add_phase(Phase::Init, |time| {
+        log.borrow_mut().push(("init", time, out.eval_u8()?));
+        input.retro_u8_(0)
+    })
+    .unwrap();
+    // the input is the current time
+    sim.add_phase(Phase::EveryCycle(1), |time| {
+        input.retro_u8_(core::primitive::u8::try_from(time).unwrap())
+    })
+    .unwrap();
+    sim.add_phase(Phase::EveryCycle(3), |time| {
+        log.borrow_mut().push(("sample", time, out.eval_u8()?));
+        Ok(())
+    })
+    .unwrap();
+    sim.add_phase(Phase::Final, |time| {
+        log.borrow_mut().push(("final", time, out.eval_u8()?));
+        Ok(())
+    })
+    .unwrap();
+    sim.run(4).unwrap();
+    assert_eq!(sim.time(), 4);
+    sim.run(4).unwrap();
+    assert_eq!(sim.time(), 8);
+    sim.finish().unwrap();
+    // the accumulator at time `t` is the sum of `0..t`
+    assert_eq!(*log.borrow(), vec![
+        ("init", 0, 0),
+        ("sample", 0, 0),
+        ("sample", 3, 3),
+        ("sample", 6, 15),
+        ("final", 8, 28),
+    ]);
+
+    // errors from callbacks stop the simulation
+    let mut sim = Sim::new(&epoch);
+    sim.add_phase(Phase::EveryCycle(2), |time| {
+        if time == 4 {
+            Err(Error::OtherStr("stop"))
+        } else {
+            Ok(())
+        }
+    })
+    .unwrap();
+    assert!(matches!(sim.run(10), Err(Error::OtherStr("stop"))));
+    assert_eq!(sim.time(), 4);
+    drop(sim);
+
+    epoch.scope(|_| {
+        drop(input);
+        drop(out);
+    });
+    drop(epoch);
+}
@@ -0,0 +1,340 @@
+use starlight::{
+    awi,
+    awi::*,
+    dag,
+    ensemble::{CellFunction, CellLibrary, Ensemble},
+    utils::StarRng,
+    Epoch, EvalAwi, LazyAwi, Loop,
+};
+
+#[test]
+fn slir() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    a.set_debug_name("a \"quoted\"").unwrap();
+    let b = LazyAwi::opaque(bw(4));
+    let counter = Loop::zero(bw(4));
+    let mut x = dag::Awi::from(&a);
+    x.add_(&b).unwrap();
+    x.xor_(&counter).unwrap();
+    let mut next = dag::Awi::from(&*counter);
+    next.inc_(true);
+    counter.drive_with_delay(&next, 1).unwrap();
+    let x = EvalAwi::from(&x);
+    x.set_debug_name("x").unwrap();
+    epoch.optimize().unwrap();
+    let ensemble = epoch.clone_ensemble();
+    let ir = ensemble.write_ir();
+    assert!(ir.starts_with("slir 1\n"));
+    assert!(ir.contains("delay 1"));
+    let parsed = Ensemble::parse_ir(&ir).unwrap();
+    assert_eq!(parsed.lnodes.len(), ensemble.lnodes.len());
+    assert_eq!(parsed.tnodes.len(), ensemble.tnodes.len());
+    assert!(parsed.notary.find_by_debug_name("a \"quoted\"").is_some());
+    assert!(parsed.notary.find_by_debug_name("x").is_some());
+    // writing is deterministic and roundtrips
+    assert_eq!(parsed.write_ir(), ir);
+    // comments and blank lines are ignored
+    let commented = ir.replace('\n', " ; comment\n\n");
+    assert_eq!(Ensemble::parse_ir(&commented).unwrap().write_ir(), ir);
+
+    assert!(Ensemble::parse_ir("").is_err());
+    assert!(Ensemble::parse_ir("slir 2").is_err());
+    assert!(Ensemble::parse_ir("slir 1\nequiv e0 unknown\nequiv e0 unknown").is_err());
+    assert!(Ensemble::parse_ir("slir 1\nequiv e0 unknown\nlnode e0 copy e1").is_err());
+    assert!(Ensemble::parse_ir("slir 1\nequiv e0 unknown\nlnode e0 lut 12 e0").is_err());
+    assert!(Ensemble::parse_ir("slir 1\nequiv e0 unknown\nrnode ro 2 e0").is_err());
+    let small = "slir 1\nequiv e0 dynam 1\nequiv e1 unknown\nlnode e1 lut 1 e0\nrnode ro 1 e1 \
+                 \"y\"\nrnode rw 1 uninit\n";
+    assert_eq!(Ensemble::parse_ir(small).unwrap().write_ir(), small);
+    drop(epoch);
+}
+
+#[test]
+fn cli_flows() {
+    use starlight::cli::{self, SimulateStep};
+
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    a.set_debug_name("a").unwrap();
+    let b = LazyAwi::opaque(bw(8));
+    b.set_debug_name("b").unwrap();
+    let mut x = dag::Awi::from(&a);
+    x.add_(&b).unwrap();
+    let mut delayed = x.clone();
+    starlight::delay(&mut delayed, 2);
+    let x = EvalAwi::from(&x);
+    x.set_debug_name("x").unwrap();
+    let delayed = EvalAwi::from(&delayed);
+    delayed.set_debug_name("delayed").unwrap();
+    epoch.lower().unwrap();
+    let ir = epoch.ensemble(|ensemble| ensemble.write_ir());
+    drop(epoch);
+
+    let optimized = cli::optimize_ir(&ir).unwrap();
+    let stats = |ir: &str| cli::export_ir(ir, "stats").unwrap();
+    assert!(stats(&optimized).contains("tnodes: 8\n"));
+    let redundant = "slir 1\nequiv e0 unknown\nequiv e1 unknown\nequiv e2 unknown\nlnode e1 copy \
+                     e0\nlnode e2 lut 2 e1\nrnode rw 1 e0 \"a\"\nrnode ro 1 e2 \"y\"\n";
+    assert_eq!(
+        cli::optimize_ir(redundant).unwrap(),
+        "slir 1\nequiv e0 unknown\nrnode rw 1 e0 \"a\"\nrnode ro 1 e0 \"y\"\n"
+    );
+    assert_eq!(
+        stats(redundant),
+        "rnodes: 2\nlnodes: 2\ntnodes: 0\nequivalences: 3\ndepth: 2\n"
+    );
+    assert_eq!(cli::export_ir(&optimized, "slir").unwrap(), optimized);
+    assert!(cli::export_ir(&optimized, "verilog").is_err());
+
+    let steps = [
+        SimulateStep::Print("x".to_owned()),
+        SimulateStep::Set("a".to_owned(), "0x10".to_owned()),
+        SimulateStep::Set("b".to_owned(), "7".to_owned()),
+        SimulateStep::Print("x".to_owned()),
+        SimulateStep::Print("delayed".to_owned()),
+        SimulateStep::Run(2.into()),
+        SimulateStep::Print("delayed".to_owned()),
+    ];
+    let expected = "x = unknown\nx = 0x17_u8\ndelayed = unknown\ndelayed = 0x17_u8\n";
+    assert_eq!(cli::simulate_ir(&ir, &steps).unwrap(), expected);
+    assert_eq!(cli::simulate_ir(&optimized, &steps).unwrap(), expected);
+    // read-only values, missing names, and bad values
+    for step in [
+        SimulateStep::Set("x".to_owned(), "0".to_owned()),
+        SimulateStep::Set("y".to_owned(), "0".to_owned()),
+        SimulateStep::Set("a".to_owned(), "0x1ff".to_owned()),
+        SimulateStep::Print("y".to_owned()),
+    ] {
+        assert!(cli::simulate_ir(&optimized, &[step]).is_err());
+    }
+
+    // the argument parsing and file handling of the binary
+    let dir = std::env::temp_dir().join(format!("starlight_cli_flows_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+    std::fs::write(path("in.slir"), &ir).unwrap();
+    let run = |args: &[&str]| cli::run(args.iter().map(|s| s.to_string()));
+    assert_eq!(run(&[]).unwrap(), cli::USAGE);
+    assert_eq!(run(&["help"]).unwrap(), cli::USAGE);
+    assert_eq!(
+        run(&["optimize", &path("in.slir"), "-o", &path("out.slir")]).unwrap(),
+        ""
+    );
+    assert_eq!(
+        stats(&std::fs::read_to_string(path("out.slir")).unwrap()),
+        stats(&optimized)
+    );
+    assert_eq!(
+        run(&[
+            "simulate",
+            &path("out.slir"),
+            "--set",
+            "a=0b1",
+            "--set",
+            "b=0o7",
+            "--print",
+            "x"
+        ])
+        .unwrap(),
+        "x = 0x8_u8\n"
+    );
+    assert_eq!(
+        run(&["export", &path("out.slir"), "--format", "stats"]).unwrap(),
+        stats(&optimized)
+    );
+    assert!(run(&["frobnicate", &path("in.slir")]).is_err());
+    assert!(run(&["optimize"]).is_err());
+    assert!(run(&["optimize", &path("in.slir"), "--print", "x"]).is_err());
+    assert!(run(&["simulate", &path("in.slir"), "--set", "a"]).is_err());
+    assert!(run(&["simulate", &path("in.slir"), "--run", "x"]).is_err());
+    assert!(run(&["optimize", &path("missing.slir")]).is_err());
+    assert!(run(&["export", &path("in.slir"), "--format", "svg"]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn blif_export() {
+    use starlight::cli;
+
+    let ir = "slir 1\nequiv e0 unknown\nequiv e1 unknown\nequiv e2 unknown\nequiv e3 \
+              unknown\nequiv e4 const 1\nlnode e2 lut 8 e0 e1\ntnode e3 e2 delay 2\nrnode rw 1 e0 \
+              \"a\"\nrnode rw 1 e1 \"b\"\nrnode ro 2 e3 e4 \"q\"\n";
+    assert_eq!(
+        cli::export_ir(ir, "blif").unwrap(),
+        ".model top\n.inputs a[0] b[0] clk\n.outputs q[0] q[1]\n.names n4\n1\n.names a[0] b[0] \
+         n2\n11 1\n.latch n2 t0_1 re clk 3\n.latch t0_1 n3 re clk 3\n.names n3 q[0]\n1 1\n.names \
+         n4 q[1]\n1 1\n.end\n"
+    );
+    // undriven nets cannot be written
+    let undriven = "slir 1\nequiv e0 unknown\nrnode ro 1 e0 \"y\"\n";
+    assert!(cli::export_ir(undriven, "blif").is_err());
+
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    a.set_debug_name("a").unwrap();
+    let counter = Loop::zero(bw(4));
+    let mut next = dag::Awi::from(counter.as_ref());
+    next.add_(&a).unwrap();
+    let count = EvalAwi::from(counter.as_ref());
+    count.set_debug_name("count").unwrap();
+    counter.drive_with_delay(&next, 1).unwrap();
+    epoch.optimize().unwrap();
+    let blif = epoch.ensemble(|ensemble| ensemble.write_blif("counter", "clock").unwrap());
+    assert!(blif.starts_with(".model counter\n.inputs a[0] a[1] a[2] a[3] clock\n"));
+    assert!(blif.contains("\n.outputs count[0] count[1] count[2] count[3]\n"));
+    assert_eq!(blif.matches(".latch ").count(), 4);
+    assert!(blif.contains(" re clock 0\n"));
+    assert!(blif.ends_with(".end\n"));
+    drop(a);
+    drop(count);
+    drop(epoch);
+}
+
+#[test]
+fn cell_mapping() {
+    let liberty = r#"
+/* a tiny library */
+library (demo) {
+  delay_model : table_lookup;
+  cell (INV_X1) {
+    area : 0.5;
+    pin (A) { direction : input; }
+    pin (Y) { direction : output; function : "A'"; delay : 0.01; }
+  }
+  cell (NAND2_X2) {
+    area : 2.0;
+    pin (A) { direction : input; }
+    pin (B) { direction : input; }
+    pin (Y) { direction : output; function : "!(A & B)"; delay : 0.01; }
+  }
+  cell (NAND2_X1) {
+    area : 1.0;
+    delay : 0.02;
+    pin (A) { direction : input; }
+    pin (B) { direction : input; }
+    pin (Y) { direction : output; function : "(A B)'"; }
+  }
+  cell (NOR2_X1) {
+    area : 1.25;
+    pin (A) { direction : input; }
+    pin (B) { direction : input; }
+    pin (ZN) { direction : output; function : "!(A + B)"; delay : 0.03; }
+  }
+  // not one of the recognized functions
+  cell (AOI21_X1) {
+    area : 1.5;
+    pin (A) { direction : input; }
+    pin (B1) { direction : input; }
+    pin (B2) { direction : input; }
+    pin (ZN) { direction : output; function : "!(A | (B1 & B2))"; }
+  }
+  cell (DFF_X1) {
+    area : 4.5;
+    ff (IQ, IQN) { next_state : "D"; clocked_on : "CK"; }
+    pin (D) { direction : input; }
+    pin (CK) { direction : input; clock : true; }
+    pin (Q) { direction : output; function : "IQ"; delay : 0.08; }
+  }
+}
+"#;
+    let library = CellLibrary::from_liberty(liberty).unwrap();
+    assert_eq!(library.name, "demo");
+    let functions: Vec<_> = library.cells.iter().map(|cell| cell.function).collect();
+    assert_eq!(functions, vec![
+        CellFunction::Inv,
+        CellFunction::Nand2,
+        CellFunction::Nand2,
+        CellFunction::Nor2,
+        CellFunction::Dff
+    ]);
+    assert_eq!(library.smallest(CellFunction::Nand2), Some(2));
+    assert_eq!(library.cells[2].delay, 0.02);
+    assert_eq!(library.cells[4].clock.as_deref(), Some("CK"));
+    assert!(CellLibrary::from_liberty("library (x) { cell (y) { area : big; } ").is_err());
+
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    a.set_debug_name("a").unwrap();
+    let b = LazyAwi::opaque(bw(4));
+    b.set_debug_name("b").unwrap();
+    let counter = Loop::zero(bw(4));
+    let mut next = dag::Awi::from(counter.as_ref());
+    next.add_(&a).unwrap();
+    let count = EvalAwi::from(counter.as_ref());
+    count.set_debug_name("count").unwrap();
+    counter.drive_with_delay(&next, 1).unwrap();
+    let mut y = dag::Awi::from(a.as_ref());
+    y.mul_add_(&b, &a).unwrap();
+    let y = EvalAwi::from(&y);
+    y.set_debug_name("y").unwrap();
+    let netlist = epoch.map_to_cells(&library).unwrap();
+    let report = netlist.report();
+    let counts: Vec<&str> = report
+        .cell_counts
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    assert_eq!(counts, vec!["DFF_X1", "INV_X1", "NAND2_X1", "NOR2_X1"]);
+    assert_eq!(report.cell_counts[0].1, 4);
+    assert!(report.area > 4.0 * 4.5);
+    assert!(report.critical_delay > 0.08);
+    let verilog = netlist.write_verilog("top", "clk");
+    assert!(verilog.starts_with("module top(clk, a, b, count, y);\n  input clk;\n"));
+    assert!(verilog.contains("  DFF_X1 g"));
+    assert!(verilog.contains(".CK(clk), .Q("));
+    assert!(verilog.ends_with("endmodule\n"));
+
+    // simulate the netlist and compare with the `Epoch`
+    let mut vals = vec![false; netlist.nets().len()];
+    vals[1] = true;
+    let mut rng = StarRng::new(0);
+    let mut expected_count = 0u8;
+    for _ in 0..32 {
+        let (a_val, b_val) = (rng.next_u8() & 0xf, rng.next_u8() & 0xf);
+        {
+            use awi::*;
+            let mut v = awi!(0u4);
+            v.u8_(a_val);
+            a.retro_(&v).unwrap();
+            v.u8_(b_val);
+            b.retro_(&v).unwrap();
+        }
+        for j in 0..4 {
+            vals[2 + j] = ((a_val >> j) & 1) != 0;
+            vals[6 + j] = ((b_val >> j) & 1) != 0;
+        }
+        let mut dffs = vec![];
+        for instance in netlist.instances() {
+            let inp = |i: usize| vals[instance.inputs[i]];
+            vals[instance.output] = match library.cells[instance.cell].function {
+                CellFunction::Inv => !inp(0),
+                CellFunction::Nand2 => !(inp(0) && inp(1)),
+                CellFunction::Nor2 => !(inp(0) || inp(1)),
+                CellFunction::Dff => {
+                    dffs.push((instance.output, inp(0)));
+                    continue
+                }
+            };
+        }
+        let output = |i: usize| {
+            let mut res = 0u8;
+            for (j, net) in netlist.outputs()[i].1.iter().enumerate() {
+                res |= u8::from(vals[*net]) << j;
+            }
+            res
+        };
+        assert_eq!(output(0), expected_count);
+        assert_eq!(output(0), count.eval().unwrap().to_u8());
+        assert_eq!(
+            output(1),
+            a_val.wrapping_mul(b_val).wrapping_add(a_val) & 0xf
+        );
+        for (q, d) in dffs {
+            vals[q] = d;
+        }
+        expected_count = expected_count.wrapping_add(a_val) & 0xf;
+        epoch.run(1).unwrap();
+    }
+    drop(epoch);
+}
@@ -0,0 +1,157 @@
+use starlight::{
+    awi::*,
+    dag,
+    ensemble::{Ensemble, LNodeKind, LutTable, PBack, PLNode, Referent},
+    triple_arena::Advancer,
+    Epoch, EvalAwi, LazyAwi,
+};
+
+mod common;
+use common::*;
+
+#[test]
+fn tmr() {
+    let epoch = Epoch::new();
+    let (a, b, x) = xor_rotl_and();
+    // nothing selected
+    assert_eq!(epoch.apply_tmr(&[]).unwrap(), 0);
+    let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    // the whole cone of `x`
+    let triplicated = epoch.apply_tmr(&[&x]).unwrap();
+    assert!(triplicated > 0);
+    assert_eq!(
+        epoch.ensemble(|ensemble| ensemble.lnodes.len()),
+        num_lnodes + 3 * triplicated
+    );
+    epoch.verify_integrity().unwrap();
+    epoch.optimize().unwrap();
+    check_xor_rotl_and(&a, &b, &x);
+    drop(epoch);
+}
+
+/// Inverts the tables of the first `n` replicas feeding each TMR voter,
+/// returning the number of inverted replicas
+fn invert_replicas(ensemble: &mut Ensemble, n: usize) -> usize {
+    let voter = awi!(1110_1000);
+    let mut inverted = 0;
+    let p_lnodes: Vec<PLNode> = ensemble.lnodes.ptrs().collect();
+    for p_lnode in p_lnodes {
+        let LNodeKind::Lut(ref inp, ref table) = ensemble.lnodes[p_lnode].kind else {
+            continue
+        };
+        if (inp.len() != 3) || (**table != voter) {
+            continue
+        }
+        let inp: Vec<PBack> = inp.iter().copied().take(n).collect();
+        for p_inp in inp {
+            let p_equiv = ensemble.backrefs.get_val(p_inp).unwrap().p_self_equiv;
+            let mut adv = ensemble.backrefs.advancer_surject(p_equiv);
+            while let Some(p_ref) = adv.advance(&ensemble.backrefs) {
+                if let Referent::ThisLNode(p_replica) = *ensemble.backrefs.get_key(p_ref).unwrap() {
+                    if let LNodeKind::Lut(_, ref mut table) = ensemble.lnodes[p_replica].kind {
+                        let mut tmp = (**table).clone();
+                        tmp.not_();
+                        *table = LutTable::new(tmp);
+                        inverted += 1;
+                    }
+                }
+            }
+        }
+    }
+    inverted
+}
+
+#[test]
+fn tmr_fault() {
+    let epoch = Epoch::new();
+    let (a, b, x) = xor_rotl_and();
+    assert!(epoch.apply_tmr(&[&x]).unwrap() > 0);
+    epoch.optimize().unwrap();
+    check_xor_rotl_and(&a, &b, &x);
+    let ensemble = epoch.clone_ensemble();
+    let (p_a, p_b, p_x) = (a.p_external(), b.p_external(), x.p_external());
+    drop(a);
+    drop(b);
+    drop(x);
+    drop(epoch);
+    // returns if `x` is correct for all inputs with `n` faulty replicas per voter
+    let check_with_faults = |n: usize| {
+        let mut ensemble = ensemble.clone();
+        assert!(invert_replicas(&mut ensemble, n) >= 8 * n);
+        let epoch = Epoch::from_ensemble(ensemble);
+        let correct = INPUTS.iter().all(|(va, vb)| {
+            epoch.poke(p_a, &Awi::from_u8(*va)).unwrap();
+            epoch.poke(p_b, &Awi::from_u8(*vb)).unwrap();
+            epoch.peek(p_x).unwrap().unwrap().to_u8() == xor_rotl_and_ref(*va, *vb)
+        });
+        drop(epoch);
+        correct
+    };
+    // a single faulty replica is outvoted
+    assert!(check_with_faults(1));
+    // sanity check that the faults are effective when they are not outvoted
+    assert!(!check_with_faults(2));
+}
+
+#[test]
+fn lock_logic() {
+    let epoch = Epoch::new();
+    let (a, b, x) = xor_rotl_and();
+    let key = LazyAwi::opaque(bw(16));
+    assert!(epoch.lock_logic(&key, 1.5).is_err());
+    let correct_key = epoch.lock_logic(&key, 1.0).unwrap();
+    epoch.verify_integrity().unwrap();
+    epoch.optimize().unwrap();
+    key.retro_(&correct_key).unwrap();
+    check_xor_rotl_and(&a, &b, &x);
+    // an incorrect key should corrupt the output
+    let mut wrong_key = correct_key.clone();
+    wrong_key.not_();
+    key.retro_(&wrong_key).unwrap();
+    assert!(INPUTS.iter().any(|(va, vb)| {
+        a.retro_u8_(*va).unwrap();
+        b.retro_u8_(*vb).unwrap();
+        x.eval_u8().unwrap() != xor_rotl_and_ref(*va, *vb)
+    }));
+    drop(epoch);
+}
+
+#[test]
+fn information_flow() {
+    let epoch = Epoch::new();
+    let secret = LazyAwi::opaque(bw(4));
+    let public = LazyAwi::opaque(bw(4));
+    let mut leaked = dag::Awi::from(&public);
+    leaked.xor_(&secret).unwrap();
+    let leaked = EvalAwi::from(&leaked);
+    // only whether the secret is zero is allowed to be revealed
+    let is_zero = dag::Awi::from_bool(secret.is_zero());
+    let declassifier = EvalAwi::from(&is_zero);
+    let mut masked = dag::Awi::from(&public);
+    masked
+        .mux_(&dag::Awi::zero(bw(4)), is_zero.to_bool())
+        .unwrap();
+    let masked = EvalAwi::from(&masked);
+    let mut unrelated = dag::Awi::from(&public);
+    unrelated.inc_(true);
+    let unrelated = EvalAwi::from(&unrelated);
+
+    let report = epoch
+        .check_information_flow(&[&secret], &[&masked, &unrelated], &[&declassifier])
+        .unwrap();
+    assert!(report.is_secure());
+    let report = epoch
+        .check_information_flow(&[&secret], &[&masked], &[])
+        .unwrap();
+    assert_eq!(report.violations.len(), 4);
+    let report = epoch
+        .check_information_flow(&[&secret], &[&leaked, &unrelated], &[&declassifier])
+        .unwrap();
+    assert_eq!(report.violations.len(), 4);
+    for (bit_i, violation) in report.violations.iter().enumerate() {
+        assert_eq!(violation.secret, (secret.p_external(), bit_i));
+        assert_eq!(violation.observable, (leaked.p_external(), bit_i));
+        assert!(violation.path.len() >= 2);
+    }
+    drop(epoch);
+}
@@ -0,0 +1,137 @@
+use starlight::{
+    awi::*,
+    awint_dag::{smallvec::smallvec, Op},
+    dag,
+    interp::eval_op,
+    utils::StarRng,
+    Epoch, Error, EvalAwi, LazyAwi,
+};
+
+#[test]
+fn interp_basic() {
+    assert_eq!(
+        eval_op(&Op::Add([awi!(5u8), awi!(3u8)]), bw(8)).unwrap(),
+        awi!(8u8)
+    );
+    assert_eq!(
+        eval_op(&Op::Rotl([awi!(0x81u8), Awi::from_usize(1)]), bw(8)).unwrap(),
+        awi!(0x03u8)
+    );
+    assert_eq!(
+        eval_op(&Op::Ult([awi!(5u8), awi!(3u8)]), bw(1)).unwrap(),
+        awi!(0)
+    );
+    assert_eq!(
+        eval_op(&Op::Mux([awi!(1u8), awi!(2u8), awi!(1)]), bw(8)).unwrap(),
+        awi!(2u8)
+    );
+    assert_eq!(
+        eval_op(&Op::UQuo([awi!(100u8), awi!(7u8)]), bw(8)).unwrap(),
+        awi!(14u8)
+    );
+    // invalid shift amount
+    assert!(eval_op(&Op::Shl([awi!(1u8), Awi::from_usize(8)]), bw(8)).is_err());
+    // division by zero
+    assert!(eval_op(&Op::URem([awi!(1u8), awi!(0u8)]), bw(8)).is_err());
+    assert_eq!(
+        eval_op(&Op::Opaque(smallvec![], None), bw(8)),
+        Err(Error::Unevaluatable)
+    );
+}
+
+#[test]
+fn interp_cross_check() {
+    // the interpreter agrees with lowering and `LNode` evaluation on random
+    // operands
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(16));
+    let s = LazyAwi::opaque(bw(3));
+    let start = LazyAwi::opaque(bw(5));
+    let end = LazyAwi::opaque(bw(5));
+    let y = LazyAwi::opaque(bw(8));
+    let (funnel, ranges, is, overflows) = {
+        use dag::*;
+        let mut funnel = Awi::zero(bw(8));
+        funnel.funnel_(&x, &s).unwrap();
+        let (start, end) = (Awi::from(&start).to_usize(), Awi::from(&end).to_usize());
+        let mut or = Awi::from(&x);
+        or.range_or_(start..end).unwrap();
+        let mut and = Awi::from(&x);
+        and.range_and_(start..end).unwrap();
+        let mut xor = Awi::from(&x);
+        xor.range_xor_(start..end).unwrap();
+        let y = Awi::from(&y);
+        let is = [
+            y.is_zero(),
+            y.is_umax(),
+            y.is_imax(),
+            y.is_imin(),
+            y.is_uone(),
+        ];
+        let mut tmp = Awi::zero(bw(5));
+        let overflows = [tmp.zero_resize_(&y), tmp.sign_resize_(&y)];
+        (
+            EvalAwi::from(&funnel),
+            [or, and, xor].map(|x| EvalAwi::from(&x)),
+            is.map(EvalAwi::from_bool),
+            overflows.map(EvalAwi::from_bool),
+        )
+    };
+    epoch.optimize().unwrap();
+
+    let mut rng = StarRng::new(0);
+    let specials = [0u8, 1, 0x7f, 0x80, 0xff];
+    for _ in 0..64 {
+        let xv = Awi::from_u16(rng.next_u16());
+        let mut sv = Awi::zero(bw(3));
+        sv.u8_(rng.next_u8() & 0b111);
+        let en = rng.index(17).unwrap();
+        let st = rng.index(en + 1).unwrap();
+        let mut stv = Awi::zero(bw(5));
+        stv.usize_(st);
+        let mut env = Awi::zero(bw(5));
+        env.usize_(en);
+        let yv = if rng.next_bool() {
+            Awi::from_u8(specials[rng.index(specials.len()).unwrap()])
+        } else {
+            Awi::from_u8(rng.next_u8())
+        };
+        x.retro_(&xv).unwrap();
+        s.retro_(&sv).unwrap();
+        start.retro_(&stv).unwrap();
+        end.retro_(&env).unwrap();
+        y.retro_(&yv).unwrap();
+
+        assert_eq!(
+            funnel.eval().unwrap(),
+            eval_op(&Op::Funnel([xv.clone(), sv]), bw(8)).unwrap()
+        );
+        let (st, en) = (Awi::from_usize(st), Awi::from_usize(en));
+        let range_ops = [
+            Op::RangeOr([xv.clone(), st.clone(), en.clone()]),
+            Op::RangeAnd([xv.clone(), st.clone(), en.clone()]),
+            Op::RangeXor([xv.clone(), st, en]),
+        ];
+        for (eval, op) in ranges.iter().zip(range_ops.iter()) {
+            assert_eq!(eval.eval().unwrap(), eval_op(op, bw(16)).unwrap());
+        }
+        let is_ops = [
+            Op::IsZero([yv.clone()]),
+            Op::IsUmax([yv.clone()]),
+            Op::IsImax([yv.clone()]),
+            Op::IsImin([yv.clone()]),
+            Op::IsUone([yv.clone()]),
+        ];
+        for (eval, op) in is.iter().zip(is_ops.iter()) {
+            assert_eq!(eval.eval().unwrap(), eval_op(op, bw(1)).unwrap());
+        }
+        let overflow_ops = [
+            Op::ZeroResizeOverflow([yv.clone()], bw(5)),
+            Op::SignResizeOverflow([yv], bw(5)),
+        ];
+        for (eval, op) in overflows.iter().zip(overflow_ops.iter()) {
+            assert_eq!(eval.eval().unwrap(), eval_op(op, bw(1)).unwrap());
+        }
+    }
+    drop(epoch);
+}
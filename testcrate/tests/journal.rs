@@ -0,0 +1,233 @@
+use starlight::{
+    awi::*,
+    awint_dag::{Lineage, Op},
+    dag,
+    ensemble::Ensemble,
+    lower::LutDecomposition,
+    utils::StarRng,
+    Epoch, EvalAwi, LazyAwi, ReplayLog,
+};
+
+#[test]
+fn replay_log() {
+    let epoch = Epoch::new();
+    epoch.start_replay_recording();
+    let x = LazyAwi::opaque(bw(8));
+    let s = LazyAwi::opaque(bw(3));
+    let (y, z, p_y, p_z) = {
+        use dag::*;
+        let mut y = awi!(x);
+        let s = Awi::from(&s);
+        y.rotl_(s.to_usize()).unwrap();
+        y.add_(&awi!(0x12u8)).unwrap();
+        let mut z = awi!(0u4);
+        z.field_from(&y, 2, 4).unwrap();
+        let mut inx = awi!(0u2);
+        inx.resize_(&s, false);
+        z.lut_(&awi!(0x5c3a_u16), &inx).unwrap();
+        let p_y = y.state();
+        let p_z = z.state();
+        (EvalAwi::from(&y), EvalAwi::from(&z), p_y, p_z)
+    };
+    let log = epoch.stop_replay_recording().unwrap();
+    assert!(!log.is_empty());
+    assert!(epoch.stop_replay_recording().is_none());
+    let index_of = |p_state| {
+        log.entries
+            .iter()
+            .rposition(|entry| entry.p_state == p_state)
+            .unwrap()
+    };
+    let (y_i, z_i) = (index_of(p_y), index_of(p_z));
+    let mut rng = StarRng::new(0);
+    let inputs: Vec<(u8, u8)> = (0..16).map(|_| (rng.next_u8(), rng.next_u8())).collect();
+    let mut vs_awi = Awi::zero(bw(3));
+    let mut expected = vec![];
+    for (vx, vs) in inputs.iter().copied() {
+        x.retro_u8_(vx).unwrap();
+        vs_awi.u8_(vs);
+        s.retro_(&vs_awi).unwrap();
+        expected.push((y.eval().unwrap(), z.eval().unwrap()));
+    }
+    drop(y);
+    drop(z);
+    drop(x);
+    drop(s);
+    drop(epoch);
+
+    // the text form round trips
+    let text = log.to_string();
+    assert_eq!(text.lines().count(), 1 + log.len() + log.roots.len());
+    let parsed = ReplayLog::parse(&text).unwrap();
+    assert_eq!(parsed.len(), log.len());
+    assert_eq!(parsed.roots.len(), log.roots.len());
+    assert_eq!(parsed.to_string(), text);
+    assert!(ReplayLog::parse("").is_err());
+    assert!(ReplayLog::parse("slreplay 1\ns0 8 not s0").is_err());
+    let delayed = "slreplay 1\ns0 4 opaque \"LazyOpaque\"\ns1 2 arg 0x3_u2\ns2 4 opaque s0 s1 \
+                   \"starlight::delay\"\n";
+    assert_eq!(ReplayLog::parse(delayed).unwrap().to_string(), delayed);
+
+    // the replayed design evaluates the same and survives optimization
+    let epoch = Epoch::new();
+    let replayed = epoch.replay(&parsed).unwrap();
+    assert_eq!(replayed.p_states.len(), log.len());
+    epoch.ensemble(|ensemble| {
+        for (entry, p_state) in log.entries.iter().zip(replayed.p_states.iter()) {
+            let state = &ensemble.stator.states[*p_state];
+            assert_eq!(state.nzbw, entry.nzbw);
+            assert_eq!(state.op.operation_name(), entry.op.operation_name());
+        }
+    });
+    assert_eq!(replayed.inputs.len(), 2);
+    let (p_x, p_s) = (replayed.inputs[0].1, replayed.inputs[1].1);
+    let find_output = |i| replayed.outputs.iter().find(|(j, _)| *j == i).unwrap().1;
+    let (p_y, p_z) = (find_output(y_i), find_output(z_i));
+    epoch.prune_unused_states().unwrap();
+    epoch.optimize().unwrap();
+    for ((vx, vs), (ey, ez)) in inputs.iter().copied().zip(expected.iter()) {
+        vs_awi.u8_(vs);
+        epoch.poke(p_x, &Awi::from_u8(vx)).unwrap();
+        epoch.poke(p_s, &vs_awi).unwrap();
+        assert_eq!(&epoch.peek(p_y).unwrap().unwrap(), ey);
+        assert_eq!(&epoch.peek(p_z).unwrap().unwrap(), ez);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn journal_undo_redo() {
+    // state creation is undone by removing states and redone with translation
+    let mut ensemble = Ensemble::new();
+    ensemble.start_journal();
+    let lit = ensemble.make_state(bw(4), Op::Literal(awi!(0101)), None);
+    let not = ensemble.make_state(bw(4), Op::Not([lit]), None);
+    let _ = ensemble.make_state(bw(4), Op::Not([not]), None);
+    assert_eq!(ensemble.stator.states.len(), 3);
+    assert_eq!(ensemble.undo(2), Ok(2));
+    assert_eq!(ensemble.stator.states.len(), 1);
+    assert_eq!(ensemble.stator.states[lit].rc, 0);
+    assert_eq!(ensemble.redo(5), Ok(2));
+    assert_eq!(ensemble.stator.states.len(), 3);
+    ensemble.verify_integrity().unwrap();
+    assert_eq!(ensemble.undo(5), Ok(3));
+    assert!(ensemble.stator.states.is_empty());
+    assert_eq!(ensemble.redo(1), Ok(1));
+    // a new mutation discards what could have been redone
+    let _ = ensemble.make_state(bw(4), Op::Literal(awi!(1111)), None);
+    assert_eq!(ensemble.redo(1), Ok(0));
+    let journal = ensemble.stop_journal().unwrap();
+    assert_eq!(journal.undo_entries().len(), 2);
+    assert!(journal.redo_entries().is_empty());
+    assert_eq!(ensemble.undo(1), Ok(0));
+
+    // coarse steps and `LNode` changes
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(2));
+    let y = {
+        use dag::*;
+        let mut y = inlawi!(0u1);
+        y.lut_(&inlawi!(1000), &x).unwrap();
+        EvalAwi::from(&y)
+    };
+    epoch.start_journal();
+    let states = epoch.ensemble(|ensemble| ensemble.stator.states.len());
+    epoch.optimize().unwrap();
+    epoch.verify_integrity().unwrap();
+    let lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    assert!(states > 0);
+    assert!(lnodes > 0);
+    x.retro_(&awi!(11)).unwrap();
+    assert!(y.eval_bool().unwrap());
+    // xor
+    epoch.replace_lut(y.p_external(), 0, &awi!(0110)).unwrap();
+    assert!(!y.eval_bool().unwrap());
+    let names: Vec<String> = epoch
+        .stop_journal()
+        .unwrap()
+        .undo_entries()
+        .iter()
+        .map(|entry| entry.to_string())
+        .collect();
+    assert_eq!(names[..2], ["lower", "optimize_all"]);
+    assert!(names[2].starts_with("replace_lut"));
+    assert_eq!(names.len(), 3);
+
+    epoch.start_journal();
+    epoch.replace_lut(y.p_external(), 0, &awi!(0001)).unwrap();
+    assert!(!y.eval_bool().unwrap());
+    assert_eq!(epoch.undo(1), Ok(1));
+    assert!(!y.eval_bool().unwrap());
+    x.retro_(&awi!(01)).unwrap();
+    assert!(y.eval_bool().unwrap());
+    assert_eq!(epoch.redo(1), Ok(1));
+    assert!(!y.eval_bool().unwrap());
+    drop(epoch);
+
+    // undoing optimization restores the states
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(8));
+    let y = {
+        use dag::*;
+        let mut y = awi!(x);
+        y.rotl_(3).unwrap();
+        y.add_(&x).unwrap();
+        EvalAwi::from(&y)
+    };
+    epoch.start_journal();
+    let states = epoch.ensemble(|ensemble| ensemble.stator.states.len());
+    epoch.optimize().unwrap();
+    let lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.stator.states.len()), 0);
+    assert_eq!(epoch.undo(2), Ok(2));
+    epoch.verify_integrity().unwrap();
+    assert_eq!(
+        epoch.ensemble(|ensemble| (ensemble.stator.states.len(), ensemble.lnodes.len())),
+        (states, 0)
+    );
+    x.retro_(&awi!(0x21u8)).unwrap();
+    assert_eq!(y.eval().unwrap(), awi!(0x2au8));
+    // the on demand lowering by `eval` is discarded by redoing
+    assert_eq!(epoch.redo(3), Ok(2));
+    epoch.verify_integrity().unwrap();
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.lnodes.len()), lnodes);
+    x.retro_(&awi!(0x21u8)).unwrap();
+    assert_eq!(y.eval().unwrap(), awi!(0x2au8));
+    drop(epoch);
+}
+
+#[test]
+fn journal_lnode_step() {
+    // steps that only change `LNode`s leave the states alone
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(5));
+    let y = {
+        use dag::*;
+        let mut y = inlawi!(0u1);
+        y.lut_(&awi!(0xdead_beef_u32), &x).unwrap();
+        EvalAwi::from(&y)
+    };
+    epoch.lower().unwrap();
+    let counts =
+        || epoch.ensemble(|ensemble| (ensemble.stator.states.len(), ensemble.lnodes.len()));
+    let before = counts();
+    epoch.start_journal();
+    assert!(epoch.decompose_luts(2, LutDecomposition::Shannon).unwrap() > 0);
+    epoch.verify_integrity().unwrap();
+    assert_eq!(counts().0, before.0);
+    assert_ne!(counts().1, before.1);
+    let after = counts();
+    assert_eq!(epoch.undo(1), Ok(1));
+    epoch.verify_integrity().unwrap();
+    assert_eq!(counts(), before);
+    let mut inx = Awi::zero(bw(5));
+    for i in 0..32u8 {
+        inx.u8_(i);
+        x.retro_(&inx).unwrap();
+        assert_eq!(y.eval_bool().unwrap(), (0xdead_beef_u32 >> i) & 1 == 1);
+    }
+    assert_eq!(epoch.redo(1), Ok(1));
+    epoch.verify_integrity().unwrap();
+    assert_eq!(counts(), after);
+    drop(epoch);
+}
@@ -0,0 +1,176 @@
+use starlight::{
+    awi,
+    awi::*,
+    awint_dag::{smallvec::smallvec, Op, PState},
+    dag,
+    ensemble::{Ensemble, PtrRequirements, U32_PTR_CAPACITY},
+    Epoch, Error, EvalAwi, LazyAwi, Limits, Loop,
+};
+
+#[test]
+fn resource_limits() {
+    let epoch = Epoch::new();
+    epoch.set_limits(Limits {
+        max_states: Some(16),
+        ..Limits::unlimited()
+    });
+
+    let _y = {
+        use dag::*;
+        let x = LazyAwi::opaque(bw(8));
+        let mut y = awi!(x);
+        for _ in 0..32 {
+            y.inc_(true);
+        }
+        EvalAwi::from(&y)
+    };
+    // the states made after the limit was reached share one state per bitwidth,
+    // instead of allocating more for each of the increments
+    let states = epoch.ensemble(|ensemble| ensemble.stator.states.len());
+    assert!(states < 24, "{states}");
+    assert_eq!(
+        epoch.try_lower(),
+        Err(Error::ResourceLimitExceeded("max_states", 16))
+    );
+    assert_eq!(
+        epoch.optimize(),
+        Err(Error::ResourceLimitExceeded("max_states", 16))
+    );
+    drop(epoch);
+}
+
+#[test]
+fn ptr_requirements() {
+    let epoch = Epoch::new();
+    let _y = {
+        use dag::*;
+        let x = LazyAwi::opaque(bw(16));
+        let mut y = awi!(x);
+        y.add_(&awi!(0x1234u16)).unwrap();
+        y.mul_add_(&x, &x).unwrap();
+        y.rotl_(x.to_usize()).unwrap();
+        EvalAwi::from(&y)
+    };
+    let before = epoch.ensemble(|ensemble| ensemble.estimate_ptr_requirements());
+    assert!(before.states > 0);
+    assert_eq!(before.lnodes, 0);
+    assert!(before.fits(U32_PTR_CAPACITY));
+    epoch.lower().unwrap();
+    let after = epoch.ensemble(|ensemble| ensemble.estimate_ptr_requirements());
+    // the estimate is rough but in the right range
+    assert!(after.backref_keys <= before.estimated_lowered_backref_keys);
+    assert!(before.estimated_lowered_backref_keys <= 4 * after.backref_keys);
+    assert!(after.lnodes > 0);
+    assert!(after.check(U32_PTR_CAPACITY).is_ok());
+
+    // a tiny capacity shows what happens near the limit
+    assert!(!after.fits(after.max_len()));
+    assert_eq!(
+        after.check(after.backref_keys),
+        Err(Error::PtrCapacityExceeded(
+            "backref_keys",
+            after.backref_keys,
+            after.backref_keys
+        ))
+    );
+    let req = PtrRequirements {
+        lnodes: 15,
+        ..after
+    };
+    assert!(req.check(16).is_err());
+    drop(epoch);
+}
+
+#[test]
+fn lower_best_effort() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    // two cones with `Loop`s that are never driven
+    let undriven0 = Loop::zero(bw(4));
+    let mut x = dag::Awi::from(&a);
+    x.add_(&undriven0).unwrap();
+    let _x = EvalAwi::from(&x);
+    let undriven1 = Loop::zero(bw(4));
+    let mut z = dag::Awi::from(&a);
+    z.xor_(&undriven1).unwrap();
+    let _z = EvalAwi::from(&z);
+    // does not depend on the `Loop`s
+    let mut y = dag::Awi::from(&a);
+    y.rotl_(1).unwrap();
+    let y = EvalAwi::from(&y);
+    let report = epoch.lower_best_effort().unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(report.num_failed(), 2);
+    assert!(report.num_lowered() > 0);
+    assert!(report.other_errors().is_empty());
+    assert_eq!(report.errors().len(), 2);
+    assert_ne!(report.errors()[0].0, report.errors()[1].0);
+    for (_, _, err) in report.errors() {
+        assert!(matches!(err, Error::LoweringFailed(..)));
+    }
+    // the unaffected logic still works
+    {
+        use awi::*;
+        a.retro_(&awi!(1001)).unwrap();
+        assert_eq!(y.eval().unwrap(), awi!(0011));
+    }
+    drop(epoch);
+
+    // normal lowering does not add the context
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let undriven = Loop::zero(bw(4));
+    let mut x = dag::Awi::from(&a);
+    x.add_(&undriven).unwrap();
+    let _x = EvalAwi::from(&x);
+    let err = epoch.lower().unwrap_err();
+    assert!(!matches!(err, Error::LoweringFailed(..)));
+    drop(epoch);
+
+    // failed assertions are reported
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    {
+        use dag::*;
+        mimick::assert!(a.is_zero());
+    }
+    a.retro_(&awi!(0110)).unwrap();
+    let report = epoch.lower_best_effort().unwrap();
+    assert!(report.errors().is_empty());
+    assert_eq!(report.other_errors().len(), 1);
+    drop(epoch);
+
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let mut x = dag::Awi::from(&a);
+    x.rotl_(1).unwrap();
+    let _x = EvalAwi::from(&x);
+    let report = epoch.lower_best_effort().unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.num_failed(), 0);
+    drop(epoch);
+}
+
+#[test]
+fn try_lower_bad_op() {
+    // `Op`s with invalid bitwidths are reported instead of panicking
+    let bad_ops: [fn(PState, PState) -> Op<PState>; 5] = [
+        |a, b| Op::Add([a, b]),
+        |a, b| Op::Lut([b, a]),
+        |a, b| Op::Funnel([a, b]),
+        |a, b| Op::Mux([a, b, b]),
+        |a, b| Op::Shl([b, a]),
+    ];
+    for bad_op in bad_ops {
+        let mut ensemble = Ensemble::new();
+        let a = ensemble.make_state(bw(8), Op::Opaque(smallvec![], Some("LazyOpaque")), None);
+        let b = ensemble.make_state(bw(4), Op::Opaque(smallvec![], Some("LazyOpaque")), None);
+        let p_state = ensemble.make_state(bw(8), bad_op(a, b), None);
+        ensemble.stator.states[p_state].inc_extern_rc();
+        ensemble.stator.states_to_lower.push(p_state);
+        let epoch = Epoch::from_ensemble(ensemble);
+        let err = epoch.try_lower().unwrap_err();
+        assert!(matches!(err, Error::LoweringFailed(..)), "{err}");
+        drop(epoch);
+    }
+}
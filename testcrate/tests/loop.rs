@@ -1,6 +1,22 @@
-use std::num::NonZeroUsize;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    num::NonZeroUsize,
+    rc::Rc,
+};
 
-use starlight::{awi, dag, ensemble::Delay, Epoch, EvalAwi, LazyAwi, Loop, Net};
+use starlight::{
+    awi,
+    awint_dag::triple_arena::Advancer,
+    clock_crossing, crossbar, dag,
+    ensemble::{Delay, Ensemble, Induction, LoopKind, Referent, ResetKind, Value},
+    pulse_synchronizer,
+    route::LatencyWrapper,
+    synchronizer,
+    utils::StarRng,
+    Cam, Channel, Csr, CsrAccess, CsrMap, Epoch, Error, EvalAwi, Fifo, LazyAwi, Limits, Loop, Mem,
+    Net, Scope,
+};
 
 // be careful not to change existing tests too much, these test a lot of
 // ordering and nonoptimization cases
@@ -94,6 +110,50 @@ fn loop_zero_delay() {
     drop(epoch);
 }
 
+#[test]
+fn loop_zero_delay_non_convergence() {
+    let epoch = Epoch::new();
+    let enable = LazyAwi::opaque(dag::bw(1));
+    let toggle = {
+        use dag::*;
+        // a toggle without any delay oscillates forever once enabled
+        let toggle = Loop::zero(bw(1));
+        let mut next = awi!(toggle);
+        next.xor_(&enable).unwrap();
+        let toggle_out = EvalAwi::from(&toggle);
+        toggle.drive_with_delay(&next, 0).unwrap();
+        toggle_out
+    };
+    {
+        use awi::*;
+        enable.retro_bool_(false).unwrap();
+        assert_eq!(toggle.eval().unwrap(), awi!(0));
+        epoch.set_limits(Limits {
+            max_zero_delay_events: Some(100),
+            ..Limits::unlimited()
+        });
+        enable.retro_bool_(true).unwrap();
+        let p_tnode = epoch.ensemble(|ensemble| ensemble.tnodes.ptrs().next().unwrap());
+        match toggle.eval() {
+            Err(Error::ZeroDelayNonConvergence {
+                events,
+                equivs,
+                lnodes,
+                tnodes,
+            }) => {
+                assert_eq!(events, 100);
+                assert!(!equivs.is_empty());
+                assert!(!lnodes.is_empty());
+                assert_eq!(tnodes, vec![p_tnode]);
+            }
+            res => panic!("{res:?}"),
+        }
+    }
+    drop(enable);
+    drop(toggle);
+    drop(epoch);
+}
+
 #[test]
 fn loop_invert() {
     use dag::*;
@@ -170,6 +230,59 @@ fn loop_net4() {
     drop(epoch);
 }
 
+#[test]
+fn loop_net_onehot() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let mut net = Net::opaque(bw(4));
+    net.push(&awi!(0xa_u4)).unwrap();
+    net.push(&awi!(0xb_u4)).unwrap();
+    net.push(&awi!(0xc_u4)).unwrap();
+    let val = EvalAwi::from(&net);
+    let onehot = LazyAwi::opaque(bw(3));
+    net.drive_onehot(&onehot).unwrap();
+
+    {
+        use awi::*;
+        onehot.retro_(&awi!(001)).unwrap();
+        assert_eq!(val.eval().unwrap(), awi!(0xa_u4));
+        epoch.assert_assertions(true).unwrap();
+
+        onehot.retro_(&awi!(100)).unwrap();
+        assert_eq!(val.eval().unwrap(), awi!(0xc_u4));
+        epoch.assert_assertions(true).unwrap();
+
+        onehot.retro_(&awi!(010)).unwrap();
+        assert_eq!(val.eval().unwrap(), awi!(0xb_u4));
+        epoch.assert_assertions(true).unwrap();
+
+        // the selected ports are ORed together if `onehot` is not one-hot
+        onehot.retro_(&awi!(000)).unwrap();
+        assert_eq!(val.eval().unwrap(), awi!(0x0_u4));
+        assert!(epoch.assert_assertions(true).is_err());
+
+        onehot.retro_(&awi!(011)).unwrap();
+        assert_eq!(val.eval().unwrap(), awi!(0xb_u4));
+        assert!(epoch.assert_assertions(true).is_err());
+
+        onehot.retro_(&awi!(110)).unwrap();
+        assert_eq!(val.eval().unwrap(), awi!(0xf_u4));
+        assert!(epoch.assert_assertions(true).is_err());
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_net_onehot_mismatch() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let mut net = Net::opaque(bw(4));
+    net.push(&awi!(0xa_u4)).unwrap();
+    net.push(&awi!(0xb_u4)).unwrap();
+    assert!(net.drive_onehot(&awi!(001)).is_err());
+    drop(epoch);
+}
+
 fn exhaustive_net_test(epoch: &Epoch, num_ports: awi::usize, diff: awi::isize) {
     use dag::*;
     let mut net = Net::opaque(bw(5));
@@ -244,3 +357,1569 @@ fn loop_net() {
 
     drop(epoch);
 }
+
+#[test]
+fn loop_channel_fifo() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let valid = LazyAwi::opaque(awi::bw(1));
+    let data = LazyAwi::opaque(bw(8));
+    let consumer_ready = LazyAwi::opaque(bw(1));
+    let ch = Channel::new(&valid, &data).unwrap();
+    let in_ready = EvalAwi::from(ch.ready());
+    let ch = ch
+        .map(|x| {
+            let mut tmp = Awi::from(x);
+            tmp.inc_(true);
+            tmp
+        })
+        .fifo(2)
+        .unwrap();
+    let out_valid = EvalAwi::from(ch.valid());
+    let out_data = EvalAwi::from(ch.data());
+    ch.drive_ready(&consumer_ready).unwrap();
+
+    {
+        use awi::*;
+        let step = |v: bool, d: u8, r: bool| {
+            valid.retro_bool_(v).unwrap();
+            data.retro_u8_(d).unwrap();
+            consumer_ready.retro_bool_(r).unwrap();
+        };
+        // fill the FIFO while the consumer is not ready
+        step(true, 5, false);
+        assert!(in_ready.eval_bool().unwrap());
+        assert!(!out_valid.eval_bool().unwrap());
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        step(true, 7, false);
+        assert!(in_ready.eval_bool().unwrap());
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        // the FIFO is full and stalls
+        step(true, 9, false);
+        assert!(!in_ready.eval_bool().unwrap());
+        assert!(out_valid.eval_bool().unwrap());
+        assert_eq!(out_data.eval_u8().unwrap(), 6);
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        // the producer must keep its data during the stall
+        step(true, 9, true);
+        assert!(in_ready.eval_bool().unwrap());
+        assert_eq!(out_data.eval_u8().unwrap(), 6);
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        step(false, 0, true);
+        assert!(out_valid.eval_bool().unwrap());
+        assert_eq!(out_data.eval_u8().unwrap(), 8);
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        assert!(out_valid.eval_bool().unwrap());
+        assert_eq!(out_data.eval_u8().unwrap(), 10);
+        epoch.run(Delay::from(1)).unwrap();
+        assert!(!out_valid.eval_bool().unwrap());
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_channel_protocol_violation() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let valid = LazyAwi::opaque(awi::bw(1));
+    let data = LazyAwi::opaque(bw(8));
+    let ready = LazyAwi::opaque(bw(1));
+    let ch = Channel::new(&valid, &data).unwrap();
+    ch.drive_ready(&ready).unwrap();
+    assert!(Channel::new(&data, &data).is_err());
+
+    {
+        valid.retro_bool_(true).unwrap();
+        data.retro_u8_(1).unwrap();
+        ready.retro_bool_(false).unwrap();
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        // changing the data during a stall
+        data.retro_u8_(2).unwrap();
+        assert!(epoch.assert_assertions(true).is_err());
+        data.retro_u8_(1).unwrap();
+        epoch.assert_assertions(true).unwrap();
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_channel_fork_join() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let valid = LazyAwi::opaque(awi::bw(1));
+    let data = LazyAwi::opaque(bw(8));
+    let ready0 = LazyAwi::opaque(bw(1));
+    let ready1 = LazyAwi::opaque(bw(1));
+    let ch = Channel::new(&valid, &data).unwrap();
+    let in_ready = EvalAwi::from(ch.ready());
+    let (ch0, ch1) = ch.fork().unwrap();
+    let valid0 = EvalAwi::from(ch0.valid());
+    let valid1 = EvalAwi::from(ch1.valid());
+    ch0.drive_ready(&ready0).unwrap();
+    ch1.drive_ready(&ready1).unwrap();
+
+    // fork and join back together
+    let joined_valid = LazyAwi::opaque(bw(1));
+    let joined_data = LazyAwi::opaque(bw(8));
+    let joined_ready = LazyAwi::opaque(bw(1));
+    let ch = Channel::new(&joined_valid, &joined_data).unwrap();
+    let joined_in_ready = EvalAwi::from(ch.ready());
+    let (ch0, ch1) = ch.fork().unwrap();
+    let ch1 = ch1.map(|x| {
+        let mut tmp = Awi::from(x);
+        tmp.not_();
+        tmp
+    });
+    let ch = ch0.join(ch1).unwrap();
+    assert_eq!(ch.bw(), 16);
+    let out_valid = EvalAwi::from(ch.valid());
+    let out_data = EvalAwi::from(ch.data());
+    ch.drive_ready(&joined_ready).unwrap();
+
+    {
+        valid.retro_bool_(true).unwrap();
+        data.retro_u8_(3).unwrap();
+        ready0.retro_bool_(true).unwrap();
+        ready1.retro_bool_(false).unwrap();
+        joined_valid.retro_bool_(true).unwrap();
+        joined_data.retro_u8_(0x12).unwrap();
+        joined_ready.retro_bool_(false).unwrap();
+        assert!(valid0.eval_bool().unwrap());
+        assert!(valid1.eval_bool().unwrap());
+        assert!(!in_ready.eval_bool().unwrap());
+        assert!(out_valid.eval_bool().unwrap());
+        assert_eq!(out_data.eval().unwrap().to_u16(), 0xed12);
+        assert!(!joined_in_ready.eval_bool().unwrap());
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        // the first consumer has already accepted
+        ready0.retro_bool_(false).unwrap();
+        ready1.retro_bool_(true).unwrap();
+        joined_ready.retro_bool_(true).unwrap();
+        assert!(!valid0.eval_bool().unwrap());
+        assert!(valid1.eval_bool().unwrap());
+        assert!(in_ready.eval_bool().unwrap());
+        assert!(joined_in_ready.eval_bool().unwrap());
+        epoch.assert_assertions(true).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        ready1.retro_bool_(false).unwrap();
+        assert!(valid0.eval_bool().unwrap());
+        assert!(valid1.eval_bool().unwrap());
+        assert!(!in_ready.eval_bool().unwrap());
+        epoch.assert_assertions(true).unwrap();
+    }
+    drop(epoch);
+}
+
+fn fifo_test(rng: &mut StarRng, depth: awi::usize) {
+    use dag::*;
+    let epoch = Epoch::new();
+    let push = LazyAwi::opaque(bw(1));
+    let data = LazyAwi::opaque(bw(8));
+    let pop = LazyAwi::opaque(bw(1));
+    let fifo = Fifo::new(bw(8), depth).unwrap();
+    assert_eq!(fifo.depth(), depth);
+    let len = EvalAwi::from(&fifo.len());
+    let is_empty = EvalAwi::from_bool(fifo.is_empty());
+    let is_full = EvalAwi::from_bool(fifo.is_full());
+    let is_almost_full = EvalAwi::from_bool(fifo.is_almost_full(depth - 1));
+    let is_almost_empty = EvalAwi::from_bool(fifo.is_almost_empty(1));
+    let front = EvalAwi::from(&fifo.front());
+    let gray = fifo.write_pointer_gray().map(|gray| EvalAwi::from(&gray));
+    assert_eq!(gray.is_ok(), depth.is_power_of_two());
+    assert!(Fifo::new(bw(8), 0).is_err());
+    fifo.drive(&push, &data, &pop).unwrap();
+
+    {
+        use awi::*;
+        let mut model = VecDeque::new();
+        let mut prev_gray = 0;
+        for _ in 0..64 {
+            let len_val = model.len();
+            assert_eq!(len.eval().unwrap().to_usize(), len_val);
+            assert_eq!(is_empty.eval_bool().unwrap(), len_val == 0);
+            assert_eq!(is_full.eval_bool().unwrap(), len_val == depth);
+            assert_eq!(is_almost_full.eval_bool().unwrap(), len_val + 1 >= depth);
+            assert_eq!(is_almost_empty.eval_bool().unwrap(), len_val <= 1);
+            if let Some(x) = model.front() {
+                assert_eq!(front.eval_u8().unwrap(), *x);
+            }
+            if let Ok(ref gray) = gray {
+                // only a single bit changes at a time
+                let gray = gray.eval().unwrap().to_usize();
+                assert!((gray ^ prev_gray).count_ones() <= 1);
+                prev_gray = gray;
+            }
+            let do_push = rng.next_bool();
+            let do_pop = rng.next_bool();
+            let x = rng.next_u8();
+            push.retro_bool_(do_push).unwrap();
+            data.retro_u8_(x).unwrap();
+            pop.retro_bool_(do_pop).unwrap();
+            let full = model.len() == depth;
+            if do_pop {
+                model.pop_front();
+            }
+            if do_push && !full {
+                model.push_back(x);
+            }
+            epoch.run(Delay::from(1)).unwrap();
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_fifo() {
+    let mut rng = StarRng::new(0);
+    for depth in 1..=5 {
+        fifo_test(&mut rng, depth);
+    }
+}
+
+fn cam_test(rng: &mut StarRng, depth: awi::usize) {
+    use dag::*;
+    let epoch = Epoch::new();
+    let write = LazyAwi::opaque(bw(1));
+    let data = LazyAwi::opaque(bw(4));
+    let valid = LazyAwi::opaque(bw(1));
+    let key = LazyAwi::opaque(bw(4));
+    let cam = Cam::new(bw(4), depth).unwrap();
+    assert_eq!(cam.depth(), depth);
+    let index_w = cam.index_nzbw();
+    let index = LazyAwi::opaque(index_w);
+    let matches = EvalAwi::from(&cam.matches(&key).unwrap());
+    let (found, hit) = cam.lookup(&key).unwrap();
+    let found = EvalAwi::from(&found);
+    let hit = EvalAwi::from_bool(hit);
+    assert!(Cam::new(bw(4), 0).is_err());
+    assert!(cam.matches(&write).is_err());
+    cam.drive(&write, &index, &data, &valid).unwrap();
+
+    {
+        use awi::*;
+        let mut model: Vec<Option<u8>> = vec![None; depth];
+        for _ in 0..64 {
+            // small values so that matches and duplicates are common
+            let do_write = rng.next_bool();
+            let i = rng.index(1 << index_w.get()).unwrap();
+            let x = rng.next_u8() & 0b11;
+            let v = rng.next_u8() != 0;
+            let k = rng.next_u8() & 0b11;
+            write.retro_bool_(do_write).unwrap();
+            let mut index_awi = Awi::zero(index_w);
+            index_awi.usize_(i);
+            index.retro_(&index_awi).unwrap();
+            let mut data_awi = Awi::zero(bw(4));
+            data_awi.u8_(x);
+            data.retro_(&data_awi).unwrap();
+            valid.retro_bool_(v).unwrap();
+            let mut key_awi = Awi::zero(bw(4));
+            key_awi.u8_(k);
+            key.retro_(&key_awi).unwrap();
+
+            let mut expected = 0usize;
+            for (j, entry) in model.iter().enumerate() {
+                if *entry == Some(k) {
+                    expected |= 1 << j;
+                }
+            }
+            assert_eq!(matches.eval().unwrap().to_usize(), expected);
+            assert_eq!(hit.eval_bool().unwrap(), expected != 0);
+            if expected != 0 {
+                assert_eq!(
+                    found.eval().unwrap().to_usize(),
+                    expected.trailing_zeros() as usize
+                );
+            }
+            if do_write && (i < depth) {
+                model[i] = if v { Some(x) } else { None };
+            }
+            epoch.run(1).unwrap();
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_cam() {
+    let mut rng = StarRng::new(0);
+    for depth in 1..=5 {
+        cam_test(&mut rng, depth);
+    }
+}
+
+fn crossbar_test(rng: &mut StarRng, pipeline: awi::usize) {
+    use dag::*;
+    let epoch = Epoch::new();
+    let num_masters = 3;
+    let mut master_valid = vec![];
+    let mut master_data = vec![];
+    let mut master_ready = vec![];
+    let mut masters = vec![];
+    for _ in 0..num_masters {
+        let valid = LazyAwi::opaque(bw(1));
+        let data = LazyAwi::opaque(bw(8));
+        let ch = Channel::new(&valid, &data).unwrap();
+        master_ready.push(EvalAwi::from(ch.ready()));
+        master_valid.push(valid);
+        master_data.push(data);
+        masters.push(ch);
+    }
+    // the address is in the two most significant bits, address 3 goes to the
+    // default slave
+    let slaves = crossbar(masters, 6..8, &[0..1, 1..3], pipeline).unwrap();
+    assert_eq!(slaves.len(), 3);
+    let mut slave_valid = vec![];
+    let mut slave_data = vec![];
+    let mut slave_ready = vec![];
+    for ch in slaves {
+        // 2 bits for the master index
+        assert_eq!(ch.bw(), 10);
+        let ready = LazyAwi::opaque(bw(1));
+        slave_valid.push(EvalAwi::from(ch.valid()));
+        slave_data.push(EvalAwi::from(ch.data()));
+        ch.drive_ready(&ready).unwrap();
+        slave_ready.push(ready);
+    }
+
+    {
+        use awi::*;
+        // the requests of each master
+        let mut queues: Vec<VecDeque<u8>> = vec![];
+        for _ in 0..num_masters {
+            let mut queue = VecDeque::new();
+            for _ in 0..12 {
+                queue.push_back(rng.next_u8());
+            }
+            queues.push(queue);
+        }
+        let expected: Vec<Vec<u8>> = queues.iter().map(|q| q.iter().copied().collect()).collect();
+        // what each slave received from each master
+        let mut received = vec![vec![vec![]; num_masters]; 3];
+        for cycle in 0..256 {
+            for i in 0..num_masters {
+                let front = queues[i].front().copied();
+                master_valid[i].retro_bool_(front.is_some()).unwrap();
+                master_data[i].retro_u8_(front.unwrap_or(0)).unwrap();
+            }
+            // drain at the end
+            let drain = cycle > 192;
+            let mut readies = vec![];
+            for ready in &slave_ready {
+                let r = drain || rng.next_bool();
+                ready.retro_bool_(r).unwrap();
+                readies.push(r);
+            }
+            for i in 0..num_masters {
+                if master_ready[i].eval_bool().unwrap() && queues[i].front().is_some() {
+                    queues[i].pop_front();
+                }
+            }
+            for (j, received) in received.iter_mut().enumerate() {
+                if slave_valid[j].eval_bool().unwrap() && readies[j] {
+                    let x = slave_data[j].eval().unwrap().to_usize();
+                    received[x >> 8].push((x & 0xff) as u8);
+                }
+            }
+            epoch.assert_assertions(true).unwrap();
+            epoch.run(1).unwrap();
+        }
+        for i in 0..num_masters {
+            assert!(queues[i].is_empty());
+            for (j, received) in received.iter().enumerate() {
+                let sent: Vec<u8> = expected[i]
+                    .iter()
+                    .copied()
+                    .filter(|x| [0, 1, 1, 2][(x >> 6) as usize] == j)
+                    .collect();
+                assert_eq!(received[i], sent);
+            }
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_crossbar() {
+    let mut rng = StarRng::new(0);
+    for pipeline in 0..3 {
+        crossbar_test(&mut rng, pipeline);
+    }
+    use dag::*;
+    let _epoch = Epoch::new();
+    let ch = || Channel::new(&Awi::zero(bw(1)), &Awi::zero(bw(8))).unwrap();
+    assert!(crossbar(vec![], 0..2, &[0..1, 1..2], 0).is_err());
+    assert!(crossbar(vec![ch()], 0..9, &[0..1, 1..2], 0).is_err());
+    assert!(crossbar(vec![ch()], 2..2, &[0..1, 1..2], 0).is_err());
+    assert!(crossbar(vec![ch()], 0..2, &[0..2, 1..3], 0).is_err());
+    assert!(crossbar(vec![ch()], 0..2, &[0..1, 1..1], 0).is_err());
+    let other = Channel::new(&Awi::zero(bw(1)), &Awi::zero(bw(4))).unwrap();
+    assert!(crossbar(vec![ch(), other], 0..2, &[0..1, 1..2], 0).is_err());
+}
+
+#[test]
+fn loop_csr() {
+    use dag::*;
+    let mut map = CsrMap::new(bw(8), bw(3));
+    map.register("ctrl", 0).unwrap();
+    map.field(
+        "enable",
+        bw(1),
+        CsrAccess::ReadWrite,
+        &awi::Awi::from_bool(true),
+    )
+    .unwrap();
+    map.field("mode", bw(3), CsrAccess::ReadWrite, &awi::Awi::zero(bw(3)))
+        .unwrap();
+    map.field_at(
+        "start",
+        7,
+        bw(1),
+        CsrAccess::WriteOnly,
+        &awi::Awi::zero(bw(1)),
+    )
+    .unwrap();
+    map.register("status", 2).unwrap();
+    map.field(
+        "irq",
+        bw(2),
+        CsrAccess::WriteOneToClear,
+        &awi::Awi::zero(bw(2)),
+    )
+    .unwrap();
+    map.field("count", bw(4), CsrAccess::ReadOnly, &awi::Awi::zero(bw(4)))
+        .unwrap();
+    assert_eq!(
+        map.to_json(),
+        "{\"data_width\":8,\"address_width\":3,\"registers\":[{\"name\":\"ctrl\",\"address\":0,\"\
+         reset\":\"0x1\",\"fields\":[{\"name\":\"enable\",\"offset\":0,\"width\":1,\"access\":\"\
+         rw\",\"reset\":\"0x1\"},{\"name\":\"mode\",\"offset\":1,\"width\":3,\"access\":\"rw\",\"\
+         reset\":\"0x0\"},{\"name\":\"start\",\"offset\":7,\"width\":1,\"access\":\"wo\",\"reset\"\
+         :\"0x0\"}]},{\"name\":\"status\",\"address\":2,\"reset\":\"0x0\",\"fields\":[{\"name\":\"\
+         irq\",\"offset\":0,\"width\":2,\"access\":\"w1c\",\"reset\":\"0x0\"},{\"name\":\"count\",\
+         \"offset\":2,\"width\":4,\"access\":\"ro\",\"reset\":\"0x0\"}]}]}"
+    );
+
+    // invalid maps
+    let zero1 = awi::Awi::zero(bw(1));
+    let mut bad = map.clone();
+    assert!(bad.register("ctrl", 3).is_err());
+    assert!(bad.register("other", 2).is_err());
+    assert!(bad.register("other", 8).is_err());
+    assert!(bad.register("1other", 3).is_err());
+    assert!(bad.register("other-reg", 3).is_err());
+    assert!(bad
+        .field("count", bw(1), CsrAccess::ReadOnly, &zero1)
+        .is_err());
+    assert!(bad
+        .field_at("overlap", 5, bw(1), CsrAccess::ReadOnly, &zero1)
+        .is_err());
+    assert!(bad
+        .field("wide", bw(3), CsrAccess::ReadOnly, &zero1)
+        .is_err());
+    assert!(bad
+        .field("wide", bw(3), CsrAccess::ReadOnly, &zero1)
+        .is_err());
+    assert!(bad.field("ok", bw(2), CsrAccess::ReadOnly, &zero1).is_err());
+    assert!(CsrMap::new(bw(8), bw(3))
+        .field("orphan", bw(1), CsrAccess::ReadOnly, &zero1)
+        .is_err());
+    assert_eq!(bad, map);
+
+    let epoch = Epoch::new();
+    let write = LazyAwi::opaque(bw(1));
+    let address = LazyAwi::opaque(bw(3));
+    let write_data = LazyAwi::opaque(bw(8));
+    let hw_irq = LazyAwi::opaque(bw(2));
+    let hw_count = LazyAwi::opaque(bw(4));
+    assert!(Csr::new(&map, &write, &write_data, &write_data).is_err());
+    let mut csr = Csr::new(&map, &write, &address, &write_data).unwrap();
+    let enable = EvalAwi::from(&csr.field("ctrl", "enable").unwrap());
+    let mode = EvalAwi::from(&csr.field("ctrl", "mode").unwrap());
+    let start = EvalAwi::from(&csr.field("ctrl", "start").unwrap());
+    let irq = EvalAwi::from(&csr.field("status", "irq").unwrap());
+    assert!(csr.field("status", "count").is_err());
+    assert!(csr.field("status", "missing").is_err());
+    assert!(csr.set("ctrl", "mode", &awi!(0_u3)).is_err());
+    assert!(csr.set("status", "count", &awi!(0_u3)).is_err());
+    csr.set("status", "irq", &hw_irq).unwrap();
+    csr.set("status", "count", &hw_count).unwrap();
+    let read_data = EvalAwi::from(&csr.drive().unwrap());
+
+    {
+        use awi::*;
+        let mut rng = StarRng::new(0);
+        let mut ctrl = 1u8;
+        let mut status_irq = 0u8;
+        for _ in 0..128 {
+            let do_write = rng.next_bool();
+            let addr = rng.next_u8() & 0b111;
+            let data = rng.next_u8();
+            let irq_set = rng.next_u8() & 0b11;
+            let count = rng.next_u8() & 0b1111;
+            write.retro_bool_(do_write).unwrap();
+            let mut addr_awi = Awi::zero(bw(3));
+            addr_awi.u8_(addr);
+            address.retro_(&addr_awi).unwrap();
+            write_data.retro_u8_(data).unwrap();
+            let mut irq_awi = Awi::zero(bw(2));
+            irq_awi.u8_(irq_set);
+            hw_irq.retro_(&irq_awi).unwrap();
+            let mut count_awi = Awi::zero(bw(4));
+            count_awi.u8_(count);
+            hw_count.retro_(&count_awi).unwrap();
+
+            assert_eq!(enable.eval().unwrap().to_u8(), ctrl & 1);
+            assert_eq!(mode.eval().unwrap().to_u8(), (ctrl >> 1) & 0b111);
+            assert_eq!(irq.eval().unwrap().to_u8(), status_irq);
+            let expected_start = if do_write && (addr == 0) {
+                data >> 7
+            } else {
+                0
+            };
+            assert_eq!(start.eval().unwrap().to_u8(), expected_start);
+            let expected_read = match addr {
+                0 => ctrl,
+                2 => status_irq | (count << 2),
+                _ => 0,
+            };
+            assert_eq!(read_data.eval_u8().unwrap(), expected_read);
+
+            if do_write && (addr == 0) {
+                ctrl = data & 0b1111;
+            }
+            if do_write && (addr == 2) {
+                status_irq &= !data;
+            }
+            status_irq |= irq_set;
+            epoch.run(1).unwrap();
+        }
+    }
+    drop(epoch);
+
+    // unset read only fields
+    let epoch = Epoch::new();
+    let write = LazyAwi::opaque(bw(1));
+    let address = LazyAwi::opaque(bw(3));
+    let write_data = LazyAwi::opaque(bw(8));
+    let csr = Csr::new(&map, &write, &address, &write_data).unwrap();
+    assert!(csr.drive().is_err());
+    drop(epoch);
+}
+
+#[test]
+fn loop_synchronizer() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(1));
+    let synced = EvalAwi::from(&synchronizer(&x, 2).unwrap());
+    assert!(synchronizer(&x, 0).is_err());
+    let pulse = LazyAwi::opaque(bw(1));
+    let pulse_out = EvalAwi::from(&pulse_synchronizer(&pulse).unwrap());
+    assert!(pulse_synchronizer(&awi!(0u2)).is_err());
+
+    {
+        x.retro_bool_(false).unwrap();
+        pulse.retro_bool_(false).unwrap();
+        epoch.run(Delay::from(4)).unwrap();
+        x.retro_bool_(true).unwrap();
+        pulse.retro_bool_(true).unwrap();
+        let mut synced_vals = vec![];
+        let mut pulse_vals = vec![];
+        for i in 0..6 {
+            synced_vals.push(synced.eval_bool().unwrap());
+            pulse_vals.push(pulse_out.eval_bool().unwrap());
+            epoch.run(Delay::from(1)).unwrap();
+            if i == 0 {
+                pulse.retro_bool_(false).unwrap();
+            }
+        }
+        assert_eq!(synced_vals, [false, false, true, true, true, true]);
+        assert_eq!(pulse_vals, [false, false, false, true, false, false]);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_clock_crossing() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let counter = Loop::zero(bw(2));
+    let mut next = awi!(counter);
+    next.inc_(true);
+    let count = EvalAwi::from(&counter);
+    // the gray code of the counter
+    let mut gray = awi!(counter);
+    gray.lshr_(1).unwrap();
+    gray.xor_(&counter).unwrap();
+    let binary_crossed = EvalAwi::from(&clock_crossing(&counter, 1));
+    let gray_crossed = EvalAwi::from(&clock_crossing(&gray, 2));
+    counter.drive_with_delay(&next, 1).unwrap();
+
+    {
+        use awi::*;
+        let to_gray = |x: u8| x ^ (x >> 1);
+        let mut prev = 0u8;
+        let mut incoherent = 0;
+        let mut resolved_old = 0;
+        for _ in 0..64 {
+            let x = count.eval().unwrap().to_u8();
+            let b = binary_crossed.eval().unwrap().to_u8();
+            // unchanged bits pass through and changed bits resolve to either value
+            assert_eq!((b ^ x) & !(x ^ prev), 0);
+            if (b != x) && (b != prev) {
+                incoherent += 1;
+            }
+            let g = gray_crossed.eval().unwrap().to_u8();
+            assert!((g == to_gray(x)) || (g == to_gray(prev)));
+            if g != to_gray(x) {
+                resolved_old += 1;
+            }
+            prev = x;
+            epoch.run(Delay::from(1)).unwrap();
+        }
+        // the binary counter is observed with values it never had, which is the bug
+        // that gray coding avoids
+        assert!(incoherent > 0);
+        assert!(resolved_old > 0);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_channel_synchronize() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let valid = LazyAwi::opaque(awi::bw(1));
+    let data = LazyAwi::opaque(bw(8));
+    let ch = Channel::new(&valid, &data).unwrap();
+    let in_ready = EvalAwi::from(ch.ready());
+    let ch = ch.synchronize().unwrap();
+    let out_valid = EvalAwi::from(ch.valid());
+    let out_data = EvalAwi::from(ch.data());
+    ch.drive_ready(&awi!(1)).unwrap();
+
+    {
+        use awi::*;
+        let mut to_send: VecDeque<u8> = [3, 1, 4, 1, 5].into_iter().collect();
+        let mut received = vec![];
+        for _ in 0..64 {
+            if let Some(x) = to_send.front() {
+                valid.retro_bool_(true).unwrap();
+                data.retro_u8_(*x).unwrap();
+            } else {
+                valid.retro_bool_(false).unwrap();
+            }
+            if out_valid.eval_bool().unwrap() {
+                received.push(out_data.eval_u8().unwrap());
+            }
+            if !to_send.is_empty() && in_ready.eval_bool().unwrap() {
+                to_send.pop_front();
+            }
+            epoch.assert_assertions(true).unwrap();
+            epoch.run(Delay::from(1)).unwrap();
+        }
+        assert_eq!(received, [3, 1, 4, 1, 5]);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_topo_iter() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let looper = Loop::zero(bw(4));
+    let x = LazyAwi::opaque(bw(4));
+    let mut tmp = awi!(looper);
+    tmp.inc_(true);
+    tmp.xor_(&x).unwrap();
+    looper.drive_with_delay(&tmp, 1).unwrap();
+    let _y = EvalAwi::from(&tmp);
+    epoch.lower().unwrap();
+
+    epoch.ensemble(|ensemble| {
+        use awi::*;
+        let order: Vec<_> = ensemble.topo_iter().unwrap().collect();
+        assert_eq!(order.len(), ensemble.lnodes.len());
+        assert!(!ensemble.tnodes.is_empty());
+        let position: HashMap<_, _> = order.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+        for (i, p_lnode) in order.iter().enumerate() {
+            ensemble.lnodes[*p_lnode].inputs(|p_inp| {
+                let mut adv = ensemble.backrefs.advancer_surject(p_inp);
+                while let Some(p_back) = adv.advance(&ensemble.backrefs) {
+                    if let Referent::ThisLNode(p_driver) =
+                        *ensemble.backrefs.get_key(p_back).unwrap()
+                    {
+                        assert!(position[&p_driver] < i);
+                    }
+                }
+            });
+        }
+        // stable ordering
+        let order2: Vec<_> = ensemble.topo_iter().unwrap().collect();
+        assert_eq!(order, order2);
+        let mut rev: Vec<_> = ensemble.topo_iter_rev().unwrap().collect();
+        rev.reverse();
+        assert_eq!(order, rev);
+    });
+    drop(epoch);
+}
+
+#[test]
+fn loop_mem() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let we = LazyAwi::opaque(bw(1));
+    let addr = LazyAwi::opaque(bw(3));
+    let data = LazyAwi::opaque(bw(8));
+    let read_addr = LazyAwi::opaque(bw(3));
+    let mem = Mem::init_from_bin(bw(8), 5, &[1, 2, 3]).unwrap();
+    assert_eq!(mem.depth(), 5);
+    assert_eq!(mem.addr_nzbw(), bw(3));
+    assert!(mem.read(&awi!(0u2)).is_err());
+    assert!(Mem::init_from_bin(bw(8), 2, &[1, 2, 3]).is_err());
+    assert!(Mem::new(bw(8), 0).is_err());
+    // addresses past the depth are errors instead of allocations
+    let path = std::env::temp_dir().join("starlight_loop_mem.hex");
+    std::fs::write(&path, "01 02\n@4 05\n").unwrap();
+    assert!(Mem::init_from_hex(bw(8), 5, &path).is_ok());
+    assert!(Mem::init_from_hex(bw(8), 4, &path).is_err());
+    std::fs::write(&path, "@ffffffffffffffff 01\n").unwrap();
+    assert!(Mem::init_from_hex(bw(8), 5, &path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    let out = EvalAwi::from(&mem.read(&read_addr).unwrap());
+    mem.drive(&we, &addr, &data).unwrap();
+
+    {
+        use awi::*;
+        let mut rng = StarRng::new(0);
+        let mut model = [1u8, 2, 3, 0, 0];
+        for _ in 0..64 {
+            for i in 0..8 {
+                let mut tmp = awi!(0u3);
+                tmp.usize_(i);
+                read_addr.retro_(&tmp).unwrap();
+                assert_eq!(out.eval_u8().unwrap(), model.get(i).copied().unwrap_or(0));
+            }
+            let do_write = rng.next_bool();
+            let i = rng.index(8).unwrap();
+            let x = rng.next_u8();
+            we.retro_bool_(do_write).unwrap();
+            let mut tmp = awi!(0u3);
+            tmp.usize_(i);
+            addr.retro_(&tmp).unwrap();
+            data.retro_u8_(x).unwrap();
+            if do_write && (i < model.len()) {
+                model[i] = x;
+            }
+            epoch.run(Delay::from(1)).unwrap();
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn loop_auto_pipeline() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(8));
+    let mut x = dag::Awi::from(&a);
+    x.add_(&b).unwrap();
+    x.xor_(&c).unwrap();
+    x.add_(&c).unwrap();
+    let mut y = dag::Awi::from(&a);
+    y.and_(&b).unwrap();
+    let x = EvalAwi::from(&x);
+    let y = EvalAwi::from(&y);
+    assert!(epoch.auto_pipeline(0).is_err());
+    let max_depth = 2;
+    let latencies = epoch.auto_pipeline(max_depth).unwrap();
+    let x_latency = latencies[&x.p_external()];
+    let y_latency = latencies[&y.p_external()];
+    assert!(x_latency > 0);
+    assert_eq!(y_latency, 0);
+    // no combinational path is longer than `max_depth`
+    epoch.ensemble(|ensemble| {
+        use awi::*;
+        let mut levels = HashMap::new();
+        for p_lnode in ensemble.topo_iter().unwrap() {
+            let mut level = 0;
+            ensemble.lnodes[p_lnode].inputs(|p_inp| {
+                let mut adv = ensemble.backrefs.advancer_surject(p_inp);
+                while let Some(p_ref) = adv.advance(&ensemble.backrefs) {
+                    if let Referent::ThisLNode(p_driver) =
+                        *ensemble.backrefs.get_key(p_ref).unwrap()
+                    {
+                        level = level.max(levels[&p_driver]);
+                    }
+                }
+            });
+            assert!(level < max_depth);
+            levels.insert(p_lnode, level + 1);
+        }
+    });
+
+    {
+        use awi::*;
+        let mut rng = StarRng::new(0);
+        let mut history = VecDeque::new();
+        for _ in 0..32 {
+            let (va, vb, vc) = (rng.next_u8(), rng.next_u8(), rng.next_u8());
+            a.retro_u8_(va).unwrap();
+            b.retro_u8_(vb).unwrap();
+            c.retro_u8_(vc).unwrap();
+            history.push_front((va.wrapping_add(vb) ^ vc).wrapping_add(vc));
+            assert_eq!(y.eval_u8().unwrap(), va & vb);
+            if let Some(expected) = history.get(x_latency) {
+                assert_eq!(x.eval_u8().unwrap(), *expected);
+            }
+            epoch.run(Delay::from(1)).unwrap();
+        }
+    }
+    drop(epoch);
+
+    let epoch = Epoch::new();
+    let looper = Loop::zero(bw(1));
+    let mut x = dag::Awi::from(&*looper);
+    x.not_();
+    looper.drive_with_delay(&x, 1).unwrap();
+    let _x = EvalAwi::from(&x);
+    assert!(epoch.auto_pipeline(2).is_err());
+    drop(epoch);
+}
+
+#[test]
+fn loop_balance_latency() {
+    let ir = "slir 1\nequiv e0 dynam 0\nequiv e1 dynam 0\nequiv e2 dynam 0\nlnode e2 lut 6 e0 \
+              e1\nrnode rw 1 e0 \"a\"\nrnode rw 1 e1 \"b\"\nrnode ro 1 e2 \"x\"\nrnode ro 1 e1 \
+              \"b_out\"\n";
+    let mut ensemble = Ensemble::parse_ir(ir).unwrap();
+    let bit = |ensemble: &Ensemble, name: &str| {
+        let p_external = ensemble.notary.find_by_debug_name(name).unwrap();
+        ensemble
+            .notary
+            .get_rnode(p_external)
+            .unwrap()
+            .1
+            .bits()
+            .unwrap()[0]
+            .unwrap()
+    };
+    let (a, b) = (bit(&ensemble, "a"), bit(&ensemble, "b"));
+    let x = bit(&ensemble, "x");
+    // delay `a` by 3 and the xor by 2
+    let latency = ensemble
+        .balance_latency(&[(a, Delay::from(3)), (x, Delay::from(2))])
+        .unwrap();
+    assert_eq!(latency, Delay::from(5));
+    ensemble.verify_integrity().unwrap();
+    // registers for `a`, the xor, and a chain for `b` tapped at 3 and 5
+    assert_eq!(ensemble.tnodes.len(), 3 + 2 + 5);
+    // balancing again keeps the existing registers as they are
+    assert_eq!(ensemble.balance_latency(&[]), Ok(Delay::from(0)));
+    assert_eq!(ensemble.tnodes.len(), 3 + 2 + 5);
+    let (x, b_out) = (bit(&ensemble, "x"), bit(&ensemble, "b_out"));
+
+    let mut rng = StarRng::new(0);
+    let mut history = VecDeque::new();
+    for _ in 0..32 {
+        let (va, vb) = (rng.next_bool(), rng.next_bool());
+        ensemble.manual_change(a, Value::Dynam(va)).unwrap();
+        ensemble.manual_change(b, Value::Dynam(vb)).unwrap();
+        history.push_back((va, vb));
+        ensemble.run(Delay::from(1)).unwrap();
+        if history.len() > 5 {
+            history.pop_front();
+            let (va, vb) = history[0];
+            assert_eq!(ensemble.request_value(x).unwrap(), Value::Dynam(va ^ vb));
+            assert_eq!(ensemble.request_value(b_out).unwrap(), Value::Dynam(vb));
+        }
+    }
+
+    // existing registers and loops are shifted along with the sources
+    let ir = "slir 1\nequiv e0 dynam 0\nequiv e1 dynam 0\nequiv e2 dynam 0\nequiv e3 dynam \
+              0\nequiv e4 dynam 0\nequiv e5 dynam 0\ntnode e2 e1 delay 1\nlnode e3 lut 6 e0 \
+              e2\nlnode e5 lut 6 e3 e4\ntnode e4 e5 delay 1 init 0\nrnode rw 1 e0 \"a\"\nrnode rw \
+              1 e1 \"b\"\nrnode ro 1 e3 \"x\"\nrnode ro 1 e4 \"acc\"\n";
+    let mut ensemble = Ensemble::parse_ir(ir).unwrap();
+    let (a, b) = (bit(&ensemble, "a"), bit(&ensemble, "b"));
+    // interconnect delay along the loop cannot be balanced
+    let acc = bit(&ensemble, "acc");
+    assert!(ensemble.balance_latency(&[(acc, Delay::from(1))]).is_err());
+    assert_eq!(
+        ensemble.write_ir(),
+        Ensemble::parse_ir(ir).unwrap().write_ir()
+    );
+    let latency = ensemble.balance_latency(&[(a, Delay::from(2))]).unwrap();
+    assert_eq!(latency, Delay::from(2));
+    ensemble.verify_integrity().unwrap();
+    // registers for `a` and after the register of `b`
+    assert_eq!(ensemble.tnodes.len(), 2 + 2 + 2);
+    let (x, acc) = (bit(&ensemble, "x"), bit(&ensemble, "acc"));
+    let mut history = VecDeque::new();
+    let mut prev = None;
+    for _ in 0..32 {
+        let (va, vb) = (rng.next_bool(), rng.next_bool());
+        ensemble.manual_change(a, Value::Dynam(va)).unwrap();
+        ensemble.manual_change(b, Value::Dynam(vb)).unwrap();
+        history.push_back((va, vb));
+        ensemble.run(Delay::from(1)).unwrap();
+        let vx = ensemble.request_value(x).unwrap();
+        let vacc = ensemble.request_value(acc).unwrap();
+        if history.len() > 4 {
+            history.pop_front();
+            assert_eq!(vx, Value::Dynam(history[2].0 ^ history[1].1));
+        }
+        if let Some((prev_x, prev_acc)) = prev {
+            if let (Value::Dynam(prev_x), Value::Dynam(prev_acc)) = (prev_x, prev_acc) {
+                assert_eq!(vacc, Value::Dynam(prev_x ^ prev_acc));
+            }
+        }
+        prev = Some((vx, vacc));
+    }
+
+    let epoch = Epoch::new();
+    let valid = LazyAwi::opaque(awi::bw(1));
+    let delayed = EvalAwi::from(&LatencyWrapper::new(Delay::from(3)).delay_valid(&valid));
+    {
+        valid.retro_bool_(true).unwrap();
+        epoch.run(2).unwrap();
+        assert!(delayed.eval_is_all_unknown().unwrap());
+        epoch.run(1).unwrap();
+        assert!(delayed.eval_bool().unwrap());
+        // a new valid value can start every cycle
+        let mut history = VecDeque::new();
+        for i in 0..12 {
+            let v = (i % 3) != 0;
+            valid.retro_bool_(v).unwrap();
+            history.push_back(v);
+            epoch.run(1).unwrap();
+            if history.len() > 3 {
+                history.pop_front();
+                assert_eq!(delayed.eval_bool().unwrap(), history[0]);
+            }
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn prune_unreachable_states() {
+    use dag::*;
+    let mut num_lnodes = vec![];
+    for prune in [false, true] {
+        let epoch = Epoch::new();
+        let advance = LazyAwi::opaque(bw(1));
+        // one-hot state machine that rotates while `advance` is set
+        let state = Loop::from_bits(&awi!(0b0001_u4));
+        let s = awi!(state);
+        let mut next = awi!(s);
+        next.rotl_(1).unwrap();
+        next.mux_(&s, !advance.to_bool()).unwrap();
+        let mut is_state = vec![];
+        for k in 0..4 {
+            let mut onehot = awi!(0_u4);
+            onehot.usize_(1 << k);
+            is_state.push(EvalAwi::from_bool(s.const_eq(&onehot).unwrap()));
+        }
+        // states that are not one-hot
+        let mut two = awi!(s);
+        two.and_(&awi!(0b0011_u4)).unwrap();
+        let two = two.const_eq(&awi!(0b0011_u4)).unwrap();
+        let invalid = EvalAwi::from_bool(s.is_zero() | two);
+        // counter that wraps around after 4
+        let counter = Loop::from_bits(&awi!(0_u3));
+        let c = awi!(counter);
+        let mut next_c = awi!(c);
+        next_c.inc_(true);
+        let wrap = c.const_eq(&awi!(4_u3)).unwrap();
+        next_c.mux_(&awi!(0_u3), wrap).unwrap();
+        let c_big = EvalAwi::from_bool(awi!(4_u3).ult(&c).unwrap());
+        let mut c_out = awi!(0_u8);
+        c_out.zero_resize_(&c);
+        let c_out = EvalAwi::from(&c_out);
+        state.drive_with_delay(&next, 1).unwrap();
+        counter.drive_with_delay(&next_c, 1).unwrap();
+        if prune {
+            let report = epoch.prune_unreachable_states(1 << 14).unwrap();
+            assert_eq!(report.groups.len(), 1);
+            assert_eq!(report.groups[0].registers, 7);
+            assert_eq!(report.groups[0].reachable, 20.0);
+            assert!(report.groups[0].complete);
+            assert!(report.constified + report.merged > 0);
+            assert!(!report.to_string().is_empty());
+        } else {
+            let report = epoch.analyze_reachability(1 << 14).unwrap();
+            assert_eq!(report.groups[0].reachable, 20.0);
+            assert_eq!((report.constified, report.merged), (0, 0));
+        }
+        num_lnodes.push(epoch.ensemble(|ensemble| ensemble.lnodes.len()));
+        {
+            let mut rng = StarRng::new(0);
+            let mut model_state = 0usize;
+            let mut model_c = 0u8;
+            for _ in 0..32 {
+                let adv = rng.next_bool();
+                advance.retro_bool_(adv).unwrap();
+                for (k, is_state) in is_state.iter().enumerate() {
+                    assert_eq!(is_state.eval_bool().unwrap(), k == model_state);
+                }
+                assert!(!invalid.eval_bool().unwrap());
+                assert!(!c_big.eval_bool().unwrap());
+                assert_eq!(c_out.eval_u8().unwrap(), model_c);
+                epoch.run(1).unwrap();
+                if adv {
+                    model_state = (model_state + 1) % 4;
+                }
+                model_c = (model_c + 1) % 5;
+            }
+        }
+        drop(epoch);
+    }
+    assert!(num_lnodes[1] < num_lnodes[0]);
+}
+
+#[test]
+fn prove_invariant() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let enable = LazyAwi::opaque(bw(1));
+    // a decimal digit counter
+    let counter = Loop::zero(bw(4));
+    let mut next = awi!(counter);
+    next.inc_(true);
+    let wrap = counter.const_eq(&awi!(9_u4)).unwrap();
+    next.mux_(&awi!(0_u4), wrap).unwrap();
+    next.mux_(&counter, !enable.to_bool()).unwrap();
+    let in_range = EvalAwi::from_bool(counter.ult(&awi!(10_u4)).unwrap());
+    let not_twelve = EvalAwi::from_bool(counter.const_ne(&awi!(12_u4)).unwrap());
+    let nonzero = EvalAwi::from_bool(!counter.is_zero());
+    let out = EvalAwi::from(&counter);
+    counter.drive_with_delay(&next, 1).unwrap();
+
+    assert_eq!(
+        epoch.prove_invariant(&in_range, 1 << 14).unwrap(),
+        Induction::Proven
+    );
+    // invariant, but 11 is an unreachable state that steps to 12
+    let Induction::NotInductive(trace) = epoch.prove_invariant(&not_twelve, 1 << 14).unwrap()
+    else {
+        panic!()
+    };
+    assert_eq!(trace.registers.len(), 4);
+    assert_eq!(trace.registers.iter().filter(|(_, b)| *b).count(), 3);
+    assert_eq!(trace.inputs.len(), 1);
+    assert_eq!(trace.inputs[0].0, enable.p_external());
+    assert!(trace.inputs[0].1.to_bool());
+    let Induction::BaseFailure(trace) = epoch.prove_invariant(&nonzero, 1 << 14).unwrap() else {
+        panic!()
+    };
+    assert!(trace.registers.iter().all(|(_, b)| !*b));
+    assert!(trace.next_inputs.is_empty());
+
+    // the analysis does not change the design
+    enable.retro_bool_(true).unwrap();
+    for i in 0..12 {
+        assert_eq!(out.eval().unwrap().to_u8(), i % 10);
+        epoch.run(1).unwrap();
+    }
+    drop(epoch);
+
+    // registers with different delays
+    let epoch = Epoch::new();
+    let a = Loop::zero(bw(1));
+    let b = Loop::zero(bw(1));
+    let property = EvalAwi::from_bool(!(a.to_bool() & b.to_bool()));
+    let (a_out, b_out) = (awi!(a), awi!(b));
+    a.drive_with_delay(&b_out, 1).unwrap();
+    b.drive_with_delay(&a_out, 2).unwrap();
+    assert!(epoch.prove_invariant(&property, 1 << 14).is_err());
+    drop(epoch);
+}
+
+#[test]
+fn reset() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let reset = LazyAwi::opaque(bw(1));
+    let ring = Loop::from_bits(&awi!(0b1010_u4));
+    let mut next = awi!(ring);
+    next.rotl_(1).unwrap();
+    let ring_out = EvalAwi::from(&ring);
+    ring.drive_with_delay(&next, 1).unwrap();
+    // no declared initial value
+    let free = Loop::opaque(bw(2));
+    let mut next = awi!(free);
+    next.inc_(true);
+    let _free_out = EvalAwi::from(&free);
+    free.drive_with_delay(&next, 1).unwrap();
+
+    assert!(epoch
+        .add_reset(&LazyAwi::opaque(bw(2)), ResetKind::SyncActiveHigh)
+        .is_err());
+    let report = epoch
+        .check_reset(&reset, ResetKind::SyncActiveLow, 1 << 14)
+        .unwrap();
+    assert_eq!(report.reset.len(), 0);
+    assert_eq!(report.unreset.len(), 6);
+    assert_eq!(
+        epoch.add_reset(&reset, ResetKind::SyncActiveLow).unwrap(),
+        4
+    );
+    let report = epoch
+        .check_reset(&reset, ResetKind::SyncActiveLow, 1 << 14)
+        .unwrap();
+    assert_eq!(report.cycles, 1);
+    assert_eq!(report.reset.len(), 4);
+    assert_eq!(report.unreset.len(), 2);
+    assert!(report.mismatched.is_empty());
+    assert!(!report.is_complete());
+    assert!(!report.to_string().is_empty());
+    let ir = epoch.ensemble(|ensemble| ensemble.write_ir());
+    assert!(ir.contains(" init 1\n"));
+    assert_eq!(Ensemble::parse_ir(&ir).unwrap().write_ir(), ir);
+
+    reset.retro_bool_(true).unwrap();
+    epoch.run(3).unwrap();
+    assert_eq!(ring_out.eval().unwrap().to_u8(), 0b0101);
+    reset.retro_bool_(false).unwrap();
+    epoch.run(1).unwrap();
+    assert_eq!(ring_out.eval().unwrap().to_u8(), 0b1010);
+    epoch.run(1).unwrap();
+    assert_eq!(ring_out.eval().unwrap().to_u8(), 0b1010);
+    reset.retro_bool_(true).unwrap();
+    epoch.run(1).unwrap();
+    assert_eq!(ring_out.eval().unwrap().to_u8(), 0b0101);
+    drop(epoch);
+
+    // a hand threaded reset that only resets the first stage of a shift
+    // register, the last stage is reset to a value other than its initial one
+    let epoch = Epoch::new();
+    let reset = LazyAwi::opaque(bw(1));
+    let input = LazyAwi::opaque(bw(1));
+    let s0 = Loop::zero(bw(1));
+    let s1 = Loop::zero(bw(1));
+    let s2 = Loop::umax(bw(1));
+    let s2_out = EvalAwi::from(&s2);
+    let (s0_out, s1_out) = (awi!(s0), awi!(s1));
+    s0.drive_with_delay(&Awi::from_bool(input.to_bool() & !reset.to_bool()), 1)
+        .unwrap();
+    s1.drive_with_delay(&s0_out, 1).unwrap();
+    s2.drive_with_delay(&s1_out, 1).unwrap();
+    let report = epoch
+        .check_reset(&reset, ResetKind::SyncActiveHigh, 1 << 14)
+        .unwrap();
+    assert_eq!(report.cycles, 3);
+    assert_eq!(report.reset.len(), 3);
+    assert!(report.reset.iter().all(|(_, b)| !*b));
+    assert_eq!(report.mismatched.len(), 1);
+    assert!(s2_out.eval_bool().unwrap());
+    drop(epoch);
+}
+
+#[test]
+fn scan_chains() {
+    let epoch = Epoch::new();
+    let scan_enable = LazyAwi::opaque(dag::bw(1));
+    let scan_in = LazyAwi::opaque(dag::bw(2));
+    let enable = LazyAwi::opaque(dag::bw(1));
+    // 5 registers
+    let counter = Loop::zero(dag::bw(5));
+    let out = {
+        use dag::*;
+        let mut next = awi!(counter);
+        next.inc_(enable.to_bool());
+        let out = EvalAwi::from(&counter);
+        counter.drive_with_delay(&next, 1).unwrap();
+        out
+    };
+
+    assert!(epoch.insert_scan_chains(&scan_in, &scan_enable).is_err());
+    let (scan_out, report) = epoch.insert_scan_chains(&scan_enable, &scan_in).unwrap();
+    assert_eq!(report.chains.len(), 2);
+    assert_eq!(report.chains[0].len(), 3);
+    assert_eq!(report.chains[1].len(), 2);
+    assert_eq!(report.max_len(), 3);
+    assert_eq!(
+        report.to_string(),
+        "chain 0: 3 registers\nchain 1: 2 registers\n"
+    );
+    // the registers are in the order of the counter bits, so the first
+    // chain has bits 0..3 and the second has bits 3..5
+
+    let retro_scan_in = |x: u8| {
+        let mut tmp = awi::Awi::zero(awi::bw(2));
+        tmp.u8_(x);
+        scan_in.retro_(&tmp).unwrap();
+    };
+    scan_enable.retro_bool_(false).unwrap();
+    enable.retro_bool_(true).unwrap();
+    epoch.run(13).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 13);
+
+    // dump the state, which comes out from the last to the first register of
+    // each chain
+    scan_enable.retro_bool_(true).unwrap();
+    retro_scan_in(0);
+    let mut dumped = 0u8;
+    for k in 0..report.max_len() {
+        let bits = scan_out.eval().unwrap().to_u8();
+        if k < 3 {
+            dumped |= (bits & 1) << (2 - k);
+        }
+        if k < 2 {
+            dumped |= ((bits >> 1) & 1) << (4 - k);
+        }
+        epoch.run(1).unwrap();
+    }
+    assert_eq!(dumped, 13);
+    assert_eq!(out.eval().unwrap().to_u8(), 0);
+
+    // load 22
+    let value = 22u8;
+    for k in 0..report.max_len() {
+        let mut bits = 0u8;
+        if k < 3 {
+            bits |= (value >> (2 - k)) & 1;
+        }
+        // the second chain is shorter, so it shifts in what it needs last
+        if k >= 1 {
+            bits |= ((value >> (4 - (k - 1))) & 1) << 1;
+        }
+        retro_scan_in(bits);
+        epoch.run(1).unwrap();
+    }
+    assert_eq!(out.eval().unwrap().to_u8(), value);
+    scan_enable.retro_bool_(false).unwrap();
+    epoch.run(2).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), value + 2);
+    drop(epoch);
+}
+
+#[test]
+fn register_state() {
+    let epoch = Epoch::new();
+    let counter = Loop::zero(dag::bw(4));
+    let out = {
+        use dag::*;
+        let mut next = awi!(counter);
+        next.inc_(true);
+        let out = EvalAwi::from(&counter);
+        counter.drive_with_delay(&next, 2).unwrap();
+        out
+    };
+
+    // the checkpoint is taken between register drives
+    epoch.run(3).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 1);
+    let state = epoch.dump_register_state().unwrap();
+    assert_eq!(state.registers.len(), 4);
+    assert!(state
+        .pending
+        .iter()
+        .all(|(delay, _)| *delay == Delay::from(1)));
+    epoch.run(6).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 4);
+
+    // the pending drive is still one unit away after restoring
+    epoch.load_register_state(&state).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 1);
+    epoch.run(1).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 2);
+    epoch.run(2).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 3);
+
+    // initialize to an interesting state, the registers are in bit order
+    let mut modified = state.clone();
+    for (i, (p_tnode, _)) in state.registers.iter().enumerate() {
+        let b = ((0b1010 >> i) & 1) != 0;
+        modified.set(*p_tnode, Value::Dynam(b)).unwrap();
+    }
+    assert_eq!(modified.get(state.registers[1].0), Some(Value::Dynam(true)));
+    epoch.load_register_state(&modified).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 10);
+    epoch.run(1).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 11);
+    drop(epoch);
+}
+
+#[test]
+fn combinational_loops() {
+    use dag::*;
+    // registers are not combinational loops
+    {
+        let epoch = Epoch::new();
+        let counter = Loop::zero(bw(4));
+        let mut next = awi!(counter);
+        next.inc_(true);
+        counter.drive_with_delay(&next, 1).unwrap();
+        assert!(epoch.combinational_loops(1000).unwrap().is_empty());
+        epoch.deny_latches(1000).unwrap();
+        drop(epoch);
+    }
+    {
+        let epoch = Epoch::new();
+        let q = Loop::zero(bw(1));
+        let mut next = Awi::from_bits(&q);
+        next.not_();
+        q.drive(&next).unwrap();
+        let loops = epoch.combinational_loops(1000).unwrap();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].kind, LoopKind::Oscillator);
+        assert_eq!(loops[0].tnodes.len(), 1);
+        assert!(loops[0]
+            .to_string()
+            .starts_with("Oscillator combinational loop"));
+        assert!(epoch.deny_latches(1000).is_err());
+        drop(epoch);
+    }
+    // an SR latch made from two cross coupled NOR gates
+    {
+        let epoch = Epoch::new();
+        let s = LazyAwi::opaque(bw(1));
+        let r = LazyAwi::opaque(bw(1));
+        let q = Loop::zero(bw(1));
+        let mut q_n = Awi::from_bits(&q);
+        q_n.or_(&r).unwrap();
+        q_n.not_();
+        let mut next = awi!(q_n);
+        next.or_(&s).unwrap();
+        next.not_();
+        q.drive(&next).unwrap();
+        let loops = epoch.combinational_loops(1000).unwrap();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].kind, LoopKind::Latch);
+        assert!(!loops[0].lnodes.is_empty());
+        drop(epoch);
+    }
+    // the feedback cancels out
+    {
+        let epoch = Epoch::new();
+        let a = LazyAwi::opaque(bw(1));
+        let q = Loop::zero(bw(1));
+        let mut next = Awi::from_bits(&q);
+        next.xor_(&q).unwrap();
+        next.xor_(&a).unwrap();
+        q.drive(&next).unwrap();
+        let loops = epoch.combinational_loops(1000).unwrap();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].kind, LoopKind::FalseLoop);
+        epoch.deny_latches(1000).unwrap();
+        drop(epoch);
+    }
+}
+
+#[test]
+fn coi_reduce() {
+    let epoch = Epoch::new();
+    let (enable, unrelated_in, first, second, mixed) = {
+        use dag::*;
+        let enable = LazyAwi::opaque(bw(1));
+        let unrelated_in = LazyAwi::opaque(bw(4));
+        // `second` counts the times that `first` has been odd, so the cone of
+        // `second` goes through the registers of both
+        let first = Loop::zero(bw(4));
+        let mut next = awi!(first);
+        next.inc_(enable.to_bool());
+        let first_val = awi!(first);
+        let first_out = EvalAwi::from(&first_val);
+        let first_lsb = first.get(0).unwrap();
+        first.drive_with_delay(&next, 1).unwrap();
+        let second = Loop::zero(bw(4));
+        let mut next = awi!(second);
+        next.inc_(first_lsb);
+        let second_out = EvalAwi::from(&second);
+        second.drive_with_delay(&next, 1).unwrap();
+        let unrelated = Loop::zero(bw(4));
+        let mut next = awi!(unrelated);
+        next.add_(&unrelated_in).unwrap();
+        let mixed = EvalAwi::from(&awi!(unrelated, first_val));
+        unrelated.drive_with_delay(&next, 1).unwrap();
+        (enable, unrelated_in, first_out, second_out, mixed)
+    };
+    enable.retro_bool_(true).unwrap();
+    unrelated_in
+        .retro_(&{
+            use awi::*;
+            awi!(1u4)
+        })
+        .unwrap();
+    epoch.run(3).unwrap();
+    assert_eq!(first.eval().unwrap().to_u8(), 3);
+    assert_eq!(second.eval().unwrap().to_u8(), 1);
+    assert_eq!(mixed.eval().unwrap().to_u8(), 0x33);
+
+    let num_tnodes = epoch.ensemble(|ensemble| ensemble.tnodes.len());
+    let reduced = epoch.coi_reduce(&[&second]).unwrap();
+    let (reduced, _) = reduced.scope(|reduced| {
+        // the cone is bit level, only the least significant register of `first`
+        // and the registers of `second` remain
+        assert_eq!(reduced.ensemble(|ensemble| ensemble.tnodes.len()), 5);
+        assert!(num_tnodes > 5);
+        assert!(unrelated_in
+            .retro_(&{
+                use awi::*;
+                awi!(2u4)
+            })
+            .is_err());
+        // the bits outside of the cone are removed
+        assert!(mixed.eval().is_err());
+        assert!(first.eval().is_err());
+        // the reduced copy continues from the same registers and events
+        assert_eq!(second.eval().unwrap().to_u8(), 1);
+        reduced.run(4).unwrap();
+        assert_eq!(second.eval().unwrap().to_u8(), 3);
+        enable.retro_bool_(false).unwrap();
+        reduced.run(4).unwrap();
+        assert_eq!(second.eval().unwrap().to_u8(), 7);
+        reduced.verify_integrity().unwrap();
+    });
+    drop(reduced);
+    // the original is unchanged
+    epoch.run(4).unwrap();
+    assert_eq!(second.eval().unwrap().to_u8(), 3);
+    drop(enable);
+    drop(unrelated_in);
+    drop(first);
+    drop(second);
+    drop(mixed);
+    drop(epoch);
+}
+
+#[test]
+fn external_cosim() {
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(dag::bw(8));
+    let acc = {
+        use dag::*;
+        let acc = Loop::zero(bw(8));
+        let mut next = awi!(acc);
+        next.add_(&x).unwrap();
+        let out = EvalAwi::from(&acc);
+        acc.drive_with_delay(&next, 10).unwrap();
+        out
+    };
+    {
+        use awi::*;
+        epoch
+            .schedule_external_event(0, x.p_external(), &awi!(1u8))
+            .unwrap();
+        // an external model that stops the accumulation once it sees 3
+        let seen = Rc::new(RefCell::new(vec![]));
+        let seen_clone = seen.clone();
+        let p_x = x.p_external();
+        epoch
+            .on_value_change(acc.p_external(), move |change| {
+                let val = change.value().to_u8();
+                seen_clone.borrow_mut().push((change.time().amount(), val));
+                if val == 3 {
+                    change.epoch().schedule_external_event(
+                        change.time().amount() + 1,
+                        p_x,
+                        &awi!(0u8),
+                    )?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        epoch.run(100).unwrap();
+        assert_eq!(epoch.current_time(), Delay::from_amount(100));
+        assert_eq!(*seen.borrow(), vec![(0, 0), (10, 1), (20, 2), (30, 3)]);
+        assert_eq!(acc.eval().unwrap(), awi!(3u8));
+        assert_eq!(epoch.peek(x.p_external()).unwrap(), Some(awi!(0u8)));
+        assert!(epoch.quiesced().unwrap());
+
+        assert!(epoch
+            .schedule_external_event(50, x.p_external(), &awi!(1u8))
+            .is_err());
+        assert!(epoch
+            .schedule_external_event(200, acc.p_external(), &awi!(1u8))
+            .is_err());
+        assert_eq!(
+            epoch.schedule_external_event(200, x.p_external(), &awi!(1u4)),
+            Err(Error::BitwidthMismatch(8, 4))
+        );
+        // events can be scheduled for the current time
+        epoch
+            .schedule_external_event(100, x.p_external(), &awi!(2u8))
+            .unwrap();
+        epoch.run(10).unwrap();
+        assert_eq!(seen.borrow().len(), 5);
+        assert_eq!(seen.borrow()[4], (110, 5));
+    }
+    drop(x);
+    drop(acc);
+    drop(epoch);
+}
+
+#[test]
+fn external_pending_activity() {
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(dag::bw(8));
+    let enable = LazyAwi::opaque(dag::bw(1));
+    let (acc, toggle) = {
+        use dag::*;
+        let acc = Loop::zero(bw(8));
+        let mut next = awi!(acc);
+        next.add_(&x).unwrap();
+        let acc_out = EvalAwi::from(&acc);
+        acc.drive_with_delay(&next, 10).unwrap();
+        // a faster domain
+        let toggle = Loop::zero(bw(1));
+        let mut next = awi!(toggle);
+        next.xor_(&enable).unwrap();
+        let toggle_out = EvalAwi::from(&toggle);
+        toggle.drive_with_delay(&next, 3).unwrap();
+        (acc_out, toggle_out)
+    };
+    {
+        use awi::*;
+        x.retro_u8_(1).unwrap();
+        enable.retro_bool_(true).unwrap();
+        assert_eq!(epoch.pending_activity().unwrap(), vec![
+            (Scope::Temporal(Delay::from(3)), Delay::from(3)),
+            (Scope::Temporal(Delay::from(10)), Delay::from(10)),
+        ]);
+        epoch
+            .schedule_external_event(5, x.p_external(), &awi!(0u8))
+            .unwrap();
+        epoch.run(4).unwrap();
+        assert!(!epoch.quiesced().unwrap());
+        assert_eq!(epoch.pending_activity().unwrap(), vec![
+            (Scope::External(x.p_external()), Delay::from(5)),
+            (Scope::Temporal(Delay::from(3)), Delay::from(6)),
+            (Scope::Temporal(Delay::from(10)), Delay::from(10)),
+        ]);
+        enable.retro_bool_(false).unwrap();
+        epoch.run(20).unwrap();
+        // the drive at time 10 saw the value set by the external event
+        assert_eq!(acc.eval().unwrap(), awi!(0u8));
+        assert_eq!(toggle.eval().unwrap(), awi!(1));
+        assert!(epoch.quiesced().unwrap());
+        assert!(epoch.pending_activity().unwrap().is_empty());
+        // scheduled external events are reported even though the `Epoch` is
+        // otherwise quiesced
+        epoch
+            .schedule_external_event(30, x.p_external(), &awi!(1u8))
+            .unwrap();
+        assert!(epoch.quiesced().unwrap());
+        assert_eq!(epoch.pending_activity().unwrap(), vec![(
+            Scope::External(x.p_external()),
+            Delay::from(30)
+        )]);
+    }
+    drop(x);
+    drop(enable);
+    drop(acc);
+    drop(toggle);
+    drop(epoch);
+}
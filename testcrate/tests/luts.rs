@@ -10,10 +10,17 @@ use starlight::{
         Lineage, Op,
     },
     dag,
-    ensemble::LNodeKind,
-    lower::meta::create_static_lut,
+    ensemble::{
+        ApproxConfig, Bdd, BddRef, ErrorSpec, LNode, LNodeKind, SmtResult, LNODE_INLINE_INPUTS,
+    },
+    lower::{
+        decompose::{curtis_decomposition, find_curtis_decomposition, shannon_cofactors},
+        meta::create_static_lut,
+        FunnelStrategy, LutDecomposition,
+    },
+    route::{Channeler, Configurator, PCEdge, PCNode, Programmability},
     utils::StarRng,
-    Epoch, EvalAwi, LazyAwi,
+    Epoch, Error, EvalAwi, LazyAwi, Limits,
 };
 
 // Test static LUT simplifications, this also handles input duplication cases
@@ -91,7 +98,7 @@ fn lut_optimization_with_dup() {
                                 inp_bits += inp.len();
                                 assert!(inp.len() <= opaque_set.count_ones());
                             }
-                            LNodeKind::DynamicLut(..) => unreachable!(),
+                            LNodeKind::Carry(_) | LNodeKind::DynamicLut(..) => unreachable!(),
                         }
                         assert!(lnodes.next().is_none());
                     }
@@ -554,3 +561,628 @@ fn lut_dynamic_optimization() {
     }
     assert_eq!((num_lut_bits, num_simplified_lut_bits), (N.1, N.2));
 }
+
+#[test]
+fn lut_carry_chain() {
+    let epoch = Epoch::new();
+    let lhs = LazyAwi::opaque(bw(8));
+    let rhs = LazyAwi::opaque(bw(8));
+    let cin = LazyAwi::opaque(bw(1));
+    let mut out = dag::Awi::zero(bw(8));
+    let mut cout = dag::Awi::zero(bw(1));
+    let (c, _) = out.cin_sum_(cin.to_bool(), &lhs, &rhs).unwrap();
+    cout.bool_(c);
+    let out = EvalAwi::from(&out);
+    let cout = EvalAwi::from(&cout);
+    epoch.optimize().unwrap();
+    epoch.ensemble(|ensemble| {
+        // the sum and the overflow are lowered separately, but there should be at
+        // least one chain spanning the width
+        let chains = ensemble.carry_chains();
+        assert!(chains.iter().any(|chain| chain.len() >= 7));
+        for p_lnode in chains.iter().flatten() {
+            assert!(matches!(
+                ensemble.lnodes[*p_lnode].kind,
+                LNodeKind::Carry(_)
+            ));
+        }
+        // the router does not try to pass signals through carries
+        let channeler: Channeler<PCNode, PCEdge> =
+            Channeler::new(ensemble, &Configurator::new()).unwrap();
+        let mut num_carries = 0;
+        for cedge in channeler.cedges.vals() {
+            if matches!(cedge.programmability(), Programmability::Carry) {
+                assert!(!cedge.is_traversable());
+                num_carries += 1;
+            }
+        }
+        assert_eq!(num_carries, chains.iter().flatten().count());
+    });
+    lhs.retro_(&awi!(0xf0u8)).unwrap();
+    rhs.retro_(&awi!(0x1fu8)).unwrap();
+    cin.retro_(&awi!(1)).unwrap();
+    assert_eq!(out.eval().unwrap(), awi!(0x10u8));
+    assert_eq!(cout.eval().unwrap(), awi!(1));
+    drop(epoch);
+}
+
+#[test]
+fn lut_carry_only_from_adders() {
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(3));
+    // a table that happens to be the carry table is still a regular LUT
+    let mut lut_out = dag::Awi::zero(bw(1));
+    lut_out
+        .lut_(&dag::Awi::from(&LNode::carry_lut()), &x)
+        .unwrap();
+    let lut_out = EvalAwi::from(&lut_out);
+    // an adder with a constant operand has no carries left to map
+    let mut inc = dag::Awi::from(&x);
+    inc.add_(&dag::Awi::from(&awi!(101))).unwrap();
+    let inc = EvalAwi::from(&inc);
+    epoch.optimize().unwrap();
+    epoch.ensemble(|ensemble| {
+        assert!(ensemble.carry_chains().is_empty());
+        assert!(ensemble
+            .lnodes
+            .vals()
+            .all(|lnode| matches!(lnode.kind, LNodeKind::Lut(..))));
+    });
+    let mut v = awi!(000);
+    for i in 0..8u8 {
+        v.u8_(i);
+        x.retro_(&v).unwrap();
+        assert_eq!(lut_out.eval_bool().unwrap(), i.count_ones() >= 2);
+        v.u8_(i + 5);
+        assert_eq!(inc.eval().unwrap(), v);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn lut_chunking() {
+    let mut rng = StarRng::new(0);
+    for chunk_inputs in 1..=3 {
+        for _ in 0..4 {
+            let epoch = Epoch::new();
+            epoch.set_limits(Limits {
+                max_single_lut_inputs: Some(chunk_inputs),
+                ..Limits::unlimited()
+            });
+            let mut static_lut = awi::Awi::zero(bw(1 << 7));
+            rng.next_bits(&mut static_lut);
+            let inx = LazyAwi::opaque(bw(7));
+            let dynamic_lut = LazyAwi::opaque(bw(1 << 5));
+            let dynamic_inx = LazyAwi::opaque(bw(5));
+            let mut out0 = dag::Awi::zero(bw(1));
+            out0.lut_(&dag::Awi::from(&static_lut), &inx).unwrap();
+            let mut out1 = dag::Awi::zero(bw(1));
+            out1.lut_(&dynamic_lut, &dynamic_inx).unwrap();
+            let out0 = EvalAwi::from(&out0);
+            let out1 = EvalAwi::from(&out1);
+            epoch.optimize().unwrap();
+            epoch.ensemble(|ensemble| {
+                for lnode in ensemble.lnodes.vals() {
+                    let len = match &lnode.kind {
+                        LNodeKind::Lut(inp, _) | LNodeKind::DynamicLut(inp, _) => inp.len(),
+                        _ => 0,
+                    };
+                    assert!(len <= chunk_inputs);
+                }
+            });
+            let mut dynamic_lut_val = awi::Awi::zero(bw(1 << 5));
+            rng.next_bits(&mut dynamic_lut_val);
+            dynamic_lut.retro_(&dynamic_lut_val).unwrap();
+            for _ in 0..8 {
+                let i = (rng.next_u8() as usize) % (1 << 7);
+                let mut tmp = awi::Awi::zero(bw(7));
+                tmp.usize_(i);
+                inx.retro_(&tmp).unwrap();
+                assert_eq!(out0.eval().unwrap().to_bool(), static_lut.get(i).unwrap());
+                let j = (rng.next_u8() as usize) % (1 << 5);
+                let mut tmp = awi::Awi::zero(bw(5));
+                tmp.usize_(j);
+                dynamic_inx.retro_(&tmp).unwrap();
+                assert_eq!(
+                    out1.eval().unwrap().to_bool(),
+                    dynamic_lut_val.get(j).unwrap()
+                );
+            }
+            drop(epoch);
+        }
+    }
+}
+
+#[test]
+fn lut_decomposition_tables() {
+    // the parity of 6 inputs only needs one signal to encode any bound set
+    let mut parity = awi::Awi::zero(bw(1 << 6));
+    for i in 0..(1 << 6) {
+        parity.set(i, (i.count_ones() & 1) != 0).unwrap();
+    }
+    let decomp = curtis_decomposition(&parity, &[0, 2, 4]).unwrap();
+    assert_eq!(decomp.free, vec![1, 3, 5]);
+    assert_eq!(decomp.bound_luts.len(), 1);
+    assert_eq!(decomp.composition_lut.bw(), 1 << 4);
+    let decomp = find_curtis_decomposition(&parity, 4).unwrap();
+    assert_eq!(decomp.bound, vec![0, 1, 2, 3]);
+    assert_eq!(decomp.bound_luts.len(), 1);
+    // the recomposition is the same function
+    for i in 0..(1usize << 6) {
+        let mut b_inx = 0;
+        for (j, k) in decomp.bound.iter().enumerate() {
+            b_inx |= ((i >> k) & 1) << j;
+        }
+        let mut h_inx = 0;
+        for (j, k) in decomp.free.iter().enumerate() {
+            h_inx |= ((i >> k) & 1) << j;
+        }
+        let g = decomp.bound_luts[0].get(b_inx).unwrap();
+        h_inx |= usize::from(g) << decomp.free.len();
+        assert_eq!(
+            decomp.composition_lut.get(h_inx).unwrap(),
+            parity.get(i).unwrap()
+        );
+    }
+    // a 2 input multiplexer selected by input 2 has no beneficial bound set of 2
+    let mux = awi::Awi::from_u8(0b1100_1010);
+    assert!(find_curtis_decomposition(&mux, 2).is_none());
+    let (lut0, lut1) = shannon_cofactors(&mux, 2);
+    let mut expected = awi::Awi::zero(bw(4));
+    expected.u8_(0b1010);
+    assert_eq!(lut0, expected);
+    expected.u8_(0b1100);
+    assert_eq!(lut1, expected);
+}
+
+#[test]
+fn lut_decomposition() {
+    let mut rng = StarRng::new(0);
+    let mut parity = awi::Awi::zero(bw(1 << 7));
+    for i in 0..(1 << 7) {
+        parity.set(i, (i.count_ones() & 1) != 0).unwrap();
+    }
+    for decomposition in [
+        LutDecomposition::Chunked,
+        LutDecomposition::Shannon,
+        LutDecomposition::Curtis,
+    ] {
+        for chunk_inputs in 1..=4 {
+            for trial in 0..3 {
+                let mut table = awi::Awi::zero(bw(1 << 7));
+                if trial == 0 {
+                    table.copy_(&parity).unwrap();
+                } else {
+                    rng.next_bits(&mut table);
+                }
+                let epoch = Epoch::new();
+                epoch.set_limits(Limits {
+                    max_single_lut_inputs: Some(chunk_inputs),
+                    lut_decomposition: decomposition,
+                    ..Limits::unlimited()
+                });
+                let inx = LazyAwi::opaque(bw(7));
+                let mut out = dag::Awi::zero(bw(1));
+                out.lut_(&dag::Awi::from(&table), &inx).unwrap();
+                let out = EvalAwi::from(&out);
+                epoch.optimize().unwrap();
+                let num_lnodes = epoch.ensemble(|ensemble| {
+                    for lnode in ensemble.lnodes.vals() {
+                        if let LNodeKind::Lut(inp, _) | LNodeKind::DynamicLut(inp, _) = &lnode.kind
+                        {
+                            assert!(inp.len() <= chunk_inputs);
+                        }
+                    }
+                    ensemble.lnodes.len()
+                });
+                if (trial == 0) && (chunk_inputs >= 2) {
+                    // parity is much smaller when its structure is used
+                    if decomposition == LutDecomposition::Curtis {
+                        assert!(num_lnodes <= 6);
+                    }
+                }
+                for i in 0..(1 << 7) {
+                    let mut tmp = awi::Awi::zero(bw(7));
+                    tmp.usize_(i);
+                    inx.retro_(&tmp).unwrap();
+                    assert_eq!(out.eval().unwrap().to_bool(), table.get(i).unwrap());
+                }
+                drop(epoch);
+            }
+        }
+    }
+}
+
+#[test]
+fn decompose_luts() {
+    let mut rng = StarRng::new(1);
+    for decomposition in [LutDecomposition::Shannon, LutDecomposition::Curtis] {
+        let epoch = Epoch::new();
+        let mut table = awi::Awi::zero(bw(1 << 6));
+        rng.next_bits(&mut table);
+        let inx = LazyAwi::opaque(bw(6));
+        let mut out = dag::Awi::zero(bw(1));
+        out.lut_(&dag::Awi::from(&table), &inx).unwrap();
+        let out = EvalAwi::from(&out);
+        epoch.optimize().unwrap();
+        assert_eq!(epoch.decompose_luts(3, decomposition).unwrap(), 1);
+        assert_eq!(epoch.decompose_luts(3, decomposition).unwrap(), 0);
+        assert!(epoch.decompose_luts(0, decomposition).is_err());
+        epoch.ensemble(|ensemble| {
+            for lnode in ensemble.lnodes.vals() {
+                if let LNodeKind::Lut(inp, _) = &lnode.kind {
+                    assert!(inp.len() <= 3);
+                }
+            }
+        });
+        for _ in 0..2 {
+            for i in 0..(1 << 6) {
+                let mut tmp = awi::Awi::zero(bw(6));
+                tmp.usize_(i);
+                inx.retro_(&tmp).unwrap();
+                assert_eq!(out.eval().unwrap().to_bool(), table.get(i).unwrap());
+            }
+            epoch.optimize().unwrap();
+        }
+        drop(epoch);
+    }
+}
+
+#[test]
+fn bdd_analysis() {
+    let mut rng = StarRng::new(2);
+    let mut bdd = Bdd::new();
+    // round trips of random tables
+    for n in 0..6 {
+        let mut lut = awi::Awi::zero(bw(1 << n));
+        rng.next_bits(&mut lut);
+        let f = bdd.apply_lut_vars(&lut);
+        assert_eq!(bdd.to_lut(f, n).unwrap(), lut);
+        for i in 0..n {
+            assert_eq!(
+                !bdd.support(f).contains(&i),
+                starlight::lower::decompose::is_independent(&lut, i)
+            );
+        }
+    }
+    let x0 = bdd.var(0);
+    let x1 = bdd.var(1);
+    let x2 = bdd.var(2);
+    // functions are canonical
+    let tmp = bdd.xor(x0, x1);
+    let lhs = bdd.xor(tmp, x2);
+    let tmp = bdd.xor(x1, x2);
+    let rhs = bdd.xor(x0, tmp);
+    assert_eq!(lhs, rhs);
+    let tmp = bdd.and(x0, x1);
+    let not_tmp = bdd.not(tmp);
+    let not_x0 = bdd.not(x0);
+    let not_x1 = bdd.not(x1);
+    assert_eq!(not_tmp, bdd.or(not_x0, not_x1));
+    assert_eq!(bdd.and(x0, not_x0), BddRef::FALSE);
+    assert_eq!(bdd.restrict(tmp, 1, true), x0);
+    assert_eq!(bdd.to_lut(lhs, 2), None);
+    // symmetry, the carry is symmetric in all inputs but a mux is not
+    let carry = bdd.apply_lut_vars(&LNode::carry_lut());
+    assert!(bdd.is_symmetric(carry, 0, 2));
+    assert!(bdd.is_symmetric(carry, 1, 2));
+    let mut mux = awi::Awi::zero(bw(8));
+    mux.u8_(0b1100_1010);
+    let mux = bdd.apply_lut_vars(&mux);
+    assert!(!bdd.is_symmetric(mux, 0, 1));
+    assert!(!bdd.is_symmetric(mux, 1, 2));
+    // decomposability
+    assert_eq!(bdd.column_multiplicity(lhs, &[0, 1]), 2);
+    assert_eq!(bdd.column_multiplicity(mux, &[0, 2]), 3);
+    assert_eq!(bdd.node_count(lhs), 5);
+    // a dynamic table selecting between `x0` and `x1`
+    assert_eq!(bdd.apply_dynamic_lut(&[x0, x1], &[x2]), mux);
+    // quantification, renaming, and counting
+    assert_eq!(bdd.exists(tmp, &[1]), x0);
+    assert_eq!(bdd.exists(lhs, &[2]), BddRef::TRUE);
+    let x3 = bdd.var(3);
+    let renamed = bdd.rename(tmp, &[(0, 3)].into_iter().collect());
+    assert_eq!(renamed, bdd.and(x3, x1));
+    assert_eq!(bdd.sat_count(tmp, &[0, 1, 2]), 2.0);
+    assert_eq!(bdd.sat_count(lhs, &[0, 1, 2]), 4.0);
+    assert_eq!(bdd.sat_count(BddRef::TRUE, &[0, 1, 2, 3]), 16.0);
+}
+
+#[test]
+fn merge_equivalent_cones() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(8));
+    // the same function with different structure
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    x.xor_(&c).unwrap();
+    let mut y = dag::Awi::from(&b);
+    y.xor_(&c).unwrap();
+    y.xor_(&a).unwrap();
+    let x = EvalAwi::from(&x);
+    let y = EvalAwi::from(&y);
+    epoch.optimize().unwrap();
+    let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    assert_eq!(epoch.merge_equivalent_cones(1 << 12).unwrap(), 8);
+    assert_eq!(epoch.merge_equivalent_cones(1 << 12).unwrap(), 0);
+    assert_eq!(
+        epoch.ensemble(|ensemble| ensemble.lnodes.len()),
+        num_lnodes - 16
+    );
+    let mut rng = StarRng::new(3);
+    for _ in 0..16 {
+        let (a_val, b_val, c_val) = (rng.next_u8(), rng.next_u8(), rng.next_u8());
+        a.retro_u8_(a_val).unwrap();
+        b.retro_u8_(b_val).unwrap();
+        c.retro_u8_(c_val).unwrap();
+        assert_eq!(x.eval_u8().unwrap(), a_val ^ b_val ^ c_val);
+        assert_eq!(y.eval_u8().unwrap(), a_val ^ b_val ^ c_val);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn funnel_strategies() {
+    let mut rng = StarRng::new(0);
+    for (strategy, uses_dynamic) in [
+        (FunnelStrategy::DynamicLut, true),
+        (FunnelStrategy::Crossbar, false),
+        // 8 table inputs plus 3 index inputs do not fit in a 4 input LUT
+        (FunnelStrategy::Auto(4), false),
+        (FunnelStrategy::Auto(11), true),
+    ] {
+        let epoch = Epoch::new();
+        epoch.set_funnel_strategy(strategy);
+        assert_eq!(epoch.funnel_strategy(), strategy);
+        let x = LazyAwi::opaque(bw(16));
+        let s = LazyAwi::opaque(bw(3));
+        let (funnel, shl, field_from) = {
+            use dag::*;
+            let mut funnel = Awi::zero(bw(8));
+            funnel.funnel_(&x, &s).unwrap();
+            let mut shl = Awi::zero(bw(8));
+            shl.resize_(&x, false);
+            shl.shl_(s.to_usize()).unwrap();
+            let mut field_from = Awi::zero(bw(8));
+            field_from.field_from(&x, s.to_usize(), 8).unwrap();
+            (
+                EvalAwi::from(&funnel),
+                EvalAwi::from(&shl),
+                EvalAwi::from(&field_from),
+            )
+        };
+        epoch.optimize().unwrap();
+        let has_dynamic = epoch.ensemble(|ensemble| {
+            ensemble
+                .lnodes
+                .vals()
+                .any(|lnode| matches!(lnode.kind, LNodeKind::DynamicLut(..)))
+        });
+        assert_eq!(has_dynamic, uses_dynamic);
+        for _ in 0..16 {
+            let mut x_val = awi!(0u16);
+            rng.next_bits(&mut x_val);
+            let s_val = rng.index(8).unwrap();
+            let mut s_awi = awi!(0u3);
+            s_awi.usize_(s_val);
+            x.retro_(&x_val).unwrap();
+            s.retro_(&s_awi).unwrap();
+            let mut expected = awi!(0u8);
+            expected.funnel_(&x_val, &s_awi).unwrap();
+            assert_eq!(funnel.eval().unwrap(), expected);
+            expected.resize_(&x_val, false);
+            expected.shl_(s_val).unwrap();
+            assert_eq!(shl.eval().unwrap(), expected);
+            expected.field_from(&x_val, s_val, 8).unwrap();
+            assert_eq!(field_from.eval().unwrap(), expected);
+        }
+        drop(epoch);
+    }
+}
+
+#[test]
+fn replace_lut() {
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(2));
+    let y = {
+        use dag::*;
+        let mut y = inlawi!(0u1);
+        y.lut_(&inlawi!(1000), &x).unwrap();
+        EvalAwi::from(&y)
+    };
+    epoch.enable_eval_cache(NonZeroUsize::new(16).unwrap());
+    {
+        use awi::*;
+        for i in 0..4 {
+            let mut v = awi!(0u2);
+            v.usize_(i);
+            x.retro_(&v).unwrap();
+            assert_eq!(y.eval_bool().unwrap(), i == 3);
+        }
+        // xor
+        epoch.replace_lut(y.p_external(), 0, &awi!(0110)).unwrap();
+        for i in 0..4 {
+            let mut v = awi!(0u2);
+            v.usize_(i);
+            x.retro_(&v).unwrap();
+            assert_eq!(y.eval_bool().unwrap(), (i == 1) || (i == 2));
+        }
+        // the value is reevaluated even if the input does not change
+        epoch.replace_lut(y.p_external(), 0, &awi!(1001)).unwrap();
+        assert!(y.eval_bool().unwrap());
+        assert_eq!(
+            epoch.replace_lut(y.p_external(), 0, &awi!(100)),
+            Err(Error::BitwidthMismatch(4, 3))
+        );
+        assert!(epoch.replace_lut(y.p_external(), 1, &awi!(0110)).is_err());
+    }
+    drop(epoch);
+}
+
+#[test]
+fn lnode_inline_inputs() {
+    // the testcrate enables the `perf` feature
+    assert_eq!(LNODE_INLINE_INPUTS, 6);
+    let epoch = Epoch::new();
+    let mut rng = StarRng::new(0);
+    let x = LazyAwi::opaque(bw(6));
+    let mut table = awi::Awi::zero(bw(64));
+    rng.next_bits(&mut table);
+    let y = {
+        use dag::*;
+        let mut y = inlawi!(0u1);
+        y.lut_(&Awi::from(&table), &x).unwrap();
+        EvalAwi::from(&y)
+    };
+    epoch.optimize().unwrap();
+    epoch.ensemble(|ensemble| {
+        let mut num_6_input = 0;
+        for lnode in ensemble.lnodes.vals() {
+            if let LNodeKind::Lut(ref inp, _) = lnode.kind {
+                assert!(!inp.spilled());
+                if inp.len() == 6 {
+                    num_6_input += 1;
+                }
+            }
+        }
+        assert_eq!(num_6_input, 1);
+    });
+    {
+        use awi::*;
+        for i in 0..64 {
+            let mut v = awi!(0u6);
+            v.usize_(i);
+            x.retro_(&v).unwrap();
+            assert_eq!(y.eval_bool().unwrap(), table.get(i).unwrap());
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn approximate_luts() {
+    for max_error in [0u128, 1, 3, 15] {
+        let epoch = Epoch::new();
+        let a = LazyAwi::opaque(bw(4));
+        let b = LazyAwi::opaque(bw(4));
+        let out = {
+            use dag::*;
+            let mut out = inlawi!(0u8);
+            out.arb_umul_add_(&a, &b);
+            EvalAwi::from(&out)
+        };
+        epoch.optimize().unwrap();
+        let lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+        let report = epoch
+            .approximate(
+                &[ErrorSpec {
+                    p_external: out.p_external(),
+                    max_error,
+                }],
+                &ApproxConfig::default(),
+            )
+            .unwrap();
+        epoch.verify_integrity().unwrap();
+        // 8 free input bits are simulated exhaustively
+        assert!(report.exhaustive);
+        assert_eq!(report.num_samples, 256);
+        assert_eq!(report.lnodes_before, lnodes);
+        assert!(report.lnodes_after <= lnodes);
+        assert!(report.max_errors[0] <= max_error);
+        let mut actual_max = 0;
+        for i in 0..16u8 {
+            for j in 0..16u8 {
+                let mut v = awi!(0u4);
+                v.u8_(i);
+                a.retro_(&v).unwrap();
+                v.u8_(j);
+                b.retro_(&v).unwrap();
+                let exact = u128::from(i) * u128::from(j);
+                let x = u128::from(out.eval_u8().unwrap());
+                actual_max = actual_max.max(x.abs_diff(exact));
+            }
+        }
+        assert_eq!(actual_max, report.max_errors[0]);
+        if max_error == 15 {
+            assert!(report.flipped_minterms > 0);
+            assert!(report.lnodes_after < lnodes);
+        }
+        drop(epoch);
+    }
+}
+
+#[test]
+fn smt_export() {
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(4));
+    let y = LazyAwi::opaque(bw(4));
+    let (a, b) = {
+        use dag::*;
+        let mut a = awi!(x);
+        a.and_(&y).unwrap();
+        let mut b = awi!(x);
+        b.or_(&y).unwrap();
+        (EvalAwi::from(&a), EvalAwi::from(&b))
+    };
+    let cone = epoch.smt_cone(&[&a, &b]).unwrap();
+    assert_eq!(cone.inputs().len(), 2);
+    assert_eq!(cone.outputs().len(), 2);
+    let (x_name, y_name) = (cone.inputs()[0].1.clone(), cone.inputs()[1].1.clone());
+    assert!(cone
+        .definitions()
+        .contains(&format!("(declare-const {x_name} (_ BitVec 4))")));
+
+    // a solver that returns a counterexample in the format of z3
+    let mut scripts = vec![];
+    let mut solver = |script: &str| -> Result<String, Error> {
+        scripts.push(script.to_owned());
+        Ok(format!(
+            "sat\n(\n  (define-fun {y_name} () (_ BitVec 4)\n    #x0)\n  (define-fun {x_name} () \
+             (_ BitVec 4)\n    #b0101)\n)\n"
+        ))
+    };
+    let res = epoch.check_equivalence_smt(&a, &b, &mut solver).unwrap();
+    assert_eq!(scripts.len(), 1);
+    assert!(scripts[0].contains("(assert (distinct out0 out1))"));
+    assert!(scripts[0].ends_with("(check-sat)\n(get-model)\n"));
+    let SmtResult::Sat(values) = res else {
+        panic!()
+    };
+    assert_eq!(values, vec![
+        (x.p_external(), awi!(0101)),
+        (y.p_external(), awi!(0000))
+    ]);
+    // the counterexample reproduces
+    for (p_external, value) in &values {
+        let lazy = if *p_external == x.p_external() {
+            &x
+        } else {
+            &y
+        };
+        lazy.retro_(value).unwrap();
+    }
+    assert_ne!(a.eval().unwrap(), b.eval().unwrap());
+
+    let mut solver = |_: &str| -> Result<String, Error> { Ok("unsat\n".to_owned()) };
+    let res = epoch.check_property_smt(&a, &mut solver).unwrap();
+    assert!(res.is_unsat());
+    assert!(matches!(
+        cone.parse_result("unknown"),
+        SmtResult::Unknown(_)
+    ));
+    assert_eq!(
+        cone.parse_result(&format!(
+            "sat\n(model (define-fun {x_name} () (_ BitVec 4) (_ bv10 4)))"
+        )),
+        SmtResult::Sat(vec![
+            (x.p_external(), awi!(1010)),
+            (y.p_external(), awi!(0000))
+        ])
+    );
+    assert!(cone
+        .equivalence_query(a.p_external(), x.p_external())
+        .is_err());
+    drop(epoch);
+}
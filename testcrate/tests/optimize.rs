@@ -0,0 +1,325 @@
+use starlight::{
+    awi,
+    awi::*,
+    dag,
+    ensemble::{rewrite_rules, EgraphConfig, Ensemble},
+    utils::{diff_ensembles, StarRng},
+    Epoch, EvalAwi, LazyAwi, MacroOp, Profile, Rom,
+};
+
+#[test]
+fn preserve_mul() {
+    let epoch = Epoch::new();
+    epoch.preserve_macro_ops(&[MacroOp::Mul]);
+    let lhs = LazyAwi::opaque(bw(8));
+    let rhs = LazyAwi::opaque(bw(8));
+    let mut out = dag::Awi::zero(bw(8));
+    out.arb_umul_add_(&lhs, &rhs);
+    let out = EvalAwi::from(&out);
+    epoch.optimize().unwrap();
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.mul_nodes.len()), 1);
+    {
+        use awi::*;
+        lhs.retro_(&awi!(7u8)).unwrap();
+        rhs.retro_(&awi!(9u8)).unwrap();
+        assert_eq!(out.eval().unwrap(), awi!(63u8));
+    }
+    // falling back to the decomposition
+    epoch.remove_macro_op_nodes().unwrap();
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.mul_nodes.len()), 0);
+    epoch.optimize().unwrap();
+    {
+        use awi::*;
+        rhs.retro_(&awi!(10u8)).unwrap();
+        assert_eq!(out.eval().unwrap(), awi!(70u8));
+    }
+    drop(epoch);
+}
+
+#[test]
+fn preserve_rom() {
+    let rom = Rom::from_bin(bw(8), &[3, 1, 4, 1]);
+    let epoch = Epoch::new();
+    epoch.preserve_macro_ops(&[MacroOp::Rom]);
+    let addr = LazyAwi::opaque(bw(2));
+    let out = EvalAwi::from(&rom.read(&addr).unwrap());
+    epoch.optimize().unwrap();
+    epoch.ensemble(|ensemble| {
+        assert_eq!(ensemble.rom_nodes.len(), 1);
+        let rom_node = ensemble.rom_nodes.vals().next().unwrap();
+        assert_eq!(rom_node.table, awi!(0x01040103u32));
+        // the table is exported and parsed back
+        let ir = ensemble.write_ir();
+        assert!(ir.contains("\nrom r"));
+        let parsed = Ensemble::parse_ir(&ir).unwrap();
+        assert_eq!(parsed.rom_nodes.len(), 1);
+        assert_eq!(parsed.write_ir(), ir);
+    });
+    {
+        use awi::*;
+        addr.retro_(&awi!(10)).unwrap();
+        assert_eq!(out.eval().unwrap(), awi!(4u8));
+    }
+    epoch.remove_macro_op_nodes().unwrap();
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.rom_nodes.len()), 0);
+    epoch.optimize().unwrap();
+    {
+        use awi::*;
+        addr.retro_(&awi!(00)).unwrap();
+        assert_eq!(out.eval().unwrap(), awi!(3u8));
+    }
+    drop(epoch);
+}
+
+#[test]
+fn profile_guided_optimization() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    x.rotl_(3).unwrap();
+    x.and_(&a).unwrap();
+    x.or_(&b).unwrap();
+    let x = EvalAwi::from(&x);
+    epoch.optimize().unwrap();
+    epoch.start_profiling();
+    let mut rng = 0u8;
+    let mut expected = vec![];
+    for _ in 0..16 {
+        rng = rng.wrapping_mul(37).wrapping_add(11);
+        let (va, vb) = (rng, rng.rotate_left(4) ^ 0x5a);
+        a.retro_u8_(va).unwrap();
+        b.retro_u8_(vb).unwrap();
+        let res = ((va ^ vb).rotate_left(3) & va) | vb;
+        assert_eq!(x.eval_u8().unwrap(), res);
+        expected.push((va, vb, res));
+    }
+    let profile: Profile = epoch.stop_profiling().unwrap();
+    assert!(profile.total_toggles() > 0);
+    assert!(profile.num_toggled() > 0);
+    assert!(epoch.stop_profiling().is_err());
+    let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    epoch.optimize_with_profile(&profile).unwrap();
+    // fusion can only remove single use tables or duplicate into hot tables
+    assert!(epoch.ensemble(|ensemble| ensemble.lnodes.len()) <= 2 * num_lnodes);
+    for (va, vb, res) in expected {
+        a.retro_u8_(va).unwrap();
+        b.retro_u8_(vb).unwrap();
+        assert_eq!(x.eval_u8().unwrap(), res);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn profile_guided_adders() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(16));
+    let b = LazyAwi::opaque(bw(16));
+    let c = LazyAwi::opaque(bw(16));
+    let d = LazyAwi::opaque(bw(16));
+    let mut hot = dag::Awi::from(&a);
+    hot.add_(&b).unwrap();
+    let mut cold = dag::Awi::from(&c);
+    cold.add_(&d).unwrap();
+    let hot = EvalAwi::from(&hot);
+    let cold = EvalAwi::from(&cold);
+    epoch.optimize().unwrap();
+    let cones = |epoch: &Epoch| {
+        let report = epoch.report_outputs().unwrap();
+        let hot = report.get(hot.p_external()).unwrap();
+        let cold = report.get(cold.p_external()).unwrap();
+        ((hot.depth, hot.lnodes), (cold.depth, cold.lnodes))
+    };
+    let (hot_before, cold_before) = cones(&epoch);
+    c.retro_u16_(0x1234).unwrap();
+    d.retro_u16_(0x4321).unwrap();
+    assert_eq!(cold.eval_u16().unwrap(), 0x5555);
+    epoch.start_profiling();
+    let mut rng = StarRng::new(0);
+    for _ in 0..64 {
+        let (va, vb) = (rng.next_u16(), rng.next_u16());
+        a.retro_u16_(va).unwrap();
+        b.retro_u16_(vb).unwrap();
+        assert_eq!(hot.eval_u16().unwrap(), va.wrapping_add(vb));
+    }
+    let profile = epoch.stop_profiling().unwrap();
+    // the profile stays valid when the internal pointers are recast
+    epoch.optimize().unwrap();
+    epoch.optimize_with_profile(&profile).unwrap();
+    let (hot_after, cold_after) = cones(&epoch);
+    // the hot adder is turned into a faster and larger carry select adder, while
+    // the cold adder stays a ripple carry adder
+    assert!(hot_after.0 < hot_before.0);
+    assert!(hot_after.1 > hot_before.1);
+    assert_eq!(cold_after, cold_before);
+    for _ in 0..64 {
+        let (va, vb, vc, vd) = (
+            rng.next_u16(),
+            rng.next_u16(),
+            rng.next_u16(),
+            rng.next_u16(),
+        );
+        a.retro_u16_(va).unwrap();
+        b.retro_u16_(vb).unwrap();
+        c.retro_u16_(vc).unwrap();
+        d.retro_u16_(vd).unwrap();
+        assert_eq!(hot.eval_u16().unwrap(), va.wrapping_add(vb));
+        assert_eq!(cold.eval_u16().unwrap(), vc.wrapping_add(vd));
+    }
+    drop(epoch);
+}
+
+#[test]
+fn deterministic_optimization() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let s = LazyAwi::opaque(bw(3));
+    let mut x = dag::Awi::from(&a);
+    x.add_(&b).unwrap();
+    x.rotl_(s.to_usize()).unwrap();
+    let mut y = dag::Awi::from(&b);
+    y.xor_(&a).unwrap();
+    y.or_(&x).unwrap();
+    // duplicate `LNode`s with equal IDs that only differ in what they fan out to
+    let mut z = dag::Awi::from(&b);
+    z.xor_(&a).unwrap();
+    z.rotl_(1).unwrap();
+    let x = EvalAwi::from(&x);
+    let y = EvalAwi::from(&y);
+    let z = EvalAwi::from(&z);
+    epoch.lower_and_prune().unwrap();
+    epoch
+        .ensemble(|ensemble| ensemble.verify_deterministic_optimization())
+        .unwrap();
+    epoch.optimize_deterministic().unwrap();
+    epoch
+        .ensemble(|ensemble| ensemble.verify_deterministic_optimization())
+        .unwrap();
+    {
+        use awi::*;
+        a.retro_(&awi!(0x35_u8)).unwrap();
+        b.retro_(&awi!(0x1c_u8)).unwrap();
+        s.retro_(&awi!(3_u3)).unwrap();
+        let sum = 0x35u8.wrapping_add(0x1c).rotate_left(3);
+        assert_eq!(x.eval().unwrap().to_u8(), sum);
+        assert_eq!(y.eval().unwrap().to_u8(), (0x35 ^ 0x1c) | sum);
+        assert_eq!(z.eval().unwrap().to_u8(), (0x35u8 ^ 0x1c).rotate_left(1));
+    }
+    drop(epoch);
+}
+
+#[test]
+fn randomize() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(8));
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    x.rotl_(3).unwrap();
+    x.mux_(&c, b.msb()).unwrap();
+    x.or_(&a).unwrap();
+    let mut y = dag::Awi::from(&c);
+    y.and_(&b).unwrap();
+    y.rotr_(1).unwrap();
+    y.xor_(&x).unwrap();
+    let x = EvalAwi::from(&x);
+    let y = EvalAwi::from(&y);
+    epoch.optimize().unwrap();
+    // the same seed is reproducible on the same `Ensemble`
+    let mut ensemble0 = epoch.ensemble(|ensemble| ensemble.clone());
+    let mut ensemble1 = ensemble0.clone();
+    let report = ensemble0.randomize(7, 300).unwrap();
+    assert_eq!(ensemble1.randomize(7, 300).unwrap(), report);
+    assert!(diff_ensembles(&ensemble0, &ensemble1).unwrap().is_empty());
+    assert!(
+        !diff_ensembles(&ensemble0, &epoch.ensemble(|ensemble| ensemble.clone()))
+            .unwrap()
+            .is_empty()
+    );
+
+    let report = epoch.randomize(7, 300).unwrap();
+    assert!(report.permutations > 0);
+    assert!(report.splits > 0);
+    assert!(report.merges > 0);
+    epoch.verify_integrity().unwrap();
+    let mut rng = StarRng::new(0);
+    for _ in 0..64 {
+        let (va, vb, vc) = (rng.next_u8(), rng.next_u8(), rng.next_u8());
+        a.retro_u8_(va).unwrap();
+        b.retro_u8_(vb).unwrap();
+        c.retro_u8_(vc).unwrap();
+        let expected_x = if (vb & 0x80) != 0 {
+            vc
+        } else {
+            (va ^ vb).rotate_left(3)
+        } | va;
+        assert_eq!(x.eval_u8().unwrap(), expected_x);
+        assert_eq!(y.eval_u8().unwrap(), (vc & vb).rotate_right(1) ^ expected_x);
+    }
+    drop(epoch);
+}
+
+/// Builds `(a & b) | (a & c)` and `((a + b) - b) ^ c`
+fn rewrite_design(a: &LazyAwi, b: &LazyAwi, c: &LazyAwi) -> (EvalAwi, EvalAwi) {
+    use dag::*;
+    let mut x = Awi::from(a);
+    x.and_(b).unwrap();
+    let mut tmp = Awi::from(a);
+    tmp.and_(c).unwrap();
+    x.or_(&tmp).unwrap();
+    let mut y = Awi::from(a);
+    y.add_(b).unwrap();
+    y.sub_(b).unwrap();
+    y.xor_(c).unwrap();
+    (EvalAwi::from(&x), EvalAwi::from(&y))
+}
+
+#[test]
+fn peephole_and_egraph() {
+    for rule in rewrite_rules() {
+        assert!(!rule.lhs.commuted_variants().is_empty());
+    }
+    let mut num_lnodes = vec![];
+    for pass in 0..3 {
+        let epoch = Epoch::new();
+        let a = LazyAwi::opaque(bw(8));
+        let b = LazyAwi::opaque(bw(8));
+        let c = LazyAwi::opaque(bw(8));
+        let (x, y) = rewrite_design(&a, &b, &c);
+        match pass {
+            0 => (),
+            1 => {
+                assert_eq!(epoch.peephole_states().unwrap(), 2);
+                assert_eq!(epoch.peephole_states().unwrap(), 0);
+            }
+            _ => {
+                let report = epoch
+                    .egraph_optimize_states(&EgraphConfig::default())
+                    .unwrap();
+                assert!(report.cost_after < report.cost_before);
+                assert_eq!(report.num_rewritten, 2);
+                let report = epoch
+                    .egraph_optimize_states(&EgraphConfig::default())
+                    .unwrap();
+                assert_eq!(report.num_rewritten, 0);
+            }
+        }
+        epoch.optimize().unwrap();
+        num_lnodes.push(epoch.ensemble(|ensemble| ensemble.lnodes.len()));
+        let mut rng = StarRng::new(pass);
+        for _ in 0..16 {
+            let (a_val, b_val, c_val) = (rng.next_u8(), rng.next_u8(), rng.next_u8());
+            a.retro_u8_(a_val).unwrap();
+            b.retro_u8_(b_val).unwrap();
+            c.retro_u8_(c_val).unwrap();
+            assert_eq!(x.eval_u8().unwrap(), (a_val & b_val) | (a_val & c_val));
+            assert_eq!(y.eval_u8().unwrap(), a_val ^ c_val);
+        }
+        drop(epoch);
+    }
+    assert!(num_lnodes[1] < num_lnodes[0]);
+    assert!(num_lnodes[2] <= num_lnodes[1]);
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "python")]
+
+use starlight::python::{
+    pyo3::{self, prelude::*, types::IntoPyDict},
+    starlight_module,
+};
+
+/// Runs `code` with the bindings imported as `starlight`
+fn run_python(code: &str) {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let module = PyModule::new_bound(py, "starlight").unwrap();
+        starlight_module(&module).unwrap();
+        let globals = [("starlight", module)].into_py_dict_bound(py);
+        if let Err(e) = py.run_bound(code, Some(&globals), None) {
+            e.print(py);
+            panic!("{e}");
+        }
+    })
+}
+
+#[test]
+fn python_simulation() {
+    run_python(
+        r#"
+with starlight.Epoch() as epoch:
+    a = starlight.LazyAwi(8)
+    b = starlight.LazyAwi(8)
+    x = a.awi()
+    y = b.awi()
+    s = starlight.EvalAwi(x + y)
+    m = starlight.EvalAwi((x * y) ^ starlight.Awi.constant(-1, 8))
+    lt = starlight.EvalAwi(x.ult(y))
+    sel = starlight.EvalAwi(x.mux(y, x.get(7)))
+    wide = starlight.EvalAwi(x.sign_resize(12) << 2)
+    delayed = starlight.EvalAwi(starlight.delay(x + y, 3))
+    assert s.bw == 8 and lt.bw == 1 and wide.bw == 12
+    a.retro(200)
+    b.retro(100)
+    assert s.eval() == (300 & 0xff)
+    assert m.eval() == ((200 * 100) & 0xff) ^ 0xff
+    assert lt.eval() == 0
+    assert sel.eval() == 100
+    assert wide.eval() == ((200 - 256) * 4) & 0xfff
+    assert delayed.eval_is_all_unknown()
+    assert not epoch.quiesced()
+    epoch.run(3)
+    assert delayed.eval() == (300 & 0xff)
+    a.retro(-1)
+    assert s.eval() == 99
+    epoch.optimize()
+    b.retro(1)
+    assert s.eval() == 0
+    assert "slir 1" in epoch.write_ir()
+"#,
+    );
+}
+
+#[test]
+fn python_errors() {
+    run_python(
+        r#"
+def raises(f, ty):
+    try:
+        f()
+    except ty:
+        return
+    raise AssertionError("expected an exception")
+
+epoch = starlight.Epoch()
+a = starlight.LazyAwi(8)
+raises(lambda: a.retro(256), ValueError)
+raises(lambda: a.retro("1"), TypeError)
+raises(lambda: starlight.LazyAwi(0), ValueError)
+raises(lambda: a.awi() + starlight.Awi.zero(4), RuntimeError)
+raises(lambda: a.awi() << 8, ValueError)
+raises(lambda: a.awi().get(8), ValueError)
+c = starlight.LazyAwi(4)
+c.retro_const(3)
+raises(lambda: c.retro(4), RuntimeError)
+raises(lambda: starlight.EvalAwi(a.awi()).eval(), RuntimeError)
+raises(lambda: starlight.route("", ""), RuntimeError)
+epoch.close()
+raises(lambda: epoch.optimize(), RuntimeError)
+"#,
+    );
+}
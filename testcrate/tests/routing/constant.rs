@@ -0,0 +1,114 @@
+use starlight::{
+    awi,
+    route::{fabrics::island, Configurator, ConstantOrigin, Router},
+    Corresponder, Epoch, In, LazyAwi, Net, Out,
+};
+
+#[test]
+fn route_constants() {
+    // a target where each output selects between the two inputs and tied off
+    // constants
+    let epoch = Epoch::new();
+    let inputs: Vec<In<1>> = (0..2).map(|_| In::opaque()).collect();
+    let mut outputs = vec![];
+    let mut configs = vec![];
+    for _ in 0..3 {
+        use starlight::dag::*;
+        let mut net = Net::opaque(bw(1));
+        net.push(&inputs[0]).unwrap();
+        net.push(&awi!(0)).unwrap();
+        net.push(&awi!(1)).unwrap();
+        net.push(&inputs[1]).unwrap();
+        outputs.push(Out::<1>::from_bits(&net).unwrap());
+        let config = LazyAwi::opaque(bw(2));
+        net.drive(&config).unwrap();
+        configs.push(config);
+    }
+    epoch.optimize().unwrap();
+    let mut configurator = Configurator::new();
+    for config in &configs {
+        configurator.configurable(config).unwrap();
+    }
+    let target_epoch = epoch.suspend();
+
+    let epoch = Epoch::new();
+    let (input, tied, copy, one, zero) = {
+        use starlight::dag::*;
+        let input = In::<1>::opaque();
+        // an input that is tied off by the program
+        let tied = In::<1>::opaque();
+        let mut x = awi!(tied);
+        x.and_(&awi!(0)).unwrap();
+        let copy = Out::<1>::from_bits(&input).unwrap();
+        let one = Out::<1>::from_bits(&awi!(1)).unwrap();
+        let zero = Out::<1>::from_bits(&x).unwrap();
+        (input, tied, copy, one, zero)
+    };
+    epoch.optimize().unwrap();
+    let program_epoch = epoch.suspend();
+
+    let mut corresponder = Corresponder::new();
+    corresponder.correspond_lazy(&input, &inputs[0]).unwrap();
+    corresponder.correspond_lazy(&tied, &inputs[1]).unwrap();
+    corresponder.correspond_eval(&copy, &outputs[0]).unwrap();
+    corresponder.correspond_eval(&one, &outputs[1]).unwrap();
+    corresponder.correspond_eval(&zero, &outputs[2]).unwrap();
+    let mut router =
+        Router::new(&target_epoch, &configurator, &program_epoch, &corresponder).unwrap();
+    router.route().unwrap();
+
+    // the constants are routed from the tied off selector inputs
+    let routes = router.constant_routes();
+    assert_eq!(routes.len(), 2);
+    for route in routes {
+        assert!(matches!(route.origin, ConstantOrigin::Source(_)));
+        assert!(!route.path.edges().is_empty());
+    }
+    let num_inputs = router.simulation_inputs().len();
+    let vectors = (0..4).map(|i| vec![awi::Awi::from_bool((i % 2) == 1); num_inputs]);
+    assert_eq!(router.verify_by_simulation(vectors).unwrap(), None);
+
+    let target_epoch = target_epoch.resume();
+    router.config_target().unwrap();
+    for b in [false, true] {
+        inputs[0].retro_bool_(b).unwrap();
+        inputs[1].retro_bool_(!b).unwrap();
+        assert_eq!(outputs[0].eval_bool().unwrap(), b);
+        assert!(outputs[1].eval_bool().unwrap());
+        assert!(!outputs[2].eval_bool().unwrap());
+    }
+    drop(inputs);
+    drop(outputs);
+    drop(configs);
+    drop(target_epoch);
+}
+
+#[test]
+fn route_constants_tie() {
+    // the island fabric has no constant sources, so a LUT is tied off
+    let fabric = island(2, 2, 2, 2).unwrap();
+    let epoch = Epoch::new();
+    let output = {
+        use starlight::dag::*;
+        Out::<1>::from_bits(&awi!(1)).unwrap()
+    };
+    epoch.optimize().unwrap();
+    let program_epoch = epoch.suspend();
+
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_eval(&output, &fabric.outputs[0])
+        .unwrap();
+    let mut router = Router::new(
+        &fabric.epoch,
+        &fabric.configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+    router.route().unwrap();
+    let routes = router.constant_routes();
+    assert_eq!(routes.len(), 1);
+    assert!(matches!(routes[0].origin, ConstantOrigin::Tie(_)));
+    assert_eq!(router.verify_by_simulation([vec![]]).unwrap(), None);
+}
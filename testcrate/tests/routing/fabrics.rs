@@ -0,0 +1,399 @@
+use starlight::{
+    awi, dag,
+    route::{
+        fabrics::island, Channeler, ClusteringHeuristic, Configurator, EmulationSchedule,
+        HierarchyConfig, QCEdge, QCNode, Router, ScheduleConfig, SlotSource,
+    },
+    triple_arena::Advancer,
+    utils::StarRng,
+    Corresponder, Epoch, EvalAwi, In, LazyAwi, Loop, MacroOp, Out,
+};
+
+#[test]
+fn island_fabric() {
+    let fabric = island(3, 3, 4, 2).unwrap();
+    assert_eq!(fabric.tiles.len(), (3, 3));
+    // every tile except the center is on the perimeter
+    assert_eq!(fabric.inputs.len(), 8);
+    assert_eq!(fabric.outputs.len(), 8);
+    fabric.tiles.for_each(|tile, _| {
+        assert_eq!(tile.lut.bw(), 16);
+        assert_eq!(tile.lut_input_selectors.len(), 4);
+        assert_eq!(tile.wire_selectors.len(), 2);
+    });
+    assert!(fabric.tiles.get((1, 1)).unwrap().output_selector.is_none());
+    fabric.channeler.verify_integrity().unwrap();
+
+    assert!(island(0, 3, 4, 2).is_err());
+    assert!(island(3, 3, 0, 2).is_err());
+    assert!(island(3, 3, 4, 0).is_err());
+    assert!(island(1, 1, usize::MAX, 1).is_err());
+
+    // route a copy from one corner to the opposite corner
+    let epoch = Epoch::new();
+    let input = In::<1>::opaque();
+    let output = Out::<1>::from_bits(&input).unwrap();
+    epoch.optimize().unwrap();
+    let program_epoch = epoch.suspend();
+
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_lazy(&input, &fabric.inputs[0])
+        .unwrap();
+    corresponder
+        .correspond_eval(&output, fabric.outputs.last().unwrap())
+        .unwrap();
+    let mut router = Router::new(
+        &fabric.epoch,
+        &fabric.configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+    router.route().unwrap();
+}
+
+#[test]
+fn island_preserved_mul() {
+    let fabric = island(3, 3, 4, 2).unwrap();
+    let epoch = Epoch::new();
+    epoch.preserve_macro_ops(&[MacroOp::Mul]);
+    let lhs = In::<1>::opaque();
+    let rhs = In::<1>::opaque();
+    let mut prod = dag::Awi::zero(dag::bw(1));
+    prod.arb_umul_add_(&lhs, &rhs);
+    let output = Out::<1>::from_bits(&prod).unwrap();
+    epoch.optimize().unwrap();
+    assert_eq!(epoch.ensemble(|ensemble| ensemble.mul_nodes.len()), 1);
+    let program_epoch = epoch.suspend();
+
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_lazy(&lhs, &fabric.inputs[0])
+        .unwrap();
+    corresponder
+        .correspond_lazy(&rhs, &fabric.inputs[1])
+        .unwrap();
+    corresponder
+        .correspond_eval(&output, fabric.outputs.last().unwrap())
+        .unwrap();
+    // the router uses the decomposition instead of the `MulNode`
+    let router = Router::new(
+        &fabric.epoch,
+        &fabric.configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+    assert!(router.program_ensemble().mul_nodes.is_empty());
+    router.program_channeler().verify_integrity().unwrap();
+
+    // the exported netlist only has the ports of the program
+    let program_epoch = program_epoch.resume();
+    let blif = program_epoch.ensemble(|ensemble| ensemble.write_blif("mul", "clock").unwrap());
+    let outputs = blif
+        .lines()
+        .find(|line| line.starts_with(".outputs"))
+        .unwrap();
+    assert_eq!(outputs.split(' ').count(), 2);
+    drop(lhs);
+    drop(rhs);
+    drop(output);
+    drop(program_epoch);
+}
+
+#[test]
+fn island_regions() {
+    let fabric = island(3, 3, 2, 2).unwrap();
+    // tile (1, 0) is a partial reconfiguration region, and the rest of the
+    // tiles are another region
+    let mut configurator = Configurator::new();
+    let (epoch, res) = fabric.epoch.scope(|_| {
+        let mut res = Ok(());
+        fabric.tiles.for_each(|tile, ij| {
+            let region = usize::from(ij == (1, 0));
+            for config in [&tile.lut]
+                .into_iter()
+                .chain(&tile.lut_input_selectors)
+                .chain(&tile.wire_selectors)
+                .chain(&tile.output_selector)
+            {
+                if res.is_ok() {
+                    res = configurator.configurable_in_region(config, region);
+                }
+            }
+        });
+        res
+    });
+    res.unwrap();
+
+    // route a copy from tile (0, 0) to tile (2, 0), where the shortest path
+    // goes through tile (1, 0)
+    let program = Epoch::new();
+    let input = In::<1>::opaque();
+    let output = Out::<1>::from_bits(&input).unwrap();
+    program.optimize().unwrap();
+    let program_epoch = program.suspend();
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_lazy(&input, &fabric.inputs[0])
+        .unwrap();
+    corresponder
+        .correspond_eval(&output, &fabric.outputs[2])
+        .unwrap();
+    let router = Router::new(&epoch, &configurator, &program_epoch, &corresponder).unwrap();
+    assert_eq!(router.regions(), vec![0, 1]);
+    let mut unrestricted = router.clone();
+    unrestricted.route().unwrap();
+    assert_eq!(unrestricted.region_report().touched, vec![0, 1]);
+
+    let mut router0 = router.clone();
+    router0.route_into_region(0).unwrap();
+    let report = router0.region_report();
+    assert_eq!(report.touched, vec![0]);
+    assert_eq!(report.untouched, vec![1]);
+    assert!(!report.touches_static);
+    let segment = router0.region_segment(0);
+    assert!(segment.iter().all(|config| config.region == Some(0)));
+    assert!(segment.iter().any(|config| config.value.is_some()));
+    assert!(router0
+        .region_segment(1)
+        .iter()
+        .all(|config| config.value.is_none()));
+    let bitstream = router0.region_bitstream(0).unwrap();
+    assert_eq!(bitstream.bw(), segment.len());
+    assert!(router0.region_bitstream(2).is_none());
+
+    // the pads are not in region 1
+    assert!(router.clone().route_into_region(1).is_err());
+    assert!(router.clone().route_into_region(2).is_err());
+    drop(epoch);
+}
+
+#[test]
+fn island_time_multiplexed() {
+    let fabric = island(3, 3, 4, 2).unwrap();
+    let input_pads: Vec<_> = fabric.inputs.iter().map(|x| (x.p_external(), 0)).collect();
+    let output_pads: Vec<_> = fabric.outputs.iter().map(|x| (x.p_external(), 0)).collect();
+
+    // a program with far more lookup tables than the 9 tiles of the target
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(awi::bw(4));
+    let b = LazyAwi::opaque(awi::bw(4));
+    let counter = Loop::zero(awi::bw(4));
+    let mut next = dag::Awi::from(counter.as_ref());
+    next.add_(&a).unwrap();
+    let count = EvalAwi::from(counter.as_ref());
+    let mut y = dag::Awi::from(a.as_ref());
+    y.mul_add_(&b, &counter).unwrap();
+    counter.drive_with_delay(&next, 1).unwrap();
+    let y = EvalAwi::from(&y);
+    epoch.optimize().unwrap();
+    let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    let program_epoch = epoch.suspend();
+    assert!(num_lnodes > 9);
+
+    let config = ScheduleConfig {
+        max_lnodes: 9,
+        max_inputs: input_pads.len(),
+        max_outputs: output_pads.len(),
+    };
+    let schedule = EmulationSchedule::new(&program_epoch, &config).unwrap();
+    assert!(schedule.slices().len() >= num_lnodes.div_ceil(9));
+    let mut num_scheduled = 0;
+    for (slice_i, slice) in schedule.slices().iter().enumerate() {
+        num_scheduled += slice.lnodes().len();
+        assert!(slice.lnodes().len() <= config.max_lnodes);
+        assert!(slice.restore().len() <= config.max_inputs);
+        assert!(slice.save().len() <= config.max_outputs);
+        // slices only restore values from before them
+        for (_, slot) in slice.restore() {
+            if let SlotSource::Slice(i) = schedule.slots()[*slot].source {
+                assert!(i < slice_i);
+            }
+        }
+    }
+    assert_eq!(num_scheduled, num_lnodes);
+    assert_eq!(
+        schedule
+            .slots()
+            .iter()
+            .filter(|slot| matches!(slot.source, SlotSource::Register { .. }))
+            .count(),
+        4
+    );
+
+    // emulating the slices through the state memory matches the program
+    let program_epoch = {
+        use awi::*;
+        let mut emulator = schedule.emulator().unwrap();
+        let program_epoch = program_epoch.resume();
+        let mut rng = StarRng::new(0);
+        for _ in 0..16 {
+            let mut v = awi!(0u4);
+            v.u8_(rng.next_u8());
+            a.retro_(&v).unwrap();
+            emulator.set(a.p_external(), &v).unwrap();
+            v.u8_(rng.next_u8());
+            b.retro_(&v).unwrap();
+            emulator.set(b.p_external(), &v).unwrap();
+            emulator.evaluate();
+            assert_eq!(
+                emulator.get(count.p_external()).unwrap(),
+                count.eval().unwrap()
+            );
+            assert_eq!(emulator.get(y.p_external()).unwrap(), y.eval().unwrap());
+            program_epoch.run(1).unwrap();
+            emulator.step();
+        }
+        assert!(emulator.set(y.p_external(), &awi!(0u4)).is_err());
+        assert!(emulator.set(a.p_external(), &awi!(0u8)).is_err());
+        program_epoch.suspend()
+    };
+
+    // the first slice is mapped onto the pads of the target
+    let router = schedule
+        .slice_router(
+            0,
+            &fabric.epoch,
+            &fabric.configurator,
+            &input_pads,
+            &output_pads,
+        )
+        .unwrap();
+    let slice = &schedule.slices()[0];
+    assert_eq!(
+        router.mappings().len(),
+        slice.restore().len() + slice.save().len()
+    );
+    assert!(schedule
+        .slice_router(
+            0,
+            &fabric.epoch,
+            &fabric.configurator,
+            &input_pads[..0],
+            &output_pads
+        )
+        .is_err());
+    assert!(schedule
+        .slice_router(
+            schedule.slices().len(),
+            &fabric.epoch,
+            &fabric.configurator,
+            &input_pads,
+            &output_pads
+        )
+        .is_err());
+
+    let tight = ScheduleConfig {
+        max_inputs: 1,
+        ..config
+    };
+    assert!(EmulationSchedule::new(&program_epoch, &tight).is_err());
+    drop(program_epoch);
+}
+
+#[test]
+fn channeler_hierarchy_config() {
+    let fabric = island(4, 4, 2, 2).unwrap();
+    let default_levels = fabric.channeler.debug_hierarchy().lines().count();
+    assert!(default_levels > 2);
+    for heuristic in [
+        ClusteringHeuristic::WidestChannel,
+        ClusteringHeuristic::NarrowestChannel,
+        ClusteringHeuristic::SmallestSubtree,
+    ] {
+        for (max_branching, max_levels) in [(usize::MAX, u16::MAX), (2, u16::MAX), (3, 2)] {
+            let config = HierarchyConfig {
+                max_branching,
+                max_levels,
+                heuristic,
+            };
+            let channeler: Channeler<QCNode, QCEdge> =
+                Channeler::from_target_with_config(&fabric.epoch, &fabric.configurator, &config)
+                    .unwrap();
+            channeler.verify_integrity().unwrap();
+            assert_eq!(channeler.top_level_cnodes.len(), 1);
+            // the bottom level, the generated levels, and the final level
+            let levels = channeler.debug_hierarchy().lines().count();
+            assert!(levels <= usize::from(max_levels.saturating_add(2)));
+            for cnode in channeler.cnodes.vals() {
+                let mut subnodes = 0;
+                let mut adv = channeler.advancer_subnodes_of_node(cnode.p_this_cnode);
+                while adv.advance(&channeler).is_some() {
+                    subnodes += 1;
+                }
+                // the final concentration is not limited
+                if channeler.get_supernode(cnode.p_this_cnode).is_some() {
+                    assert!(subnodes <= max_branching);
+                }
+            }
+        }
+    }
+    for config in [
+        HierarchyConfig {
+            max_branching: 1,
+            ..Default::default()
+        },
+        HierarchyConfig {
+            max_levels: 0,
+            ..Default::default()
+        },
+    ] {
+        assert!(Channeler::<QCNode, QCEdge>::from_target_with_config(
+            &fabric.epoch,
+            &fabric.configurator,
+            &config
+        )
+        .is_err());
+    }
+}
+
+#[test]
+fn island_fabric_cli() {
+    use starlight::cli;
+
+    // name the pads and every configuration value so that they survive being
+    // written to the text format
+    let fabric = island(3, 3, 4, 2).unwrap();
+    let (target_epoch, (target_ir, num_configs)) = fabric.epoch.scope(|epoch| {
+        for (i, input) in fabric.inputs.iter().enumerate() {
+            input.set_debug_name(format!("in{i}")).unwrap();
+        }
+        for (i, output) in fabric.outputs.iter().enumerate() {
+            output.set_debug_name(format!("out{i}")).unwrap();
+        }
+        let mut i = 0;
+        fabric.tiles.for_each(|tile, _| {
+            let configs = [&tile.lut]
+                .into_iter()
+                .chain(tile.lut_input_selectors.iter())
+                .chain(tile.wire_selectors.iter())
+                .chain(tile.output_selector.iter());
+            for config in configs {
+                config.set_debug_name(format!("config{i}")).unwrap();
+                i += 1;
+            }
+        });
+        (epoch.ensemble(|ensemble| ensemble.write_ir()), i)
+    });
+    drop(target_epoch);
+
+    let epoch = Epoch::new();
+    let input = In::<1>::opaque();
+    input.set_debug_name("in0").unwrap();
+    let output = Out::<1>::from_bits(&input).unwrap();
+    output.set_debug_name("out7").unwrap();
+    epoch.optimize().unwrap();
+    let program_ir = epoch.ensemble(|ensemble| ensemble.write_ir());
+    drop(epoch);
+
+    let configs = cli::route_ir(&program_ir, &target_ir, "config").unwrap();
+    assert_eq!(configs.lines().count(), num_configs);
+    assert!(configs.lines().all(|line| line.starts_with("config")));
+    // a real route has to set something
+    assert!(configs.lines().any(|line| !line.contains("= 0x0_")));
+    // the program output is writable in the target
+    let bad_program_ir = program_ir.replace("\"in0\"", "\"out0\"");
+    assert!(cli::route_ir(&bad_program_ir, &target_ir, "config").is_err());
+}
@@ -1,3 +1,6 @@
+mod constant;
+mod fabrics;
+mod pack;
 mod pure;
 mod targets;
 
@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+
+use starlight::{awi::bw, dag, route::Packing, Epoch, EvalAwi, LazyAwi};
+
+#[test]
+fn pack_clusters() {
+    let epoch = Epoch::new();
+    // two independent halves that only share their inputs
+    let a = LazyAwi::opaque(bw(16));
+    let b = LazyAwi::opaque(bw(16));
+    let mut x = dag::Awi::from(&a);
+    x.xor_(&b).unwrap();
+    x.rotl_(5).unwrap();
+    x.and_(&a).unwrap();
+    let mut y = dag::Awi::from(&b);
+    y.or_(&a).unwrap();
+    y.rotr_(3).unwrap();
+    y.xor_(&b).unwrap();
+    let _x = EvalAwi::from(&x);
+    let _y = EvalAwi::from(&y);
+    epoch.optimize().unwrap();
+
+    let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    assert!(num_lnodes > 8);
+    for max_cluster_size in [1, 4, 8, num_lnodes] {
+        let packing = epoch
+            .ensemble(|ensemble| Packing::new(ensemble, max_cluster_size))
+            .unwrap();
+        // every `LNode` is in exactly one cluster
+        let mut seen = HashSet::new();
+        for cluster in packing.clusters() {
+            assert!(!cluster.is_empty());
+            assert!(cluster.len() <= max_cluster_size);
+            for p_lnode in cluster {
+                assert!(seen.insert(*p_lnode));
+            }
+        }
+        assert_eq!(seen.len(), num_lnodes);
+        if max_cluster_size == num_lnodes {
+            assert_eq!(packing.clusters().len(), 1);
+            assert_eq!(packing.cut_nets(), 0);
+        }
+    }
+    assert!(epoch
+        .ensemble(|ensemble| Packing::new(ensemble, 0))
+        .is_err());
+    drop(epoch);
+}
@@ -1,6 +1,11 @@
 //! pure routing with no combinatorics
 
-use starlight::{route::Router, Corresponder, Epoch, In, Out, SuspendedEpoch};
+use starlight::{
+    awi, dag, delay,
+    route::{ConfigDelta, Configurator, Router},
+    utils::Trace,
+    Corresponder, Delay, Epoch, In, LazyAwi, Net, Out, SuspendedEpoch,
+};
 
 use super::FabricTargetInterface;
 
@@ -49,5 +54,336 @@ fn route_pure() {
     )
     .unwrap();
 
+    // before routing the target outputs are not configured
+    let vectors = [vec![awi::Awi::from_bool(true)]];
+    let divergence = router.verify_by_simulation(vectors).unwrap().unwrap();
+    assert_eq!(divergence.cycle, 0);
+    assert!(divergence.expected);
+    assert_eq!(divergence.target.0, target.outputs[output_i].p_external());
+
+    router.route().unwrap();
+
+    assert_eq!(router.simulation_inputs(), vec![program.input.p_external()]);
+    let vectors = (0..4).map(|i| vec![awi::Awi::from_bool((i % 2) == 1)]);
+    assert_eq!(router.verify_by_simulation(vectors).unwrap(), None);
+    assert!(router.verify_by_simulation([vec![]]).is_err());
+
+    // the configured target itself copies the input
+    let target_epoch = target_epoch.resume();
+    router.config_target().unwrap();
+    for b in [false, true] {
+        target.inputs[input_i].retro_bool_(b).unwrap();
+        assert_eq!(target.outputs[output_i].eval_bool().unwrap(), b);
+    }
+    drop(target);
+    drop(target_epoch);
+}
+
+#[test]
+fn route_pure_verify() {
+    let (target, target_configurator, target_epoch) = FabricTargetInterface::target((2, 2));
+    let (program, program_epoch) = SimpleCopyProgramInterface::program();
+
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_lazy(&program.input, &target.inputs[0])
+        .unwrap();
+    corresponder
+        .correspond_eval(&program.output, &target.outputs[0])
+        .unwrap();
+    let mut router = Router::new(
+        &target_epoch,
+        &target_configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+    router.route().unwrap();
+
+    let vectors = || (0..4).map(|i| vec![awi::Awi::from_bool((i % 2) == 1)]);
+    assert_eq!(router.verify_by_simulation(vectors()).unwrap(), None);
+
+    // flip every determined configuration bit in turn, at least one of them
+    // must break the copy in the first cycle
+    let p_configs: Vec<_> = router
+        .configurator()
+        .configurations
+        .ptrs()
+        .filter(|p| {
+            router
+                .configurator()
+                .configurations
+                .get_val(*p)
+                .unwrap()
+                .value
+                .is_some()
+        })
+        .collect();
+    let mut diverged = false;
+    for p_config in p_configs {
+        let mut corrupted = router.clone();
+        let config = corrupted
+            .configurator_mut()
+            .configurations
+            .get_val_mut(p_config)
+            .unwrap();
+        config.value = config.value.map(|b| !b);
+        if let Some(divergence) = corrupted.verify_by_simulation(vectors()).unwrap() {
+            diverged = true;
+            assert_eq!(divergence.cycle, 0);
+            // the output is the same equivalence as the input in the program
+            assert!([program.input.p_external(), program.output.p_external()]
+                .contains(&divergence.program.0));
+            assert_eq!(divergence.target, (target.outputs[0].p_external(), 0));
+            assert!(!divergence.expected);
+        }
+    }
+    assert!(diverged);
+    drop(target);
+    drop(target_epoch);
+}
+
+#[test]
+fn route_pure_bits() {
+    let (target, target_configurator, target_epoch) = FabricTargetInterface::target((2, 2));
+    let epoch = Epoch::new();
+    let input = In::<2>::opaque();
+    let output = Out::<2>::from_bits(&input).unwrap();
+    epoch.optimize().unwrap();
+    let program_epoch = epoch.suspend();
+
+    // a wide program value split across narrower target values
+    let mut corresponder = Corresponder::new();
+    for (bit_i, target_i) in [(0, 0), (1, target.inputs.len() - 1)] {
+        corresponder
+            .correspond_lazy_bits(&input, bit_i..(bit_i + 1), &target.inputs[target_i], 0..1)
+            .unwrap();
+    }
+    for (bit_i, target_i) in [(0, 0), (1, target.outputs.len() - 1)] {
+        corresponder
+            .correspond_eval_bits(&output, bit_i..(bit_i + 1), &target.outputs[target_i], 0..1)
+            .unwrap();
+    }
+
+    let mut router = Router::new(
+        &target_epoch,
+        &target_configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+
+    router.route().unwrap();
+    let vectors = (0..4u8).map(|i| {
+        let mut x = awi::Awi::zero(awi::bw(2));
+        x.u8_(i);
+        vec![x]
+    });
+    assert_eq!(router.verify_by_simulation(vectors).unwrap(), None);
+}
+
+#[test]
+fn route_pure_packed() {
+    let (target, target_configurator, target_epoch) = FabricTargetInterface::target((2, 2));
+    let epoch = Epoch::new();
+    let input = In::<2>::opaque();
+    let output = Out::<2>::from_bits(&input).unwrap();
+    epoch.optimize().unwrap();
+    let program_epoch = epoch.suspend();
+
+    let mut corresponder = Corresponder::new();
+    let last = target.inputs.len() - 1;
+    corresponder
+        .correspond_lazy_packed(&input, &[&target.inputs[0], &target.inputs[last]])
+        .unwrap();
+    let last = target.outputs.len() - 1;
+    corresponder
+        .correspond_eval_packed(&output, &[&target.outputs[0], &target.outputs[last]])
+        .unwrap();
+
+    let mut router = Router::new(
+        &target_epoch,
+        &target_configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+
     router.route().unwrap();
+    let vectors = (0..4u8).map(|i| {
+        let mut x = awi::Awi::zero(awi::bw(2));
+        x.u8_(i);
+        vec![x]
+    });
+    assert_eq!(router.verify_by_simulation(vectors).unwrap(), None);
+}
+
+#[test]
+fn route_pure_trace() {
+    let (target, target_configurator, target_epoch) = FabricTargetInterface::target((2, 2));
+    let (program, program_epoch) = SimpleCopyProgramInterface::program();
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_lazy(&program.input, &target.inputs[0])
+        .unwrap();
+    corresponder
+        .correspond_eval(&program.output, &target.outputs[0])
+        .unwrap();
+    let mut router = Router::new(
+        &target_epoch,
+        &target_configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+
+    let ((), trace) = Trace::record(|| router.route().unwrap());
+    let route = trace.filter("route");
+    assert_eq!(route.spans()[0].name, "Router::route");
+    assert_eq!(route.spans()[0].parent, None);
+    assert!(route.spans().iter().any(|span| span.name == "route_level"));
+    assert!(route
+        .events()
+        .iter()
+        .all(|event| event.name == "route_level iteration"));
+    assert!(route.folded().contains("route::Router::route;route::route"));
+}
+
+#[test]
+fn route_pure_reconfiguration() {
+    let (target, target_configurator, target_epoch) = FabricTargetInterface::target((2, 2));
+    let (program, program_epoch) = SimpleCopyProgramInterface::program();
+    let last = target.inputs.len() - 1;
+    // the same output copying from two different inputs
+    let mut routers = vec![];
+    for input_i in [0, last] {
+        let mut corresponder = Corresponder::new();
+        corresponder
+            .correspond_lazy(&program.input, &target.inputs[input_i])
+            .unwrap();
+        corresponder
+            .correspond_eval(&program.output, &target.outputs[0])
+            .unwrap();
+        let mut router = Router::new(
+            &target_epoch,
+            &target_configurator,
+            &program_epoch,
+            &corresponder,
+        )
+        .unwrap();
+        router.route().unwrap();
+        routers.push(router);
+    }
+    let delta = routers[0].config_delta(&routers[1]);
+    assert!(!delta.is_empty());
+    assert!(routers[0].config_delta(&routers[0]).is_empty());
+
+    let target_epoch = target_epoch.resume();
+    routers[0].config_target().unwrap();
+    target.inputs[0].retro_bool_(true).unwrap();
+    target.inputs[last].retro_bool_(false).unwrap();
+    routers[0].apply_config_at(10, &delta).unwrap();
+    target_epoch.run(5).unwrap();
+    assert!(target.outputs[0].eval_bool().unwrap());
+    target_epoch.run(5).unwrap();
+    assert!(!target.outputs[0].eval_bool().unwrap());
+    target.inputs[last].retro_bool_(true).unwrap();
+    assert!(target.outputs[0].eval_bool().unwrap());
+
+    assert!(routers[0].apply_config_at(5, &delta).is_err());
+    let mut not_configurable = ConfigDelta::new();
+    not_configurable.push(&target.inputs[0], 0, Some(true));
+    assert!(routers[0].apply_config_at(20, &not_configurable).is_err());
+    drop(target);
+    drop(target_epoch);
+}
+
+#[test]
+fn route_pure_back_annotate() {
+    // a target where the only way from the input to the output is through a
+    // wire with a delay of 3
+    let target_epoch = Epoch::new();
+    let target_input = In::<1>::opaque();
+    let mut wire = dag::Awi::from(target_input.as_ref());
+    delay(&mut wire, 3);
+    let mut net = Net::opaque(dag::bw(1));
+    net.push(&wire).unwrap();
+    net.push(&dag::Awi::zero(dag::bw(1))).unwrap();
+    let target_output = Out::<1>::from_bits(&net).unwrap();
+    let config = LazyAwi::opaque(dag::bw(1));
+    net.drive(&config).unwrap();
+    target_epoch.optimize().unwrap();
+    let mut target_configurator = Configurator::new();
+    target_configurator.configurable(&config).unwrap();
+    let target_epoch = target_epoch.suspend();
+
+    let (program, program_epoch) = SimpleCopyProgramInterface::program();
+    let mut corresponder = Corresponder::new();
+    corresponder
+        .correspond_lazy(&program.input, &target_input)
+        .unwrap();
+    corresponder
+        .correspond_eval(&program.output, &target_output)
+        .unwrap();
+    let mut router = Router::new(
+        &target_epoch,
+        &target_configurator,
+        &program_epoch,
+        &corresponder,
+    )
+    .unwrap();
+    router.route().unwrap();
+
+    let delays = router.interconnect_delays();
+    assert_eq!(delays.len(), 1);
+    assert_eq!(delays[0].1, Delay::from(3));
+    // the program itself is not changed, and annotating again gives the same result
+    assert!(program_epoch.ensemble(|ensemble| ensemble.tnodes.is_empty()));
+    assert_eq!(router.back_annotate().unwrap().tnodes.len(), 1);
+    let annotated = router.back_annotate().unwrap();
+    assert_eq!(annotated.tnodes.len(), 1);
+
+    // the annotated program sees the input after the routing delay
+    let (p_input, p_output) = (program.input.p_external(), program.output.p_external());
+    let epoch = Epoch::from_ensemble(annotated);
+    epoch.poke(p_input, &awi::Awi::from_bool(false)).unwrap();
+    epoch.run(Delay::from(4)).unwrap();
+    assert_eq!(
+        epoch.peek(p_output).unwrap(),
+        Some(awi::Awi::from_bool(false))
+    );
+    epoch.poke(p_input, &awi::Awi::from_bool(true)).unwrap();
+    for t in 0..5 {
+        let expected = awi::Awi::from_bool(t >= 3);
+        assert_eq!(epoch.peek(p_output).unwrap(), Some(expected));
+        epoch.run(Delay::from(1)).unwrap();
+    }
+    drop(epoch);
+
+    // the balanced copy uses registers, so a new input can start every cycle
+    let (balanced, wrapper) = router.generate_latency_wrapper().unwrap();
+    assert_eq!(wrapper.latency(), Delay::from(3));
+    assert_eq!(balanced.tnodes.len(), 3);
+    assert!(program_epoch.ensemble(|ensemble| ensemble.tnodes.is_empty()));
+    let epoch = Epoch::from_ensemble(balanced);
+    let mut history = vec![];
+    for t in 0..10 {
+        let b = (t % 3) == 1;
+        history.push(b);
+        epoch.poke(p_input, &awi::Awi::from_bool(b)).unwrap();
+        epoch.run(Delay::from(1)).unwrap();
+        if t >= 3 {
+            assert_eq!(
+                epoch.peek(p_output).unwrap(),
+                Some(awi::Awi::from_bool(history[t - 2]))
+            );
+        }
+    }
+    drop(epoch);
+    drop(program);
+    drop(program_epoch);
+    drop(config);
+    drop(target_input);
+    drop(target_output);
+    drop(target_epoch);
 }
@@ -0,0 +1,157 @@
+use starlight::{
+    awi, awi::*, dag, ensemble::BATCH_LANES, utils::StarRng, Epoch, EvalAwi, EvalCacheStats,
+    LazyAwi, Loop,
+};
+
+mod common;
+use common::*;
+
+#[test]
+fn eval_cache() {
+    let (epoch, a, b, x) = optimized_xor_rotl_and();
+    assert!(epoch.eval_cache_stats().is_none());
+    epoch.enable_eval_cache(bw(64));
+    for (va, vb) in INPUTS {
+        a.retro_u8_(va).unwrap();
+        b.retro_u8_(vb).unwrap();
+        let res = xor_rotl_and_ref(va, vb);
+        // the first evaluation goes through the evaluator, the rest are cached
+        for _ in 0..3 {
+            assert_eq!(x.eval_u8().unwrap(), res);
+        }
+    }
+    let stats: EvalCacheStats = epoch.eval_cache_stats().unwrap();
+    assert_eq!(stats.misses, 4 * 8);
+    assert_eq!(stats.hits, 4 * 2 * 8);
+    assert_eq!(stats.invalidations, 3);
+    assert_eq!(stats.evictions, 0);
+
+    // a tiny cache keeps being evicted but stays correct
+    epoch.enable_eval_cache(bw(1));
+    a.retro_u8_(0x12).unwrap();
+    b.retro_u8_(0x34).unwrap();
+    for _ in 0..2 {
+        assert_eq!(x.eval_u8().unwrap(), xor_rotl_and_ref(0x12, 0x34));
+    }
+    let stats = epoch.disable_eval_cache().unwrap();
+    assert_eq!(stats.hits + stats.misses, 2 * 8);
+    assert!(stats.evictions > 0);
+    assert!(epoch.disable_eval_cache().is_none());
+    drop(epoch);
+}
+
+#[test]
+fn batch_sim() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let b = LazyAwi::opaque(bw(4));
+    let counter = Loop::zero(bw(4));
+    let mut next = dag::Awi::from(counter.as_ref());
+    next.add_(&a).unwrap();
+    let count = EvalAwi::from(counter.as_ref());
+    counter.drive_with_delay(&next, 1).unwrap();
+    let mut y = dag::Awi::from(a.as_ref());
+    y.mul_add_(&b, &a).unwrap();
+    let y = EvalAwi::from(&y);
+    let mut sim = epoch.batch_sim().unwrap();
+    assert!(sim.set(y.p_external(), 0, &awi!(0u4)).is_err());
+    assert!(sim.set(a.p_external(), BATCH_LANES, &awi!(0u4)).is_err());
+    assert!(sim.set(a.p_external(), 0, &awi!(0u8)).is_err());
+
+    let mut rng = StarRng::new(0);
+    let mut counts = [0u8; BATCH_LANES];
+    for _ in 0..8 {
+        sim.randomize(a.p_external(), &mut rng).unwrap();
+        let mut b_vals = [0u8; BATCH_LANES];
+        for (lane, b_val) in b_vals.iter_mut().enumerate() {
+            *b_val = rng.next_u8() & 0xf;
+            let mut v = awi!(0u4);
+            v.u8_(*b_val);
+            sim.set(b.p_external(), lane, &v).unwrap();
+        }
+        sim.evaluate();
+        let a_words = sim.get_words(a.p_external()).unwrap();
+        for (lane, b_val) in b_vals.iter().enumerate() {
+            let mut a_val = 0u8;
+            for (j, word) in a_words.iter().enumerate() {
+                a_val |= u8::from(((word >> lane) & 1) != 0) << j;
+            }
+            assert_eq!(
+                sim.get(count.p_external(), lane).unwrap().to_u8(),
+                counts[lane]
+            );
+            assert_eq!(
+                sim.get(y.p_external(), lane).unwrap().to_u8(),
+                a_val.wrapping_mul(*b_val).wrapping_add(a_val) & 0xf
+            );
+            counts[lane] = counts[lane].wrapping_add(a_val) & 0xf;
+        }
+        sim.step();
+    }
+    let profile = sim.profile();
+    assert!(profile.total_toggles() > 0);
+    sim.clear_toggles();
+    assert_eq!(sim.profile().total_toggles(), 0);
+
+    // lane 0 agrees with the event driven evaluator
+    let mut sim = epoch.batch_sim().unwrap();
+    {
+        use awi::*;
+        let mut v = awi!(0u4);
+        for _ in 0..4 {
+            let a_val = rng.next_u8() & 0xf;
+            v.u8_(a_val);
+            a.retro_(&v).unwrap();
+            sim.set(a.p_external(), 0, &v).unwrap();
+            v.u8_(rng.next_u8() & 0xf);
+            b.retro_(&v).unwrap();
+            sim.set(b.p_external(), 0, &v).unwrap();
+            sim.evaluate();
+            assert_eq!(
+                sim.get(count.p_external(), 0).unwrap(),
+                count.eval().unwrap()
+            );
+            assert_eq!(sim.get(y.p_external(), 0).unwrap(), y.eval().unwrap());
+            epoch.run(1).unwrap();
+            sim.step();
+        }
+    }
+    drop(epoch);
+
+    // a pipeline of delay 1 registers agrees with the event driven evaluator, but
+    // single `TNode`s with larger delays sample at fire time and are rejected
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let mut x = dag::Awi::from(a.as_ref());
+    for _ in 0..3 {
+        let reg = Loop::zero(bw(4));
+        let out = dag::Awi::from(reg.as_ref());
+        reg.drive_with_delay(&x, 1).unwrap();
+        x = out;
+    }
+    let x = EvalAwi::from(&x);
+    let mut sim = epoch.batch_sim().unwrap();
+    {
+        use awi::*;
+        let mut v = awi!(0u4);
+        for i in 0..8 {
+            v.u8_(i);
+            a.retro_(&v).unwrap();
+            sim.set(a.p_external(), 0, &v).unwrap();
+            sim.evaluate();
+            assert_eq!(sim.get(x.p_external(), 0).unwrap(), x.eval().unwrap());
+            assert_eq!(x.eval().unwrap().to_u8(), i.saturating_sub(3));
+            epoch.run(1).unwrap();
+            sim.step();
+        }
+    }
+    drop(epoch);
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let mut y = dag::Awi::from(a.as_ref());
+    starlight::delay(&mut y, 3);
+    let _y = EvalAwi::from(&y);
+    let err = format!("{}", epoch.batch_sim().unwrap_err());
+    assert!(err.contains("delay 3"));
+    drop(epoch);
+}
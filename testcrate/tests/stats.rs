@@ -1,4 +1,13 @@
-use starlight::{awi, dag, Delay, Epoch, EvalAwi, LazyAwi, Net};
+use std::panic::catch_unwind;
+
+use starlight::{
+    awi, dag,
+    ensemble::Ensemble,
+    lower::LutDecomposition,
+    sweep::{format_table, Sweep},
+    utils::{IterationAudit, Trace},
+    Delay, Epoch, Error, EvalAwi, LazyAwi, Loop, Net, OptimizePass,
+};
 
 // this is done separately from the benchmarks because getting the `ensemble` is
 // expensive
@@ -136,3 +145,411 @@ fn stats_loop_net() {
     }
     drop(epoch);
 }
+
+#[test]
+fn stats_sweep() {
+    let build = |w: &usize| {
+        if *w == 0 {
+            return Err(Error::OtherStr("zero width"))
+        }
+        let sum = {
+            use dag::*;
+            let a = LazyAwi::opaque(bw(*w));
+            let b = LazyAwi::opaque(bw(*w));
+            let mut sum = Awi::from(&a);
+            sum.add_(&b).unwrap();
+            EvalAwi::from(&sum)
+        };
+        Ok(sum)
+    };
+    let params = vec![0usize, 2, 4, 8, 16];
+    let sequential = Sweep::new().run(params.clone(), build);
+    let parallel = Sweep::new().threads(3).run(params.clone(), build);
+    assert_eq!(sequential.len(), params.len());
+    assert_eq!(parallel.len(), params.len());
+    for (lhs, rhs) in sequential.iter().zip(parallel.iter()) {
+        assert_eq!(lhs.param, rhs.param);
+        match (&lhs.stats, &rhs.stats) {
+            (Ok(lhs), Ok(rhs)) => assert_eq!(lhs, rhs),
+            (Err(_), Err(_)) => (),
+            _ => panic!(),
+        }
+    }
+    assert!(sequential[0].stats.is_err());
+    // wider adders are larger and have longer carry paths
+    for i in 2..sequential.len() {
+        let prev = sequential[i - 1].stats.as_ref().unwrap();
+        let next = sequential[i].stats.as_ref().unwrap();
+        assert!(prev.num_lnodes < next.num_lnodes);
+        assert!(prev.depth <= next.depth);
+    }
+    // weighting carries changes the estimated delay
+    let weighted = Sweep::new()
+        .carry_delay(Delay::from_amount(10))
+        .run(vec![16usize], build);
+    assert!(
+        weighted[0].stats.as_ref().unwrap().estimated_delay
+            >= sequential[4].stats.as_ref().unwrap().estimated_delay
+    );
+    let table = format_table(&sequential);
+    assert_eq!(table.lines().count(), params.len() + 1);
+}
+
+// identical comparisons and field masks are fused during lowering instead of
+// being lowered separately
+#[test]
+fn stats_fusion() {
+    // returns the number of `LNode`s after lowering, and the values of the output
+    // for every `(a, b, width)` in `vals`
+    let build = |twice_ult: bool, shared_width: bool, vals: &[(u8, u8, usize)]| {
+        let epoch = Epoch::new();
+        let (a, b, width, other_width, out) = {
+            use dag::*;
+            let a = LazyAwi::opaque(bw(8));
+            let b = LazyAwi::opaque(bw(8));
+            let width = LazyAwi::opaque(bw(4));
+            let other_width = LazyAwi::opaque(bw(4));
+            let lt = a.ult(&b).unwrap();
+            let mut x = Awi::from(&a);
+            x.mux_(&b, lt).unwrap();
+            let mut y = Awi::from(&b);
+            y.mux_(&a, if twice_ult { a.ult(&b).unwrap() } else { lt })
+                .unwrap();
+            let mut z = awi!(0u8);
+            z.field_width(&a, width.to_usize()).unwrap();
+            let mut w = awi!(0u8);
+            if shared_width {
+                w.field_width(&b, width.to_usize()).unwrap();
+            } else {
+                w.field_width(&b, other_width.to_usize()).unwrap();
+            }
+            let out = EvalAwi::from(&awi!(x, y, z, w));
+            (a, b, width, other_width, out)
+        };
+        epoch.lower().unwrap();
+        let num_lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+        let mut res = vec![];
+        for (a_val, b_val, width_val) in vals.iter().copied() {
+            use awi::*;
+            a.retro_u8_(a_val).unwrap();
+            b.retro_u8_(b_val).unwrap();
+            let mut tmp = Awi::zero(bw(4));
+            tmp.usize_(width_val);
+            width.retro_(&tmp).unwrap();
+            other_width.retro_(&tmp).unwrap();
+            res.push(out.eval().unwrap().to_u32());
+        }
+        drop(epoch);
+        (num_lnodes, res)
+    };
+    let vals = [(3, 200, 0), (200, 3, 5), (0x5a, 0x5a, 8), (17, 18, 3)];
+    let (reused, reused_res) = build(false, true, &vals);
+    let (fused, fused_res) = build(true, true, &vals);
+    let (unshared, unshared_res) = build(true, false, &vals);
+    // a comparison written twice ends up the same as if it were reused
+    assert_eq!(fused, reused);
+    // the mask logic of the second `field_width` is shared
+    assert!(fused < unshared);
+    assert_eq!(fused_res, reused_res);
+    assert_eq!(fused_res, unshared_res);
+    for (i, (a_val, b_val, width_val)) in vals.iter().copied().enumerate() {
+        let mask = ((1u32 << width_val) - 1) as u8;
+        let (x, y) = if a_val < b_val {
+            (b_val, a_val)
+        } else {
+            (a_val, b_val)
+        };
+        let expected = u32::from_be_bytes([x, y, a_val & mask, b_val & mask]);
+        assert_eq!(fused_res[i], expected);
+    }
+}
+
+// identical tables of many `LNode`s should share one allocation
+#[test]
+fn stats_lut_interning() {
+    use dag::*;
+    let epoch = Epoch::new();
+
+    let a = LazyAwi::opaque(bw(64));
+    let b = LazyAwi::opaque(bw(64));
+    let c = LazyAwi::opaque(bw(64));
+    let mut out = Awi::from(&a);
+    out.xor_(&b).unwrap();
+    out.and_(&c).unwrap();
+    let eval = EvalAwi::from(&out);
+    epoch.optimize().unwrap();
+    let stats = epoch.lut_table_stats();
+    assert_eq!(stats.num_luts, 128);
+    assert_eq!(stats.num_unique, 2);
+    assert!(stats.bytes * 32 < stats.bytes_without_interning);
+    epoch.ensemble(|ensemble| {
+        assert!(ensemble.lut_tables.len() <= 3);
+    });
+    {
+        use awi::*;
+        a.retro_(&awi!(0xffff_0000_ffff_0000_u64)).unwrap();
+        b.retro_(&awi!(0xff00_ff00_ff00_ff00_u64)).unwrap();
+        c.retro_(&awi!(0xf0f0_f0f0_f0f0_f0f0_u64)).unwrap();
+        assert_eq!(eval.eval().unwrap(), awi!(0x00f0_f000_00f0_f000_u64));
+    }
+    drop(epoch);
+}
+
+// `Ensemble`s can be sent to other threads, e.x. to analyze clones in parallel
+#[test]
+fn ensemble_is_send() {
+    const fn assert_send<T: Send>() {}
+    const { assert_send::<Ensemble>() };
+    let (epoch, ensemble) = lowered_rotation(3);
+    let num_lnodes = ensemble.lnodes.len();
+    let handle = std::thread::spawn(move || ensemble.lut_table_stats().num_luts);
+    assert!(handle.join().unwrap() <= num_lnodes);
+    drop(epoch);
+}
+
+/// Lowers a rotation by `s` and returns the `Epoch` and a clone of its
+/// `Ensemble`
+fn lowered_rotation(s: usize) -> (Epoch, Ensemble) {
+    use dag::*;
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(bw(16));
+    let mut y = awi!(x);
+    y.rotl_(s).unwrap();
+    let _y = EvalAwi::from(&y);
+    let ((), lowering) = IterationAudit::record(|| epoch.lower().unwrap());
+    assert_eq!(lowering.entries()[0].site, "Ensemble::lower_for_rnodes");
+    let ensemble = epoch.ensemble(|ensemble| ensemble.clone());
+    (epoch, ensemble)
+}
+
+/// Optimizes a clone of `ensemble` while recording the iteration order
+fn audited_optimization(ensemble: &Ensemble) -> IterationAudit {
+    let mut ensemble = ensemble.clone();
+    IterationAudit::record(|| ensemble.optimize_all().unwrap()).1
+}
+
+#[test]
+fn stats_iteration_audit() {
+    let (epoch, ensemble) = lowered_rotation(3);
+    let audit = audited_optimization(&ensemble);
+    let sites: Vec<&str> = audit.entries().iter().map(|entry| entry.site).collect();
+    assert_eq!(sites, vec![
+        "Ensemble::optimize_all preinvestigate",
+        "Ensemble::optimize_all optimize"
+    ]);
+    // the same run results in the same order
+    let same = audited_optimization(&ensemble);
+    assert_eq!(audit.first_divergence(&same), None);
+    assert_eq!(audit.digest(), same.digest());
+    assert_eq!(audit.to_string(), same.to_string());
+    drop(epoch);
+
+    // a different run is reported at its first difference
+    let (epoch, ensemble) = lowered_rotation(5);
+    let different = audited_optimization(&ensemble);
+    let divergence = audit.first_divergence(&different).unwrap();
+    assert!(audit.digest() != different.digest());
+    assert_eq!(
+        divergence.sites.0,
+        audit
+            .entries()
+            .get(divergence.entry_i)
+            .map(|entry| entry.site)
+    );
+    assert!(divergence.to_string().contains("first diverged at entry"));
+
+    // nothing is recorded outside of `IterationAudit::record`, and an inner
+    // recording is separate from the outer one
+    let (inner, outer) = IterationAudit::record(|| audited_optimization(&ensemble));
+    assert!(outer.entries().is_empty());
+    assert_eq!(inner.first_divergence(&different), None);
+
+    // a panic in an inner recording does not leave it recording in place of the
+    // outer one
+    let ((), outer) = IterationAudit::record(|| {
+        ensemble.clone().optimize_all().unwrap();
+        let res = catch_unwind(|| IterationAudit::record(|| panic!()));
+        assert!(res.is_err());
+    });
+    assert_eq!(outer.first_divergence(&different), None);
+    drop(epoch);
+}
+
+#[test]
+fn stats_trace() {
+    let epoch = Epoch::new();
+    let x = LazyAwi::opaque(dag::bw(8));
+    let acc = {
+        use dag::*;
+        let acc = Loop::zero(bw(8));
+        let mut next = awi!(acc);
+        next.add_(&x).unwrap();
+        let acc_out = EvalAwi::from(&acc);
+        acc.drive_with_delay(&next, 1).unwrap();
+        acc_out
+    };
+    // nothing is recorded outside of `Trace::record`
+    epoch.lower().unwrap();
+    let ((), trace) = Trace::record(|| {
+        epoch.optimize().unwrap();
+        x.retro_u8_(3).unwrap();
+        epoch.run(4).unwrap();
+        assert_eq!(acc.eval_u8().unwrap(), 12);
+    });
+    let optimize = trace.filter("optimize");
+    assert_eq!(optimize.spans().len(), 1);
+    assert_eq!(optimize.spans()[0].name, "Ensemble::optimize_all");
+    assert!(trace.total_count("optimize", "optimizations") > 0);
+    assert!(
+        trace.total_count("optimize", "lnodes_after")
+            <= trace.total_count("optimize", "lnodes_before")
+    );
+    assert!(trace.total_count("eval", "events") > 0);
+    assert_eq!(trace.total_count("eval", "timestamps"), 4);
+    let eval = trace.filter("eval");
+    assert!(eval.spans().iter().all(|span| span.target == "eval"));
+    // the evaluations within `run` are nested under it
+    let p_run = eval
+        .spans()
+        .iter()
+        .position(|span| span.name == "Ensemble::run")
+        .unwrap();
+    assert!(eval.spans().iter().any(|span| span.parent == Some(p_run)));
+    assert!(trace.total_duration("eval") >= eval.spans()[p_run].duration);
+    assert!(
+        trace
+            .folded()
+            .lines()
+            .any(|line| line
+                .starts_with("eval::Ensemble::run;eval::Ensemble::restart_request_phase "))
+    );
+    assert!(trace
+        .to_string()
+        .contains("[optimize] Ensemble::optimize_all"));
+
+    // nested recordings are separate
+    let (inner, outer) = Trace::record(|| Trace::record(|| epoch.run(1).unwrap()).1);
+    assert!(outer.spans().is_empty());
+    assert_eq!(inner.total_count("eval", "timestamps"), 1);
+
+    // a panic in an inner recording does not leave it recording in place of the
+    // outer one
+    let ((), outer) = Trace::record(|| {
+        epoch.run(1).unwrap();
+        assert!(catch_unwind(|| Trace::record(|| panic!())).is_err());
+    });
+    assert_eq!(outer.total_count("eval", "timestamps"), 1);
+    drop(x);
+    drop(acc);
+    drop(epoch);
+}
+
+/// Returns the number of `LNode`s after optimizing `(a ^ b) & c` with constant
+/// `a` and `b`, where the `a ^ b` probe is optionally marked `keep` and then
+/// optionally dropped before optimization
+fn kept_probe_lnodes(keep: bool, drop_probe: bool) -> usize {
+    use dag::*;
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let c = LazyAwi::opaque(bw(8));
+    let mut mid = Awi::from(&a);
+    mid.xor_(&b).unwrap();
+    let mut out = mid.clone();
+    out.and_(&c).unwrap();
+    let probe = EvalAwi::from(&mid);
+    let eval = EvalAwi::from(&out);
+    if keep {
+        probe.mark_keep().unwrap();
+    }
+    if drop_probe {
+        drop(probe);
+    }
+    {
+        use awi::*;
+        a.retro_const_(&awi!(0b1100_1010_u8)).unwrap();
+        b.retro_const_(&awi!(0b1010_0110_u8)).unwrap();
+        epoch.optimize().unwrap();
+        epoch.verify_integrity().unwrap();
+        c.retro_(&awi!(0b1111_0000_u8)).unwrap();
+        assert_eq!(eval.eval().unwrap(), awi!(0b0110_0000_u8));
+    }
+    let lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+    drop(epoch);
+    lnodes
+}
+
+#[test]
+fn stats_mark_keep() {
+    // without the barrier, the constants are propagated into the `&`
+    assert_eq!(kept_probe_lnodes(false, true), 0);
+    assert_eq!(kept_probe_lnodes(false, false), 0);
+    // the `&` keeps both of its inputs whether or not the probe still exists
+    assert_eq!(kept_probe_lnodes(true, false), 8);
+    assert_eq!(kept_probe_lnodes(true, true), 8);
+}
+
+#[test]
+fn stats_optimize_with_report() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(16));
+    let b = LazyAwi::opaque(bw(16));
+    let mut out = Awi::from(&a);
+    out.add_(&b).unwrap();
+    let eval = EvalAwi::from(&out);
+    let report = epoch
+        .optimize_passes_with_report(&[
+            OptimizePass::Peephole,
+            OptimizePass::Lower,
+            OptimizePass::OptimizeAll,
+            OptimizePass::MergeEquivalentCones(1000),
+            OptimizePass::DecomposeLuts(2, LutDecomposition::Shannon),
+        ])
+        .unwrap();
+    epoch.verify_integrity().unwrap();
+    let passes = report.passes();
+    assert_eq!(passes.len(), 5);
+    for (pass, next) in passes.iter().zip(passes.iter().skip(1)) {
+        assert_eq!(pass.after, next.before);
+    }
+    // nothing is lowered until the `Lower` pass
+    assert_eq!(passes[0].before.num_lnodes, 0);
+    assert!(passes[1].lnode_delta() > 0);
+    assert!(passes[2].lnode_delta() <= 0);
+    // decomposition into 2 input lookup tables can only add `LNode`s and depth
+    assert!(passes[4].lnode_delta() >= 0);
+    assert!(passes[4].depth_delta() >= 0);
+    assert_eq!(report.final_stats().unwrap(), passes[4].after);
+    // a header and one row per pass
+    assert_eq!(report.to_string().lines().count(), 6);
+    {
+        use awi::*;
+        a.retro_(&awi!(1234u16)).unwrap();
+        b.retro_(&awi!(4321u16)).unwrap();
+        assert_eq!(eval.eval().unwrap(), awi!(5555u16));
+    }
+    drop(epoch);
+
+    // the default pipeline is the same as `Epoch::optimize`
+    let expected = {
+        let epoch = Epoch::new();
+        let a = LazyAwi::opaque(bw(16));
+        let mut out = Awi::from(&a);
+        out.inc_(true);
+        let _eval = EvalAwi::from(&out);
+        epoch.optimize().unwrap();
+        let lnodes = epoch.ensemble(|ensemble| ensemble.lnodes.len());
+        drop(epoch);
+        lnodes
+    };
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(16));
+    let mut out = Awi::from(&a);
+    out.inc_(true);
+    let _eval = EvalAwi::from(&out);
+    let report = epoch.optimize_with_report().unwrap();
+    assert_eq!(report.passes().len(), 2);
+    assert_eq!(report.final_stats().unwrap().num_lnodes, expected);
+    drop(epoch);
+}
@@ -0,0 +1,643 @@
+use starlight::{
+    awi,
+    awi::*,
+    awint_dag::Op,
+    dag, dag_enum,
+    ensemble::{Ensemble, MIN_SHARED_LITERAL_BW},
+    mux_, ports, sel_,
+    sweep::SweepStats,
+    utils::StarRng,
+    Decoder, Delay, Enum, Epoch, Error, EvalAwi, InOut, LazyAwi, OverflowArith, Ports, Rom,
+};
+
+#[test]
+fn inout_contention() {
+    use dag::*;
+    let epoch = Epoch::new();
+
+    let mut pin = InOut::<4>::opaque();
+    // the program drives the inverse of what it sees when it is driving
+    let mut inverse = awi!(pin);
+    inverse.not_();
+    let output_en = LazyAwi::opaque(bw(1));
+    pin.drive(&inverse, output_en.to_bool()).unwrap();
+    assert!(pin.drive(&inverse, output_en.to_bool()).is_err());
+
+    {
+        use awi::*;
+
+        output_en.retro_bool_(false).unwrap();
+        pin.retro_(&awi!(0101)).unwrap();
+        assert_eq!(pin.eval().unwrap(), awi!(0101));
+        assert!(pin.eval_input_en().unwrap());
+        assert!(!pin.eval_output_en().unwrap());
+        pin.check_contention().unwrap();
+        epoch.assert_assertions(true).unwrap();
+
+        pin.retro_release_().unwrap();
+        assert!(pin.eval().is_err());
+        output_en.retro_bool_(true).unwrap();
+        assert!(pin.eval().is_err());
+        pin.check_contention().unwrap();
+        epoch.assert_assertions(true).unwrap();
+
+        pin.retro_(&awi!(0011)).unwrap();
+        assert_eq!(pin.eval().unwrap(), awi!(1100));
+        assert!(pin.check_contention().is_err());
+        assert!(epoch.assert_assertions(true).is_err());
+    }
+    drop(epoch);
+}
+
+dag_enum! {
+    enum Opcode {
+        Add,
+        Sub,
+        Xor,
+    }
+}
+
+#[test]
+fn dag_enum() {
+    let epoch = Epoch::new();
+    assert_eq!(Enum::<Opcode>::bw(), 2);
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let opcode_bits = LazyAwi::opaque(Enum::<Opcode>::nzbw());
+    assert!(Enum::<Opcode>::from_bits(&a).is_err());
+    let opcode = Enum::<Opcode>::from_bits(&opcode_bits).unwrap();
+    let out = opcode
+        .match_(|variant| {
+            let mut tmp = dag::Awi::from(&a);
+            match variant {
+                Opcode::Add => tmp.add_(&b).unwrap(),
+                Opcode::Sub => tmp.sub_(&b).unwrap(),
+                Opcode::Xor => tmp.xor_(&b).unwrap(),
+            }
+            tmp
+        })
+        .unwrap();
+    let is_sub = EvalAwi::from_bool(opcode.is(Opcode::Sub));
+    assert!(opcode
+        .match_(|variant| dag::Awi::zero(bw(1 + (variant == Opcode::Xor) as usize)))
+        .is_err());
+    let out = EvalAwi::from(&out);
+    epoch.optimize().unwrap();
+    a.retro_u8_(7).unwrap();
+    b.retro_u8_(5).unwrap();
+    let mut encoding = awi::Awi::zero(bw(2));
+    for (i, res) in [12u8, 2, 2].into_iter().enumerate() {
+        encoding.usize_(i);
+        opcode_bits.retro_(&encoding).unwrap();
+        assert_eq!(out.eval_u8().unwrap(), res);
+        assert_eq!(is_sub.eval_bool().unwrap(), i == 1);
+        epoch.assert_assertions(true).unwrap();
+    }
+    // invalid encoding
+    encoding.usize_(3);
+    opcode_bits.retro_(&encoding).unwrap();
+    assert!(epoch.assert_assertions(true).is_err());
+    drop(epoch);
+}
+
+ports! {
+    struct AluPorts {
+        lhs: 8,
+        rhs: 8,
+        sub: 1,
+    }
+}
+
+#[test]
+fn ports() {
+    let epoch = Epoch::new();
+    assert_eq!(AluPorts::bw(), 17);
+    assert_eq!(AluPorts::FIELDS, &[("lhs", 8), ("rhs", 8), ("sub", 1)]);
+    let inputs = AluPorts::<LazyAwi>::opaque();
+    let dut = inputs.to_dag();
+    let mut out = dut.lhs.clone();
+    out.add_(&dut.rhs).unwrap();
+    let mut diff = dut.lhs.clone();
+    diff.sub_(&dut.rhs).unwrap();
+    out.mux_(&diff, dut.sub.to_bool()).unwrap();
+    let outputs = AluPorts {
+        lhs: out.clone(),
+        rhs: dut.rhs.clone(),
+        sub: dut.sub.clone(),
+    }
+    .to_eval();
+    // flattening and unflattening
+    let flat = dut.flatten().unwrap();
+    let unflat = AluPorts::unflatten(&flat).unwrap();
+    let widths = unflat.each_ref().map(|_, field| field.bw());
+    assert_eq!((widths.lhs, widths.rhs, widths.sub), (8, 8, 1));
+    let flat = EvalAwi::from(&flat);
+    assert!(AluPorts::unflatten(&out).is_err());
+    let mut bad = AluPorts::<dag::Awi>::unflatten(&dag::Awi::zero(bw(17))).unwrap();
+    bad.sub = dag::Awi::zero(bw(2));
+    assert!(matches!(bad.flatten(), Err(Error::BitwidthMismatch(1, 2))));
+    epoch.optimize().unwrap();
+
+    inputs
+        .retro_(&AluPorts {
+            lhs: awi!(7u8),
+            rhs: awi!(5u8),
+            sub: awi!(0),
+        })
+        .unwrap();
+    let values = outputs.eval().unwrap();
+    assert_eq!(values.lhs, awi!(12u8));
+    assert_eq!(flat.eval().unwrap(), awi!(0, 5u8, 7u8));
+    // `sub` is the most significant
+    inputs.retro_flat_(&awi!(1, 5u8, 7u8)).unwrap();
+    assert_eq!(outputs.eval_flat().unwrap(), awi!(1, 5u8, 2u8));
+    assert!(inputs.retro_flat_(&awi!(0u8)).is_err());
+    let names = inputs.map(|name, lazy| (name, lazy.bw()));
+    assert_eq!(names.sub, ("sub", 1));
+    drop(epoch);
+}
+
+#[test]
+fn manual_lut() {
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(2));
+    let b = LazyAwi::opaque(bw(1));
+    // a full adder with `{carry, sum}` entries, the index is `{b, a[1], a[0]}`
+    let mut table = Awi::zero(bw(16));
+    for i in 0..8usize {
+        let sum = i.count_ones() as usize;
+        table.field_to(i * 2, &Awi::from_usize(sum), 2).unwrap();
+    }
+    let out = epoch.lut(&[&a, &b], &table).unwrap();
+    assert_eq!(out.bw(), 2);
+    // use the output in ordinary mimicking operations
+    let mut tmp = out.clone();
+    tmp.not_();
+    let not_out = EvalAwi::from(&tmp);
+    let out = EvalAwi::from(&out);
+    assert!(epoch.lut(&[&a, &b], &awi!(0u12)).is_err());
+    assert!(epoch.lut(&[&a, &b], &awi!(0u8)).is_ok());
+    for optimize in [false, true] {
+        if optimize {
+            epoch.optimize().unwrap();
+        }
+        let mut inx = Awi::zero(bw(2));
+        for i in 0..8usize {
+            inx.usize_(i);
+            a.retro_(&inx).unwrap();
+            b.retro_bool_((i & 0b100) != 0).unwrap();
+            let sum = i.count_ones() as usize;
+            assert_eq!(out.eval().unwrap().to_usize(), sum);
+            assert_eq!(not_out.eval().unwrap().to_usize(), !sum & 0b11);
+        }
+    }
+    epoch.verify_integrity().unwrap();
+    drop(epoch);
+}
+
+#[test]
+fn rom() {
+    let hex = "// a comment\n0A 1b_c // another\n\n@4 ff\n";
+    let rom = Rom::from_hex_str(bw(12), hex).unwrap();
+    let expected = [0x00au16, 0x1bc, 0, 0, 0xff];
+    assert_eq!(rom.len(), expected.len());
+    for (word, x) in rom.words().iter().zip(expected) {
+        assert_eq!(word.to_u16(), x);
+    }
+    let path = std::env::temp_dir().join("starlight_rom_test.hex");
+    std::fs::write(&path, hex).unwrap();
+    let rom_file = Rom::from_hex(bw(12), &path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(rom_file.words(), rom.words());
+    assert!(Rom::from_hex(bw(12), &path).is_err());
+    assert!(Rom::from_hex_str(bw(4), "1f").is_err());
+    assert!(Rom::from_hex_str(bw(4), "g").is_err());
+    assert!(Rom::from_hex_str(bw(4), "@x").is_err());
+    assert!(Rom::from_hex_str(bw(4), "@ffffffffffffffff 1").is_err());
+    let rom_bin = Rom::from_bin(bw(12), &[0x34, 0x12, 0xab]);
+    assert_eq!(rom_bin.len(), 2);
+    assert_eq!(rom_bin.words()[0].to_u16(), 0x234);
+    assert_eq!(rom_bin.words()[1].to_u16(), 0xab1);
+    let mut rom_push = Rom::new(bw(12));
+    assert!(rom_push.is_empty());
+    assert!(rom_push.push(&awi!(0u8)).is_err());
+    rom_push.push(&awi!(0x123u12)).unwrap();
+    assert_eq!(rom_push.len(), 1);
+
+    let epoch = Epoch::new();
+    let addr = LazyAwi::opaque(bw(3));
+    let out = EvalAwi::from(&rom.read(&addr).unwrap());
+    assert!(rom.read(&dag::Awi::zero(bw(2))).is_err());
+    for optimize in [false, true] {
+        if optimize {
+            epoch.optimize().unwrap();
+        }
+        for i in 0..8 {
+            let mut tmp = awi!(0u3);
+            tmp.usize_(i);
+            addr.retro_(&tmp).unwrap();
+            assert_eq!(
+                out.eval().unwrap().to_u16(),
+                expected.get(i).copied().unwrap_or(0)
+            );
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn rom_shared_literal() {
+    let bytes: Vec<u8> = (0..32u8).map(|i| i.wrapping_mul(37)).collect();
+    let rom = Rom::from_bin(bw(16), &bytes);
+    let epoch = Epoch::new();
+    let addr0 = LazyAwi::opaque(bw(4));
+    let addr1 = LazyAwi::opaque(bw(4));
+    let out0 = EvalAwi::from(&rom.read(&addr0).unwrap());
+    let out1 = EvalAwi::from(&rom.read(&addr1).unwrap());
+    let num_tables = || {
+        epoch.ensemble(|ensemble| {
+            ensemble
+                .stator
+                .states
+                .vals()
+                .filter(|state| matches!(state.op, Op::Literal(ref lit) if lit.bw() == 256))
+                .count()
+        })
+    };
+    const { assert!(256 >= MIN_SHARED_LITERAL_BW) };
+    // both read ports use the same literal state for the table
+    assert_eq!(num_tables(), 1);
+    for lower in [false, true] {
+        if lower {
+            epoch.lower().unwrap();
+            epoch.verify_integrity().unwrap();
+        }
+        for i in 0..16 {
+            let mut tmp = awi!(0u4);
+            tmp.u8_(i);
+            addr0.retro_(&tmp).unwrap();
+            tmp.u8_(15 - i);
+            addr1.retro_(&tmp).unwrap();
+            assert_eq!(out0.eval().unwrap(), rom.words()[usize::from(i)]);
+            assert_eq!(out1.eval().unwrap(), rom.words()[usize::from(15 - i)]);
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn overflow_arith() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let lhs = LazyAwi::opaque(bw(8));
+    let rhs = LazyAwi::opaque(bw(8));
+    let mut outs = vec![];
+    for signed in [false, true] {
+        let mut checked = vec![];
+        let mut sat = vec![];
+        for i in 0..3 {
+            let mut x = Awi::from(&lhs);
+            let o = match i {
+                0 => x.checked_add_(&rhs, signed),
+                1 => x.checked_sub_(&rhs, signed),
+                _ => x.checked_mul_(&rhs, signed),
+            }
+            .unwrap();
+            checked.push((EvalAwi::from(&x), EvalAwi::from_bool(o)));
+            let mut x = Awi::from(&lhs);
+            match i {
+                0 => x.add_sat_(&rhs, signed),
+                1 => x.sub_sat_(&rhs, signed),
+                _ => x.mul_sat_(&rhs, signed),
+            }
+            .unwrap();
+            sat.push(EvalAwi::from(&x));
+        }
+        outs.push((checked, sat));
+    }
+    assert!(Awi::zero(bw(8))
+        .checked_add_(&Awi::zero(bw(7)), false)
+        .is_err());
+
+    let mut rng = StarRng::new(0);
+    let mut pairs = vec![
+        (0u8, 0u8),
+        (255, 255),
+        (128, 128),
+        (127, 1),
+        (128, 255),
+        (0, 1),
+    ];
+    for _ in 0..64 {
+        pairs.push((rng.next_u8(), rng.next_u8()));
+    }
+    for optimize in [false, true] {
+        if optimize {
+            epoch.optimize().unwrap();
+        }
+        for (a, b) in pairs.iter().copied() {
+            use awi::*;
+            lhs.retro_u8_(a).unwrap();
+            rhs.retro_u8_(b).unwrap();
+            let (checked, sat) = &outs[0];
+            let expected = [
+                a.overflowing_add(b),
+                a.overflowing_sub(b),
+                a.overflowing_mul(b),
+            ];
+            for ((x, o), e) in checked.iter().zip(expected) {
+                assert_eq!((x.eval_u8().unwrap(), o.eval_bool().unwrap()), e);
+            }
+            let expected = [
+                a.saturating_add(b),
+                a.saturating_sub(b),
+                a.saturating_mul(b),
+            ];
+            for (x, e) in sat.iter().zip(expected) {
+                assert_eq!(x.eval_u8().unwrap(), e);
+            }
+            let (a, b) = (a as i8, b as i8);
+            let (checked, sat) = &outs[1];
+            let expected = [
+                a.overflowing_add(b),
+                a.overflowing_sub(b),
+                a.overflowing_mul(b),
+            ];
+            for ((x, o), e) in checked.iter().zip(expected) {
+                assert_eq!((x.eval_i8().unwrap(), o.eval_bool().unwrap()), e);
+            }
+            let expected = [
+                a.saturating_add(b),
+                a.saturating_sub(b),
+                a.saturating_mul(b),
+            ];
+            for (x, e) in sat.iter().zip(expected) {
+                assert_eq!(x.eval_i8().unwrap(), e);
+            }
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn sel_and_mux_macros() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let conds = LazyAwi::opaque(bw(5));
+    let sel = LazyAwi::opaque(bw(3));
+    let vals: Vec<Awi> = (0..6u8).map(|i| Awi::from_u8(i * 17)).collect();
+    let x = sel_!(
+        conds.get(0).unwrap() => vals[0],
+        conds.get(1).unwrap() => &vals[1],
+        conds.get(2).unwrap() => vals[2],
+        conds.get(3).unwrap() => vals[3],
+        conds.get(4).unwrap() => vals[4],
+        _ => vals[5],
+    )
+    .unwrap();
+    let x = EvalAwi::from(&x);
+    let y = mux_!(sel;
+        0 => vals[0],
+        1 | 5 => vals[1],
+        2 => vals[2],
+        3 | 6 | 7 => vals[3],
+        _ => vals[5],
+    )
+    .unwrap();
+    let y = EvalAwi::from(&y);
+    assert!(sel_!(true => inlawi!(0u7), _ => vals[0]).is_err());
+    assert!(mux_!(sel; 8 => vals[0], _ => vals[1]).is_err());
+    assert_eq!(
+        EvalAwi::from(&sel_!(_ => vals[2]).unwrap())
+            .eval_u8()
+            .unwrap(),
+        34
+    );
+
+    for optimize in [false, true] {
+        if optimize {
+            epoch.optimize().unwrap();
+        }
+        for i in 0..32u8 {
+            use awi::*;
+            let mut tmp = awi!(0u5);
+            tmp.u8_(i);
+            conds.retro_(&tmp).unwrap();
+            let expected = (0..5).find(|j| (i & (1 << j)) != 0).unwrap_or(5);
+            assert_eq!(x.eval_u8().unwrap(), (expected as u8) * 17);
+        }
+        for i in 0..8u8 {
+            use awi::*;
+            let mut tmp = awi!(0u3);
+            tmp.u8_(i);
+            sel.retro_(&tmp).unwrap();
+            let expected = match i {
+                0 => 0,
+                1 | 5 => 1,
+                2 => 2,
+                3 | 6 | 7 => 3,
+                _ => 5,
+            };
+            assert_eq!(y.eval_u8().unwrap(), expected * 17);
+        }
+    }
+    drop(epoch);
+}
+
+#[test]
+fn demo_circuit() {
+    use starlight::demo::Circuit;
+
+    // outlives an `Epoch` created afterwards
+    let mut circuit = Circuit::new();
+    let epoch = Epoch::new();
+    let a = circuit.input("a", 8).unwrap();
+    let b = circuit.input("b", 8).unwrap();
+    let one = circuit.constant("1", 8).unwrap();
+    let sum = circuit.add(a, b).unwrap();
+    let inc = circuit.add(sum, one).unwrap();
+    let less = circuit.ult(a, b).unwrap();
+    let max = circuit.mux(a, b, less).unwrap();
+    let delayed = circuit.delay(max, 5).unwrap();
+    assert_eq!(circuit.width(less).unwrap(), 1);
+    circuit.output("inc", inc).unwrap();
+    circuit.output("max", max).unwrap();
+    circuit.output("delayed", delayed).unwrap();
+    assert_eq!(circuit.get("inc").unwrap(), None);
+    circuit.set("a", "0x10").unwrap();
+    circuit.set("b", "0b111").unwrap();
+    assert_eq!(circuit.get("inc").unwrap().as_deref(), Some("0x18"));
+    assert_eq!(circuit.get("max").unwrap().as_deref(), Some("0x10"));
+    // wires still work after evaluation
+    let twice = circuit.add(sum, sum).unwrap();
+    let low = circuit.lshr(twice, 4).unwrap();
+    circuit.output("low", low).unwrap();
+    assert_eq!(circuit.get("low").unwrap().as_deref(), Some("0x2"));
+    circuit.optimize().unwrap();
+    assert!(circuit.num_lnodes() > 0);
+    circuit.set("b", "0x20").unwrap();
+    assert_eq!(circuit.get("inc").unwrap().as_deref(), Some("0x31"));
+    assert_eq!(circuit.get("max").unwrap().as_deref(), Some("0x20"));
+    circuit.run(5).unwrap();
+    assert_eq!(circuit.get("delayed").unwrap().as_deref(), Some("0x20"));
+    assert!(circuit.write_ir().contains("inc"));
+    // wires are invalidated, but new ones can be made
+    assert!(circuit.not(a).is_err());
+    let c = circuit.input("c", 4).unwrap();
+    let d = circuit.constant("0b0101", 4).unwrap();
+    let e = circuit.xor(c, d).unwrap();
+    let f = circuit.shl(e, 1).unwrap();
+    assert_eq!(circuit.width(f).unwrap(), 4);
+    circuit.output("f", f).unwrap();
+    circuit.set("c", "0b0011").unwrap();
+    assert_eq!(circuit.get("f").unwrap().as_deref(), Some("0xc"));
+
+    // errors
+    assert!(circuit.input("a", 8).is_err());
+    assert!(circuit.input("g", 0).is_err());
+    assert!(circuit.output("inc", c).is_err());
+    assert!(circuit.not(1000).is_err());
+    let one_bit = circuit.constant("1", 1).unwrap();
+    assert!(matches!(
+        circuit.and(c, one_bit),
+        Err(Error::BitwidthMismatch(4, 1))
+    ));
+    assert!(circuit.mux(c, d, c).is_err());
+    assert!(circuit.shl(c, 4).is_err());
+    assert!(circuit.set("missing", "0").is_err());
+    assert!(circuit.set("a", "0x100").is_err());
+    assert!(circuit.get("missing").is_err());
+    assert!(circuit.constant("0x10", 4).is_err());
+    drop(epoch);
+    drop(circuit);
+}
+
+#[test]
+fn decoder() {
+    // a subset of the RV32I base opcodes, as (name, mask, value)
+    let table: &[(&str, u32, u32)] = &[
+        ("lui", 0x7f, 0x37),
+        ("auipc", 0x7f, 0x17),
+        ("jal", 0x7f, 0x6f),
+        ("jalr", 0x707f, 0x67),
+        ("beq", 0x707f, 0x63),
+        ("bne", 0x707f, 0x1063),
+        ("lw", 0x707f, 0x2003),
+        ("sw", 0x707f, 0x2023),
+        ("addi", 0x707f, 0x13),
+        ("add", 0xfe00707f, 0x33),
+        ("sub", 0xfe00707f, 0x40000033),
+        ("ecall", 0xffffffff, 0x73),
+        ("ebreak", 0xffffffff, 0x100073),
+    ];
+    let mut decoder = Decoder::new(bw(32));
+    for (name, mask, value) in table.iter().copied() {
+        decoder
+            .pattern(name, &Awi::from_u32(mask), &Awi::from_u32(value))
+            .unwrap();
+    }
+    assert_eq!(decoder.patterns().len(), table.len());
+    assert_eq!(decoder.index_of("sub"), Some(10));
+    assert_eq!(decoder.index_of("mul"), None);
+    // overlaps with "addi"
+    assert!(matches!(
+        decoder.pattern("nop", &awi!(0xffffffff_u32), &awi!(0x13_u32)),
+        Err(Error::OtherString(_))
+    ));
+    // duplicate name
+    assert!(decoder
+        .pattern("add", &awi!(0xffffffff_u32), &awi!(0x1_u32))
+        .is_err());
+    // value outside of mask
+    assert!(decoder
+        .pattern("x", &awi!(0x7f_u32), &awi!(0x80_u32))
+        .is_err());
+    assert!(matches!(
+        decoder.pattern("x", &awi!(0x7f_u8), &awi!(0x7f_u8)),
+        Err(Error::BitwidthMismatch(32, 8))
+    ));
+    assert_eq!(decoder.patterns().len(), table.len());
+
+    let epoch = Epoch::new();
+    let inst = LazyAwi::opaque(bw(32));
+    let onehot = EvalAwi::from(&decoder.decode(&inst).unwrap());
+    assert!(Decoder::new(bw(32)).decode(&inst).is_err());
+    epoch.optimize().unwrap();
+    let mut rng = StarRng::new(0);
+    for i in 0..256 {
+        let x = if (i % 2) == 0 {
+            // make sure that the instructions match often
+            let (_, mask, value) = table[rng.index(table.len()).unwrap()];
+            (rng.next_u32() & !mask) | value
+        } else {
+            rng.next_u32()
+        };
+        inst.retro_u32_(x).unwrap();
+        let mut expected = 0u16;
+        for (j, (_, mask, value)) in table.iter().copied().enumerate() {
+            if (x & mask) == value {
+                expected |= 1 << j;
+            }
+        }
+        assert_eq!(onehot.eval().unwrap().to_u16(), expected);
+    }
+    drop(epoch);
+}
+
+#[test]
+fn blackbox() {
+    use dag::*;
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(8));
+    let b = LazyAwi::opaque(bw(8));
+    let mut sum = Awi::from(&a);
+    sum.add_(&b).unwrap();
+    let outputs = epoch
+        .blackbox("vendor_ram", &[&sum, &a], &[bw(8), bw(1)], &[
+            (0, 0, Delay::from(10)),
+            (1, 1, Delay::from(2)),
+        ])
+        .unwrap();
+    let mut data = Awi::from(&outputs[0]);
+    data.xor_(&b).unwrap();
+    let out = EvalAwi::from(&data);
+    let flag = EvalAwi::from(&outputs[1]);
+
+    assert!(epoch.blackbox("", &[&sum], &[bw(1)], &[]).is_err());
+    assert!(epoch
+        .blackbox("bad", &[&sum], &[bw(1)], &[(1, 0, Delay::from(1))])
+        .is_err());
+    assert!(epoch
+        .blackbox("bad", &[&sum], &[bw(1)], &[
+            (0, 0, Delay::from(1)),
+            (0, 0, Delay::from(2))
+        ])
+        .is_err());
+
+    epoch.optimize().unwrap();
+    epoch.ensemble(|ensemble| {
+        assert_eq!(ensemble.blackboxes.len(), 1);
+        let stats = SweepStats::from_ensemble(ensemble, Delay::from(1), Delay::from(1)).unwrap();
+        // the adder before and the XOR after the 10 delay path
+        assert!(stats.estimated_delay >= Delay::from(12));
+        // the adder is kept alive by the black-box
+        assert!(stats.num_lnodes >= 16);
+    });
+
+    a.retro_u8_(3).unwrap();
+    b.retro_u8_(5).unwrap();
+    assert!(out.eval().is_err());
+    assert!(flag.eval().is_err());
+    // the black-box can be modeled by assigning its outputs
+    outputs[0].retro_u8_(0xf0).unwrap();
+    assert_eq!(out.eval().unwrap().to_u8(), 0xf5);
+
+    let ir = epoch.ensemble(|ensemble| ensemble.write_ir());
+    assert!(ir.contains("blackbox \"vendor_ram\" in r"));
+    assert!(ir.contains("path 0 0 10 path 1 1 2"));
+    let parsed = Ensemble::parse_ir(&ir).unwrap();
+    assert_eq!(parsed.blackboxes.len(), 1);
+    assert_eq!(parsed.write_ir(), ir);
+    assert!(Ensemble::parse_ir("slir 1\nblackbox \"x\" in out r0").is_err());
+    drop(epoch);
+}
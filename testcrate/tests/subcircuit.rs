@@ -0,0 +1,211 @@
+use starlight::{awi, awi::*, dag, utils::StarRng, Epoch, EvalAwi, LazyAwi};
+
+#[test]
+fn find_subcircuits() {
+    // returns the sum and carry of a full adder, optionally with the operands of
+    // every operation swapped
+    let full_adder = |x: &dag::Bits, y: &dag::Bits, c: &dag::Bits, swapped: bool| {
+        let op = |lhs: &dag::Bits, rhs: &dag::Bits, f: fn(&mut dag::Bits, &dag::Bits)| {
+            let (lhs, rhs) = if swapped { (rhs, lhs) } else { (lhs, rhs) };
+            let mut res = dag::Awi::from(lhs);
+            f(&mut res, rhs);
+            res
+        };
+        let xor = |a: &mut dag::Bits, b: &dag::Bits| a.xor_(b).unwrap();
+        let and = |a: &mut dag::Bits, b: &dag::Bits| a.and_(b).unwrap();
+        let or = |a: &mut dag::Bits, b: &dag::Bits| a.or_(b).unwrap();
+        let p = op(x, y, xor);
+        let sum = op(&p, c, xor);
+        let carry = op(&op(x, y, and), &op(&p, c, and), or);
+        (sum, carry)
+    };
+    let make_pattern = |swapped: bool| {
+        let epoch = Epoch::new();
+        let inputs: Vec<LazyAwi> = (0..3).map(|_| LazyAwi::opaque(bw(1))).collect();
+        let (sum, carry) = full_adder(&inputs[0], &inputs[1], &inputs[2], swapped);
+        let outputs = (EvalAwi::from(&sum), EvalAwi::from(&carry));
+        epoch.optimize().unwrap();
+        (inputs, outputs, epoch.suspend())
+    };
+    let (_pattern_inputs, pattern_outputs, pattern) = make_pattern(false);
+    let (_inputs1, _outputs1, pattern1) = make_pattern(true);
+    let pattern_lnodes = pattern.ensemble(|ensemble| ensemble.lnodes.len());
+    // one `LNode` per operation, the optimizer does not fuse them
+    assert_eq!(pattern_lnodes, 5);
+
+    // a 4 bit ripple carry adder built out of full adders
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let b = LazyAwi::opaque(bw(4));
+    let cin = LazyAwi::opaque(bw(1));
+    let mut carry = dag::Awi::from(cin.as_ref());
+    let mut sums = vec![];
+    for i in 0..4 {
+        let mut x = dag::Awi::zero(bw(1));
+        x.field_from(&a, i, 1).unwrap();
+        let mut y = dag::Awi::zero(bw(1));
+        y.field_from(&b, i, 1).unwrap();
+        let (sum, next) = full_adder(&x, &y, &carry, false);
+        sums.push(EvalAwi::from(&sum));
+        carry = next;
+    }
+    let cout = EvalAwi::from(&carry);
+    let matches = epoch.find_subcircuits(&pattern).unwrap();
+    assert_eq!(matches.len(), 4);
+    for m in &matches {
+        assert_eq!(m.lnodes.len(), pattern_lnodes);
+        assert_eq!(m.inputs.len(), 3);
+        assert_eq!(m.outputs.len(), 2);
+    }
+    // the sums are found through the pattern sum output
+    let sum_equivs: Vec<_> = epoch.ensemble(|ensemble| {
+        sums.iter()
+            .map(|sum| {
+                let (_, rnode) = ensemble.notary.get_rnode(sum.p_external()).unwrap();
+                let p_bit = rnode.bits().unwrap()[0].unwrap();
+                ensemble.backrefs.get_val(p_bit).unwrap().p_self_equiv
+            })
+            .collect()
+    });
+    for sum_equiv in sum_equivs {
+        assert!(matches.iter().any(|m| m.outputs.iter().any(
+            |(p_external, _, p_equiv)| (*p_external == pattern_outputs.0.p_external())
+                && (*p_equiv == sum_equiv)
+        )));
+    }
+    assert_eq!(epoch.find_subcircuits(&pattern1).unwrap().len(), 4);
+
+    // a pattern that is not in the design
+    let other = Epoch::new();
+    let xs: Vec<LazyAwi> = (0..3).map(|_| LazyAwi::opaque(bw(1))).collect();
+    let mut all = dag::Awi::from(xs[0].as_ref());
+    all.and_(&xs[1]).unwrap();
+    all.and_(&xs[2]).unwrap();
+    let y = EvalAwi::from(&all);
+    other.optimize().unwrap();
+    let other = other.suspend();
+    assert!(epoch.find_subcircuits(&other).unwrap().is_empty());
+    drop(cout);
+    drop(epoch);
+    drop(xs);
+    drop(y);
+    drop(other);
+    drop(pattern);
+    drop(pattern1);
+}
+
+#[test]
+fn replace_subcircuits() {
+    // a full adder that also returns its propagate bit
+    let full_adder = |x: &dag::Bits, y: &dag::Bits, c: &dag::Bits| {
+        let mut p = dag::Awi::from(x);
+        p.xor_(y).unwrap();
+        let mut sum = p.clone();
+        sum.xor_(c).unwrap();
+        let mut carry = dag::Awi::from(x);
+        carry.and_(y).unwrap();
+        let mut tmp = p.clone();
+        tmp.and_(c).unwrap();
+        carry.or_(&tmp).unwrap();
+        (sum, carry, p)
+    };
+    // makes an optimized program with named inputs "x", "y", "c" and outputs "sum"
+    // and "carry"
+    let make = |f: &dyn Fn(&dag::Bits, &dag::Bits, &dag::Bits) -> (dag::Awi, dag::Awi)| {
+        let epoch = Epoch::new();
+        let inputs: Vec<LazyAwi> = (0..3).map(|_| LazyAwi::opaque(bw(1))).collect();
+        for (input, name) in inputs.iter().zip(["x", "y", "c"]) {
+            input.set_debug_name(name).unwrap();
+        }
+        let (sum, carry) = f(&inputs[0], &inputs[1], &inputs[2]);
+        let outputs = (EvalAwi::from(&sum), EvalAwi::from(&carry));
+        outputs.0.set_debug_name("sum").unwrap();
+        outputs.1.set_debug_name("carry").unwrap();
+        epoch.optimize().unwrap();
+        (inputs, outputs, epoch.suspend())
+    };
+    let (_inputs0, _outputs0, pattern) = make(&|x, y, c| {
+        let (sum, carry, _) = full_adder(x, y, c);
+        (sum, carry)
+    });
+    // the carry as `(x & y) | (c & (x | y))`
+    let (_inputs1, _outputs1, replacement) = make(&|x, y, c| {
+        let mut sum = dag::Awi::from(x);
+        sum.xor_(c).unwrap();
+        sum.xor_(y).unwrap();
+        let mut carry = dag::Awi::from(x);
+        carry.and_(y).unwrap();
+        let mut tmp = dag::Awi::from(x);
+        tmp.or_(y).unwrap();
+        tmp.and_(c).unwrap();
+        carry.or_(&tmp).unwrap();
+        (sum, carry)
+    });
+    // not equivalent
+    let (_inputs2, _outputs2, wrong) = make(&|x, y, c| {
+        let (sum, ..) = full_adder(x, y, c);
+        let mut carry = dag::Awi::from(x);
+        carry.and_(y).unwrap();
+        (sum, carry)
+    });
+
+    // a 4 bit ripple carry adder where the propagate bit of the first full adder
+    // is also used externally
+    let epoch = Epoch::new();
+    let a = LazyAwi::opaque(bw(4));
+    let b = LazyAwi::opaque(bw(4));
+    let cin = LazyAwi::opaque(bw(1));
+    let mut carry = dag::Awi::from(cin.as_ref());
+    let mut sum = dag::Awi::zero(bw(4));
+    let mut p0 = None;
+    for i in 0..4 {
+        let mut x = dag::Awi::zero(bw(1));
+        x.field_from(&a, i, 1).unwrap();
+        let mut y = dag::Awi::zero(bw(1));
+        y.field_from(&b, i, 1).unwrap();
+        let (s, next, p) = full_adder(&x, &y, &carry);
+        sum.field_to(i, &s, 1).unwrap();
+        if i == 0 {
+            p0 = Some(EvalAwi::from(&p));
+        }
+        carry = next;
+    }
+    let sum = EvalAwi::from(&sum);
+    let cout = EvalAwi::from(&carry);
+    assert_eq!(epoch.find_subcircuits(&pattern).unwrap().len(), 4);
+    assert!(epoch.replace_subcircuits(&pattern, &wrong).is_err());
+    assert_eq!(epoch.find_subcircuits(&pattern).unwrap().len(), 4);
+    // the first full adder is skipped because of its external use
+    assert_eq!(
+        epoch.replace_subcircuits(&pattern, &replacement).unwrap(),
+        3
+    );
+    assert_eq!(epoch.find_subcircuits(&pattern).unwrap().len(), 1);
+    assert_eq!(epoch.find_subcircuits(&replacement).unwrap().len(), 3);
+    {
+        use awi::*;
+        let mut rng = StarRng::new(0);
+        for _ in 0..32 {
+            let x = rng.next_u8() & 0xf;
+            let y = rng.next_u8() & 0xf;
+            let c = rng.next_bool();
+            let mut tmp = Awi::zero(bw(4));
+            tmp.u8_(x);
+            a.retro_(&tmp).unwrap();
+            tmp.u8_(y);
+            b.retro_(&tmp).unwrap();
+            cin.retro_bool_(c).unwrap();
+            let total = x + y + u8::from(c);
+            assert_eq!(sum.eval().unwrap().to_u8(), total & 0xf);
+            assert_eq!(cout.eval_bool().unwrap(), total >= 16);
+            assert_eq!(
+                p0.as_ref().unwrap().eval_bool().unwrap(),
+                ((x ^ y) & 1) != 0
+            );
+        }
+    }
+    drop(epoch);
+    drop(pattern);
+    drop(replacement);
+    drop(wrong);
+}
@@ -1,4 +1,8 @@
-use starlight::{awi, dag, delay, Delay, Epoch, EvalAwi, LazyAwi};
+use starlight::{
+    awi, dag, delay,
+    ensemble::{Ensemble, PExternal, Value},
+    Delay, Epoch, EvalAwi, LazyAwi, OptimizeSettings, TieBreak,
+};
 
 // Note: these tests have duplications between versions with quiescence testing,
 // because `EvalAwi`s and quiescence testing both do lowering stuff, and we need
@@ -154,6 +158,47 @@ fn tnode_delay_lowered() {
     drop(epoch);
 }
 
+#[test]
+fn tnode_simultaneous_chain() {
+    let epoch = Epoch::new();
+    let (a, b, c) = {
+        use dag::*;
+        (
+            LazyAwi::opaque(bw(1)),
+            LazyAwi::opaque(bw(1)),
+            LazyAwi::opaque(bw(1)),
+        )
+    };
+    let _c = EvalAwi::from(&c);
+    a.retro_bool_(true).unwrap();
+    b.retro_bool_(false).unwrap();
+    c.retro_bool_(false).unwrap();
+    epoch.lower().unwrap();
+    let mut ensemble = epoch.clone_ensemble();
+    let [p_a, p_b, p_c] = [a.p_external(), b.p_external(), c.p_external()].map(|p_external| {
+        ensemble
+            .notary
+            .get_rnode(p_external)
+            .unwrap()
+            .1
+            .bits()
+            .unwrap()[0]
+            .unwrap()
+    });
+    // `a` drives `b` which drives `c`, with both drives happening at the same time
+    let p_tnode_b = ensemble.make_tnode(p_b, p_a, Delay::from(1));
+    let p_tnode_c = ensemble.make_tnode(p_c, p_b, Delay::from(1));
+    ensemble.eval_tnode(p_tnode_b).unwrap();
+    ensemble.eval_tnode(p_tnode_c).unwrap();
+    ensemble.run(Delay::from(1)).unwrap();
+    assert_eq!(ensemble.request_value(p_b).unwrap(), Value::Dynam(true));
+    // `c` gets the old value of `b` like in a chain of flip-flops
+    assert_eq!(ensemble.request_value(p_c).unwrap(), Value::Dynam(false));
+    ensemble.run(Delay::from(1)).unwrap();
+    assert_eq!(ensemble.request_value(p_c).unwrap(), Value::Dynam(true));
+    drop(epoch);
+}
+
 #[test]
 fn tnode_delay_opaque_quiesced_lowered() {
     use dag::*;
@@ -202,3 +247,102 @@ fn tnode_delay_opaque_quiesced() {
     }
     drop(epoch);
 }
+
+#[test]
+fn tnode_const_through_delays() {
+    use dag::*;
+    for const_through_delays in [false, true] {
+        let epoch = Epoch::new();
+        assert_eq!(epoch.optimize_settings(), OptimizeSettings::default());
+        epoch.set_optimize_settings(OptimizeSettings {
+            const_through_delays,
+        });
+        let a = LazyAwi::opaque(bw(4));
+        // a tied off register
+        let mut tied = awi!(0b0101_u4);
+        delay(&mut tied, 3);
+        let mut y = awi!(a);
+        y.and_(&tied).unwrap();
+        // a register with a nonconstant driver is kept
+        let mut z = awi!(a);
+        delay(&mut z, 2);
+        let y = EvalAwi::from(&y);
+        let z = EvalAwi::from(&z);
+        epoch.optimize().unwrap();
+        let (lnodes, tnodes) =
+            epoch.ensemble(|ensemble| (ensemble.lnodes.len(), ensemble.tnodes.len()));
+        {
+            use awi::*;
+            a.retro_(&awi!(0b0110_u4)).unwrap();
+            if const_through_delays {
+                assert_eq!(tnodes, 4);
+                // only the bits of `a` that are anded with 1 are used
+                assert!(lnodes <= 2);
+                // the initial transient is skipped
+                assert_eq!(y.eval().unwrap(), awi!(0b0100_u4));
+            } else {
+                assert_eq!(tnodes, 8);
+                assert!(y.eval().is_err());
+            }
+            epoch.run(3).unwrap();
+            assert_eq!(y.eval().unwrap(), awi!(0b0100_u4));
+            assert_eq!(z.eval().unwrap(), awi!(0b0110_u4));
+        }
+        drop(epoch);
+    }
+}
+
+#[test]
+fn tnode_tie_break() {
+    let epoch = Epoch::new();
+    let (a, b, c) = {
+        use dag::*;
+        (
+            LazyAwi::opaque(bw(1)),
+            LazyAwi::opaque(bw(1)),
+            LazyAwi::opaque(bw(1)),
+        )
+    };
+    let _c = EvalAwi::from(&c);
+    a.retro_bool_(true).unwrap();
+    b.retro_bool_(false).unwrap();
+    c.retro_bool_(false).unwrap();
+    epoch.lower().unwrap();
+    assert!(matches!(epoch.tie_break(), TieBreak::SourceOrder));
+    epoch.set_tie_break(TieBreak::StableHash);
+    assert!(matches!(epoch.tie_break(), TieBreak::StableHash));
+    // `a` and then `b` drive `c` at the same time
+    let race = |tie_break: TieBreak| {
+        let mut ensemble = epoch.clone_ensemble();
+        ensemble.evaluator.tie_break = tie_break;
+        let bit = |ensemble: &Ensemble, p_external: PExternal| {
+            ensemble
+                .notary
+                .get_rnode(p_external)
+                .unwrap()
+                .1
+                .bits()
+                .unwrap()[0]
+                .unwrap()
+        };
+        let p_a = bit(&ensemble, a.p_external());
+        let p_b = bit(&ensemble, b.p_external());
+        let p_c = bit(&ensemble, c.p_external());
+        let p_tnode_a = ensemble.make_tnode(p_c, p_a, Delay::from(1));
+        let p_tnode_b = ensemble.make_tnode(p_c, p_b, Delay::from(1));
+        ensemble.eval_tnode(p_tnode_a).unwrap();
+        ensemble.eval_tnode(p_tnode_b).unwrap();
+        ensemble.run(Delay::from(1)).unwrap();
+        ensemble.request_value(p_c).unwrap()
+    };
+    // the last drive wins
+    assert_eq!(race(TieBreak::SourceOrder), Value::Dynam(false));
+    assert_eq!(
+        race(TieBreak::Comparator(|lhs, rhs| rhs.p_self.cmp(&lhs.p_self))),
+        Value::Dynam(true)
+    );
+    let hashed = race(TieBreak::StableHash);
+    assert!(hashed.is_known());
+    assert_eq!(race(TieBreak::StableHash), hashed);
+    drop(epoch);
+}